@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate clap;
+
+mod metadata;
+mod peers;
+mod tx;
+
+use std::{error::Error, process};
+
+use clap::{App, ArgMatches};
+
+pub type CliResult = Result<(), Box<dyn Error>>;
+
+async fn run(matches: &ArgMatches<'_>) -> CliResult {
+    match matches.subcommand() {
+        ("get-metadata", Some(sub_matches)) => metadata::get(sub_matches).await,
+        ("verify-metadata", Some(sub_matches)) => metadata::verify(sub_matches),
+        ("put-metadata", Some(sub_matches)) => metadata::put(sub_matches).await,
+        ("crawl", Some(sub_matches)) => peers::crawl(sub_matches).await,
+        ("decode-tx", Some(sub_matches)) => tx::decode(sub_matches),
+        ("txid", Some(sub_matches)) => tx::txid(sub_matches),
+        ("sighash", Some(sub_matches)) => tx::sighash(sub_matches),
+        ("broadcast", Some(sub_matches)) => tx::broadcast(sub_matches).await,
+        _ => unreachable!(), // This is safe as a subcommand is required
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let yaml = load_yaml!("cli.yml");
+    #[allow(deprecated)]
+    let matches = App::from_yaml(yaml)
+        .about(crate_description!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .version(crate_version!())
+        .get_matches();
+
+    if let Err(err) = run(&matches).await {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs;
+
+use cashweb::{
+    auth_wrapper::AuthWrapper,
+    keyserver::{avatar::ImageConstraints, AddressMetadata},
+    keyserver_client::KeyserverClient,
+    secp256k1::PublicKey,
+};
+use clap::ArgMatches;
+use prost::Message as _;
+
+use crate::CliResult;
+
+fn print_metadata(public_key: &PublicKey, metadata: &AddressMetadata) {
+    println!("public key: {}", hex::encode(public_key.serialize()));
+    println!("timestamp: {}", metadata.timestamp);
+    println!("ttl: {}", metadata.ttl);
+    for (index, entry) in metadata.entries.iter().enumerate() {
+        println!(
+            "entry {}: kind {:?}, {} bytes",
+            index,
+            entry.kind,
+            entry.body.len()
+        );
+        for header in &entry.headers {
+            println!("  {}: {}", header.name, header.value);
+        }
+    }
+}
+
+pub async fn get(matches: &ArgMatches<'_>) -> CliResult {
+    // This is safe as the arguments are required
+    let keyserver_url = matches.value_of("keyserver").unwrap().trim_end_matches('/');
+    let address = matches.value_of("address").unwrap();
+
+    // The client verifies the signature before returning the package
+    let package = KeyserverClient::new_tls()
+        .get_metadata(keyserver_url, address)
+        .await?;
+    println!("token: {}", package.token);
+    print_metadata(&package.public_key, &package.metadata);
+
+    if let Some(path) = matches.value_of("output") {
+        fs::write(path, &package.raw_auth_wrapper)?;
+    }
+    Ok(())
+}
+
+pub fn verify(matches: &ArgMatches<'_>) -> CliResult {
+    let raw_auth_wrapper = fs::read(matches.value_of("file").unwrap())?; // This is safe
+
+    let parsed_auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.as_slice())?.parse()?;
+    parsed_auth_wrapper.verify()?;
+    let metadata = AddressMetadata::decode(parsed_auth_wrapper.payload.as_slice())?;
+    println!("signature: valid");
+    print_metadata(&parsed_auth_wrapper.public_key, &metadata);
+    Ok(())
+}
+
+pub async fn put(matches: &ArgMatches<'_>) -> CliResult {
+    // This is safe as the arguments are required
+    let keyserver_url = matches.value_of("keyserver").unwrap().trim_end_matches('/');
+    let address = matches.value_of("address").unwrap();
+    let raw_auth_wrapper = fs::read(matches.value_of("file").unwrap())?;
+    let token = matches.value_of("token").unwrap();
+    let max_image_size: usize = matches.value_of("max-image-size").unwrap().parse()?;
+    let max_image_dimension: u32 = matches.value_of("max-image-dimension").unwrap().parse()?;
+
+    // Check image entries before uploading, as the keyserver would reject them
+    let auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.as_slice())?;
+    let metadata = AddressMetadata::decode(auth_wrapper.payload.as_slice())?;
+    ImageConstraints::new()
+        .with_max_size(max_image_size)
+        .with_max_dimensions(max_image_dimension, max_image_dimension)
+        .validate_metadata(&metadata)?;
+
+    KeyserverClient::new_tls()
+        .put_raw_metadata(keyserver_url, address, raw_auth_wrapper, token.to_string())
+        .await?;
+    println!("metadata put to {}", keyserver_url);
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::collections::{HashSet, VecDeque};
+
+use cashweb::keyserver_client::KeyserverClient;
+use clap::ArgMatches;
+
+use crate::CliResult;
+
+/// Breadth-first traversal of the peer graph, printing each keyserver visited.
+pub async fn crawl(matches: &ArgMatches<'_>) -> CliResult {
+    // This is safe as the arguments are required or defaulted
+    let start = matches.value_of("keyserver").unwrap().trim_end_matches('/');
+    let max_peers: usize = matches.value_of("max-peers").unwrap().parse()?;
+
+    let client = KeyserverClient::new_tls();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(start.to_string());
+    queue.push_back(start.to_string());
+
+    while let Some(url) = queue.pop_front() {
+        let peers = match client.get_peers(&url).await {
+            Ok(ok) => ok.peers,
+            Err(err) => {
+                println!("{} unreachable: {}", url, err);
+                continue;
+            }
+        };
+        println!("{} ({} peers)", url, peers.len());
+        for peer in peers {
+            let peer_url = peer.url.trim_end_matches('/').to_string();
+            if visited.len() < max_peers && visited.insert(peer_url.clone()) {
+                queue.push_back(peer_url);
+            }
+        }
+    }
+
+    println!("visited {} keyservers", visited.len());
+    Ok(())
+}
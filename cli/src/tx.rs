@@ -0,0 +1,111 @@
+use cashweb::{
+    bitcoin::{
+        transaction::{
+            self,
+            script::Script,
+            sighash::{SighashCache, SighashParams},
+            tx_id::TxId,
+            SignatureHashType, Transaction,
+        },
+        Encodable,
+    },
+    bitcoin_client::{profile::Chain, BitcoinClient, BitcoinClientHTTP},
+};
+use clap::ArgMatches;
+
+use crate::CliResult;
+
+fn parse_tx(
+    matches: &ArgMatches<'_>,
+) -> Result<(Vec<u8>, Transaction), Box<dyn std::error::Error>> {
+    let tx = Transaction::from_hex(matches.value_of("tx").unwrap())?; // This is safe
+    let mut raw_tx = Vec::with_capacity(tx.encoded_len());
+    tx.encode_raw(&mut raw_tx);
+    Ok((raw_tx, tx))
+}
+
+pub fn decode(matches: &ArgMatches<'_>) -> CliResult {
+    let (_, tx) = parse_tx(matches)?;
+    println!("version: {}", tx.version);
+    println!("lock time: {}", tx.lock_time);
+    for (index, input) in tx.inputs.iter().enumerate() {
+        println!(
+            "input {}: {}:{}, sequence {}, script {}",
+            index,
+            TxId::from(input.outpoint.tx_id),
+            input.outpoint.vout,
+            input.sequence,
+            hex::encode(input.script.as_bytes())
+        );
+    }
+    for (index, output) in tx.outputs.iter().enumerate() {
+        println!(
+            "output {}: {} sats, script {}",
+            index,
+            output.value,
+            hex::encode(output.script.as_bytes())
+        );
+    }
+    Ok(())
+}
+
+pub fn txid(matches: &ArgMatches<'_>) -> CliResult {
+    let (raw_tx, tx) = parse_tx(matches)?;
+    println!("txid: {}", tx.tx_id());
+    println!(
+        "hash: {}",
+        hex::encode(transaction::transaction_hash_rev(&raw_tx))
+    );
+    Ok(())
+}
+
+pub fn sighash(matches: &ArgMatches<'_>) -> CliResult {
+    let (_, tx) = parse_tx(matches)?;
+    // This is safe as the arguments are required or defaulted
+    let input: usize = matches.value_of("input").unwrap().parse()?;
+    let script = Script::from(hex::decode(matches.value_of("script").unwrap())?);
+    let hash_type = match matches.value_of("hash-type").unwrap() {
+        "none" => SignatureHashType::None,
+        "single" => SignatureHashType::Single,
+        "anyone-can-pay-all" => SignatureHashType::AnyoneCanPayAll,
+        "anyone-can-pay-none" => SignatureHashType::AnyoneCanPayNone,
+        "anyone-can-pay-single" => SignatureHashType::AnyoneCanPaySingle,
+        _ => SignatureHashType::All,
+    };
+    let params = match matches.value_of("chain").unwrap() {
+        "legacy" => SighashParams::LEGACY,
+        "xec" => Chain::Xec.sighash_params(),
+        "xpi" => Chain::Xpi.sighash_params(),
+        _ => Chain::Bch.sighash_params(),
+    };
+
+    // Only the value of the output spent by the input is committed to
+    let mut values = vec![0; tx.inputs.len()];
+    if params != SighashParams::LEGACY {
+        let value = matches
+            .value_of("value")
+            .ok_or("the value of the output being spent is required")?;
+        if let Some(some) = values.get_mut(input) {
+            *some = value.parse()?;
+        }
+    }
+
+    let sig_hash = SighashCache::with_params(&tx, params, values)
+        .signature_hash(input, &script, hash_type)
+        .ok_or("input or corresponding output does not exist")?;
+    println!("{}", hex::encode(sig_hash));
+    Ok(())
+}
+
+pub async fn broadcast(matches: &ArgMatches<'_>) -> CliResult {
+    let (raw_tx, _) = parse_tx(matches)?;
+    // This is safe as the arguments are defaulted
+    let client = BitcoinClientHTTP::new(
+        matches.value_of("rpc-addr").unwrap().to_string(),
+        matches.value_of("rpc-username").unwrap().to_string(),
+        matches.value_of("rpc-password").unwrap().to_string(),
+    );
+    let tx_id = client.send_tx(&raw_tx).await?;
+    println!("{}", tx_id);
+    Ok(())
+}
@@ -1,29 +1,143 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
-use cashweb::keyserver::Peers;
+use async_trait::async_trait;
+use bitcoincash_addr::Address;
+use cashweb::{
+    auth_wrapper::AuthWrapper,
+    keyserver::{
+        namespace::SEPARATOR,
+        store::{MetadataStore, StoredMetadata},
+        vcard::{VCard, VCARD_KIND},
+        AddressMetadata, Peers, SearchMatch, StorageStats,
+    },
+    token::store::{StoredToken, StoredTokenDecodeError, TokenStore},
+};
 use prost::Message;
-use rocksdb::{Error as RocksError, Options, DB};
+use rocksdb::{Direction, Error as RocksError, IteratorMode, Options, WriteBatch, DB};
+use thiserror::Error;
+use tokio::task;
+use tracing::info;
 
-use crate::models::database::DatabaseWrapper;
+use crate::{crypto::sha256, models::database::DatabaseWrapper};
 
 const METADATA_NAMESPACE: u8 = b'm';
+const METADATA_TIME_NAMESPACE: u8 = b'i';
 const PEER_NAMESPACE: u8 = b'p';
+const TOKEN_NAMESPACE: u8 = b't';
+const HEALTH_NAMESPACE: u8 = b'h';
+const SEARCH_NAMESPACE: u8 = b'n';
+const SCHEMA_NAMESPACE: u8 = b'v';
+
+/// Version of the database layout, recorded so that older databases are migrated on open.
+const SCHEMA_VERSION: u8 = 1;
+
+/// Number of metadata documents indexed per write while migrating.
+const MIGRATION_BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum TokenStoreError {
+    #[error(transparent)]
+    Rocks(#[from] RocksError),
+    #[error(transparent)]
+    Decode(#[from] StoredTokenDecodeError),
+}
 
 #[derive(Clone)]
-pub struct Database(Arc<DB>);
+pub struct Database {
+    db: Arc<DB>,
+    // Serializes read-modify-write updates of metadata and its time index
+    metadata_lock: Arc<Mutex<()>>,
+}
 
 impl Database {
     pub fn try_new(path: &str) -> Result<Self, RocksError> {
         let mut opts = Options::default();
         opts.create_if_missing(true);
 
-        DB::open(&opts, &path).map(Arc::new).map(Database)
+        let db = DB::open(&opts, &path)?;
+        let database = Database {
+            db: Arc::new(db),
+            metadata_lock: Default::default(),
+        };
+        database.migrate()?;
+        Ok(database)
+    }
+
+    /// Bring the database up to the current schema version.
+    fn migrate(&self) -> Result<(), RocksError> {
+        let version = self
+            .db
+            .get([SCHEMA_NAMESPACE])?
+            .and_then(|raw| raw.first().copied())
+            .unwrap_or(0);
+        if version >= SCHEMA_VERSION {
+            return Ok(());
+        }
+        if version < 1 {
+            let indexed = self.backfill_indexes()?;
+            info!(message = "backfilled metadata indexes", indexed);
+        }
+        self.db.put([SCHEMA_NAMESPACE], [SCHEMA_VERSION])
+    }
+
+    /// Add time and search index entries for metadata written before the indexes existed.
+    ///
+    /// Entries are derived from the stored metadata, so rewriting existing entries is harmless.
+    fn backfill_indexes(&self) -> Result<usize, RocksError> {
+        // This is safe as the lock guards no data
+        let _guard = self.metadata_lock.lock().unwrap();
+
+        let mut indexed = 0;
+        let mut batch = WriteBatch::default();
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(
+                &[METADATA_NAMESPACE],
+                Direction::Forward,
+            ))
+            .take_while(|(key, _)| key.first() == Some(&METADATA_NAMESPACE));
+        for (key, raw) in iter {
+            // Skip metadata which can't be decoded, rather than refusing to start
+            let wrapper = match DatabaseWrapper::decode(&raw[..]) {
+                Ok(wrapper) => wrapper,
+                Err(_) => continue,
+            };
+            batch.put(metadata_time_key(wrapper.timestamp, &key[1..]), b"");
+            for (search_key, search_match) in search_index(&key[1..], &wrapper) {
+                let mut raw_search_match = Vec::with_capacity(search_match.encoded_len());
+                search_match.encode(&mut raw_search_match).unwrap(); // This is safe
+                batch.put(search_key, raw_search_match);
+            }
+            indexed += 1;
+            if indexed % MIGRATION_BATCH_SIZE == 0 {
+                self.db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        self.db.write(batch)?;
+        Ok(indexed)
+    }
+
+    /// Run a blocking operation against the database on the blocking thread pool, so that it
+    /// doesn't stall the async runtime.
+    pub async fn run_blocking<F, T>(&self, operation: F) -> T
+    where
+        F: FnOnce(&Self) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let database = self.clone();
+        // This panics if the operation panicked
+        task::spawn_blocking(move || operation(&database))
+            .await
+            .unwrap()
     }
 
     /// Get raw `DatabaseWrapper` from the database.
     pub fn get_raw_metadata(&self, addr: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
         let key = [&[METADATA_NAMESPACE], addr].concat();
-        self.0.get(key)
+        self.db.get(key)
     }
 
     /// Get a `DatabaseWrapper` from the database.
@@ -35,12 +149,150 @@ impl Database {
         })
     }
 
-    /// Put a serialized `DatabaseWrapper` to the database.
-    pub fn put_metadata(&self, addr: &[u8], raw: &[u8]) -> Result<(), RocksError> {
-        // Prefix key
-        let key = [&[METADATA_NAMESPACE], addr].concat();
+    /// Atomically replace the metadata for the key with the result of `update`, which is given
+    /// the existing metadata.
+    pub fn update_metadata<F, E>(&self, key: &[u8], update: F) -> Result<(), E>
+    where
+        F: FnOnce(Option<StoredMetadata>) -> Result<StoredMetadata, E>,
+        E: From<RocksError>,
+    {
+        // This is safe as the lock guards no data
+        let _guard = self.metadata_lock.lock().unwrap();
 
-        self.0.put(key, raw)
+        let old = self.get_metadata(key)?;
+        let old_timestamp = old.as_ref().map(|old| old.timestamp);
+        let old_search_keys: Vec<Vec<u8>> = old
+            .as_ref()
+            .map(|old| search_index(key, old))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(search_key, _)| search_key)
+            .collect();
+        let metadata = update(old.map(StoredMetadata::from))?;
+
+        let database_wrapper = DatabaseWrapper {
+            serialized_auth_wrapper: metadata.raw_auth_wrapper,
+            token: metadata.token,
+            timestamp: metadata.timestamp,
+            namespace: metadata.namespace,
+        };
+        let mut raw_database_wrapper = Vec::with_capacity(database_wrapper.encoded_len());
+        database_wrapper.encode(&mut raw_database_wrapper).unwrap(); // This is safe
+
+        let mut batch = WriteBatch::default();
+        if let Some(old_timestamp) = old_timestamp {
+            batch.delete(metadata_time_key(old_timestamp, key));
+        }
+        for search_key in old_search_keys {
+            batch.delete(search_key);
+        }
+        for (search_key, search_match) in search_index(key, &database_wrapper) {
+            let mut raw_search_match = Vec::with_capacity(search_match.encoded_len());
+            search_match.encode(&mut raw_search_match).unwrap(); // This is safe
+            batch.put(search_key, raw_search_match);
+        }
+        batch.put(metadata_time_key(metadata.timestamp, key), b"");
+        batch.put([&[METADATA_NAMESPACE], key].concat(), raw_database_wrapper);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Remove the metadata of the address, including its namespaces, and its POP token.
+    ///
+    /// Returns the number of metadata documents removed.
+    pub fn purge(&self, addr: &[u8]) -> Result<u32, RocksError> {
+        // This is safe as the lock guards no data
+        let _guard = self.metadata_lock.lock().unwrap();
+
+        let mut batch = WriteBatch::default();
+        let mut removed = 0;
+        let mut remove = |key: &[u8], raw: &[u8]| {
+            // This panics if stored bytes are malformed
+            let wrapper = DatabaseWrapper::decode(raw).unwrap();
+            batch.delete(metadata_time_key(wrapper.timestamp, &key[1..]));
+            for (search_key, _) in search_index(&key[1..], &wrapper) {
+                batch.delete(search_key);
+            }
+            batch.delete(key);
+            removed += 1;
+        };
+        let root_key = [&[METADATA_NAMESPACE], addr].concat();
+        if let Some(raw) = self.db.get(&root_key)? {
+            remove(&root_key, &raw);
+        }
+        let namespace_prefix = [&[METADATA_NAMESPACE], addr, &[SEPARATOR as u8]].concat();
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(&namespace_prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&namespace_prefix));
+        for (key, raw) in iter {
+            remove(&key, &raw);
+        }
+        batch.delete([&[TOKEN_NAMESPACE], addr].concat());
+        self.db.write(batch)?;
+        Ok(removed)
+    }
+
+    /// Count the stored metadata and tokens.
+    pub fn stats(&self) -> Result<StorageStats, RocksError> {
+        let mut stats = StorageStats::default();
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(
+                &[METADATA_NAMESPACE],
+                Direction::Forward,
+            ))
+            .take_while(|(key, _)| key.first() == Some(&METADATA_NAMESPACE));
+        for (_, raw) in iter {
+            // This panics if stored bytes are malformed
+            let wrapper = DatabaseWrapper::decode(&raw[..]).unwrap();
+            if wrapper.namespace.is_empty() {
+                stats.metadata += 1;
+            } else {
+                stats.namespaced_metadata += 1;
+            }
+            stats.metadata_bytes += wrapper.serialized_auth_wrapper.len() as u64;
+        }
+        stats.tokens = self
+            .db
+            .iterator(IteratorMode::From(&[TOKEN_NAMESPACE], Direction::Forward))
+            .take_while(|(key, _)| key.first() == Some(&TOKEN_NAMESPACE))
+            .count() as u64;
+        Ok(stats)
+    }
+
+    /// Get up to `limit` matches whose lowercase name starts with the lowercase `prefix`, in
+    /// ascending order of name.
+    ///
+    /// The matches begin after the `cursor` returned with the previous page, and are returned with
+    /// the cursor of the last match.
+    pub fn search(
+        &self,
+        prefix: &str,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<SearchMatch>, Option<Vec<u8>>), RocksError> {
+        let prefix = [&[SEARCH_NAMESPACE], prefix.to_lowercase().as_bytes()].concat();
+        // Resume strictly after the cursor, unless it precedes the prefix
+        let start = match cursor {
+            Some(cursor) => [&[SEARCH_NAMESPACE], cursor, &[0]]
+                .concat()
+                .max(prefix.clone()),
+            None => prefix.clone(),
+        };
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .take(limit);
+        let mut matches = Vec::new();
+        let mut last_key = None;
+        for (key, raw) in iter {
+            // This panics if stored bytes are malformed
+            matches.push(SearchMatch::decode(&raw[..]).unwrap());
+            last_key = Some(key[1..].to_vec());
+        }
+        Ok((matches, last_key))
     }
 
     /// Get `Peers` from database.
@@ -54,22 +306,248 @@ impl Database {
 
     /// Get serialized `Peers` from database.
     pub fn get_peers_raw(&self) -> Result<Option<Vec<u8>>, RocksError> {
-        self.0.get([PEER_NAMESPACE])
+        self.db.get([PEER_NAMESPACE])
     }
 
     /// Put serialized `Peers` to database.
     pub fn put_peers(&self, raw: &[u8]) -> Result<(), RocksError> {
-        self.0.put([PEER_NAMESPACE], raw)
+        self.db.put([PEER_NAMESPACE], raw)
+    }
+
+    /// Write to a reserved key, checking that the database is writable.
+    pub fn probe_write(&self) -> Result<(), RocksError> {
+        self.db.put([HEALTH_NAMESPACE], [])
+    }
+}
+
+/// Key of the time index entry for the address.
+fn metadata_time_key(timestamp: i64, addr: &[u8]) -> Vec<u8> {
+    // Flip the sign bit so that keys order by timestamp
+    let timestamp = (timestamp as u64 ^ (1 << 63)).to_be_bytes();
+    [&[METADATA_TIME_NAMESPACE], &timestamp[..], addr].concat()
+}
+
+/// Search index entries for the names and handles in the vCards of root metadata.
+///
+/// Entries are keyed by the lowercase name followed by the address, so that they order by name and
+/// an address may share a name with others. Metadata which can't be decoded isn't indexed.
+fn search_index(key: &[u8], wrapper: &DatabaseWrapper) -> Vec<(Vec<u8>, SearchMatch)> {
+    if !wrapper.namespace.is_empty() {
+        return Vec::new();
+    }
+    let payload = match AuthWrapper::decode(&wrapper.serialized_auth_wrapper[..]) {
+        Ok(auth_wrapper) => auth_wrapper.payload,
+        Err(_) => return Vec::new(),
+    };
+    let metadata = match AddressMetadata::decode(&payload[..]) {
+        Ok(metadata) => metadata,
+        Err(_) => return Vec::new(),
+    };
+    let address = Address {
+        body: key.to_vec(),
+        ..Default::default()
+    };
+    let address = match address.encode() {
+        Ok(address) => address,
+        Err(_) => return Vec::new(),
+    };
+    let payload_digest = sha256(&payload).to_vec();
+
+    let mut names: Vec<String> = metadata
+        .entries
+        .iter()
+        .filter(|entry| entry.kind == VCARD_KIND)
+        .filter_map(|entry| VCard::from_entry(entry).ok())
+        .flat_map(|vcard| {
+            let handles = vcard.social_handles.into_iter().map(|social| social.handle);
+            std::iter::once(vcard.full_name).chain(handles)
+        })
+        .map(|name| name.trim().to_string())
+        // The NUL byte separates the name from the address in the key
+        .filter(|name| !name.is_empty() && !name.contains('\0'))
+        .collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.dedup_by_key(|name| name.to_lowercase());
+
+    names
+        .into_iter()
+        .map(|name| {
+            let search_key = [
+                &[SEARCH_NAMESPACE],
+                name.to_lowercase().as_bytes(),
+                &[0],
+                key,
+            ]
+            .concat();
+            let search_match = SearchMatch {
+                address: address.clone(),
+                name,
+                payload_digest: payload_digest.clone(),
+                timestamp: wrapper.timestamp,
+            };
+            (search_key, search_match)
+        })
+        .collect()
+}
+
+impl From<DatabaseWrapper> for StoredMetadata {
+    fn from(wrapper: DatabaseWrapper) -> Self {
+        Self {
+            raw_auth_wrapper: wrapper.serialized_auth_wrapper,
+            token: wrapper.token,
+            timestamp: wrapper.timestamp,
+            namespace: wrapper.namespace,
+        }
+    }
+}
+
+impl Database {
+    fn delete_metadata(&self, address: &[u8]) -> Result<bool, RocksError> {
+        // This is safe as the lock guards no data
+        let _guard = self.metadata_lock.lock().unwrap();
+
+        let old = match self.get_metadata(address)? {
+            Some(some) => some,
+            None => return Ok(false),
+        };
+        let mut batch = WriteBatch::default();
+        batch.delete(metadata_time_key(old.timestamp, address));
+        for (search_key, _) in search_index(address, &old) {
+            batch.delete(search_key);
+        }
+        batch.delete([&[METADATA_NAMESPACE], address].concat());
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn metadata_since(
+        &self,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, StoredMetadata)>, RocksError> {
+        let start = metadata_time_key(since, &[]);
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+            .take_while(|(key, _)| key.first() == Some(&METADATA_TIME_NAMESPACE))
+            .take(limit);
+        let mut entries = Vec::new();
+        for (key, _) in iter {
+            // Strip the database namespace and timestamp
+            let metadata_key = key[9..].to_vec();
+            if let Some(metadata) = self.get_metadata(&metadata_key)? {
+                entries.push((metadata_key, metadata.into()));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn lookup_token(&self, address: &[u8]) -> Result<Option<StoredToken>, TokenStoreError> {
+        let key = [&[TOKEN_NAMESPACE], address].concat();
+        let token = match self.db.get(key)? {
+            Some(raw) => StoredToken::from_bytes(&raw)?,
+            None => return Ok(None),
+        };
+        if token.is_expired(SystemTime::now()) {
+            return Ok(None);
+        }
+        Ok(Some(token))
+    }
+
+    fn expire_tokens(&self, now: SystemTime) -> Result<usize, TokenStoreError> {
+        let mut expired = 0;
+        let iter = self
+            .db
+            .iterator(IteratorMode::From(&[TOKEN_NAMESPACE], Direction::Forward));
+        for (key, raw) in iter.take_while(|(key, _)| key.first() == Some(&TOKEN_NAMESPACE)) {
+            // Remove tokens which have expired or can't be decoded
+            let is_expired = StoredToken::from_bytes(&raw)
+                .map(|token| token.is_expired(now))
+                .unwrap_or(true);
+            if is_expired {
+                self.db.delete(key)?;
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+}
+
+#[async_trait]
+impl MetadataStore for Database {
+    type Error = RocksError;
+
+    async fn get(&self, address: &[u8]) -> Result<Option<StoredMetadata>, Self::Error> {
+        let address = address.to_vec();
+        self.run_blocking(move |database| {
+            Ok(database.get_metadata(&address)?.map(StoredMetadata::from))
+        })
+        .await
+    }
+
+    async fn put(&self, address: &[u8], metadata: StoredMetadata) -> Result<(), Self::Error> {
+        let address = address.to_vec();
+        self.run_blocking(move |database| database.update_metadata(&address, |_| Ok(metadata)))
+            .await
+    }
+
+    async fn delete(&self, address: &[u8]) -> Result<bool, Self::Error> {
+        let address = address.to_vec();
+        self.run_blocking(move |database| database.delete_metadata(&address))
+            .await
+    }
+
+    async fn since(
+        &self,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, StoredMetadata)>, Self::Error> {
+        self.run_blocking(move |database| database.metadata_since(since, limit))
+            .await
+    }
+}
+
+#[async_trait]
+impl TokenStore for Database {
+    type Error = TokenStoreError;
+
+    async fn insert(&self, address: &[u8], token: StoredToken) -> Result<(), Self::Error> {
+        let key = [&[TOKEN_NAMESPACE], address].concat();
+        self.run_blocking(move |database| database.db.put(key, token.to_bytes()))
+            .await?;
+        Ok(())
+    }
+
+    async fn lookup(&self, address: &[u8]) -> Result<Option<StoredToken>, Self::Error> {
+        let address = address.to_vec();
+        self.run_blocking(move |database| database.lookup_token(&address))
+            .await
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Self::Error> {
+        self.run_blocking(move |database| database.expire_tokens(now))
+            .await
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use cashweb::keyserver::{Peer, Peers};
+    use cashweb::{
+        auth_wrapper::AuthWrapper,
+        keyserver::{
+            namespace::metadata_key,
+            store::{MetadataStore, StoredMetadata},
+            vcard::{SocialHandle, VCard},
+            AddressMetadata, Peer, Peers,
+        },
+    };
     use prost::Message as _;
-    use rocksdb::{Options, DB};
+    use rocksdb::{Error as RocksError, Options, DB};
 
-    use crate::{db::Database, models::database::DatabaseWrapper};
+    use crate::{
+        db::{Database, METADATA_NAMESPACE, SCHEMA_NAMESPACE, SCHEMA_VERSION},
+        models::database::DatabaseWrapper,
+    };
 
     #[test]
     fn peers() {
@@ -79,12 +557,8 @@ pub mod tests {
         let database = Database::try_new(TEST_NAME).unwrap();
 
         // Create peers
-        let peer_a = Peer {
-            url: "url a".to_string(),
-        };
-        let peer_b = Peer {
-            url: "url b".to_string(),
-        };
+        let peer_a = Peer::new("url a".to_string());
+        let peer_b = Peer::new("url b".to_string());
         let peers_in = Peers {
             peers: vec![peer_a, peer_b],
         };
@@ -103,8 +577,8 @@ pub mod tests {
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
 
-    #[test]
-    fn metadata() {
+    #[tokio::test]
+    async fn metadata() {
         const TEST_NAME: &str = "./tests/metadata";
 
         // Create database
@@ -114,15 +588,16 @@ pub mod tests {
         let database_wrapper_in = DatabaseWrapper {
             token: vec![0, 1, 3, 4],
             serialized_auth_wrapper: vec![2, 3, 4],
+            timestamp: 0,
+            namespace: String::new(),
         };
-        let mut database_wrapper_raw = Vec::with_capacity(database_wrapper_in.encoded_len());
-        database_wrapper_in
-            .encode(&mut database_wrapper_raw)
-            .unwrap();
 
         // Put to database
         let addr = vec![0, 3, 4, 3, 2];
-        database.put_metadata(&addr, &database_wrapper_raw).unwrap();
+        database
+            .put(&addr, StoredMetadata::from(database_wrapper_in.clone()))
+            .await
+            .unwrap();
 
         // Get from database
         let data_wrapper_out = database.get_metadata(&addr).unwrap().unwrap();
@@ -132,4 +607,204 @@ pub mod tests {
         drop(database);
         DB::destroy(&Options::default(), TEST_NAME).unwrap();
     }
+
+    #[tokio::test]
+    async fn metadata_since() {
+        const TEST_NAME: &str = "./tests/metadata_since";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        let metadata = |timestamp| StoredMetadata {
+            timestamp,
+            ..Default::default()
+        };
+        database.put(b"alice", metadata(300)).await.unwrap();
+        database.put(b"bob", metadata(-100)).await.unwrap();
+        database.put(b"carol", metadata(200)).await.unwrap();
+        database.put(b"bob", metadata(400)).await.unwrap();
+
+        // Replaced entries are removed from the index
+        let since = database.since(i64::MIN, 10).await.unwrap();
+        let addresses: Vec<&[u8]> = since.iter().map(|(addr, _)| &addr[..]).collect();
+        assert_eq!(addresses, vec![&b"carol"[..], b"alice", b"bob"]);
+
+        // Namespaced metadata is indexed alongside the root document
+        let profile = StoredMetadata {
+            timestamp: 500,
+            namespace: "profile".to_string(),
+            ..Default::default()
+        };
+        let key = metadata_key(b"alice", "profile");
+        database.put(&key, profile.clone()).await.unwrap();
+        assert_eq!(database.since(450, 10).await.unwrap(), vec![(key, profile)]);
+        assert_eq!(database.get(b"alice").await.unwrap(), Some(metadata(300)));
+
+        // Failed updates leave the metadata untouched
+        let result = database.update_metadata::<_, Option<RocksError>>(b"alice", |old| {
+            assert_eq!(old, Some(metadata(300)));
+            Err(None)
+        });
+        assert!(matches!(result, Err(None)));
+        database
+            .update_metadata::<_, RocksError>(b"alice", |old| {
+                Ok(metadata(old.unwrap().timestamp + 300))
+            })
+            .unwrap();
+        assert_eq!(database.get(b"alice").await.unwrap(), Some(metadata(600)));
+
+        assert!(database.delete(b"bob").await.unwrap());
+        assert_eq!(database.since(350, 10).await.unwrap().len(), 2);
+        assert_eq!(database.get(b"alice").await.unwrap(), Some(metadata(600)));
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate() {
+        const TEST_NAME: &str = "./tests/migrate";
+
+        // Write metadata without index entries, as older versions did
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let wrapper = DatabaseWrapper {
+            timestamp: 100,
+            ..Default::default()
+        };
+        let mut raw_wrapper = Vec::with_capacity(wrapper.encoded_len());
+        wrapper.encode(&mut raw_wrapper).unwrap();
+        database
+            .db
+            .put([&[METADATA_NAMESPACE], &b"alice"[..]].concat(), raw_wrapper)
+            .unwrap();
+        database.db.delete([SCHEMA_NAMESPACE]).unwrap();
+        assert!(database.since(i64::MIN, 10).await.unwrap().is_empty());
+        drop(database);
+
+        // Reopening backfills the time index
+        let database = Database::try_new(TEST_NAME).unwrap();
+        let since = database.since(i64::MIN, 10).await.unwrap();
+        assert_eq!(
+            since,
+            vec![(b"alice".to_vec(), StoredMetadata::from(wrapper))]
+        );
+        assert_eq!(
+            database.db.get([SCHEMA_NAMESPACE]).unwrap(),
+            Some(vec![SCHEMA_VERSION])
+        );
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn purge() {
+        const TEST_NAME: &str = "./tests/purge";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        let metadata = |namespace: &str| StoredMetadata {
+            raw_auth_wrapper: vec![0; 4],
+            timestamp: 100,
+            namespace: namespace.to_string(),
+            ..Default::default()
+        };
+        database.put(b"alice", metadata("")).await.unwrap();
+        let key = metadata_key(b"alice", "profile");
+        database.put(&key, metadata("profile")).await.unwrap();
+        database.put(b"bob", metadata("")).await.unwrap();
+
+        let stats = database.stats().unwrap();
+        assert_eq!(stats.metadata, 2);
+        assert_eq!(stats.namespaced_metadata, 1);
+        assert_eq!(stats.metadata_bytes, 12);
+
+        // The root document and namespaces are removed, along with their index entries
+        assert_eq!(database.purge(b"alice").unwrap(), 2);
+        assert_eq!(database.get(&key).await.unwrap(), None);
+        let since = database.since(i64::MIN, 10).await.unwrap();
+        assert_eq!(since, vec![(b"bob".to_vec(), metadata(""))]);
+        assert_eq!(database.purge(b"alice").unwrap(), 0);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
+
+    #[tokio::test]
+    async fn search() {
+        const TEST_NAME: &str = "./tests/search";
+
+        // Create database
+        let database = Database::try_new(TEST_NAME).unwrap();
+
+        let metadata = |full_name: &str, handles: &[&str]| {
+            let vcard = VCard {
+                full_name: full_name.to_string(),
+                social_handles: handles
+                    .iter()
+                    .map(|handle| SocialHandle {
+                        service: "twitter".to_string(),
+                        handle: handle.to_string(),
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            let address_metadata = AddressMetadata {
+                timestamp: 100,
+                entries: vec![vcard.to_entry()],
+                ..Default::default()
+            };
+            let mut payload = Vec::with_capacity(address_metadata.encoded_len());
+            address_metadata.encode(&mut payload).unwrap();
+            let auth_wrapper = AuthWrapper {
+                payload,
+                ..Default::default()
+            };
+            let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+            auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+            StoredMetadata {
+                raw_auth_wrapper,
+                timestamp: 100,
+                ..Default::default()
+            }
+        };
+        let names = |prefix: &str, cursor: Option<&[u8]>, limit: usize| {
+            let (matches, cursor) = database.search(prefix, cursor, limit).unwrap();
+            let names: Vec<String> = matches.into_iter().map(|m| m.name).collect();
+            (names, cursor)
+        };
+        database
+            .put(&[1; 20], metadata("Al Bundy", &["@peggy"]))
+            .await
+            .unwrap();
+        database
+            .put(&[2; 20], metadata("alice", &["alice"]))
+            .await
+            .unwrap();
+
+        // Names and handles match case-insensitively, once per address
+        assert_eq!(names("AL", None, 10).0, vec!["Al Bundy", "alice"]);
+        assert_eq!(names("@p", None, 10).0, vec!["@peggy"]);
+
+        // Pages resume after the cursor
+        let (page, cursor) = names("al", None, 1);
+        assert_eq!(page, vec!["Al Bundy"]);
+        let (page, cursor) = names("al", cursor.as_deref(), 1);
+        assert_eq!(page, vec!["alice"]);
+        assert!(names("al", cursor.as_deref(), 1).0.is_empty());
+
+        // Replaced and deleted metadata is removed from the index
+        database.put(&[1; 20], metadata("Bud", &[])).await.unwrap();
+        assert_eq!(names("", None, 10).0, vec!["alice", "Bud"]);
+        assert!(database.delete(&[2; 20]).await.unwrap());
+        assert_eq!(names("", None, 10).0, vec!["Bud"]);
+
+        // Destroy database
+        drop(database);
+        DB::destroy(&Options::default(), TEST_NAME).unwrap();
+    }
 }
@@ -9,6 +9,7 @@ mod net;
 mod peering;
 mod pubsub;
 mod settings;
+mod zmq;
 
 #[cfg(feature = "monitoring")]
 pub mod monitoring;
@@ -16,15 +17,40 @@ pub mod monitoring;
 use std::{env, sync::Arc, time::Duration};
 
 use cashweb::{
-    auth_wrapper::AuthWrapper, bitcoin_client::BitcoinClientHTTP, payments::preprocess_payment,
-    token::schemes::chain_commitment::ChainCommitmentScheme,
+    auth_wrapper::AuthWrapper,
+    bitcoin_client::{
+        follower::{ChainFollower, FollowerError},
+        metrics::{GlobalMetrics, InstrumentedClient},
+        utxo::UtxoCache,
+        BitcoinClientHTTP, Timeouts,
+    },
+    health::{from_fn, CheckResult, Health, Heartbeat, HeartbeatCheck, NodeCheck},
+    keyserver::{
+        admin::{ADMIN_PATH, BAN_PEER_PATH, KEYS_PATH, REPLICATE_PATH, STATS_PATH},
+        idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED},
+        namespace::Namespace,
+    },
+    keyserver_client::{
+        credentials::CredentialsLayer,
+        policy::{PeerPolicy, PolicyRules},
+        replication::Replicator,
+        KeyserverClient,
+    },
+    lifecycle::{bus::EventBus, shutdown_signal, Lifecycle},
+    payments::preprocess_payment,
+    token::{
+        schemes::{chain_commitment::ChainCommitmentScheme, ErasedScheme},
+        signing::{SigningLayer, VerifyingLayer},
+    },
 };
 use futures::prelude::*;
 use hyper::{client::HttpConnector, http::Uri};
+use hyper_tls::HttpsConnector;
 use lazy_static::lazy_static;
 use prost::Message as _;
 use serde::Deserialize;
-use tracing::{error, info};
+use tower_layer::Layer;
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 use warp::{
     http::{header, Method},
@@ -40,8 +66,13 @@ use crate::{
 
 const METADATA_PATH: &str = "keys";
 const PEERS_PATH: &str = "peers";
+const SEARCH_PATH: &str = "search";
 pub const PAYMENTS_PATH: &str = "payments";
 const MESSAGES_PATH: &str = "messages";
+const HEALTH_PATH: &str = "health";
+
+/// Number of recent blocks whose outputs and POP transactions are cached.
+const UTXO_CACHE_DEPTH: usize = 10;
 
 lazy_static! {
     // Static settings
@@ -58,6 +89,19 @@ async fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("no global subscriber has been set");
 
+    // Feed library metrics to the Prometheus exporter
+    #[cfg(feature = "monitoring")]
+    monitoring::install();
+
+    // Request shutdown on SIGTERM or Ctrl-C
+    let lifecycle = Lifecycle::new();
+    let lifecycle_inner = lifecycle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!(message = "shutdown requested");
+        lifecycle_inner.shutdown();
+    });
+
     // Initialize databases
     let db = Database::try_new(&SETTINGS.db_path).expect("failed to open database");
     let pubsub_db = PubSubDatabase::new(&SETTINGS.pubsub_db_path).expect("failed to open database");
@@ -88,8 +132,14 @@ async fn main() {
     connector.set_keepalive(Some(Duration::from_secs(SETTINGS.peering.keep_alive)));
     connector.set_connect_timeout(Some(Duration::from_secs(SETTINGS.peering.timeout)));
 
-    // Setup peer state
-    let peer_handler = PeerHandler::new(peers);
+    // Setup peer state, with misbehaviour scored by a policy shared with replication
+    let peer_policy = PeerPolicy::new(PolicyRules {
+        max_clock_skew: SETTINGS.peering.max_clock_skew as i64,
+        ban_threshold: SETTINGS.peering.ban_threshold,
+        ban_duration: Duration::from_millis(SETTINGS.peering.ban_duration),
+        ..Default::default()
+    });
+    let peer_handler = PeerHandler::new(peers, peer_policy.clone(), net::PEER_CREDENTIALS.clone());
     if let Err(err) = peer_handler.inflate().await {
         error!(message = "failed to inflate peer list", error = %err)
     };
@@ -102,46 +152,195 @@ async fn main() {
     // Token cache
     let token_cache = TokenCache::default();
 
+    // Event bus
+    let events = EventBus::default();
+
     // Setup ZMQ stream
-    let mut subscriber = async_zmq::subscribe(&SETTINGS.bitcoin_rpc.zmq_address)
-        .unwrap()
-        .connect()
-        .unwrap();
-    subscriber.set_subscribe("hashblock").unwrap(); // Unrecoverable
+    // This is safe as the ZMQ address is validated with the settings
+    let zmq_address = SETTINGS.bitcoin_rpc.zmq_address.clone().unwrap();
+    let subscriber = zmq::connect(&zmq_address).unwrap(); // Unrecoverable
+
+    // Announce blocks
+    tokio::spawn(zmq::watch_blocks(
+        subscriber,
+        zmq_address,
+        events.clone(),
+        lifecycle.token(),
+    ));
 
     // Start broadcast heartbeat
+    let mut blocks = events.block_connected().subscribe();
+    let mut gaps = events.notification_gap().subscribe();
     let token_cache_inner = token_cache.clone();
     let peer_handler_inner = peer_handler.clone();
     let db_inner = db.clone();
+    let lifecycle_inner = lifecycle.clone();
+    let shutdown = lifecycle.token();
     let broadcast_heartbeat = async move {
-        while let Some(val) = subscriber.next().await {
-            if let Ok(inner) = val {
-                if let Some(block) = inner.get(1) {
-                    info!(message = "found block", block_id = %hex::encode(block.as_ref()));
-                    token_cache_inner
-                        .broadcast_block(&peer_handler_inner, &db_inner)
-                        .await;
-                }
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().cancelled() => break,
+                block = blocks.recv() => if block.is_none() {
+                    break;
+                },
+                // Blocks were missed, so broadcast rather than wait a block longer
+                gap = gaps.recv() => if gap.is_none() {
+                    break;
+                },
             }
+            lifecycle_inner
+                .run(token_cache_inner.broadcast_block(&peer_handler_inner, &db_inner))
+                .await;
         }
     };
     tokio::spawn(broadcast_heartbeat);
 
+    // Deliver webhooks
+    tokio::spawn(net::deliver_webhooks(
+        events.metadata_updated().subscribe(),
+        lifecycle.token(),
+    ));
+
+    // Initialize bitcoin client
+    let bitcoin_client = BitcoinClientHTTP::with_timeouts(
+        SETTINGS.bitcoin_rpc.address.clone(),
+        SETTINGS.bitcoin_rpc.username.clone(),
+        SETTINGS.bitcoin_rpc.password.expose().clone(),
+        Timeouts {
+            connect: Some(SETTINGS.bitcoin_rpc.connect_timeout()),
+            request: Some(SETTINGS.bitcoin_rpc.request_timeout()),
+        },
+    );
+
+    // Follow the chain, caching recent outputs and POP transactions
+    let utxo_cache = UtxoCache::new(UTXO_CACHE_DEPTH);
+    let mut follower = ChainFollower::new(bitcoin_client.clone(), UTXO_CACHE_DEPTH);
+    let mut blocks = events.block_connected().subscribe();
+    let mut gaps = events.notification_gap().subscribe();
+    let utxo_cache_inner = utxo_cache.clone();
+    let shutdown = lifecycle.token();
+    let follow_chain = async move {
+        loop {
+            match follower.poll().await {
+                Ok(chain_events) => {
+                    for chain_event in &chain_events {
+                        if let Err(err) = utxo_cache_inner.apply(chain_event) {
+                            warn!(message = "failed to update UTXO cache", error = %err);
+                        }
+                    }
+                }
+                Err(err @ FollowerError::ReorgTooDeep(_))
+                | Err(err @ FollowerError::GenesisMismatch) => {
+                    warn!(message = "lost the followed chain", error = %err);
+                    utxo_cache_inner.clear();
+                }
+                Err(err) => warn!(message = "failed to follow chain", error = %err),
+            }
+            tokio::select! {
+                _ = shutdown.clone().cancelled() => break,
+                block = blocks.recv() => if block.is_none() {
+                    break;
+                },
+                gap = gaps.recv() => if gap.is_none() {
+                    break;
+                },
+            }
+        }
+    };
+    tokio::spawn(follow_chain);
+
+    // Start replication from peers, beating after each pass
+    let replication_heartbeat = Heartbeat::default();
+    let replicator: Option<net::SharedReplicator> = if SETTINGS.peering.enabled {
+        let https = HttpsConnector::new();
+        let client = VerifyingLayer::optional(net::PEER_VERIFIER.clone())
+            .layer(hyper::Client::builder().build(https));
+        let client = SigningLayer::optional(net::PEER_SIGNER.clone()).layer(client);
+        let client = CredentialsLayer::new(net::PEER_CREDENTIALS.clone()).layer(client);
+        let replicator = Replicator::new(KeyserverClient::from_service(client), db.clone())
+            .with_page_size(SETTINGS.limits.replication_page_size)
+            .with_lifecycle(lifecycle.clone())
+            .with_events(events.clone())
+            .with_policy(peer_policy.clone())
+            .with_token_scheme(ErasedScheme::shared(
+                ChainCommitmentScheme::from_client(bitcoin_client.clone())
+                    .with_cache(utxo_cache.clone()),
+            ));
+        Some(Arc::new(replicator))
+    } else {
+        None
+    };
+    if let Some(replicator) = replicator.clone() {
+        let peer_handler_inner = peer_handler.clone();
+        let heartbeat = replication_heartbeat.clone();
+        let shutdown = lifecycle.token();
+        let replication = async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_millis(SETTINGS.peering.replication_interval));
+            loop {
+                tokio::select! {
+                    _ = shutdown.clone().cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let peers = peer_handler_inner.get_urls().await;
+                for (peer, result) in replicator.replicate_all(&peers).await {
+                    match result {
+                        Ok(report) => info!(
+                            message = "replicated from peer",
+                            peer = %peer,
+                            accepted = report.accepted,
+                            stale = report.stale,
+                            invalid = report.invalid
+                        ),
+                        Err(err) => {
+                            warn!(message = "replication failed", peer = %peer, error = %err)
+                        }
+                    }
+                }
+                heartbeat.beat();
+            }
+        };
+        tokio::spawn(replication);
+    }
+
     // Peer state
     let peer_handler = warp::any().map(move || peer_handler.clone());
 
+    // Replicator state
+    let replicator_state = warp::any().map(move || replicator.clone());
+
     // Database state
+    let db_health = db.clone();
     let db_state = warp::any().map(move || db.clone());
 
     // PubSub Database state
     let pubsub_db_state = warp::any().map(move || pubsub_db.clone());
 
-    // Initialize bitcoin client
-    let bitcoin_client = BitcoinClientHTTP::new(
-        SETTINGS.bitcoin_rpc.address.clone(),
-        SETTINGS.bitcoin_rpc.username.clone(),
-        SETTINGS.bitcoin_rpc.password.clone(),
-    );
+    // Health report, covering the node, database and replication lag
+    let mut health = Health::new(lifecycle.clone())
+        .with_check("node", NodeCheck(bitcoin_client.clone()))
+        .with_check(
+            "store",
+            from_fn(move || {
+                let db_health = db_health.clone();
+                async move {
+                    match db_health.run_blocking(Database::probe_write).await {
+                        Ok(()) => CheckResult::healthy(),
+                        Err(err) => CheckResult::unhealthy(err),
+                    }
+                }
+            }),
+        );
+    if SETTINGS.peering.enabled {
+        health = health.with_check(
+            "replication",
+            HeartbeatCheck {
+                heartbeat: replication_heartbeat,
+                max_age: Duration::from_millis(3 * SETTINGS.peering.replication_interval),
+            },
+        );
+    }
+    let health = Arc::new(health);
 
     // Address string converter
     let addr_base = warp::path::param().and_then(|addr_str: String| async move {
@@ -149,31 +348,77 @@ async fn main() {
     });
 
     // Token generator
-    let token_scheme = Arc::new(ChainCommitmentScheme::from_client(bitcoin_client.clone()));
+    let token_scheme =
+        Arc::new(ChainCommitmentScheme::from_client(bitcoin_client.clone()).with_cache(utxo_cache));
     let token_scheme_state = warp::any().map(move || token_scheme.clone());
 
     // Token cache state
     let token_cache_state = warp::any().map(move || token_cache.clone());
 
+    // Idempotency state
+    let idempotency = net::IdempotencyCache::default();
+    let idempotency_state = warp::any().map(move || idempotency.clone());
+    let idempotency_key = warp::header::optional::<String>(IDEMPOTENCY_KEY);
+
+    // Event bus state
+    let events_state = warp::any().map(move || events.clone());
+
     // Bitcoin client state
     let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
 
+    // Namespace converter
+    let namespace_param = warp::path::tail().and_then(|tail: warp::path::Tail| async move {
+        Namespace::new(tail.as_str()).map_err(|_| warp::reject::not_found())
+    });
+
     // Protection
-    let addr_protected = addr_base
-        .and(warp::body::content_length_limit(
-            SETTINGS.limits.metadata_size,
-        ))
+    let protection = warp::body::content_length_limit(SETTINGS.limits.metadata_size)
         .and(warp::body::bytes())
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and_then(|body, content_encoding| async move {
+            net::decompress_body(body, content_encoding, SETTINGS.limits.metadata_size)
+                .map_err(warp::reject::custom)
+        })
         .and(warp::header::headers_cloned())
-        .and(token_scheme_state.clone())
+        .and(token_scheme_state.clone());
+    let addr_protected = addr_base
+        .and(warp::path::end())
+        .and(protection.clone())
         .and_then(move |addr, body, headers, token_scheme| {
             net::pop_protection(addr, body, headers, token_scheme).map_err(warp::reject::custom)
         })
         .untuple_one();
+    let addr_patch_protected = addr_base
+        .and(warp::path::end())
+        .and(protection.clone())
+        .and_then(move |addr, body, headers, token_scheme| {
+            net::patch_protection(addr, body, headers, token_scheme).map_err(warp::reject::custom)
+        })
+        .untuple_one();
+    let addr_namespace_protected = addr_base
+        .and(namespace_param)
+        .and(protection)
+        .and_then(
+            move |addr, namespace: Namespace, body, headers, token_scheme| async move {
+                let (addr, auth_wrapper_raw, auth_wrapper, raw_token) =
+                    net::pop_protection(addr, body, headers, token_scheme)
+                        .await
+                        .map_err(warp::reject::custom)?;
+                Ok::<_, warp::Rejection>((
+                    addr,
+                    namespace,
+                    auth_wrapper_raw,
+                    auth_wrapper,
+                    raw_token,
+                ))
+            },
+        )
+        .untuple_one();
 
     // Metadata handlers
     let metadata_get = warp::path(METADATA_PATH)
         .and(addr_base)
+        .and(warp::path::end())
         .and(warp::get())
         .and(warp::header::headers_cloned())
         .and(db_state.clone())
@@ -181,32 +426,167 @@ async fn main() {
         .and_then(move |addr, headers, db, peer_handler| {
             net::get_metadata(addr, headers, db, peer_handler).map_err(warp::reject::custom)
         });
+    #[derive(Deserialize)]
+    struct MetadataSinceQueryParameters {
+        since: i64,
+        limit: Option<usize>,
+    }
+    let request_uri = warp::path::full()
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .map(net::request_uri);
+    let peer_signature = warp::method()
+        .and(request_uri)
+        .and(warp::header::headers_cloned())
+        .and_then(|method, uri, headers| async move {
+            net::verify_peer(method, uri, headers).map_err(warp::reject::custom)
+        });
+    let metadata_since = warp::path(METADATA_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(peer_signature)
+        .and(warp::query::<MetadataSinceQueryParameters>())
+        .and(db_state.clone())
+        .and_then(
+            move |peer_request, params: MetadataSinceQueryParameters, db| {
+                net::get_metadata_since(params.since, params.limit, peer_request, db)
+                    .map_err(warp::reject::custom)
+            },
+        );
     let metadata_put = warp::path(METADATA_PATH)
-        .and(addr_protected)
         .and(warp::put())
+        .and(addr_protected)
         .and(warp::body::content_length_limit(
             SETTINGS.limits.metadata_size,
         ))
+        .and(idempotency_key)
         .and(db_state.clone())
-        .and(token_cache_state)
+        .and(token_cache_state.clone())
+        .and(idempotency_state.clone())
+        .and(events_state.clone())
         .and_then(
-            move |addr, auth_wrapper_raw, auth_wrapper, raw_token, db, token_cache| {
+            move |addr,
+                  auth_wrapper_raw,
+                  auth_wrapper,
+                  raw_token,
+                  idempotency_key,
+                  db,
+                  token_cache,
+                  idempotency,
+                  events| {
                 net::put_metadata(
                     addr,
                     auth_wrapper_raw,
                     auth_wrapper,
                     raw_token,
+                    idempotency_key,
                     db,
                     token_cache,
+                    idempotency,
+                    events,
                 )
                 .map_err(warp::reject::custom)
             },
         );
 
+    let metadata_patch = warp::path(METADATA_PATH)
+        .and(warp::patch())
+        .and(addr_patch_protected)
+        .and(idempotency_key)
+        .and(db_state.clone())
+        .and(token_cache_state)
+        .and(idempotency_state.clone())
+        .and(events_state.clone())
+        .and_then(
+            move |addr,
+                  metadata_patch,
+                  auth_wrapper,
+                  raw_token,
+                  idempotency_key,
+                  db,
+                  token_cache,
+                  idempotency,
+                  events| {
+                net::patch_metadata(
+                    addr,
+                    metadata_patch,
+                    auth_wrapper,
+                    raw_token,
+                    idempotency_key,
+                    db,
+                    token_cache,
+                    idempotency,
+                    events,
+                )
+                .map_err(warp::reject::custom)
+            },
+        );
+    let metadata_namespace_get = warp::path(METADATA_PATH)
+        .and(addr_base)
+        .and(namespace_param)
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(db_state.clone())
+        .and_then(move |addr, namespace, headers, db| {
+            net::get_namespace_metadata(addr, namespace, headers, db).map_err(warp::reject::custom)
+        });
+    let metadata_namespace_put = warp::path(METADATA_PATH)
+        .and(warp::put())
+        .and(addr_namespace_protected)
+        .and(idempotency_key)
+        .and(db_state.clone())
+        .and(idempotency_state)
+        .and(events_state.clone())
+        .and_then(
+            move |addr,
+                  namespace,
+                  auth_wrapper_raw,
+                  auth_wrapper,
+                  raw_token,
+                  idempotency_key,
+                  db,
+                  idempotency,
+                  events| {
+                net::put_namespace_metadata(
+                    addr,
+                    namespace,
+                    auth_wrapper_raw,
+                    auth_wrapper,
+                    raw_token,
+                    idempotency_key,
+                    db,
+                    idempotency,
+                    events,
+                )
+                .map_err(warp::reject::custom)
+            },
+        );
+
+    // Search handler
+    #[derive(Deserialize)]
+    struct SearchQueryParameters {
+        prefix: String,
+        cursor: Option<String>,
+        limit: Option<usize>,
+    }
+    let search = warp::path(SEARCH_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<SearchQueryParameters>())
+        .and(db_state.clone())
+        .and_then(move |params: SearchQueryParameters, db| {
+            net::search(params.prefix, params.cursor, params.limit, db)
+                .map_err(warp::reject::custom)
+        });
+
     // Peer handler
     let peers_get = warp::path(PEERS_PATH)
         .and(warp::get())
-        .and(peer_handler)
+        .and(peer_handler.clone())
         .and_then(move |peer_handler| net::get_peers(peer_handler).map_err(warp::reject::custom));
 
     let payload_digest_path_param =
@@ -245,6 +625,7 @@ async fn main() {
         .and_then(move |db, bitcoin_client, body| {
             println!("Received new message");
             let wrapper = AuthWrapper::decode(body).unwrap();
+            let bitcoin_client = InstrumentedClient::new(bitcoin_client, GlobalMetrics);
             pubsub::put_message(db, bitcoin_client, wrapper)
         });
 
@@ -262,38 +643,132 @@ async fn main() {
                 .map_err(warp::reject::custom)
         })
         .and(bitcoin_client_state.clone())
-        .and_then(move |payment, bitcoin_client| async move {
-            net::process_payment(payment, bitcoin_client)
+        .and(events_state)
+        .and_then(move |payment, bitcoin_client, events| async move {
+            net::process_payment(payment, bitcoin_client, events)
                 .await
                 .map_err(warp::reject::custom)
         });
 
+    // Admin handlers
+    let admin_auth = warp::method()
+        .and(request_uri)
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.metadata_size,
+        ))
+        .and(warp::body::bytes())
+        .and_then(|method, uri, headers, body| async move {
+            net::authorize_admin(method, uri, headers, body).map_err(warp::reject::custom)
+        });
+    let admin_ban_peer = warp::path(ADMIN_PATH)
+        .and(warp::path(BAN_PEER_PATH))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin_auth)
+        .and(peer_handler.clone())
+        .and(db_state.clone())
+        .and_then(move |body, peer_handler, db| {
+            net::ban_peer(body, peer_handler, db).map_err(warp::reject::custom)
+        });
+    let admin_purge = warp::path(ADMIN_PATH)
+        .and(warp::path(KEYS_PATH))
+        .and(addr_base)
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(admin_auth)
+        .and(db_state.clone())
+        .and_then(move |addr, _, db| net::purge_address(addr, db).map_err(warp::reject::custom));
+    let admin_stats = warp::path(ADMIN_PATH)
+        .and(warp::path(STATS_PATH))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin_auth)
+        .and(db_state.clone())
+        .and(peer_handler.clone())
+        .and_then(move |_, db, peer_handler| {
+            net::storage_stats(db, peer_handler).map_err(warp::reject::custom)
+        });
+    let admin_replicate = warp::path(ADMIN_PATH)
+        .and(warp::path(REPLICATE_PATH))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin_auth)
+        .and(replicator_state)
+        .and(peer_handler.clone())
+        .and_then(move |_, replicator, peer_handler| {
+            net::force_replication(replicator, peer_handler).map_err(warp::reject::custom)
+        });
+
     // Root handler
     let root = warp::path::end()
         .and(warp::get())
         .and(warp::fs::file("./static/index.html"));
 
+    // Health probes
+    let health_get = warp::path(HEALTH_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || net::health_report(health.clone()));
+    let lifecycle_inner = lifecycle.clone();
+    let health_live = warp::path(HEALTH_PATH)
+        .and(warp::path("live"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || net::probe(lifecycle_inner.is_live()));
+    let lifecycle_inner = lifecycle.clone();
+    let health_ready = warp::path(HEALTH_PATH)
+        .and(warp::path("ready"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || net::probe(lifecycle_inner.is_ready()));
+
     // CORs
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_methods(vec![Method::GET, Method::PUT, Method::POST, Method::DELETE])
-        .allow_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
+        .allow_methods(vec![
+            Method::GET,
+            Method::PUT,
+            Method::PATCH,
+            Method::POST,
+            Method::DELETE,
+        ])
+        .allow_headers(vec![
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+        ])
+        .allow_header(IDEMPOTENCY_KEY)
         .expose_headers(vec![
             header::AUTHORIZATION,
+            header::CONTENT_ENCODING,
             header::ACCEPT,
             header::LOCATION,
         ])
+        .expose_header(IDEMPOTENT_REPLAYED)
         .build();
 
     // Init REST API
     let rest_api = root
         .or(payments)
         .or(metadata_get)
+        .or(metadata_since)
         .or(metadata_put)
+        .or(metadata_patch)
+        .or(metadata_namespace_get)
+        .or(metadata_namespace_put)
+        .or(search)
         .or(peers_get)
         .or(messages_get)
         .or(messages_get_id)
         .or(messages_put)
+        .or(health_get)
+        .or(health_live)
+        .or(health_ready)
+        .or(admin_ban_peer)
+        .or(admin_purge)
+        .or(admin_stats)
+        .or(admin_replicate)
         .recover(net::handle_rejection)
         .with(cors)
         .with(warp::trace::request());
@@ -309,11 +784,13 @@ async fn main() {
 
         // Init REST API
         let rest_api = rest_api.with(warp::log::custom(monitoring::measure));
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
+        let (_, rest_api_task) = warp::serve(rest_api)
+            .bind_with_graceful_shutdown(SETTINGS.bind, lifecycle.token().cancelled());
 
         // Spawn servers
         tokio::spawn(prometheus_task);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        lifecycle.set_ready();
+        rest_api_task.await;
     }
 
     // If monitoring is disabled
@@ -321,7 +798,18 @@ async fn main() {
     {
         info!(monitoring = false);
 
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        let (_, rest_api_task) = warp::serve(rest_api)
+            .bind_with_graceful_shutdown(SETTINGS.bind, lifecycle.token().cancelled());
+        lifecycle.set_ready();
+        rest_api_task.await;
+    }
+
+    // Wait for in-flight work to complete
+    if let Err(err) = lifecycle
+        .drain(Duration::from_millis(SETTINGS.drain_timeout))
+        .await
+    {
+        warn!(message = "stopped before draining", error = %err);
     }
+    info!(message = "stopped");
 }
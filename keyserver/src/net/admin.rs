@@ -0,0 +1,184 @@
+use std::{sync::Arc, time::SystemTime};
+
+use bitcoincash_addr::Address;
+use bytes::Bytes;
+use cashweb::{
+    keyserver::{admin::signing_data, Peer, PurgeSummary, ReplicationSummary},
+    keyserver_client::{credentials::CredentialsService, replication::Replicator},
+    token::{
+        keys::SecretKey,
+        schemes::hmac_bearer::{HmacScheme, ValidationError},
+        signing::{SignatureError, SigningService, VerifyingService, SIGNATURE_HEADER},
+    },
+};
+use http::header::{HeaderMap, AUTHORIZATION};
+use hyper::{client::HttpConnector, Uri};
+use hyper_tls::HttpsConnector;
+use lazy_static::lazy_static;
+use prost::Message as _;
+use thiserror::Error;
+use tracing::{info, warn};
+use warp::{
+    http::{Method, Response},
+    hyper::Body,
+    reject::Reject,
+};
+
+use crate::{
+    db::Database,
+    net::{ToResponse, ADMIN_VERIFIER},
+    peering::PeerHandler,
+    SETTINGS,
+};
+
+type PeerClient = CredentialsService<
+    SigningService<VerifyingService<hyper::Client<HttpsConnector<HttpConnector>>>>,
+>;
+
+/// Replicator shared by the replication loop and the admin API.
+pub type SharedReplicator = Arc<Replicator<PeerClient, Database>>;
+
+lazy_static! {
+    // Validates admin tokens, if the admin API is enabled
+    static ref ADMIN_SCHEME: Option<HmacScheme> = SETTINGS.admin.secret.as_ref().map(|secret| {
+        let key = SecretKey::load(secret.expose()).expect("unable to load admin secret");
+        HmacScheme::from_secret(key)
+    });
+}
+
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error("admin API disabled")]
+    Disabled,
+    #[error("missing bearer token")]
+    MissingToken,
+    #[error("unauthorized: {0}")]
+    Unauthorized(ValidationError),
+    #[error("signature rejected: {0}")]
+    Signature(SignatureError),
+    #[error("invalid peer: {0}")]
+    InvalidPeer(String),
+    #[error("peering disabled")]
+    PeeringDisabled,
+    #[error(transparent)]
+    Database(#[from] rocksdb::Error),
+}
+
+impl Reject for AdminError {}
+
+impl ToResponse for AdminError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::Disabled => 404,
+            Self::MissingToken | Self::Unauthorized(_) | Self::Signature(_) => 401,
+            Self::InvalidPeer(_) => 400,
+            Self::PeeringDisabled => 501,
+            Self::Database(_) => 500,
+        }
+    }
+}
+
+/// Check an admin request is signed by an admin key, or carries a bearer token covering its
+/// method, path and body.
+pub fn authorize_admin(
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Bytes, AdminError> {
+    if let Some(verifier) = ADMIN_VERIFIER.as_ref() {
+        if headers.contains_key(SIGNATURE_HEADER) {
+            verifier
+                .verify(&method, &uri, &headers, &body)
+                .map_err(AdminError::Signature)?;
+            return Ok(body);
+        }
+    }
+
+    let scheme = match ADMIN_SCHEME.as_ref() {
+        Some(some) => some,
+        None if ADMIN_VERIFIER.is_some() => return Err(AdminError::MissingToken),
+        None => return Err(AdminError::Disabled),
+    };
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AdminError::MissingToken)?;
+    let data = signing_data(method.as_str(), uri.path(), &body);
+    scheme
+        .validate_token_at(&data, token, SystemTime::now())
+        .map_err(AdminError::Unauthorized)?;
+    Ok(body)
+}
+
+fn encoded_response<M: prost::Message>(message: M) -> Response<Body> {
+    let mut raw = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut raw).unwrap(); // This is safe
+    Response::builder().body(Body::from(raw)).unwrap() // This is safe
+}
+
+/// Handles peer ban requests.
+pub async fn ban_peer<S: Clone>(
+    body: Bytes,
+    peer_handler: PeerHandler<S>,
+    database: Database,
+) -> Result<Response<Body>, AdminError> {
+    let peer = Peer::decode(body).map_err(|err| AdminError::InvalidPeer(err.to_string()))?;
+    let uri: Uri = peer
+        .url
+        .parse()
+        .map_err(|_| AdminError::InvalidPeer(peer.url.clone()))?;
+    info!(message = "banning peer", peer = %uri);
+    peer_handler.ban(uri).await;
+    peer_handler.persist(&database).await?;
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles address purge requests.
+pub async fn purge_address(
+    addr: Address,
+    database: Database,
+) -> Result<Response<Body>, AdminError> {
+    let key = addr.as_body().to_vec();
+    let removed = database
+        .run_blocking(move |database| database.purge(&key))
+        .await?;
+    let addr_str = addr.encode().unwrap(); // This is safe
+    info!(message = "purged address", address = %addr_str, removed);
+    Ok(encoded_response(PurgeSummary { removed }))
+}
+
+/// Handles storage statistics requests.
+pub async fn storage_stats<S: Clone>(
+    database: Database,
+    peer_handler: PeerHandler<S>,
+) -> Result<Response<Body>, AdminError> {
+    let mut stats = database.run_blocking(Database::stats).await?;
+    stats.peers = peer_handler.get_urls().await.len() as u64;
+    Ok(encoded_response(stats))
+}
+
+/// Handles forced replication requests, running a pass over every peer.
+pub async fn force_replication<S: Clone>(
+    replicator: Option<SharedReplicator>,
+    peer_handler: PeerHandler<S>,
+) -> Result<Response<Body>, AdminError> {
+    let replicator = replicator.ok_or(AdminError::PeeringDisabled)?;
+    let peers = peer_handler.get_urls().await;
+    let mut summary = ReplicationSummary::default();
+    for (peer, result) in replicator.replicate_all(&peers).await {
+        match result {
+            Ok(report) => {
+                summary.accepted += report.accepted as u64;
+                summary.stale += report.stale as u64;
+                summary.invalid += report.invalid as u64;
+            }
+            Err(err) => {
+                warn!(message = "replication failed", peer = %peer, error = %err);
+                summary.failed_peers.push(peer.to_string());
+            }
+        }
+    }
+    Ok(encoded_response(summary))
+}
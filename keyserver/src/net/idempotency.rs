@@ -0,0 +1,177 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use cashweb::keyserver::idempotency::{is_valid_key, IDEMPOTENT_REPLAYED};
+use dashmap::{mapref::entry::Entry, DashMap};
+use thiserror::Error;
+use warp::{http::Response, hyper::Body};
+
+use crate::net::HEADER_VALUE_TRUE;
+
+/// How long the outcome of an idempotent write is remembered.
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Error)]
+pub enum IdempotencyError {
+    #[error("invalid idempotency key")]
+    InvalidKey,
+    #[error("a request with this idempotency key is in progress")]
+    InProgress,
+    #[error("idempotency key was used by a different request")]
+    KeyReused,
+}
+
+impl IdempotencyError {
+    pub fn to_status(&self) -> u16 {
+        match self {
+            Self::InvalidKey => 400,
+            Self::InProgress => 409,
+            Self::KeyReused => 422,
+        }
+    }
+}
+
+struct Record {
+    request_digest: [u8; 32],
+    is_complete: bool,
+    created: Instant,
+}
+
+type RecordKey = (Vec<u8>, String);
+
+/// Remembers metadata writes by idempotency key, so that retries are answered without writing
+/// again.
+#[derive(Clone, Default)]
+pub struct IdempotencyCache {
+    records: Arc<DashMap<RecordKey, Record>>,
+}
+
+/// Whether a write should proceed or be answered as a replay.
+pub enum Admission {
+    /// Proceed with the write, completing the guard if it succeeds.
+    Proceed(IdempotencyGuard),
+    /// The write already succeeded.
+    Replay,
+}
+
+/// Releases the idempotency key if the write fails or is cancelled before completion.
+pub struct IdempotencyGuard {
+    inner: Option<(IdempotencyCache, RecordKey)>,
+}
+
+impl IdempotencyGuard {
+    /// Record the write as successful.
+    pub fn complete(mut self) {
+        if let Some((cache, key)) = self.inner.take() {
+            if let Some(mut record) = cache.records.get_mut(&key) {
+                record.is_complete = true;
+            }
+        }
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        if let Some((cache, key)) = self.inner.take() {
+            cache.records.remove(&key);
+        }
+    }
+}
+
+impl IdempotencyCache {
+    /// Admit a write to the metadata at the store key, with the digest of the request body.
+    ///
+    /// Writes without an idempotency key always proceed.
+    pub fn admit(
+        &self,
+        store_key: &[u8],
+        idempotency_key: Option<String>,
+        request_digest: [u8; 32],
+    ) -> Result<Admission, IdempotencyError> {
+        let idempotency_key = match idempotency_key {
+            Some(some) => some,
+            None => return Ok(Admission::Proceed(IdempotencyGuard { inner: None })),
+        };
+        if !is_valid_key(&idempotency_key) {
+            return Err(IdempotencyError::InvalidKey);
+        }
+
+        // Forget expired writes
+        self.records
+            .retain(|_, record| record.created.elapsed() < IDEMPOTENCY_TTL);
+
+        let key = (store_key.to_vec(), idempotency_key);
+        match self.records.entry(key.clone()) {
+            Entry::Occupied(occupied) => {
+                let record = occupied.get();
+                if record.request_digest != request_digest {
+                    Err(IdempotencyError::KeyReused)
+                } else if record.is_complete {
+                    Ok(Admission::Replay)
+                } else {
+                    Err(IdempotencyError::InProgress)
+                }
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(Record {
+                    request_digest,
+                    is_complete: false,
+                    created: Instant::now(),
+                });
+                Ok(Admission::Proceed(IdempotencyGuard {
+                    inner: Some((self.clone(), key)),
+                }))
+            }
+        }
+    }
+}
+
+/// The response to a replayed write.
+pub fn replayed_response() -> Response<Body> {
+    Response::builder()
+        .header(IDEMPOTENT_REPLAYED, HEADER_VALUE_TRUE)
+        .body(Body::empty())
+        .unwrap() // This is safe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admit() {
+        let cache = IdempotencyCache::default();
+        let key = Some("key".to_string());
+
+        // Concurrent retries wait for the original write
+        let guard = match cache.admit(b"addr", key.clone(), [1; 32]).unwrap() {
+            Admission::Proceed(guard) => guard,
+            Admission::Replay => panic!("unexpected replay"),
+        };
+        assert!(matches!(
+            cache.admit(b"addr", key.clone(), [1; 32]),
+            Err(IdempotencyError::InProgress)
+        ));
+        guard.complete();
+
+        // Retries are replayed, and other bodies refused
+        assert!(matches!(
+            cache.admit(b"addr", key.clone(), [1; 32]),
+            Ok(Admission::Replay)
+        ));
+        assert!(matches!(
+            cache.admit(b"addr", key.clone(), [2; 32]),
+            Err(IdempotencyError::KeyReused)
+        ));
+
+        // Failed writes release the key
+        let guard = cache.admit(b"other", key.clone(), [1; 32]).unwrap();
+        drop(guard);
+        assert!(matches!(
+            cache.admit(b"other", key, [1; 32]),
+            Ok(Admission::Proceed(_))
+        ));
+    }
+}
@@ -1,8 +1,15 @@
-use cashweb::auth_wrapper::{ParseError, VerifyError};
+use cashweb::{
+    auth_wrapper::{ParseError, VerifyError},
+    keyserver::{
+        avatar::{ImageError, InvalidImageEntry},
+        namespace::OutsideNamespace,
+    },
+};
+use prost::DecodeError;
 use thiserror::Error;
 use warp::reject::Reject;
 
-use crate::net::ToResponse;
+use crate::net::{IdempotencyError, ToResponse};
 
 #[derive(Debug, Error)]
 pub enum PutMetadataError {
@@ -12,6 +19,22 @@ pub enum PutMetadataError {
     InvalidAuthWrapper(ParseError),
     #[error("failed to parse authorization wrapper: {0}")]
     VerifyAuthWrapper(VerifyError),
+    #[error("failed to decode metadata: {0}")]
+    MetadataDecode(DecodeError),
+    #[error("metadata timestamp lies in the future")]
+    FutureTimestamp,
+    #[error(transparent)]
+    OutsideNamespace(OutsideNamespace),
+    #[error(transparent)]
+    Image(InvalidImageEntry),
+    #[error(transparent)]
+    Idempotency(IdempotencyError),
+    #[error("no metadata to patch")]
+    MissingBase,
+    #[error("base digest does not match the stored metadata")]
+    Conflict,
+    #[error("patched metadata does not match the payload digest")]
+    DigestMismatch,
 }
 
 impl From<rocksdb::Error> for PutMetadataError {
@@ -26,6 +49,13 @@ impl ToResponse for PutMetadataError {
     fn to_status(&self) -> u16 {
         match self {
             Self::Database(_) => 500,
+            Self::MissingBase | Self::Conflict => 409,
+            Self::Idempotency(err) => err.to_status(),
+            Self::Image(invalid) => match invalid.error {
+                ImageError::TooLarge { .. } | ImageError::TooManyPixels { .. } => 413,
+                ImageError::UnsupportedType(_) => 415,
+                _ => 400,
+            },
             _ => 400,
         }
     }
@@ -55,3 +85,22 @@ impl ToResponse for GetMetadataError {
         }
     }
 }
+
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error("failed to decode cursor: {0}")]
+    CursorDecode(hex::FromHexError),
+    #[error("failed to read from database: {0}")]
+    Database(rocksdb::Error),
+}
+
+impl Reject for SearchError {}
+
+impl ToResponse for SearchError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::CursorDecode(_) => 400,
+            Self::Database(_) => 500,
+        }
+    }
+}
@@ -2,28 +2,104 @@ mod errors;
 
 pub use crate::net::metadata::errors::*;
 
-use std::fmt;
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bitcoincash_addr::Address;
 use bytes::Bytes;
-use cashweb::auth_wrapper::AuthWrapper;
+use cashweb::{
+    auth_wrapper::AuthWrapper,
+    keyserver::{
+        avatar::ImageConstraints,
+        compression::Encoding,
+        namespace::{metadata_key, split_metadata_key, Namespace},
+        patch,
+        store::{MetadataStore, StoredMetadata},
+        AddressMetadata, MetadataEntry, MetadataPage, MetadataPatch, SearchPage,
+    },
+    lifecycle::bus::{EventBus, MetadataUpdated, Origin},
+};
 use http::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    header::{
+        HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CACHE_CONTROL, CONTENT_ENCODING,
+        VARY,
+    },
     Request,
 };
+use lazy_static::lazy_static;
 use prost::Message as _;
 use tokio::task;
 use tower_service::Service;
 use warp::{http::Response, hyper::Body};
 
 use crate::{
+    crypto::sha256,
     db::Database,
-    models::database::DatabaseWrapper,
-    net::{HEADER_VALUE_FALSE, SAMPLING},
+    net::{
+        compress_body, replayed_response, Admission, IdempotencyCache, PeerRequest,
+        HEADER_VALUE_FALSE, SAMPLING,
+    },
     peering::{PeerHandler, TokenCache},
     SETTINGS,
 };
 
+lazy_static! {
+    // Constraints on image entries, such as avatars
+    static ref IMAGE_CONSTRAINTS: ImageConstraints = ImageConstraints::new()
+        .with_max_size(SETTINGS.limits.image_size)
+        .with_max_dimensions(SETTINGS.limits.image_dimension, SETTINGS.limits.image_dimension);
+
+    // Cache-Control header attached to metadata responses, if caching is enabled
+    static ref CACHE_CONTROL_VALUE: Option<String> = match (
+        SETTINGS.caching.max_age,
+        SETTINGS.caching.stale_while_revalidate,
+    ) {
+        (0, 0) => None,
+        (max_age, 0) => Some(format!("max-age={}", max_age)),
+        (max_age, stale) => Some(format!("max-age={}, stale-while-revalidate={}", max_age, stale)),
+    };
+}
+
+/// Check the image entries of the metadata against the configured limits.
+fn check_images(metadata: &AddressMetadata) -> Result<(), PutMetadataError> {
+    IMAGE_CONSTRAINTS
+        .validate_metadata(metadata)
+        .map_err(PutMetadataError::Image)
+}
+
+/// Check the metadata timestamp, in milliseconds, doesn't lie beyond the allowed clock skew.
+///
+/// Peers skip future metadata during replication, so accepting it would leave it unreplicated.
+fn check_timestamp(timestamp: i64) -> Result<(), PutMetadataError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap() // This is safe
+        .as_millis() as i64;
+    if timestamp > now.saturating_add(SETTINGS.peering.max_clock_skew as i64) {
+        return Err(PutMetadataError::FutureTimestamp);
+    }
+    Ok(())
+}
+
+/// Announce metadata written by a client.
+fn publish_update(
+    events: &EventBus,
+    addr: &Address,
+    namespace: &str,
+    timestamp: i64,
+    raw_auth_wrapper: Vec<u8>,
+) {
+    events.metadata_updated().publish(MetadataUpdated {
+        address: addr.as_body().to_vec(),
+        namespace: namespace.to_string(),
+        timestamp,
+        raw_auth_wrapper,
+        origin: Origin::Local,
+    });
+}
+
 /// Handles metadata GET requests.
 pub async fn get_metadata<S>(
     addr: Address,
@@ -44,17 +120,12 @@ where
 
     // If found in the database
     if let Some(some) = wrapper_opt {
-        let raw_auth_wrapper = some.serialized_auth_wrapper;
-
-        // Encode token
-        let raw_token = some.token;
-        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let token = format!("POP {}", base64::encode_config(raw_token, url_safe_config));
-
-        return Ok(Response::builder()
-            .header(AUTHORIZATION, token)
-            .body(Body::from(raw_auth_wrapper))
-            .unwrap()); // TODO: Headers
+        let token = pop_header(some.token);
+        return Ok(metadata_response(
+            &some.serialized_auth_wrapper,
+            token,
+            &headers,
+        )); // TODO: Headers
     }
 
     // If MAX_FORWARDS is 0 then don't sample peers
@@ -73,10 +144,7 @@ where
             if let Some((_, metadata_package)) = sample_response.response {
                 let token = metadata_package.token;
                 let raw_auth_wrapper = metadata_package.raw_auth_wrapper;
-                Ok(Response::builder()
-                    .header(AUTHORIZATION, token)
-                    .body(Body::from(raw_auth_wrapper))
-                    .unwrap())
+                Ok(metadata_response(&raw_auth_wrapper, token, &headers))
             } else {
                 Err(GetMetadataError::NotFound)
             }
@@ -85,35 +153,279 @@ where
     }
 }
 
+/// Encode a raw POP token as an `Authorization` header value.
+fn pop_header(raw_token: Vec<u8>) -> String {
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    format!("POP {}", base64::encode_config(raw_token, url_safe_config))
+}
+
+/// Respond with the authorization wrapper and its POP token, compressed if the client accepts
+/// it.
+fn metadata_response(
+    raw_auth_wrapper: &[u8],
+    token: String,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    let (encoding, body) = compress_body(raw_auth_wrapper, headers);
+    let mut builder = Response::builder()
+        .header(AUTHORIZATION, token)
+        .header(VARY, ACCEPT_ENCODING.as_str());
+    if let Some(cache_control) = CACHE_CONTROL_VALUE.as_ref() {
+        builder = builder.header(CACHE_CONTROL, cache_control.as_str());
+    }
+    if encoding != Encoding::Identity {
+        builder = builder.header(CONTENT_ENCODING, encoding.as_str());
+    }
+    builder.body(Body::from(body)).unwrap()
+}
+
+/// Handles namespaced metadata GET requests.
+///
+/// Namespaces are replicated between peers, so only the local database is consulted.
+pub async fn get_namespace_metadata(
+    addr: Address,
+    namespace: Namespace,
+    headers: HeaderMap,
+    database: Database,
+) -> Result<Response<Body>, GetMetadataError> {
+    let key = metadata_key(addr.as_body(), namespace.as_str());
+    let wrapper = database
+        .get_metadata(&key)
+        .map_err(GetMetadataError::Database)?
+        .ok_or(GetMetadataError::NotFound)?;
+
+    let token = pop_header(wrapper.token);
+    Ok(metadata_response(
+        &wrapper.serialized_auth_wrapper,
+        token,
+        &headers,
+    ))
+}
+
+/// Handles metadata page GET requests, used by peers during replication.
+///
+/// POP tokens are only included for authenticated peers, as they would otherwise allow anyone to
+/// replay the tokens of other users. The response is signed, if a signing key is configured.
+pub async fn get_metadata_since(
+    since: i64,
+    limit: Option<usize>,
+    peer_request: PeerRequest,
+    database: Database,
+) -> Result<Response<Body>, GetMetadataError> {
+    let limit = limit
+        .unwrap_or(SETTINGS.limits.replication_page_size)
+        .min(SETTINGS.limits.replication_page_size);
+    let entries = database
+        .since(since, limit)
+        .await
+        .map_err(GetMetadataError::Database)?
+        .into_iter()
+        .map(|(key, metadata)| MetadataEntry {
+            address: split_metadata_key(&key, &metadata.namespace).to_vec(),
+            raw_auth_wrapper: metadata.raw_auth_wrapper,
+            token: if peer_request.authenticated {
+                metadata.token
+            } else {
+                Vec::new()
+            },
+            timestamp: metadata.timestamp,
+            namespace: metadata.namespace,
+        })
+        .collect();
+    let page = MetadataPage { entries };
+    let mut raw_page = Vec::with_capacity(page.encoded_len());
+    page.encode(&mut raw_page).unwrap(); // This is safe
+
+    let mut response = Response::builder().body(Body::empty()).unwrap();
+    peer_request.sign_response(&mut response, &raw_page);
+    *response.body_mut() = Body::from(raw_page);
+    Ok(response)
+}
+
+/// Handles search requests, matching names and handles in the vCards of root metadata.
+pub async fn search(
+    prefix: String,
+    cursor: Option<String>,
+    limit: Option<usize>,
+    database: Database,
+) -> Result<Response<Body>, SearchError> {
+    let limit = limit
+        .unwrap_or(SETTINGS.limits.replication_page_size)
+        .min(SETTINGS.limits.replication_page_size);
+    let cursor = cursor
+        .map(|cursor| hex::decode(cursor).map_err(SearchError::CursorDecode))
+        .transpose()?;
+    let (matches, last_key) =
+        task::spawn_blocking(move || database.search(&prefix, cursor.as_deref(), limit))
+            .await
+            .unwrap()
+            .map_err(SearchError::Database)?;
+
+    // A full page may be followed by further matches
+    let next_cursor = match last_key {
+        Some(last_key) if matches.len() == limit => hex::encode(last_key),
+        _ => String::new(),
+    };
+    let page = SearchPage {
+        matches,
+        next_cursor,
+    };
+    let mut raw_page = Vec::with_capacity(page.encoded_len());
+    page.encode(&mut raw_page).unwrap(); // This is safe
+
+    Ok(Response::builder().body(Body::from(raw_page)).unwrap())
+}
+
+/// Admit a write, unless it replays an earlier write with the same idempotency key.
+fn admit(
+    idempotency: &IdempotencyCache,
+    key: &[u8],
+    idempotency_key: Option<String>,
+    body: &[u8],
+) -> Result<Admission, PutMetadataError> {
+    idempotency
+        .admit(key, idempotency_key, sha256(body))
+        .map_err(PutMetadataError::Idempotency)
+}
+
 /// Handles metadata PUT requests.
+///
+/// A retry carrying the idempotency key of an earlier successful put is answered without writing
+/// again, so it can't overwrite newer metadata.
+#[allow(clippy::too_many_arguments)]
 pub async fn put_metadata(
     addr: Address,
     auth_wrapper_raw: Bytes,
     auth_wrapper: AuthWrapper,
     token_raw: Vec<u8>,
+    idempotency_key: Option<String>,
     db_data: Database,
     token_cache: TokenCache,
+    idempotency: IdempotencyCache,
+    events: EventBus,
 ) -> Result<Response<Body>, PutMetadataError> {
+    let guard = match admit(
+        &idempotency,
+        addr.as_body(),
+        idempotency_key,
+        &auth_wrapper_raw,
+    )? {
+        Admission::Proceed(guard) => guard,
+        Admission::Replay => return Ok(replayed_response()),
+    };
+
     // Verify signatures
-    auth_wrapper
+    let parsed_auth_wrapper = auth_wrapper
         .parse()
-        .map_err(PutMetadataError::InvalidAuthWrapper)?
+        .map_err(PutMetadataError::InvalidAuthWrapper)?;
+    parsed_auth_wrapper
         .verify()
         .map_err(PutMetadataError::VerifyAuthWrapper)?;
 
-    // Wrap with database
-    let database_wrapper = DatabaseWrapper {
-        serialized_auth_wrapper: auth_wrapper_raw.to_vec(),
-        token: token_raw,
+    // Index by the metadata timestamp and check its images, if it can be decoded
+    let timestamp = match AddressMetadata::decode(parsed_auth_wrapper.payload.as_slice()) {
+        Ok(address_metadata) => {
+            check_timestamp(address_metadata.timestamp)?;
+            check_images(&address_metadata)?;
+            address_metadata.timestamp
+        }
+        Err(_) => 0,
     };
-    let mut raw_database_wrapper = Vec::with_capacity(database_wrapper.encoded_len());
-    database_wrapper.encode(&mut raw_database_wrapper).unwrap(); // This is safe
 
     // Put to database
-    let addr_raw = addr.as_body().to_vec();
-    task::spawn_blocking(move || db_data.put_metadata(&addr_raw, &raw_database_wrapper))
-        .await
-        .unwrap()?;
+    let metadata = StoredMetadata {
+        raw_auth_wrapper: auth_wrapper_raw.to_vec(),
+        token: token_raw,
+        timestamp,
+        namespace: String::new(),
+    };
+    db_data.put(addr.as_body(), metadata).await?;
+    guard.complete();
+    publish_update(&events, &addr, "", timestamp, auth_wrapper_raw.to_vec());
+
+    // Put token to cache
+    token_cache.add_token(addr).await;
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+/// Handles metadata PATCH requests.
+///
+/// The patch is applied to the stored metadata, provided its digest matches the base digest of
+/// the patch, and the result is verified against the authorization wrapper. Concurrent writes to
+/// the address are serialized, so a patch never applies to a stale base.
+#[allow(clippy::too_many_arguments)]
+pub async fn patch_metadata(
+    addr: Address,
+    metadata_patch: MetadataPatch,
+    mut auth_wrapper: AuthWrapper,
+    token_raw: Vec<u8>,
+    idempotency_key: Option<String>,
+    db_data: Database,
+    token_cache: TokenCache,
+    idempotency: IdempotencyCache,
+    events: EventBus,
+) -> Result<Response<Body>, PutMetadataError> {
+    let mut raw_patch = Vec::with_capacity(metadata_patch.encoded_len());
+    metadata_patch.encode(&mut raw_patch).unwrap(); // This is safe
+    let guard = match admit(&idempotency, addr.as_body(), idempotency_key, &raw_patch)? {
+        Admission::Proceed(guard) => guard,
+        Admission::Replay => return Ok(replayed_response()),
+    };
+
+    let key = addr.as_body().to_vec();
+    let patched = db_data
+        .run_blocking(move |database| {
+            let mut patched = None;
+            database.update_metadata(&key, |base| {
+                // Decode the stored metadata
+                let base = base.ok_or(PutMetadataError::MissingBase)?;
+                let base_auth_wrapper = AuthWrapper::decode(base.raw_auth_wrapper.as_slice())
+                    .map_err(PutMetadataError::MetadataDecode)?;
+                if sha256(&base_auth_wrapper.payload)[..] != metadata_patch.base_digest[..] {
+                    return Err(PutMetadataError::Conflict);
+                }
+                let base_metadata = AddressMetadata::decode(base_auth_wrapper.payload.as_slice())
+                    .map_err(PutMetadataError::MetadataDecode)?;
+
+                // Apply the patch and check it against the signed digest
+                let metadata = patch::apply(&base_metadata, &metadata_patch);
+                check_timestamp(metadata.timestamp)?;
+                check_images(&metadata)?;
+                let mut payload = Vec::with_capacity(metadata.encoded_len());
+                metadata.encode(&mut payload).unwrap(); // This is safe
+                if sha256(&payload)[..] != auth_wrapper.payload_digest[..] {
+                    return Err(PutMetadataError::DigestMismatch);
+                }
+                auth_wrapper.payload = payload;
+
+                // Verify signatures
+                let parsed_auth_wrapper = auth_wrapper
+                    .clone()
+                    .parse()
+                    .map_err(PutMetadataError::InvalidAuthWrapper)?;
+                parsed_auth_wrapper
+                    .verify()
+                    .map_err(PutMetadataError::VerifyAuthWrapper)?;
+
+                let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+                auth_wrapper.encode(&mut raw_auth_wrapper).unwrap(); // This is safe
+                patched = Some((metadata.timestamp, raw_auth_wrapper.clone()));
+                Ok(StoredMetadata {
+                    raw_auth_wrapper,
+                    token: token_raw,
+                    timestamp: metadata.timestamp,
+                    namespace: String::new(),
+                })
+            })?;
+            Ok::<_, PutMetadataError>(patched)
+        })
+        .await?;
+    guard.complete();
+    if let Some((timestamp, raw_auth_wrapper)) = patched {
+        publish_update(&events, &addr, "", timestamp, raw_auth_wrapper);
+    }
 
     // Put token to cache
     token_cache.add_token(addr).await;
@@ -121,3 +433,63 @@ pub async fn put_metadata(
     // Respond
     Ok(Response::builder().body(Body::empty()).unwrap())
 }
+
+/// Handles namespaced metadata PUT requests.
+///
+/// Every entry of the signed [`AddressMetadata`] must belong to the namespace. The root document
+/// and other namespaces are left untouched.
+#[allow(clippy::too_many_arguments)]
+pub async fn put_namespace_metadata(
+    addr: Address,
+    namespace: Namespace,
+    auth_wrapper_raw: Bytes,
+    auth_wrapper: AuthWrapper,
+    token_raw: Vec<u8>,
+    idempotency_key: Option<String>,
+    db_data: Database,
+    idempotency: IdempotencyCache,
+    events: EventBus,
+) -> Result<Response<Body>, PutMetadataError> {
+    let key = metadata_key(addr.as_body(), namespace.as_str());
+    let guard = match admit(&idempotency, &key, idempotency_key, &auth_wrapper_raw)? {
+        Admission::Proceed(guard) => guard,
+        Admission::Replay => return Ok(replayed_response()),
+    };
+
+    // Verify signatures
+    let parsed_auth_wrapper = auth_wrapper
+        .parse()
+        .map_err(PutMetadataError::InvalidAuthWrapper)?;
+    parsed_auth_wrapper
+        .verify()
+        .map_err(PutMetadataError::VerifyAuthWrapper)?;
+
+    // Check the entries lie within the namespace
+    let address_metadata = AddressMetadata::decode(parsed_auth_wrapper.payload.as_slice())
+        .map_err(PutMetadataError::MetadataDecode)?;
+    namespace
+        .check(&address_metadata)
+        .map_err(PutMetadataError::OutsideNamespace)?;
+    check_timestamp(address_metadata.timestamp)?;
+    check_images(&address_metadata)?;
+
+    // Put to database
+    let metadata = StoredMetadata {
+        raw_auth_wrapper: auth_wrapper_raw.to_vec(),
+        token: token_raw,
+        timestamp: address_metadata.timestamp,
+        namespace: namespace.to_string(),
+    };
+    db_data.put(&key, metadata).await?;
+    guard.complete();
+    publish_update(
+        &events,
+        &addr,
+        namespace.as_str(),
+        address_metadata.timestamp,
+        auth_wrapper_raw.to_vec(),
+    );
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
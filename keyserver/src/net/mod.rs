@@ -1,26 +1,42 @@
+mod admin;
+mod idempotency;
 mod metadata;
 mod payments;
 mod peers;
 mod protection;
+mod signing;
+mod webhooks;
 
+pub use crate::net::admin::*;
+pub use crate::net::idempotency::*;
 pub use crate::net::metadata::*;
 pub use crate::net::payments::*;
 pub use crate::net::peers::*;
 pub use crate::net::protection::*;
+pub use crate::net::signing::*;
+pub use crate::net::webhooks::*;
 
-use std::{convert::Infallible, fmt};
+use std::{convert::Infallible, fmt, sync::Arc};
 
 use bitcoincash_addr::Address;
+use bytes::Bytes;
+use cashweb::{
+    health::Health,
+    keyserver::compression::{CompressionError, Encoding},
+};
+use http::header::{HeaderMap, ACCEPT_ENCODING};
 use thiserror::Error;
 use tracing::error;
 use warp::{
-    http::Response,
+    http::{Response, StatusCode},
     hyper::Body,
     reject::{PayloadTooLarge, Reject, Rejection},
+    Reply,
 };
 
 pub const SAMPLING: &str = "Sample-Peers";
 pub const HEADER_VALUE_FALSE: &str = "false";
+pub const HEADER_VALUE_TRUE: &str = "true";
 
 #[derive(Debug, Error)]
 pub struct AddressDecode(
@@ -48,6 +64,61 @@ impl ToResponse for AddressDecode {
     }
 }
 
+/// Bodies smaller than this are not worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Error)]
+#[error("failed to decompress body: {0}")]
+pub struct Decompression(CompressionError);
+
+impl Reject for Decompression {}
+
+impl ToResponse for Decompression {
+    fn to_status(&self) -> u16 {
+        match self.0 {
+            CompressionError::Unsupported(_) => 415,
+            CompressionError::TooLarge(_) => 413,
+            CompressionError::Io(_) => 400,
+        }
+    }
+}
+
+/// Helper method for decompressing a body given its `Content-Encoding`, failing if the result
+/// exceeds `limit` bytes.
+pub fn decompress_body(
+    body: Bytes,
+    content_encoding: Option<String>,
+    limit: u64,
+) -> Result<Bytes, Decompression> {
+    let encoding: Encoding = content_encoding
+        .as_deref()
+        .unwrap_or_default()
+        .parse()
+        .map_err(Decompression)?;
+    if encoding == Encoding::Identity {
+        return Ok(body);
+    }
+    encoding
+        .decompress(&body, limit as usize)
+        .map(Bytes::from)
+        .map_err(Decompression)
+}
+
+/// Helper method for compressing a body using the encoding preferred by the `Accept-Encoding` of
+/// the request.
+pub fn compress_body(body: &[u8], headers: &HeaderMap) -> (Encoding, Vec<u8>) {
+    let accept_encoding = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let encoding = if body.len() < COMPRESSION_THRESHOLD {
+        Encoding::Identity
+    } else {
+        Encoding::negotiate(accept_encoding)
+    };
+    (encoding, encoding.compress(body))
+}
+
 /// Helper trait for converting errors into a response.
 pub trait ToResponse: fmt::Display + Sized {
     /// Convert error into a status code.
@@ -78,6 +149,11 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<Decompression>() {
+        error!(message = "failed to decompress body", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<GetMetadataError>() {
         error!(message = "failed to get metadata", error = %err);
         return Ok(err.to_response());
@@ -88,11 +164,26 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<SearchError>() {
+        error!(message = "failed to search metadata", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PaymentError>() {
         error!(message = "payment failed", error = %err);
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<PeerSignatureError>() {
+        error!(message = "peer request rejected", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<AdminError>() {
+        error!(message = "admin request failed", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PeeringUnavailible>() {
         error!(message = "failed to get peers", error = %err);
         return Ok(err.to_response());
@@ -116,3 +207,18 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
     error!(message = "unexpected error", error = ?err);
     Ok(Response::builder().status(500).body(Body::empty()).unwrap())
 }
+
+/// Reply to a liveness or readiness probe.
+pub fn probe(healthy: bool) -> impl Reply {
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply(), status)
+}
+
+/// Reply with the JSON health report, for load balancers.
+pub async fn health_report(health: Arc<Health>) -> Result<Response<Body>, Infallible> {
+    Ok(health.report().await.into_response())
+}
@@ -1,4 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    convert::TryInto,
+    time::{Duration, SystemTime},
+};
 
 use bitcoincash_addr::{cashaddr, Address};
 use cashweb::{
@@ -6,16 +9,33 @@ use cashweb::{
         transaction::{self, Transaction},
         Decodable,
     },
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
-    payments::{bip70, PreprocessingError},
-    token::schemes::chain_commitment::{construct_commitment, construct_token},
+    bitcoin_client::{
+        metrics::{GlobalMetrics, InstrumentedClient},
+        BitcoinClient, BitcoinClientHTTP, NodeError,
+    },
+    lifecycle::bus::{EventBus, PaymentSeen},
+    payments::{
+        bip70,
+        builder::{encode_message, PaymentDetailsBuilder},
+        PreprocessingError, PAYMENT_ACK_MIME, PAYMENT_REQUEST_MIME,
+    },
+    token::{
+        keys::SecretKey,
+        schemes::{
+            chain_commitment::{construct_commitment, construct_token},
+            hmac_bearer::{HmacScheme, ValidationError as QuoteError},
+        },
+    },
+};
+use lazy_static::lazy_static;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
 };
-use prost::Message as _;
-use ring::digest::{digest, SHA256};
 use thiserror::Error;
 use warp::{
     http::{
-        header::{AUTHORIZATION, LOCATION},
+        header::{AUTHORIZATION, CONTENT_TYPE, LOCATION},
         Response,
     },
     hyper::Body,
@@ -27,6 +47,107 @@ use crate::{net::ToResponse, METADATA_PATH, PAYMENTS_PATH, SETTINGS};
 pub const COMMITMENT_PREIMAGE_SIZE: usize = 32 + 32;
 pub const COMMITMENT_SIZE: usize = 32;
 pub const OP_RETURN: u8 = 106;
+pub const QUOTE_SEPARATOR: char = '.';
+
+lazy_static! {
+    // Authenticates the prices pinned in invoices, keyed by `payments.quote_secret` or a random key
+    static ref QUOTE_SCHEME: HmacScheme = {
+        let key = match &SETTINGS.payments.quote_secret {
+            Some(secret) => SecretKey::load(secret.expose()).expect("unable to load quote secret"),
+            None => {
+                let mut key = vec![0; 32];
+                SystemRandom::new()
+                    .fill(&mut key)
+                    .expect("unable to generate quote secret");
+                SecretKey::from(key)
+            }
+        };
+        HmacScheme::from_secret(key)
+    };
+}
+
+/// A price, in satoshis, pinned when an invoice is issued.
+///
+/// The tag is an expiring HMAC token covering the commitment preimage and the price, so tokens
+/// paid at the quoted price remain valid when the fiat rate moves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedQuote {
+    pub price: u64,
+    pub tag: String,
+}
+
+impl PinnedQuote {
+    /// Pin the price quoted for the commitment preimage at `now`, expiring after `ttl`.
+    pub fn issue(
+        scheme: &HmacScheme,
+        commitment_preimage: &[u8],
+        price: u64,
+        now: SystemTime,
+        ttl: Duration,
+    ) -> Self {
+        let data = quote_data(commitment_preimage, price);
+        let tag = scheme.construct_token_expiring_at(&data, now, ttl);
+        Self { price, tag }
+    }
+
+    /// Validate the pinned quote against the commitment preimage at `now`.
+    pub fn validate(
+        &self,
+        scheme: &HmacScheme,
+        commitment_preimage: &[u8],
+        now: SystemTime,
+    ) -> Result<(), QuoteError> {
+        let data = quote_data(commitment_preimage, self.price);
+        scheme.validate_token_at(&data, &self.tag, now)
+    }
+
+    /// Serialize into invoice merchant data, following the commitment preimage.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.price.to_be_bytes()[..], self.tag.as_bytes()].concat()
+    }
+
+    /// Deserialize from invoice merchant data, following the commitment preimage.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() <= 8 {
+            return None;
+        }
+        let price = u64::from_be_bytes(raw[..8].try_into().unwrap()); // This is safe as the length is checked
+        let tag = String::from_utf8(raw[8..].to_vec()).ok()?;
+        Some(Self { price, tag })
+    }
+
+    /// Encode as the suffix of a POP token.
+    pub fn encode(&self) -> String {
+        format!("{}{}{}", self.price, QUOTE_SEPARATOR, self.tag)
+    }
+
+    /// Decode from the suffix of a POP token.
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let mut parts = encoded.splitn(2, QUOTE_SEPARATOR);
+        let price = parts.next()?.parse().ok()?;
+        let tag = parts.next().filter(|tag| !tag.is_empty())?.to_string();
+        Some(Self { price, tag })
+    }
+}
+
+fn quote_data(commitment_preimage: &[u8], price: u64) -> Vec<u8> {
+    [commitment_preimage, &price.to_be_bytes()[..]].concat()
+}
+
+/// Split a POP token into the outpoint token and the pinned quote, if any.
+pub fn split_pinned_token(token: &str) -> (&str, Option<&str>) {
+    let mut parts = token.splitn(2, QUOTE_SEPARATOR);
+    let outpoint_token = parts.next().unwrap_or_default(); // This is safe as splitn yields at least once
+    (outpoint_token, parts.next())
+}
+
+/// Validate a pinned quote at the present time, using the configured quote secret.
+pub fn validate_pinned_quote(
+    quote: &PinnedQuote,
+    commitment_preimage: &[u8],
+) -> Result<(), QuoteError> {
+    quote.validate(&QUOTE_SCHEME, commitment_preimage, SystemTime::now())
+}
 
 #[derive(Debug, Error)]
 pub enum PaymentError {
@@ -42,6 +163,8 @@ pub enum PaymentError {
     Node(NodeError),
     #[error("incorrect length preimage")]
     IncorrectLengthPreimage,
+    #[error("invalid pinned quote")]
+    InvalidQuote,
     #[error("address encoding failed: {0}")]
     Address(cashaddr::EncodingError),
 }
@@ -53,6 +176,7 @@ impl ToResponse for PaymentError {
         match self {
             Self::Address(_) => 400,
             Self::IncorrectLengthPreimage => 400,
+            Self::InvalidQuote => 400,
             Self::Preprocess(err) => match err {
                 PreprocessingError::MissingAcceptHeader => 406,
                 PreprocessingError::MissingContentTypeHeader => 415,
@@ -72,6 +196,7 @@ impl ToResponse for PaymentError {
 pub async fn process_payment(
     payment: bip70::Payment,
     bitcoin_client: BitcoinClientHTTP,
+    events: EventBus,
 ) -> Result<Response<Body>, PaymentError> {
     // Deserialize transactions
     let txs_res: Result<Vec<(Transaction, Vec<u8>)>, _> = payment
@@ -87,14 +212,27 @@ pub async fn process_payment(
     let txs = txs_res.map_err(PaymentError::MalformedTx)?;
 
     // Find commitment output
-    let commitment_preimage = payment
+    let merchant_data = payment
         .merchant_data
         .as_ref()
         .ok_or(PaymentError::MissingMerchantData)?;
 
-    if commitment_preimage.len() != COMMITMENT_PREIMAGE_SIZE {
+    if merchant_data.len() < COMMITMENT_PREIMAGE_SIZE {
         return Err(PaymentError::IncorrectLengthPreimage);
     }
+    let (commitment_preimage, quote_raw) = merchant_data.split_at(COMMITMENT_PREIMAGE_SIZE);
+
+    // Check the quote pinned by the invoice, an expired quote is re-quoted at validation
+    let quote = if quote_raw.is_empty() {
+        None
+    } else {
+        let quote = PinnedQuote::from_bytes(quote_raw).ok_or(PaymentError::InvalidQuote)?;
+        match validate_pinned_quote(&quote, commitment_preimage) {
+            Ok(()) => Some(quote),
+            Err(QuoteError::Expired) => None,
+            Err(_) => return Err(PaymentError::InvalidQuote),
+        }
+    };
 
     // Get address
     let pub_key_hash = &commitment_preimage[..32];
@@ -115,23 +253,18 @@ pub async fn process_payment(
             tx.outputs
                 .iter()
                 .enumerate()
-                .find_map(|(vout, output)| {
-                    let raw_script = output.script.as_bytes();
-                    if raw_script.len() == 2 + COMMITMENT_SIZE
-                        && raw_script[0] == OP_RETURN
-                        && raw_script[1] == COMMITMENT_SIZE as u8
-                        && raw_script[2..34] == expected_commitment[..]
-                    {
-                        Some(vout)
-                    } else {
-                        None
-                    }
-                })
+                .find_map(
+                    |(vout, output)| match output.script.op_return_data()?.as_slice() {
+                        [commitment] if commitment[..] == expected_commitment[..] => Some(vout),
+                        _ => None,
+                    },
+                )
                 .map(|vout| (tx_id, vout))
         })
         .ok_or(PaymentError::MissingCommitment)?;
 
     // Broadcast transactions
+    let bitcoin_client = InstrumentedClient::new(bitcoin_client, GlobalMetrics);
     for tx in &payment.transactions {
         bitcoin_client
             .send_tx(tx)
@@ -139,67 +272,116 @@ pub async fn process_payment(
             .map_err(PaymentError::Node)?;
     }
 
+    events.payment_seen().publish(PaymentSeen {
+        tx_id: tx_id.clone(),
+        vout: vout as u32,
+        address: pub_key_hash.to_vec(),
+    });
+
     // Construct token
-    let token = format!("POP {}", construct_token(tx_id, vout as u32));
+    let token = match quote {
+        Some(quote) => format!(
+            "POP {}{}{}",
+            construct_token(tx_id, vout as u32),
+            QUOTE_SEPARATOR,
+            quote.encode()
+        ),
+        None => format!("POP {}", construct_token(tx_id, vout as u32)),
+    };
 
     // Create PaymentAck
     let memo = Some(SETTINGS.payments.memo.clone());
-    let payment_ack = bip70::PaymentAck { payment, memo };
-
-    // Encode payment ack
-    let mut raw_ack = Vec::with_capacity(payment_ack.encoded_len());
-    payment_ack.encode(&mut raw_ack).unwrap();
+    let payment_ack = bip70::PaymentAck::new(payment, memo);
+    let raw_ack = encode_message(&payment_ack);
 
     Ok(Response::builder()
+        .header(CONTENT_TYPE, PAYMENT_ACK_MIME)
         .header(LOCATION, format!("/{}/{}", METADATA_PATH, addr_str))
         .header(AUTHORIZATION, token)
         .body(Body::from(raw_ack))
         .unwrap())
 }
 
-pub fn construct_payment_response(pub_key_hash: &[u8], metadata_digest: &[u8]) -> Response<Body> {
+pub fn construct_payment_response(
+    pub_key_hash: &[u8],
+    metadata_digest: &[u8],
+    price: u64,
+) -> Response<Body> {
     // Construct metadata commitment
     let commitment_preimage = [pub_key_hash, metadata_digest].concat();
     let commitment = digest(&SHA256, &commitment_preimage);
-    let op_return_pre: [u8; 2] = [106, COMMITMENT_SIZE as u8];
+    let op_return_pre: [u8; 2] = [OP_RETURN, COMMITMENT_SIZE as u8];
     let script = [&op_return_pre[..], commitment.as_ref()].concat();
-    let output = bip70::Output {
-        amount: None,
-        script,
-    };
+    let amount = Some(price).filter(|price| *price != 0);
 
-    // Valid interval
-    let current_time = SystemTime::now();
-
-    let payment_details = bip70::PaymentDetails {
-        network: Some(SETTINGS.network.to_string()),
-        time: current_time.duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        expires: None,
-        memo: None,
-        merchant_data: Some(commitment_preimage),
-        outputs: vec![output],
-        payment_url: Some(format!("/{}", PAYMENTS_PATH)),
-    };
-    let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
-    payment_details
-        .encode(&mut serialized_payment_details)
-        .unwrap();
+    // Pin the quoted price, so the token is validated against it rather than a later quote
+    let quote = PinnedQuote::issue(
+        &QUOTE_SCHEME,
+        &commitment_preimage,
+        price,
+        SystemTime::now(),
+        Duration::from_millis(SETTINGS.payments.quote_ttl),
+    );
+    let merchant_data = [&commitment_preimage[..], &quote.to_bytes()].concat();
 
     // Generate payment invoice
     // TODO: Signing
-    let pki_type = Some("none".to_string());
-    let payment_invoice = bip70::PaymentRequest {
-        pki_type,
-        pki_data: None,
-        payment_details_version: Some(1),
-        serialized_payment_details,
-        signature: None,
-    };
-    let mut payment_invoice_raw = Vec::with_capacity(payment_invoice.encoded_len());
-    payment_invoice.encode(&mut payment_invoice_raw).unwrap();
+    let payment_invoice = PaymentDetailsBuilder::new()
+        .network(SETTINGS.network.to_string())
+        .output(script, amount)
+        .merchant_data(merchant_data)
+        .payment_url(format!("/{}", PAYMENTS_PATH))
+        .build_request();
+    let payment_invoice_raw = encode_message(&payment_invoice);
 
     Response::builder()
         .status(402)
+        .header(CONTENT_TYPE, PAYMENT_REQUEST_MIME)
         .body(Body::from(payment_invoice_raw))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned_quote() {
+        let scheme = HmacScheme::new(b"quote secret");
+        let preimage = [1; COMMITMENT_PREIMAGE_SIZE];
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+        let quote = PinnedQuote::issue(&scheme, &preimage, 1_000, now, ttl);
+
+        // Round trips through merchant data and POP tokens
+        let parsed = PinnedQuote::from_bytes(&quote.to_bytes()).unwrap();
+        assert_eq!(parsed, quote);
+        let token = format!("outpoint{}{}", QUOTE_SEPARATOR, quote.encode());
+        let (outpoint_token, encoded) = split_pinned_token(&token);
+        assert_eq!(outpoint_token, "outpoint");
+        assert_eq!(PinnedQuote::decode(encoded.unwrap()).unwrap(), quote);
+        assert_eq!(split_pinned_token("outpoint"), ("outpoint", None));
+        assert_eq!(PinnedQuote::decode("1000"), None);
+
+        assert_eq!(quote.validate(&scheme, &preimage, now), Ok(()));
+
+        // A lowered price or other commitment is rejected
+        let lowered = PinnedQuote {
+            price: 1,
+            ..quote.clone()
+        };
+        assert_eq!(
+            lowered.validate(&scheme, &preimage, now),
+            Err(QuoteError::Invalid)
+        );
+        assert_eq!(
+            quote.validate(&scheme, &[2; COMMITMENT_PREIMAGE_SIZE], now),
+            Err(QuoteError::Invalid)
+        );
+
+        assert_eq!(
+            quote.validate(&scheme, &preimage, now + ttl),
+            Err(QuoteError::Expired)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use cashweb::keyserver::peers::VERSION_HEADER;
 use thiserror::Error;
 use warp::{http::Response, hyper::Body, reject::Reject};
 
@@ -23,5 +24,8 @@ pub async fn get_peers<S: Clone>(
     }
 
     let raw_peers = peer_handler.get_raw_peers().await;
-    Ok(Response::builder().body(Body::from(raw_peers)).unwrap()) // TODO: Headers
+    Ok(Response::builder()
+        .header(VERSION_HEADER, env!("CARGO_PKG_VERSION"))
+        .body(Body::from(raw_peers))
+        .unwrap()) // This is safe
 }
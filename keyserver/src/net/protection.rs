@@ -1,43 +1,107 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use bitcoincash_addr::Address;
 use bytes::Bytes;
 use cashweb::{
     auth_wrapper::AuthWrapper,
     bitcoin_client::BitcoinClientHTTP,
-    token::{extract_pop, schemes::chain_commitment::*},
+    keyserver::MetadataPatch,
+    token::{
+        extract_pop,
+        pricing::{PriceOracle, SizePrice},
+        rates::{CachedRates, FiatPrice, JsonRateProvider},
+        schemes::{chain_commitment::*, hmac_bearer::ValidationError as QuoteError, TokenError},
+    },
 };
 use http::header::HeaderMap;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use lazy_static::lazy_static;
 use prost::Message as _;
 use thiserror::Error;
-use tracing::info;
+use tracing::{error, info};
 use warp::{http::Response, hyper::Body, reject::Reject};
 
-use crate::{crypto::sha256, net::payments};
+use crate::{
+    crypto::sha256,
+    net::payments::{self, PinnedQuote},
+    SETTINGS,
+};
+
+type FiatOracle =
+    FiatPrice<CachedRates<JsonRateProvider<hyper::Client<HttpsConnector<HttpConnector>>>>>;
+
+lazy_static! {
+    // Fiat pricing, sharing cached rates between requests
+    static ref FIAT_ORACLE: Option<FiatOracle> = SETTINGS.payments.fiat.as_ref().map(|fiat| {
+        let provider = JsonRateProvider::new(fiat.rate_url.clone(), fiat.rate_pointer.clone());
+        let rates = CachedRates::new(provider, Duration::from_millis(fiat.rate_ttl));
+        FiatPrice::new(rates, fiat.currency.clone(), fiat.base_price)
+            .with_per_byte(fiat.price_per_byte)
+            .with_sats_per_coin(fiat.sats_per_coin)
+    });
+}
 
 #[derive(Debug, Error)]
 pub enum ProtectionError {
     #[error("missing token, pubkey: {}", hex::encode(.0))]
-    MissingToken(Vec<u8>, Vec<u8>),
+    MissingToken(Vec<u8>, Vec<u8>, usize),
     #[error("validation failed: {0}")]
     Validation(ValidationError),
     #[error("failed to decode authorization wrapper: {0}")]
     Decode(prost::DecodeError),
+    #[error("failed to quote price: {0}")]
+    Quote(String),
+}
+
+/// Quote the price, in satoshis, of a token for the address payload, covering `size` bytes.
+async fn quote(pub_key_hash: &[u8], size: usize) -> Result<u64, ProtectionError> {
+    match FIAT_ORACLE.as_ref() {
+        Some(oracle) => oracle
+            .quote(pub_key_hash, size)
+            .await
+            .map_err(|err| ProtectionError::Quote(err.to_string())),
+        None => {
+            let oracle = SizePrice {
+                base: SETTINGS.payments.base_price,
+                per_byte: SETTINGS.payments.price_per_byte,
+            };
+            match oracle.quote(pub_key_hash, size).await {
+                Ok(ok) => Ok(ok),
+                Err(err) => match err {},
+            }
+        }
+    }
 }
 
 pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body> {
     match err {
-        ProtectionError::Validation(_) => Response::builder()
-            .status(400)
+        ProtectionError::Validation(validation_err) => Response::builder()
+            .status(validation_err.kind().status())
             .body(Body::from(err.to_string()))
             .unwrap(),
-        ProtectionError::MissingToken(pubkey_digest, metadata_digest) => {
-            payments::construct_payment_response(pubkey_digest, metadata_digest)
+        ProtectionError::MissingToken(pubkey_digest, metadata_digest, size) => {
+            let price = match quote(pubkey_digest, *size).await {
+                Ok(ok) => ok,
+                Err(err) => {
+                    // Refuse to quote rather than undercharge
+                    error!(message = "failed to quote price", error = %err);
+                    return Response::builder()
+                        .status(503)
+                        .body(Body::from(err.to_string()))
+                        .unwrap();
+                }
+            };
+            payments::construct_payment_response(pubkey_digest, metadata_digest, price)
         }
         ProtectionError::Decode(err) => Response::builder()
             .status(400)
             .body(Body::from(err.to_string()))
             .unwrap(),
+        ProtectionError::Quote(_) => Response::builder()
+            .status(503)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
     }
 }
 
@@ -48,6 +112,18 @@ pub async fn pop_protection(
     auth_wrapper_raw: Bytes,
     header_map: HeaderMap,
     token_scheme: Arc<ChainCommitmentScheme<BitcoinClientHTTP>>,
+) -> Result<(Address, Bytes, AuthWrapper, Vec<u8>), ProtectionError> {
+    let size = auth_wrapper_raw.len();
+    protect(addr, auth_wrapper_raw, header_map, token_scheme, size).await
+}
+
+/// Protect an authorization wrapper, requiring the POP token to burn the price of `size` bytes.
+async fn protect(
+    addr: Address,
+    auth_wrapper_raw: Bytes,
+    header_map: HeaderMap,
+    token_scheme: Arc<ChainCommitmentScheme<BitcoinClientHTTP>>,
+    size: usize,
 ) -> Result<(Address, Bytes, AuthWrapper, Vec<u8>), ProtectionError> {
     let auth_wrapper =
         AuthWrapper::decode(auth_wrapper_raw.clone()).map_err(ProtectionError::Decode)?;
@@ -64,8 +140,36 @@ pub async fn pop_protection(
     match extract_pop(&header_map) {
         Some(pop_token) => {
             info!(message = "found token", token = %pop_token);
+            let (outpoint_token, quote_encoded) = payments::split_pinned_token(pop_token);
+
+            // Burn the price pinned by the invoice, re-quoting if absent or expired
+            let pinned_price = match quote_encoded {
+                Some(encoded) => {
+                    let quote = PinnedQuote::decode(encoded)
+                        .ok_or(ProtectionError::Validation(ValidationError::Invalid))?;
+                    let commitment_preimage = [pub_key_hash.as_ref(), &metadata_hash].concat();
+                    match payments::validate_pinned_quote(&quote, &commitment_preimage) {
+                        Ok(()) => Some(quote.price),
+                        Err(QuoteError::Expired) => None,
+                        Err(_) => {
+                            return Err(ProtectionError::Validation(ValidationError::Invalid))
+                        }
+                    }
+                }
+                None => None,
+            };
+            let price = match pinned_price {
+                Some(some) => some,
+                None => quote(pub_key_hash.as_ref(), size).await?,
+            };
+            let mandate = Mandate::Burn(price);
             let raw_token = token_scheme
-                .validate_token(pub_key_hash.as_ref(), &metadata_hash, pop_token)
+                .validate_token_mandated(
+                    pub_key_hash.as_ref(),
+                    &metadata_hash,
+                    outpoint_token,
+                    &mandate,
+                )
                 .await
                 .map_err(ProtectionError::Validation)?;
             Ok((addr, auth_wrapper_raw, auth_wrapper, raw_token))
@@ -73,6 +177,28 @@ pub async fn pop_protection(
         None => Err(ProtectionError::MissingToken(
             pub_key_hash.to_vec(),
             metadata_hash,
+            size,
         )),
     }
 }
+
+/// Protect a metadata patch, whose authorization wrapper covers the patched metadata.
+pub async fn patch_protection(
+    addr: Address,
+    patch_raw: Bytes,
+    header_map: HeaderMap,
+    token_scheme: Arc<ChainCommitmentScheme<BitcoinClientHTTP>>,
+) -> Result<(Address, MetadataPatch, AuthWrapper, Vec<u8>), ProtectionError> {
+    let patch = MetadataPatch::decode(patch_raw.clone()).map_err(ProtectionError::Decode)?;
+    let auth_wrapper_raw = Bytes::from(patch.raw_auth_wrapper.clone());
+    // Price by the size of the patch
+    let (addr, _, auth_wrapper, raw_token) = protect(
+        addr,
+        auth_wrapper_raw,
+        header_map,
+        token_scheme,
+        patch_raw.len(),
+    )
+    .await?;
+    Ok((addr, patch, auth_wrapper, raw_token))
+}
@@ -0,0 +1,117 @@
+use std::time::SystemTime;
+
+use cashweb::{
+    keyserver_client::credentials::HostCredentials,
+    token::signing::{request_target, RequestSigner, RequestVerifier, SignatureError},
+};
+use http::header::HeaderMap;
+use hyper::Uri;
+use lazy_static::lazy_static;
+use thiserror::Error;
+use warp::{
+    http::{Method, Response},
+    hyper::Body,
+    path::FullPath,
+    reject::Reject,
+};
+
+use crate::{
+    net::ToResponse,
+    settings::{load_signing_key, parse_public_keys, peer_credentials},
+    SETTINGS,
+};
+
+lazy_static! {
+    // Signs requests to peers, if a signing key is configured
+    pub static ref PEER_SIGNER: Option<RequestSigner> =
+        SETTINGS.peering.signing_secret.as_ref().map(|secret| {
+            RequestSigner::new(load_signing_key(secret.expose()).expect("invalid signing key"))
+        });
+
+    // Presented to peers fronted by authenticating gateways
+    pub static ref PEER_CREDENTIALS: HostCredentials =
+        peer_credentials(&SETTINGS.peering.credentials).expect("invalid peer credentials");
+
+    // Verifies requests from, and responses of, peers, if trusted keys are configured
+    pub static ref PEER_VERIFIER: Option<RequestVerifier> = verifier(&SETTINGS.peering.trusted_keys);
+
+    // Verifies signed admin requests, if admin keys are configured
+    pub static ref ADMIN_VERIFIER: Option<RequestVerifier> = verifier(&SETTINGS.admin.public_keys);
+}
+
+fn verifier(keys: &[String]) -> Option<RequestVerifier> {
+    if keys.is_empty() {
+        return None;
+    }
+    let keys = parse_public_keys(keys).expect("invalid public keys");
+    Some(RequestVerifier::new(keys))
+}
+
+#[derive(Debug, Error)]
+#[error("peer signature rejected: {0}")]
+pub struct PeerSignatureError(SignatureError);
+
+impl Reject for PeerSignatureError {}
+
+impl ToResponse for PeerSignatureError {
+    fn to_status(&self) -> u16 {
+        401
+    }
+}
+
+/// The URI of a received request, being its path and query.
+pub fn request_uri(path: FullPath, query: Option<String>) -> Uri {
+    match query {
+        Some(query) => format!("{}?{}", path.as_str(), query).parse(),
+        None => path.as_str().parse(),
+    }
+    .unwrap() // This is safe
+}
+
+/// A request received from a peer.
+#[derive(Debug)]
+pub struct PeerRequest {
+    /// Whether the request was signed by a trusted peer.
+    pub authenticated: bool,
+    method: Method,
+    target: String,
+}
+
+impl PeerRequest {
+    /// Sign the response to the request, with the given body, if a signing key is configured.
+    pub fn sign_response(&self, response: &mut Response<Body>, body: &[u8]) {
+        if let Some(signer) = PEER_SIGNER.as_ref() {
+            signer.sign_response_at(
+                &self.method,
+                &self.target,
+                response,
+                body,
+                SystemTime::now(),
+            );
+        }
+    }
+}
+
+/// Check a bodyless request is signed by a trusted peer, if trusted keys are configured.
+///
+/// The request is never authenticated if no trusted keys are configured.
+pub fn verify_peer(
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<PeerRequest, PeerSignatureError> {
+    let authenticated = match PEER_VERIFIER.as_ref() {
+        Some(verifier) => {
+            verifier
+                .verify(&method, &uri, &headers, &[])
+                .map_err(PeerSignatureError)?;
+            true
+        }
+        None => false,
+    };
+    Ok(PeerRequest {
+        authenticated,
+        target: request_target(&uri, &headers),
+        method,
+    })
+}
@@ -0,0 +1,81 @@
+use bitcoincash_addr::Address;
+use cashweb::{
+    keyserver::MetadataNotification,
+    keyserver_client::webhook::WebhookSender,
+    lifecycle::{
+        bus::{MetadataUpdated, Origin, Subscriber},
+        ShutdownToken,
+    },
+    token::{
+        keys::{SecretKey, MIN_ENTROPY_BITS},
+        schemes::hmac_bearer::HmacScheme,
+    },
+};
+use hyper::{client::HttpConnector, Uri};
+use hyper_tls::HttpsConnector;
+use lazy_static::lazy_static;
+use tracing::warn;
+
+use crate::SETTINGS;
+
+type HttpsClient = hyper::Client<HttpsConnector<HttpConnector>>;
+
+lazy_static! {
+    // Webhook URLs, notified of metadata updates
+    static ref WEBHOOK_URLS: Vec<Uri> = SETTINGS
+        .webhooks
+        .urls
+        .iter()
+        .map(|url| url.parse().unwrap()) // This is safe as the URLs are validated
+        .collect();
+
+    // Signs and delivers webhooks, if any are configured
+    static ref WEBHOOK_SENDER: Option<WebhookSender<HttpsClient>> =
+        SETTINGS.webhooks.secret.as_ref().map(|secret| {
+            let key = SecretKey::load(secret.expose()).expect("unable to load webhook secret");
+            if let Err(err) = key.check_entropy(MIN_ENTROPY_BITS) {
+                warn!(message = "weak webhook secret", error = %err);
+            }
+            WebhookSender::new_tls(HmacScheme::from_secret(key))
+        });
+}
+
+/// Notify the configured webhooks of metadata written by clients, until shutdown.
+pub async fn deliver_webhooks(
+    mut subscriber: Subscriber<MetadataUpdated>,
+    shutdown: ShutdownToken,
+) {
+    let sender = match WEBHOOK_SENDER.as_ref() {
+        Some(some) if !WEBHOOK_URLS.is_empty() => some,
+        _ => return,
+    };
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.clone().cancelled() => break,
+            event = subscriber.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+        };
+        if event.origin != Origin::Local {
+            continue;
+        }
+        let address = Address {
+            body: event.address,
+            ..Default::default()
+        };
+        let notification = MetadataNotification {
+            address: address.encode().unwrap(), // This is safe
+            namespace: event.namespace,
+            timestamp: event.timestamp,
+            raw_auth_wrapper: event.raw_auth_wrapper,
+        };
+        tokio::spawn(async move {
+            for (uri, result) in sender.deliver_all(&WEBHOOK_URLS, &notification).await {
+                if let Err(err) = result {
+                    warn!(message = "failed to deliver webhook", %uri, error = %err);
+                }
+            }
+        });
+    }
+}
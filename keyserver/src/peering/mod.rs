@@ -2,11 +2,13 @@ mod token_cache;
 
 pub use token_cache::*;
 
-use std::{fmt, sync::Arc};
+use std::{collections::HashSet, fmt, sync::Arc};
 
 use cashweb::{
-    keyserver::{Peer, Peers},
+    keyserver::{peers::rank, Peer, Peers},
     keyserver_client::{
+        credentials::{CredentialsLayer, CredentialsService, HostCredentials},
+        policy::PeerPolicy,
         services::{GetPeersError, SampleError},
         KeyserverManager,
     },
@@ -15,6 +17,7 @@ use hyper::{client::HttpConnector, Body, Request, Response, Uri};
 use hyper_tls::HttpsConnector;
 use prost::Message as _;
 use tokio::sync::RwLock;
+use tower_layer::Layer;
 use tower_service::Service;
 use tracing::warn;
 
@@ -35,35 +38,33 @@ pub fn parse_uri_warn(uri_str: &str) -> Option<Uri> {
 pub struct PeerHandler<S> {
     keyserver_manager: KeyserverManager<S>,
     peers_cache: Arc<RwLock<Vec<u8>>>,
+    banned: Arc<RwLock<HashSet<Uri>>>,
 }
 
-fn uris_to_peers(uris: &[Uri]) -> Peers {
-    let peers = uris
-        .iter()
-        .map(|uri| Peer {
-            url: uri.to_string(),
-        })
-        .collect();
-    Peers { peers }
-}
-
-fn uris_to_raw_peers(uris: &[Uri]) -> Vec<u8> {
-    let mut buffer = Vec::with_capacity(uris.len());
-    let peers = uris_to_peers(uris);
+fn peers_to_raw_peers(peers: Vec<Peer>) -> Vec<u8> {
+    let peers = Peers { peers };
+    let mut buffer = Vec::with_capacity(peers.encoded_len());
     peers.encode(&mut buffer).unwrap(); // Never fails
     buffer
 }
 
-impl PeerHandler<hyper::Client<HttpsConnector<HttpConnector>>> {
-    /// Construct new [`PeerHandler`].
-    pub fn new(uris: Vec<Uri>) -> Self {
+fn uris_to_peers(uris: &[Uri]) -> Vec<Peer> {
+    uris.iter().map(|uri| Peer::new(uri.to_string())).collect()
+}
+
+impl PeerHandler<CredentialsService<hyper::Client<HttpsConnector<HttpConnector>>>> {
+    /// Construct new [`PeerHandler`], presenting the credentials to peers.
+    pub fn new(uris: Vec<Uri>, policy: PeerPolicy, credentials: HostCredentials) -> Self {
         let https = HttpsConnector::new();
-        let http_client = hyper::Client::builder().build(https);
-        let peers_cache = Arc::new(RwLock::new(uris_to_raw_peers(&uris)));
-        let keyserver_manager = KeyserverManager::from_service(http_client, uris);
+        let http_client =
+            CredentialsLayer::new(credentials).layer(hyper::Client::builder().build(https));
+        let peers_cache = Arc::new(RwLock::new(peers_to_raw_peers(uris_to_peers(&uris))));
+        let keyserver_manager =
+            KeyserverManager::from_service(http_client, uris).with_policy(policy);
         Self {
             keyserver_manager,
             peers_cache,
+            banned: Default::default(),
         }
     }
 }
@@ -76,27 +77,43 @@ where
         &self.keyserver_manager
     }
 
-    // TODO: actually use this
-    #[allow(dead_code)]
     pub async fn get_urls(&self) -> Vec<Uri> {
         self.keyserver_manager.get_uris().read().await.clone()
     }
 
-    pub async fn set_peers(&self, uris: Vec<Uri>) {
+    /// Set the peers, keeping their quality data, with the URIs ordered best first.
+    pub async fn set_peers(&self, mut peers: Vec<Peer>) {
+        rank(&mut peers);
+        let banned = self.banned.read().await;
+        let (peers, uris): (Vec<_>, Vec<_>) = peers
+            .into_iter()
+            .filter_map(|peer| parse_uri_warn(&peer.url).map(|uri| (peer, uri)))
+            .filter(|(_, uri)| !banned.contains(uri))
+            .unzip();
         let mut peer_cache_write = self.peers_cache.write().await;
         let uris_shared = self.keyserver_manager.get_uris();
         let mut uris_write = uris_shared.write().await;
-        *peer_cache_write = uris_to_raw_peers(&uris);
+        *peer_cache_write = peers_to_raw_peers(peers);
         *uris_write = uris;
     }
 
+    /// Remove the peer and exclude it from future peer lists, until restart.
+    pub async fn ban(&self, uri: Uri) {
+        self.banned.write().await.insert(uri);
+        let raw_peers = self.get_raw_peers().await;
+        let peers = Peers::decode(raw_peers.as_slice()).unwrap(); // This is safe
+        self.set_peers(peers.peers).await;
+    }
+
     pub async fn get_raw_peers(&self) -> Vec<u8> {
         self.peers_cache.read().await.clone()
     }
 
     pub async fn persist(&self, database: &Database) -> Result<(), rocksdb::Error> {
         let raw_peers = self.get_raw_peers().await;
-        database.put_peers(&raw_peers)
+        database
+            .run_blocking(move |database| database.put_peers(&raw_peers))
+            .await
     }
 }
 
@@ -108,18 +125,10 @@ where
     S::Error: fmt::Debug + Send + fmt::Display,
 {
     pub async fn inflate(&self) -> Result<(), SampleError<GetPeersError<S::Error>>> {
-        // Crawl peers, collecting Peers
+        // Crawl peers, collecting Peers. Misbehaving peers are reported to the policy
         let aggregate_response = self.get_keyserver_manager().crawl_peers().await?;
-        // TODO: Ban misbehaviour
 
-        // Collect URIs
-        let uris = aggregate_response
-            .response
-            .peers
-            .into_iter()
-            .filter_map(|peer| parse_uri_warn(&peer.url))
-            .collect();
-        self.set_peers(uris).await;
+        self.set_peers(aggregate_response.response.peers).await;
         Ok(())
     }
 }
@@ -48,7 +48,8 @@ impl TokenCache {
 
         // Broadcast each metadata
         for addr in token_block.into_iter() {
-            let db_wrapper = match db.get_metadata(addr.as_body()) {
+            let key = addr.as_body().to_vec();
+            let db_wrapper = match db.run_blocking(move |db| db.get_metadata(&key)).await {
                 Ok(Some(some)) => some,
                 _ => continue,
             };
@@ -87,7 +87,7 @@ struct BurnOutputsWithAmounts(BurnOutputs, i64);
 
 pub async fn put_message(
     db: PubSubDatabase,
-    client: impl BitcoinClient,
+    client: impl BitcoinClient + Send + Sync,
     mut message: AuthWrapper,
 ) -> Result<impl Reply, Rejection> {
     if message.transactions.is_empty() {
@@ -254,7 +254,7 @@ pub mod tests {
             transaction::{output::Output, script::Script},
             Encodable,
         },
-        bitcoin_client::NodeError,
+        bitcoin_client::{FeePolicy, NodeError},
     };
     use rocksdb::{Options, DB};
 
@@ -264,7 +264,11 @@ pub mod tests {
 
     #[async_trait]
     impl BitcoinClient for MockTransactionSender {
-        async fn send_tx(&self, _raw_tx: &[u8]) -> Result<String, NodeError> {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
             return Ok("".to_string());
         }
         /// Get a new receiving address from the bitcoin daemon
@@ -1,20 +1,36 @@
 use std::net::SocketAddr;
 
+use cashweb::{
+    config::{ConfigError, Loader, NodeConfig, Secret, Validate, ValidationError},
+    keyserver_client::credentials::{Credential, HostCredentials},
+    secp256k1::{self, key::PublicKey},
+    token::keys::SecretKey,
+};
 use clap::App;
-use config::{Config, ConfigError, File};
+use hyper::{http::header::HeaderName, Uri};
 use serde::Deserialize;
 
 const FOLDER_DIR: &str = ".keyserver";
+const ENV_PREFIX: &str = "KEYSERVER";
 const DEFAULT_BIND: &str = "127.0.0.1:8080";
 const DEFAULT_RPC_ADDR: &str = "http://127.0.0.1:18443";
 const DEFAULT_RPC_USER: &str = "user";
 const DEFAULT_RPC_PASSWORD: &str = "password";
+const DEFAULT_RPC_CONNECT_TIMEOUT: u64 = 5_000;
+const DEFAULT_RPC_REQUEST_TIMEOUT: u64 = 30_000;
 const DEFAULT_NETWORK: &str = "regtest";
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30_000;
 const DEFAULT_PING_INTERVAL: u64 = 10_000;
 const DEFAULT_METADATA_LIMIT: usize = 1_000 * 5; // 5KB
 const DEFAULT_PAYMENT_LIMIT: usize = 1_000 * 3; // 3KB
+const DEFAULT_IMAGE_LIMIT: usize = 1_000 * 4; // 4KB
+const DEFAULT_IMAGE_DIMENSION: u32 = 256;
+const DEFAULT_REPLICATION_PAGE_SIZE: usize = 256;
 const DEFAULT_TRUNCATION_LENGTH: usize = 500;
 const DEFAULT_MEMO: &str = "Thanks for your custom!";
+const DEFAULT_BASE_PRICE: u64 = 0;
+const DEFAULT_PRICE_PER_BYTE: u64 = 0;
+const DEFAULT_QUOTE_TTL: u64 = 3_600_000;
 const DEFAULT_MAX_PEERS: u32 = 128;
 const DEFAULT_PEERING: bool = true;
 const DEFAULT_ZMQ_ADDRESS: &str = "tcp://127.0.0.1:28332";
@@ -23,27 +39,57 @@ const DEFAULT_PEER_TIMEOUT: u64 = 60_000;
 const DEFAULT_PEER_KEEP_ALIVE: u64 = 30_000;
 const DEFAULT_PEER_BROADCAST_DELAY: usize = 2;
 const DEFAULT_PEER_FAN_SIZE: usize = 4;
+const DEFAULT_REPLICATION_INTERVAL: u64 = 60_000;
+const DEFAULT_MAX_CLOCK_SKEW: u64 = 300_000;
+const DEFAULT_BAN_THRESHOLD: u32 = 100;
+const DEFAULT_BAN_DURATION: u64 = 3_600_000;
+const DEFAULT_RATE_TTL: u64 = 300_000;
+const DEFAULT_SATS_PER_COIN: u64 = 100_000_000;
+const DEFAULT_WEBHOOK_URLS: &[String] = &[];
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
 
 #[cfg(feature = "monitoring")]
 const DEFAULT_BIND_PROM: &str = "127.0.0.1:9095";
 
-#[derive(Debug, Deserialize)]
-pub struct BitcoinRpc {
-    pub address: String,
-    pub username: String,
-    pub password: String,
-    pub zmq_address: String,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct Limits {
     pub metadata_size: u64,
     pub payment_size: u64,
+    pub replication_page_size: usize,
+    pub image_size: usize,
+    pub image_dimension: u32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Payment {
     pub memo: String,
+    pub base_price: u64,
+    pub price_per_byte: u64,
+    pub quote_ttl: u64,
+    pub quote_secret: Option<Secret<String>>,
+    pub fiat: Option<FiatPricing>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FiatPricing {
+    pub currency: String,
+    pub base_price: f64,
+    #[serde(default)]
+    pub price_per_byte: f64,
+    pub rate_url: String,
+    pub rate_pointer: String,
+    #[serde(default = "default_rate_ttl")]
+    pub rate_ttl: u64,
+    #[serde(default = "default_sats_per_coin")]
+    pub sats_per_coin: u64,
+}
+
+fn default_rate_ttl() -> u64 {
+    DEFAULT_RATE_TTL
+}
+
+fn default_sats_per_coin() -> u64 {
+    DEFAULT_SATS_PER_COIN
 }
 
 #[derive(Debug, Deserialize)]
@@ -55,7 +101,47 @@ pub struct Peering {
     pub pull_fan_size: usize,
     pub push_fan_size: usize,
     pub broadcast_delay: usize,
+    pub replication_interval: u64,
+    pub max_clock_skew: u64,
+    pub ban_threshold: u32,
+    pub ban_duration: u64,
     pub peers: Vec<String>,
+    pub signing_secret: Option<Secret<String>>,
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    #[serde(default)]
+    pub credentials: Vec<PeerCredential>,
+}
+
+/// Credential presented to a peer fronted by an authenticating gateway.
+#[derive(Debug, Deserialize)]
+pub struct PeerCredential {
+    pub host: String,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub api_key_header: Option<String>,
+    pub api_key: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    pub secret: Option<Secret<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Caching {
+    #[serde(default)]
+    pub max_age: u64,
+    #[serde(default)]
+    pub stale_while_revalidate: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Admin {
+    pub secret: Option<Secret<String>>,
+    #[serde(default)]
+    pub public_keys: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -64,18 +150,22 @@ pub struct Settings {
     #[cfg(feature = "monitoring")]
     pub bind_prom: SocketAddr,
     pub db_path: String,
+    pub drain_timeout: u64,
     pub pubsub_db_path: String,
     pub network: String,
-    pub bitcoin_rpc: BitcoinRpc,
+    pub bitcoin_rpc: NodeConfig,
     pub limits: Limits,
     pub payments: Payment,
     pub peering: Peering,
+    pub webhooks: Webhooks,
+    #[serde(default)]
+    pub admin: Admin,
+    #[serde(default)]
+    pub caching: Caching,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
-        let mut s = Config::new();
-
         // Set defaults
         let yaml = load_yaml!("cli.yml");
         #[allow(deprecated)]
@@ -88,97 +178,284 @@ impl Settings {
             Some(some) => some,
             None => return Err(ConfigError::Message("no home directory".to_string())),
         };
-        s.set_default("bind", DEFAULT_BIND)?;
+        let mut s = Loader::new(ENV_PREFIX)
+            .with_default("bind", DEFAULT_BIND)
+            .with_default("network", DEFAULT_NETWORK)
+            .with_default("drain_timeout", DEFAULT_DRAIN_TIMEOUT as i64);
         #[cfg(feature = "monitoring")]
-        s.set_default("bind_prom", DEFAULT_BIND_PROM)?;
-        s.set_default("network", DEFAULT_NETWORK)?;
+        {
+            s = s.with_default("bind_prom", DEFAULT_BIND_PROM);
+        }
         let mut default_db = home_dir.clone();
         default_db.push(format!("{}/db", FOLDER_DIR));
-        s.set_default("db_path", default_db.to_str())?;
         let mut default_pubsub_db = home_dir.clone();
         default_pubsub_db.push(format!("{}/pubsub_db", FOLDER_DIR));
-        s.set_default("pubsub_db_path", default_pubsub_db.to_str())?;
-
-        s.set_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)?;
-        s.set_default("bitcoin_rpc.username", DEFAULT_RPC_USER)?;
-        s.set_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)?;
-        s.set_default("bitcoin_rpc.zmq_address", DEFAULT_ZMQ_ADDRESS)?;
-
-        s.set_default("limits.metadata_size", DEFAULT_METADATA_LIMIT as i64)?;
-        s.set_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)?;
-
-        s.set_default("payments.memo", DEFAULT_MEMO)?;
-
-        s.set_default("peering.enabled", DEFAULT_PEERING)?;
-        s.set_default("peering.max_peers", DEFAULT_MAX_PEERS as i64)?;
-        s.set_default("peering.timeout", DEFAULT_PEER_TIMEOUT as i64)?;
-        s.set_default("peering.keep_alive", DEFAULT_PEER_KEEP_ALIVE as i64)?;
-        s.set_default("peering.peers", DEFAULT_PEERS.to_vec())?;
-        s.set_default("peering.push_fan_size", DEFAULT_PEER_FAN_SIZE as i64)?;
-        s.set_default("peering.pull_fan_size", DEFAULT_PEER_FAN_SIZE as i64)?;
-        s.set_default(
-            "peering.broadcast_delay",
-            DEFAULT_PEER_BROADCAST_DELAY as i64,
-        )?;
-
-        s.set_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64)?;
-        s.set_default(
-            "websocket.truncation_length",
-            DEFAULT_TRUNCATION_LENGTH as i64,
-        )?;
-
-        // Load config from file
+        s = s
+            .with_default("db_path", default_db.to_string_lossy().as_ref())
+            .with_default(
+                "pubsub_db_path",
+                default_pubsub_db.to_string_lossy().as_ref(),
+            );
+
+        s = s
+            .with_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)
+            .with_default("bitcoin_rpc.username", DEFAULT_RPC_USER)
+            .with_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)
+            .with_default(
+                "bitcoin_rpc.connect_timeout",
+                DEFAULT_RPC_CONNECT_TIMEOUT as i64,
+            )
+            .with_default(
+                "bitcoin_rpc.request_timeout",
+                DEFAULT_RPC_REQUEST_TIMEOUT as i64,
+            )
+            .with_default("bitcoin_rpc.zmq_address", DEFAULT_ZMQ_ADDRESS);
+
+        s = s
+            .with_default("limits.metadata_size", DEFAULT_METADATA_LIMIT as i64)
+            .with_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)
+            .with_default(
+                "limits.replication_page_size",
+                DEFAULT_REPLICATION_PAGE_SIZE as i64,
+            )
+            .with_default("limits.image_size", DEFAULT_IMAGE_LIMIT as i64)
+            .with_default("limits.image_dimension", DEFAULT_IMAGE_DIMENSION as i64);
+
+        s = s
+            .with_default("payments.memo", DEFAULT_MEMO)
+            .with_default("payments.base_price", DEFAULT_BASE_PRICE as i64)
+            .with_default("payments.price_per_byte", DEFAULT_PRICE_PER_BYTE as i64)
+            .with_default("payments.quote_ttl", DEFAULT_QUOTE_TTL as i64);
+
+        s = s
+            .with_default("peering.enabled", DEFAULT_PEERING)
+            .with_default("peering.max_peers", DEFAULT_MAX_PEERS as i64)
+            .with_default("peering.timeout", DEFAULT_PEER_TIMEOUT as i64)
+            .with_default("peering.keep_alive", DEFAULT_PEER_KEEP_ALIVE as i64)
+            .with_default("peering.peers", DEFAULT_PEERS.to_vec())
+            .with_default("peering.push_fan_size", DEFAULT_PEER_FAN_SIZE as i64)
+            .with_default("peering.pull_fan_size", DEFAULT_PEER_FAN_SIZE as i64)
+            .with_default(
+                "peering.broadcast_delay",
+                DEFAULT_PEER_BROADCAST_DELAY as i64,
+            )
+            .with_default(
+                "peering.replication_interval",
+                DEFAULT_REPLICATION_INTERVAL as i64,
+            )
+            .with_default("peering.max_clock_skew", DEFAULT_MAX_CLOCK_SKEW as i64)
+            .with_default("peering.ban_threshold", DEFAULT_BAN_THRESHOLD as i64)
+            .with_default("peering.ban_duration", DEFAULT_BAN_DURATION as i64);
+
+        s = s.with_default("webhooks.urls", DEFAULT_WEBHOOK_URLS.to_vec());
+
+        s = s
+            .with_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64)
+            .with_default(
+                "websocket.truncation_length",
+                DEFAULT_TRUNCATION_LENGTH as i64,
+            );
+
+        // Load config from file, then the environment
         let mut default_config = home_dir;
         default_config.push(format!("{}/config", FOLDER_DIR));
-        let default_config_str = default_config.to_str().unwrap();
-        let config_path = matches.value_of("config").unwrap_or(default_config_str);
-        s.merge(File::with_name(config_path).required(false))?;
+        s = match matches.value_of("config") {
+            Some(config_path) => s.with_file(config_path),
+            None => s.with_optional_file(default_config),
+        };
 
         // Set bind address from cmd line
         if let Some(bind) = matches.value_of("bind") {
-            s.set("bind", bind)?;
+            s = s.with_override("bind", bind);
         }
 
         // Set bind address from cmd line
         if let Some(bind_prom) = matches.value_of("bind-prom") {
-            s.set("bind_prom", bind_prom)?;
+            s = s.with_override("bind_prom", bind_prom);
         }
 
         // Set the bitcoin network
         if let Some(network) = matches.value_of("network") {
-            s.set("network", network)?;
+            s = s.with_override("network", network);
         }
 
         // Set db from cmd line
         if let Some(db_path) = matches.value_of("db-path") {
-            s.set("db_path", db_path)?;
+            s = s.with_override("db_path", db_path);
         }
 
         // Set db from cmd line
         if let Some(pubsub_db_path) = matches.value_of("pubsub-db-path") {
-            s.set("pubsub_db_path", pubsub_db_path)?;
+            s = s.with_override("pubsub_db_path", pubsub_db_path);
         }
 
         // Set node IP from cmd line
         if let Some(node_ip) = matches.value_of("rpc-addr") {
-            s.set("bitcoin_rpc.address", node_ip)?;
+            s = s.with_override("bitcoin_rpc.address", node_ip);
         }
 
         // Set rpc username from cmd line
         if let Some(rpc_username) = matches.value_of("rpc-username") {
-            s.set("bitcoin_rpc.username", rpc_username)?;
+            s = s.with_override("bitcoin_rpc.username", rpc_username);
         }
 
         // Set rpc password from cmd line
         if let Some(rpc_password) = matches.value_of("rpc-password") {
-            s.set("bitcoin_rpc.password", rpc_password)?;
+            s = s.with_override("bitcoin_rpc.password", rpc_password);
         }
 
         // Set ZMQ address from cmd line
-        if let Some(rpc_password) = matches.value_of("rpc-password") {
-            s.set("bitcoin_rpc.zmq_address", rpc_password)?;
+        if let Some(zmq_address) = matches.value_of("zmq-addr") {
+            s = s.with_override("bitcoin_rpc.zmq_address", zmq_address);
         }
 
-        s.try_into()
+        s.load()
+    }
+}
+
+impl Validate for Settings {
+    fn validate(&self) -> Result<(), ValidationError> {
+        self.bitcoin_rpc
+            .validate()
+            .map_err(|err| err.in_section("bitcoin_rpc"))?;
+        if self.bitcoin_rpc.zmq_address.is_none() {
+            return Err(ValidationError::new(
+                "bitcoin_rpc.zmq_address",
+                "a ZMQ address is required",
+            ));
+        }
+        if self.limits.metadata_size == 0 || self.limits.payment_size == 0 {
+            return Err(ValidationError::new("limits", "sizes must be positive"));
+        }
+        if self.limits.image_size == 0 || self.limits.image_dimension == 0 {
+            return Err(ValidationError::new(
+                "limits",
+                "image limits must be positive",
+            ));
+        }
+        if self.limits.replication_page_size == 0 {
+            return Err(ValidationError::new(
+                "limits.replication_page_size",
+                "must be positive",
+            ));
+        }
+        if let Some(url) = self
+            .webhooks
+            .urls
+            .iter()
+            .find(|url| url.parse::<Uri>().is_err())
+        {
+            return Err(ValidationError::new(
+                "webhooks.urls",
+                format!("invalid URL {:?}", url),
+            ));
+        }
+        match &self.webhooks.secret {
+            Some(secret) => {
+                if let Err(err) = SecretKey::load(secret.expose()) {
+                    return Err(ValidationError::new(
+                        "webhooks.secret",
+                        format!("unable to load key: {}", err),
+                    ));
+                }
+            }
+            None if !self.webhooks.urls.is_empty() => {
+                return Err(ValidationError::new(
+                    "webhooks.secret",
+                    "a secret is required to sign webhooks",
+                ));
+            }
+            None => (),
+        }
+        if let Some(secret) = &self.admin.secret {
+            if let Err(err) = SecretKey::load(secret.expose()) {
+                return Err(ValidationError::new(
+                    "admin.secret",
+                    format!("unable to load key: {}", err),
+                ));
+            }
+        }
+        if let Err(err) = parse_public_keys(&self.admin.public_keys) {
+            return Err(ValidationError::new("admin.public_keys", err));
+        }
+        if let Some(secret) = &self.peering.signing_secret {
+            if let Err(err) = load_signing_key(secret.expose()) {
+                return Err(ValidationError::new("peering.signing_secret", err));
+            }
+        }
+        if let Err(err) = parse_public_keys(&self.peering.trusted_keys) {
+            return Err(ValidationError::new("peering.trusted_keys", err));
+        }
+        if let Err(err) = peer_credentials(&self.peering.credentials) {
+            return Err(ValidationError::new("peering.credentials", err));
+        }
+        if let Some(secret) = &self.payments.quote_secret {
+            if let Err(err) = SecretKey::load(secret.expose()) {
+                return Err(ValidationError::new(
+                    "payments.quote_secret",
+                    format!("unable to load key: {}", err),
+                ));
+            }
+        }
+        if let Some(fiat) = &self.payments.fiat {
+            if fiat.sats_per_coin == 0 {
+                return Err(ValidationError::new(
+                    "payments.fiat.sats_per_coin",
+                    "must be positive",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse hex encoded secp256k1 public keys.
+pub fn parse_public_keys(keys: &[String]) -> Result<Vec<PublicKey>, String> {
+    keys.iter()
+        .map(|key| {
+            let raw = hex::decode(key).map_err(|_| format!("invalid hex {:?}", key))?;
+            PublicKey::from_slice(&raw).map_err(|err| format!("invalid key {:?}: {}", key, err))
+        })
+        .collect()
+}
+
+/// Load a secp256k1 secret key from a key spec.
+pub fn load_signing_key(spec: &str) -> Result<secp256k1::key::SecretKey, String> {
+    let key = SecretKey::load(spec).map_err(|err| format!("unable to load key: {}", err))?;
+    secp256k1::key::SecretKey::from_slice(key.as_bytes())
+        .map_err(|err| format!("invalid secp256k1 key: {}", err))
+}
+
+/// Build the credentials presented to peers, each being either a username and password or an API
+/// key.
+pub fn peer_credentials(credentials: &[PeerCredential]) -> Result<HostCredentials, String> {
+    let mut host_credentials = HostCredentials::new();
+    for credential in credentials {
+        let host = &credential.host;
+        let parsed = match (
+            &credential.username,
+            &credential.password,
+            &credential.api_key_header,
+            &credential.api_key,
+        ) {
+            (Some(username), Some(password), None, None) => {
+                Credential::basic(username, password.expose())
+            }
+            (None, None, header, Some(api_key)) => {
+                let header = header.as_deref().unwrap_or(DEFAULT_API_KEY_HEADER);
+                let header = HeaderName::from_bytes(header.as_bytes())
+                    .map_err(|_| format!("{}: invalid header {:?}", host, header))?;
+                Credential::api_key(header, api_key.expose())
+            }
+            _ => {
+                return Err(format!(
+                    "{}: either a username and password, or an API key, is required",
+                    host
+                ))
+            }
+        }
+        .map_err(|err| format!("{}: {}", host, err))?;
+        host_credentials.insert(host, parsed);
     }
+    Ok(host_credentials)
 }
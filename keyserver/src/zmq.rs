@@ -0,0 +1,202 @@
+use std::{collections::HashMap, convert::TryInto, time::Duration};
+
+use async_zmq::Subscribe;
+use cashweb::lifecycle::{
+    bus::{BlockConnected, EventBus, GapCause, NotificationGap},
+    ShutdownToken,
+};
+use futures::prelude::*;
+use tracing::{error, info, warn};
+
+/// The topic on which the node announces blocks.
+const BLOCK_TOPIC: &str = "hashblock";
+
+/// Delay before reconnecting once the notification stream ends.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A notification published by the node, made of topic, body and sequence number frames.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Notification<'a> {
+    pub topic: &'a [u8],
+    pub body: &'a [u8],
+    pub sequence: u32,
+}
+
+impl<'a> Notification<'a> {
+    pub fn parse(frames: &[&'a [u8]]) -> Option<Self> {
+        match frames {
+            [topic, body, sequence] => Some(Self {
+                topic,
+                body,
+                sequence: u32::from_le_bytes((*sequence).try_into().ok()?),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks the sequence number of each topic, detecting missed notifications.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    expected: HashMap<Vec<u8>, u32>,
+}
+
+impl SequenceTracker {
+    /// Observe the sequence number of a notification, returning the cause of the gap preceding
+    /// it, if any.
+    pub fn observe(&mut self, topic: &[u8], sequence: u32) -> Option<GapCause> {
+        let expected = self
+            .expected
+            .insert(topic.to_vec(), sequence.wrapping_add(1))?;
+        if sequence == expected {
+            None
+        } else if sequence < expected {
+            Some(GapCause::Restarted)
+        } else {
+            Some(GapCause::Skipped(sequence - expected))
+        }
+    }
+
+    /// Forget the sequence numbers, as they can't be compared across connections.
+    pub fn reset(&mut self) {
+        self.expected.clear();
+    }
+}
+
+/// Subscribe to the blocks announced at the ZMQ address.
+pub fn connect(address: &str) -> Option<Subscribe> {
+    let builder = match async_zmq::subscribe(address) {
+        Ok(ok) => ok,
+        Err(err) => {
+            error!(message = "invalid ZMQ address", error = %err);
+            return None;
+        }
+    };
+    let subscriber = match builder.connect() {
+        Ok(ok) => ok,
+        Err(err) => {
+            error!(message = "failed to connect to ZMQ", error = %err);
+            return None;
+        }
+    };
+    if let Err(err) = subscriber.set_subscribe(BLOCK_TOPIC) {
+        error!(message = "failed to subscribe to ZMQ topic", topic = BLOCK_TOPIC, error = %err);
+        return None;
+    }
+    Some(subscriber)
+}
+
+/// Announce the blocks published by the node until shutdown, reconnecting whenever the stream
+/// ends.
+///
+/// Gaps in the sequence numbers, and reconnections, are announced as [`NotificationGap`]s, so
+/// that consumers can recover what they missed over RPC.
+pub async fn watch_blocks(
+    mut subscriber: Subscribe,
+    address: String,
+    events: EventBus,
+    shutdown: ShutdownToken,
+) {
+    let mut tracker = SequenceTracker::default();
+    loop {
+        loop {
+            let frames = tokio::select! {
+                _ = shutdown.clone().cancelled() => return,
+                frames = subscriber.next() => match frames {
+                    Some(Ok(frames)) => frames,
+                    Some(Err(err)) => {
+                        warn!(message = "failed to receive ZMQ notification", error = %err);
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            let frames: Vec<&[u8]> = frames.iter().map(|frame| frame.as_ref()).collect();
+            let notification = match Notification::parse(&frames) {
+                Some(some) => some,
+                None => {
+                    warn!(
+                        message = "malformed ZMQ notification",
+                        frames = frames.len()
+                    );
+                    continue;
+                }
+            };
+
+            let topic = String::from_utf8_lossy(notification.topic);
+            if let Some(cause) = tracker.observe(notification.topic, notification.sequence) {
+                warn!(message = "missed ZMQ notifications", %topic, ?cause);
+                events.notification_gap().publish(NotificationGap {
+                    topic: topic.to_string(),
+                    cause,
+                });
+            }
+            if topic == BLOCK_TOPIC {
+                info!(message = "found block", block_id = %hex::encode(notification.body));
+                events.block_connected().publish(BlockConnected {
+                    hash: notification.body.to_vec(),
+                });
+            }
+        }
+
+        // Notifications published while disconnected are lost
+        warn!(message = "ZMQ stream ended, reconnecting");
+        subscriber = loop {
+            tokio::select! {
+                _ = shutdown.clone().cancelled() => return,
+                _ = tokio::time::sleep(RECONNECT_DELAY) => (),
+            }
+            if let Some(subscriber) = connect(&address) {
+                break subscriber;
+            }
+        };
+        tracker.reset();
+        events.notification_gap().publish(NotificationGap {
+            topic: BLOCK_TOPIC.to_string(),
+            cause: GapCause::Reconnected,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let sequence = 7u32.to_le_bytes();
+        let frames: [&[u8]; 3] = [b"hashblock", &[1, 2], &sequence];
+        assert_eq!(
+            Notification::parse(&frames),
+            Some(Notification {
+                topic: b"hashblock",
+                body: &[1, 2],
+                sequence: 7,
+            })
+        );
+        assert_eq!(Notification::parse(&frames[..2]), None);
+        assert_eq!(Notification::parse(&[b"hashblock", &[1, 2], &[7]]), None);
+    }
+
+    #[test]
+    fn gaps() {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.observe(b"hashblock", 5), None);
+        assert_eq!(tracker.observe(b"hashblock", 6), None);
+        // Topics are sequenced independently
+        assert_eq!(tracker.observe(b"hashtx", 100), None);
+        assert_eq!(tracker.observe(b"hashblock", 9), Some(GapCause::Skipped(2)));
+        assert_eq!(tracker.observe(b"hashblock", 0), Some(GapCause::Restarted));
+        assert_eq!(tracker.observe(b"hashblock", 1), None);
+
+        // Sequence numbers wrap
+        assert_eq!(
+            tracker.observe(b"hashtx", u32::MAX),
+            Some(GapCause::Skipped(u32::MAX - 101))
+        );
+        assert_eq!(tracker.observe(b"hashtx", 0), None);
+
+        tracker.reset();
+        assert_eq!(tracker.observe(b"hashblock", 42), None);
+    }
+}
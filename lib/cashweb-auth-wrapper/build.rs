@@ -1,3 +1,22 @@
 fn main() {
-    prost_build::compile_protos(&["src/proto/wrapper.proto"], &["src/"]).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Regenerate the models from the .proto files, rather than use those checked in
+    #[cfg(feature = "codegen")]
+    {
+        println!("cargo:rerun-if-changed=src/proto/wrapper.proto");
+        println!("cargo:rerun-if-changed=src/generated/wrapper.rs");
+        prost_build::compile_protos(&["src/proto/wrapper.proto"], &["src/"]).unwrap();
+
+        // Flag models which have drifted from those checked in
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let generated = std::fs::read_to_string(format!("{}/wrapper.rs", out_dir)).unwrap();
+        let checked_in = std::fs::read_to_string("src/generated/wrapper.rs").unwrap_or_default();
+        if generated != checked_in {
+            println!(
+                "cargo:warning=src/generated/wrapper.rs is out of date, see {}/wrapper.rs",
+                out_dir
+            );
+        }
+    }
 }
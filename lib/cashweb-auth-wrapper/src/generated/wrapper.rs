@@ -0,0 +1,58 @@
+/// BurnOutputs represents a transaction and the output which burns some lotus
+/// to commit to the auth wrapper's payload. This ensures that some amount was
+/// paid to relay a message.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BurnOutputs {
+    /// Transaction which burns some XPI to commit to the message
+    #[prost(bytes="vec", tag="1")]
+    pub tx: ::prost::alloc::vec::Vec<u8>,
+    /// Index of the op_return which contains the commitment to for the authwrapper
+    #[prost(uint32, tag="2")]
+    pub index: u32,
+}
+/// AuthWrapper provides integrity, authentication, and non-repuditation by
+/// providing a standard structure for covering blobs with signatures.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthWrapper {
+    /// The public key associated with the signature.
+    #[prost(bytes="vec", tag="1")]
+    pub public_key: ::prost::alloc::vec::Vec<u8>,
+    /// The signature by public key covering the payload.
+    #[prost(bytes="vec", tag="2")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    /// The signature scheme used for signing.
+    #[prost(enumeration="auth_wrapper::SignatureScheme", tag="3")]
+    pub scheme: i32,
+    /// The payload covered by the signature.
+    #[prost(bytes="vec", tag="4")]
+    pub payload: ::prost::alloc::vec::Vec<u8>,
+    /// The SHA256 digest of the payload.
+    #[prost(bytes="vec", tag="5")]
+    pub payload_digest: ::prost::alloc::vec::Vec<u8>,
+    /// Net amount of lotus burned in the transaction set associated with this auth
+    /// wrapper.
+    #[prost(int64, tag="6")]
+    pub burn_amount: i64,
+    /// Full serialized bitcoin transactions which committed to the payload_digest
+    #[prost(message, repeated, tag="7")]
+    pub transactions: ::prost::alloc::vec::Vec<BurnOutputs>,
+}
+/// Nested message and enum types in `AuthWrapper`.
+pub mod auth_wrapper {
+    /// Supported signature schemes. Default is Schnorr, but can be ECDSA.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum SignatureScheme {
+        /// Schnorr signature scheme
+        Schnorr = 0,
+        /// Elliptic curve digital signature scheme
+        Ecdsa = 1,
+    }
+}
+/// Set of auth wrappers for returning multiple items to the client as needed.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthWrapperSet {
+    /// Set of auth wrappers that can be used in certain get responses
+    #[prost(message, repeated, tag="1")]
+    pub items: ::prost::alloc::vec::Vec<AuthWrapper>,
+}
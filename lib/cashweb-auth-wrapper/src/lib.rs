@@ -15,7 +15,10 @@ mod models;
 use std::convert::TryInto;
 
 use ring::digest::{digest, SHA256};
-use secp256k1::{key::PublicKey, Error as SecpError, Message, Secp256k1, Signature};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Message, Secp256k1, Signature,
+};
 use thiserror::Error;
 
 pub use models::{auth_wrapper::SignatureScheme, *};
@@ -59,6 +62,25 @@ pub enum ParseError {
 }
 
 impl AuthWrapper {
+    /// Construct an [`AuthWrapper`] covering the payload, signed by the secret key using ECDSA.
+    ///
+    /// The public key and the digest of the payload are included.
+    pub fn sign_ecdsa(secret_key: &SecretKey, payload: Vec<u8>) -> Self {
+        let secp = Secp256k1::signing_only();
+        let payload_digest = digest(&SHA256, &payload);
+        let msg = Message::from_slice(payload_digest.as_ref()).unwrap(); // This is safe
+        AuthWrapper {
+            public_key: PublicKey::from_secret_key(&secp, secret_key)
+                .serialize()
+                .to_vec(),
+            signature: secp.sign(&msg, secret_key).serialize_compact().to_vec(),
+            scheme: SignatureScheme::Ecdsa as i32,
+            payload_digest: payload_digest.as_ref().to_vec(),
+            payload,
+            ..Default::default()
+        }
+    }
+
     /// Parse the [`AuthWrapper`] to construct a [`ParsedAuthWrapper`].
     ///
     /// The involves deserialization of both public keys, calculation of the payload digest, and coercion of byte fields
@@ -1 +1,4 @@
+#[cfg(feature = "codegen")]
 include!(concat!(env!("OUT_DIR"), "/wrapper.rs"));
+#[cfg(not(feature = "codegen"))]
+include!("generated/wrapper.rs");
@@ -0,0 +1,195 @@
+//! This module contains the [`ChainClient`] trait which provides methods for retrieving
+//! blocks from bitcoind.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cashweb_bitcoin::{
+    block::{Block, BlockHeader, DecodeError as BlockDecodeError},
+    Decodable,
+};
+use json_rpc::prelude::RequestFactory;
+use serde_json::Value;
+
+use crate::{call, BitcoinClientHTTP, BitcoinClientTLS, BitcoinJsonClient, Connectable, NodeError};
+
+/// Chain-oriented client methods.
+#[async_trait]
+pub trait ChainClient {
+    /// Get the height of the most-work fully-validated chain from bitcoind.
+    async fn get_block_count(&self) -> Result<u64, NodeError>;
+
+    /// Get the hash of the block at the given height in the best chain from bitcoind.
+    async fn get_block_hash(&self, height: u64) -> Result<Vec<u8>, NodeError>;
+
+    /// Get the header of the block with the given hash from bitcoind.
+    ///
+    /// The hash is expected in the same byte order as the lotusd-rpc hex encoding.
+    async fn get_block_header(&self, block_hash: &[u8]) -> Result<BlockHeader, NodeError>;
+
+    /// Get the raw block with the given hash from bitcoind.
+    ///
+    /// The hash is expected in the same byte order as the lotusd-rpc hex encoding.
+    async fn get_raw_block(&self, block_hash: &[u8]) -> Result<Vec<u8>, NodeError>;
+
+    /// Get the block with the given hash from bitcoind and decode it.
+    ///
+    /// The scripts of the block share the raw block buffer, see [`Block::into_owned`].
+    async fn get_block(&self, block_hash: &[u8]) -> Result<Block, NodeError> {
+        let mut raw_block = Bytes::from(self.get_raw_block(block_hash).await?);
+        Block::decode_shared(&mut raw_block).map_err(Into::into)
+    }
+}
+
+/// Client methods querying the UTXO set.
+#[async_trait]
+pub trait UtxoClient {
+    /// Whether the output at the outpoint exists and is unspent, taking the mempool into account.
+    ///
+    /// The transaction ID is expected in the same byte order as the lotusd-rpc hex encoding.
+    async fn is_unspent(&self, tx_id: &[u8], vout: u32) -> Result<bool, NodeError>;
+}
+
+/// Calls the `getblockhash` method.
+async fn get_block_hash<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    height: u64,
+) -> Result<Vec<u8>, NodeError> {
+    let block_hash_hex: String = call(client, "getblockhash", vec![Value::from(height)]).await?;
+    hex::decode(block_hash_hex).map_err(Into::into)
+}
+
+/// Calls the `getblockheader` method with verbose set to false.
+async fn get_block_header<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    block_hash: &[u8],
+) -> Result<BlockHeader, NodeError> {
+    let params = vec![Value::String(hex::encode(block_hash)), Value::Bool(false)];
+    let header_hex: String = call(client, "getblockheader", params).await?;
+    let raw_header = hex::decode(header_hex)?;
+    BlockHeader::decode(&mut raw_header.as_slice())
+        .map_err(|err| NodeError::BlockDecode(BlockDecodeError::Header(err)))
+}
+
+/// Calls the `getblock` method with verbosity 0.
+async fn get_raw_block<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    block_hash: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    let params = vec![Value::String(hex::encode(block_hash)), Value::from(0)];
+    let block_hex: String = call(client, "getblock", params).await?;
+    hex::decode(block_hex).map_err(Into::into)
+}
+
+/// Calls the `gettxout` method, including the mempool.
+async fn is_unspent<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    tx_id: &[u8],
+    vout: u32,
+) -> Result<bool, NodeError> {
+    let params = vec![
+        Value::String(hex::encode(tx_id)),
+        Value::from(vout),
+        Value::Bool(true),
+    ];
+    let request = client
+        .build_request()
+        .method("gettxout")
+        .params(params)
+        .finish()
+        .unwrap();
+    let response = client.send(request).await?;
+    if response.is_error() {
+        return Err(NodeError::Rpc(response.error().unwrap()));
+    }
+    // A null result indicates the output is spent or never existed
+    let result: Option<Result<Value, _>> = response.into_result();
+    match result {
+        Some(result) => result.map(|_| true).map_err(NodeError::Json),
+        None => Ok(false),
+    }
+}
+
+#[async_trait]
+impl ChainClient for BitcoinClientHTTP {
+    /// Calls the `getblockcount` method.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        call(&self.0, "getblockcount", vec![]).await
+    }
+
+    /// Calls the `getblockhash` method.
+    async fn get_block_hash(&self, height: u64) -> Result<Vec<u8>, NodeError> {
+        get_block_hash(&self.0, height).await
+    }
+
+    /// Calls the `getblockheader` method.
+    async fn get_block_header(&self, block_hash: &[u8]) -> Result<BlockHeader, NodeError> {
+        get_block_header(&self.0, block_hash).await
+    }
+
+    /// Calls the `getblock` method.
+    async fn get_raw_block(&self, block_hash: &[u8]) -> Result<Vec<u8>, NodeError> {
+        get_raw_block(&self.0, block_hash).await
+    }
+}
+
+#[async_trait]
+impl ChainClient for BitcoinClientTLS {
+    /// Calls the `getblockcount` method.
+    async fn get_block_count(&self) -> Result<u64, NodeError> {
+        call(&self.0, "getblockcount", vec![]).await
+    }
+
+    /// Calls the `getblockhash` method.
+    async fn get_block_hash(&self, height: u64) -> Result<Vec<u8>, NodeError> {
+        get_block_hash(&self.0, height).await
+    }
+
+    /// Calls the `getblockheader` method.
+    async fn get_block_header(&self, block_hash: &[u8]) -> Result<BlockHeader, NodeError> {
+        get_block_header(&self.0, block_hash).await
+    }
+
+    /// Calls the `getblock` method.
+    async fn get_raw_block(&self, block_hash: &[u8]) -> Result<Vec<u8>, NodeError> {
+        get_raw_block(&self.0, block_hash).await
+    }
+}
+
+#[async_trait]
+impl UtxoClient for BitcoinClientHTTP {
+    /// Calls the `gettxout` method.
+    async fn is_unspent(&self, tx_id: &[u8], vout: u32) -> Result<bool, NodeError> {
+        is_unspent(&self.0, tx_id, vout).await
+    }
+}
+
+#[async_trait]
+impl UtxoClient for BitcoinClientTLS {
+    /// Calls the `gettxout` method.
+    async fn is_unspent(&self, tx_id: &[u8], vout: u32) -> Result<bool, NodeError> {
+        is_unspent(&self.0, tx_id, vout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+
+    #[tokio::test]
+    async fn is_unspent() {
+        let (client, requests) = MockRpc::default()
+            .result("gettxout", json!({ "value": 1.0, "confirmations": 0 }))
+            .start();
+        assert!(client.is_unspent(&[1, 2], 3).await.unwrap());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![("gettxout".to_string(), json!(["0102", 3, true]))]
+        );
+
+        let (client, _) = MockRpc::default().result("gettxout", Value::Null).start();
+        assert!(!client.is_unspent(&[1, 2], 3).await.unwrap());
+    }
+}
@@ -0,0 +1,218 @@
+//! This module contains the [`BroadcastAndConfirm`] helper which broadcasts transactions and waits
+//! for bitcoind to announce them over its ZMQ `hashtx` topic.
+//!
+//! This gives positive confirmation of mempool acceptance beyond the RPC return value.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use cashweb_bitcoin::transaction::Transaction;
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use thiserror::Error;
+use tokio::{
+    sync::oneshot::{self, Receiver, Sender},
+    time::timeout,
+};
+
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// Error associated with [`BroadcastAndConfirm::broadcast`].
+#[derive(Debug, Error)]
+pub enum ConfirmError {
+    /// The broadcast failed.
+    #[error(transparent)]
+    Node(#[from] NodeError),
+    /// bitcoind did not announce the transaction before the timeout elapsed.
+    #[error("transaction announcement timed out")]
+    Timeout,
+}
+
+type Waiters = HashMap<[u8; 32], Vec<Sender<()>>>;
+
+/// Broadcasts transactions and waits for them to be announced over ZMQ.
+///
+/// Announcements are fed to the helper using [`BroadcastAndConfirm::listen`], which should be run
+/// alongside any broadcasts.
+#[derive(Clone, Debug)]
+pub struct BroadcastAndConfirm<C> {
+    client: C,
+    timeout: Duration,
+    waiters: Arc<Mutex<Waiters>>,
+}
+
+impl<C> BroadcastAndConfirm<C> {
+    /// Create a new [`BroadcastAndConfirm`], waiting at most `timeout` for each announcement.
+    pub fn new(client: C, timeout: Duration) -> Self {
+        Self {
+            client,
+            timeout,
+            waiters: Default::default(),
+        }
+    }
+
+    /// Consume a stream of `hashtx` message bodies, resolving the broadcasts awaiting them.
+    ///
+    /// Each item is expected to be a transaction ID in the byte order used by the RPC. Items of
+    /// the wrong length are ignored.
+    pub async fn listen<S, T>(&self, announcements: S)
+    where
+        S: Stream<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        pin_mut!(announcements);
+        while let Some(tx_id) = announcements.next().await {
+            let tx_id = match <[u8; 32]>::try_from(tx_id.as_ref()) {
+                Ok(ok) => ok,
+                Err(_) => continue,
+            };
+            let senders = self.waiters.lock().unwrap().remove(&tx_id);
+            for sender in senders.into_iter().flatten() {
+                let _ = sender.send(());
+            }
+        }
+    }
+
+    fn register(&self, tx_id: [u8; 32]) -> Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.waiters
+            .lock()
+            .unwrap()
+            .entry(tx_id)
+            .or_default()
+            .push(sender);
+        receiver
+    }
+
+    fn unregister(&self, tx_id: &[u8; 32]) {
+        let mut waiters = self.waiters.lock().unwrap();
+        if let Some(senders) = waiters.get_mut(tx_id) {
+            senders.retain(|sender| !sender.is_closed());
+            if senders.is_empty() {
+                waiters.remove(tx_id);
+            }
+        }
+    }
+}
+
+impl<C> BroadcastAndConfirm<C>
+where
+    C: BitcoinClient + Sync,
+{
+    /// Broadcast a [`Transaction`] and wait for bitcoind to announce it.
+    ///
+    /// bitcoind does not announce a transaction already in its mempool, so a transaction it
+    /// already knows is confirmed once rebroadcast, without waiting.
+    ///
+    /// Returns the transaction ID in the byte order used by the RPC.
+    pub async fn broadcast(
+        &self,
+        transaction: &Transaction,
+        fee_policy: FeePolicy,
+    ) -> Result<[u8; 32], ConfirmError> {
+        // Register before checking and sending to avoid missing the announcement
+        let tx_id = transaction.transaction_id_rev();
+        let receiver = self.register(tx_id);
+
+        let known = self.client.get_raw_transaction(&tx_id).await.is_ok();
+        let result = self.client.send_transaction(transaction, fee_policy).await;
+        if let Err(err) = result {
+            drop(receiver);
+            self.unregister(&tx_id);
+            return Err(err.into());
+        }
+        if known {
+            drop(receiver);
+            self.unregister(&tx_id);
+            return Ok(tx_id);
+        }
+
+        match timeout(self.timeout, receiver).await {
+            Ok(Ok(())) => Ok(tx_id),
+            _ => {
+                self.unregister(&tx_id);
+                Err(ConfirmError::Timeout)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use futures_util::stream;
+
+    use super::*;
+
+    struct MockClient {
+        known: bool,
+    }
+
+    #[async_trait]
+    impl BitcoinClient for MockClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            if self.known {
+                Ok(Vec::new())
+            } else {
+                Err(NodeError::EmptyResponse)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn confirmed() {
+        let confirm = BroadcastAndConfirm::new(MockClient { known: false }, Duration::from_secs(1));
+        let transaction = Transaction::default();
+        let tx_id = transaction.transaction_id_rev();
+
+        let announce = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let announcements = vec![vec![0; 3], [1; 32].to_vec(), tx_id.to_vec()];
+            confirm.listen(stream::iter(announcements)).await;
+        };
+        let (result, _) = tokio::join!(
+            confirm.broadcast(&transaction, FeePolicy::Default),
+            announce
+        );
+        assert_eq!(result.unwrap(), tx_id);
+        assert!(confirm.waiters.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_out() {
+        let confirm =
+            BroadcastAndConfirm::new(MockClient { known: false }, Duration::from_millis(10));
+        let result = confirm
+            .broadcast(&Transaction::default(), FeePolicy::Default)
+            .await;
+        assert!(matches!(result, Err(ConfirmError::Timeout)));
+        assert!(confirm.waiters.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn already_known() {
+        // No announcement is made for a transaction already in the mempool
+        let confirm =
+            BroadcastAndConfirm::new(MockClient { known: true }, Duration::from_millis(10));
+        let transaction = Transaction::default();
+        let result = confirm.broadcast(&transaction, FeePolicy::Default).await;
+        assert_eq!(result.unwrap(), transaction.transaction_id_rev());
+        assert!(confirm.waiters.lock().unwrap().is_empty());
+    }
+}
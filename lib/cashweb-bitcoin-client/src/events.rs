@@ -0,0 +1,180 @@
+//! This module contains the [`PublishingClient`] which publishes a [`BroadcastEvent`] to an
+//! [`EventSink`] for every transaction accepted by bitcoind.
+//!
+//! Message bus integrations, such as Kafka or NATS, are provided by implementing [`EventSink`].
+
+use std::{fmt, time::SystemTime};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{error::SendError, UnboundedSender};
+use tracing::error;
+
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// A transaction accepted by bitcoind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BroadcastEvent {
+    /// Transaction ID, as returned by bitcoind.
+    pub tx_id: String,
+    /// The raw transaction.
+    pub raw_tx: Vec<u8>,
+    /// Time at which bitcoind accepted the transaction.
+    pub timestamp: SystemTime,
+    /// Label identifying the node which accepted the transaction.
+    pub node: String,
+}
+
+/// A destination for [`BroadcastEvent`]s.
+#[async_trait]
+pub trait EventSink {
+    /// Error associated with publishing an event.
+    type Error: fmt::Display;
+
+    /// Publish an event.
+    async fn publish(&self, event: BroadcastEvent) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+impl EventSink for UnboundedSender<BroadcastEvent> {
+    type Error = SendError<BroadcastEvent>;
+
+    async fn publish(&self, event: BroadcastEvent) -> Result<(), Self::Error> {
+        self.send(event)
+    }
+}
+
+/// A [`BitcoinClient`] which publishes accepted transactions to an [`EventSink`].
+///
+/// Failing to publish an event is logged but does not fail the broadcast, as the transaction has
+/// already been accepted.
+#[derive(Clone, Debug)]
+pub struct PublishingClient<C, S> {
+    inner_client: C,
+    sink: S,
+    node: String,
+}
+
+impl<C, S> PublishingClient<C, S> {
+    /// Wrap a [`BitcoinClient`] with an [`EventSink`], labelling events with the given node.
+    pub fn new(inner_client: C, sink: S, node: String) -> Self {
+        Self {
+            inner_client,
+            sink,
+            node,
+        }
+    }
+
+    /// Get the [`EventSink`].
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Converts the publishing client into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.inner_client
+    }
+}
+
+#[async_trait]
+impl<C, S> BitcoinClient for PublishingClient<C, S>
+where
+    C: BitcoinClient + Send + Sync,
+    S: EventSink + Send + Sync,
+{
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let tx_id = self
+            .inner_client
+            .send_tx_with_fee_policy(raw_tx, fee_policy)
+            .await?;
+
+        let event = BroadcastEvent {
+            tx_id: tx_id.clone(),
+            raw_tx: raw_tx.to_vec(),
+            timestamp: SystemTime::now(),
+            node: self.node.clone(),
+        };
+        if let Err(err) = self.sink.publish(event).await {
+            error!(message = "failed to publish broadcast event", %tx_id, error = %err);
+        }
+
+        Ok(tx_id)
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.inner_client.get_new_addr().await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.inner_client.get_raw_transaction(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    /// Accepts every transaction, or times out if `accept` is unset.
+    #[derive(Debug)]
+    struct MockClient {
+        accept: bool,
+    }
+
+    #[async_trait]
+    impl BitcoinClient for MockClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            if self.accept {
+                Ok(hex::encode(raw_tx))
+            } else {
+                Err(NodeError::Timeout)
+            }
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_accepted() {
+        let (sender, mut receiver) = unbounded_channel();
+        let client = PublishingClient::new(MockClient { accept: true }, sender, "a".to_string());
+        assert_eq!(client.send_tx(&[1, 2]).await.unwrap(), "0102");
+
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.tx_id, "0102");
+        assert_eq!(event.raw_tx, vec![1, 2]);
+        assert_eq!(event.node, "a");
+    }
+
+    #[tokio::test]
+    async fn skips_failed() {
+        let (sender, mut receiver) = unbounded_channel();
+        let client = PublishingClient::new(MockClient { accept: false }, sender, "a".to_string());
+        assert!(client.send_tx(&[1, 2]).await.is_err());
+
+        drop(client);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sink_failure_does_not_fail_broadcast() {
+        let (sender, receiver) = unbounded_channel();
+        drop(receiver);
+        let client = PublishingClient::new(MockClient { accept: true }, sender, "a".to_string());
+        assert_eq!(client.send_tx(&[1, 2]).await.unwrap(), "0102");
+    }
+}
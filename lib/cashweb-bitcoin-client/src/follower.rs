@@ -0,0 +1,304 @@
+//! This module contains the [`ChainFollower`] which walks the best chain of bitcoind,
+//! detecting reorganizations and emitting [`ChainEvent`]s.
+
+use std::collections::VecDeque;
+
+use cashweb_bitcoin::block::Block;
+use thiserror::Error;
+
+use crate::{chain::ChainClient, NodeError};
+
+/// Event emitted by the [`ChainFollower`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The block was connected to the tip of the best chain.
+    Connected(Block),
+    /// The block was disconnected from the tip of the best chain.
+    Disconnected(Block),
+}
+
+/// Error associated with following the chain.
+#[derive(Debug, Error)]
+pub enum FollowerError {
+    /// Error communicating with bitcoind.
+    #[error(transparent)]
+    Node(#[from] NodeError),
+    /// The reorganization was deeper than the blocks retained by the follower.
+    ///
+    /// The next call to [`ChainFollower::poll`] begins following from the current tip.
+    #[error("reorganization deeper than {0} blocks")]
+    ReorgTooDeep(usize),
+    /// The genesis block differs from the one followed, so bitcoind is on a different chain.
+    ///
+    /// The next call to [`ChainFollower::poll`] begins following from the current tip.
+    #[error("genesis block changed")]
+    GenesisMismatch,
+}
+
+#[derive(Clone, Debug)]
+struct ChainEntry {
+    height: u64,
+    hash: Vec<u8>,
+}
+
+/// Follows the best chain of bitcoind.
+///
+/// The follower retains the hashes of the most recent blocks. Each call to [`ChainFollower::poll`]
+/// compares them against the best chain, disconnecting stale blocks and connecting new ones.
+#[derive(Clone, Debug)]
+pub struct ChainFollower<C> {
+    client: C,
+    max_depth: usize,
+    chain: VecDeque<ChainEntry>,
+}
+
+impl<C> ChainFollower<C> {
+    /// Create a new [`ChainFollower`] retaining at most `max_depth` blocks.
+    ///
+    /// The first call to [`ChainFollower::poll`] begins following from the current tip.
+    pub fn new(client: C, max_depth: usize) -> Self {
+        Self {
+            client,
+            max_depth,
+            chain: VecDeque::with_capacity(max_depth),
+        }
+    }
+
+    /// Get the height and hash of the current tip, if any.
+    pub fn tip(&self) -> Option<(u64, &[u8])> {
+        self.chain
+            .back()
+            .map(|entry| (entry.height, entry.hash.as_slice()))
+    }
+
+    /// Converts the follower into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+impl<C> ChainFollower<C>
+where
+    C: ChainClient + Sync,
+{
+    /// Synchronize with the best chain, returning the resulting [`ChainEvent`]s in order.
+    pub async fn poll(&mut self) -> Result<Vec<ChainEvent>, FollowerError> {
+        let best_height = self.client.get_block_count().await?;
+
+        let tip_height = match self.chain.back() {
+            Some(entry) => entry.height,
+            None => {
+                // Begin following from the current tip
+                let hash = self.client.get_block_hash(best_height).await?;
+                self.chain.push_back(ChainEntry {
+                    height: best_height,
+                    hash,
+                });
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut events = Vec::new();
+
+        // Disconnect blocks which are no longer in the best chain
+        let mut height = tip_height;
+        while let Some(entry) = self.chain.back() {
+            if entry.height <= best_height {
+                let hash = self.client.get_block_hash(entry.height).await?;
+                if hash == entry.hash {
+                    break;
+                }
+            }
+            let block = self.client.get_block(&entry.hash).await?;
+            events.push(ChainEvent::Disconnected(block));
+            height = match entry.height.checked_sub(1) {
+                Some(some) => some,
+                None => {
+                    // The genesis block can't be reorganized away
+                    self.chain.clear();
+                    return Err(FollowerError::GenesisMismatch);
+                }
+            };
+            self.chain.pop_back();
+        }
+        if self.chain.is_empty() {
+            return Err(FollowerError::ReorgTooDeep(self.max_depth));
+        }
+
+        // Connect blocks from the best chain
+        while height < best_height {
+            height += 1;
+            let hash = self.client.get_block_hash(height).await?;
+            let block = self.client.get_block(&hash).await?;
+
+            // The chain may have reorganized since the block hash was fetched
+            let prev_hash = &self.chain.back().unwrap().hash; // This is safe as the chain is non-empty
+            if !prev_hash
+                .iter()
+                .rev()
+                .eq(block.header.prev_block_hash.iter())
+            {
+                break;
+            }
+
+            self.chain.push_back(ChainEntry { height, hash });
+            if self.chain.len() > self.max_depth {
+                self.chain.pop_front();
+            }
+            events.push(ChainEvent::Connected(block));
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use cashweb_bitcoin::block::BlockHeader;
+
+    use super::*;
+
+    /// The hash of a block is its height followed by the fork it belongs to.
+    fn block_hash(height: u64, fork: u8) -> Vec<u8> {
+        let mut hash = vec![0; 32];
+        hash[..8].copy_from_slice(&height.to_le_bytes());
+        hash[8] = fork;
+        hash
+    }
+
+    struct MockChain {
+        forks: Mutex<Vec<u8>>,
+    }
+
+    impl MockChain {
+        fn new(len: usize) -> Self {
+            Self {
+                forks: Mutex::new(vec![0; len]),
+            }
+        }
+
+        fn fork_of(hash: &[u8]) -> (u64, u8) {
+            let mut height = [0; 8];
+            height.copy_from_slice(&hash[..8]);
+            (u64::from_le_bytes(height), hash[8])
+        }
+    }
+
+    #[async_trait]
+    impl ChainClient for MockChain {
+        async fn get_block_count(&self) -> Result<u64, NodeError> {
+            Ok(self.forks.lock().unwrap().len() as u64 - 1)
+        }
+
+        async fn get_block_hash(&self, height: u64) -> Result<Vec<u8>, NodeError> {
+            let fork = self.forks.lock().unwrap()[height as usize];
+            Ok(block_hash(height, fork))
+        }
+
+        async fn get_block_header(&self, _block_hash: &[u8]) -> Result<BlockHeader, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_block(&self, _block_hash: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_block(&self, hash: &[u8]) -> Result<Block, NodeError> {
+            let (height, fork) = Self::fork_of(hash);
+            let prev_fork = if height == 0 {
+                0
+            } else {
+                // Blocks on a fork build upon the fork, or the main chain at the fork point
+                let forks = self.forks.lock().unwrap();
+                if forks[height as usize - 1] == fork {
+                    fork
+                } else {
+                    0
+                }
+            };
+            let mut prev_block_hash = [0; 32];
+            let prev_hash = block_hash(height.saturating_sub(1), prev_fork);
+            prev_block_hash.copy_from_slice(&prev_hash);
+            prev_block_hash.reverse();
+            Ok(Block {
+                header: BlockHeader {
+                    prev_block_hash,
+                    height: height as i32,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        }
+    }
+
+    fn heights(events: &[ChainEvent]) -> Vec<(bool, i32)> {
+        events
+            .iter()
+            .map(|event| match event {
+                ChainEvent::Connected(block) => (true, block.header.height),
+                ChainEvent::Disconnected(block) => (false, block.header.height),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn connect_and_reorg() {
+        let mut follower = ChainFollower::new(MockChain::new(3), 10);
+        assert!(follower.poll().await.unwrap().is_empty());
+        assert_eq!(follower.tip().unwrap().0, 2);
+
+        // Extend the chain
+        follower.client.forks.lock().unwrap().extend(&[0, 0]);
+        let events = follower.poll().await.unwrap();
+        assert_eq!(heights(&events), vec![(true, 3), (true, 4)]);
+
+        // Replace the last two blocks and extend
+        {
+            let mut forks = follower.client.forks.lock().unwrap();
+            forks.truncate(3);
+            forks.extend(&[1, 1, 1]);
+        }
+        let events = follower.poll().await.unwrap();
+        assert_eq!(
+            heights(&events),
+            vec![(false, 4), (false, 3), (true, 3), (true, 4), (true, 5)]
+        );
+        assert_eq!(follower.tip().unwrap(), (5, block_hash(5, 1).as_slice()));
+    }
+
+    #[tokio::test]
+    async fn reorg_too_deep() {
+        let mut follower = ChainFollower::new(MockChain::new(3), 2);
+        follower.poll().await.unwrap();
+        follower.client.forks.lock().unwrap().push(0);
+        follower.poll().await.unwrap();
+
+        *follower.client.forks.lock().unwrap() = vec![1; 4];
+        assert!(matches!(
+            follower.poll().await,
+            Err(FollowerError::ReorgTooDeep(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn genesis() {
+        let mut follower = ChainFollower::new(MockChain::new(1), 10);
+        follower.poll().await.unwrap();
+        assert_eq!(follower.tip().unwrap(), (0, block_hash(0, 0).as_slice()));
+
+        follower.client.forks.lock().unwrap().push(0);
+        let events = follower.poll().await.unwrap();
+        assert_eq!(heights(&events), vec![(true, 1)]);
+
+        // Replacing the genesis block is reported rather than underflowing the height
+        *follower.client.forks.lock().unwrap() = vec![1];
+        assert!(matches!(
+            follower.poll().await,
+            Err(FollowerError::GenesisMismatch)
+        ));
+        assert!(follower.tip().is_none());
+    }
+}
@@ -0,0 +1,275 @@
+//! This module contains the [`RegtestNode`] harness for writing end-to-end tests against a real
+//! regtest bitcoind or lotusd.
+//!
+//! A node is either launched as a child process, in a fresh data directory which is removed
+//! when the harness is dropped, or attached to by RPC address. [`RegtestNode::from_env`] allows
+//! test suites to skip gracefully when no node is available:
+//!
+//! - `CASHWEB_REGTEST_RPC`, with `CASHWEB_REGTEST_USER` and `CASHWEB_REGTEST_PASSWORD`, attaches
+//!   to a running node,
+//! - otherwise `CASHWEB_REGTEST_BIN` launches the binary at the given path.
+
+use std::{
+    env, fs, io,
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use thiserror::Error;
+use tokio::time::sleep;
+
+use crate::{
+    call, chain::ChainClient, wallet::WalletClient, BitcoinClient, BitcoinClientHTTP, NodeError,
+};
+
+/// Environment variable holding the RPC address of a running regtest node.
+pub const RPC_ENV: &str = "CASHWEB_REGTEST_RPC";
+
+/// Environment variable holding the RPC username of a running regtest node.
+pub const USER_ENV: &str = "CASHWEB_REGTEST_USER";
+
+/// Environment variable holding the RPC password of a running regtest node.
+pub const PASSWORD_ENV: &str = "CASHWEB_REGTEST_PASSWORD";
+
+/// Environment variable holding the path of a node binary to launch.
+pub const BIN_ENV: &str = "CASHWEB_REGTEST_BIN";
+
+/// Number of confirmations before a coinbase output may be spent.
+pub const COINBASE_MATURITY: u64 = 100;
+
+/// Default duration to wait for a launched node to accept RPCs.
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RPC_USER: &str = "cashweb";
+const RPC_PASSWORD: &str = "cashweb";
+
+/// Error associated with the [`RegtestNode`] harness.
+#[derive(Debug, Error)]
+pub enum HarnessError {
+    /// Failed to prepare the data directory, allocate ports or spawn the node.
+    #[error("failed to launch node: {0}")]
+    Launch(io::Error),
+    /// The node did not accept RPCs within the startup timeout.
+    #[error("node failed to start within {0:?}")]
+    StartupTimeout(Duration),
+    /// An RPC failed.
+    #[error(transparent)]
+    Node(#[from] NodeError),
+}
+
+/// Configuration for launching a regtest node.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    binary: PathBuf,
+    args: Vec<String>,
+    startup_timeout: Duration,
+}
+
+impl NodeConfig {
+    /// Create a configuration launching the node binary, such as `bitcoind` or `lotusd`.
+    pub fn new<P: Into<PathBuf>>(binary: P) -> Self {
+        Self {
+            binary: binary.into(),
+            args: Vec::new(),
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+        }
+    }
+
+    /// Pass an additional argument to the node, such as `-zmqpubsequence=tcp://127.0.0.1:28332`.
+    pub fn with_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Set the duration to wait for the node to accept RPCs.
+    pub fn with_startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = startup_timeout;
+        self
+    }
+
+    /// The arguments used to launch the node.
+    fn launch_args(&self, data_dir: &str, rpc_port: u16, p2p_port: u16) -> Vec<String> {
+        let mut args = vec![
+            "-regtest".to_string(),
+            "-server".to_string(),
+            "-listen=0".to_string(),
+            "-txindex".to_string(),
+            "-fallbackfee=0.0001".to_string(),
+            format!("-datadir={}", data_dir),
+            format!("-rpcport={}", rpc_port),
+            format!("-port={}", p2p_port),
+            format!("-rpcuser={}", RPC_USER),
+            format!("-rpcpassword={}", RPC_PASSWORD),
+        ];
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+/// Allocate an unused local port.
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// A launched, or attached, regtest node.
+///
+/// A launched node is killed, and its data directory removed, when the harness is dropped.
+#[derive(Debug)]
+pub struct RegtestNode {
+    client: BitcoinClientHTTP,
+    rpc_address: String,
+    process: Option<(Child, PathBuf)>,
+}
+
+impl RegtestNode {
+    /// Attach to a running regtest node.
+    pub fn attach(rpc_address: String, username: String, password: String) -> Self {
+        Self {
+            client: BitcoinClientHTTP::new(rpc_address.clone(), username, password),
+            rpc_address,
+            process: None,
+        }
+    }
+
+    /// Launch a regtest node, waiting until it accepts RPCs.
+    pub async fn launch(config: NodeConfig) -> Result<Self, HarnessError> {
+        let rpc_port = free_port().map_err(HarnessError::Launch)?;
+        let p2p_port = free_port().map_err(HarnessError::Launch)?;
+        let data_dir = env::temp_dir().join(format!(
+            "cashweb-regtest-{}-{}",
+            std::process::id(),
+            rpc_port
+        ));
+        fs::create_dir_all(&data_dir).map_err(HarnessError::Launch)?;
+
+        let child = Command::new(&config.binary)
+            .args(config.launch_args(&data_dir.to_string_lossy(), rpc_port, p2p_port))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(HarnessError::Launch)?;
+        let rpc_address = format!("http://127.0.0.1:{}", rpc_port);
+        let node = Self {
+            client: BitcoinClientHTTP::new(
+                rpc_address.clone(),
+                RPC_USER.to_string(),
+                RPC_PASSWORD.to_string(),
+            ),
+            rpc_address,
+            process: Some((child, data_dir)),
+        };
+
+        // Poll until the RPC server is warmed up
+        let start = Instant::now();
+        while node.client.get_block_count().await.is_err() {
+            if start.elapsed() > config.startup_timeout {
+                return Err(HarnessError::StartupTimeout(config.startup_timeout));
+            }
+            sleep(Duration::from_millis(250)).await;
+        }
+
+        // Create a wallet, ignoring the error raised by nodes which load one by default
+        let _: Result<Value, _> = call(
+            &node.client.0,
+            "createwallet",
+            vec![Value::String("cashweb".to_string())],
+        )
+        .await;
+        Ok(node)
+    }
+
+    /// Attach to, or launch, a node as specified by the environment.
+    ///
+    /// Returns `None` if neither [`RPC_ENV`] nor [`BIN_ENV`] is set, allowing tests to be skipped.
+    pub async fn from_env() -> Option<Result<Self, HarnessError>> {
+        if let Ok(rpc_address) = env::var(RPC_ENV) {
+            let username = env::var(USER_ENV).unwrap_or_default();
+            let password = env::var(PASSWORD_ENV).unwrap_or_default();
+            return Some(Ok(Self::attach(rpc_address, username, password)));
+        }
+        let binary = env::var_os(BIN_ENV)?;
+        Some(Self::launch(NodeConfig::new(binary)).await)
+    }
+
+    /// The RPC address of the node.
+    pub fn rpc_address(&self) -> &str {
+        &self.rpc_address
+    }
+
+    /// A client for the node, usable as a [`BitcoinClient`] broadcaster, a [`ChainClient`]
+    /// fetcher and a [`WalletClient`].
+    pub fn client(&self) -> BitcoinClientHTTP {
+        self.client.clone()
+    }
+
+    /// Mine blocks paying the address, returning the block hashes.
+    pub async fn mine_to(&self, blocks: u64, address: &str) -> Result<Vec<String>, HarnessError> {
+        let params = vec![Value::from(blocks), Value::String(address.to_string())];
+        Ok(call(&self.client.0, "generatetoaddress", params).await?)
+    }
+
+    /// Mine blocks paying the node wallet, returning the block hashes.
+    pub async fn mine(&self, blocks: u64) -> Result<Vec<String>, HarnessError> {
+        let address = self.client.get_new_addr().await?;
+        self.mine_to(blocks, &address).await
+    }
+
+    /// Mine enough blocks for the node wallet to hold a spendable coinbase output.
+    pub async fn mature(&self) -> Result<(), HarnessError> {
+        self.mine(COINBASE_MATURITY + 1).await?;
+        Ok(())
+    }
+
+    /// Pay the amount, in satoshis, from the node wallet to the address and mine a block
+    /// confirming it, returning the transaction ID.
+    pub async fn fund(&self, address: &str, satoshis: u64) -> Result<String, HarnessError> {
+        let tx_id = self.client.send_to_address(address, satoshis).await?;
+        self.mine(1).await?;
+        Ok(tx_id)
+    }
+}
+
+impl Drop for RegtestNode {
+    fn drop(&mut self) {
+        if let Some((child, data_dir)) = &mut self.process {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = fs::remove_dir_all(data_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launch_args() {
+        let config = NodeConfig::new("lotusd").with_arg("-zmqpubsequence=tcp://127.0.0.1:28332");
+        let args = config.launch_args("/tmp/node", 18443, 18444);
+        assert!(args.contains(&"-regtest".to_string()));
+        assert!(args.contains(&"-rpcport=18443".to_string()));
+        assert!(args.contains(&"-datadir=/tmp/node".to_string()));
+        assert_eq!(
+            args.last().unwrap(),
+            "-zmqpubsequence=tcp://127.0.0.1:28332"
+        );
+    }
+
+    #[tokio::test]
+    async fn regtest() {
+        // Skipped unless a node is configured
+        let node = match RegtestNode::from_env().await {
+            Some(node) => node.unwrap(),
+            None => return,
+        };
+        node.mature().await.unwrap();
+        let height = node.client().get_block_count().await.unwrap();
+        let address = node.client().get_new_addr().await.unwrap();
+        node.fund(&address, 100_000_000).await.unwrap();
+        assert_eq!(node.client().get_block_count().await.unwrap(), height + 1);
+    }
+}
@@ -0,0 +1,469 @@
+//! This module contains the [`Journal`] trait, a write-ahead log of broadcast transactions, and the
+//! [`JournaledClient`] which uses it to ensure transactions are broadcast despite crashes.
+//!
+//! Raw transactions are recorded before submission and acknowledged once bitcoind has responded.
+//! On startup, [`JournaledClient::replay`] resubmits any transactions which were never acknowledged,
+//! using the [`FeePolicy`] they were originally broadcast with.
+//!
+//! Journal operations block until written to disk, so [`JournaledClient`] runs them on tokio's
+//! blocking thread pool.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::task::{self, JoinError};
+use tracing::warn;
+
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// Error associated with a [`Journal`].
+#[derive(Debug, Error)]
+pub enum JournalError {
+    /// Failed to read from or write to the journal.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The journal contained a malformed entry.
+    #[error("malformed entry on line {0}")]
+    Corrupt(usize),
+    /// The blocking task accessing the journal failed.
+    #[error("journal task failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+/// A raw transaction recorded in a [`Journal`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    /// The entry ID.
+    pub id: u64,
+    /// The raw transaction.
+    pub raw_tx: Vec<u8>,
+    /// The [`FeePolicy`] the transaction was broadcast with.
+    pub fee_policy: FeePolicy,
+}
+
+/// A write-ahead log of raw transactions.
+///
+/// Operations may block on disk I/O and should not be called directly from async tasks.
+pub trait Journal {
+    /// Durably record a raw transaction and its [`FeePolicy`], returning its entry ID.
+    fn record(&self, raw_tx: &[u8], fee_policy: FeePolicy) -> Result<u64, JournalError>;
+
+    /// Durably mark an entry as acknowledged.
+    fn acknowledge(&self, id: u64) -> Result<(), JournalError>;
+
+    /// Get the entries which have not been acknowledged, in the order they were recorded.
+    fn pending(&self) -> Result<Vec<JournalEntry>, JournalError>;
+}
+
+/// Encode a [`FeePolicy`] as a single journal field.
+fn encode_fee_policy(fee_policy: FeePolicy) -> String {
+    match fee_policy {
+        FeePolicy::Default => "default".to_string(),
+        FeePolicy::AllowHighFees(allow) => format!("allowhighfees={}", allow),
+        FeePolicy::MaxFeeRate(rate) => format!("maxfeerate={}", rate),
+    }
+}
+
+/// Decode a [`FeePolicy`] from a journal field.
+fn decode_fee_policy(field: &str) -> Option<FeePolicy> {
+    if field == "default" {
+        return Some(FeePolicy::Default);
+    }
+    let mut parts = field.splitn(2, '=');
+    match (parts.next()?, parts.next()?) {
+        ("allowhighfees", allow) => allow.parse().ok().map(FeePolicy::AllowHighFees),
+        ("maxfeerate", rate) => rate.parse().ok().map(FeePolicy::MaxFeeRate),
+        _ => None,
+    }
+}
+
+/// Write a record entry to the journal.
+fn write_record(file: &mut File, id: u64, entry: &(Vec<u8>, FeePolicy)) -> io::Result<()> {
+    writeln!(
+        file,
+        "R {} {} {}",
+        id,
+        encode_fee_policy(entry.1),
+        hex::encode(&entry.0)
+    )
+}
+
+#[derive(Debug)]
+struct FileJournalState {
+    file: File,
+    next_id: u64,
+    pending: BTreeMap<u64, (Vec<u8>, FeePolicy)>,
+}
+
+/// A [`Journal`] backed by an append-only file.
+///
+/// Each entry is flushed to disk before the call returns. The file is compacted when opened, which
+/// discards a final entry left incomplete by a crash mid-write.
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+    state: Mutex<FileJournalState>,
+}
+
+impl FileJournal {
+    /// Open the journal at the given path, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+
+        // Rebuild the pending entries
+        let mut next_id = 0;
+        let mut pending = BTreeMap::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+
+                // Every complete entry is newline terminated, anything after the last newline is
+                // the remains of an interrupted write
+                let complete_len = contents.rfind('\n').map(|index| index + 1).unwrap_or(0);
+                if complete_len < contents.len() {
+                    warn!(
+                        message = "discarding incomplete journal entry",
+                        bytes = contents.len() - complete_len
+                    );
+                }
+
+                for (index, line) in contents[..complete_len].lines().enumerate() {
+                    let line_number = index + 1;
+                    let mut parts = line.split(' ');
+                    let kind = parts.next();
+                    let id: u64 = parts
+                        .next()
+                        .and_then(|id| id.parse().ok())
+                        .ok_or(JournalError::Corrupt(line_number))?;
+                    match (kind, parts.next(), parts.next(), parts.next()) {
+                        (Some("R"), Some(fee_policy), Some(raw_tx_hex), None) => {
+                            let fee_policy = decode_fee_policy(fee_policy)
+                                .ok_or(JournalError::Corrupt(line_number))?;
+                            let raw_tx = hex::decode(raw_tx_hex)
+                                .map_err(|_| JournalError::Corrupt(line_number))?;
+                            pending.insert(id, (raw_tx, fee_policy));
+                        }
+                        (Some("A"), None, None, None) => {
+                            pending.remove(&id);
+                        }
+                        _ => return Err(JournalError::Corrupt(line_number)),
+                    }
+                    next_id = next_id.max(id + 1);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        // Compact the journal by rewriting only the pending entries
+        let compact_path = path.with_extension("compact");
+        let mut compact_file = File::create(&compact_path)?;
+        for (id, entry) in &pending {
+            write_record(&mut compact_file, *id, entry)?;
+        }
+        compact_file.sync_all()?;
+        fs::rename(&compact_path, &path)?;
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(FileJournalState {
+                file,
+                next_id,
+                pending,
+            }),
+        })
+    }
+
+    /// Get the path of the journal.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Journal for FileJournal {
+    fn record(&self, raw_tx: &[u8], fee_policy: FeePolicy) -> Result<u64, JournalError> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        let entry = (raw_tx.to_vec(), fee_policy);
+        write_record(&mut state.file, id, &entry)?;
+        state.file.sync_data()?;
+        state.next_id += 1;
+        state.pending.insert(id, entry);
+        Ok(id)
+    }
+
+    fn acknowledge(&self, id: u64) -> Result<(), JournalError> {
+        let mut state = self.state.lock().unwrap();
+        writeln!(state.file, "A {}", id)?;
+        state.file.sync_data()?;
+        state.pending.remove(&id);
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<JournalEntry>, JournalError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .pending
+            .iter()
+            .map(|(id, (raw_tx, fee_policy))| JournalEntry {
+                id: *id,
+                raw_tx: raw_tx.clone(),
+                fee_policy: *fee_policy,
+            })
+            .collect())
+    }
+}
+
+/// A [`BitcoinClient`] which records broadcast transactions in a [`Journal`].
+///
+/// An entry is acknowledged once bitcoind has responded, whether it accepted or rejected the
+/// transaction. Entries whose broadcast failed due to connection errors or timeouts remain pending.
+#[derive(Debug)]
+pub struct JournaledClient<C, J> {
+    inner_client: C,
+    journal: Arc<J>,
+}
+
+impl<C, J> JournaledClient<C, J> {
+    /// Wrap a [`BitcoinClient`] with a [`Journal`].
+    pub fn new(inner_client: C, journal: J) -> Self {
+        Self {
+            inner_client,
+            journal: Arc::new(journal),
+        }
+    }
+
+    /// Get the [`Journal`].
+    pub fn journal(&self) -> &J {
+        &self.journal
+    }
+
+    /// Converts the journaled client into the underlying client and journal.
+    pub fn into_inner(self) -> (C, Arc<J>) {
+        (self.inner_client, self.journal)
+    }
+}
+
+impl<C, J> JournaledClient<C, J>
+where
+    C: BitcoinClient + Send + Sync,
+    J: Journal + Send + Sync + 'static,
+{
+    /// Run a journal operation on the blocking thread pool.
+    async fn with_journal<F, T>(&self, operation: F) -> Result<T, JournalError>
+    where
+        F: FnOnce(&J) -> Result<T, JournalError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let journal = self.journal.clone();
+        task::spawn_blocking(move || operation(&journal)).await?
+    }
+
+    async fn broadcast(
+        &self,
+        id: u64,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let result = self
+            .inner_client
+            .send_tx_with_fee_policy(raw_tx, fee_policy)
+            .await;
+        match &result {
+            Err(NodeError::RpcConnectError(_)) | Err(NodeError::Timeout) => (),
+            _ => {
+                self.with_journal(move |journal| journal.acknowledge(id))
+                    .await?
+            }
+        }
+        result
+    }
+
+    /// Resubmit the transactions which were never acknowledged, returning the result of each
+    /// broadcast alongside its entry ID.
+    pub async fn replay(&self) -> Result<Vec<(u64, Result<String, NodeError>)>, JournalError> {
+        let mut results = Vec::new();
+        for entry in self.with_journal(|journal| journal.pending()).await? {
+            let result = self
+                .broadcast(entry.id, &entry.raw_tx, entry.fee_policy)
+                .await;
+            results.push((entry.id, result));
+        }
+        Ok(results)
+    }
+}
+
+#[async_trait]
+impl<C, J> BitcoinClient for JournaledClient<C, J>
+where
+    C: BitcoinClient + Send + Sync,
+    J: Journal + Send + Sync + 'static,
+{
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let owned_raw_tx = raw_tx.to_vec();
+        let id = self
+            .with_journal(move |journal| journal.record(&owned_raw_tx, fee_policy))
+            .await?;
+        self.broadcast(id, raw_tx, fee_policy).await
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.inner_client.get_new_addr().await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.inner_client.get_raw_transaction(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entry(id: u64, raw_tx: &[u8], fee_policy: FeePolicy) -> JournalEntry {
+        JournalEntry {
+            id,
+            raw_tx: raw_tx.to_vec(),
+            fee_policy,
+        }
+    }
+
+    #[test]
+    fn file_journal_reopen() {
+        let path = temp_path("journal-test");
+
+        let journal = FileJournal::open(&path).unwrap();
+        let first = journal.record(&[1, 2, 3], FeePolicy::Default).unwrap();
+        let second = journal
+            .record(&[4, 5, 6], FeePolicy::AllowHighFees(true))
+            .unwrap();
+        journal.acknowledge(first).unwrap();
+        drop(journal);
+
+        let journal = FileJournal::open(&path).unwrap();
+        assert_eq!(
+            journal.pending().unwrap(),
+            vec![entry(second, &[4, 5, 6], FeePolicy::AllowHighFees(true))]
+        );
+        let third = journal.record(&[7], FeePolicy::MaxFeeRate(0.25)).unwrap();
+        assert!(third > second);
+        drop(journal);
+
+        let journal = FileJournal::open(&path).unwrap();
+        assert_eq!(
+            journal.pending().unwrap(),
+            vec![
+                entry(second, &[4, 5, 6], FeePolicy::AllowHighFees(true)),
+                entry(third, &[7], FeePolicy::MaxFeeRate(0.25))
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[derive(Default)]
+    struct MockClient {
+        sent: Mutex<Vec<(Vec<u8>, FeePolicy)>>,
+    }
+
+    #[async_trait]
+    impl BitcoinClient for MockClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            raw_tx: &[u8],
+            fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((raw_tx.to_vec(), fee_policy));
+            Ok(String::new())
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_fee_policy() {
+        let path = temp_path("journal-replay-test");
+
+        // Entries left pending by a crash
+        fs::write(&path, "R 0 allowhighfees=true 0102\nR 1 default 03\n").unwrap();
+
+        let client = JournaledClient::new(MockClient::default(), FileJournal::open(&path).unwrap());
+        let results = client.replay().await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            *client.inner_client.sent.lock().unwrap(),
+            vec![
+                (vec![1, 2], FeePolicy::AllowHighFees(true)),
+                (vec![3], FeePolicy::Default)
+            ]
+        );
+        assert!(client.journal().pending().unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_journal_torn_tail() {
+        let path = temp_path("journal-torn-test");
+
+        let journal = FileJournal::open(&path).unwrap();
+        let first = journal.record(&[1, 2, 3], FeePolicy::Default).unwrap();
+        drop(journal);
+
+        // Simulate a crash part way through writing an entry
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "R {} allowhighf", first + 1).unwrap();
+        drop(file);
+
+        let journal = FileJournal::open(&path).unwrap();
+        assert_eq!(
+            journal.pending().unwrap(),
+            vec![entry(first, &[1, 2, 3], FeePolicy::Default)]
+        );
+        let second = journal.record(&[4], FeePolicy::Default).unwrap();
+        assert_eq!(second, first + 1);
+        drop(journal);
+
+        // The incomplete entry was compacted away
+        let journal = FileJournal::open(&path).unwrap();
+        assert_eq!(journal.pending().unwrap().len(), 2);
+        drop(journal);
+
+        // Malformed complete entries are still rejected
+        fs::write(&path, "R 0 default zz\n").unwrap();
+        assert!(matches!(
+            FileJournal::open(&path),
+            Err(JournalError::Corrupt(1))
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
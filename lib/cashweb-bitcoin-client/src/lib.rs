@@ -7,16 +7,48 @@
 
 //! `cashweb-bitcoin-client` is a library providing a [`BitcoinClient`] with
 //! basic asynchronous methods for interacting with bitcoind.
+
+pub mod chain;
+pub mod confirm;
+pub mod events;
+pub mod follower;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod journal;
+pub mod limit;
+pub mod mempool;
+pub mod metrics;
+pub mod mining;
+#[cfg(test)]
+mod mock_rpc;
+pub mod profile;
+pub mod spv;
+pub mod utxo;
+#[cfg(feature = "wallet")]
+pub mod wallet;
+
+use std::{ops::Deref, time::Duration};
+
 use async_trait::async_trait;
+use cashweb_bitcoin::{
+    block::DecodeError as BlockDecodeError, transaction::Transaction, Encodable,
+};
 use hex::FromHexError;
 use hyper::client::{connect::Connect, HttpConnector};
 use hyper_tls::HttpsConnector;
+use journal::JournalError;
 use json_rpc::{
     clients::http::Client as JsonClient,
+    objects::{Request, Response},
     prelude::{JsonError, RequestFactory, RpcError},
 };
+use native_tls::Certificate;
+use profile::AmountError;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use thiserror::Error;
+use tokio::time::timeout;
+use tokio_native_tls::TlsConnector;
 
 /// Standard HTTP client.
 pub type HttpClient = hyper::Client<HttpConnector>;
@@ -36,65 +68,274 @@ pub enum NodeError {
     /// Failed to deserialize response JSON.
     #[error(transparent)]
     Json(JsonError),
+    /// The request timeout elapsed.
+    #[error("request timed out")]
+    Timeout,
     /// The response JSON was empty.
     #[error("empty response")]
     EmptyResponse,
     /// Failed to decode hexidecimal response.
     #[error(transparent)]
     HexDecode(#[from] FromHexError),
+    /// Failed to decode a block.
+    #[error("block decode: {0}")]
+    BlockDecode(#[from] BlockDecodeError),
+    /// Failed to record the broadcast in the journal.
+    #[error("journal: {0}")]
+    Journal(#[from] JournalError),
+    /// Failed to convert an amount to or from the coin units used by the RPC.
+    #[error("amount: {0}")]
+    Amount(#[from] AmountError),
+}
+
+/// Fee policy passed as the second argument of `sendrawtransaction`.
+///
+/// Older nodes accept a boolean `allowhighfees` while newer nodes accept a numeric `maxfeerate`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeePolicy {
+    /// Omit the second argument and defer to the node's default policy.
+    Default,
+    /// Pass the `allowhighfees` boolean.
+    AllowHighFees(bool),
+    /// Pass the `maxfeerate`, given in coins per kilobyte. A value of `0` disables the check.
+    MaxFeeRate(f64),
+}
+
+impl FeePolicy {
+    /// Convert the policy into the `sendrawtransaction` parameter, if any.
+    pub fn to_param(&self) -> Option<Value> {
+        match self {
+            Self::Default => None,
+            Self::AllowHighFees(allow) => Some(Value::Bool(*allow)),
+            Self::MaxFeeRate(rate) => Some(Value::from(*rate)),
+        }
+    }
 }
 
 /// Bitcoin Client function traits
 #[async_trait]
 pub trait BitcoinClient {
     /// Send a raw transaction to bitcoind
-    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError>;
+    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
+        self.send_tx_with_fee_policy(raw_tx, FeePolicy::Default)
+            .await
+    }
+    /// Send a raw transaction to bitcoind using a specific [`FeePolicy`]
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError>;
+    /// Serialize and send a [`Transaction`] to bitcoind.
+    ///
+    /// Returns the locally computed transaction ID, in the byte order used by the RPC, for correlation.
+    async fn send_transaction(
+        &self,
+        transaction: &Transaction,
+        fee_policy: FeePolicy,
+    ) -> Result<[u8; 32], NodeError> {
+        let mut raw_tx = Vec::with_capacity(transaction.encoded_len());
+        transaction.encode_raw(&mut raw_tx);
+        self.send_tx_with_fee_policy(&raw_tx, fee_policy).await?;
+        Ok(transaction.transaction_id_rev())
+    }
+    /// Send a batch of independent raw transactions to bitcoind, in order.
+    ///
+    /// A failed transaction does not stop the remainder of the batch from being sent. The
+    /// results are returned in the order of the batch.
+    async fn send_txs(&self, raw_txs: &[Vec<u8>]) -> Vec<Result<String, NodeError>> {
+        let mut results = Vec::with_capacity(raw_txs.len());
+        for raw_tx in raw_txs {
+            results.push(self.send_tx(raw_tx).await);
+        }
+        results
+    }
     /// Get a new receiving address from the bitcoin daemon
     async fn get_new_addr(&self) -> Result<String, NodeError>;
     /// Get a raw bitcoin transaction by txid
     async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError>;
 }
 
+/// Timeouts applied to the JSON-RPC transport.
+///
+/// Without timeouts, requests to an unresponsive bitcoind never complete.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Maximum duration allowed to establish a connection.
+    pub connect: Option<Duration>,
+    /// Maximum duration allowed for a request to complete, including connection.
+    pub request: Option<Duration>,
+}
+
+impl Timeouts {
+    fn http_connector(&self) -> HttpConnector {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(self.connect);
+        connector
+    }
+}
+
 /// Basic Bitcoin JSON-RPC client.
 #[derive(Clone, Debug)]
-pub struct BitcoinClientHTTP(JsonClient<hyper::Client<HttpConnector>>);
+pub struct BitcoinClientHTTP(BitcoinJsonClient<HttpConnector>);
 
 impl BitcoinClientHTTP {
     /// Create a new HTTP [`BitcoinClient`].
     pub fn new(endpoint: String, username: String, password: String) -> Self {
-        BitcoinClientHTTP(JsonClient::new(endpoint, Some(username), Some(password)))
+        BitcoinClientHTTP(BitcoinJsonClient {
+            inner: JsonClient::new(endpoint, Some(username), Some(password)),
+            request_timeout: None,
+        })
+    }
+
+    /// Create a new HTTP [`BitcoinClient`] with [`Timeouts`].
+    pub fn with_timeouts(
+        endpoint: String,
+        username: String,
+        password: String,
+        timeouts: Timeouts,
+    ) -> Self {
+        let client = hyper::Client::builder().build(timeouts.http_connector());
+        BitcoinClientHTTP(BitcoinJsonClient {
+            inner: JsonClient::from_service(client, endpoint, Some(username), Some(password)),
+            request_timeout: timeouts.request,
+        })
     }
 }
 
 /// Basic HTTPS Bitcoin JSON-RPC client.
 #[derive(Clone, Debug)]
-pub struct BitcoinClientTLS(JsonClient<hyper::Client<HttpsConnector<HttpConnector>>>);
+pub struct BitcoinClientTLS(BitcoinJsonClient<HttpsConnector<HttpConnector>>);
 
 impl BitcoinClientTLS {
     /// Create a new HTTPS [`BitcoinClient`].
     pub fn new(endpoint: String, username: String, password: String) -> Self {
-        BitcoinClientTLS(JsonClient::new_tls(
-            endpoint,
-            Some(username),
-            Some(password),
-        ))
+        BitcoinClientTLS(BitcoinJsonClient {
+            inner: JsonClient::new_tls(endpoint, Some(username), Some(password)),
+            request_timeout: None,
+        })
+    }
+
+    /// Create a new HTTPS [`BitcoinClient`] with [`Timeouts`].
+    pub fn with_timeouts(
+        endpoint: String,
+        username: String,
+        password: String,
+        timeouts: Timeouts,
+    ) -> Self {
+        let mut http_connector = timeouts.http_connector();
+        http_connector.enforce_http(false);
+        let client =
+            hyper::Client::builder().build(HttpsConnector::new_with_connector(http_connector));
+        BitcoinClientTLS(BitcoinJsonClient {
+            inner: JsonClient::from_service(client, endpoint, Some(username), Some(password)),
+            request_timeout: timeouts.request,
+        })
+    }
+
+    /// Create a new HTTPS [`BitcoinClient`] with [`Timeouts`] and [`TlsOptions`].
+    ///
+    /// Fails if a root certificate is malformed or the TLS backend cannot be initialized.
+    pub fn with_tls_options(
+        endpoint: String,
+        username: String,
+        password: String,
+        timeouts: Timeouts,
+        tls_options: &TlsOptions,
+    ) -> Result<Self, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for pem in &tls_options.root_certificates {
+            builder.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        builder.danger_accept_invalid_certs(tls_options.danger_accept_invalid_certs);
+        let tls_connector = TlsConnector::from(builder.build()?);
+
+        let mut http_connector = timeouts.http_connector();
+        http_connector.enforce_http(false);
+        let client =
+            hyper::Client::builder().build(HttpsConnector::from((http_connector, tls_connector)));
+        Ok(BitcoinClientTLS(BitcoinJsonClient {
+            inner: JsonClient::from_service(client, endpoint, Some(username), Some(password)),
+            request_timeout: timeouts.request,
+        }))
+    }
+}
+
+/// TLS options for connecting to bitcoind, typically when it is behind a reverse proxy using a
+/// self-signed certificate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// PEM encoded root certificates trusted in addition to the system roots.
+    pub root_certificates: Vec<Vec<u8>>,
+    /// Accept invalid certificates, including expired certificates and those with mismatched
+    /// hostnames.
+    ///
+    /// **Warning**: This leaves the connection open to man-in-the-middle attacks. Prefer
+    /// providing the certificate via `root_certificates`.
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// JSON-RPC client with an optional request timeout.
+#[derive(Clone, Debug)]
+struct BitcoinJsonClient<C> {
+    inner: JsonClient<hyper::Client<C>>,
+    request_timeout: Option<Duration>,
+}
+
+impl<C> Deref for BitcoinJsonClient<C> {
+    type Target = JsonClient<hyper::Client<C>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<C: Connectable> BitcoinJsonClient<C> {
+    /// Sends a request, failing if the request timeout elapses.
+    async fn send(&self, request: Request) -> Result<Response, NodeError> {
+        let response = self.inner.send(request);
+        let result = match self.request_timeout {
+            Some(duration) => timeout(duration, response)
+                .await
+                .map_err(|_| NodeError::Timeout)?,
+            None => response.await,
+        };
+        result.map_err(|err| NodeError::RpcConnectError(err.to_string()))
     }
 }
 
-type BitcoinJsonClient<C> = JsonClient<hyper::Client<C>>;
 trait Connectable: Connect + Clone + Send + Sync + 'static {}
 impl<T: Connect + Clone + Send + Sync + 'static> Connectable for T {}
 
+/// Calls a method and deserializes the result.
+async fn call<C: Connectable, T: DeserializeOwned>(
+    client: &BitcoinJsonClient<C>,
+    method: &str,
+    params: Vec<Value>,
+) -> Result<T, NodeError> {
+    let request = client
+        .build_request()
+        .method(method)
+        .params(params)
+        .finish()
+        .unwrap();
+    let response = client.send(request).await?;
+    if response.is_error() {
+        return Err(NodeError::Rpc(response.error().unwrap()));
+    }
+    response
+        .into_result()
+        .ok_or(NodeError::EmptyResponse)?
+        .map_err(NodeError::Json)
+}
+
 async fn get_new_addr<C: Connectable>(client: &BitcoinJsonClient<C>) -> Result<String, NodeError> {
     let request = client
         .build_request()
         .method("getnewaddress")
         .finish()
         .unwrap();
-    let response = client
-        .send(request)
-        .await
-        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    let response = client.send(request).await?;
     if response.is_error() {
         return Err(NodeError::Rpc(response.error().unwrap()));
     }
@@ -107,17 +348,19 @@ async fn get_new_addr<C: Connectable>(client: &BitcoinJsonClient<C>) -> Result<S
 async fn send_tx<C: Connectable>(
     client: &BitcoinJsonClient<C>,
     raw_tx: &[u8],
+    fee_policy: FeePolicy,
 ) -> Result<String, NodeError> {
+    let mut params = vec![Value::String(hex::encode(raw_tx))];
+    if let Some(param) = fee_policy.to_param() {
+        params.push(param);
+    }
     let request = client
         .build_request()
         .method("sendrawtransaction")
-        .params(vec![Value::String(hex::encode(raw_tx))])
+        .params(params)
         .finish()
         .unwrap();
-    let response = client
-        .send(request)
-        .await
-        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    let response = client.send(request).await?;
     if response.is_error() {
         let err = response.error().unwrap();
         return Err(NodeError::Rpc(err));
@@ -139,10 +382,7 @@ async fn get_raw_transaction<C: Connectable>(
         .params(vec![Value::String(hex::encode(tx_id))])
         .finish()
         .unwrap();
-    let response = client
-        .send(request)
-        .await
-        .map_err(|err| NodeError::RpcConnectError(err.to_string()))?;
+    let response = client.send(request).await?;
     if response.is_error() {
         return Err(NodeError::Rpc(response.error().unwrap()));
     }
@@ -161,8 +401,12 @@ impl BitcoinClient for BitcoinClientTLS {
     }
 
     /// Calls the `sendrawtransaction` method.
-    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
-        send_tx(&self.0, raw_tx).await
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        send_tx(&self.0, raw_tx, fee_policy).await
     }
 
     /// Calls the `getrawtransaction` method.
@@ -179,8 +423,12 @@ impl BitcoinClient for BitcoinClientHTTP {
     }
 
     /// Calls the `sendrawtransaction` method.
-    async fn send_tx(&self, raw_tx: &[u8]) -> Result<String, NodeError> {
-        send_tx(&self.0, raw_tx).await
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        send_tx(&self.0, raw_tx, fee_policy).await
     }
 
     /// Calls the `getrawtransaction` method.
@@ -188,3 +436,106 @@ impl BitcoinClient for BitcoinClientHTTP {
         get_raw_transaction(&self.0, tx_id).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+
+    #[test]
+    fn fee_policy_params() {
+        assert_eq!(FeePolicy::Default.to_param(), None);
+        assert_eq!(
+            FeePolicy::AllowHighFees(true).to_param(),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            FeePolicy::MaxFeeRate(0.5).to_param(),
+            Some(Value::from(0.5))
+        );
+    }
+
+    #[tokio::test]
+    async fn send_transaction() {
+        let (client, requests) = MockRpc::default()
+            .result("sendrawtransaction", json!("ignored"))
+            .start();
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 7,
+            ..Default::default()
+        };
+        let tx_id = client
+            .send_transaction(&transaction, FeePolicy::MaxFeeRate(0.1))
+            .await
+            .unwrap();
+
+        // The transaction ID is computed locally rather than taken from the response
+        assert_eq!(tx_id, transaction.transaction_id_rev());
+        let mut raw_tx = Vec::new();
+        transaction.encode_raw(&mut raw_tx);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "sendrawtransaction".to_string(),
+                json!([hex::encode(&raw_tx), 0.1])
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_transaction_rejected() {
+        let (client, _) = MockRpc::default()
+            .error("sendrawtransaction", -26, "txn-mempool-conflict")
+            .start();
+        let result = client
+            .send_transaction(&Transaction::default(), FeePolicy::Default)
+            .await;
+        match result {
+            Err(NodeError::Rpc(err)) => assert_eq!(err.message, "txn-mempool-conflict"),
+            _ => panic!("expected rejection"),
+        }
+    }
+
+    /// A self-signed certificate for `localhost`.
+    const ROOT_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBfzCCASWgAwIBAgIUV1vGY3KMM1IS1GkLHvu3a1sxIi4wCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MCAXDTI2MTAxNjE3MDUyM1oYDzIxMjYwOTIy
+MTcwNTIzWjAUMRIwEAYDVQQDDAlsb2NhbGhvc3QwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAASy0q/ZDxaWcJS41oTSCyAOBiR4rVjmJjhCr6hYFN0tknsiFiM74VNV
+ZmX5xnq6xDmi547ExlX24awm8mNcOZllo1MwUTAdBgNVHQ4EFgQUXxwIp9Oo4t2f
+DzVp9BI7uTu4gcIwHwYDVR0jBBgwFoAUXxwIp9Oo4t2fDzVp9BI7uTu4gcIwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA0JpnjPgHub/+aHaA8ONn
+3a1G+Trj/Tojdc63uALL5jkCIFz/kbv3VTyR1OhOYbjNDefoca2oHtvXWY30GsjI
+OczZ
+-----END CERTIFICATE-----";
+
+    fn tls_client(tls_options: &TlsOptions) -> Result<BitcoinClientTLS, native_tls::Error> {
+        BitcoinClientTLS::with_tls_options(
+            "https://127.0.0.1:8332".to_string(),
+            "user".to_string(),
+            "password".to_string(),
+            Timeouts::default(),
+            tls_options,
+        )
+    }
+
+    #[test]
+    fn tls_options() {
+        assert!(tls_client(&TlsOptions::default()).is_ok());
+        assert!(tls_client(&TlsOptions {
+            root_certificates: vec![ROOT_CERTIFICATE.as_bytes().to_vec()],
+            danger_accept_invalid_certs: false,
+        })
+        .is_ok());
+
+        // Malformed certificates are rejected rather than ignored
+        assert!(tls_client(&TlsOptions {
+            root_certificates: vec![b"-----BEGIN CERTIFICATE-----".to_vec()],
+            danger_accept_invalid_certs: false,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! This module contains the [`LimitedClient`] which wraps a [`BitcoinClient`] and applies
+//! per-node concurrency and rate limits.
+//!
+//! This prevents bulk jobs from exhausting the RPC work queue of bitcoind.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::{
+    sync::Semaphore,
+    time::{sleep_until, Instant},
+};
+
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// Limits applied to the requests made to a single node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of requests in-flight at once.
+    pub max_in_flight: usize,
+    /// Maximum number of requests started within each `period`.
+    pub max_requests: u32,
+    /// Length of the rate limiting period.
+    pub period: Duration,
+}
+
+/// Error associated with invalid [`Limits`].
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum LimitsError {
+    /// No requests would ever be permitted in-flight.
+    #[error("maximum in-flight requests must be non-zero")]
+    ZeroInFlight,
+    /// No requests would ever be permitted within a period.
+    #[error("maximum requests per period must be non-zero")]
+    ZeroRequests,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// A [`BitcoinClient`] with a cap on in-flight requests and a rate limit.
+#[derive(Clone, Debug)]
+pub struct LimitedClient<C> {
+    inner_client: C,
+    limits: Limits,
+    in_flight: Arc<Semaphore>,
+    window: Arc<Mutex<Window>>,
+}
+
+impl<C> LimitedClient<C> {
+    /// Wrap a [`BitcoinClient`] with the given [`Limits`].
+    ///
+    /// Limits which would never permit a request are rejected.
+    pub fn new(inner_client: C, limits: Limits) -> Result<Self, LimitsError> {
+        if limits.max_in_flight == 0 {
+            return Err(LimitsError::ZeroInFlight);
+        }
+        if limits.max_requests == 0 {
+            return Err(LimitsError::ZeroRequests);
+        }
+        Ok(Self {
+            inner_client,
+            limits,
+            in_flight: Arc::new(Semaphore::new(limits.max_in_flight)),
+            window: Arc::new(Mutex::new(Window {
+                start: Instant::now(),
+                count: 0,
+            })),
+        })
+    }
+
+    /// Get the [`Limits`] applied to the client.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Converts the limited client into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.inner_client
+    }
+
+    /// Reserve a slot in the rate limiting window, returning the instant it begins.
+    fn reserve(&self) -> Instant {
+        // This is safe as the lock is never held across a panic
+        let mut window = self.window.lock().unwrap();
+        let now = Instant::now();
+        if now.duration_since(window.start) >= self.limits.period {
+            window.start = now;
+            window.count = 0;
+        }
+        if window.count >= self.limits.max_requests {
+            // Queue behind the requests already reserved so they are released in order
+            window.start += self.limits.period;
+            window.count = 0;
+        }
+        window.count += 1;
+        window.start.max(now)
+    }
+
+    /// Wait until the rate limit permits another request.
+    async fn throttle(&self) {
+        // The lock is released before sleeping so other callers can reserve their slots
+        let start = self.reserve();
+        if start > Instant::now() {
+            sleep_until(start).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<C> BitcoinClient for LimitedClient<C>
+where
+    C: BitcoinClient + Send + Sync,
+{
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let _permit = self.in_flight.acquire().await.unwrap(); // This is safe as the semaphore is never closed
+        self.throttle().await;
+        self.inner_client
+            .send_tx_with_fee_policy(raw_tx, fee_policy)
+            .await
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        let _permit = self.in_flight.acquire().await.unwrap(); // This is safe as the semaphore is never closed
+        self.throttle().await;
+        self.inner_client.get_new_addr().await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        let _permit = self.in_flight.acquire().await.unwrap(); // This is safe as the semaphore is never closed
+        self.throttle().await;
+        self.inner_client.get_raw_transaction(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CountingClient {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BitcoinClient for CountingClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(String::new())
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn limits(max_in_flight: usize, max_requests: u32) -> Limits {
+        Limits {
+            max_in_flight,
+            max_requests,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        assert_eq!(
+            LimitedClient::new(CountingClient::default(), limits(0, 1)).unwrap_err(),
+            LimitsError::ZeroInFlight
+        );
+        assert_eq!(
+            LimitedClient::new(CountingClient::default(), limits(1, 0)).unwrap_err(),
+            LimitsError::ZeroRequests
+        );
+    }
+
+    #[tokio::test]
+    async fn caps_in_flight() {
+        let client =
+            Arc::new(LimitedClient::new(CountingClient::default(), limits(2, 100)).unwrap());
+        let requests = (0..6).map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get_new_addr().await })
+        });
+        for request in requests.collect::<Vec<_>>() {
+            request.await.unwrap().unwrap();
+        }
+        assert_eq!(client.inner_client.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttle_does_not_block_reservations() {
+        let client = LimitedClient::new(CountingClient::default(), limits(10, 2)).unwrap();
+        let start = Instant::now();
+
+        // The first window is filled immediately and the following reservations are queued
+        // behind it without waiting on the sleeping callers
+        let reservations: Vec<_> = (0..5).map(|_| client.reserve()).collect();
+        assert_eq!(reservations[0], start);
+        assert_eq!(reservations[1], start);
+        assert_eq!(reservations[2], start + Duration::from_secs(1));
+        assert_eq!(reservations[3], start + Duration::from_secs(1));
+        assert_eq!(reservations[4], start + Duration::from_secs(2));
+
+        client.throttle().await;
+        assert_eq!(Instant::now(), start + Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,302 @@
+//! This module contains the [`MempoolWatcher`] which polls the mempool of bitcoind, emitting the
+//! transactions added and removed since the previous poll as [`MempoolDelta`]s, and the
+//! [`MempoolFeed`] which shares them between many consumers.
+//!
+//! Consumers, such as confirmation trackers and double-spend watchers, subscribe to a single feed
+//! rather than each polling bitcoind independently.
+
+use std::{collections::HashSet, time::Duration};
+
+use async_trait::async_trait;
+use futures_core::Stream;
+use futures_util::{pin_mut, stream, StreamExt};
+use thiserror::Error;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{interval, Interval},
+};
+use tracing::warn;
+
+use crate::{call, BitcoinClientHTTP, BitcoinClientTLS, BitcoinJsonClient, Connectable, NodeError};
+
+/// Default capacity of a [`MempoolFeed`], in deltas buffered per subscriber.
+pub const DEFAULT_FEED_CAPACITY: usize = 64;
+
+/// Mempool-oriented client methods.
+#[async_trait]
+pub trait MempoolClient {
+    /// Get the IDs of the transactions in the mempool of bitcoind.
+    ///
+    /// The IDs are in the byte order used by the RPC.
+    async fn get_raw_mempool(&self) -> Result<Vec<[u8; 32]>, NodeError>;
+}
+
+/// Calls the `getrawmempool` method with verbose set to false.
+async fn get_raw_mempool<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+) -> Result<Vec<[u8; 32]>, NodeError> {
+    let tx_ids_hex: Vec<String> = call(client, "getrawmempool", vec![]).await?;
+    tx_ids_hex
+        .iter()
+        .map(|tx_id_hex| {
+            let mut tx_id = [0; 32];
+            hex::decode_to_slice(tx_id_hex, &mut tx_id)?;
+            Ok(tx_id)
+        })
+        .collect()
+}
+
+#[async_trait]
+impl MempoolClient for BitcoinClientHTTP {
+    /// Calls the `getrawmempool` method.
+    async fn get_raw_mempool(&self) -> Result<Vec<[u8; 32]>, NodeError> {
+        get_raw_mempool(&self.0).await
+    }
+}
+
+#[async_trait]
+impl MempoolClient for BitcoinClientTLS {
+    /// Calls the `getrawmempool` method.
+    async fn get_raw_mempool(&self) -> Result<Vec<[u8; 32]>, NodeError> {
+        get_raw_mempool(&self.0).await
+    }
+}
+
+/// The transactions added to and removed from the mempool between two polls.
+///
+/// Removed transactions were either confirmed, conflicted or evicted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MempoolDelta {
+    /// IDs of the transactions added, in the byte order used by the RPC.
+    pub added: Vec<[u8; 32]>,
+    /// IDs of the transactions removed, in the byte order used by the RPC.
+    pub removed: Vec<[u8; 32]>,
+}
+
+impl MempoolDelta {
+    /// Whether the mempool was unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Polls the mempool of bitcoind, maintaining the set of transaction IDs within it.
+#[derive(Clone, Debug)]
+pub struct MempoolWatcher<C> {
+    client: C,
+    tx_ids: HashSet<[u8; 32]>,
+}
+
+impl<C> MempoolWatcher<C> {
+    /// Create a new [`MempoolWatcher`].
+    ///
+    /// The first call to [`MempoolWatcher::poll`] reports the whole mempool as added.
+    pub fn new(client: C) -> Self {
+        Self {
+            client,
+            tx_ids: HashSet::new(),
+        }
+    }
+
+    /// Whether the transaction was in the mempool as of the last poll.
+    pub fn contains(&self, tx_id: &[u8; 32]) -> bool {
+        self.tx_ids.contains(tx_id)
+    }
+
+    /// Number of transactions in the mempool as of the last poll.
+    pub fn len(&self) -> usize {
+        self.tx_ids.len()
+    }
+
+    /// Whether the mempool was empty as of the last poll.
+    pub fn is_empty(&self) -> bool {
+        self.tx_ids.is_empty()
+    }
+
+    /// Converts the watcher into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.client
+    }
+}
+
+impl<C> MempoolWatcher<C>
+where
+    C: MempoolClient + Sync,
+{
+    /// Snapshot the mempool, returning the [`MempoolDelta`] since the previous snapshot.
+    pub async fn poll(&mut self) -> Result<MempoolDelta, NodeError> {
+        let tx_ids: HashSet<[u8; 32]> = self.client.get_raw_mempool().await?.into_iter().collect();
+        let delta = MempoolDelta {
+            added: tx_ids.difference(&self.tx_ids).copied().collect(),
+            removed: self.tx_ids.difference(&tx_ids).copied().collect(),
+        };
+        self.tx_ids = tx_ids;
+        Ok(delta)
+    }
+}
+
+impl<C> MempoolWatcher<C>
+where
+    C: MempoolClient + Send + Sync + 'static,
+{
+    /// Poll the mempool every `period`, streaming the non-empty [`MempoolDelta`]s.
+    ///
+    /// Errors are yielded without ending the stream, and the following poll reports the delta
+    /// since the last successful one.
+    pub fn into_stream(
+        self,
+        period: Duration,
+    ) -> impl Stream<Item = Result<MempoolDelta, NodeError>> + Send + 'static {
+        stream::unfold(
+            (self, interval(period)),
+            |(mut watcher, mut interval): (Self, Interval)| async move {
+                loop {
+                    interval.tick().await;
+                    match watcher.poll().await {
+                        Ok(delta) if delta.is_empty() => continue,
+                        result => return Some((result, (watcher, interval))),
+                    }
+                }
+            },
+        )
+    }
+}
+
+/// Error associated with a [`MempoolFeed`] subscription.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum FeedError {
+    /// The subscriber fell behind and missed the number of deltas.
+    ///
+    /// Its view of the mempool should be rebuilt, for example from a fresh [`MempoolWatcher`].
+    #[error("missed {0} mempool deltas")]
+    Lagged(u64),
+}
+
+/// Shares the [`MempoolDelta`]s of a single [`MempoolWatcher`] between many subscribers.
+#[derive(Clone, Debug)]
+pub struct MempoolFeed {
+    sender: broadcast::Sender<MempoolDelta>,
+}
+
+impl Default for MempoolFeed {
+    fn default() -> Self {
+        Self::new(DEFAULT_FEED_CAPACITY)
+    }
+}
+
+impl MempoolFeed {
+    /// Create a new [`MempoolFeed`], buffering at most `capacity` deltas per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Publish a delta to the current subscribers.
+    pub fn publish(&self, delta: MempoolDelta) {
+        // A send only fails when there are no subscribers
+        let _ = self.sender.send(delta);
+    }
+
+    /// Number of current subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Stream the deltas published after subscribing.
+    pub fn subscribe(
+        &self,
+    ) -> impl Stream<Item = Result<MempoolDelta, FeedError>> + Send + 'static {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(delta) => Some((Ok(delta), receiver)),
+                Err(RecvError::Lagged(missed)) => Some((Err(FeedError::Lagged(missed)), receiver)),
+                Err(RecvError::Closed) => None,
+            }
+        })
+    }
+
+    /// Drive the watcher, polling the mempool every `period` and publishing the non-empty deltas.
+    ///
+    /// This is expected to be spawned alongside the subscribers, and never returns. Errors polling
+    /// bitcoind are logged and retried at the next period.
+    pub async fn run<C>(&self, watcher: MempoolWatcher<C>, period: Duration)
+    where
+        C: MempoolClient + Send + Sync + 'static,
+    {
+        let deltas = watcher.into_stream(period);
+        pin_mut!(deltas);
+        while let Some(result) = deltas.next().await {
+            match result {
+                Ok(delta) => self.publish(delta),
+                Err(err) => warn!(message = "failed to poll mempool", error = %err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct MockMempool(Arc<Mutex<Vec<[u8; 32]>>>);
+
+    #[async_trait]
+    impl MempoolClient for MockMempool {
+        async fn get_raw_mempool(&self) -> Result<Vec<[u8; 32]>, NodeError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn poll() {
+        let mempool = MockMempool::default();
+        *mempool.0.lock().unwrap() = vec![[1; 32], [2; 32]];
+        let mut watcher = MempoolWatcher::new(mempool.clone());
+
+        // The first poll reports the whole mempool
+        let mut delta = watcher.poll().await.unwrap();
+        delta.added.sort_unstable();
+        assert_eq!(delta.added, vec![[1; 32], [2; 32]]);
+        assert!(delta.removed.is_empty());
+        assert!(watcher.poll().await.unwrap().is_empty());
+
+        *mempool.0.lock().unwrap() = vec![[2; 32], [3; 32]];
+        let delta = watcher.poll().await.unwrap();
+        assert_eq!(
+            delta,
+            MempoolDelta {
+                added: vec![[3; 32]],
+                removed: vec![[1; 32]],
+            }
+        );
+        assert!(watcher.contains(&[3; 32]));
+        assert_eq!(watcher.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn feed() {
+        let mempool = MockMempool::default();
+        let feed = MempoolFeed::new(1);
+        let first = feed.subscribe();
+        let second = feed.subscribe();
+        pin_mut!(first, second);
+        assert_eq!(feed.subscriber_count(), 2);
+
+        *mempool.0.lock().unwrap() = vec![[1; 32]];
+        let mut watcher = MempoolWatcher::new(mempool.clone());
+        feed.publish(watcher.poll().await.unwrap());
+        assert_eq!(first.next().await.unwrap().unwrap().added, vec![[1; 32]]);
+        assert_eq!(second.next().await.unwrap().unwrap().added, vec![[1; 32]]);
+
+        // Subscribers which fall behind are told so
+        *mempool.0.lock().unwrap() = vec![];
+        feed.publish(watcher.poll().await.unwrap());
+        *mempool.0.lock().unwrap() = vec![[2; 32]];
+        feed.publish(watcher.poll().await.unwrap());
+        assert_eq!(first.next().await.unwrap(), Err(FeedError::Lagged(1)));
+        assert_eq!(first.next().await.unwrap().unwrap().added, vec![[2; 32]]);
+    }
+}
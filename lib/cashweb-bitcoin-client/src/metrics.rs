@@ -0,0 +1,288 @@
+//! This module contains the [`InstrumentedClient`] which reports the outcome of each broadcast to
+//! a [`MetricsSink`] and emits `tracing` events.
+//!
+//! The [`GlobalMetrics`] sink feeds the process-wide `cashweb-metrics` facade, so that broadcasts
+//! are exported alongside the metrics of other crates.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use cashweb_metrics::{Counter, Histogram};
+use tracing::{info, warn};
+
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// The outcome of a broadcast.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BroadcastOutcome<'a> {
+    /// bitcoind accepted the transaction.
+    Accepted,
+    /// bitcoind rejected the transaction.
+    Rejected {
+        /// JSON-RPC error code.
+        code: i32,
+        /// Reason given by bitcoind, for example `txn-mempool-conflict`.
+        reason: &'a str,
+    },
+    /// The broadcast failed before bitcoind responded, for example due to a connection error or
+    /// timeout.
+    Failed,
+}
+
+/// A destination for broadcast metrics.
+pub trait MetricsSink {
+    /// Record a broadcast attempt, its outcome and its latency.
+    fn record_broadcast(&self, outcome: BroadcastOutcome<'_>, latency: Duration);
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for Arc<T> {
+    fn record_broadcast(&self, outcome: BroadcastOutcome<'_>, latency: Duration) {
+        (**self).record_broadcast(outcome, latency)
+    }
+}
+
+/// Broadcast attempts, by outcome.
+pub const BROADCASTS: Counter = Counter::new(
+    "cashweb_broadcasts_total",
+    "Broadcast attempts, by outcome.",
+    &["outcome"],
+);
+
+/// Duration of broadcast attempts, by outcome.
+pub const BROADCAST_DURATION: Histogram = Histogram::new(
+    "cashweb_broadcast_duration_seconds",
+    "Duration of broadcast attempts, by outcome.",
+    &["outcome"],
+);
+
+/// A [`MetricsSink`] feeding [`BROADCASTS`] and [`BROADCAST_DURATION`] to the global
+/// `cashweb-metrics` recorder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GlobalMetrics;
+
+impl MetricsSink for GlobalMetrics {
+    fn record_broadcast(&self, outcome: BroadcastOutcome<'_>, latency: Duration) {
+        let outcome = match outcome {
+            BroadcastOutcome::Accepted => "accepted",
+            BroadcastOutcome::Rejected { .. } => "rejected",
+            BroadcastOutcome::Failed => "failed",
+        };
+        BROADCASTS.increment(&[outcome]);
+        BROADCAST_DURATION.observe(&[outcome], latency.as_secs_f64());
+    }
+}
+
+/// A snapshot of [`BroadcastCounters`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastStats {
+    /// Number of attempted broadcasts.
+    pub attempts: u64,
+    /// Number of accepted broadcasts.
+    pub accepts: u64,
+    /// Number of rejected broadcasts, by JSON-RPC error code.
+    ///
+    /// The free-form reasons given by bitcoind are not used as keys, keeping the map bounded.
+    pub rejects: HashMap<i32, u64>,
+    /// Number of broadcasts which failed before bitcoind responded.
+    pub failures: u64,
+    /// Total latency of all attempts.
+    pub total_latency: Duration,
+}
+
+/// An in-memory [`MetricsSink`] which counts broadcast outcomes.
+#[derive(Debug, Default)]
+pub struct BroadcastCounters {
+    attempts: AtomicU64,
+    accepts: AtomicU64,
+    failures: AtomicU64,
+    total_latency_micros: AtomicU64,
+    rejects: Mutex<HashMap<i32, u64>>,
+}
+
+impl BroadcastCounters {
+    /// Get a snapshot of the counters.
+    pub fn stats(&self) -> BroadcastStats {
+        BroadcastStats {
+            attempts: self.attempts.load(Ordering::Relaxed),
+            accepts: self.accepts.load(Ordering::Relaxed),
+            rejects: self.rejects.lock().unwrap().clone(),
+            failures: self.failures.load(Ordering::Relaxed),
+            total_latency: Duration::from_micros(self.total_latency_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl MetricsSink for BroadcastCounters {
+    fn record_broadcast(&self, outcome: BroadcastOutcome<'_>, latency: Duration) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        match outcome {
+            BroadcastOutcome::Accepted => {
+                self.accepts.fetch_add(1, Ordering::Relaxed);
+            }
+            BroadcastOutcome::Rejected { code, .. } => {
+                *self.rejects.lock().unwrap().entry(code).or_default() += 1;
+            }
+            BroadcastOutcome::Failed => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A [`BitcoinClient`] which reports the outcome of each broadcast to a [`MetricsSink`].
+#[derive(Clone, Debug)]
+pub struct InstrumentedClient<C, S> {
+    inner_client: C,
+    sink: S,
+}
+
+impl<C, S> InstrumentedClient<C, S> {
+    /// Wrap a [`BitcoinClient`] with a [`MetricsSink`].
+    pub fn new(inner_client: C, sink: S) -> Self {
+        Self { inner_client, sink }
+    }
+
+    /// Get the [`MetricsSink`].
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Converts the instrumented client into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.inner_client
+    }
+}
+
+#[async_trait]
+impl<C, S> BitcoinClient for InstrumentedClient<C, S>
+where
+    C: BitcoinClient + Send + Sync,
+    S: MetricsSink + Send + Sync,
+{
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let start = Instant::now();
+        let result = self
+            .inner_client
+            .send_tx_with_fee_policy(raw_tx, fee_policy)
+            .await;
+        let latency = start.elapsed();
+
+        let outcome = match &result {
+            Ok(tx_id) => {
+                info!(message = "broadcast accepted", %tx_id, ?latency);
+                BroadcastOutcome::Accepted
+            }
+            Err(NodeError::Rpc(err)) => {
+                warn!(message = "broadcast rejected", code = err.code, reason = %err.message, ?latency);
+                BroadcastOutcome::Rejected {
+                    code: err.code,
+                    reason: &err.message,
+                }
+            }
+            Err(err) => {
+                warn!(message = "broadcast failed", error = %err, ?latency);
+                BroadcastOutcome::Failed
+            }
+        };
+        self.sink.record_broadcast(outcome, latency);
+
+        result
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.inner_client.get_new_addr().await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.inner_client.get_raw_transaction(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json_rpc::prelude::RpcError;
+
+    use super::*;
+
+    /// Answers each broadcast with the next queued result.
+    #[derive(Debug)]
+    struct MockClient(Mutex<Vec<Result<String, NodeError>>>);
+
+    #[async_trait]
+    impl BitcoinClient for MockClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            self.0.lock().unwrap().remove(0)
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Ok(String::new())
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn rejection(code: i32, message: &str) -> NodeError {
+        NodeError::Rpc(RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn counts_outcomes() {
+        let results = vec![
+            Ok("txid".to_string()),
+            Err(rejection(-26, "txn-mempool-conflict")),
+            Err(rejection(-26, "min relay fee not met")),
+            Err(rejection(-27, "transaction already in block chain")),
+            Err(NodeError::Timeout),
+        ];
+        let client = InstrumentedClient::new(
+            MockClient(Mutex::new(results)),
+            Arc::new(BroadcastCounters::default()),
+        );
+        for _ in 0..5 {
+            let _ = client.send_tx(&[]).await;
+        }
+
+        let stats = client.sink().stats();
+        assert_eq!(stats.attempts, 5);
+        assert_eq!(stats.accepts, 1);
+        assert_eq!(stats.failures, 1);
+        let mut expected_rejects = HashMap::new();
+        expected_rejects.insert(-26, 2);
+        expected_rejects.insert(-27, 1);
+        assert_eq!(stats.rejects, expected_rejects);
+    }
+
+    #[tokio::test]
+    async fn passes_through_results() {
+        let results = vec![Ok("txid".to_string()), Err(NodeError::Timeout)];
+        let client = InstrumentedClient::new(
+            MockClient(Mutex::new(results)),
+            BroadcastCounters::default(),
+        );
+        assert_eq!(client.send_tx(&[]).await.unwrap(), "txid");
+        assert!(matches!(client.send_tx(&[]).await, Err(NodeError::Timeout)));
+    }
+}
@@ -0,0 +1,190 @@
+//! This module contains the [`MiningClient`] trait which provides the miner-oriented
+//! `getblocktemplate` and `submitblock` methods.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use json_rpc::prelude::RequestFactory;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{call, BitcoinClientHTTP, BitcoinClientTLS, BitcoinJsonClient, Connectable, NodeError};
+
+/// A transaction to be included in a [`BlockTemplate`].
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct TemplateTransaction {
+    /// Hex encoded raw transaction.
+    pub data: String,
+    /// Transaction ID.
+    pub txid: String,
+    /// Transaction hash.
+    pub hash: String,
+    /// Indexes of the transactions within the template which this transaction depends on.
+    #[serde(default)]
+    pub depends: Vec<u64>,
+    /// Fee paid by the transaction, in satoshis.
+    pub fee: i64,
+    /// Number of signature operations.
+    #[serde(default)]
+    pub sigops: Option<u64>,
+}
+
+/// The result of the `getblocktemplate` method.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct BlockTemplate {
+    /// Block version.
+    pub version: u32,
+    /// Hash of the current highest block.
+    #[serde(rename = "previousblockhash")]
+    pub previous_block_hash: String,
+    /// Transactions to be included in the block.
+    pub transactions: Vec<TemplateTransaction>,
+    /// Maximum allowable input to the coinbase transaction, in satoshis.
+    #[serde(rename = "coinbasevalue")]
+    pub coinbase_value: i64,
+    /// Hash target.
+    pub target: String,
+    /// Minimum timestamp appropriate for the block.
+    #[serde(rename = "mintime")]
+    pub min_time: u64,
+    /// Current timestamp.
+    #[serde(rename = "curtime")]
+    pub cur_time: u64,
+    /// Compressed target of the block.
+    pub bits: String,
+    /// Height of the block.
+    pub height: u64,
+    /// Fields which are specific to the node implementation, such as those used by Lotus.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// Miner-oriented client methods.
+#[async_trait]
+pub trait MiningClient {
+    /// Get a [`BlockTemplate`] from bitcoind.
+    async fn get_block_template(&self) -> Result<BlockTemplate, NodeError>;
+    /// Submit a raw block to bitcoind.
+    ///
+    /// Returns `None` if the block was accepted, otherwise the reason for its rejection.
+    async fn submit_block(&self, raw_block: &[u8]) -> Result<Option<String>, NodeError>;
+}
+
+async fn submit_block<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    raw_block: &[u8],
+) -> Result<Option<String>, NodeError> {
+    let request = client
+        .build_request()
+        .method("submitblock")
+        .params(vec![Value::String(hex::encode(raw_block))])
+        .finish()
+        .unwrap();
+    let response = client.send(request).await?;
+    if response.is_error() {
+        return Err(NodeError::Rpc(response.error().unwrap()));
+    }
+    // A null result indicates the block was accepted
+    let result: Option<Result<Option<String>, _>> = response.into_result();
+    match result {
+        Some(result) => result.map_err(NodeError::Json),
+        None => Ok(None),
+    }
+}
+
+#[async_trait]
+impl MiningClient for BitcoinClientHTTP {
+    /// Calls the `getblocktemplate` method.
+    async fn get_block_template(&self) -> Result<BlockTemplate, NodeError> {
+        call(&self.0, "getblocktemplate", vec![]).await
+    }
+
+    /// Calls the `submitblock` method.
+    async fn submit_block(&self, raw_block: &[u8]) -> Result<Option<String>, NodeError> {
+        submit_block(&self.0, raw_block).await
+    }
+}
+
+#[async_trait]
+impl MiningClient for BitcoinClientTLS {
+    /// Calls the `getblocktemplate` method.
+    async fn get_block_template(&self) -> Result<BlockTemplate, NodeError> {
+        call(&self.0, "getblocktemplate", vec![]).await
+    }
+
+    /// Calls the `submitblock` method.
+    async fn submit_block(&self, raw_block: &[u8]) -> Result<Option<String>, NodeError> {
+        submit_block(&self.0, raw_block).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+
+    #[tokio::test]
+    async fn get_block_template() {
+        let (client, _) = MockRpc::default()
+            .result(
+                "getblocktemplate",
+                json!({
+                    "version": 536870912,
+                    "previousblockhash": "00",
+                    "transactions": [{
+                        "data": "01",
+                        "txid": "02",
+                        "hash": "03",
+                        "fee": 226,
+                    }],
+                    "coinbasevalue": 625000000,
+                    "target": "7f",
+                    "mintime": 1600000000,
+                    "curtime": 1600000600,
+                    "bits": "207fffff",
+                    "height": 101,
+                    "epochblockhash": "04",
+                }),
+            )
+            .start();
+        let template = client.get_block_template().await.unwrap();
+        assert_eq!(template.height, 101);
+        assert_eq!(template.coinbase_value, 625000000);
+        assert_eq!(
+            template.transactions,
+            vec![TemplateTransaction {
+                data: "01".to_string(),
+                txid: "02".to_string(),
+                hash: "03".to_string(),
+                depends: Vec::new(),
+                fee: 226,
+                sigops: None,
+            }]
+        );
+
+        // Node specific fields are retained
+        assert_eq!(template.extra.get("epochblockhash"), Some(&json!("04")));
+    }
+
+    #[tokio::test]
+    async fn submit_block() {
+        let (client, requests) = MockRpc::default()
+            .result("submitblock", Value::Null)
+            .start();
+        assert_eq!(client.submit_block(&[1, 2]).await.unwrap(), None);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![("submitblock".to_string(), json!(["0102"]))]
+        );
+
+        let (client, _) = MockRpc::default()
+            .result("submitblock", json!("bad-txnmrklroot"))
+            .start();
+        assert_eq!(
+            client.submit_block(&[1, 2]).await.unwrap().as_deref(),
+            Some("bad-txnmrklroot")
+        );
+    }
+}
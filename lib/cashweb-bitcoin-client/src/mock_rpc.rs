@@ -0,0 +1,89 @@
+//! This module contains [`MockRpc`], a JSON-RPC server which answers each method with a canned
+//! response, for testing the RPC clients.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use serde_json::{json, Value};
+
+use crate::BitcoinClientHTTP;
+
+/// The method and parameters of each request received by a [`MockRpc`].
+pub(crate) type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+/// A JSON-RPC server answering each method with a canned result or error.
+#[derive(Default)]
+pub(crate) struct MockRpc {
+    responses: HashMap<String, Value>,
+}
+
+impl MockRpc {
+    /// Answer the method with the result.
+    pub(crate) fn result(mut self, method: &str, result: Value) -> Self {
+        self.responses.insert(
+            method.to_string(),
+            json!({ "result": result, "error": null }),
+        );
+        self
+    }
+
+    /// Answer the method with an error.
+    pub(crate) fn error(mut self, method: &str, code: i32, message: &str) -> Self {
+        let error = json!({ "code": code, "message": message });
+        self.responses.insert(
+            method.to_string(),
+            json!({ "result": null, "error": error }),
+        );
+        self
+    }
+
+    /// Start serving on a local port, returning a client connected to it and the requests it
+    /// receives.
+    pub(crate) fn start(self) -> (BitcoinClientHTTP, Requests) {
+        let responses = Arc::new(self.responses);
+        let requests = Requests::default();
+        let service_requests = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let responses = responses.clone();
+            let requests = service_requests.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let responses = responses.clone();
+                    let requests = requests.clone();
+                    async move {
+                        let body = to_bytes(request.into_body()).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let method = request["method"].as_str().unwrap().to_string();
+                        let mut response = responses.get(&method).cloned().unwrap_or_else(|| {
+                            json!({
+                                "result": null,
+                                "error": { "code": -32601, "message": "Method not found" }
+                            })
+                        });
+                        response["id"] = request["id"].clone();
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push((method, request["params"].clone()));
+                        Ok::<_, Infallible>(Response::new(Body::from(response.to_string())))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let client = BitcoinClientHTTP::new(endpoint, "user".to_string(), "password".to_string());
+        (client, requests)
+    }
+}
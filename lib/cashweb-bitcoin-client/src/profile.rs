@@ -0,0 +1,320 @@
+//! This module contains the [`Chain`] enumeration, capturing the RPC quirks of each supported node
+//! implementation, and the [`ProfiledClient`] which applies them.
+//!
+//! This allows a single binary to talk to heterogeneous nodes without branching on the chain
+//! throughout application code.
+
+use async_trait::async_trait;
+use cashweb_bitcoin::{transaction::sighash::SighashParams, Network};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(feature = "wallet")]
+use crate::wallet::{SignedTransaction, Unspent, WalletClient, RPC_CHAIN};
+use crate::{BitcoinClient, FeePolicy, NodeError};
+
+/// Error associated with converting between coin units and satoshis.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum AmountError {
+    /// The amount was not a finite decimal number.
+    #[error("malformed amount: {0}")]
+    Malformed(String),
+    /// The amount had more decimal places than the coin units allow.
+    #[error("amount has more than {0} decimal places")]
+    Precision(u32),
+    /// The amount does not fit in 64 bits of satoshis.
+    #[error("amount out of range")]
+    OutOfRange,
+}
+
+/// Enumeration of supported chains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chain {
+    /// Bitcoin Cash, served by Bitcoin Cash Node.
+    Bch,
+    /// eCash, served by Bitcoin ABC.
+    Xec,
+    /// Lotus, served by lotusd.
+    Xpi,
+}
+
+/// The shape of the fee argument accepted by `sendrawtransaction`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeArgument {
+    /// A boolean `allowhighfees`.
+    AllowHighFees,
+    /// A numeric `maxfeerate`, where zero disables the limit.
+    MaxFeeRate,
+}
+
+impl Chain {
+    /// The shape of the fee argument accepted by `sendrawtransaction`.
+    pub fn fee_argument(self) -> FeeArgument {
+        match self {
+            Self::Bch => FeeArgument::AllowHighFees,
+            Self::Xec | Self::Xpi => FeeArgument::MaxFeeRate,
+        }
+    }
+
+    /// Number of decimal places in the coin units used by the RPC.
+    pub fn decimals(self) -> u32 {
+        match self {
+            Self::Bch => 8,
+            Self::Xec => 2,
+            Self::Xpi => 6,
+        }
+    }
+
+    /// Address prefix used in verbose RPC outputs.
+    pub fn address_prefix(self, network: Network) -> &'static str {
+        match (self, network) {
+            (Self::Bch, Network::Mainnet) => "bitcoincash",
+            (Self::Bch, Network::Testnet) => "bchtest",
+            (Self::Bch, Network::Regtest) => "bchreg",
+            (Self::Xec, Network::Mainnet) => "ecash",
+            (Self::Xec, Network::Testnet) => "ectest",
+            (Self::Xec, Network::Regtest) => "ecregtest",
+            (Self::Xpi, Network::Mainnet) => "lotus",
+            (Self::Xpi, Network::Testnet) => "lotusT",
+            (Self::Xpi, Network::Regtest) => "lotusR",
+        }
+    }
+
+    /// The signature hash parameters expected by the node.
+    pub fn sighash_params(self) -> SighashParams {
+        match self {
+            Self::Bch => SighashParams::BCH,
+            Self::Xec => SighashParams::XEC,
+            Self::Xpi => SighashParams::LOTUS,
+        }
+    }
+
+    /// Convert an amount in the coin units used by the RPC into satoshis.
+    ///
+    /// The amount is converted via its shortest decimal representation, so that amounts parsed
+    /// from JSON convert exactly. Fails if the amount is not finite, is more precise than the
+    /// coin units or is out of range.
+    pub fn amount_to_satoshis(self, amount: f64) -> Result<i64, AmountError> {
+        if !amount.is_finite() {
+            return Err(AmountError::Malformed(amount.to_string()));
+        }
+        // Display never uses exponential notation
+        self.parse_amount(&amount.to_string())
+    }
+
+    /// Parse a decimal amount in the coin units used by the RPC into satoshis.
+    pub fn parse_amount(self, amount: &str) -> Result<i64, AmountError> {
+        let malformed = || AmountError::Malformed(amount.to_string());
+        let (negative, unsigned) = match amount.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, amount),
+        };
+        let mut parts = unsigned.splitn(2, '.');
+        let integer = parts.next().unwrap(); // This is safe
+        let fraction = parts.next().unwrap_or_default();
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if integer.is_empty() || !is_digits(integer) || !is_digits(fraction) {
+            return Err(malformed());
+        }
+
+        // Digits beyond the coin units must be zero
+        let decimals = self.decimals() as usize;
+        if fraction.len() > decimals && fraction[decimals..].bytes().any(|byte| byte != b'0') {
+            return Err(AmountError::Precision(self.decimals()));
+        }
+        let fraction = &fraction[..fraction.len().min(decimals)];
+
+        let digits = format!("{}{:0<width$}", integer, fraction, width = decimals);
+        let satoshis: i64 = digits.parse().map_err(|_| AmountError::OutOfRange)?;
+        Ok(if negative { -satoshis } else { satoshis })
+    }
+
+    /// Convert an amount in satoshis into a decimal amount in the coin units used by the RPC.
+    ///
+    /// bitcoind accepts amounts given as strings, avoiding any loss of precision.
+    pub fn satoshis_to_amount(self, satoshis: i64) -> String {
+        let decimals = self.decimals() as usize;
+        let sign = if satoshis < 0 { "-" } else { "" };
+        let digits = format!("{:0>width$}", satoshis.unsigned_abs(), width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        if fraction.is_empty() {
+            format!("{}{}", sign, integer)
+        } else {
+            format!("{}{}.{}", sign, integer, fraction)
+        }
+    }
+
+    /// Translate a [`FeePolicy`] into one accepted by the node.
+    ///
+    /// A `maxfeerate` of zero corresponds to `allowhighfees`, other rates cannot be expressed as a
+    /// boolean and fall back to the node's default policy.
+    pub fn translate_fee_policy(self, fee_policy: FeePolicy) -> FeePolicy {
+        match (self.fee_argument(), fee_policy) {
+            (_, FeePolicy::Default) => FeePolicy::Default,
+            (FeeArgument::AllowHighFees, FeePolicy::MaxFeeRate(rate)) => {
+                if rate == 0.0 {
+                    FeePolicy::AllowHighFees(true)
+                } else {
+                    FeePolicy::Default
+                }
+            }
+            (FeeArgument::MaxFeeRate, FeePolicy::AllowHighFees(true)) => FeePolicy::MaxFeeRate(0.0),
+            (FeeArgument::MaxFeeRate, FeePolicy::AllowHighFees(false)) => FeePolicy::Default,
+            (_, fee_policy) => fee_policy,
+        }
+    }
+}
+
+/// A [`BitcoinClient`] which adapts requests to the quirks of a [`Chain`].
+#[derive(Clone, Debug)]
+pub struct ProfiledClient<C> {
+    inner_client: C,
+    chain: Chain,
+}
+
+impl<C> ProfiledClient<C> {
+    /// Wrap a [`BitcoinClient`] connected to a node of the given [`Chain`].
+    pub fn new(inner_client: C, chain: Chain) -> Self {
+        Self {
+            inner_client,
+            chain,
+        }
+    }
+
+    /// Get the [`Chain`].
+    pub fn chain(&self) -> Chain {
+        self.chain
+    }
+
+    /// Converts the profiled client into the underlying client.
+    pub fn into_inner(self) -> C {
+        self.inner_client
+    }
+
+    /// Ratio between the satoshis of the [`Chain`] and those assumed by the plain wallet clients.
+    #[cfg(feature = "wallet")]
+    fn wallet_factor(&self) -> u64 {
+        10u64.pow(RPC_CHAIN.decimals().saturating_sub(self.chain.decimals()))
+    }
+}
+
+#[async_trait]
+impl<C> BitcoinClient for ProfiledClient<C>
+where
+    C: BitcoinClient + Send + Sync,
+{
+    async fn send_tx_with_fee_policy(
+        &self,
+        raw_tx: &[u8],
+        fee_policy: FeePolicy,
+    ) -> Result<String, NodeError> {
+        let fee_policy = self.chain.translate_fee_policy(fee_policy);
+        self.inner_client
+            .send_tx_with_fee_policy(raw_tx, fee_policy)
+            .await
+    }
+
+    async fn get_new_addr(&self) -> Result<String, NodeError> {
+        self.inner_client.get_new_addr().await
+    }
+
+    async fn get_raw_transaction(&self, tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+        self.inner_client.get_raw_transaction(tx_id).await
+    }
+}
+
+/// Rescales the satoshis of the plain wallet clients into those of the [`Chain`].
+#[cfg(feature = "wallet")]
+#[async_trait]
+impl<C> WalletClient for ProfiledClient<C>
+where
+    C: WalletClient + Send + Sync,
+{
+    async fn list_unspent(&self, min_conf: u64, max_conf: u64) -> Result<Vec<Unspent>, NodeError> {
+        let factor = self.wallet_factor();
+        let mut unspent = self.inner_client.list_unspent(min_conf, max_conf).await?;
+        for output in &mut unspent {
+            output.satoshis /= factor;
+        }
+        Ok(unspent)
+    }
+
+    async fn sign_raw_transaction_with_wallet(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<SignedTransaction, NodeError> {
+        self.inner_client
+            .sign_raw_transaction_with_wallet(raw_tx)
+            .await
+    }
+
+    async fn send_to_address(&self, address: &str, satoshis: u64) -> Result<String, NodeError> {
+        let satoshis = satoshis
+            .checked_mul(self.wallet_factor())
+            .ok_or(AmountError::OutOfRange)?;
+        self.inner_client.send_to_address(address, satoshis).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_fee_policy() {
+        assert_eq!(
+            Chain::Bch.translate_fee_policy(FeePolicy::MaxFeeRate(0.0)),
+            FeePolicy::AllowHighFees(true)
+        );
+        assert_eq!(
+            Chain::Bch.translate_fee_policy(FeePolicy::MaxFeeRate(0.1)),
+            FeePolicy::Default
+        );
+        assert_eq!(
+            Chain::Xpi.translate_fee_policy(FeePolicy::AllowHighFees(true)),
+            FeePolicy::MaxFeeRate(0.0)
+        );
+        assert_eq!(
+            Chain::Xec.translate_fee_policy(FeePolicy::MaxFeeRate(0.1)),
+            FeePolicy::MaxFeeRate(0.1)
+        );
+    }
+
+    #[test]
+    fn satoshis() {
+        assert_eq!(Chain::Bch.amount_to_satoshis(0.00000123), Ok(123));
+        assert_eq!(Chain::Xec.amount_to_satoshis(1.23), Ok(123));
+        assert_eq!(Chain::Xpi.amount_to_satoshis(1.5), Ok(1_500_000));
+        assert_eq!(Chain::Bch.amount_to_satoshis(-0.1), Ok(-10_000_000));
+        assert_eq!(
+            Chain::Bch.amount_to_satoshis(20_999_999.976_9),
+            Ok(2_099_999_997_690_000)
+        );
+        assert_eq!(Chain::Xpi.satoshis_to_amount(1_500_000), "1.500000");
+        assert_eq!(Chain::Bch.satoshis_to_amount(123), "0.00000123");
+        assert_eq!(Chain::Xec.satoshis_to_amount(-5), "-0.05");
+
+        assert_eq!(Chain::Bch.parse_amount("1.10000000000"), Ok(110_000_000));
+        assert_eq!(
+            Chain::Xec.amount_to_satoshis(0.001),
+            Err(AmountError::Precision(2))
+        );
+        assert_eq!(
+            Chain::Bch.amount_to_satoshis(1e300),
+            Err(AmountError::OutOfRange)
+        );
+        assert!(matches!(
+            Chain::Bch.amount_to_satoshis(f64::NAN),
+            Err(AmountError::Malformed(_))
+        ));
+        assert!(matches!(
+            Chain::Bch.amount_to_satoshis(f64::INFINITY),
+            Err(AmountError::Malformed(_))
+        ));
+        assert!(matches!(
+            Chain::Bch.parse_amount("1e-8"),
+            Err(AmountError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,808 @@
+//! This module contains the [`HeaderChain`] which syncs and validates block headers from
+//! bitcoind, and verifies the inclusion of transactions using merkle proofs.
+//!
+//! Headers are validated from a trusted checkpoint: each must link to its predecessor, commit to
+//! the target required by the difficulty adjustment algorithm of the [`ChainParams`], meet that
+//! target, and have a timestamp above the median of the preceding blocks. When bitcoind reports a
+//! competing branch, it is only adopted if it carries more cumulative work than the current chain.
+//! A node serving an invalid header or a low-work fork is detected rather than trusted, allowing
+//! payments to be validated without relying on a single RPC response.
+
+use std::collections::HashMap;
+
+use cashweb_bitcoin::{
+    block::{Block, BlockHeader},
+    merkle::{sha256d, MerkleProof},
+    pow::{self, BitsError},
+    transaction::Transaction,
+};
+use thiserror::Error;
+
+use crate::{chain::ChainClient, NodeError};
+
+/// The proof-of-work limit of regtest networks.
+pub const REGTEST_POW_LIMIT: u32 = 0x207f_ffff;
+
+/// Number of preceding blocks used to calculate the median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Default ideal time between blocks, in seconds.
+pub const DEFAULT_TARGET_SPACING: u64 = 600;
+
+/// Default half-life of the aserti3-2d algorithm, in seconds.
+pub const DEFAULT_HALF_LIFE: u64 = 2 * 24 * 60 * 60;
+
+/// Fixed-point radix of the aserti3-2d exponent.
+const ASERT_RADIX: i64 = 1 << 16;
+
+/// Parameters of the aserti3-2d difficulty adjustment algorithm, which retargets exponentially
+/// according to how far the chain is ahead of or behind the ideal schedule since an anchor block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AsertParams {
+    /// Height of the anchor block.
+    pub anchor_height: i32,
+    /// Target of the anchor block, in compact encoding.
+    pub anchor_bits: u32,
+    /// Timestamp of the parent of the anchor block.
+    pub anchor_parent_time: u64,
+    /// Ideal time between blocks, in seconds.
+    pub target_spacing: u64,
+    /// Time the chain must fall behind the ideal schedule to double the target, in seconds.
+    pub half_life: u64,
+}
+
+impl AsertParams {
+    /// Create parameters from the anchor block, with the default target spacing and half-life.
+    pub fn new(anchor_height: i32, anchor_bits: u32, anchor_parent_time: u64) -> Self {
+        Self {
+            anchor_height,
+            anchor_bits,
+            anchor_parent_time,
+            target_spacing: DEFAULT_TARGET_SPACING,
+            half_life: DEFAULT_HALF_LIFE,
+        }
+    }
+
+    /// Set the ideal time between blocks, in seconds.
+    pub fn with_target_spacing(mut self, target_spacing: u64) -> Self {
+        self.target_spacing = target_spacing;
+        self
+    }
+
+    /// Set the half-life, in seconds.
+    pub fn with_half_life(mut self, half_life: u64) -> Self {
+        self.half_life = half_life;
+        self
+    }
+
+    /// Calculate the target of the block following the tip, clamped to the limit.
+    fn next_target(
+        &self,
+        tip: &BlockHeader,
+        limit: &pow::Target,
+    ) -> Result<pow::Target, HeaderError> {
+        if tip.height < self.anchor_height {
+            return Err(HeaderError::PrecedesAnchor);
+        }
+        let anchor_target = pow::target_from_bits(self.anchor_bits).map_err(HeaderError::Bits)?;
+
+        let time_delta = tip.time as i64 - self.anchor_parent_time as i64;
+        let height_delta = i64::from(tip.height - self.anchor_height);
+        let exponent = ((time_delta - self.target_spacing as i64 * (height_delta + 1))
+            * ASERT_RADIX)
+            / self.half_life as i64;
+
+        // Approximate 2^fraction using a cubic polynomial, then shift by the integral part
+        let shifts = exponent >> 16;
+        let fraction = u128::from(exponent as u16);
+        let factor = ASERT_RADIX as u128
+            + ((195_766_423_245_049 * fraction
+                + 971_821_376 * fraction * fraction
+                + 5127 * fraction * fraction * fraction
+                + (1 << 47))
+                >> 48);
+        // This is safe as the factor is below 2^17
+        let target = match pow::multiply(&anchor_target, factor as u32) {
+            Some(target) => target,
+            None => return Ok(*limit),
+        };
+        let shifts = shifts - 16;
+        let target = if shifts < 0 {
+            pow::shift_right(&target, shifts.unsigned_abs().min(256) as u32)
+        } else {
+            pow::shift_left(&target, shifts.min(256) as u32)
+        };
+
+        if target == [0; 32] {
+            let mut one = [0; 32];
+            one[31] = 1;
+            return Ok(one);
+        }
+        Ok(target.min(*limit))
+    }
+}
+
+/// Difficulty adjustment algorithm determining the target of each block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Daa {
+    /// The target never changes, as on regtest networks.
+    Fixed,
+    /// The aserti3-2d algorithm.
+    Asert(AsertParams),
+}
+
+/// Consensus parameters used to validate headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainParams {
+    /// The easiest target permitted, in compact encoding.
+    pub pow_limit: u32,
+    /// The difficulty adjustment algorithm.
+    pub daa: Daa,
+    /// Whether a block more than twice the target spacing after its predecessor may use the
+    /// proof-of-work limit, as on test networks.
+    pub allow_min_difficulty_blocks: bool,
+}
+
+impl ChainParams {
+    /// Create parameters with the proof-of-work limit and difficulty adjustment algorithm.
+    pub fn new(pow_limit: u32, daa: Daa) -> Self {
+        Self {
+            pow_limit,
+            daa,
+            allow_min_difficulty_blocks: false,
+        }
+    }
+
+    /// Parameters of regtest networks, which never retarget.
+    pub fn regtest() -> Self {
+        Self::new(REGTEST_POW_LIMIT, Daa::Fixed)
+    }
+
+    /// Set whether blocks may use the proof-of-work limit after more than twice the target
+    /// spacing.
+    pub fn with_min_difficulty_blocks(mut self, allow_min_difficulty_blocks: bool) -> Self {
+        self.allow_min_difficulty_blocks = allow_min_difficulty_blocks;
+        self
+    }
+
+    /// Calculate the target, in compact encoding, required of a block with the timestamp
+    /// following the tip.
+    pub fn next_bits(&self, tip: &BlockHeader, time: u64) -> Result<u32, HeaderError> {
+        let limit = pow::target_from_bits(self.pow_limit).map_err(HeaderError::Bits)?;
+        match self.daa {
+            Daa::Fixed => Ok(tip.bits),
+            Daa::Asert(asert) => {
+                let spacing = asert.target_spacing;
+                if self.allow_min_difficulty_blocks && time > tip.time + 2 * spacing {
+                    return Ok(self.pow_limit);
+                }
+                let target = asert.next_target(tip, &limit)?;
+                Ok(pow::bits_from_target(&target))
+            }
+        }
+    }
+}
+
+/// Error associated with validating a [`BlockHeader`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum HeaderError {
+    /// The target could not be decoded.
+    #[error("invalid bits: {0}")]
+    Bits(BitsError),
+    /// The header did not build upon the tip.
+    #[error("header does not connect to the tip")]
+    UnexpectedPrevious,
+    /// The header height did not follow the tip.
+    #[error("unexpected height: expected {expected}, found {found}")]
+    UnexpectedHeight {
+        /// Height following the tip.
+        expected: i32,
+        /// Height of the header.
+        found: i32,
+    },
+    /// The target was easier than the proof-of-work limit.
+    #[error("target above proof-of-work limit")]
+    AboveLimit,
+    /// The target differed from that required by the difficulty adjustment algorithm.
+    #[error("unexpected bits: expected {expected:#010x}, found {found:#010x}")]
+    UnexpectedBits {
+        /// Target required by the difficulty adjustment algorithm, in compact encoding.
+        expected: u32,
+        /// Target of the header, in compact encoding.
+        found: u32,
+    },
+    /// The tip precedes the anchor block of the difficulty adjustment algorithm.
+    #[error("tip precedes the difficulty adjustment anchor")]
+    PrecedesAnchor,
+    /// The block hash did not meet the target.
+    #[error("insufficient proof-of-work")]
+    InsufficientWork,
+    /// The timestamp was not above the median time past.
+    #[error("timestamp {time} not above median time past {median_time_past}")]
+    TimeTooOld {
+        /// Timestamp of the header.
+        time: u64,
+        /// Median timestamp of the preceding blocks.
+        median_time_past: u64,
+    },
+}
+
+/// Error associated with syncing headers.
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// Error communicating with bitcoind.
+    #[error(transparent)]
+    Node(#[from] NodeError),
+    /// bitcoind served an invalid header.
+    #[error("invalid header at height {height}: {error}")]
+    InvalidHeader {
+        /// Height of the header.
+        height: i32,
+        /// Reason the header was invalid.
+        error: HeaderError,
+    },
+    /// bitcoind served a header which did not match the requested hash.
+    #[error("header does not match block hash")]
+    HashMismatch,
+    /// bitcoind's best chain does not contain the checkpoint.
+    #[error("best chain diverges from the checkpoint")]
+    CheckpointReorg,
+    /// bitcoind's best chain forks from the header chain with less cumulative work.
+    #[error("best chain has less work than the header chain")]
+    InsufficientChainWork,
+}
+
+/// Summary of a [`HeaderChain::sync`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Number of headers connected.
+    pub connected: usize,
+    /// Number of headers disconnected by reorganizations.
+    pub disconnected: usize,
+}
+
+/// Error associated with verifying the inclusion of a transaction.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InclusionError {
+    /// The block is not in the header chain.
+    #[error("unknown block")]
+    UnknownBlock,
+    /// The proof does not commit to the merkle root of the block.
+    #[error("invalid merkle proof")]
+    InvalidProof,
+}
+
+/// Calculate the merkle leaf of a transaction, committing to both its hash and its ID.
+pub fn transaction_leaf(transaction: &Transaction) -> [u8; 32] {
+    sha256d(&[transaction.transaction_hash(), transaction.transaction_id()].concat())
+}
+
+/// Construct the [`MerkleProof`] of the transaction with the ID, in little-endian format, if it
+/// is in the block.
+pub fn prove_transaction(block: &Block, transaction_id: &[u8; 32]) -> Option<MerkleProof> {
+    let index = block
+        .iter()
+        .position(|transaction| transaction.transaction_id() == *transaction_id)?;
+    let leaves: Vec<[u8; 32]> = block.iter().map(transaction_leaf).collect();
+    MerkleProof::from_leaves(&leaves, index)
+}
+
+/// A chain of validated [`BlockHeader`]s, starting from a trusted checkpoint.
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    params: ChainParams,
+    headers: Vec<BlockHeader>,
+    hashes: Vec<[u8; 32]>,
+    chain_work: Vec<pow::Work>,
+    heights: HashMap<[u8; 32], usize>,
+}
+
+impl HeaderChain {
+    /// Create a chain from the trusted checkpoint.
+    pub fn new(params: ChainParams, checkpoint: BlockHeader) -> Self {
+        let hash = checkpoint.hash();
+        let work = checkpoint
+            .target()
+            .map(|target| pow::work(&target))
+            .unwrap_or_default();
+        let mut heights = HashMap::new();
+        heights.insert(hash, 0);
+        Self {
+            params,
+            headers: vec![checkpoint],
+            hashes: vec![hash],
+            chain_work: vec![work],
+            heights,
+        }
+    }
+
+    /// Get the tip of the chain.
+    pub fn tip(&self) -> &BlockHeader {
+        // This is safe as the checkpoint is never disconnected
+        self.headers.last().unwrap()
+    }
+
+    /// Get the hash of the tip of the chain, in little-endian format.
+    pub fn tip_hash(&self) -> [u8; 32] {
+        // This is safe as the checkpoint is never disconnected
+        *self.hashes.last().unwrap()
+    }
+
+    /// Get the cumulative work of the chain, counted from the checkpoint.
+    pub fn chain_work(&self) -> pow::Work {
+        // This is safe as the checkpoint is never disconnected
+        *self.chain_work.last().unwrap()
+    }
+
+    /// Get the header with the hash, in little-endian format.
+    pub fn get(&self, block_hash: &[u8; 32]) -> Option<&BlockHeader> {
+        self.heights
+            .get(block_hash)
+            .map(|position| &self.headers[*position])
+    }
+
+    /// Number of confirmations of the block with the hash, in little-endian format, if it is in
+    /// the chain. The tip has one confirmation.
+    pub fn confirmations(&self, block_hash: &[u8; 32]) -> Option<usize> {
+        self.heights
+            .get(block_hash)
+            .map(|position| self.headers.len() - position)
+    }
+
+    fn median_time_past(&self) -> u64 {
+        let start = self.headers.len().saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times: Vec<u64> = self.headers[start..]
+            .iter()
+            .map(|header| header.time)
+            .collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// Validate the header against the tip, without connecting it.
+    pub fn validate(&self, header: &BlockHeader) -> Result<(), HeaderError> {
+        let tip = self.tip();
+        if header.prev_block_hash != self.tip_hash() {
+            return Err(HeaderError::UnexpectedPrevious);
+        }
+        let expected = tip.height.wrapping_add(1);
+        if header.height != expected {
+            return Err(HeaderError::UnexpectedHeight {
+                expected,
+                found: header.height,
+            });
+        }
+
+        // Check the target
+        let expected = self.params.next_bits(tip, header.time)?;
+        if header.bits != expected {
+            return Err(HeaderError::UnexpectedBits {
+                expected,
+                found: header.bits,
+            });
+        }
+        let target = header.target().map_err(HeaderError::Bits)?;
+        let limit = pow::target_from_bits(self.params.pow_limit).map_err(HeaderError::Bits)?;
+        if target > limit {
+            return Err(HeaderError::AboveLimit);
+        }
+
+        // Check the timestamp
+        let median_time_past = self.median_time_past();
+        if header.time <= median_time_past {
+            return Err(HeaderError::TimeTooOld {
+                time: header.time,
+                median_time_past,
+            });
+        }
+
+        // Check the proof-of-work
+        if !pow::meets_target(&header.hash(), &target) {
+            return Err(HeaderError::InsufficientWork);
+        }
+        Ok(())
+    }
+
+    /// Validate the header and connect it to the tip, returning its hash in little-endian format.
+    pub fn connect(&mut self, header: BlockHeader) -> Result<[u8; 32], HeaderError> {
+        self.validate(&header)?;
+        Ok(self.push(header))
+    }
+
+    fn push(&mut self, header: BlockHeader) -> [u8; 32] {
+        let hash = header.hash();
+        let work = header
+            .target()
+            .map(|target| pow::work(&target))
+            .unwrap_or_default();
+        self.chain_work
+            .push(pow::add_work(&self.chain_work(), &work));
+        self.heights.insert(hash, self.headers.len());
+        self.headers.push(header);
+        self.hashes.push(hash);
+        hash
+    }
+
+    /// Disconnect the tip, unless it is the checkpoint.
+    pub fn disconnect(&mut self) -> Option<BlockHeader> {
+        if self.headers.len() == 1 {
+            return None;
+        }
+        // This is safe as the chain contains more than the checkpoint
+        let hash = self.hashes.pop().unwrap();
+        self.heights.remove(&hash);
+        self.chain_work.pop();
+        self.headers.pop()
+    }
+
+    /// Disconnect headers above the position, returning them in order of height.
+    fn truncate(&mut self, position: usize) -> Vec<BlockHeader> {
+        let mut disconnected = Vec::new();
+        while self.headers.len() > position + 1 {
+            // This is safe as the chain contains more than the checkpoint
+            disconnected.push(self.disconnect().unwrap());
+        }
+        disconnected.reverse();
+        disconnected
+    }
+
+    /// Replace headers above the position with those previously disconnected.
+    fn restore(&mut self, position: usize, disconnected: Vec<BlockHeader>) {
+        self.truncate(position);
+        for header in disconnected {
+            self.push(header);
+        }
+    }
+
+    /// Find the position of the highest header shared with the best chain of bitcoind.
+    async fn find_fork<C>(&self, client: &C, best_height: i64) -> Result<usize, SyncError>
+    where
+        C: ChainClient + Sync,
+    {
+        let checkpoint_height = i64::from(self.headers[0].height);
+        if best_height < checkpoint_height {
+            return Err(SyncError::CheckpointReorg);
+        }
+        let mut position = (self.headers.len() - 1).min((best_height - checkpoint_height) as usize);
+        loop {
+            let hash = client
+                .get_block_hash((checkpoint_height + position as i64) as u64)
+                .await?;
+            if hash[..] == self.headers[position].hash_rev()[..] {
+                return Ok(position);
+            }
+            if position == 0 {
+                return Err(SyncError::CheckpointReorg);
+            }
+            position -= 1;
+        }
+    }
+
+    /// Verify that the leaf is included in the block with the hash, in little-endian format,
+    /// returning the number of confirmations of the block.
+    pub fn verify_inclusion(
+        &self,
+        block_hash: &[u8; 32],
+        leaf: [u8; 32],
+        proof: &MerkleProof,
+    ) -> Result<usize, InclusionError> {
+        let header = self.get(block_hash).ok_or(InclusionError::UnknownBlock)?;
+        if !proof.verify(leaf, &header.merkle_root) {
+            return Err(InclusionError::InvalidProof);
+        }
+        // This is safe as the block is in the chain
+        Ok(self.confirmations(block_hash).unwrap())
+    }
+
+    /// Verify that the transaction is included in the block with the hash, in little-endian
+    /// format, returning the number of confirmations of the block.
+    pub fn verify_transaction(
+        &self,
+        block_hash: &[u8; 32],
+        transaction: &Transaction,
+        proof: &MerkleProof,
+    ) -> Result<usize, InclusionError> {
+        self.verify_inclusion(block_hash, transaction_leaf(transaction), proof)
+    }
+
+    /// Sync headers with the best chain of bitcoind, validating each header and following
+    /// reorganizations back as far as the checkpoint.
+    ///
+    /// A branch forking from the chain is only adopted if it is valid and has more cumulative
+    /// work than the headers it replaces, otherwise the chain is left unchanged.
+    pub async fn sync<C>(&mut self, client: &C) -> Result<SyncReport, SyncError>
+    where
+        C: ChainClient + Sync,
+    {
+        let best_height = client.get_block_count().await? as i64;
+        let fork = self.find_fork(client, best_height).await?;
+        let previous_work = self.chain_work();
+        let disconnected = self.truncate(fork);
+
+        let mut report = SyncReport {
+            connected: 0,
+            disconnected: disconnected.len(),
+        };
+        let fork_height = i64::from(self.headers[0].height) + fork as i64;
+        for height in fork_height + 1..=best_height {
+            let result = self.fetch_and_connect(client, height).await;
+            if let Err(error) = result {
+                if !disconnected.is_empty() {
+                    self.restore(fork, disconnected);
+                }
+                return Err(error);
+            }
+            report.connected += 1;
+        }
+
+        if !disconnected.is_empty() && self.chain_work() <= previous_work {
+            self.restore(fork, disconnected);
+            return Err(SyncError::InsufficientChainWork);
+        }
+        Ok(report)
+    }
+
+    async fn fetch_and_connect<C>(&mut self, client: &C, height: i64) -> Result<(), SyncError>
+    where
+        C: ChainClient + Sync,
+    {
+        let hash = client.get_block_hash(height as u64).await?;
+        let header = client.get_block_header(&hash).await?;
+        if header.hash_rev()[..] != hash[..] {
+            return Err(SyncError::HashMismatch);
+        }
+        self.connect(header)
+            .map_err(|error| SyncError::InvalidHeader {
+                height: height as i32,
+                error,
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// Mine a header building upon the previous header.
+    fn mine(prev: &BlockHeader, merkle_root: [u8; 32]) -> BlockHeader {
+        let mut header = BlockHeader {
+            prev_block_hash: prev.hash(),
+            bits: REGTEST_POW_LIMIT,
+            time: prev.time + 600,
+            height: prev.height + 1,
+            merkle_root,
+            ..Default::default()
+        };
+        while !header.check_proof_of_work().unwrap() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn genesis() -> BlockHeader {
+        BlockHeader {
+            bits: REGTEST_POW_LIMIT,
+            time: 1_600_000_000,
+            ..Default::default()
+        }
+    }
+
+    struct MockChain {
+        headers: Mutex<Vec<BlockHeader>>,
+    }
+
+    #[async_trait]
+    impl ChainClient for MockChain {
+        async fn get_block_count(&self) -> Result<u64, NodeError> {
+            Ok(self.headers.lock().unwrap().len() as u64 - 1)
+        }
+
+        async fn get_block_hash(&self, height: u64) -> Result<Vec<u8>, NodeError> {
+            Ok(self.headers.lock().unwrap()[height as usize]
+                .hash_rev()
+                .to_vec())
+        }
+
+        async fn get_block_header(&self, block_hash: &[u8]) -> Result<BlockHeader, NodeError> {
+            self.headers
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|header| header.hash_rev()[..] == *block_hash)
+                .cloned()
+                .ok_or(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_block(&self, _block_hash: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let genesis = genesis();
+        let mut chain = HeaderChain::new(ChainParams::regtest(), genesis.clone());
+        let header = mine(&genesis, [0; 32]);
+
+        let mut wrong_height = header.clone();
+        wrong_height.height = 5;
+        assert_eq!(
+            chain.validate(&wrong_height),
+            Err(HeaderError::UnexpectedHeight {
+                expected: 1,
+                found: 5
+            })
+        );
+
+        let mut too_old = header.clone();
+        too_old.time = genesis.time;
+        assert!(matches!(
+            chain.validate(&too_old),
+            Err(HeaderError::TimeTooOld { .. })
+        ));
+
+        let mut too_hard = header.clone();
+        too_hard.bits = 0x1d00_ffff;
+        assert_eq!(
+            chain.validate(&too_hard),
+            Err(HeaderError::UnexpectedBits {
+                expected: REGTEST_POW_LIMIT,
+                found: 0x1d00_ffff
+            })
+        );
+
+        let hash = chain.connect(header).unwrap();
+        assert_eq!(chain.tip_hash(), hash);
+        assert_eq!(
+            chain.validate(&mine(&genesis, [0; 32])),
+            Err(HeaderError::UnexpectedPrevious)
+        );
+    }
+
+    #[test]
+    fn asert() {
+        let asert = AsertParams::new(100, 0x1804_dafe, 1_600_000_000);
+        let params = ChainParams::new(0x1d00_ffff, Daa::Asert(asert));
+        let anchor = BlockHeader {
+            bits: 0x1804_dafe,
+            time: 1_600_000_600,
+            height: 100,
+            ..Default::default()
+        };
+
+        // On schedule the target is unchanged
+        assert_eq!(
+            params.next_bits(&anchor, anchor.time + 600),
+            Ok(0x1804_dafe)
+        );
+        let mut on_schedule = anchor.clone();
+        on_schedule.height = 110;
+        on_schedule.time += 6000;
+        assert_eq!(params.next_bits(&on_schedule, 0), Ok(0x1804_dafe));
+
+        // Falling a half-life behind doubles the target, and a half-life ahead halves it
+        let mut behind = anchor.clone();
+        behind.time += DEFAULT_HALF_LIFE;
+        assert_eq!(params.next_bits(&behind, 0), Ok(0x1809_b5fc));
+        let mut ahead = anchor.clone();
+        ahead.time -= DEFAULT_HALF_LIFE;
+        assert_eq!(params.next_bits(&ahead, 0), Ok(0x1802_6d7f));
+
+        // Part of a half-life is interpolated
+        let mut slightly_behind = anchor.clone();
+        slightly_behind.time += DEFAULT_HALF_LIFE / 2;
+        let bits = params.next_bits(&slightly_behind, 0).unwrap();
+        assert!(bits > 0x1806_dc00 && bits < 0x1806_e000);
+
+        // The target is clamped to the proof-of-work limit
+        let mut stalled = anchor.clone();
+        stalled.time += 100 * DEFAULT_HALF_LIFE;
+        assert_eq!(params.next_bits(&stalled, 0), Ok(0x1d00_ffff));
+
+        // Test networks permit the proof-of-work limit after a long gap
+        let testnet = params.clone().with_min_difficulty_blocks(true);
+        assert_eq!(
+            testnet.next_bits(&anchor, anchor.time + 1200),
+            Ok(0x1804_dafe)
+        );
+        assert_eq!(
+            testnet.next_bits(&anchor, anchor.time + 1201),
+            Ok(0x1d00_ffff)
+        );
+
+        let mut early = anchor;
+        early.height = 99;
+        assert_eq!(
+            params.next_bits(&early, 0),
+            Err(HeaderError::PrecedesAnchor)
+        );
+    }
+
+    #[test]
+    fn inclusion() {
+        let leaves = [[1; 32], [2; 32], [3; 32]];
+        let proof = MerkleProof::from_leaves(&leaves, 2).unwrap();
+        let merkle_root = proof.root([3; 32]);
+
+        let genesis = genesis();
+        let mut chain = HeaderChain::new(ChainParams::regtest(), genesis.clone());
+        let block = mine(&genesis, merkle_root);
+        let block_hash = chain.connect(block.clone()).unwrap();
+        chain.connect(mine(&block, [0; 32])).unwrap();
+
+        assert_eq!(chain.verify_inclusion(&block_hash, [3; 32], &proof), Ok(2));
+        assert_eq!(
+            chain.verify_inclusion(&block_hash, [2; 32], &proof),
+            Err(InclusionError::InvalidProof)
+        );
+        assert_eq!(
+            chain.verify_inclusion(&[0; 32], [3; 32], &proof),
+            Err(InclusionError::UnknownBlock)
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_and_reorg() {
+        let genesis = genesis();
+        let mut headers = vec![genesis.clone()];
+        for _ in 0..3 {
+            let next = mine(headers.last().unwrap(), [0; 32]);
+            headers.push(next);
+        }
+        let client = MockChain {
+            headers: Mutex::new(headers),
+        };
+
+        let mut chain = HeaderChain::new(ChainParams::regtest(), genesis);
+        let report = chain.sync(&client).await.unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                connected: 3,
+                disconnected: 0
+            }
+        );
+        assert_eq!(chain.tip().height, 3);
+
+        // Replace the last two headers with a longer fork
+        {
+            let mut headers = client.headers.lock().unwrap();
+            headers.truncate(2);
+            for _ in 0..3 {
+                let next = mine(headers.last().unwrap(), [1; 32]);
+                headers.push(next);
+            }
+        }
+        let report = chain.sync(&client).await.unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                connected: 3,
+                disconnected: 2
+            }
+        );
+        assert_eq!(chain.tip().merkle_root, [1; 32]);
+        assert_eq!(chain.tip().height, 4);
+
+        // A fork with less work is rejected, leaving the chain unchanged
+        let tip_hash = chain.tip_hash();
+        let chain_work = chain.chain_work();
+        {
+            let mut headers = client.headers.lock().unwrap();
+            headers.truncate(2);
+            let next = mine(headers.last().unwrap(), [2; 32]);
+            headers.push(next);
+        }
+        assert!(matches!(
+            chain.sync(&client).await,
+            Err(SyncError::InsufficientChainWork)
+        ));
+        assert_eq!(chain.tip_hash(), tip_hash);
+        assert_eq!(chain.chain_work(), chain_work);
+    }
+}
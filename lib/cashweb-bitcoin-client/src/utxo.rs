@@ -0,0 +1,500 @@
+//! This module contains the [`UtxoCache`] which maintains, in process, the outputs created and not
+//! yet spent by the blocks connected by a [`ChainFollower`].
+//!
+//! Blocks are applied as [`ChainEvent`]s. Each connected block records the outputs it created and
+//! spent, so that it can be rolled back when disconnected by a reorganization. Lookups of recent
+//! outputs, of outputs recently spent and of the commitment transactions of retained blocks are
+//! then answered without a round trip to bitcoind.
+//!
+//! The number of cached outputs is capped, evicting the oldest first, so memory use is bounded
+//! however long the cache follows the chain.
+//!
+//! [`ChainFollower`]: crate::follower::ChainFollower
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use cashweb_bitcoin::{
+    block::Block,
+    transaction::{outpoint::Outpoint, output::Output, Transaction},
+};
+use thiserror::Error;
+
+use crate::follower::ChainEvent;
+
+/// Default maximum number of outputs held by a [`UtxoCache`].
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// Error associated with applying a [`ChainEvent`] to a [`UtxoCache`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum UtxoCacheError {
+    /// The disconnected block was not the last block connected.
+    #[error("disconnected block is not the tip of the cache")]
+    NotTip,
+    /// The reorganization was deeper than the blocks retained by the cache.
+    ///
+    /// The cache is cleared, and repopulated by the blocks connected subsequently.
+    #[error("reorganization deeper than {0} blocks")]
+    ReorgTooDeep(usize),
+}
+
+/// An unspent output held by a [`UtxoCache`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedUtxo {
+    /// The output.
+    pub output: Output,
+    /// Height of the block which created the output.
+    pub height: i32,
+    /// Whether the output was created by a coinbase transaction.
+    pub coinbase: bool,
+    /// The [`commitments`] of the transaction which created the output.
+    pub commitments: Vec<Vec<u8>>,
+}
+
+/// The data pushed by the `OP_RETURN` outputs of the transaction which consist of a single push,
+/// such as the commitments of POP payments.
+pub fn commitments(transaction: &Transaction) -> Vec<Vec<u8>> {
+    transaction
+        .outputs
+        .iter()
+        .filter_map(|output| match output.script.op_return_data()?.as_slice() {
+            [data] => Some(data.to_vec()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The changes made by a connected block, to be reverted when it is disconnected.
+#[derive(Debug)]
+struct BlockUndo {
+    hash: [u8; 32],
+    created: Vec<Outpoint>,
+    spent: Vec<(Outpoint, CachedUtxo)>,
+    transactions: Vec<[u8; 32]>,
+}
+
+/// A set of outpoints which forgets the oldest once over capacity.
+#[derive(Debug, Default)]
+struct BoundedSet {
+    outpoints: HashSet<Outpoint>,
+    order: VecDeque<Outpoint>,
+}
+
+impl BoundedSet {
+    /// Insert the outpoint, returning those evicted to stay within capacity.
+    fn insert(&mut self, outpoint: Outpoint, capacity: usize) -> Vec<Outpoint> {
+        if self.outpoints.insert(outpoint.clone()) {
+            self.order.push_back(outpoint);
+        }
+        let mut evicted = Vec::new();
+        while self.outpoints.len() > capacity {
+            // This is safe as every outpoint in the set is in the queue
+            let oldest = self.order.pop_front().unwrap();
+            if self.outpoints.remove(&oldest) {
+                evicted.push(oldest);
+            }
+        }
+        self.compact();
+        evicted
+    }
+
+    fn remove(&mut self, outpoint: &Outpoint) {
+        if self.outpoints.remove(outpoint) {
+            self.compact();
+        }
+    }
+
+    /// Drop removed outpoints from the queue once they dominate it.
+    fn compact(&mut self) {
+        if self.order.len() > 2 * self.outpoints.len() + 1 {
+            let outpoints = &self.outpoints;
+            self.order.retain(|outpoint| outpoints.contains(outpoint));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.outpoints.clear();
+        self.order.clear();
+    }
+}
+
+#[derive(Debug, Default)]
+struct UtxoSet {
+    utxos: HashMap<Outpoint, CachedUtxo>,
+    // The cached outpoints in order of insertion, evicting the oldest
+    cached: BoundedSet,
+    spent: BoundedSet,
+    transactions: HashMap<[u8; 32], Transaction>,
+    undo: VecDeque<BlockUndo>,
+}
+
+impl UtxoSet {
+    fn insert(&mut self, outpoint: Outpoint, utxo: CachedUtxo, capacity: usize) {
+        self.spent.remove(&outpoint);
+        self.utxos.insert(outpoint.clone(), utxo);
+        for evicted in self.cached.insert(outpoint, capacity) {
+            self.utxos.remove(&evicted);
+        }
+    }
+
+    fn remove(&mut self, outpoint: &Outpoint) -> Option<CachedUtxo> {
+        self.cached.remove(outpoint);
+        self.utxos.remove(outpoint)
+    }
+
+    fn forget_block(&mut self, undo: &BlockUndo) {
+        for tx_id in &undo.transactions {
+            self.transactions.remove(tx_id);
+        }
+    }
+}
+
+/// An outpoint-indexed cache of unspent outputs, fed by [`ChainEvent`]s.
+///
+/// The cache only holds outputs created since it began following the chain, and evicts the oldest
+/// once it holds `capacity` outputs, so a miss does not imply the output is spent. Outputs seen
+/// spent by a connected block are remembered, up to the same capacity, so that they are rejected
+/// without a round trip. It retains the changes of the most recent `max_depth` blocks, which
+/// should match that of the [`ChainFollower`] feeding it, along with the transactions of those
+/// blocks which commit data.
+///
+/// Clones of the cache share its contents.
+///
+/// [`ChainFollower`]: crate::follower::ChainFollower
+#[derive(Clone, Debug)]
+pub struct UtxoCache {
+    max_depth: usize,
+    capacity: usize,
+    set: Arc<RwLock<UtxoSet>>,
+}
+
+impl UtxoCache {
+    /// Create an empty [`UtxoCache`], able to roll back at most `max_depth` blocks and holding
+    /// at most [`DEFAULT_CAPACITY`] outputs.
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            capacity: DEFAULT_CAPACITY,
+            set: Default::default(),
+        }
+    }
+
+    /// Set the maximum number of cached outputs, and of remembered spent outputs.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Whether the cache saw the output at the outpoint spent by a connected block.
+    pub fn is_spent(&self, outpoint: &Outpoint) -> bool {
+        self.set.read().unwrap().spent.outpoints.contains(outpoint)
+    }
+
+    /// Get the transaction with the ID, in little-endian format, if it commits data and was
+    /// confirmed by one of the retained blocks.
+    pub fn get_transaction(&self, tx_id: &[u8; 32]) -> Option<Transaction> {
+        self.set.read().unwrap().transactions.get(tx_id).cloned()
+    }
+
+    /// Get the unspent output at the outpoint, if cached.
+    pub fn get(&self, outpoint: &Outpoint) -> Option<CachedUtxo> {
+        self.set.read().unwrap().utxos.get(outpoint).cloned()
+    }
+
+    /// Whether the output at the outpoint is cached.
+    pub fn contains(&self, outpoint: &Outpoint) -> bool {
+        self.set.read().unwrap().utxos.contains_key(outpoint)
+    }
+
+    /// Number of cached outputs.
+    pub fn len(&self) -> usize {
+        self.set.read().unwrap().utxos.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.set.read().unwrap().utxos.is_empty()
+    }
+
+    /// Remove every cached output, transaction and change, as when the chain followed is lost.
+    pub fn clear(&self) {
+        let mut set = self.set.write().unwrap();
+        set.utxos.clear();
+        set.cached.clear();
+        set.spent.clear();
+        set.transactions.clear();
+        set.undo.clear();
+    }
+
+    /// Apply the block connected to, or disconnected from, the tip of the best chain.
+    pub fn apply(&self, event: &ChainEvent) -> Result<(), UtxoCacheError> {
+        match event {
+            ChainEvent::Connected(block) => {
+                self.connect(block);
+                Ok(())
+            }
+            ChainEvent::Disconnected(block) => self.disconnect(block),
+        }
+    }
+
+    /// Insert the outputs created by the block, and remove those it spends.
+    ///
+    /// OP_RETURN outputs are never cached, as they can never be spent.
+    pub fn connect(&self, block: &Block) {
+        let mut undo = BlockUndo {
+            hash: block.header.hash(),
+            created: Vec::new(),
+            spent: Vec::new(),
+            transactions: Vec::new(),
+        };
+        let mut created_here = HashSet::new();
+
+        let mut set = self.set.write().unwrap();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            // The coinbase spends no outputs
+            let coinbase = index == 0;
+            if !coinbase {
+                for input in &transaction.inputs {
+                    if let Some(utxo) = set.remove(&input.outpoint) {
+                        // Outputs created by the block are removed by rolling back its creations
+                        if !created_here.contains(&input.outpoint) {
+                            undo.spent.push((input.outpoint.clone(), utxo));
+                        }
+                    }
+                    set.spent.insert(input.outpoint.clone(), self.capacity);
+                }
+            }
+
+            let tx_id = transaction.transaction_id();
+            let commitments = commitments(transaction);
+            if !commitments.is_empty() {
+                set.transactions
+                    .insert(tx_id, transaction.clone().into_owned());
+                undo.transactions.push(tx_id);
+            }
+            for (vout, output) in transaction.outputs.iter().enumerate() {
+                if output.script.is_op_return() {
+                    continue;
+                }
+                let outpoint = Outpoint {
+                    tx_id,
+                    vout: vout as u32,
+                };
+                let utxo = CachedUtxo {
+                    output: output.clone().into_owned(),
+                    height: block.header.height,
+                    coinbase,
+                    commitments: commitments.clone(),
+                };
+                set.insert(outpoint.clone(), utxo, self.capacity);
+                created_here.insert(outpoint.clone());
+                undo.created.push(outpoint);
+            }
+        }
+
+        set.undo.push_back(undo);
+        if set.undo.len() > self.max_depth {
+            // This is safe as the undo is not empty
+            let expired = set.undo.pop_front().unwrap();
+            set.forget_block(&expired);
+        }
+    }
+
+    /// Roll back the block, which must be the last block connected, restoring the outputs it spent.
+    pub fn disconnect(&self, block: &Block) -> Result<(), UtxoCacheError> {
+        let mut set = self.set.write().unwrap();
+        let undo = match set.undo.back() {
+            Some(undo) if undo.hash == block.header.hash() => set.undo.pop_back().unwrap(), // This is safe as the undo exists
+            Some(_) => return Err(UtxoCacheError::NotTip),
+            None => {
+                // The changes of the block are unknown, so no cached output can be trusted
+                drop(set);
+                self.clear();
+                return Err(UtxoCacheError::ReorgTooDeep(self.max_depth));
+            }
+        };
+        for outpoint in &undo.created {
+            set.remove(outpoint);
+        }
+        // The outputs spent by the block are unspent once more
+        for transaction in &block.transactions {
+            for input in &transaction.inputs {
+                set.spent.remove(&input.outpoint);
+            }
+        }
+        set.forget_block(&undo);
+        for (outpoint, utxo) in undo.spent {
+            set.insert(outpoint, utxo, self.capacity);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_bitcoin::{
+        block::BlockHeader,
+        transaction::{input::Input, script::Script, Transaction},
+    };
+
+    use super::*;
+
+    fn output(value: u64) -> Output {
+        Output {
+            value,
+            script: Script::from(vec![0x51]),
+        }
+    }
+
+    fn spend(outpoint: &Outpoint, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            inputs: vec![Input {
+                outpoint: outpoint.clone(),
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![output(value)],
+            lock_time: 0,
+        }
+    }
+
+    fn block(height: i32, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                height,
+                ..Default::default()
+            },
+            metadata: vec![],
+            transactions,
+        }
+    }
+
+    fn outpoint(transaction: &Transaction) -> Outpoint {
+        Outpoint {
+            tx_id: transaction.transaction_id(),
+            vout: 0,
+        }
+    }
+
+    #[test]
+    fn connect_and_rollback() {
+        let cache = UtxoCache::new(2);
+        let coinbase = Transaction {
+            outputs: vec![
+                output(5_000),
+                Output {
+                    value: 0,
+                    script: Script::from(vec![0x6a]),
+                },
+            ],
+            ..Default::default()
+        };
+        let first = block(1, vec![coinbase.clone()]);
+        cache.apply(&ChainEvent::Connected(first)).unwrap();
+        let cached = cache.get(&outpoint(&coinbase)).unwrap();
+        assert!(cached.coinbase);
+        assert_eq!((cached.output.value, cached.height), (5_000, 1));
+        assert_eq!(cache.len(), 1);
+
+        // Spend the coinbase, and the spend within the same block
+        let spend_coinbase = spend(&outpoint(&coinbase), 4_000);
+        let spend_spend = spend(&outpoint(&spend_coinbase), 3_000);
+        let second = block(
+            2,
+            vec![
+                Transaction {
+                    version: 2,
+                    ..Default::default()
+                },
+                spend_coinbase.clone(),
+                spend_spend.clone(),
+            ],
+        );
+        cache.connect(&second);
+        assert!(!cache.contains(&outpoint(&coinbase)));
+        assert!(!cache.contains(&outpoint(&spend_coinbase)));
+        assert!(cache.contains(&outpoint(&spend_spend)));
+        assert!(cache.is_spent(&outpoint(&coinbase)));
+
+        // Only the tip can be disconnected
+        assert_eq!(
+            cache.apply(&ChainEvent::Disconnected(block(1, vec![]))),
+            Err(UtxoCacheError::NotTip)
+        );
+        cache.apply(&ChainEvent::Disconnected(second)).unwrap();
+        assert!(cache.contains(&outpoint(&coinbase)));
+        assert!(!cache.contains(&outpoint(&spend_coinbase)));
+        assert!(!cache.contains(&outpoint(&spend_spend)));
+        assert!(!cache.is_spent(&outpoint(&coinbase)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn capacity() {
+        let cache = UtxoCache::new(2).with_capacity(2);
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|version| Transaction {
+                version,
+                outputs: vec![output(1_000)],
+                ..Default::default()
+            })
+            .collect();
+        for (height, transaction) in transactions.iter().enumerate() {
+            cache.connect(&block(height as i32, vec![transaction.clone()]));
+        }
+
+        // The oldest output is evicted
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&outpoint(&transactions[0])));
+        assert!(cache.contains(&outpoint(&transactions[2])));
+    }
+
+    #[test]
+    fn commitment_transactions() {
+        let cache = UtxoCache::new(1);
+        let commitment = Transaction {
+            outputs: vec![Output {
+                value: 0,
+                script: Script::from(vec![0x6a, 0x01, 0x07]),
+            }],
+            ..Default::default()
+        };
+        let tx_id = commitment.transaction_id();
+        let first = block(1, vec![Transaction::default(), commitment.clone()]);
+        cache.connect(&first);
+        assert_eq!(cache.get_transaction(&tx_id), Some(commitment));
+
+        // Transactions are forgotten once their block is no longer retained
+        cache.connect(&block(2, vec![]));
+        assert_eq!(cache.get_transaction(&tx_id), None);
+    }
+
+    #[test]
+    fn reorg_too_deep() {
+        let cache = UtxoCache::new(1);
+        let blocks: Vec<_> = (0..2)
+            .map(|height| {
+                block(
+                    height,
+                    vec![Transaction {
+                        version: height as u32,
+                        outputs: vec![output(1_000)],
+                        ..Default::default()
+                    }],
+                )
+            })
+            .collect();
+        for block in &blocks {
+            cache.connect(block);
+        }
+        cache.disconnect(&blocks[1]).unwrap();
+        assert_eq!(
+            cache.disconnect(&blocks[0]),
+            Err(UtxoCacheError::ReorgTooDeep(1))
+        );
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,340 @@
+//! This module contains the [`WalletClient`] trait which wraps the wallet RPCs of bitcoind, for
+//! deployments which delegate key management to the node wallet.
+//!
+//! Amounts are given in satoshis and only converted to the coin units used by the RPC at the JSON
+//! boundary. The plain clients assume the eight decimal places of Bitcoin Cash Node, clients of
+//! other nodes should be wrapped in a [`ProfiledClient`](crate::profile::ProfiledClient) which
+//! rescales amounts for its [`Chain`].
+
+use std::convert::TryFrom;
+
+use async_trait::async_trait;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::{
+    call,
+    profile::{AmountError, Chain},
+    BitcoinClientHTTP, BitcoinClientTLS, BitcoinJsonClient, Connectable, NodeError,
+};
+
+/// The [`Chain`] whose coin units the plain clients assume.
+pub(crate) const RPC_CHAIN: Chain = Chain::Bch;
+
+/// Deserialize an amount in the coin units used by the RPC into satoshis.
+fn deserialize_satoshis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let amount = f64::deserialize(deserializer)?;
+    let satoshis = RPC_CHAIN
+        .amount_to_satoshis(amount)
+        .map_err(de::Error::custom)?;
+    u64::try_from(satoshis).map_err(|_| de::Error::custom(AmountError::OutOfRange))
+}
+
+/// Serialize an amount in satoshis into the coin units used by the RPC.
+fn satoshis_param(satoshis: u64) -> Result<Value, NodeError> {
+    let satoshis = i64::try_from(satoshis).map_err(|_| AmountError::OutOfRange)?;
+    Ok(Value::String(RPC_CHAIN.satoshis_to_amount(satoshis)))
+}
+
+/// An unspent output held by the node wallet, as returned by `listunspent`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Unspent {
+    /// Transaction ID.
+    pub txid: String,
+    /// Output index.
+    pub vout: u32,
+    /// Address the output pays to.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Hex encoded output script.
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: String,
+    /// Value of the output, in satoshis.
+    #[serde(rename = "amount", deserialize_with = "deserialize_satoshis")]
+    pub satoshis: u64,
+    /// Number of confirmations.
+    pub confirmations: u64,
+    /// Whether the wallet has the keys required to spend the output.
+    pub spendable: bool,
+    /// Whether the wallet knows how to spend the output, ignoring the lack of keys.
+    pub solvable: bool,
+    /// Whether the output is considered safe to spend.
+    pub safe: bool,
+}
+
+/// An error which occurred while signing an input, as returned by `signrawtransactionwithwallet`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SigningError {
+    /// ID of the transaction spent by the input.
+    pub txid: String,
+    /// Index of the output spent by the input.
+    pub vout: u32,
+    /// Hex encoded input script.
+    #[serde(rename = "scriptSig")]
+    pub script_sig: String,
+    /// Input sequence number.
+    pub sequence: u32,
+    /// Error message.
+    pub error: String,
+}
+
+#[derive(Deserialize)]
+struct RawSignedTransaction {
+    hex: String,
+    complete: bool,
+    #[serde(default)]
+    errors: Vec<SigningError>,
+}
+
+/// A transaction signed by the node wallet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedTransaction {
+    /// The raw transaction.
+    pub raw_tx: Vec<u8>,
+    /// Whether the transaction has a complete set of signatures.
+    pub complete: bool,
+    /// Errors which occurred while signing inputs.
+    pub errors: Vec<SigningError>,
+}
+
+/// Node wallet client methods.
+#[async_trait]
+pub trait WalletClient {
+    /// List the unspent outputs held by the node wallet with between `min_conf` and `max_conf`
+    /// confirmations.
+    async fn list_unspent(&self, min_conf: u64, max_conf: u64) -> Result<Vec<Unspent>, NodeError>;
+
+    /// Sign the inputs of a raw transaction using the keys held by the node wallet.
+    async fn sign_raw_transaction_with_wallet(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<SignedTransaction, NodeError>;
+
+    /// Send an amount, in satoshis, to an address using the node wallet, returning the
+    /// transaction ID.
+    async fn send_to_address(&self, address: &str, satoshis: u64) -> Result<String, NodeError>;
+}
+
+/// Calls the `signrawtransactionwithwallet` method.
+async fn sign_raw_transaction_with_wallet<C: Connectable>(
+    client: &BitcoinJsonClient<C>,
+    raw_tx: &[u8],
+) -> Result<SignedTransaction, NodeError> {
+    let params = vec![Value::String(hex::encode(raw_tx))];
+    let signed: RawSignedTransaction = call(client, "signrawtransactionwithwallet", params).await?;
+    Ok(SignedTransaction {
+        raw_tx: hex::decode(signed.hex)?,
+        complete: signed.complete,
+        errors: signed.errors,
+    })
+}
+
+#[async_trait]
+impl WalletClient for BitcoinClientHTTP {
+    /// Calls the `listunspent` method.
+    async fn list_unspent(&self, min_conf: u64, max_conf: u64) -> Result<Vec<Unspent>, NodeError> {
+        let params = vec![Value::from(min_conf), Value::from(max_conf)];
+        call(&self.0, "listunspent", params).await
+    }
+
+    /// Calls the `signrawtransactionwithwallet` method.
+    async fn sign_raw_transaction_with_wallet(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<SignedTransaction, NodeError> {
+        sign_raw_transaction_with_wallet(&self.0, raw_tx).await
+    }
+
+    /// Calls the `sendtoaddress` method.
+    async fn send_to_address(&self, address: &str, satoshis: u64) -> Result<String, NodeError> {
+        let params = vec![
+            Value::String(address.to_string()),
+            satoshis_param(satoshis)?,
+        ];
+        call(&self.0, "sendtoaddress", params).await
+    }
+}
+
+#[async_trait]
+impl WalletClient for BitcoinClientTLS {
+    /// Calls the `listunspent` method.
+    async fn list_unspent(&self, min_conf: u64, max_conf: u64) -> Result<Vec<Unspent>, NodeError> {
+        let params = vec![Value::from(min_conf), Value::from(max_conf)];
+        call(&self.0, "listunspent", params).await
+    }
+
+    /// Calls the `signrawtransactionwithwallet` method.
+    async fn sign_raw_transaction_with_wallet(
+        &self,
+        raw_tx: &[u8],
+    ) -> Result<SignedTransaction, NodeError> {
+        sign_raw_transaction_with_wallet(&self.0, raw_tx).await
+    }
+
+    /// Calls the `sendtoaddress` method.
+    async fn send_to_address(&self, address: &str, satoshis: u64) -> Result<String, NodeError> {
+        let params = vec![
+            Value::String(address.to_string()),
+            satoshis_param(satoshis)?,
+        ];
+        call(&self.0, "sendtoaddress", params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+
+    #[tokio::test]
+    async fn list_unspent() {
+        let (client, requests) = MockRpc::default()
+            .result(
+                "listunspent",
+                json!([{
+                    "txid": "aa",
+                    "vout": 1,
+                    "scriptPubKey": "76a9",
+                    "amount": 0.5,
+                    "confirmations": 6,
+                    "spendable": true,
+                    "solvable": true,
+                    "safe": true,
+                }]),
+            )
+            .start();
+        let unspent = client.list_unspent(1, 9999999).await.unwrap();
+        assert_eq!(
+            unspent,
+            vec![Unspent {
+                txid: "aa".to_string(),
+                vout: 1,
+                address: None,
+                script_pub_key: "76a9".to_string(),
+                satoshis: 50_000_000,
+                confirmations: 6,
+                spendable: true,
+                solvable: true,
+                safe: true,
+            }]
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![("listunspent".to_string(), json!([1, 9999999]))]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_raw_transaction_with_wallet() {
+        let (client, requests) = MockRpc::default()
+            .result(
+                "signrawtransactionwithwallet",
+                json!({
+                    "hex": "0102",
+                    "complete": false,
+                    "errors": [{
+                        "txid": "aa",
+                        "vout": 0,
+                        "scriptSig": "",
+                        "sequence": 4294967295u32,
+                        "error": "Input not found or already spent",
+                    }],
+                }),
+            )
+            .start();
+        let signed = client.sign_raw_transaction_with_wallet(&[1]).await.unwrap();
+        assert_eq!(signed.raw_tx, vec![1, 2]);
+        assert!(!signed.complete);
+        assert_eq!(signed.errors[0].error, "Input not found or already spent");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![("signrawtransactionwithwallet".to_string(), json!(["01"]))]
+        );
+
+        // Malformed hex is reported rather than returning an empty transaction
+        let (client, _) = MockRpc::default()
+            .result(
+                "signrawtransactionwithwallet",
+                json!({ "hex": "zz", "complete": true }),
+            )
+            .start();
+        assert!(matches!(
+            client.sign_raw_transaction_with_wallet(&[1]).await,
+            Err(NodeError::HexDecode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_to_address() {
+        let (client, requests) = MockRpc::default()
+            .result("sendtoaddress", json!("bb"))
+            .start();
+        let tx_id = client
+            .send_to_address("bchreg:qq", 25_000_001)
+            .await
+            .unwrap();
+        assert_eq!(tx_id, "bb");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "sendtoaddress".to_string(),
+                json!(["bchreg:qq", "0.25000001"])
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn profiled_amounts() {
+        let (client, requests) = MockRpc::default()
+            .result("sendtoaddress", json!("bb"))
+            .result(
+                "listunspent",
+                json!([{
+                    "txid": "aa",
+                    "vout": 1,
+                    "scriptPubKey": "76a9",
+                    "amount": 12.34,
+                    "confirmations": 6,
+                    "spendable": true,
+                    "solvable": true,
+                    "safe": true,
+                }]),
+            )
+            .start();
+        let client = crate::profile::ProfiledClient::new(client, Chain::Xec);
+
+        client.send_to_address("ecregtest:qq", 1_234).await.unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "sendtoaddress".to_string(),
+                json!(["ecregtest:qq", "12.34000000"])
+            )]
+        );
+        let unspent = client.list_unspent(1, 9999999).await.unwrap();
+        assert_eq!(unspent[0].satoshis, 1_234);
+
+        // Amounts more precise than satoshis are rejected
+        let (client, _) = MockRpc::default()
+            .result(
+                "listunspent",
+                json!([{
+                    "txid": "aa",
+                    "vout": 1,
+                    "scriptPubKey": "76a9",
+                    "amount": 0.000000001,
+                    "confirmations": 6,
+                    "spendable": true,
+                    "solvable": true,
+                    "safe": true,
+                }]),
+            )
+            .start();
+        assert!(matches!(
+            client.list_unspent(1, 9999999).await,
+            Err(NodeError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,47 @@
+#!/usr/bin/env python3
+"""Fail if a benchmark regressed against the criterion baseline it was last compared with.
+
+Run the benchmarks with `--baseline <name>` first, so that criterion records the relative
+change of each benchmark. A benchmark regresses when the lower bound of the confidence
+interval of its mean change exceeds the threshold.
+
+Usage: gate.py [CRITERION_DIR] [--threshold FRACTION]
+"""
+
+import argparse
+import json
+import os
+import sys
+
+
+def changes(criterion_dir):
+    for root, _, files in os.walk(criterion_dir):
+        if os.path.basename(root) == "change" and "estimates.json" in files:
+            with open(os.path.join(root, "estimates.json")) as f:
+                mean = json.load(f)["mean"]
+            name = os.path.relpath(os.path.dirname(root), criterion_dir)
+            yield name, mean["point_estimate"], mean["confidence_interval"]["lower_bound"]
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("criterion_dir", nargs="?", default="target/criterion")
+    parser.add_argument("--threshold", type=float, default=0.05)
+    args = parser.parse_args()
+
+    results = sorted(changes(args.criterion_dir))
+    if not results:
+        sys.exit("no benchmark changes found, run the benchmarks with --baseline first")
+
+    regressions = 0
+    for name, change, lower_bound in results:
+        regressed = lower_bound > args.threshold
+        regressions += regressed
+        marker = "REGRESSED" if regressed else "ok"
+        print("{:<50} {:>+8.2%}  {}".format(name, change, marker))
+    if regressions:
+        sys.exit("{} benchmarks regressed by more than {:.0%}".format(regressions, args.threshold))
+
+
+if __name__ == "__main__":
+    main()
@@ -1,5 +1,65 @@
-use cashweb_bitcoin::{transaction::Transaction, Decodable, Encodable};
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cashweb_bitcoin::{
+    pool::BufferPool,
+    transaction::{
+        input::Input, outpoint::Outpoint, output::Output, script::Script, sighash::SighashCache,
+        SignatureHashType, Transaction,
+    },
+    Decodable, Encodable,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Transactions seen on the network, hex encoded.
+const RAW_CORPUS: &[(&str, &str)] = &[
+    ("mixed_scripts", include_str!("corpus/mixed_scripts.hex")),
+    ("fan_out", include_str!("corpus/fan_out.hex")),
+];
+
+/// Length of a P2PKH `scriptSig` carrying a 72 byte signature.
+const P2PKH_SCRIPT_SIG_LEN: usize = 1 + 72 + 1 + 33;
+
+/// Construct a transaction spending P2PKH inputs to P2PKH outputs.
+fn p2pkh(inputs: u32, outputs: u8) -> Transaction {
+    Transaction {
+        version: 1,
+        inputs: (0..inputs)
+            .map(|vout| Input {
+                outpoint: Outpoint {
+                    tx_id: [vout as u8; 32],
+                    vout,
+                },
+                script: Script::from(vec![0x30; P2PKH_SCRIPT_SIG_LEN]),
+                sequence: u32::MAX,
+            })
+            .collect(),
+        outputs: (0..outputs)
+            .map(|index| {
+                let mut script = vec![0x76, 0xa9, 0x14];
+                script.extend_from_slice(&[index; 20]);
+                script.extend_from_slice(&[0x88, 0xac]);
+                Output {
+                    value: 1_000,
+                    script: Script::from(script),
+                }
+            })
+            .collect(),
+        lock_time: 0,
+    }
+}
+
+/// Representative transactions: a payment, a batched payment, a sweep of many small outputs and
+/// transactions seen on the network.
+fn corpus() -> Vec<(&'static str, Transaction)> {
+    let mut corpus = vec![
+        ("payment", p2pkh(1, 2)),
+        ("batch", p2pkh(10, 2)),
+        ("sweep", p2pkh(500, 1)),
+    ];
+    for (name, tx_hex) in RAW_CORPUS {
+        let raw_tx = hex::decode(tx_hex.trim()).unwrap();
+        corpus.push((name, decode(&raw_tx)));
+    }
+    corpus
+}
 
 fn decode(mut raw_tx: &[u8]) -> Transaction {
     Transaction::decode(&mut raw_tx).unwrap()
@@ -12,14 +72,81 @@ fn encode(tx: &Transaction) -> Vec<u8> {
 }
 
 fn transaction_encoding_benchmark(c: &mut Criterion) {
-    let tx_hex = "c47d5ad60485cb2f7a825587b95ea665a593769191382852f3514a486d7a7a11d220b62c54000000000663655253acab8c3cf32b0285b040e50dcf6987ddf7c385b3665048ad2f9317b9e0c5ba0405d8fde4129b00000000095251ab00ac65635300ffffffff549fe963ee410d6435bb2ed3042a7c294d0c7382a83edefba8582a2064af3265000000000152fffffffff7737a85e0e94c2d19cd1cde47328ece04b3e33cd60f24a8a345da7f2a96a6d0000000000865ab6a0051656aab28ff30d5049613ea020000000005ac51000063f06df1050000000008ac63516aabac5153afef5901000000000700656500655253688bc00000000000086aab5352526a53521ff1d5ff";
-    let raw_tx = hex::decode(tx_hex).unwrap();
-    c.bench_function("transaction decode", |b| {
-        b.iter(|| decode(black_box(&raw_tx)))
-    });
-    let tx = decode(&raw_tx);
-    c.bench_function("transaction encode", |b| b.iter(|| encode(black_box(&tx))));
+    let corpus = corpus();
+
+    let mut group = c.benchmark_group("transaction decode");
+    for (name, tx) in &corpus {
+        let raw_tx = encode(tx);
+        group.throughput(Throughput::Bytes(raw_tx.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &raw_tx, |b, raw_tx| {
+            b.iter(|| decode(black_box(raw_tx)))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("transaction encode");
+    for (name, tx) in &corpus {
+        group.throughput(Throughput::Bytes(tx.encoded_len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), tx, |b, tx| {
+            b.iter(|| encode(black_box(tx)))
+        });
+    }
+    group.finish();
+
+    let pool = BufferPool::default();
+    let mut group = c.benchmark_group("transaction encode pooled");
+    for (name, tx) in &corpus {
+        group.throughput(Throughput::Bytes(tx.encoded_len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), tx, |b, tx| {
+            b.iter(|| black_box(tx).encode_to_pooled(&pool))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("transaction id");
+    for (name, tx) in &corpus {
+        group.throughput(Throughput::Bytes(tx.encoded_len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), tx, |b, tx| {
+            b.iter(|| black_box(tx).transaction_id())
+        });
+    }
+    group.finish();
+}
+
+fn signature_hash_benchmark(c: &mut Criterion) {
+    let script_pubkey = Script::from(vec![0x76, 0xa9, 0x14]);
+
+    // Signature hashes of every input, as when signing the whole transaction
+    let mut group = c.benchmark_group("signature hash");
+    group.sample_size(10);
+    for (name, tx) in corpus() {
+        group.throughput(Throughput::Elements(tx.inputs.len() as u64));
+        group.bench_with_input(BenchmarkId::new("transaction", name), &tx, |b, tx| {
+            b.iter(|| {
+                for index in 0..tx.inputs.len() {
+                    black_box(tx.signature_hash(
+                        index,
+                        script_pubkey.clone(),
+                        SignatureHashType::All,
+                    ));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("cache", name), &tx, |b, tx| {
+            b.iter(|| {
+                let cache = SighashCache::new(tx);
+                for index in 0..tx.inputs.len() {
+                    black_box(cache.signature_hash(index, &script_pubkey, SignatureHashType::All));
+                }
+            })
+        });
+    }
+    group.finish();
 }
 
-criterion_group!(benches, transaction_encoding_benchmark);
+criterion_group!(
+    benches,
+    transaction_encoding_benchmark,
+    signature_hash_benchmark
+);
 criterion_main!(benches);
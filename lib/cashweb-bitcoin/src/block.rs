@@ -0,0 +1,396 @@
+//! This module contains the [`Block`] struct which represents a Lotus block, and its
+//! constituent [`BlockHeader`] and [`MetadataField`]s. All of them enjoy [`Encodable`] and [`Decodable`].
+
+use std::convert::TryInto;
+
+use bytes::{Buf, BufMut};
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+
+use crate::{
+    pow::{self, BitsError},
+    transaction::{self, Transaction},
+    var_int::{DecodeError as VarIntDecodeError, VarInt},
+    Decodable, Encodable,
+};
+
+/// The length of an encoded [`BlockHeader`].
+pub const HEADER_LEN: usize = 160;
+
+/// The length of the leading layer of an encoded [`BlockHeader`], containing the fields mutated
+/// while mining.
+const HEADER_LAYER_LEN: usize = 52;
+
+/// Represents a block header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct BlockHeader {
+    pub prev_block_hash: [u8; 32],
+    pub bits: u32,
+    /// Timestamp, encoded as a 48-bit integer.
+    pub time: u64,
+    pub reserved: u16,
+    pub nonce: u64,
+    pub version: u8,
+    /// Size of the block, encoded as a 56-bit integer.
+    pub size: u64,
+    pub height: i32,
+    pub epoch_block_hash: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub extended_metadata_hash: [u8; 32],
+}
+
+impl BlockHeader {
+    /// Calculate the block hash in little-endian format, matching `prev_block_hash`.
+    ///
+    /// This is the SHA256 digest of the SHA256 digests of the leading 52 bytes of the encoded
+    /// header, ending with the nonce, and of the remaining 108 bytes.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut raw_header = Vec::with_capacity(HEADER_LEN);
+        self.encode_raw(&mut raw_header);
+        let (layer, rest) = raw_header.split_at(HEADER_LAYER_LEN);
+        let layers = [
+            digest(&SHA256, layer).as_ref(),
+            digest(&SHA256, rest).as_ref(),
+        ]
+        .concat();
+        // This is safe as the digest is 32 bytes
+        digest(&SHA256, &layers).as_ref().try_into().unwrap()
+    }
+
+    /// Calculate the reversed block hash which is used in the lotusd rpc.
+    pub fn hash_rev(&self) -> [u8; 32] {
+        let mut hash = self.hash();
+        hash.reverse();
+        hash
+    }
+
+    /// Decode the target encoded in `bits`.
+    pub fn target(&self) -> Result<pow::Target, BitsError> {
+        pow::target_from_bits(self.bits)
+    }
+
+    /// Whether the block hash meets the target encoded in `bits`.
+    pub fn check_proof_of_work(&self) -> Result<bool, BitsError> {
+        Ok(pow::meets_target(&self.hash(), &self.target()?))
+    }
+}
+
+impl Encodable for BlockHeader {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        HEADER_LEN
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put(&self.prev_block_hash[..]);
+        buf.put_u32_le(self.bits);
+        buf.put_uint_le(self.time, 6);
+        buf.put_u16_le(self.reserved);
+        buf.put_u64_le(self.nonce);
+        buf.put_u8(self.version);
+        buf.put_uint_le(self.size, 7);
+        buf.put_i32_le(self.height);
+        buf.put(&self.epoch_block_hash[..]);
+        buf.put(&self.merkle_root[..]);
+        buf.put(&self.extended_metadata_hash[..]);
+    }
+}
+
+/// Error associated with [`BlockHeader`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("header too short")]
+pub struct HeaderDecodeError;
+
+impl Decodable for BlockHeader {
+    type Error = HeaderDecodeError;
+
+    #[inline]
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        if buf.remaining() < HEADER_LEN {
+            return Err(HeaderDecodeError);
+        }
+        let mut prev_block_hash = [0; 32];
+        buf.copy_to_slice(&mut prev_block_hash);
+        let bits = buf.get_u32_le();
+        let time = buf.get_uint_le(6);
+        let reserved = buf.get_u16_le();
+        let nonce = buf.get_u64_le();
+        let version = buf.get_u8();
+        let size = buf.get_uint_le(7);
+        let height = buf.get_i32_le();
+        let mut epoch_block_hash = [0; 32];
+        buf.copy_to_slice(&mut epoch_block_hash);
+        let mut merkle_root = [0; 32];
+        buf.copy_to_slice(&mut merkle_root);
+        let mut extended_metadata_hash = [0; 32];
+        buf.copy_to_slice(&mut extended_metadata_hash);
+
+        Ok(BlockHeader {
+            prev_block_hash,
+            bits,
+            time,
+            reserved,
+            nonce,
+            version,
+            size,
+            height,
+            epoch_block_hash,
+            merkle_root,
+            extended_metadata_hash,
+        })
+    }
+}
+
+/// Represents a block metadata field.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct MetadataField {
+    pub field_id: u32,
+    pub data: Vec<u8>,
+}
+
+impl MetadataField {
+    /// Length of the data as `VarInt`.
+    #[inline]
+    fn data_len_varint(&self) -> VarInt {
+        VarInt(self.data.len() as u64)
+    }
+}
+
+impl Encodable for MetadataField {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        4 + self.data_len_varint().encoded_len() + self.data.len()
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u32_le(self.field_id);
+        self.data_len_varint().encode_raw(buf);
+        buf.put(&self.data[..]);
+    }
+}
+
+/// Error associated with [`MetadataField`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum MetadataDecodeError {
+    /// Exhausted buffer when decoding `field_id` field.
+    #[error("field id too short")]
+    FieldIdTooShort,
+    /// Failed to decode data length [`VarInt`].
+    #[error("data length: {0}")]
+    DataLen(VarIntDecodeError),
+    /// Exhausted buffer when decoding `data` field.
+    #[error("data too short")]
+    DataTooShort,
+}
+
+impl Decodable for MetadataField {
+    type Error = MetadataDecodeError;
+
+    #[inline]
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error> {
+        if buf.remaining() < 4 {
+            return Err(Self::Error::FieldIdTooShort);
+        }
+        let field_id = buf.get_u32_le();
+
+        let data_len: u64 = VarInt::decode(buf).map_err(Self::Error::DataLen)?.into();
+        let data_len = data_len as usize;
+        if buf.remaining() < data_len {
+            return Err(Self::Error::DataTooShort);
+        }
+        let mut data = vec![0; data_len];
+        buf.copy_to_slice(&mut data);
+
+        Ok(MetadataField { field_id, data })
+    }
+}
+
+/// Represents a block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub metadata: Vec<MetadataField>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// Returns an iterator over the transactions within the block.
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, Transaction> {
+        self.transactions.iter()
+    }
+
+    /// Copy every script into its own allocation, releasing any buffer the block was decoded
+    /// from.
+    pub fn into_owned(self) -> Self {
+        Block {
+            transactions: self
+                .transactions
+                .into_iter()
+                .map(Transaction::into_owned)
+                .collect(),
+            ..self
+        }
+    }
+}
+
+impl IntoIterator for Block {
+    type Item = Transaction;
+    type IntoIter = std::vec::IntoIter<Transaction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.transactions.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Block {
+    type Item = &'a Transaction;
+    type IntoIter = std::slice::Iter<'a, Transaction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.transactions.iter()
+    }
+}
+
+impl Encodable for Block {
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        let metadata_len: usize = self.metadata.iter().map(|field| field.encoded_len()).sum();
+        let transactions_len: usize = self.transactions.iter().map(|tx| tx.encoded_len()).sum();
+        HEADER_LEN
+            + VarInt(self.metadata.len() as u64).encoded_len()
+            + metadata_len
+            + VarInt(self.transactions.len() as u64).encoded_len()
+            + transactions_len
+    }
+
+    #[inline]
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        self.header.encode_raw(buf);
+        VarInt(self.metadata.len() as u64).encode_raw(buf);
+        for field in &self.metadata {
+            field.encode_raw(buf);
+        }
+        VarInt(self.transactions.len() as u64).encode_raw(buf);
+        for transaction in &self.transactions {
+            transaction.encode_raw(buf);
+        }
+    }
+}
+
+/// Error associated with [`Block`] deserialization.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    /// Failed to decode the [`BlockHeader`].
+    #[error(transparent)]
+    Header(HeaderDecodeError),
+    /// Failed to decode metadata count [`VarInt`].
+    #[error("metadata count: {0}")]
+    MetadataCount(VarIntDecodeError),
+    /// Failed to decode a [`MetadataField`].
+    #[error("metadata: {0}")]
+    Metadata(MetadataDecodeError),
+    /// Failed to decode transaction count [`VarInt`].
+    #[error("transaction count: {0}")]
+    TransactionCount(VarIntDecodeError),
+    /// Failed to decode a [`Transaction`].
+    #[error("transaction: {0}")]
+    Transaction(transaction::DecodeError),
+}
+
+impl Decodable for Block {
+    type Error = DecodeError;
+
+    fn decode<B: Buf>(mut buf: &mut B) -> Result<Self, Self::Error> {
+        // Parse header
+        let header = BlockHeader::decode(&mut buf).map_err(Self::Error::Header)?;
+
+        // Parse metadata
+        let n_fields: u64 = VarInt::decode(&mut buf)
+            .map_err(Self::Error::MetadataCount)?
+            .into();
+        let metadata = (0..n_fields)
+            .map(|_| MetadataField::decode(buf))
+            .collect::<Result<Vec<MetadataField>, _>>()
+            .map_err(Self::Error::Metadata)?;
+
+        // Parse transactions
+        let n_transactions: u64 = VarInt::decode(&mut buf)
+            .map_err(Self::Error::TransactionCount)?
+            .into();
+        let transactions = (0..n_transactions)
+            .map(|_| Transaction::decode(buf))
+            .collect::<Result<Vec<Transaction>, _>>()
+            .map_err(Self::Error::Transaction)?;
+
+        Ok(Block {
+            header,
+            metadata,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_block() -> Block {
+        let raw_tx = hex::decode("0100000001b14bdcbc3e01bdaad36cc08e81e69c82e1060bc14e518db2b49aa43ad90ba26000000000490047304402203f16c6f40162ab686621ef3000b04e75418a0c0cb2d8aebeac894ae360ac1e780220ddc15ecdfc3507ac48e1681a33eb60996631bf6bf5bc0a0682c4db743ce7ca2b01ffffffff0140420f00000000001976a914660d4ef3a743e3e696ad990364e555c271ad504b88ac00000000").unwrap();
+        let transaction = Transaction::decode(&mut raw_tx.as_slice()).unwrap();
+        Block {
+            header: BlockHeader {
+                prev_block_hash: [1; 32],
+                bits: 0x1d00ffff,
+                time: 0x0000_ffff_ffff_ffff,
+                reserved: 0,
+                nonce: 42,
+                version: 1,
+                size: 0x00ff_ffff_ffff_ffff,
+                height: 100,
+                epoch_block_hash: [2; 32],
+                merkle_root: [3; 32],
+                extended_metadata_hash: [4; 32],
+            },
+            metadata: vec![MetadataField {
+                field_id: 7,
+                data: vec![1, 2, 3],
+            }],
+            transactions: vec![transaction.clone(), transaction],
+        }
+    }
+
+    #[test]
+    fn header_encoded_len() {
+        let header = test_block().header;
+        let mut raw_header = Vec::new();
+        header.encode_raw(&mut raw_header);
+        assert_eq!(raw_header.len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn round_trip() {
+        let block = test_block();
+        let mut raw_block = Vec::with_capacity(block.encoded_len());
+        block.encode(&mut raw_block).unwrap();
+        assert_eq!(raw_block.len(), block.encoded_len());
+
+        let decoded = Block::decode(&mut raw_block.as_slice()).unwrap();
+        assert_eq!(decoded, block);
+        assert_eq!(decoded.iter().count(), 2);
+    }
+
+    #[test]
+    fn decode_short_header() {
+        let raw_header = [0; HEADER_LEN - 1];
+        assert_eq!(
+            Block::decode(&mut &raw_header[..]),
+            Err(DecodeError::Header(HeaderDecodeError))
+        );
+    }
+}
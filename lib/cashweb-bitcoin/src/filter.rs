@@ -0,0 +1,334 @@
+//! This module contains the [`BlockFilter`] struct which represents a [`BIP158`] compact block
+//! filter, a Golomb-coded set of the scripts a block spends and creates.
+//!
+//! Light backends fetch a filter per block and test it against the scripts they watch, only
+//! downloading the blocks which match. Filters have no false negatives, and false positives with
+//! probability `1 / FILTER_M`.
+//!
+//! [`BIP158`]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+
+use std::{collections::HashSet, convert::TryInto, hash::Hasher};
+
+use ring::digest::{digest, SHA256};
+use siphasher::sip::SipHasher24;
+use thiserror::Error;
+
+use crate::{
+    block::Block,
+    transaction::script::Script,
+    var_int::{DecodeError as VarIntDecodeError, VarInt},
+    Decodable, Encodable,
+};
+
+/// Number of bits of the remainder of each Golomb-Rice coded value.
+pub const FILTER_P: u8 = 19;
+
+/// Inverse of the false positive rate of a filter.
+pub const FILTER_M: u64 = 784_931;
+
+/// Error associated with decoding a [`BlockFilter`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum FilterError {
+    /// Failed to decode the number of elements.
+    #[error("number of elements: {0}")]
+    ElementCount(VarIntDecodeError),
+    /// The filter ended before all its elements were decoded.
+    #[error("filter truncated")]
+    Truncated,
+}
+
+/// A compact block filter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockFilter {
+    content: Vec<u8>,
+}
+
+impl From<Vec<u8>> for BlockFilter {
+    fn from(content: Vec<u8>) -> Self {
+        Self { content }
+    }
+}
+
+impl From<BlockFilter> for Vec<u8> {
+    fn from(filter: BlockFilter) -> Self {
+        filter.content
+    }
+}
+
+/// Writes bits, most significant first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8,
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, used: 8 }
+    }
+
+    fn write(&mut self, value: u64, bits: u8) {
+        for bit in (0..bits).rev() {
+            if self.used == 8 {
+                self.bytes.push(0);
+                self.used = 0;
+            }
+            if (value >> bit) & 1 == 1 {
+                // This is safe as a byte was pushed above
+                *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+            }
+            self.used += 1;
+        }
+    }
+}
+
+/// Reads bits, most significant first.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1 == 1;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read(&mut self, bits: u8) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..bits {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    /// Read a Golomb-Rice coded value.
+    fn read_golomb_rice(&mut self) -> Option<u64> {
+        let mut quotient = 0;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let remainder = self.read(FILTER_P)?;
+        Some((quotient << FILTER_P) + remainder)
+    }
+}
+
+/// Hash the elements onto the range `[0, count * FILTER_M)`, keyed by the block hash, in
+/// ascending order.
+fn hash_elements<'a, I>(block_hash: &[u8; 32], elements: I, count: u64) -> Vec<u64>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    // These are safe as the slices are 8 bytes
+    let k0 = u64::from_le_bytes(block_hash[0..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().unwrap());
+    let range = count as u128 * FILTER_M as u128;
+    let mut hashes: Vec<u64> = elements
+        .into_iter()
+        .map(|element| {
+            let mut hasher = SipHasher24::new_with_keys(k0, k1);
+            hasher.write(element);
+            ((hasher.finish() as u128 * range) >> 64) as u64
+        })
+        .collect();
+    hashes.sort_unstable();
+    hashes
+}
+
+impl BlockFilter {
+    /// Construct the basic filter of the block, given the scripts of the outputs spent by its
+    /// transactions, excluding the coinbase.
+    ///
+    /// Empty and OP_RETURN output scripts are omitted, as they can never be spent.
+    pub fn new<'a, I>(block: &'a Block, spent_scripts: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Script>,
+    {
+        let output_scripts = block
+            .transactions
+            .iter()
+            .flat_map(|transaction| &transaction.outputs)
+            .map(|output| &output.script)
+            .filter(|script| !script.is_empty() && !script.is_op_return());
+        let scripts = output_scripts
+            .chain(spent_scripts)
+            .map(Script::as_bytes)
+            .filter(|script| !script.is_empty());
+        Self::from_elements(&block.header.hash(), scripts)
+    }
+
+    /// Construct the filter of the elements, keyed by the block hash, ignoring duplicates.
+    pub fn from_elements<'a, I>(block_hash: &[u8; 32], elements: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let elements: HashSet<&[u8]> = elements.into_iter().collect();
+        let count = elements.len() as u64;
+
+        let mut content = Vec::with_capacity(9 + elements.len() * 3);
+        VarInt(count).encode_raw(&mut content);
+        let mut writer = BitWriter::new(content);
+        let mut last = 0;
+        for hash in hash_elements(block_hash, elements, count) {
+            let delta = hash - last;
+            last = hash;
+            // Unary quotient terminated by a zero, followed by the remainder
+            for _ in 0..delta >> FILTER_P {
+                writer.write(1, 1);
+            }
+            writer.write(0, 1);
+            writer.write(delta, FILTER_P);
+        }
+        Self {
+            content: writer.bytes,
+        }
+    }
+
+    /// Converts the filter into a byte slice.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Calculate the filter hash. This is the double SHA256 digest of the filter.
+    pub fn filter_hash(&self) -> [u8; 32] {
+        let filter_hash = digest(&SHA256, digest(&SHA256, &self.content).as_ref());
+        // This is safe as SHA256 digests are 32 bytes
+        filter_hash.as_ref().try_into().unwrap()
+    }
+
+    /// Calculate the filter header, committing to the filter and the previous filter header.
+    pub fn filter_header(&self, prev_filter_header: &[u8; 32]) -> [u8; 32] {
+        let preimage = [&self.filter_hash()[..], &prev_filter_header[..]].concat();
+        let filter_header = digest(&SHA256, digest(&SHA256, &preimage).as_ref());
+        // This is safe as SHA256 digests are 32 bytes
+        filter_header.as_ref().try_into().unwrap()
+    }
+
+    /// Check whether any of the scripts may be created or spent by the block with the hash.
+    pub fn matches_any<'a, I>(&self, block_hash: &[u8; 32], scripts: I) -> Result<bool, FilterError>
+    where
+        I: IntoIterator<Item = &'a Script>,
+    {
+        self.matches_any_element(block_hash, scripts.into_iter().map(Script::as_bytes))
+    }
+
+    /// Check whether any of the elements may be in the filter keyed by the block hash.
+    pub fn matches_any_element<'a, I>(
+        &self,
+        block_hash: &[u8; 32],
+        elements: I,
+    ) -> Result<bool, FilterError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut buf = &self.content[..];
+        let count: u64 = VarInt::decode(&mut buf)
+            .map_err(FilterError::ElementCount)?
+            .into();
+        let queries = hash_elements(block_hash, elements, count);
+        let mut reader = BitReader {
+            bytes: buf,
+            position: 0,
+        };
+
+        // Walk both sorted sets, advancing whichever is behind
+        let mut queries = queries.into_iter().peekable();
+        let mut value = 0;
+        for _ in 0..count {
+            value += reader.read_golomb_rice().ok_or(FilterError::Truncated)?;
+            while let Some(&query) = queries.peek() {
+                if query < value {
+                    queries.next();
+                } else if query == value {
+                    return Ok(true);
+                } else {
+                    break;
+                }
+            }
+            if queries.peek().is_none() {
+                break;
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{output::Output, script::opcodes, Transaction};
+
+    #[test]
+    fn bip158_genesis() {
+        // Basic filter of the testnet genesis block, from the BIP158 test vectors
+        let mut block_hash: [u8; 32] =
+            hex::decode("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        block_hash.reverse();
+        let script = hex::decode(
+            "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+        )
+        .unwrap();
+        let filter = BlockFilter::from_elements(&block_hash, vec![&script[..]]);
+        assert_eq!(filter.as_bytes(), &hex::decode("019dfca8").unwrap()[..]);
+    }
+
+    #[test]
+    fn matches() {
+        let watched: Vec<Script> = (0..50u8).map(|i| Script::from(vec![i; 25])).collect();
+        let block = Block {
+            transactions: vec![Transaction {
+                outputs: watched[..10]
+                    .iter()
+                    .map(|script| Output {
+                        value: 1_000,
+                        script: script.clone(),
+                    })
+                    .chain(std::iter::once(Output {
+                        value: 0,
+                        script: Script::from(vec![opcodes::OP_RETURN, 1, 0xaa]),
+                    }))
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let block_hash = block.header.hash();
+        let filter = BlockFilter::new(&block, &watched[10..20]);
+        let raw_filter: Vec<u8> = filter.clone().into();
+        assert_eq!(BlockFilter::from(raw_filter), filter);
+
+        // Created and spent scripts match
+        for script in &watched[..20] {
+            assert!(filter.matches_any(&block_hash, vec![script]).unwrap());
+        }
+        assert!(filter.matches_any(&block_hash, &watched[15..30]).unwrap());
+        assert!(!filter.matches_any(&block_hash, &watched[20..]).unwrap());
+        assert!(!filter
+            .matches_any(
+                &block_hash,
+                &[Script::from(vec![opcodes::OP_RETURN, 1, 0xaa])]
+            )
+            .unwrap());
+
+        // Filters are keyed by the block hash
+        assert!(!filter.matches_any(&[0; 32], &watched[..20]).unwrap());
+
+        let empty = BlockFilter::new(&Block::default(), vec![]);
+        assert_eq!(empty.as_bytes(), &[0]);
+        assert!(!empty.matches_any(&block_hash, &watched).unwrap());
+
+        let truncated = BlockFilter::from(vec![5]);
+        assert_eq!(
+            truncated.matches_any(&block_hash, &watched),
+            Err(FilterError::Truncated)
+        );
+        assert!(BlockFilter::from(vec![0xfd])
+            .matches_any(&block_hash, &watched)
+            .is_err());
+    }
+}
@@ -11,16 +11,24 @@
 //! [`Hierarchical Deterministic Wallets`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
 
 pub mod bip32;
+pub mod block;
+pub mod filter;
 pub mod merkle;
+pub mod pool;
+pub mod pow;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod transaction;
 pub mod var_int;
 
 use std::convert::TryFrom;
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use pool::{BufferPool, PooledBuffer};
+
 /// Insufficient capacity in buffer when encoding a Bitcoin structure.
 #[derive(Clone, Debug, PartialEq, Eq, Error)]
 #[error("buffer has insufficient capacity")]
@@ -43,6 +51,14 @@ pub trait Encodable: Sized {
 
     /// Encodes structure to a buffer. This panics if buffer contains insufficient capacity.
     fn encode_raw<B: BufMut>(&self, buf: &mut B);
+
+    /// Encodes structure to a buffer taken from a [`BufferPool`].
+    #[inline]
+    fn encode_to_pooled(&self, pool: &BufferPool) -> PooledBuffer {
+        let mut buf = pool.get(self.encoded_len());
+        self.encode_raw(&mut *buf);
+        buf
+    }
 }
 
 /// Provides a common interface for the deserialization of bitcoin structures.
@@ -52,6 +68,15 @@ pub trait Decodable: Sized {
 
     /// Decode a buffer.
     fn decode<B: Buf>(buf: &mut B) -> Result<Self, Self::Error>;
+
+    /// Decode a shared buffer.
+    ///
+    /// Variable-length fields, such as scripts, are split from `buf` rather than copied and so
+    /// share its allocation, which is released once every structure decoded from it is dropped.
+    #[inline]
+    fn decode_shared(buf: &mut Bytes) -> Result<Self, Self::Error> {
+        Self::decode(buf)
+    }
 }
 
 /// Enumeration of all standard Bitcoin networks.
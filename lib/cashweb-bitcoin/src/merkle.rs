@@ -47,11 +47,66 @@ pub fn lotus_merkle_root(mut hashes: Vec<[u8; 32]>) -> ([u8; 32], u8) {
     lotus_merkle_root_inline(&mut hashes, 1)
 }
 
+/// A merkle branch proving the inclusion of a leaf in a tree, as constructed by
+/// [`lotus_merkle_root`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Index of the leaf.
+    pub index: u32,
+    /// Sibling hashes, from the leaves to the root.
+    pub branch: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Construct the proof for the leaf at the index, if it exists.
+    pub fn from_leaves(leaves: &[[u8; 32]], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut level = leaves.to_vec();
+        let mut position = index;
+        let mut branch = Vec::new();
+        while level.len() > 1 {
+            branch.push(level.get(position ^ 1).copied().unwrap_or([0; 32]));
+            level = level
+                .chunks(2)
+                .map(|pair| sha256d(&[pair[0], *pair.get(1).unwrap_or(&[0; 32])].concat()))
+                .collect();
+            position /= 2;
+        }
+        Some(Self {
+            index: index as u32,
+            branch,
+        })
+    }
+
+    /// Calculate the merkle root implied by the leaf.
+    pub fn root(&self, leaf: [u8; 32]) -> [u8; 32] {
+        let mut position = self.index;
+        self.branch.iter().fold(leaf, |hash, sibling| {
+            let parent = if position & 1 == 0 {
+                sha256d(&[hash, *sibling].concat())
+            } else {
+                sha256d(&[*sibling, hash].concat())
+            };
+            position >>= 1;
+            parent
+        })
+    }
+
+    /// Whether the proof shows the inclusion of the leaf in the tree with the merkle root.
+    pub fn verify(&self, leaf: [u8; 32], root: &[u8; 32]) -> bool {
+        // Reject indices which are not covered by the branch
+        let covered = self.branch.len() >= 32 || self.index >> self.branch.len() == 0;
+        covered && self.root(leaf) == *root
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryInto;
 
-    use crate::merkle::lotus_merkle_root;
+    use crate::merkle::{lotus_merkle_root, MerkleProof};
 
     #[test]
     fn test_merkle_calc() {
@@ -66,6 +121,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_merkle_proof() {
+        for (raw_hashes, result, _) in test_txs_for_txid() {
+            let hashes: Vec<[u8; 32]> = raw_hashes
+                .into_iter()
+                .map(|raw_hash| hex::decode(raw_hash).unwrap().try_into().unwrap())
+                .collect();
+            let root: [u8; 32] = hex::decode(result).unwrap().try_into().unwrap();
+            for (index, hash) in hashes.iter().enumerate() {
+                let proof = MerkleProof::from_leaves(&hashes, index).unwrap();
+                assert!(proof.verify(*hash, &root));
+                assert!(!proof.verify(hashes[(index + 1) % hashes.len()], &root));
+
+                let mut wrong_index = proof.clone();
+                wrong_index.index += 1 << proof.branch.len();
+                assert!(!wrong_index.verify(*hash, &root));
+            }
+            assert_eq!(MerkleProof::from_leaves(&hashes, hashes.len()), None);
+        }
+    }
+
     fn test_txs_for_txid() -> Vec<(Vec<&'static str>, &'static str, u8)> {
         vec![(
             vec![
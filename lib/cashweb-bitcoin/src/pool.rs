@@ -0,0 +1,171 @@
+//! This module contains the [`BufferPool`] struct which recycles encoding buffers.
+//!
+//! Serializing a structure with [`Encodable::encode_to_pooled`] reuses the allocation of a
+//! previously dropped [`PooledBuffer`], rather than allocating afresh for each call.
+//!
+//! [`Encodable::encode_to_pooled`]: crate::Encodable::encode_to_pooled
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// Default maximum number of idle buffers retained by a [`BufferPool`].
+pub const DEFAULT_MAX_IDLE: usize = 64;
+
+/// Default maximum capacity, in bytes, of a buffer returned to a [`BufferPool`].
+pub const DEFAULT_MAX_CAPACITY: usize = 1024 * 1024;
+
+#[derive(Debug)]
+struct Inner {
+    idle: Mutex<Vec<Vec<u8>>>,
+    max_idle: usize,
+    max_capacity: usize,
+}
+
+/// A pool of reusable byte buffers.
+///
+/// Cloning a pool is cheap and the clones share the same buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool(Arc<Inner>);
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IDLE)
+    }
+}
+
+impl BufferPool {
+    /// Create a pool retaining at most `max_idle` idle buffers.
+    pub fn new(max_idle: usize) -> Self {
+        BufferPool(Arc::new(Inner {
+            idle: Mutex::new(Vec::with_capacity(max_idle)),
+            max_idle,
+            max_capacity: DEFAULT_MAX_CAPACITY,
+        }))
+    }
+
+    /// Discard, rather than retain, buffers which have grown beyond `max_capacity` bytes.
+    ///
+    /// This prevents a single oversized structure from pinning a large allocation. The returned
+    /// pool does not share buffers with clones of `self`.
+    pub fn with_max_capacity(self, max_capacity: usize) -> Self {
+        BufferPool(Arc::new(Inner {
+            idle: Mutex::new(Vec::with_capacity(self.0.max_idle)),
+            max_idle: self.0.max_idle,
+            max_capacity,
+        }))
+    }
+
+    /// Take an empty buffer from the pool with at least `capacity` bytes of capacity.
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        let recycled = self
+            .0
+            .idle
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop();
+        let mut buffer = recycled.unwrap_or_default();
+        buffer.reserve(capacity);
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Number of idle buffers currently retained.
+    pub fn idle(&self) -> usize {
+        self.0
+            .idle
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .len()
+    }
+
+    fn recycle(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.0.max_capacity {
+            return;
+        }
+        buffer.clear();
+        let mut idle = self.0.idle.lock().unwrap_or_else(|err| err.into_inner());
+        if idle.len() < self.0.max_idle {
+            idle.push(buffer);
+        }
+    }
+}
+
+/// A byte buffer which is returned to its [`BufferPool`] when dropped.
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PooledBuffer").field(&self.buffer).finish()
+    }
+}
+
+impl PooledBuffer {
+    /// Detach the underlying buffer from the pool.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.recycle(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::Transaction, Encodable};
+
+    #[test]
+    fn recycle() {
+        let pool = BufferPool::new(1).with_max_capacity(1024);
+        let transaction = Transaction::default();
+
+        let raw = transaction.encode_to_pooled(&pool);
+        assert_eq!(raw.len(), transaction.encoded_len());
+        let ptr = raw.as_ptr();
+        drop(raw);
+        assert_eq!(pool.idle(), 1);
+
+        // The allocation is reused, and at most `max_idle` buffers are retained
+        let (first, second) = (pool.get(8), pool.get(8));
+        assert!(first.is_empty());
+        assert_eq!(first.as_ptr(), ptr);
+        drop((first, second));
+        assert_eq!(pool.idle(), 1);
+
+        // Oversized and detached buffers are not returned
+        drop(pool.get(2048));
+        pool.get(8).into_vec();
+        assert_eq!(pool.idle(), 0);
+    }
+}
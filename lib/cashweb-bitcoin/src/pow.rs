@@ -0,0 +1,277 @@
+//! This module contains methods for decoding the compact target encoding used in the `bits`
+//! field of a [`BlockHeader`] and checking proof-of-work against it.
+//!
+//! Targets and chainwork are represented as 256-bit big-endian integers, so that they may be
+//! compared lexicographically.
+//!
+//! [`BlockHeader`]: crate::block::BlockHeader
+
+use thiserror::Error;
+
+/// A 256-bit big-endian integer.
+pub type Target = [u8; 32];
+
+/// Expected number of hashes required to produce a chain, as a 256-bit big-endian integer.
+pub type Work = [u8; 32];
+
+/// Error associated with decoding the compact target encoding.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum BitsError {
+    /// The sign bit was set.
+    #[error("negative target")]
+    Negative,
+    /// The target exceeded 256 bits.
+    #[error("target overflow")]
+    Overflow,
+    /// The target was zero.
+    #[error("zero target")]
+    Zero,
+}
+
+/// Decode the compact target encoding.
+pub fn target_from_bits(bits: u32) -> Result<Target, BitsError> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 && mantissa != 0 {
+        return Err(BitsError::Negative);
+    }
+    if mantissa == 0 {
+        return Err(BitsError::Zero);
+    }
+
+    let mut target = [0; 32];
+    let mantissa_bytes = &mantissa.to_be_bytes()[1..];
+    if exponent <= 3 {
+        let value = mantissa >> (8 * (3 - exponent));
+        if value == 0 {
+            return Err(BitsError::Zero);
+        }
+        target[28..].copy_from_slice(&value.to_be_bytes());
+        return Ok(target);
+    }
+
+    // The mantissa occupies the bytes starting `exponent` bytes from the end
+    for (offset, byte) in mantissa_bytes.iter().enumerate() {
+        let position = (32 + offset).checked_sub(exponent);
+        match position {
+            Some(position) => target[position] = *byte,
+            None if *byte != 0 => return Err(BitsError::Overflow),
+            None => (),
+        }
+    }
+    Ok(target)
+}
+
+/// Encode the target in the compact target encoding, rounding down.
+pub fn bits_from_target(target: &Target) -> u32 {
+    let start = match target.iter().position(|byte| *byte != 0) {
+        Some(start) => start,
+        None => return 0,
+    };
+    let mut size = (32 - start) as u32;
+    let mut mantissa_bytes = [0; 4];
+    for (offset, byte) in target[start..].iter().take(3).enumerate() {
+        mantissa_bytes[1 + offset] = *byte;
+    }
+    let mut mantissa = u32::from_be_bytes(mantissa_bytes);
+
+    // The mantissa must not set the sign bit
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    mantissa | size << 24
+}
+
+/// Whether the hash, in the byte order of [`BlockHeader::hash`], is at most the target.
+///
+/// [`BlockHeader::hash`]: crate::block::BlockHeader::hash
+pub fn meets_target(hash: &[u8; 32], target: &Target) -> bool {
+    hash.iter().rev().le(target.iter())
+}
+
+/// Shift the target left, saturating at the maximum 256-bit integer.
+pub fn shift_left(target: &Target, shift: u32) -> Target {
+    let mut result = *target;
+    for _ in 0..shift {
+        if result[0] & 0x80 != 0 {
+            return [0xff; 32];
+        }
+        let mut carry = 0;
+        for byte in result.iter_mut().rev() {
+            let next_carry = *byte >> 7;
+            *byte = (*byte << 1) | carry;
+            carry = next_carry;
+        }
+    }
+    result
+}
+
+/// Multiply the target by the factor, returning `None` on overflow.
+pub fn multiply(target: &Target, factor: u32) -> Option<Target> {
+    let mut result = [0; 32];
+    let mut carry = 0;
+    for (result_byte, byte) in result.iter_mut().zip(target.iter()).rev() {
+        let product = u64::from(*byte) * u64::from(factor) + carry;
+        *result_byte = product as u8;
+        carry = product >> 8;
+    }
+    if carry != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+fn add(left: &[u8; 32], right: &[u8; 32]) -> ([u8; 32], bool) {
+    let mut result = [0; 32];
+    let mut carry = 0;
+    for ((result_byte, left), right) in result.iter_mut().zip(left.iter()).zip(right.iter()).rev() {
+        let sum = u16::from(*left) + u16::from(*right) + carry;
+        *result_byte = sum as u8;
+        carry = sum >> 8;
+    }
+    (result, carry != 0)
+}
+
+fn wrapping_sub(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut result = [0; 32];
+    let mut borrow = 0;
+    for ((result_byte, left), right) in result.iter_mut().zip(left.iter()).zip(right.iter()).rev() {
+        let difference = i16::from(*left) - i16::from(*right) - borrow;
+        *result_byte = difference as u8;
+        borrow = (difference < 0) as i16;
+    }
+    result
+}
+
+/// Calculate the expected number of hashes required to meet the target, `2^256 / (target + 1)`.
+pub fn work(target: &Target) -> Work {
+    let mut one = [0; 32];
+    one[31] = 1;
+    let (divisor, overflow) = add(target, &one);
+    if overflow {
+        return one;
+    }
+
+    // 2^256 / (target + 1) = (2^256 - target - 1) / (target + 1) + 1, where the numerator is the
+    // bitwise complement of the target
+    let mut quotient = [0; 32];
+    let mut remainder = [0; 32];
+    for bit in 0..256 {
+        let mut carry = (!target[bit / 8] >> (7 - bit % 8)) & 1;
+        for byte in remainder.iter_mut().rev() {
+            let next_carry = *byte >> 7;
+            *byte = (*byte << 1) | carry;
+            carry = next_carry;
+        }
+        if carry != 0 || remainder >= divisor {
+            remainder = wrapping_sub(&remainder, &divisor);
+            quotient[bit / 8] |= 0x80 >> (bit % 8);
+        }
+    }
+    add(&quotient, &one).0
+}
+
+/// Add work, saturating at the maximum 256-bit integer.
+pub fn add_work(left: &Work, right: &Work) -> Work {
+    match add(left, right) {
+        (_, true) => [0xff; 32],
+        (sum, false) => sum,
+    }
+}
+
+/// Shift the target right.
+pub fn shift_right(target: &Target, shift: u32) -> Target {
+    let mut result = *target;
+    for _ in 0..shift {
+        let mut carry = 0;
+        for byte in result.iter_mut() {
+            let next_carry = *byte & 1;
+            *byte = (*byte >> 1) | (carry << 7);
+            carry = next_carry;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits() {
+        let target = target_from_bits(0x1d00ffff).unwrap();
+        assert_eq!(
+            hex::encode(target),
+            "00000000ffff0000000000000000000000000000000000000000000000000000"
+        );
+
+        let target = target_from_bits(0x207fffff).unwrap();
+        assert_eq!(
+            hex::encode(target),
+            "7fffff0000000000000000000000000000000000000000000000000000000000"
+        );
+
+        let target = target_from_bits(0x03123456).unwrap();
+        assert_eq!(&target[29..], &[0x12, 0x34, 0x56]);
+
+        assert_eq!(target_from_bits(0x04923456), Err(BitsError::Negative));
+        assert_eq!(target_from_bits(0x22123456), Err(BitsError::Overflow));
+        assert_eq!(target_from_bits(0x1d000000), Err(BitsError::Zero));
+    }
+
+    #[test]
+    fn compact() {
+        for bits in &[0x1d00ffff, 0x207fffff, 0x1804dafe, 0x03123456, 0x01120000] {
+            let target = target_from_bits(*bits).unwrap();
+            assert_eq!(bits_from_target(&target), *bits);
+        }
+
+        // The sign bit is avoided by widening the encoding
+        let mut target = [0; 32];
+        target[29] = 0x80;
+        assert_eq!(bits_from_target(&target), 0x04008000);
+        assert_eq!(bits_from_target(&[0; 32]), 0);
+    }
+
+    #[test]
+    fn work_and_multiply() {
+        // Chainwork of the Bitcoin genesis block
+        let target = target_from_bits(0x1d00ffff).unwrap();
+        assert_eq!(
+            hex::encode(work(&target)),
+            "0000000000000000000000000000000000000000000000000000000100010001"
+        );
+        assert_eq!(work(&target_from_bits(0x207fffff).unwrap())[31], 2);
+        assert_eq!(work(&[0xff; 32])[31], 1);
+
+        let total = add_work(&work(&target), &work(&target));
+        assert_eq!(&total[26..], &[0, 2, 0, 2, 0, 2]);
+        assert_eq!(add_work(&[0xff; 32], &total), [0xff; 32]);
+
+        assert_eq!(multiply(&target, 4), Some(shift_left(&target, 2)));
+        assert_eq!(multiply(&[0x40; 32], 4), None);
+    }
+
+    #[test]
+    fn shifts() {
+        let target = target_from_bits(0x1d00ffff).unwrap();
+        assert_eq!(
+            shift_left(&target, 2),
+            target_from_bits(0x1d03fffc).unwrap()
+        );
+        assert_eq!(shift_right(&shift_left(&target, 9), 9), target);
+        assert_eq!(shift_left(&[0x40; 32], 2), [0xff; 32]);
+    }
+
+    #[test]
+    fn meets() {
+        let target = target_from_bits(0x1d00ffff).unwrap();
+        let mut hash = [0; 32];
+        hash[27] = 0xff;
+        hash[26] = 0xff;
+        assert!(meets_target(&hash, &target));
+        hash[25] = 1;
+        assert!(!meets_target(&hash, &target));
+    }
+}
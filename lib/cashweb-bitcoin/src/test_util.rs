@@ -0,0 +1,210 @@
+//! This module contains [`proptest`] strategies for Bitcoin structures and assertion helpers for
+//! checking the laws every [`Encodable`]/[`Decodable`] pair is expected to obey.
+//!
+//! Enable the `test-util` feature to use these from downstream crates.
+
+use std::fmt;
+
+use proptest::{collection::vec, prelude::*, test_runner::TestCaseError};
+
+use crate::{
+    transaction::{
+        input::Input,
+        outpoint::Outpoint,
+        output::Output,
+        script::{opcodes, Script},
+        Transaction,
+    },
+    var_int::VarInt,
+    Decodable, Encodable,
+};
+
+/// Generates [`VarInt`]s spread evenly across the four encoding widths.
+pub fn var_int() -> impl Strategy<Value = VarInt> {
+    prop_oneof![
+        (0..=0xfc_u64),
+        (0xfd..=0xffff_u64),
+        (0x10000..=0xffff_ffff_u64),
+        (0x1_0000_0000..=u64::MAX),
+    ]
+    .prop_map(VarInt)
+}
+
+/// Generates arbitrary [`Script`]s of at most `max_len` bytes.
+pub fn script(max_len: usize) -> impl Strategy<Value = Script> {
+    vec(any::<u8>(), 0..=max_len).prop_map(Script::from)
+}
+
+/// Generates `OP_RETURN` [`Script`]s together with the items they push, using every push encoding.
+pub fn op_return_script(max_items: usize) -> impl Strategy<Value = (Script, Vec<Vec<u8>>)> {
+    let item = prop_oneof![
+        vec(any::<u8>(), 1..=0x4b),
+        vec(any::<u8>(), 0..=0xff),
+        vec(any::<u8>(), 0x100..=0x200),
+    ];
+    (vec(item, 0..=max_items), vec(0..3_u8, max_items)).prop_map(|(items, widths)| {
+        let mut raw = vec![opcodes::OP_RETURN];
+        for (item, width) in items.iter().zip(widths) {
+            push_data(&mut raw, item, width);
+        }
+        (Script::from(raw), items)
+    })
+}
+
+fn push_data(raw: &mut Vec<u8>, item: &[u8], width: u8) {
+    let len = item.len();
+    match width {
+        0 if (1..=0x4b).contains(&len) => raw.push(len as u8),
+        0..=1 if len <= 0xff => {
+            raw.push(opcodes::OP_PUSHDATA1);
+            raw.push(len as u8);
+        }
+        0..=2 if len <= 0xffff => {
+            raw.push(opcodes::OP_PUSHDATA2);
+            raw.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        _ => {
+            raw.push(opcodes::OP_PUSHDATA4);
+            raw.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+    }
+    raw.extend_from_slice(item);
+}
+
+/// Generates arbitrary [`Outpoint`]s.
+pub fn outpoint() -> impl Strategy<Value = Outpoint> {
+    (any::<[u8; 32]>(), any::<u32>()).prop_map(|(tx_id, vout)| Outpoint { tx_id, vout })
+}
+
+/// Generates arbitrary [`Input`]s with scripts of at most `max_script_len` bytes.
+pub fn input(max_script_len: usize) -> impl Strategy<Value = Input> {
+    (outpoint(), script(max_script_len), any::<u32>()).prop_map(|(outpoint, script, sequence)| {
+        Input {
+            outpoint,
+            script,
+            sequence,
+        }
+    })
+}
+
+/// Generates arbitrary [`Output`]s with scripts of at most `max_script_len` bytes.
+pub fn output(max_script_len: usize) -> impl Strategy<Value = Output> {
+    (any::<u64>(), script(max_script_len)).prop_map(|(value, script)| Output { value, script })
+}
+
+/// Generates arbitrary [`Transaction`]s with at most `max_inputs` inputs and `max_outputs` outputs.
+pub fn transaction(max_inputs: usize, max_outputs: usize) -> impl Strategy<Value = Transaction> {
+    (
+        any::<u32>(),
+        vec(input(256), 0..=max_inputs),
+        vec(output(256), 0..=max_outputs),
+        any::<u32>(),
+    )
+        .prop_map(|(version, inputs, outputs, lock_time)| Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        })
+}
+
+/// Encodes `value`, asserting that exactly [`Encodable::encoded_len`] bytes are written.
+///
+/// Returns the encoding so that further properties may be checked against it.
+pub fn assert_encoded_len<T: Encodable>(value: &T) -> Result<Vec<u8>, TestCaseError> {
+    let mut raw = Vec::with_capacity(value.encoded_len());
+    value
+        .encode(&mut raw)
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+    prop_assert_eq!(raw.len(), value.encoded_len(), "encoded_len mismatch");
+    Ok(raw)
+}
+
+/// Asserts that decoding the encoding of `value` consumes it entirely and yields `value`.
+pub fn assert_round_trip<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: Encodable + Decodable + PartialEq + fmt::Debug,
+    T::Error: fmt::Debug,
+{
+    let raw = assert_encoded_len(value)?;
+    let mut buf = raw.as_slice();
+    let decoded = T::decode(&mut buf)
+        .map_err(|err| TestCaseError::fail(format!("decoding failed: {:?}", err)))?;
+    prop_assert!(buf.is_empty(), "{} trailing bytes", buf.len());
+    prop_assert_eq!(&decoded, value);
+    Ok(())
+}
+
+/// Asserts that every strict prefix of the encoding of `value` fails to decode.
+pub fn assert_truncation_rejected<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: Encodable + Decodable + fmt::Debug,
+{
+    let raw = assert_encoded_len(value)?;
+    for len in 0..raw.len() {
+        let mut buf = &raw[..len];
+        prop_assert!(
+            T::decode(&mut buf).is_err(),
+            "prefix of length {} decoded",
+            len
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn var_int_round_trip(var_int in var_int()) {
+            assert_round_trip(&var_int)?;
+            assert_truncation_rejected(&var_int)?;
+        }
+
+        #[test]
+        fn var_int_non_minimal_rejected(var_int in var_int()) {
+            let width = var_int.encoded_len();
+            prop_assume!(width < 9);
+            let mut raw = vec![0xff];
+            raw.extend_from_slice(&var_int.0.to_le_bytes());
+            prop_assert!(VarInt::decode(&mut raw.as_slice()).is_err());
+        }
+
+        #[test]
+        fn script_encoded_len(script in script(1024)) {
+            let raw = assert_encoded_len(&script)?;
+            prop_assert_eq!(raw.as_slice(), script.as_bytes());
+        }
+
+        #[test]
+        fn op_return_data((script, items) in op_return_script(4)) {
+            let data = script.op_return_data();
+            let expected: Vec<&[u8]> = items.iter().map(Vec::as_slice).collect();
+            prop_assert_eq!(data, Some(expected));
+        }
+
+        #[test]
+        fn outpoint_round_trip(outpoint in outpoint()) {
+            assert_round_trip(&outpoint)?;
+        }
+
+        #[test]
+        fn input_round_trip(input in input(1024)) {
+            assert_round_trip(&input)?;
+            assert_truncation_rejected(&input)?;
+        }
+
+        #[test]
+        fn output_round_trip(output in output(1024)) {
+            assert_round_trip(&output)?;
+            assert_truncation_rejected(&output)?;
+        }
+
+        #[test]
+        fn transaction_round_trip(transaction in transaction(4, 4)) {
+            assert_round_trip(&transaction)?;
+        }
+    }
+}
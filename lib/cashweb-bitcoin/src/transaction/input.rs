@@ -7,7 +7,7 @@ use thiserror::Error;
 use crate::{
     transaction::{
         outpoint::{self, Outpoint},
-        script::Script,
+        script::{Script, MAX_SCRIPT_SIZE},
     },
     var_int::{self, VarInt},
     Decodable, Encodable,
@@ -30,6 +30,14 @@ pub enum DecodeError {
     SequenceTooShort,
 }
 
+/// Error associated with constructing an [`Input`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ValidationError {
+    /// Script exceeds [`MAX_SCRIPT_SIZE`].
+    #[error("script of {0} bytes exceeds maximum")]
+    ScriptTooLarge(usize),
+}
+
 /// Represents an input.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -39,6 +47,36 @@ pub struct Input {
     pub sequence: u32,
 }
 
+impl Input {
+    /// Construct an input spending the outpoint.
+    pub fn new(outpoint: Outpoint, script: Script, sequence: u32) -> Result<Self, ValidationError> {
+        let input = Input {
+            outpoint,
+            script,
+            sequence,
+        };
+        input.validate()?;
+        Ok(input)
+    }
+
+    /// Check the script is at most [`MAX_SCRIPT_SIZE`].
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.script.len() > MAX_SCRIPT_SIZE {
+            return Err(ValidationError::ScriptTooLarge(self.script.len()));
+        }
+        Ok(())
+    }
+
+    /// Copy the script into its own allocation, see [`Script::into_owned`].
+    #[inline]
+    pub fn into_owned(self) -> Self {
+        Input {
+            script: self.script.into_owned(),
+            ..self
+        }
+    }
+}
+
 impl Encodable for Input {
     #[inline]
     fn encoded_len(&self) -> usize {
@@ -73,9 +111,7 @@ impl Decodable for Input {
         if buf.remaining() < script_len {
             return Err(Self::Error::ScriptTooShort);
         }
-        let mut raw_script = vec![0; script_len];
-        buf.copy_to_slice(&mut raw_script);
-        let script = raw_script.into();
+        let script = buf.copy_to_bytes(script_len).into();
 
         // Parse sequence number
         if buf.remaining() < 4 {
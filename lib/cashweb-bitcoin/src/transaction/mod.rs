@@ -5,6 +5,8 @@ pub mod input;
 pub mod outpoint;
 pub mod output;
 pub mod script;
+pub mod sighash;
+pub mod tx_id;
 
 use std::convert::TryInto;
 
@@ -14,7 +16,12 @@ use thiserror::Error;
 
 use crate::{
     merkle,
-    transaction::{input::Input, output::Output, script::Script},
+    transaction::{
+        input::Input,
+        output::Output,
+        script::Script,
+        tx_id::{HexError, TxId},
+    },
     var_int::{DecodeError as VarIntDecodeError, VarInt},
     Decodable, Encodable,
 };
@@ -45,7 +52,10 @@ impl SignatureHashType {
     /// Checks whether the signature hash is `anyone-can-pay`.
     #[inline]
     pub fn is_anyone_can_pay(&self) -> bool {
-        matches!(self, Self::All | Self::None | Self::Single)
+        matches!(
+            self,
+            Self::AnyoneCanPayAll | Self::AnyoneCanPayNone | Self::AnyoneCanPaySingle
+        )
     }
 }
 
@@ -67,6 +77,16 @@ pub fn transaction_hash(raw_transaction: &[u8]) -> [u8; 32] {
 }
 
 impl Transaction {
+    /// Copy every script into its own allocation, releasing any buffer the transaction was
+    /// decoded from.
+    pub fn into_owned(self) -> Self {
+        Transaction {
+            inputs: self.inputs.into_iter().map(Input::into_owned).collect(),
+            outputs: self.outputs.into_iter().map(Output::into_owned).collect(),
+            ..self
+        }
+    }
+
     /// Calculate the transaction hash in little-endian format. This is the double SHA256 digest of the raw transaction.
     ///
     /// Note that typically the transaction hash are big-endian encoded.
@@ -125,6 +145,30 @@ impl Transaction {
         merkle::sha256d(&buf)
     }
 
+    /// The [`TxId`] of the transaction, see [`Transaction::transaction_id`].
+    #[inline]
+    pub fn tx_id(&self) -> TxId {
+        TxId::from(self.transaction_id())
+    }
+
+    /// Decode a transaction from its hex encoding, rejecting any bytes following it.
+    pub fn from_hex(hex: &str) -> Result<Self, HexError> {
+        let raw_transaction = tx_id::decode_hex(hex)?;
+        let mut buf = raw_transaction.as_slice();
+        let transaction = Self::decode(&mut buf).map_err(HexError::Transaction)?;
+        if !buf.is_empty() {
+            return Err(HexError::TrailingBytes(buf.len()));
+        }
+        Ok(transaction)
+    }
+
+    /// Encode the transaction as hex.
+    pub fn to_hex(&self) -> String {
+        let mut raw_transaction = Vec::with_capacity(self.encoded_len());
+        self.encode_raw(&mut raw_transaction);
+        hex::encode(raw_transaction)
+    }
+
     /// Calculate input count VarInt.
     #[inline]
     fn input_count_varint(&self) -> VarInt {
@@ -146,7 +190,11 @@ impl Transaction {
         sig_hash_type: SignatureHashType,
     ) -> Option<[u8; 32]> {
         // Special-case sighash_single bug because this is easy enough.
-        if sig_hash_type == SignatureHashType::Single && input_index >= self.outputs.len() {
+        let single = matches!(
+            sig_hash_type,
+            SignatureHashType::Single | SignatureHashType::AnyoneCanPaySingle
+        );
+        if single && input_index >= self.outputs.len() {
             const UNIT_HASH: [u8; 32] = [
                 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0,
@@ -191,8 +239,8 @@ impl Transaction {
 
         // Construct outputs
         let outputs = match sig_hash_type {
-            SignatureHashType::All => self.outputs.clone(),
-            SignatureHashType::Single => self
+            SignatureHashType::All | SignatureHashType::AnyoneCanPayAll => self.outputs.clone(),
+            SignatureHashType::Single | SignatureHashType::AnyoneCanPaySingle => self
                 .outputs
                 .iter()
                 .take(input_index + 1)
@@ -205,8 +253,7 @@ impl Transaction {
                     }
                 })
                 .collect(),
-            SignatureHashType::None => vec![],
-            _ => unreachable!(), // This is safe because we return earlier in these cases
+            SignatureHashType::None | SignatureHashType::AnyoneCanPayNone => vec![],
         };
 
         // Construct transaction
@@ -329,13 +376,54 @@ impl Decodable for Transaction {
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
+
     use super::*;
 
     #[test]
     fn decode() {
         for hex_tx in test_txs() {
-            let raw_tx = hex::decode(hex_tx).unwrap();
-            Transaction::decode(&mut raw_tx.as_slice()).unwrap();
+            let tx = Transaction::from_hex(hex_tx).unwrap();
+            assert_eq!(tx.to_hex(), hex_tx);
+        }
+    }
+
+    #[test]
+    fn from_hex_strict() {
+        let hex_tx = test_txs()[0];
+        assert_eq!(
+            Transaction::from_hex(&format!("{}00", hex_tx)),
+            Err(HexError::TrailingBytes(1))
+        );
+        assert!(matches!(
+            Transaction::from_hex(&hex_tx[..hex_tx.len() - 2]),
+            Err(HexError::Transaction(_))
+        ));
+        assert_eq!(
+            Transaction::from_hex(&hex_tx[1..]),
+            Err(HexError::OddLength)
+        );
+    }
+
+    #[test]
+    fn decode_shared() {
+        fn scripts(tx: &Transaction) -> impl Iterator<Item = &Script> {
+            let inputs = tx.inputs.iter().map(|input| &input.script);
+            let outputs = tx.outputs.iter().map(|output| &output.script);
+            inputs.chain(outputs).filter(|script| !script.is_empty())
+        }
+
+        for hex_tx in test_txs() {
+            let raw_tx = Bytes::from(hex::decode(hex_tx).unwrap());
+            let source = raw_tx.as_ptr_range();
+            let tx = Transaction::decode_shared(&mut raw_tx.clone()).unwrap();
+            assert_eq!(tx, Transaction::decode(&mut raw_tx.as_ref()).unwrap());
+
+            // Scripts are split from the source buffer until copied
+            assert!(scripts(&tx).all(|script| source.contains(&script.as_bytes().as_ptr())));
+            let owned = tx.clone().into_owned();
+            assert_eq!(owned, tx);
+            assert!(scripts(&owned).all(|script| !source.contains(&script.as_bytes().as_ptr())));
         }
     }
 
@@ -370,7 +458,7 @@ mod tests {
             let raw_tx_input = hex::decode(hex_tx).unwrap();
             let tx = Transaction::decode(&mut raw_tx_input.as_slice()).unwrap();
 
-            let mut raw_tx_output = Vec::with_capacity(0);
+            let mut raw_tx_output: Vec<u8> = Vec::with_capacity(0);
             assert!(tx.encode(&mut raw_tx_output.as_mut_slice()).is_err());
         }
     }
@@ -378,12 +466,14 @@ mod tests {
     #[test]
     fn test_txid_calculations() {
         for (hex_tx, hex_txid) in test_txs_for_txid() {
-            let raw_tx_input = hex::decode(hex_tx).unwrap();
-            let raw_tx_id = hex::decode(hex_txid).unwrap();
-
-            let tx = Transaction::decode(&mut raw_tx_input.as_slice()).unwrap();
-
-            assert_eq!(tx.transaction_id_rev().to_vec(), raw_tx_id);
+            let tx = Transaction::from_hex(hex_tx).unwrap();
+            let tx_id = TxId::from_hex::<tx_id::DisplayOrder>(hex_txid).unwrap();
+
+            assert_eq!(
+                tx.transaction_id_rev().to_vec(),
+                hex::decode(hex_txid).unwrap()
+            );
+            assert_eq!(tx.tx_id(), tx_id);
         }
     }
 
@@ -7,7 +7,7 @@ use thiserror::Error;
 use crate::{Decodable, Encodable};
 
 /// Represents an outpoint.
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
 pub struct Outpoint {
     pub tx_id: [u8; 32],
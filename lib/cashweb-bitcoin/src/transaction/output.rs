@@ -5,7 +5,7 @@ use bytes::{Buf, BufMut};
 use thiserror::Error;
 
 use crate::{
-    transaction::script::Script,
+    transaction::script::{opcodes, push_data, Script, MAX_SCRIPT_SIZE},
     var_int::{DecodeError as VarIntDecodeError, VarInt},
     Decodable, Encodable,
 };
@@ -24,6 +24,26 @@ pub enum DecodeError {
     ScriptTooShort,
 }
 
+/// Error associated with constructing an [`Output`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ValidationError {
+    /// Value exceeds [`MAX_MONEY`].
+    #[error("value {0} exceeds maximum")]
+    ValueTooLarge(u64),
+    /// Script exceeds [`MAX_SCRIPT_SIZE`].
+    #[error("script of {0} bytes exceeds maximum")]
+    ScriptTooLarge(usize),
+    /// OP_RETURN script exceeds [`MAX_OP_RETURN_SIZE`].
+    #[error("op_return script of {0} bytes exceeds maximum")]
+    OpReturnTooLarge(usize),
+}
+
+/// Maximum value, in satoshis, of an output.
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+/// Maximum size, in bytes, of an OP_RETURN script relayed by nodes.
+pub const MAX_OP_RETURN_SIZE: usize = 223;
+
 /// Represents an output.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -32,6 +52,55 @@ pub struct Output {
     pub script: Script,
 }
 
+impl Output {
+    /// Construct an output paying the value to the script.
+    pub fn new(value: u64, script: Script) -> Result<Self, ValidationError> {
+        let output = Output { value, script };
+        output.validate()?;
+        Ok(output)
+    }
+
+    /// Construct an output paying the value to the public key hash.
+    pub fn new_p2pkh(value: u64, pubkey_hash: &[u8; 20]) -> Result<Self, ValidationError> {
+        Self::new(value, Script::p2pkh(pubkey_hash))
+    }
+
+    /// Construct an OP_RETURN output, of zero value, pushing each item of the data.
+    pub fn new_op_return(data: &[&[u8]]) -> Result<Self, ValidationError> {
+        let mut script = vec![opcodes::OP_RETURN];
+        for item in data {
+            push_data(&mut script, item);
+        }
+        Self::new(0, Script::from(script))
+    }
+
+    /// Check the value is at most [`MAX_MONEY`] and the script is at most [`MAX_SCRIPT_SIZE`], or
+    /// [`MAX_OP_RETURN_SIZE`] for OP_RETURN scripts.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.value > MAX_MONEY {
+            return Err(ValidationError::ValueTooLarge(self.value));
+        }
+        let script_len = self.script.len();
+        if self.script.is_op_return() {
+            if script_len > MAX_OP_RETURN_SIZE {
+                return Err(ValidationError::OpReturnTooLarge(script_len));
+            }
+        } else if script_len > MAX_SCRIPT_SIZE {
+            return Err(ValidationError::ScriptTooLarge(script_len));
+        }
+        Ok(())
+    }
+
+    /// Copy the script into its own allocation, see [`Script::into_owned`].
+    #[inline]
+    pub fn into_owned(self) -> Self {
+        Output {
+            script: self.script.into_owned(),
+            ..self
+        }
+    }
+}
+
 impl Encodable for Output {
     #[inline]
     fn encoded_len(&self) -> usize {
@@ -63,9 +132,39 @@ impl Decodable for Output {
         if buf.remaining() < script_len {
             return Err(Self::Error::ScriptTooShort);
         }
-        let mut raw_script = vec![0; script_len];
-        buf.copy_to_slice(&mut raw_script);
-        let script = raw_script.into();
+        let script = buf.copy_to_bytes(script_len).into();
         Ok(Output { value, script })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        let output = Output::new_p2pkh(1_000, &[7; 20]).unwrap();
+        assert!(output.script.is_p2pkh());
+        assert_eq!(
+            Output::new_p2pkh(MAX_MONEY + 1, &[7; 20]),
+            Err(ValidationError::ValueTooLarge(MAX_MONEY + 1))
+        );
+
+        let output = Output::new_op_return(&[b"logos", &[0xff; 3]]).unwrap();
+        assert_eq!(output.value, 0);
+        assert_eq!(
+            output.script.op_return_data(),
+            Some(vec![&b"logos"[..], &[0xff; 3][..]])
+        );
+        assert_eq!(
+            Output::new_op_return(&[&[0; 221]]),
+            Err(ValidationError::OpReturnTooLarge(224))
+        );
+
+        let script = Script::from(vec![opcodes::OP_CHECKSIG; MAX_SCRIPT_SIZE + 1]);
+        assert_eq!(
+            Output::new(0, script),
+            Err(ValidationError::ScriptTooLarge(MAX_SCRIPT_SIZE + 1))
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! This module contains conversions between a [`Script`] and the [`Address`] it pays, in either
+//! the cashaddr or base58 format.
+
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+pub use bitcoincash_addr::{Address, HashType, Network, Scheme};
+
+use super::{opcodes, Script, ScriptClass};
+
+/// Error associated with converting between a [`Script`] and an [`Address`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum AddressError {
+    /// The address body was not a 20 byte hash.
+    #[error("address body has invalid length ({0})")]
+    InvalidLength(usize),
+    /// The script was neither P2PKH nor P2SH.
+    #[error("script is neither p2pkh nor p2sh")]
+    NonStandard,
+}
+
+impl Script {
+    /// Construct the P2PKH script paying the public key hash.
+    pub fn p2pkh(pubkey_hash: &[u8; 20]) -> Script {
+        let mut script = Vec::with_capacity(25);
+        script.push(opcodes::OP_DUP);
+        script.push(opcodes::OP_HASH160);
+        script.push(opcodes::OP_PUSHBYTES_20);
+        script.extend_from_slice(pubkey_hash);
+        script.push(opcodes::OP_EQUALVERIFY);
+        script.push(opcodes::OP_CHECKSIG);
+        Script::from(script)
+    }
+
+    /// Construct the P2SH script paying the script hash.
+    pub fn p2sh(script_hash: &[u8; 20]) -> Script {
+        let mut script = Vec::with_capacity(23);
+        script.push(opcodes::OP_HASH160);
+        script.push(opcodes::OP_PUSHBYTES_20);
+        script.extend_from_slice(script_hash);
+        script.push(opcodes::OP_EQUAL);
+        Script::from(script)
+    }
+
+    /// Construct the script paying the address.
+    pub fn from_address(address: &Address) -> Result<Script, AddressError> {
+        let body = address.as_body();
+        let hash: &[u8; 20] =
+            <&[u8; 20]>::try_from(body).map_err(|_| AddressError::InvalidLength(body.len()))?;
+        Ok(match address.hash_type {
+            HashType::Key => Script::p2pkh(hash),
+            HashType::Script => Script::p2sh(hash),
+        })
+    }
+
+    /// The hash paid by a P2PKH or P2SH script, along with its type.
+    ///
+    /// Returns `None` for any other script.
+    pub fn address_hash(&self) -> Option<(HashType, &[u8])> {
+        match self.classify() {
+            // These are safe as the scripts fit the patterns
+            ScriptClass::P2PKH => Some((HashType::Key, &self.raw[3..23])),
+            ScriptClass::P2SH => Some((HashType::Script, &self.raw[2..22])),
+            _ => None,
+        }
+    }
+
+    /// The address, on the network and in the format, paid by a P2PKH or P2SH script.
+    pub fn to_address(&self, network: Network, scheme: Scheme) -> Result<Address, AddressError> {
+        let (hash_type, hash) = self.address_hash().ok_or(AddressError::NonStandard)?;
+        Ok(Address::new(hash.to_vec(), scheme, hash_type, network))
+    }
+}
+
+impl TryFrom<&Address> for Script {
+    type Error = AddressError;
+
+    fn try_from(address: &Address) -> Result<Self, Self::Error> {
+        Script::from_address(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for hash_type in [HashType::Key, HashType::Script] {
+            for scheme in [Scheme::CashAddr, Scheme::Base58] {
+                let address = Address::new(
+                    vec![7; 20],
+                    scheme.clone(),
+                    hash_type.clone(),
+                    Network::Test,
+                );
+                let script = Script::from_address(&address).unwrap();
+                assert_eq!(
+                    script.address_hash(),
+                    Some((hash_type.clone(), &[7; 20][..]))
+                );
+                assert_eq!(script.to_address(Network::Test, scheme).unwrap(), address);
+            }
+        }
+        assert!(Script::p2pkh(&[0; 20]).is_p2pkh());
+        assert!(Script::p2sh(&[0; 20]).is_p2sh());
+
+        let address = Address::new(vec![7; 32], Scheme::CashAddr, HashType::Key, Network::Main);
+        assert_eq!(
+            Script::try_from(&address),
+            Err(AddressError::InvalidLength(32))
+        );
+        let script = Script::from(vec![opcodes::OP_RETURN]);
+        assert_eq!(
+            script.to_address(Network::Main, Scheme::CashAddr),
+            Err(AddressError::NonStandard)
+        );
+    }
+}
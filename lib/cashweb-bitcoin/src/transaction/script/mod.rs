@@ -1,39 +1,258 @@
 //! This module contains the [`Script`] struct which represents a Bitcoin transaction script.
 //! It enjoys [`Encodable`], and provides some utility methods.
 
+pub mod address;
 pub mod opcodes;
+pub mod timelock;
 
-use bytes::BufMut;
+use std::{fmt, sync::OnceLock};
+
+use bytes::{BufMut, Bytes};
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
 
 use crate::{var_int::VarInt, Encodable};
 
+/// Maximum size, in bytes, of a script executed.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Maximum number of operations executed by a script, see [`Script::op_count`].
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Maximum number of public keys checked by a multisig operation, see [`Script::sigop_count`].
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
 /// Represents a script.
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub struct Script(pub Vec<u8>);
+///
+/// The instructions of the script are parsed on first access and cached, which requires Rust
+/// 1.70 for [`OnceLock`]. The [`ScriptClass`] is matched from the bytes without parsing.
+///
+/// The bytes are private, where they were previously a public tuple field. Construct scripts using
+/// [`Script::from`] and access the bytes using [`Script::as_bytes`] or [`Script::into_bytes`].
+///
+/// The script may share its bytes with the buffer it was decoded from, see
+/// [`Decodable::decode_shared`] and [`Script::into_owned`].
+///
+/// [`Decodable::decode_shared`]: crate::Decodable::decode_shared
+#[derive(Clone, Default)]
+pub struct Script {
+    raw: Bytes,
+    parsed: OnceLock<Parsed>,
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.raw).finish()
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl Eq for Script {}
 
 impl From<Script> for Vec<u8> {
     fn from(script: Script) -> Self {
-        script.0
+        script.raw.to_vec()
+    }
+}
+
+impl From<Script> for Bytes {
+    fn from(script: Script) -> Self {
+        script.raw
     }
 }
 
 impl From<Vec<u8>> for Script {
     fn from(raw: Vec<u8>) -> Self {
-        Script(raw)
+        Bytes::from(raw).into()
+    }
+}
+
+impl From<Bytes> for Script {
+    fn from(raw: Bytes) -> Self {
+        Script {
+            raw,
+            parsed: OnceLock::new(),
+        }
+    }
+}
+
+/// Enumerates the standard script patterns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptClass {
+    /// Pay to public key hash.
+    P2PKH,
+    /// Pay to script hash.
+    P2SH,
+    /// Provably unspendable data carrier.
+    OpReturn,
+    /// Any other script.
+    NonStandard,
+}
+
+/// A single script instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction<'a> {
+    /// Data pushed by one of the push opcodes, `OP_PUSHBYTES_1` through `OP_PUSHDATA4`.
+    PushBytes(&'a [u8]),
+    /// Any other operation.
+    Op(u8),
+}
+
+/// Iterator over the instructions of a [`Script`], see [`Script::instructions`].
+#[derive(Clone, Debug)]
+pub struct Instructions<'a> {
+    raw: &'a [u8],
+    spans: std::slice::Iter<'a, Span>,
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Instruction<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let instruction = match *self.spans.next()? {
+            Span::Push(start, end) => Instruction::PushBytes(&self.raw[start..end]),
+            Span::Op(opcode) => Instruction::Op(opcode),
+        };
+        Some(instruction)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.spans.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Instructions<'_> {}
+
+#[derive(Clone, Copy, Debug)]
+enum Span {
+    Push(usize, usize),
+    Op(u8),
+}
+
+#[derive(Clone, Debug)]
+struct Parsed {
+    // The instructions preceding any push running past the end of the script
+    spans: Vec<Span>,
+    truncated: bool,
+}
+
+impl Parsed {
+    fn new(raw: &[u8]) -> Self {
+        let mut spans = Vec::new();
+        let truncated = parse_spans(raw, &mut spans).is_none();
+        Parsed { spans, truncated }
+    }
+}
+
+/// Parse the instructions of the script, returning `None` if a push runs past its end.
+fn parse_spans(raw: &[u8], spans: &mut Vec<Span>) -> Option<()> {
+    let mut cursor = 0;
+    while let Some(&opcode) = raw.get(cursor) {
+        cursor += 1;
+        let len = match opcode {
+            0x01..=0x4b => opcode as usize,
+            opcodes::OP_PUSHDATA1 => *raw.get(cursor)? as usize,
+            opcodes::OP_PUSHDATA2 => {
+                let len = raw.get(cursor..cursor + 2)?;
+                u16::from_le_bytes([len[0], len[1]]) as usize
+            }
+            opcodes::OP_PUSHDATA4 => {
+                let len = raw.get(cursor..cursor + 4)?;
+                u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize
+            }
+            _ => {
+                spans.push(Span::Op(opcode));
+                continue;
+            }
+        };
+        cursor += match opcode {
+            opcodes::OP_PUSHDATA1 => 1,
+            opcodes::OP_PUSHDATA2 => 2,
+            opcodes::OP_PUSHDATA4 => 4,
+            _ => 0,
+        };
+        let end = cursor.checked_add(len).filter(|end| *end <= raw.len())?;
+        spans.push(Span::Push(cursor, end));
+        cursor = end;
+    }
+    Some(())
+}
+
+fn classify(raw: &[u8]) -> ScriptClass {
+    match raw {
+        [opcodes::OP_DUP, opcodes::OP_HASH160, opcodes::OP_PUSHBYTES_20, .., opcodes::OP_EQUALVERIFY, opcodes::OP_CHECKSIG]
+            if raw.len() == 25 =>
+        {
+            ScriptClass::P2PKH
+        }
+        [opcodes::OP_HASH160, opcodes::OP_PUSHBYTES_20, .., opcodes::OP_EQUAL]
+            if raw.len() == 23 =>
+        {
+            ScriptClass::P2SH
+        }
+        [opcodes::OP_RETURN, ..] => ScriptClass::OpReturn,
+        _ => ScriptClass::NonStandard,
+    }
+}
+
+/// Push the data onto a script, using the smallest push operation.
+pub fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    let len = data.len();
+    if len < opcodes::OP_PUSHDATA1 as usize {
+        script.push(len as u8);
+    } else if len <= u8::MAX as usize {
+        script.push(opcodes::OP_PUSHDATA1);
+        script.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        script.push(opcodes::OP_PUSHDATA2);
+        script.extend_from_slice(&(len as u16).to_le_bytes());
+    } else {
+        script.push(opcodes::OP_PUSHDATA4);
+        script.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    script.extend_from_slice(data);
+}
+
+/// Push a number onto a script, minimally encoded.
+pub fn push_number(script: &mut Vec<u8>, number: u32) {
+    match number {
+        0 => script.push(opcodes::OP_0),
+        1..=16 => script.push(opcodes::OP_1 + number as u8 - 1),
+        _ => {
+            let mut bytes = number.to_le_bytes().to_vec();
+            while bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            // Pad to keep the number positive
+            if matches!(bytes.last(), Some(byte) if byte & 0x80 != 0) {
+                bytes.push(0);
+            }
+            push_data(script, &bytes);
+        }
     }
 }
 
 impl Script {
+    #[inline]
+    fn parsed(&self) -> &Parsed {
+        self.parsed.get_or_init(|| Parsed::new(&self.raw))
+    }
+
     /// Check whether the script is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.raw.is_empty()
     }
 
     /// Length of the script.
     #[inline]
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.raw.len()
     }
 
     /// Length of the script as `VarInt`.
@@ -48,38 +267,310 @@ impl Script {
         self.into()
     }
 
+    /// Copy the script into its own allocation, releasing any buffer it was decoded from.
+    ///
+    /// The cached parse is kept.
+    #[inline]
+    pub fn into_owned(self) -> Self {
+        Script {
+            raw: Bytes::copy_from_slice(&self.raw),
+            parsed: self.parsed,
+        }
+    }
+
     /// Converts the script into a byte slice.
     #[inline]
     pub fn as_bytes(&self) -> &[u8] {
-        &self.0
+        &self.raw
+    }
+
+    /// Iterate over the instructions of the script.
+    ///
+    /// Returns `None` if a push runs past the end of the script.
+    #[inline]
+    pub fn instructions(&self) -> Option<Instructions<'_>> {
+        let parsed = self.parsed();
+        if parsed.truncated {
+            return None;
+        }
+        Some(self.leading_instructions())
+    }
+
+    /// Iterate over the instructions preceding any push running past the end of the script.
+    fn leading_instructions(&self) -> Instructions<'_> {
+        Instructions {
+            raw: &self.raw,
+            spans: self.parsed().spans.iter(),
+        }
+    }
+
+    /// Count the operations of the script, excluding pushes and the constants `OP_1NEGATE` to
+    /// `OP_16`, as limited by [`MAX_OPS_PER_SCRIPT`] during execution.
+    ///
+    /// Operations following a truncated push are not counted.
+    pub fn op_count(&self) -> usize {
+        self.leading_instructions()
+            .filter(|instruction| matches!(instruction, Instruction::Op(opcode) if *opcode > opcodes::OP_16))
+            .count()
+    }
+
+    /// Count the signature operations of the script, without executing it.
+    ///
+    /// `OP_CHECKSIG`, `OP_CHECKDATASIG` and their verifying variants count one each.
+    /// `OP_CHECKMULTISIG` and `OP_CHECKMULTISIGVERIFY` count their number of public keys when
+    /// `accurate` and preceded by `OP_1` to `OP_16`, as in redeem scripts, and otherwise
+    /// [`MAX_PUBKEYS_PER_MULTISIG`]. Operations following a truncated push are not counted.
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last_opcode = None;
+        for instruction in self.leading_instructions() {
+            let opcode = match instruction {
+                Instruction::Op(opcode) => opcode,
+                Instruction::PushBytes(_) => {
+                    last_opcode = None;
+                    continue;
+                }
+            };
+            match opcode {
+                opcodes::OP_CHECKSIG
+                | opcodes::OP_CHECKSIGVERIFY
+                | opcodes::OP_CHECKDATASIG
+                | opcodes::OP_CHECKDATASIGVERIFY => count += 1,
+                opcodes::OP_CHECKMULTISIG | opcodes::OP_CHECKMULTISIGVERIFY => {
+                    count += match last_opcode {
+                        Some(n @ opcodes::OP_1..=opcodes::OP_16) if accurate => {
+                            (n - opcodes::OP_1 + 1) as usize
+                        }
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    }
+                }
+                _ => (),
+            }
+            last_opcode = Some(opcode);
+        }
+        count
+    }
+
+    /// Count the signature operations of the redeem script spent by a P2SH script, being the
+    /// last push of the `scriptSig`, accurately.
+    ///
+    /// Returns the inaccurate count of this script when it is not P2SH, and zero when the
+    /// `scriptSig` contains operations other than pushes, as it is then invalid.
+    pub fn p2sh_sigop_count(&self, script_sig: &Script) -> usize {
+        if !self.is_p2sh() {
+            return self.sigop_count(false);
+        }
+        let mut redeem_script = None;
+        for instruction in script_sig.leading_instructions() {
+            match instruction {
+                Instruction::PushBytes(data) => redeem_script = Some(data),
+                // Pushes of no data and small numbers
+                Instruction::Op(opcode) if opcode <= opcodes::OP_16 => redeem_script = None,
+                Instruction::Op(_) => return 0,
+            }
+        }
+        redeem_script
+            .map(|redeem_script| Script::from(redeem_script.to_vec()).sigop_count(true))
+            .unwrap_or(0)
+    }
+
+    /// Classify the script, matching its bytes against the standard patterns.
+    #[inline]
+    pub fn classify(&self) -> ScriptClass {
+        classify(&self.raw)
     }
 
     /// Checks whether the script fits the OP_RETURN pattern.
     #[inline]
     pub fn is_op_return(&self) -> bool {
-        !self.0.is_empty() && self.0[0] == opcodes::OP_RETURN
+        self.classify() == ScriptClass::OpReturn
+    }
+
+    /// Parse the data pushed by an OP_RETURN script.
+    ///
+    /// Returns `None` if the script does not fit the OP_RETURN pattern, contains operations other
+    /// than pushes, or is truncated.
+    pub fn op_return_data(&self) -> Option<Vec<&[u8]>> {
+        if !self.is_op_return() {
+            return None;
+        }
+        self.instructions()?
+            .skip(1)
+            .map(|instruction| match instruction {
+                Instruction::PushBytes(data) => Some(data),
+                Instruction::Op(_) => None,
+            })
+            .collect()
     }
 
     /// Checks whether the scripts the P2PKH pattern.
     #[inline]
     pub fn is_p2pkh(&self) -> bool {
-        self.0.len() == 25
-            && self.0[0] == opcodes::OP_DUP
-            && self.0[1] == opcodes::OP_HASH160
-            && self.0[2] == opcodes::OP_PUSHBYTES_20
-            && self.0[23] == opcodes::OP_EQUALVERIFY
-            && self.0[24] == opcodes::OP_CHECKSIG
+        self.classify() == ScriptClass::P2PKH
+    }
+
+    /// Checks whether the scripts the P2SH pattern.
+    #[inline]
+    pub fn is_p2sh(&self) -> bool {
+        self.classify() == ScriptClass::P2SH
+    }
+
+    /// Calculate the HASH160 of the script, as committed to by a P2SH script.
+    pub fn script_hash(&self) -> [u8; 20] {
+        let sha256_digest = digest(&SHA256, &self.raw);
+        Ripemd160::digest(sha256_digest.as_ref()).into()
+    }
+
+    /// Construct the P2SH script paying to this redeem script.
+    pub fn to_p2sh(&self) -> Script {
+        Script::p2sh(&self.script_hash())
     }
 }
 
 impl Encodable for Script {
     #[inline]
     fn encoded_len(&self) -> usize {
-        self.0.len()
+        self.raw.len()
     }
 
     #[inline]
     fn encode_raw<B: BufMut>(&self, buf: &mut B) {
-        buf.put(&self.0[..]);
+        buf.put(&self.raw[..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push() {
+        let mut script = Vec::new();
+        push_number(&mut script, 16);
+        push_number(&mut script, 128);
+        push_number(&mut script, 700_000);
+        assert_eq!(script, vec![0x60, 2, 0x80, 0x00, 3, 0x60, 0xae, 0x0a]);
+
+        let mut script = Vec::new();
+        push_data(&mut script, &[0xff; 80]);
+        push_data(&mut script, &[0xff; 300]);
+        let script = Script::from(script);
+        let pushes: Vec<_> = script.instructions().unwrap().collect();
+        assert_eq!(
+            pushes,
+            vec![
+                Instruction::PushBytes(&[0xff; 80]),
+                Instruction::PushBytes(&[0xff; 300])
+            ]
+        );
+        assert!(script.to_p2sh().is_p2sh());
+    }
+
+    #[test]
+    fn op_return_data() {
+        let script = Script::from(vec![
+            opcodes::OP_RETURN,
+            0x02,
+            1,
+            2,
+            opcodes::OP_PUSHDATA1,
+            0x01,
+            3,
+        ]);
+        assert_eq!(script.op_return_data(), Some(vec![&[1, 2][..], &[3][..]]));
+
+        // Truncated pushes, other operations and other patterns are rejected
+        assert_eq!(
+            Script::from(vec![opcodes::OP_RETURN, 0x02, 1]).op_return_data(),
+            None
+        );
+        let script = Script::from(vec![opcodes::OP_RETURN, opcodes::OP_DUP]);
+        assert_eq!(script.op_return_data(), None);
+        assert_eq!(Script::from(vec![opcodes::OP_DUP]).op_return_data(), None);
+    }
+
+    #[test]
+    fn classify() {
+        let mut p2pkh = vec![
+            opcodes::OP_DUP,
+            opcodes::OP_HASH160,
+            opcodes::OP_PUSHBYTES_20,
+        ];
+        p2pkh.extend_from_slice(&[0; 20]);
+        p2pkh.extend_from_slice(&[opcodes::OP_EQUALVERIFY, opcodes::OP_CHECKSIG]);
+        assert_eq!(Script::from(p2pkh).classify(), ScriptClass::P2PKH);
+
+        let mut p2sh = vec![opcodes::OP_HASH160, opcodes::OP_PUSHBYTES_20];
+        p2sh.extend_from_slice(&[0; 20]);
+        p2sh.push(opcodes::OP_EQUAL);
+        assert_eq!(Script::from(p2sh).classify(), ScriptClass::P2SH);
+
+        let script = Script::from(vec![opcodes::OP_RETURN, 0x02, 1]);
+        assert_eq!(script.classify(), ScriptClass::OpReturn);
+        assert_eq!(Script::default().classify(), ScriptClass::NonStandard);
+
+        // Classifying does not parse the instructions
+        assert!(script.parsed.get().is_none());
+    }
+
+    #[test]
+    fn instructions() {
+        let script = Script::from(vec![opcodes::OP_DUP, 0x01, 7, opcodes::OP_PUSHDATA2, 0, 0]);
+        let instructions: Vec<_> = script.instructions().unwrap().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Op(opcodes::OP_DUP),
+                Instruction::PushBytes(&[7]),
+                Instruction::PushBytes(&[]),
+            ]
+        );
+
+        // Clones carry over the cache, which does not affect equality
+        let cloned = script.clone();
+        assert_eq!(cloned.instructions().unwrap().len(), 3);
+        assert_eq!(cloned, Script::from(script.into_bytes()));
+
+        assert!(Script::from(vec![opcodes::OP_PUSHDATA1])
+            .instructions()
+            .is_none());
+    }
+
+    #[test]
+    fn sigop_count() {
+        // 2-of-3 multisig
+        let mut multisig = Vec::new();
+        push_number(&mut multisig, 2);
+        for _ in 0..3 {
+            push_data(&mut multisig, &[0x02; 33]);
+        }
+        push_number(&mut multisig, 3);
+        multisig.push(opcodes::OP_CHECKMULTISIG);
+        let multisig = Script::from(multisig);
+        assert_eq!(multisig.sigop_count(true), 3);
+        assert_eq!(multisig.sigop_count(false), MAX_PUBKEYS_PER_MULTISIG);
+        assert_eq!(multisig.op_count(), 1);
+
+        let mut script_sig = vec![opcodes::OP_0];
+        push_data(&mut script_sig, multisig.as_bytes());
+        let script_sig = Script::from(script_sig);
+        assert_eq!(multisig.to_p2sh().p2sh_sigop_count(&script_sig), 3);
+        assert_eq!(multisig.to_p2sh().sigop_count(true), 0);
+
+        // Operations in the scriptSig invalidate the spend
+        let invalid = Script::from(vec![opcodes::OP_DUP, 0x01, 7]);
+        assert_eq!(multisig.to_p2sh().p2sh_sigop_count(&invalid), 0);
+
+        // Counting stops at a truncated push
+        let script = Script::from(vec![
+            opcodes::OP_CHECKSIG,
+            opcodes::OP_CHECKDATASIGVERIFY,
+            opcodes::OP_PUSHDATA1,
+            0x02,
+            opcodes::OP_CHECKSIG,
+        ]);
+        assert_eq!(script.sigop_count(true), 2);
+        assert_eq!(script.op_count(), 2);
+        assert!(script.instructions().is_none());
     }
 }
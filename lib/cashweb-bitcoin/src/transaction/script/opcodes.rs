@@ -17,3 +17,63 @@ pub const OP_EQUALVERIFY: u8 = 0x88;
 
 /// OP_CHECKSIG
 pub const OP_CHECKSIG: u8 = 0xac;
+
+/// OP_PUSHDATA1
+pub const OP_PUSHDATA1: u8 = 0x4c;
+
+/// OP_PUSHDATA2
+pub const OP_PUSHDATA2: u8 = 0x4d;
+
+/// OP_PUSHDATA4
+pub const OP_PUSHDATA4: u8 = 0x4e;
+
+/// OP_EQUAL
+pub const OP_EQUAL: u8 = 0x87;
+
+/// OP_0
+pub const OP_0: u8 = 0x00;
+
+/// OP_PUSHBYTES_33
+pub const OP_PUSHBYTES_33: u8 = 0x21;
+
+/// OP_1
+pub const OP_1: u8 = 0x51;
+
+/// OP_2
+pub const OP_2: u8 = 0x52;
+
+/// OP_16
+pub const OP_16: u8 = 0x60;
+
+/// OP_IF
+pub const OP_IF: u8 = 0x63;
+
+/// OP_ELSE
+pub const OP_ELSE: u8 = 0x67;
+
+/// OP_ENDIF
+pub const OP_ENDIF: u8 = 0x68;
+
+/// OP_DROP
+pub const OP_DROP: u8 = 0x75;
+
+/// OP_CHECKSIGVERIFY
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+
+/// OP_CHECKMULTISIG
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// OP_CHECKMULTISIGVERIFY
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+
+/// OP_CHECKLOCKTIMEVERIFY
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+
+/// OP_CHECKSEQUENCEVERIFY
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+/// OP_CHECKDATASIG
+pub const OP_CHECKDATASIG: u8 = 0xba;
+
+/// OP_CHECKDATASIGVERIFY
+pub const OP_CHECKDATASIGVERIFY: u8 = 0xbb;
@@ -0,0 +1,223 @@
+//! This module contains [`Timelock`], which encumbers a script path with an absolute,
+//! `OP_CHECKLOCKTIMEVERIFY`, or relative, `OP_CHECKSEQUENCEVERIFY`, timelock, and
+//! [`RevocableScript`], which pays a beneficiary once a timelock expires unless a revoker spends
+//! it first.
+
+use secp256k1::PublicKey;
+
+use crate::transaction::{
+    script::{opcodes, push_data, push_number, Instruction, Script},
+    Transaction,
+};
+
+/// Lock times below this value are block heights, and UNIX times otherwise.
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// Flag of a sequence number indicating its relative timelock is in units of 512 seconds, rather
+/// than blocks.
+pub const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+/// Sequence number of an input enabling the lock time of its transaction, without a relative
+/// timelock.
+const FINAL_SEQUENCE: u32 = u32::MAX;
+
+/// A timelock encumbering a script path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timelock {
+    /// Spendable once the lock time of the spending transaction reaches the value, a block height
+    /// or, from [`LOCK_TIME_THRESHOLD`], a UNIX time. Enforced by `OP_CHECKLOCKTIMEVERIFY`.
+    Absolute(u32),
+    /// Spendable once the output spent is as old as the value, a [`BIP68`] sequence number.
+    /// Enforced by `OP_CHECKSEQUENCEVERIFY`.
+    ///
+    /// [`BIP68`]: https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
+    Relative(u32),
+}
+
+impl Timelock {
+    /// A relative timelock of a number of blocks.
+    pub fn blocks(blocks: u16) -> Self {
+        Self::Relative(blocks as u32)
+    }
+
+    /// A relative timelock of at least a number of seconds, rounded up to a multiple of 512.
+    pub fn seconds(seconds: u32) -> Self {
+        let intervals = seconds / 512 + (seconds % 512 != 0) as u32;
+        Self::Relative(SEQUENCE_TYPE_FLAG | intervals.min(u16::MAX as u32))
+    }
+
+    /// Push `<value> OP_CHECKLOCKTIMEVERIFY OP_DROP`, or its relative counterpart, onto the
+    /// script.
+    pub fn push_verify(&self, script: &mut Vec<u8>) {
+        match *self {
+            Self::Absolute(lock_time) => {
+                push_number(script, lock_time);
+                script.push(opcodes::OP_CHECKLOCKTIMEVERIFY);
+            }
+            Self::Relative(sequence) => {
+                push_number(script, sequence);
+                script.push(opcodes::OP_CHECKSEQUENCEVERIFY);
+            }
+        }
+        script.push(opcodes::OP_DROP);
+    }
+
+    /// Set the lock time, version or input sequence of the transaction such that the input
+    /// satisfies the timelock.
+    ///
+    /// Returns `None` if the input does not exist.
+    pub fn apply(&self, transaction: &mut Transaction, input_index: usize) -> Option<()> {
+        let input = transaction.inputs.get_mut(input_index)?;
+        match *self {
+            Self::Absolute(lock_time) => {
+                // A final sequence disables the lock time
+                if input.sequence == FINAL_SEQUENCE {
+                    input.sequence = FINAL_SEQUENCE - 1;
+                }
+                transaction.lock_time = lock_time;
+            }
+            Self::Relative(sequence) => {
+                input.sequence = sequence;
+                // Relative timelocks apply from version 2
+                transaction.version = transaction.version.max(2);
+            }
+        }
+        Some(())
+    }
+}
+
+/// The path by which a [`RevocableScript`] was spent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpendPath {
+    /// Spent by the revoker.
+    Revoke,
+    /// Spent by the beneficiary, after the timelock.
+    Claim,
+}
+
+/// A redeem script paying the beneficiary once the timelock expires, unless the revoker spends it
+/// first.
+///
+/// `OP_IF <revoker> OP_CHECKSIG OP_ELSE <timelock> OP_DROP <beneficiary> OP_CHECKSIG OP_ENDIF`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevocableScript {
+    /// Public key which may spend at any time.
+    pub revoker: PublicKey,
+    /// Public key which may spend after the timelock.
+    pub beneficiary: PublicKey,
+    /// The timelock of the beneficiary.
+    pub timelock: Timelock,
+}
+
+impl RevocableScript {
+    /// The redeem script.
+    pub fn redeem_script(&self) -> Script {
+        let mut script = Vec::with_capacity(96);
+        script.push(opcodes::OP_IF);
+        push_data(&mut script, &self.revoker.serialize());
+        script.push(opcodes::OP_CHECKSIG);
+        script.push(opcodes::OP_ELSE);
+        self.timelock.push_verify(&mut script);
+        push_data(&mut script, &self.beneficiary.serialize());
+        script.push(opcodes::OP_CHECKSIG);
+        script.push(opcodes::OP_ENDIF);
+        Script::from(script)
+    }
+
+    /// The P2SH script paying to the redeem script.
+    pub fn p2sh_script(&self) -> Script {
+        self.redeem_script().to_p2sh()
+    }
+
+    /// The `scriptSig` spending by the path, given the signature followed by the signature hash
+    /// type.
+    pub fn script_sig(&self, path: SpendPath, signature: &[u8]) -> Script {
+        let mut script = Vec::with_capacity(signature.len() + 100);
+        push_data(&mut script, signature);
+        script.push(match path {
+            SpendPath::Revoke => opcodes::OP_1,
+            SpendPath::Claim => opcodes::OP_0,
+        });
+        push_data(&mut script, self.redeem_script().as_bytes());
+        Script::from(script)
+    }
+
+    /// The path spent by the `scriptSig`, or `None` if it does not spend this script.
+    pub fn spend_path(&self, script_sig: &Script) -> Option<SpendPath> {
+        let instructions: Vec<_> = script_sig.instructions()?.collect();
+        match instructions[..] {
+            [Instruction::PushBytes(_), Instruction::Op(selector), Instruction::PushBytes(redeem_script)]
+                if redeem_script == self.redeem_script().as_bytes() =>
+            {
+                match selector {
+                    opcodes::OP_1 => Some(SpendPath::Revoke),
+                    opcodes::OP_0 => Some(SpendPath::Claim),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::transaction::{input::Input, outpoint::Outpoint};
+
+    #[test]
+    fn revocable_script() {
+        let secp = Secp256k1::signing_only();
+        let revoker = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap());
+        let beneficiary =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+        let script = RevocableScript {
+            revoker,
+            beneficiary,
+            timelock: Timelock::seconds(3_600),
+        };
+        assert_eq!(script.timelock, Timelock::Relative(SEQUENCE_TYPE_FLAG | 8));
+        assert!(script.p2sh_script().is_p2sh());
+
+        let revoke = script.script_sig(SpendPath::Revoke, &[0x30; 71]);
+        assert_eq!(script.spend_path(&revoke), Some(SpendPath::Revoke));
+        let claim = script.script_sig(SpendPath::Claim, &[0x30; 71]);
+        assert_eq!(script.spend_path(&claim), Some(SpendPath::Claim));
+
+        // Other scripts are not recognised
+        let other = RevocableScript {
+            timelock: Timelock::Absolute(700_000),
+            ..script
+        };
+        assert_eq!(other.spend_path(&claim), None);
+    }
+
+    #[test]
+    fn apply() {
+        let mut transaction = Transaction {
+            version: 1,
+            inputs: vec![Input {
+                outpoint: Outpoint {
+                    tx_id: [0; 32],
+                    vout: 0,
+                },
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![],
+            lock_time: 0,
+        };
+
+        let mut absolute = transaction.clone();
+        Timelock::Absolute(700_000).apply(&mut absolute, 0).unwrap();
+        assert_eq!(absolute.lock_time, 700_000);
+        assert_eq!(absolute.inputs[0].sequence, u32::MAX - 1);
+
+        Timelock::blocks(144).apply(&mut transaction, 0).unwrap();
+        assert_eq!(transaction.version, 2);
+        assert_eq!(transaction.inputs[0].sequence, 144);
+        assert_eq!(Timelock::blocks(1).apply(&mut transaction, 1), None);
+    }
+}
@@ -0,0 +1,531 @@
+//! This module contains the [`SighashCache`] which computes the signature hashes of every input
+//! of a [`Transaction`] without re-serializing it for each input, and [`SighashParams`] which
+//! select the signature hash algorithm of a chain.
+
+use std::convert::TryInto;
+
+use ring::digest::{digest, Context, SHA256};
+use secp256k1::{Message, Secp256k1, SecretKey, Signing};
+
+use crate::{
+    transaction::{input::Input, script::Script, SignatureHashType, Transaction},
+    Encodable,
+};
+
+/// Flag of a signature hash type selecting the [`SighashAlgorithm::ForkId`] algorithm.
+pub const SIGHASH_FORKID: u32 = 0x40;
+
+/// Enumerates the algorithms computing signature hashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SighashAlgorithm {
+    /// The original algorithm, serializing a modified copy of the transaction.
+    Legacy,
+    /// The [`BIP143`] algorithm, committing to the value spent, with replay protection.
+    ///
+    /// [`BIP143`]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    ForkId,
+}
+
+/// The signature hash parameters of a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SighashParams {
+    /// The algorithm computing signature hashes.
+    pub algorithm: SighashAlgorithm,
+    /// The fork ID committed to by [`SighashAlgorithm::ForkId`] signature hashes.
+    pub fork_id: u32,
+}
+
+impl SighashParams {
+    /// Parameters of the original algorithm, without replay protection.
+    pub const LEGACY: Self = Self {
+        algorithm: SighashAlgorithm::Legacy,
+        fork_id: 0,
+    };
+
+    /// Parameters of Bitcoin Cash.
+    pub const BCH: Self = Self {
+        algorithm: SighashAlgorithm::ForkId,
+        fork_id: 0,
+    };
+
+    /// Parameters of eCash.
+    pub const XEC: Self = Self {
+        algorithm: SighashAlgorithm::ForkId,
+        fork_id: 0,
+    };
+
+    /// Parameters of Lotus.
+    ///
+    /// Besides its own `SIGHASH_LOTUS` algorithm, which commits to every output spent, lotusd
+    /// verifies the `SIGHASH_FORKID` signatures of Bitcoin Cash, with a fork ID of zero. These
+    /// are produced, so that a signer needs only the value spent by the input it signs.
+    pub const LOTUS: Self = Self {
+        algorithm: SighashAlgorithm::ForkId,
+        fork_id: 0,
+    };
+
+    /// The signature hash type committed to by the signature hash, the lowest byte of which
+    /// follows the signature.
+    pub fn hash_type(&self, sig_hash_type: SignatureHashType) -> u32 {
+        match self.algorithm {
+            SighashAlgorithm::Legacy => sig_hash_type as u32,
+            SighashAlgorithm::ForkId => (self.fork_id << 8) | SIGHASH_FORKID | sig_hash_type as u32,
+        }
+    }
+}
+
+impl Default for SighashParams {
+    fn default() -> Self {
+        Self::LEGACY
+    }
+}
+
+/// The digests of a transaction shared by the [`SighashAlgorithm::ForkId`] signature hashes of
+/// its inputs.
+#[derive(Debug)]
+struct ForkIdDigests {
+    prevouts: [u8; 32],
+    sequences: [u8; 32],
+    outputs: [u8; 32],
+}
+
+/// Calculate the double SHA256 digest.
+fn sha256d(data: &[u8]) -> [u8; 32] {
+    // This is safe as SHA256 digests are 32 bytes
+    digest(&SHA256, digest(&SHA256, data).as_ref())
+        .as_ref()
+        .try_into()
+        .unwrap()
+}
+
+impl ForkIdDigests {
+    fn new(transaction: &Transaction) -> Self {
+        let mut prevouts = Vec::with_capacity(transaction.inputs.len() * OUTPOINT_LEN);
+        let mut sequences = Vec::with_capacity(transaction.inputs.len() * 4);
+        for input in &transaction.inputs {
+            input.outpoint.encode_raw(&mut prevouts);
+            sequences.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        let mut outputs = Vec::new();
+        for output in &transaction.outputs {
+            output.encode_raw(&mut outputs);
+        }
+        Self {
+            prevouts: sha256d(&prevouts),
+            sequences: sha256d(&sequences),
+            outputs: sha256d(&outputs),
+        }
+    }
+}
+
+/// Caches the serialization of a [`Transaction`] for computing signature hashes.
+///
+/// The [`SighashAlgorithm::Legacy`] signature hash of each input is streamed from the cached
+/// serialization, so signing a transaction with thousands of inputs avoids cloning and encoding
+/// it once per input. Legacy signature hash types other than [`SignatureHashType::All`] fall back
+/// to [`Transaction::signature_hash`]. The [`SighashAlgorithm::ForkId`] signature hashes share
+/// digests of the outpoints, sequences and outputs, computed once.
+#[derive(Debug)]
+pub struct SighashCache<'a> {
+    transaction: &'a Transaction,
+    params: SighashParams,
+    values: Vec<u64>,
+    fork_id_digests: Option<ForkIdDigests>,
+    prefix: Vec<u8>,
+    inputs: Vec<u8>,
+    offsets: Vec<usize>,
+    suffix: Vec<u8>,
+}
+
+/// Length of an encoded outpoint.
+const OUTPOINT_LEN: usize = 36;
+
+impl<'a> SighashCache<'a> {
+    /// Serialize the transaction, with empty input scripts, for signing.
+    pub fn new(transaction: &'a Transaction) -> Self {
+        let mut prefix = Vec::with_capacity(4 + 9);
+        prefix.extend_from_slice(&transaction.version.to_le_bytes());
+        transaction.input_count_varint().encode_raw(&mut prefix);
+
+        let mut inputs = Vec::new();
+        let mut offsets = Vec::with_capacity(transaction.inputs.len());
+        for input in &transaction.inputs {
+            offsets.push(inputs.len());
+            Input {
+                outpoint: input.outpoint.clone(),
+                script: Script::default(),
+                sequence: input.sequence,
+            }
+            .encode_raw(&mut inputs);
+        }
+
+        let mut suffix = Vec::new();
+        transaction.output_count_varint().encode_raw(&mut suffix);
+        for output in &transaction.outputs {
+            output.encode_raw(&mut suffix);
+        }
+        suffix.extend_from_slice(&transaction.lock_time.to_le_bytes());
+
+        Self {
+            transaction,
+            params: SighashParams::LEGACY,
+            values: Vec::new(),
+            fork_id_digests: None,
+            prefix,
+            inputs,
+            offsets,
+            suffix,
+        }
+    }
+
+    /// Prepare the transaction for signing with the chain parameters, given the values, in
+    /// satoshis, of the outputs spent by each input.
+    ///
+    /// The values are committed to by [`SighashAlgorithm::ForkId`] signature hashes, and ignored
+    /// otherwise.
+    pub fn with_params(
+        transaction: &'a Transaction,
+        params: SighashParams,
+        values: Vec<u64>,
+    ) -> Self {
+        match params.algorithm {
+            SighashAlgorithm::Legacy => Self::new(transaction),
+            SighashAlgorithm::ForkId => Self {
+                transaction,
+                params,
+                values,
+                fork_id_digests: Some(ForkIdDigests::new(transaction)),
+                prefix: Vec::new(),
+                inputs: Vec::new(),
+                offsets: Vec::new(),
+                suffix: Vec::new(),
+            },
+        }
+    }
+
+    /// The chain parameters of the signature hashes.
+    pub fn params(&self) -> SighashParams {
+        self.params
+    }
+
+    /// Calculate the signature hash of a specific input, by the algorithm of the chain
+    /// parameters.
+    ///
+    /// Returns `None` if the input, or the value it spends, does not exist.
+    pub fn signature_hash(
+        &self,
+        input_index: usize,
+        script_pubkey: &Script,
+        sig_hash_type: SignatureHashType,
+    ) -> Option<[u8; 32]> {
+        if let Some(fork_id_digests) = &self.fork_id_digests {
+            return self.fork_id_signature_hash(
+                fork_id_digests,
+                input_index,
+                script_pubkey,
+                sig_hash_type,
+            );
+        }
+        if sig_hash_type != SignatureHashType::All {
+            return self.transaction.signature_hash(
+                input_index,
+                script_pubkey.clone(),
+                sig_hash_type,
+            );
+        }
+        let start = *self.offsets.get(input_index)?;
+
+        // Splice the script into the input, replacing its empty script length
+        let mut script = Vec::with_capacity(9 + script_pubkey.encoded_len());
+        script_pubkey.len_varint().encode_raw(&mut script);
+        script_pubkey.encode_raw(&mut script);
+
+        let mut context = Context::new(&SHA256);
+        context.update(&self.prefix);
+        context.update(&self.inputs[..start + OUTPOINT_LEN]);
+        context.update(&script);
+        context.update(&self.inputs[start + OUTPOINT_LEN + 1..]);
+        context.update(&self.suffix);
+        context.update(&(sig_hash_type as u32).to_le_bytes());
+
+        // This is safe as SHA256 digests are 32 bytes
+        Some(
+            digest(&SHA256, context.finish().as_ref())
+                .as_ref()
+                .try_into()
+                .unwrap(),
+        )
+    }
+}
+
+impl SighashCache<'_> {
+    /// Calculate the [`SighashAlgorithm::ForkId`] signature hash of a specific input.
+    fn fork_id_signature_hash(
+        &self,
+        digests: &ForkIdDigests,
+        input_index: usize,
+        script_code: &Script,
+        sig_hash_type: SignatureHashType,
+    ) -> Option<[u8; 32]> {
+        let hash_type = self.params.hash_type(sig_hash_type.clone());
+        self.bip143_signature_hash(digests, input_index, script_code, sig_hash_type, hash_type)
+    }
+
+    /// Calculate the [`BIP143`] signature hash of a specific input, committing to the hash type.
+    ///
+    /// [`BIP143`]: https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    fn bip143_signature_hash(
+        &self,
+        digests: &ForkIdDigests,
+        input_index: usize,
+        script_code: &Script,
+        sig_hash_type: SignatureHashType,
+        hash_type: u32,
+    ) -> Option<[u8; 32]> {
+        let input = self.transaction.inputs.get(input_index)?;
+        let value = *self.values.get(input_index)?;
+        const ZERO_HASH: [u8; 32] = [0; 32];
+
+        let anyone_can_pay = sig_hash_type.is_anyone_can_pay();
+        let prevouts = if anyone_can_pay {
+            &ZERO_HASH
+        } else {
+            &digests.prevouts
+        };
+        let sequences = match sig_hash_type {
+            SignatureHashType::All => &digests.sequences,
+            _ => &ZERO_HASH,
+        };
+        let single_output;
+        let outputs = match sig_hash_type {
+            SignatureHashType::All | SignatureHashType::AnyoneCanPayAll => &digests.outputs,
+            SignatureHashType::Single | SignatureHashType::AnyoneCanPaySingle => {
+                match self.transaction.outputs.get(input_index) {
+                    Some(output) => {
+                        let mut raw_output = Vec::with_capacity(output.encoded_len());
+                        output.encode_raw(&mut raw_output);
+                        single_output = sha256d(&raw_output);
+                        &single_output
+                    }
+                    None => &ZERO_HASH,
+                }
+            }
+            SignatureHashType::None | SignatureHashType::AnyoneCanPayNone => &ZERO_HASH,
+        };
+
+        let mut preimage = Vec::with_capacity(156 + script_code.encoded_len());
+        preimage.extend_from_slice(&self.transaction.version.to_le_bytes());
+        preimage.extend_from_slice(prevouts);
+        preimage.extend_from_slice(sequences);
+        input.outpoint.encode_raw(&mut preimage);
+        script_code.len_varint().encode_raw(&mut preimage);
+        script_code.encode_raw(&mut preimage);
+        preimage.extend_from_slice(&value.to_le_bytes());
+        preimage.extend_from_slice(&input.sequence.to_le_bytes());
+        preimage.extend_from_slice(outputs);
+        preimage.extend_from_slice(&self.transaction.lock_time.to_le_bytes());
+        preimage.extend_from_slice(&hash_type.to_le_bytes());
+        Some(sha256d(&preimage))
+    }
+
+    /// Sign a specific input, returning the DER signature followed by the signature hash type, as
+    /// pushed by a `scriptSig`.
+    ///
+    /// Returns `None` if the input does not exist.
+    pub fn sign<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        input_index: usize,
+        script_code: &Script,
+        sig_hash_type: SignatureHashType,
+        secret_key: &SecretKey,
+    ) -> Option<Vec<u8>> {
+        let sig_hash = self.signature_hash(input_index, script_code, sig_hash_type.clone())?;
+        // This is safe as the hash is 32 bytes
+        let message = Message::from_slice(&sig_hash).unwrap();
+        let mut signature = secp.sign(&message, secret_key).serialize_der().to_vec();
+        signature.push(self.params.hash_type(sig_hash_type) as u8);
+        Some(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{outpoint::Outpoint, output::Output};
+
+    #[test]
+    fn matches_signature_hash() {
+        let transaction = Transaction {
+            version: 2,
+            inputs: (0..3)
+                .map(|vout| Input {
+                    outpoint: Outpoint {
+                        tx_id: [vout as u8; 32],
+                        vout,
+                    },
+                    script: Script::from(vec![0xff; 4]),
+                    sequence: u32::MAX - vout,
+                })
+                .collect(),
+            outputs: vec![Output {
+                value: 1_000,
+                script: Script::from(vec![0x6a]),
+            }],
+            lock_time: 7,
+        };
+        let script_pubkey = Script::from(vec![0x76, 0xa9, 0x14]);
+        let cache = SighashCache::new(&transaction);
+        for index in 0..3 {
+            assert_eq!(
+                cache.signature_hash(index, &script_pubkey, SignatureHashType::All),
+                transaction.signature_hash(index, script_pubkey.clone(), SignatureHashType::All)
+            );
+        }
+        assert_eq!(
+            cache.signature_hash(3, &script_pubkey, SignatureHashType::All),
+            None
+        );
+
+        // Anyone-can-pay commits to the signed input alone
+        let single_input = Transaction {
+            inputs: vec![transaction.inputs[1].clone()],
+            ..transaction.clone()
+        };
+        assert_eq!(
+            cache.signature_hash(1, &script_pubkey, SignatureHashType::AnyoneCanPayAll),
+            SighashCache::new(&single_input).signature_hash(
+                0,
+                &script_pubkey,
+                SignatureHashType::AnyoneCanPayAll
+            )
+        );
+    }
+
+    #[test]
+    fn fork_id() {
+        let transaction = Transaction {
+            version: 2,
+            inputs: (0..2)
+                .map(|vout| Input {
+                    outpoint: Outpoint {
+                        tx_id: [vout as u8; 32],
+                        vout,
+                    },
+                    script: Script::default(),
+                    sequence: u32::MAX,
+                })
+                .collect(),
+            outputs: vec![Output {
+                value: 1_000,
+                script: Script::from(vec![0x6a]),
+            }],
+            lock_time: 0,
+        };
+        let script_code = Script::from(vec![0x76, 0xa9, 0x14]);
+        let sig_hash = |transaction: &Transaction, values: Vec<u64>, sig_hash_type| {
+            SighashCache::with_params(transaction, SighashParams::LOTUS, values).signature_hash(
+                0,
+                &script_code,
+                sig_hash_type,
+            )
+        };
+
+        // The value spent is committed to
+        let all = sig_hash(&transaction, vec![2_000, 3_000], SignatureHashType::All).unwrap();
+        assert_ne!(
+            sig_hash(&transaction, vec![2_001, 3_000], SignatureHashType::All).unwrap(),
+            all
+        );
+        assert_ne!(
+            SighashCache::new(&transaction).signature_hash(0, &script_code, SignatureHashType::All),
+            Some(all)
+        );
+        assert_eq!(sig_hash(&transaction, vec![], SignatureHashType::All), None);
+
+        // Anyone-can-pay commits to the signed input alone
+        let mut other_inputs = transaction.clone();
+        other_inputs.inputs[1].outpoint.vout = 7;
+        assert_eq!(
+            sig_hash(
+                &transaction,
+                vec![2_000, 3_000],
+                SignatureHashType::AnyoneCanPayAll
+            ),
+            sig_hash(
+                &other_inputs,
+                vec![2_000, 0],
+                SignatureHashType::AnyoneCanPayAll
+            )
+        );
+        assert_ne!(
+            sig_hash(&transaction, vec![2_000, 3_000], SignatureHashType::All),
+            sig_hash(&other_inputs, vec![2_000, 3_000], SignatureHashType::All)
+        );
+
+        // The signature hash type carries the fork ID flag
+        assert_eq!(SighashParams::LOTUS.hash_type(SignatureHashType::All), 0x41);
+        let signature = SighashCache::with_params(&transaction, SighashParams::BCH, vec![2_000])
+            .sign(
+                &Secp256k1::signing_only(),
+                0,
+                &script_code,
+                SignatureHashType::All,
+                &SecretKey::from_slice(&[1; 32]).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(signature.last(), Some(&0x41));
+    }
+
+    #[test]
+    fn bip143_vectors() {
+        // The native P2WPKH and P2SH-P2WPKH examples of BIP143, whose digest is that of the
+        // ForkId algorithm without the fork ID flag
+        let vectors = [
+            (
+                "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+                1,
+                "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac",
+                600_000_000,
+                "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+            ),
+            (
+                "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000",
+                0,
+                "76a91479091972186c449eb1ded22b78e40d009bdf008988ac",
+                1_000_000_000,
+                "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6",
+            ),
+        ];
+        for (raw_transaction, input_index, script_code, value, expected) in &vectors {
+            let transaction = Transaction::from_hex(raw_transaction).unwrap();
+            let script_code = Script::from(hex::decode(script_code).unwrap());
+            let mut values = vec![0; transaction.inputs.len()];
+            values[*input_index] = *value;
+            let cache = SighashCache::with_params(&transaction, SighashParams::BCH, values);
+            let digests = cache.fork_id_digests.as_ref().unwrap();
+            let sig_hash = cache
+                .bip143_signature_hash(
+                    digests,
+                    *input_index,
+                    &script_code,
+                    SignatureHashType::All,
+                    SignatureHashType::All as u32,
+                )
+                .unwrap();
+            assert_eq!(hex::encode(sig_hash), *expected);
+
+            // The ForkId algorithm differs only in the hash type committed to
+            assert_eq!(
+                cache.signature_hash(*input_index, &script_code, SignatureHashType::All),
+                cache.bip143_signature_hash(
+                    digests,
+                    *input_index,
+                    &script_code,
+                    SignatureHashType::All,
+                    0x41,
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! This module contains the [`TxId`] struct which represents a transaction ID, and strict hex
+//! decoding of transactions and their IDs.
+//!
+//! Hashes are serialized in one byte order and displayed, by the RPC and block explorers, in the
+//! reverse. The order of a hex encoding is made explicit by the [`ByteOrder`] and
+//! [`DisplayOrder`] markers, for example `TxId::from_hex::<DisplayOrder>(tx_id_hex)`.
+
+use std::{convert::TryFrom, fmt};
+
+use hex::FromHexError;
+use thiserror::Error;
+
+use crate::transaction::DecodeError;
+
+/// Error associated with decoding hex.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum HexError {
+    /// A character was not a hex digit.
+    #[error("invalid character {character:?} at position {index}")]
+    InvalidCharacter {
+        /// The character.
+        character: char,
+        /// Position of the character.
+        index: usize,
+    },
+    /// The number of hex digits was odd.
+    #[error("odd number of hex digits")]
+    OddLength,
+    /// The number of bytes encoded was unexpected.
+    #[error("expected {expected} bytes, found {found}")]
+    InvalidLength {
+        /// The number of bytes expected.
+        expected: usize,
+        /// The number of bytes encoded.
+        found: usize,
+    },
+    /// Failed to decode the transaction.
+    #[error("transaction: {0}")]
+    Transaction(DecodeError),
+    /// Bytes followed the encoded transaction.
+    #[error("{0} trailing bytes")]
+    TrailingBytes(usize),
+}
+
+impl From<FromHexError> for HexError {
+    fn from(err: FromHexError) -> Self {
+        match err {
+            FromHexError::InvalidHexCharacter { c, index } => Self::InvalidCharacter {
+                character: c,
+                index,
+            },
+            // Lengths are checked before decoding into slices
+            FromHexError::OddLength | FromHexError::InvalidStringLength => Self::OddLength,
+        }
+    }
+}
+
+/// Decode hex, rejecting any character other than a hex digit.
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>, HexError> {
+    hex::decode(hex).map_err(Into::into)
+}
+
+/// Decode hex of exactly 32 bytes.
+fn decode_hash(hex: &str) -> Result<[u8; 32], HexError> {
+    if hex.len() != 64 {
+        // Report odd lengths ahead of the length mismatch
+        if hex.len() % 2 == 1 {
+            return Err(HexError::OddLength);
+        }
+        return Err(HexError::InvalidLength {
+            expected: 32,
+            found: hex.len() / 2,
+        });
+    }
+    let mut hash = [0; 32];
+    hex::decode_to_slice(hex, &mut hash)?;
+    Ok(hash)
+}
+
+/// The byte order of the hex encoding of a hash.
+pub trait HexOrder {
+    /// Convert the hash between serialization and the order of the encoding, in place.
+    fn reorder(hash: &mut [u8; 32]);
+}
+
+/// The order in which a hash is serialized, such as within an [`Outpoint`].
+///
+/// [`Outpoint`]: crate::transaction::outpoint::Outpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {}
+
+impl HexOrder for ByteOrder {
+    fn reorder(_hash: &mut [u8; 32]) {}
+}
+
+/// The reverse of the [`ByteOrder`], in which hashes are displayed by the RPC and block explorers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayOrder {}
+
+impl HexOrder for DisplayOrder {
+    fn reorder(hash: &mut [u8; 32]) {
+        hash.reverse();
+    }
+}
+
+/// A transaction ID, held in the [`ByteOrder`].
+///
+/// The ID is displayed, by [`fmt::Display`], in the [`DisplayOrder`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TxId([u8; 32]);
+
+impl TxId {
+    /// Decode a transaction ID from 64 hex digits in the given order.
+    pub fn from_hex<O: HexOrder>(hex: &str) -> Result<Self, HexError> {
+        let mut tx_id = decode_hash(hex)?;
+        O::reorder(&mut tx_id);
+        Ok(Self(tx_id))
+    }
+
+    /// Encode the transaction ID as hex in the given order.
+    pub fn to_hex<O: HexOrder>(&self) -> String {
+        let mut tx_id = self.0;
+        O::reorder(&mut tx_id);
+        hex::encode(tx_id)
+    }
+
+    /// The transaction ID in the [`ByteOrder`].
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Converts the transaction ID into its bytes, in the [`ByteOrder`].
+    #[inline]
+    pub fn into_bytes(self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl From<[u8; 32]> for TxId {
+    /// Wrap a transaction ID in the [`ByteOrder`].
+    fn from(tx_id: [u8; 32]) -> Self {
+        Self(tx_id)
+    }
+}
+
+impl From<TxId> for [u8; 32] {
+    fn from(tx_id: TxId) -> Self {
+        tx_id.0
+    }
+}
+
+impl TryFrom<&[u8]> for TxId {
+    type Error = HexError;
+
+    /// Wrap a transaction ID in the [`ByteOrder`].
+    fn try_from(tx_id: &[u8]) -> Result<Self, Self::Error> {
+        <[u8; 32]>::try_from(tx_id)
+            .map(Self)
+            .map_err(|_| HexError::InvalidLength {
+                expected: 32,
+                found: tx_id.len(),
+            })
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex::<DisplayOrder>())
+    }
+}
+
+impl fmt::Debug for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TxId").field(&self.to_string()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders() {
+        let display_hex = "92c8a467696f41fd9c171f4d53e1aff2932bb0a32b7ca81108fe0a9dc01d7aaf";
+        let tx_id = TxId::from_hex::<DisplayOrder>(display_hex).unwrap();
+        assert_eq!(tx_id.as_bytes()[0], 0xaf);
+        assert_eq!(tx_id.to_string(), display_hex);
+        let byte_hex = tx_id.to_hex::<ByteOrder>();
+        assert!(byte_hex.starts_with("af7a"));
+        assert_eq!(TxId::from_hex::<ByteOrder>(&byte_hex).unwrap(), tx_id);
+    }
+
+    #[test]
+    fn strict() {
+        assert_eq!(
+            TxId::from_hex::<ByteOrder>("00"),
+            Err(HexError::InvalidLength {
+                expected: 32,
+                found: 1
+            })
+        );
+        assert_eq!(
+            TxId::from_hex::<ByteOrder>(&"0".repeat(63)),
+            Err(HexError::OddLength)
+        );
+        let mut invalid = "0".repeat(64);
+        invalid.replace_range(10..11, "g");
+        assert_eq!(
+            TxId::from_hex::<ByteOrder>(&invalid),
+            Err(HexError::InvalidCharacter {
+                character: 'g',
+                index: 10
+            })
+        );
+        assert!(TxId::from_hex::<ByteOrder>(&format!(" {}", "0".repeat(63))).is_err());
+    }
+}
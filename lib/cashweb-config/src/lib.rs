@@ -0,0 +1,479 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-config` is a library providing typed, layered configuration for cash:web server
+//! components, such as the keyserver and relay handlers and the transaction broadcaster.
+//!
+//! A [`Loader`] merges the following layers, each overriding the last:
+//!
+//! 1. defaults, set using [`Loader::with_default`],
+//! 2. TOML files, added using [`Loader::with_file`] and [`Loader::with_optional_file`],
+//! 3. environment variables named `<PREFIX>_<KEY>`, where nested keys are separated by `__`,
+//!    for example `KEYSERVER_BITCOIN_RPC__PASSWORD` sets `bitcoin_rpc.password`,
+//! 4. overrides, such as command line arguments, set using [`Loader::with_override`].
+//!
+//! Environment variables are interpreted according to the type they are deserialized into.
+//! Strings are taken verbatim, so that `KEYSERVER_HMAC_SECRET=123456` remains a string, while other
+//! values are parsed as TOML values, so that `KEYSERVER_LIMITS__PAYMENT_SIZE=3000` is an integer.
+//!
+//! The merged configuration is deserialized and checked using [`Validate`]. Credentials and keys
+//! should be wrapped in [`Secret`] so that they are redacted from `Debug` output.
+
+pub mod node;
+pub mod secret;
+
+#[doc(inline)]
+pub use node::NodeConfig;
+#[doc(inline)]
+pub use secret::Secret;
+
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::de::{
+    value::MapDeserializer, DeserializeOwned, Deserializer, IntoDeserializer, Visitor,
+};
+use thiserror::Error;
+use toml::{value::Table, Value};
+
+/// Separator between nested keys in environment variable names.
+pub const ENV_SEPARATOR: &str = "__";
+
+/// A configuration value failed validation.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("invalid {field}: {reason}")]
+pub struct ValidationError {
+    /// Dotted path of the offending field, for example `bitcoin_rpc.address`.
+    pub field: String,
+    /// Reason the value is invalid.
+    pub reason: String,
+}
+
+impl ValidationError {
+    /// Create a new validation error.
+    pub fn new<F: Into<String>, R: Into<String>>(field: F, reason: R) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Prefix the field with the name of the section containing it.
+    pub fn in_section(mut self, section: &str) -> Self {
+        self.field = format!("{}.{}", section, self.field);
+        self
+    }
+}
+
+/// Validates configuration after it is loaded.
+pub trait Validate {
+    /// Check the configuration, returning the first invalid field.
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+/// Error associated with loading configuration.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// Failed to read a configuration file.
+    #[error("failed to read {}: {error}", path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Underlying error.
+        error: io::Error,
+    },
+    /// Failed to parse a configuration file.
+    #[error("failed to parse {}: {error}", path.display())]
+    Parse {
+        /// Path of the file.
+        path: PathBuf,
+        /// Underlying error.
+        error: toml::de::Error,
+    },
+    /// The merged configuration did not match the expected structure.
+    #[error("failed to deserialize configuration: {0}")]
+    Deserialize(toml::de::Error),
+    /// The configuration failed validation.
+    #[error(transparent)]
+    Invalid(#[from] ValidationError),
+    /// An application specific error.
+    #[error("{0}")]
+    Message(String),
+}
+
+/// Loads configuration from defaults, TOML files, the environment and overrides.
+#[derive(Clone, Debug)]
+pub struct Loader {
+    prefix: String,
+    defaults: NodeTable,
+    files: Vec<(PathBuf, bool)>,
+    env: Option<Vec<(String, String)>>,
+    overrides: NodeTable,
+}
+
+impl Loader {
+    /// Create a loader reading environment variables starting with `<prefix>_`.
+    pub fn new<P: Into<String>>(prefix: P) -> Self {
+        Self {
+            prefix: prefix.into(),
+            defaults: NodeTable::new(),
+            files: Vec::new(),
+            env: None,
+            overrides: NodeTable::new(),
+        }
+    }
+
+    /// Set the default value of a dotted key, such as `bitcoin_rpc.address`.
+    pub fn with_default<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        set_key(&mut self.defaults, key, Node::from(value.into()));
+        self
+    }
+
+    /// Merge a TOML file, which must exist.
+    ///
+    /// If the path has no extension and does not exist, the path with a `.toml` extension is
+    /// tried.
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push((path.into(), true));
+        self
+    }
+
+    /// Merge a TOML file, if it exists.
+    pub fn with_optional_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files.push((path.into(), false));
+        self
+    }
+
+    /// Use the variables in place of the process environment.
+    pub fn with_env_vars<I: IntoIterator<Item = (String, String)>>(mut self, vars: I) -> Self {
+        self.env = Some(vars.into_iter().collect());
+        self
+    }
+
+    /// Set the value of a dotted key, overriding all other layers.
+    pub fn with_override<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        set_key(&mut self.overrides, key, Node::from(value.into()));
+        self
+    }
+
+    /// Merge the layers, deserialize and validate the configuration.
+    pub fn load<T: DeserializeOwned + Validate>(&self) -> Result<T, ConfigError> {
+        let mut table = self.defaults.clone();
+        for (path, required) in &self.files {
+            if let Some(file) = read_file(path, *required)? {
+                merge(&mut table, from_table(file));
+            }
+        }
+
+        let prefix = format!("{}_", self.prefix);
+        let vars = match &self.env {
+            Some(vars) => vars.clone(),
+            None => env::vars().collect(),
+        };
+        for (name, raw) in vars {
+            if let Some(name) = name.strip_prefix(&prefix) {
+                let key = name.to_lowercase().replace(ENV_SEPARATOR, ".");
+                set_key(&mut table, &key, Node::Env(raw));
+            }
+        }
+
+        merge(&mut table, self.overrides.clone());
+
+        let config = T::deserialize(Node::Table(table)).map_err(ConfigError::Deserialize)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Read a TOML file, returning `None` if an optional file does not exist.
+fn read_file(path: &Path, required: bool) -> Result<Option<Table>, ConfigError> {
+    let path = if !path.exists() && path.extension().is_none() {
+        path.with_extension("toml")
+    } else {
+        path.to_path_buf()
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(error) => return Err(ConfigError::Io { path, error }),
+    };
+    toml::from_str(&contents)
+        .map(Some)
+        .map_err(|error| ConfigError::Parse { path, error })
+}
+
+/// Parse an environment variable as a TOML value, falling back to a string.
+fn parse_env_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// A layer of configuration, in which environment variables are retained unparsed until the type
+/// they are deserialized into is known.
+#[derive(Clone, Debug)]
+enum Node {
+    Table(NodeTable),
+    Value(Value),
+    Env(String),
+}
+
+type NodeTable = BTreeMap<String, Node>;
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Table(table) => Node::Table(from_table(table)),
+            value => Node::Value(value),
+        }
+    }
+}
+
+impl Node {
+    fn into_value(self) -> Value {
+        match self {
+            Node::Table(table) => Value::Table(
+                table
+                    .into_iter()
+                    .map(|(key, node)| (key, node.into_value()))
+                    .collect(),
+            ),
+            Node::Value(value) => value,
+            Node::Env(raw) => parse_env_value(&raw),
+        }
+    }
+}
+
+fn from_table(table: Table) -> NodeTable {
+    table
+        .into_iter()
+        .map(|(key, value)| (key, Node::from(value)))
+        .collect()
+}
+
+impl<'de> Deserializer<'de> for Node {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Node::Table(table) => visitor.visit_map(MapDeserializer::new(table.into_iter())),
+            Node::Value(value) => value.deserialize_any(visitor),
+            Node::Env(raw) => parse_env_value(&raw).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            // Strings are taken verbatim, rather than parsed
+            Node::Env(raw) => visitor.visit_string(raw),
+            node => node.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.into_value().deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char bytes byte_buf unit unit_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, toml::de::Error> for Node {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Set the value of a dotted key, creating intermediate tables.
+fn set_key(table: &mut NodeTable, key: &str, value: Node) {
+    let mut parts = key.split('.').peekable();
+    let mut current = table;
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            current.insert(part.to_string(), value);
+            return;
+        }
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| Node::Table(NodeTable::new()));
+        if !matches!(entry, Node::Table(_)) {
+            *entry = Node::Table(NodeTable::new());
+        }
+        current = match entry {
+            Node::Table(table) => table,
+            // This is safe as the entry was made a table above
+            _ => unreachable!(),
+        };
+    }
+}
+
+/// Merge `other` into `table`, recursing into tables present in both.
+fn merge(table: &mut NodeTable, other: NodeTable) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(Node::Table(existing)), Node::Table(value)) => merge(existing, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        bind: String,
+        bitcoin_rpc: NodeConfig,
+        limits: Limits,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Limits {
+        payment_size: u64,
+    }
+
+    impl Validate for Settings {
+        fn validate(&self) -> Result<(), ValidationError> {
+            self.bitcoin_rpc
+                .validate()
+                .map_err(|err| err.in_section("bitcoin_rpc"))
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let path = env::temp_dir().join(format!("cashweb-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "bind = \"0.0.0.0:8080\"\n[bitcoin_rpc]\nusername = \"alice\"\n",
+        )
+        .unwrap();
+
+        let loader = Loader::new("TEST")
+            .with_default("bind", "127.0.0.1:8080")
+            .with_default("bitcoin_rpc.password", "password")
+            .with_default("limits.payment_size", 3_000)
+            .with_file(&path)
+            .with_optional_file("/nonexistent/config")
+            .with_env_vars(vec![
+                ("TEST_BITCOIN_RPC__PASSWORD".to_string(), "1234".to_string()),
+                (
+                    "TEST_BITCOIN_RPC__ADDRESS".to_string(),
+                    "http://node:8332".to_string(),
+                ),
+                ("OTHER_BIND".to_string(), "ignored".to_string()),
+            ])
+            .with_override("limits.payment_size", 5_000);
+        let settings: Settings = loader.load().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.bind, "0.0.0.0:8080");
+        assert_eq!(settings.bitcoin_rpc.username, "alice");
+        assert_eq!(settings.bitcoin_rpc.address, "http://node:8332");
+        assert_eq!(settings.limits.payment_size, 5_000);
+        assert_eq!(settings.bitcoin_rpc.password.expose(), "1234");
+        assert!(!format!("{:?}", settings).contains("1234"));
+    }
+
+    #[test]
+    fn env_types() {
+        #[derive(Debug, Deserialize)]
+        struct Env {
+            hmac_secret: Secret<String>,
+            name: Option<String>,
+            port: u16,
+            verbose: bool,
+            peers: Vec<String>,
+            limits: Option<Limits>,
+        }
+
+        impl Validate for Env {
+            fn validate(&self) -> Result<(), ValidationError> {
+                Ok(())
+            }
+        }
+
+        let vars = [
+            ("TEST_HMAC_SECRET", "123456"),
+            ("TEST_NAME", "true"),
+            ("TEST_PORT", "8080"),
+            ("TEST_VERBOSE", "true"),
+            ("TEST_PEERS", "[\"http://a\", \"http://b\"]"),
+            ("TEST_LIMITS__PAYMENT_SIZE", "3000"),
+        ];
+        let env: Env = Loader::new("TEST")
+            .with_env_vars(
+                vars.iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            )
+            .load()
+            .unwrap();
+
+        // Strings are taken verbatim, regardless of whether a lower layer sets them
+        assert_eq!(env.hmac_secret.expose(), "123456");
+        assert_eq!(env.name.as_deref(), Some("true"));
+        assert_eq!(env.port, 8080);
+        assert!(env.verbose);
+        assert_eq!(env.peers, vec!["http://a", "http://b"]);
+        assert_eq!(env.limits.unwrap().payment_size, 3_000);
+    }
+
+    #[test]
+    fn errors() {
+        let loader = Loader::new("TEST")
+            .with_default("bind", "127.0.0.1:8080")
+            .with_default("limits.payment_size", 3_000)
+            .with_env_vars(vec![(
+                "TEST_BITCOIN_RPC__ADDRESS".to_string(),
+                "node:8332".to_string(),
+            )]);
+        match loader.load::<Settings>() {
+            Err(ConfigError::Invalid(err)) => assert_eq!(err.field, "bitcoin_rpc.address"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let loader = Loader::new("TEST").with_file("/nonexistent/config.toml");
+        assert!(matches!(
+            loader.load::<Settings>(),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! This module contains [`NodeConfig`], the configuration section describing the bitcoin node
+//! used for broadcasting and chain queries.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{Secret, Validate, ValidationError};
+
+/// Default RPC address of the node.
+pub const DEFAULT_RPC_ADDRESS: &str = "http://127.0.0.1:18443";
+
+/// Default RPC connection timeout, in milliseconds.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 5_000;
+
+/// Default RPC request timeout, in milliseconds.
+pub const DEFAULT_REQUEST_TIMEOUT: u64 = 30_000;
+
+/// Connection details of a bitcoin node.
+///
+/// Missing fields take their default values. The password is redacted from `Debug` output.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// RPC address, for example `http://127.0.0.1:18443`.
+    pub address: String,
+    /// RPC username.
+    pub username: String,
+    /// RPC password.
+    pub password: Secret<String>,
+    /// RPC connection timeout, in milliseconds.
+    pub connect_timeout: u64,
+    /// RPC request timeout, in milliseconds.
+    pub request_timeout: u64,
+    /// ZMQ address publishing node notifications, for example `tcp://127.0.0.1:28332`.
+    pub zmq_address: Option<String>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_RPC_ADDRESS.to_string(),
+            username: "user".to_string(),
+            password: Secret::new("password".to_string()),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            zmq_address: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// The RPC connection timeout.
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout)
+    }
+
+    /// The RPC request timeout.
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout)
+    }
+}
+
+impl Validate for NodeConfig {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if !self.address.starts_with("http://") && !self.address.starts_with("https://") {
+            return Err(ValidationError::new("address", "expected an http(s) URL"));
+        }
+        if self.connect_timeout == 0 {
+            return Err(ValidationError::new("connect_timeout", "must be positive"));
+        }
+        if self.request_timeout == 0 {
+            return Err(ValidationError::new("request_timeout", "must be positive"));
+        }
+        if let Some(zmq_address) = &self.zmq_address {
+            if !zmq_address.contains("://") {
+                return Err(ValidationError::new(
+                    "zmq_address",
+                    "expected a ZMQ endpoint",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        assert!(NodeConfig::default().validate().is_ok());
+
+        let config = NodeConfig {
+            address: "127.0.0.1:18443".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.validate().unwrap_err().field, "address");
+
+        let config = NodeConfig {
+            request_timeout: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.validate().unwrap_err().field, "request_timeout");
+    }
+}
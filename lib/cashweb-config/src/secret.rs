@@ -0,0 +1,52 @@
+//! This module contains [`Secret`], which wraps credentials and keys so that they are redacted
+//! from `Debug` output.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// A configuration value which is redacted from `Debug` output.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Secret").field(&"<redacted>").finish()
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Secret<T> {
+    /// Wrap a secret value.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Expose the secret value.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Convert into the secret value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(format!("{:?}", secret), "Secret(\"<redacted>\")");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+}
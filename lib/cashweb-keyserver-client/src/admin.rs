@@ -0,0 +1,286 @@
+//! This module contains the [`AdminClient`], with which operators script maintenance of a
+//! keyserver through its admin API, such as banning peers, purging addresses, reading storage
+//! statistics and forcing replication.
+//!
+//! Requests are authorized by an expiring [`HmacScheme`] token covering the
+//! [`signing_data`] of the request, keyed by the admin secret of the keyserver. Alternatively,
+//! requests are signed with an admin key by a [`SigningService`].
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use cashweb_keyserver::{
+    admin::{signing_data, ADMIN_PATH, BAN_PEER_PATH, KEYS_PATH, REPLICATE_PATH, STATS_PATH},
+    Peer, PurgeSummary, ReplicationSummary, StorageStats,
+};
+use cashweb_token::{schemes::hmac_bearer::HmacScheme, signing::SigningService};
+use hyper::{
+    body::to_bytes,
+    client::HttpConnector,
+    http::{header::AUTHORIZATION, uri::InvalidUri, Method},
+    Body, Request, Response, StatusCode, Uri,
+};
+use hyper_tls::HttpsConnector;
+use prost::Message as _;
+use thiserror::Error;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+/// Default lifetime of an admin token.
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// Error associated with an admin request.
+#[derive(Debug, Error)]
+pub enum AdminError<E: fmt::Debug + fmt::Display> {
+    /// Invalid URI.
+    #[error(transparent)]
+    Uri(InvalidUri),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// Error while decoding the body.
+    #[error("body decoding failure: {0}")]
+    Decode(prost::DecodeError),
+    /// The admin API is disabled on the keyserver, or the resource was not found.
+    #[error("not found")]
+    NotFound,
+    /// The token was rejected.
+    #[error("unauthorized")]
+    Unauthorized,
+    /// Peering is disabled on the keyserver, so it can't replicate.
+    #[error("peering disabled")]
+    PeeringDisabled,
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+/// `AdminClient` sends authorized requests to the admin API of keyservers.
+#[derive(Clone, Debug)]
+pub struct AdminClient<S> {
+    inner_client: S,
+    scheme: Option<Arc<HmacScheme>>,
+    ttl: Duration,
+}
+
+impl<S> AdminClient<SigningService<S>> {
+    /// Create a new client from a [`SigningService`], authorizing by its signatures rather than
+    /// tokens.
+    pub fn from_signing_service(service: SigningService<S>) -> Self {
+        Self {
+            inner_client: service,
+            scheme: None,
+            ttl: DEFAULT_TOKEN_TTL,
+        }
+    }
+}
+
+impl<S> AdminClient<S> {
+    /// Create a new client from a [`Service`], authorizing with the scheme.
+    pub fn from_service(service: S, scheme: HmacScheme) -> Self {
+        Self {
+            inner_client: service,
+            scheme: Some(Arc::new(scheme)),
+            ttl: DEFAULT_TOKEN_TTL,
+        }
+    }
+
+    /// Set the lifetime of tokens.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The `Authorization` header value of a request issued at `now`, if authorizing by tokens.
+    pub fn authorization(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Option<String> {
+        let scheme = self.scheme.as_ref()?;
+        let data = signing_data(method.as_str(), path, body);
+        let token = scheme.construct_token_expiring_at(&data, now, self.ttl);
+        Some(format!("Bearer {}", token))
+    }
+}
+
+impl AdminClient<hyper::Client<HttpsConnector<HttpConnector>>> {
+    /// Create new HTTPS client, authorizing with the scheme.
+    pub fn new_tls(scheme: HmacScheme) -> Self {
+        let https = HttpsConnector::new();
+        Self::from_service(hyper::Client::builder().build(https), scheme)
+    }
+}
+
+impl<S> AdminClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Clone,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    /// Send an authorized request to the path, below [`ADMIN_PATH`], returning the response body.
+    async fn request(
+        &self,
+        keyserver_url: &str,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+    ) -> Result<Bytes, AdminError<S::Error>> {
+        let path = format!("/{}/{}", ADMIN_PATH, path);
+        let uri: Uri = format!("{}{}", keyserver_url.trim_end_matches('/'), path)
+            .parse()
+            .map_err(AdminError::Uri)?;
+        let mut builder = Request::builder().method(method.clone()).uri(uri);
+        if let Some(authorization) = self.authorization(&method, &path, &body, SystemTime::now()) {
+            builder = builder.header(AUTHORIZATION, authorization);
+        }
+        let request = builder.body(Body::from(body)).unwrap(); // This is safe
+
+        let response = self
+            .inner_client
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(AdminError::Service)?;
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Err(AdminError::NotFound),
+            StatusCode::UNAUTHORIZED => return Err(AdminError::Unauthorized),
+            StatusCode::NOT_IMPLEMENTED => return Err(AdminError::PeeringDisabled),
+            code => return Err(AdminError::UnexpectedStatusCode(code.as_u16())),
+        }
+        to_bytes(response.into_body())
+            .await
+            .map_err(AdminError::Body)
+    }
+
+    /// Ban a peer, removing it from the peer list of the keyserver.
+    pub async fn ban_peer(
+        &self,
+        keyserver_url: &str,
+        peer_url: &str,
+    ) -> Result<(), AdminError<S::Error>> {
+        let peer = Peer::new(peer_url.to_string());
+        let mut body = Vec::with_capacity(peer.encoded_len());
+        peer.encode(&mut body).unwrap(); // This is safe
+        self.request(keyserver_url, Method::POST, BAN_PEER_PATH, body)
+            .await?;
+        Ok(())
+    }
+
+    /// Purge the metadata of an address, including its namespaces, and its POP tokens.
+    pub async fn purge_address(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+    ) -> Result<PurgeSummary, AdminError<S::Error>> {
+        let path = format!("{}/{}", KEYS_PATH, address);
+        let body = self
+            .request(keyserver_url, Method::DELETE, &path, Vec::new())
+            .await?;
+        PurgeSummary::decode(body).map_err(AdminError::Decode)
+    }
+
+    /// Get the [`StorageStats`] of the keyserver.
+    pub async fn storage_stats(
+        &self,
+        keyserver_url: &str,
+    ) -> Result<StorageStats, AdminError<S::Error>> {
+        let body = self
+            .request(keyserver_url, Method::GET, STATS_PATH, Vec::new())
+            .await?;
+        StorageStats::decode(body).map_err(AdminError::Decode)
+    }
+
+    /// Force a replication pass over the peers of the keyserver.
+    pub async fn replicate(
+        &self,
+        keyserver_url: &str,
+    ) -> Result<ReplicationSummary, AdminError<S::Error>> {
+        let body = self
+            .request(keyserver_url, Method::POST, REPLICATE_PATH, Vec::new())
+            .await?;
+        ReplicationSummary::decode(body).map_err(AdminError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use super::*;
+
+    /// Answers with the stats if the token is valid.
+    #[derive(Clone)]
+    struct MockKeyserver {
+        scheme: Arc<HmacScheme>,
+    }
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let token = request
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .unwrap_or_default();
+            let data = signing_data(request.method().as_str(), request.uri().path(), b"");
+            let response = match self
+                .scheme
+                .validate_token_at(&data, token, SystemTime::now())
+            {
+                Ok(()) => {
+                    let stats = StorageStats {
+                        metadata: 3,
+                        ..Default::default()
+                    };
+                    let mut body = Vec::new();
+                    stats.encode(&mut body).unwrap();
+                    Response::new(Body::from(body))
+                }
+                Err(_) => Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::empty())
+                    .unwrap(),
+            };
+            ready(Ok(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn authorized() {
+        let keyserver = MockKeyserver {
+            scheme: Arc::new(HmacScheme::new(b"secret")),
+        };
+        let client = AdminClient::from_service(keyserver.clone(), HmacScheme::new(b"secret"));
+        let stats = client.storage_stats("http://keyserver/").await.unwrap();
+        assert_eq!(stats.metadata, 3);
+
+        let client = AdminClient::from_service(keyserver, HmacScheme::new(b"other"));
+        assert!(matches!(
+            client.storage_stats("http://keyserver").await,
+            Err(AdminError::Unauthorized)
+        ));
+    }
+}
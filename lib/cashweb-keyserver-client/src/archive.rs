@@ -0,0 +1,353 @@
+//! This module contains the [`Archiver`] which exports the metadata held in a [`MetadataStore`]
+//! to a signed, compressed archive, and imports such archives, so that keyservers can be backed up
+//! and migrated between storage backends.
+//!
+//! An archive is a [`MetadataArchive`], wrapped in an [`AuthWrapper`] signed by the exporting
+//! key, and compressed using zstd. On import, both the signature of the archive and that of each
+//! entry are verified, along with the POP token of each entry if a token scheme is given, and
+//! conflicts are resolved in favour of the higher timestamp, as during replication.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cashweb_auth_wrapper::{AuthWrapper, ParseError, VerifyError};
+use cashweb_keyserver::{
+    compression::{CompressionError, Encoding},
+    namespace::{metadata_key, split_metadata_key},
+    store::MetadataStore,
+    MetadataArchive, MetadataEntry,
+};
+use cashweb_token::schemes::{DynTokenScheme, ErrorKind};
+use prost::Message as _;
+use secp256k1::key::{PublicKey, SecretKey};
+use thiserror::Error;
+
+use crate::replication::{
+    verify_entry, verify_token, InvalidEntry, ReplicationReport, DEFAULT_PAGE_SIZE,
+};
+
+/// The version of the archive format written by [`Archiver::export`].
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Default maximum size of a decompressed archive, in bytes.
+pub const DEFAULT_MAX_ARCHIVE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Error associated with exporting or importing an archive.
+#[derive(Debug, Error)]
+pub enum ArchiveError<M: fmt::Debug + fmt::Display> {
+    /// Failed to read from, or write to, the store.
+    #[error("store failure: {0}")]
+    Store(M),
+    /// Error while decompressing the archive.
+    #[error("decompression failure: {0}")]
+    Decompress(CompressionError),
+    /// Error while decoding the archive.
+    #[error("archive decoding failure: {0}")]
+    Decode(prost::DecodeError),
+    /// Error while parsing the [`AuthWrapper`] of the archive.
+    #[error("authwrapper parsing failure: {0}")]
+    Parse(ParseError),
+    /// Error while verifying the [`AuthWrapper`] of the archive.
+    #[error("authwrapper verification failure: {0}")]
+    Verify(VerifyError),
+    /// The archive was signed by a key other than the one expected.
+    #[error("archive signed by unexpected key")]
+    UnexpectedSigner,
+    /// The archive format version is unsupported.
+    #[error("unsupported archive version {0}")]
+    Version(u32),
+    /// The token scheme was unable to validate a token, for example as bitcoind was unavailable.
+    #[error("token validation unavailable")]
+    TokenUnavailable,
+}
+
+/// Exports metadata from, and imports metadata into, a [`MetadataStore`].
+pub struct Archiver<M> {
+    store: M,
+    page_size: usize,
+    max_size: usize,
+    token_scheme: Option<Arc<DynTokenScheme>>,
+}
+
+impl<M: fmt::Debug> fmt::Debug for Archiver<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archiver")
+            .field("store", &self.store)
+            .field("page_size", &self.page_size)
+            .field("max_size", &self.max_size)
+            .finish()
+    }
+}
+
+impl<M> Archiver<M> {
+    /// Create a new archiver over the store.
+    pub fn new(store: M) -> Self {
+        Self {
+            store,
+            page_size: DEFAULT_PAGE_SIZE,
+            max_size: DEFAULT_MAX_ARCHIVE_SIZE,
+            token_scheme: None,
+        }
+    }
+
+    /// Set the number of entries read from the store at a time during export.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Set the maximum size of a decompressed archive accepted by import, in bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Validate the POP token of each imported entry using the scheme, skipping those which fail.
+    pub fn with_token_scheme(mut self, token_scheme: Arc<DynTokenScheme>) -> Self {
+        self.token_scheme = Some(token_scheme);
+        self
+    }
+
+    /// Converts the archiver into the underlying store.
+    pub fn into_inner(self) -> M {
+        self.store
+    }
+}
+
+impl<M> Archiver<M>
+where
+    M: MetadataStore,
+{
+    /// Export all metadata held in the store to an archive signed by the secret key.
+    pub async fn export(&self, secret_key: &SecretKey) -> Result<Vec<u8>, ArchiveError<M::Error>> {
+        // Keys are indexed by position, as metadata updated during export appears again later
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut entries: Vec<MetadataEntry> = Vec::new();
+        let mut cursor = i64::MIN;
+        let mut page_size = self.page_size;
+        loop {
+            let page = self
+                .store
+                .since(cursor, page_size)
+                .await
+                .map_err(ArchiveError::Store)?;
+            let full = page.len() >= page_size;
+            let next_cursor = page
+                .last()
+                .map(|(_, metadata)| metadata.timestamp)
+                .unwrap_or(cursor);
+            for (key, metadata) in page {
+                let entry = MetadataEntry {
+                    address: split_metadata_key(&key, &metadata.namespace).to_vec(),
+                    raw_auth_wrapper: metadata.raw_auth_wrapper,
+                    token: metadata.token,
+                    timestamp: metadata.timestamp,
+                    namespace: metadata.namespace,
+                };
+                match positions.get(&key) {
+                    Some(&position) => entries[position] = entry,
+                    None => {
+                        positions.insert(key, entries.len());
+                        entries.push(entry);
+                    }
+                }
+            }
+            if !full {
+                break;
+            }
+
+            // The start bound is inclusive, so widen a page filled by a single timestamp rather
+            // than skip past it
+            if next_cursor == cursor {
+                page_size = page_size.saturating_mul(2);
+            }
+            cursor = next_cursor;
+        }
+        entries.sort_by_key(|entry| entry.timestamp);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or_default();
+        let archive = MetadataArchive {
+            version: ARCHIVE_VERSION,
+            timestamp,
+            entries,
+        };
+        let mut payload = Vec::with_capacity(archive.encoded_len());
+        archive.encode(&mut payload).unwrap(); // This is safe
+
+        let auth_wrapper = AuthWrapper::sign_ecdsa(secret_key, payload);
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap(); // This is safe
+        Ok(Encoding::Zstd.compress(&raw_auth_wrapper))
+    }
+
+    /// Import the metadata of an archive into the store, returning a [`ReplicationReport`] of the
+    /// entries accepted, stale and invalid.
+    ///
+    /// The archive must be signed by `signer`, if given. Entries failing verification are
+    /// skipped, and metadata is only replaced by that with a higher timestamp.
+    pub async fn import(
+        &self,
+        archive: &[u8],
+        signer: Option<&PublicKey>,
+    ) -> Result<ReplicationReport, ArchiveError<M::Error>> {
+        let raw_auth_wrapper = Encoding::Zstd
+            .decompress(archive, self.max_size)
+            .map_err(ArchiveError::Decompress)?;
+        let parsed_auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.as_slice())
+            .map_err(ArchiveError::Decode)?
+            .parse()
+            .map_err(ArchiveError::Parse)?;
+        parsed_auth_wrapper.verify().map_err(ArchiveError::Verify)?;
+        if matches!(signer, Some(signer) if *signer != parsed_auth_wrapper.public_key) {
+            return Err(ArchiveError::UnexpectedSigner);
+        }
+        let archive = MetadataArchive::decode(parsed_auth_wrapper.payload.as_slice())
+            .map_err(ArchiveError::Decode)?;
+        if archive.version != ARCHIVE_VERSION {
+            return Err(ArchiveError::Version(archive.version));
+        }
+
+        let mut report = ReplicationReport::default();
+        for entry in archive.entries {
+            let metadata = match verify_entry(&entry) {
+                Ok(ok) => ok,
+                Err(_) => {
+                    report.invalid += 1;
+                    continue;
+                }
+            };
+
+            // Prefer the higher timestamp
+            let key = metadata_key(&entry.address, &entry.namespace);
+            let existing = self.store.get(&key).await.map_err(ArchiveError::Store)?;
+            if matches!(existing, Some(existing) if existing.timestamp >= metadata.timestamp) {
+                report.stale += 1;
+                continue;
+            }
+            if let Some(token_scheme) = &self.token_scheme {
+                match verify_token(&entry, token_scheme.as_ref()).await {
+                    Ok(()) => (),
+                    Err(InvalidEntry::Token(ErrorKind::Internal)) => {
+                        return Err(ArchiveError::TokenUnavailable)
+                    }
+                    Err(_) => {
+                        report.invalid += 1;
+                        continue;
+                    }
+                }
+            }
+            self.store
+                .put(&key, metadata)
+                .await
+                .map_err(ArchiveError::Store)?;
+            report.accepted += 1;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_keyserver::{
+        store::{MemoryMetadataStore, StoredMetadata},
+        AddressMetadata,
+    };
+    use ring::digest::{digest, SHA256};
+    use ripemd160::{Digest, Ripemd160};
+    use secp256k1::Secp256k1;
+
+    use super::*;
+
+    /// The address payload and key of the user with the name.
+    fn user(name: &[u8]) -> (Vec<u8>, SecretKey) {
+        let secret_key = SecretKey::from_slice(digest(&SHA256, name).as_ref()).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        let address = Ripemd160::digest(digest(&SHA256, &public_key.serialize()).as_ref());
+        (address.to_vec(), secret_key)
+    }
+
+    fn metadata(secret_key: &SecretKey, timestamp: i64) -> StoredMetadata {
+        let metadata = AddressMetadata {
+            timestamp,
+            ..Default::default()
+        };
+        let mut payload = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut payload).unwrap();
+        let auth_wrapper = AuthWrapper::sign_ecdsa(secret_key, payload);
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        StoredMetadata {
+            raw_auth_wrapper,
+            token: b"token".to_vec(),
+            timestamp,
+            namespace: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let archive_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let archive_public_key = PublicKey::from_secret_key(&Secp256k1::new(), &archive_key);
+
+        // Pages of one entry are filled by a shared timestamp
+        let source = MemoryMetadataStore::new();
+        for (name, timestamp) in &[("alice", 100), ("bob", 100), ("carol", 200)] {
+            let (address, user_key) = user(name.as_bytes());
+            let metadata = metadata(&user_key, *timestamp);
+            source.put(&address, metadata).await.unwrap();
+        }
+        let archive = Archiver::new(source)
+            .with_page_size(1)
+            .export(&archive_key)
+            .await
+            .unwrap();
+
+        let destination = MemoryMetadataStore::new();
+        let (carol, carol_key) = user(b"carol");
+        destination
+            .put(&carol, metadata(&carol_key, 300))
+            .await
+            .unwrap();
+        let archiver = Archiver::new(destination);
+        let report = archiver
+            .import(&archive, Some(&archive_public_key))
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ReplicationReport {
+                accepted: 2,
+                stale: 1,
+                invalid: 0,
+            }
+        );
+        let destination = archiver.into_inner();
+        let (bob, bob_key) = user(b"bob");
+        assert_eq!(
+            destination.get(&bob).await.unwrap(),
+            Some(metadata(&bob_key, 100))
+        );
+
+        // The signer is checked, as is the signature
+        let other_key = PublicKey::from_secret_key(&Secp256k1::new(), &bob_key);
+        let archiver = Archiver::new(destination);
+        assert!(matches!(
+            archiver.import(&archive, Some(&other_key)).await,
+            Err(ArchiveError::UnexpectedSigner)
+        ));
+        let mut raw_auth_wrapper = Encoding::Zstd
+            .decompress(&archive, DEFAULT_MAX_ARCHIVE_SIZE)
+            .unwrap();
+        let last = raw_auth_wrapper.len() - 1;
+        raw_auth_wrapper[last] ^= 1;
+        let tampered = Encoding::Zstd.compress(&raw_auth_wrapper);
+        assert!(archiver.import(&tampered, None).await.is_err());
+    }
+}
@@ -2,19 +2,79 @@
 
 pub mod services;
 
-use std::{error, fmt};
+use std::{error, fmt, future::Future};
 
 use bytes::Bytes;
 use cashweb_auth_wrapper::AuthWrapper;
-use cashweb_keyserver::{AddressMetadata, Peers};
-use hyper::{client::HttpConnector, http::uri::InvalidUri, Uri};
+use cashweb_keyserver::{
+    compression::Encoding,
+    delegation::{self, DelegationError, ParsedDelegatedKey},
+    namespace::Namespace,
+    unknown::Preserved,
+    AddressMetadata, MetadataPage, MetadataPatch, Peers, SearchPage,
+};
+use cashweb_metrics::{Counter, Histogram};
+#[cfg(feature = "native")]
+use hyper::client::HttpConnector;
+use hyper::{http::uri::InvalidUri, Uri};
+#[cfg(feature = "native")]
 use hyper_tls::HttpsConnector;
+use prost::Message as _;
+use ring::digest::{digest, SHA256};
 use secp256k1::key::PublicKey;
 use thiserror::Error;
 use tower_service::Service;
 use tower_util::ServiceExt;
 
-use crate::client::services::{GetMetadata, GetPeers, PutMetadata, PutRawAuthWrapper};
+use crate::{
+    client::services::{
+        GetMetadata, GetMetadataSince, GetPeers, PatchMetadata, PeerStream, PutMetadata,
+        PutRawAuthWrapper, Search, StreamPeers,
+    },
+    time::Instant,
+    CachePolicy,
+};
+
+/// Requests sent to keyservers, by client method and outcome.
+pub const REQUESTS: Counter = Counter::new(
+    "cashweb_keyserver_client_requests_total",
+    "Requests sent to keyservers, by client method and outcome.",
+    &["method", "outcome"],
+);
+
+/// Duration of requests sent to keyservers, by client method.
+pub const REQUEST_DURATION: Histogram = Histogram::new(
+    "cashweb_keyserver_client_request_duration_seconds",
+    "Duration of requests sent to keyservers, by client method.",
+    &["method"],
+);
+
+/// Record the outcome and duration of a request.
+async fn instrument<T, E, F>(method: &'static str, request: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = request.await;
+    REQUEST_DURATION.observe(&[method], start.elapsed().as_secs_f64());
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    REQUESTS.increment(&[method, outcome]);
+    result
+}
+
+/// Percent-encode a query parameter, leaving only unreserved characters intact.
+fn encode_query_param(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
 
 /// Error associated with sending a request to a keyserver.
 #[derive(Debug, Error)]
@@ -42,6 +102,35 @@ pub struct MetadataPackage {
     pub metadata: AddressMetadata,
     /// The raw [`AuthWrapper`]
     pub raw_auth_wrapper: Bytes,
+    /// Caching directives given by the keyserver, see [`MetadataCache`].
+    ///
+    /// [`MetadataCache`]: crate::MetadataCache
+    pub cache_policy: CachePolicy,
+}
+
+impl MetadataPackage {
+    /// Decode the signed payload of the [`AuthWrapper`], preserving fields unknown to this
+    /// version of [`AddressMetadata`] so that the metadata can be mirrored without breaking the
+    /// signature.
+    pub fn preserved_metadata(&self) -> Result<Preserved<AddressMetadata>, prost::DecodeError> {
+        let auth_wrapper = AuthWrapper::decode(self.raw_auth_wrapper.clone())?;
+        Preserved::decode(&auth_wrapper.payload)
+    }
+
+    /// Find a valid delegation of the scope from the public key of the metadata to `child`, at
+    /// `now`, given in milliseconds.
+    ///
+    /// For example, the sender of a relay message signed by a hot key is checked by fetching the
+    /// metadata of the sender's address and finding a delegation to the message's source public
+    /// key within [`delegation::RELAY_SCOPE`].
+    pub fn find_delegation(
+        &self,
+        child: &PublicKey,
+        scope: &str,
+        now: i64,
+    ) -> Result<ParsedDelegatedKey, DelegationError> {
+        delegation::find_delegation(&self.public_key, &self.metadata, child, scope, now)
+    }
 }
 
 /// The raw [`AuthWrapper`] paired with a [`POP token`].
@@ -55,10 +144,19 @@ pub struct RawAuthWrapperPackage {
     pub raw_auth_wrapper: Bytes,
 }
 
+/// Default maximum size of a decompressed metadata body, in bytes.
+pub const DEFAULT_MAX_METADATA_SIZE: usize = 2 * 1024 * 1024;
+
 /// `KeyserverClient` allows queries to specific keyservers.
+///
+/// Metadata is requested with every supported [`Encoding`] accepted, and decompressed
+/// transparently. Metadata bodies are put uncompressed unless an encoding is set using
+/// [`KeyserverClient::with_encoding`].
 #[derive(Clone, Debug)]
 pub struct KeyserverClient<S> {
     inner_client: S,
+    encoding: Encoding,
+    max_metadata_size: usize,
 }
 
 impl<S> KeyserverClient<S> {
@@ -68,18 +166,32 @@ impl<S> KeyserverClient<S> {
     pub fn from_service(service: S) -> Self {
         Self {
             inner_client: service,
+            encoding: Encoding::Identity,
+            max_metadata_size: DEFAULT_MAX_METADATA_SIZE,
         }
     }
+
+    /// Compress metadata bodies put to keyservers using the encoding.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Set the maximum size of a decompressed metadata body, in bytes.
+    pub fn with_max_metadata_size(mut self, max_metadata_size: usize) -> Self {
+        self.max_metadata_size = max_metadata_size;
+        self
+    }
 }
 
+#[cfg(feature = "native")]
 impl Default for KeyserverClient<hyper::Client<HttpConnector>> {
     fn default() -> Self {
-        Self {
-            inner_client: hyper::Client::new(),
-        }
+        Self::from_service(hyper::Client::new())
     }
 }
 
+#[cfg(feature = "native")]
 impl KeyserverClient<hyper::Client<HttpConnector>> {
     /// Create a new HTTP client.
     pub fn new() -> Self {
@@ -87,13 +199,20 @@ impl KeyserverClient<hyper::Client<HttpConnector>> {
     }
 }
 
+#[cfg(feature = "native")]
 impl KeyserverClient<hyper::Client<HttpsConnector<HttpConnector>>> {
     /// Create new HTTPS client.
     pub fn new_tls() -> Self {
         let https = HttpsConnector::new();
-        Self {
-            inner_client: hyper::Client::builder().build(https),
-        }
+        Self::from_service(hyper::Client::builder().build(https))
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl KeyserverClient<crate::fetch::FetchClient> {
+    /// Create a new client using the `fetch` API of the browser.
+    pub fn new_fetch() -> Self {
+        Self::from_service(crate::fetch::FetchClient::new())
     }
 }
 
@@ -102,7 +221,7 @@ where
     Self: Service<(Uri, GetPeers), Response = Peers>,
     Self: Sync + Clone + Send + 'static,
     <Self as Service<(Uri, GetPeers)>>::Error: fmt::Display + std::error::Error,
-    <Self as Service<(Uri, GetPeers)>>::Future: Send + Sync + 'static,
+    <Self as Service<(Uri, GetPeers)>>::Future: Send + 'static,
 {
     /// Get [`Peers`] from a keyserver.
     pub async fn get_peers(
@@ -116,8 +235,33 @@ where
         // Construct request
         let request = (uri, GetPeers);
 
-        self.clone()
-            .oneshot(request)
+        instrument("get_peers", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+}
+
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, StreamPeers), Response = PeerStream>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, StreamPeers)>>::Error: fmt::Display + std::error::Error,
+    <Self as Service<(Uri, StreamPeers)>>::Future: Send + 'static,
+{
+    /// Get the [`Peers`] of a keyserver as a [`PeerStream`], decoding each entry as it arrives
+    /// rather than buffering the entire body.
+    pub async fn stream_peers(
+        &self,
+        keyserver_url: &str,
+    ) -> Result<PeerStream, KeyserverError<<Self as Service<(Uri, StreamPeers)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/peers", keyserver_url);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (uri, StreamPeers);
+
+        instrument("stream_peers", self.clone().oneshot(request))
             .await
             .map_err(KeyserverError::Error)
     }
@@ -128,7 +272,7 @@ where
     Self: Service<(Uri, GetMetadata), Response = MetadataPackage>,
     Self: Sync + Clone + Send + 'static,
     <Self as Service<(Uri, GetMetadata)>>::Error: fmt::Display + std::error::Error,
-    <Self as Service<(Uri, GetMetadata)>>::Future: Send + Sync + 'static,
+    <Self as Service<(Uri, GetMetadata)>>::Future: Send + 'static,
 {
     /// Get [`AddressMetadata`] from a server. The result is wrapped in [`MetadataPackage`].
     pub async fn get_metadata(
@@ -143,8 +287,104 @@ where
         // Construct request
         let request = (uri, GetMetadata);
 
-        self.clone()
-            .oneshot(request)
+        instrument("get_metadata", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+
+    /// Get the [`AddressMetadata`] of a [`Namespace`] from a server. The result is wrapped in
+    /// [`MetadataPackage`].
+    pub async fn get_namespace_metadata(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        namespace: &Namespace,
+    ) -> Result<MetadataPackage, KeyserverError<<Self as Service<(Uri, GetMetadata)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/keys/{}/{}", keyserver_url, address, namespace);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetMetadata);
+
+        instrument("get_namespace_metadata", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+}
+
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, GetMetadataSince), Response = MetadataPage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetMetadataSince)>>::Error: fmt::Display + std::error::Error,
+    <Self as Service<(Uri, GetMetadataSince)>>::Future: Send + 'static,
+{
+    /// Get a [`MetadataPage`] of at most `limit` entries, with timestamps of at least `since`,
+    /// from a keyserver.
+    pub async fn get_metadata_since(
+        &self,
+        keyserver_url: &str,
+        since: i64,
+        limit: usize,
+    ) -> Result<MetadataPage, KeyserverError<<Self as Service<(Uri, GetMetadataSince)>>::Error>>
+    {
+        // Construct URI
+        let full_path = format!("{}/keys?since={}&limit={}", keyserver_url, since, limit);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetMetadataSince { since, limit });
+
+        instrument("get_metadata_since", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+}
+
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, Search), Response = SearchPage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, Search)>>::Error: fmt::Display + std::error::Error,
+    <Self as Service<(Uri, Search)>>::Future: Send + 'static,
+{
+    /// Search a keyserver for addresses with a handle or name starting with `prefix`, returning a
+    /// [`SearchPage`] of at most `limit` matches.
+    ///
+    /// Further pages are requested by passing the `next_cursor` of the previous page, until it is
+    /// empty.
+    pub async fn search(
+        &self,
+        keyserver_url: &str,
+        prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<SearchPage, KeyserverError<<Self as Service<(Uri, Search)>>::Error>> {
+        // Construct URI
+        let mut full_path = format!(
+            "{}/search?prefix={}&limit={}",
+            keyserver_url,
+            encode_query_param(prefix),
+            limit
+        );
+        if let Some(cursor) = cursor {
+            full_path.push_str("&cursor=");
+            full_path.push_str(&encode_query_param(cursor));
+        }
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (
+            uri,
+            Search {
+                prefix: prefix.to_string(),
+                cursor: cursor.map(ToString::to_string),
+                limit,
+            },
+        );
+
+        instrument("search", self.clone().oneshot(request))
             .await
             .map_err(KeyserverError::Error)
     }
@@ -155,7 +395,7 @@ where
     Self: Service<(Uri, PutMetadata), Response = ()>,
     Self: Sync + Clone + Send + 'static,
     <Self as Service<(Uri, PutMetadata)>>::Error: fmt::Display + std::error::Error,
-    <Self as Service<(Uri, PutMetadata)>>::Future: Send + Sync + 'static,
+    <Self as Service<(Uri, PutMetadata)>>::Future: Send + 'static,
 {
     /// Put [`AuthWrapper`] to a keyserver.
     pub async fn put_metadata(
@@ -175,12 +415,123 @@ where
             PutMetadata {
                 token,
                 auth_wrapper,
+                idempotency_key: None,
             },
         );
 
         // Get response
-        self.clone()
-            .oneshot(request)
+        instrument("put_metadata", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+
+    /// Put [`AuthWrapper`] to a keyserver, with an idempotency key.
+    ///
+    /// The keyserver responds to a retry with the same key and body using the outcome of the
+    /// original put, without writing again, so a put may be retried safely after a timeout.
+    /// [`idempotency::derive_key`] derives a key from the serialized [`AuthWrapper`].
+    ///
+    /// [`idempotency::derive_key`]: cashweb_keyserver::idempotency::derive_key
+    pub async fn put_metadata_idempotent(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        auth_wrapper: AuthWrapper,
+        token: String,
+        idempotency_key: String,
+    ) -> Result<(), KeyserverError<<Self as Service<(Uri, PutMetadata)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/keys/{}", keyserver_url, address);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (
+            uri,
+            PutMetadata {
+                token,
+                auth_wrapper,
+                idempotency_key: Some(idempotency_key),
+            },
+        );
+
+        // Get response
+        instrument("put_metadata", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+
+    /// Put [`AuthWrapper`], covering the [`AddressMetadata`] of a [`Namespace`], to a keyserver.
+    ///
+    /// Every entry of the [`AddressMetadata`] must belong to the namespace. Other namespaces, and
+    /// the root document, are left untouched.
+    pub async fn put_namespace_metadata(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        namespace: &Namespace,
+        auth_wrapper: AuthWrapper,
+        token: String,
+    ) -> Result<(), KeyserverError<<Self as Service<(Uri, PutMetadata)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/keys/{}/{}", keyserver_url, address, namespace);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Construct request
+        let request = (
+            uri,
+            PutMetadata {
+                token,
+                auth_wrapper,
+                idempotency_key: None,
+            },
+        );
+
+        // Get response
+        instrument("put_namespace_metadata", self.clone().oneshot(request))
+            .await
+            .map_err(KeyserverError::Error)
+    }
+}
+
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, PatchMetadata), Response = ()>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PatchMetadata)>>::Error: fmt::Display + std::error::Error,
+    <Self as Service<(Uri, PatchMetadata)>>::Future: Send + 'static,
+{
+    /// Patch the [`AddressMetadata`] held by a keyserver, sending only the changed entries.
+    ///
+    /// The [`AuthWrapper`] must cover the patched metadata, such as that given to
+    /// [`patch::diff`], and its payload is omitted from the request. The keyserver refuses the
+    /// patch if it holds metadata other than the base of the patch.
+    ///
+    /// [`patch::diff`]: cashweb_keyserver::patch::diff
+    pub async fn patch_metadata(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        mut patch: MetadataPatch,
+        mut auth_wrapper: AuthWrapper,
+        token: String,
+    ) -> Result<(), KeyserverError<<Self as Service<(Uri, PatchMetadata)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/keys/{}", keyserver_url, address);
+        let uri: Uri = full_path.parse().map_err(KeyserverError::Uri)?;
+
+        // Replace the payload with its digest
+        if auth_wrapper.payload_digest.is_empty() {
+            auth_wrapper.payload_digest = digest(&SHA256, &auth_wrapper.payload).as_ref().to_vec();
+        }
+        auth_wrapper.payload = Vec::new();
+        patch.raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut patch.raw_auth_wrapper).unwrap(); // This is safe
+
+        // Construct request
+        let request = (uri, PatchMetadata { token, patch });
+
+        // Get response
+        instrument("patch_metadata", self.clone().oneshot(request))
             .await
             .map_err(KeyserverError::Error)
     }
@@ -191,7 +542,7 @@ where
     Self: Service<(Uri, PutRawAuthWrapper), Response = ()>,
     Self: Sync + Clone + Send + 'static,
     <Self as Service<(Uri, PutRawAuthWrapper)>>::Error: std::error::Error,
-    <Self as Service<(Uri, PutRawAuthWrapper)>>::Future: Send + Sync + 'static,
+    <Self as Service<(Uri, PutRawAuthWrapper)>>::Future: Send + 'static,
 {
     /// Put raw [`AuthWrapper`] to a keyserver.
     pub async fn put_raw_metadata(
@@ -211,13 +562,121 @@ where
             PutRawAuthWrapper {
                 token,
                 raw_auth_wrapper,
+                idempotency_key: None,
             },
         );
 
         // Get response
-        self.clone()
-            .oneshot(request)
+        instrument("put_raw_metadata", self.clone().oneshot(request))
             .await
             .map_err(KeyserverError::Error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use cashweb_keyserver::{Peer, SearchMatch};
+    use hyper::{Body, Request, Response};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let page = match request.uri().query() {
+                Some("prefix=al%20b&limit=1") => SearchPage {
+                    matches: vec![SearchMatch {
+                        address: "alice".to_string(),
+                        name: "al bundy".to_string(),
+                        ..Default::default()
+                    }],
+                    next_cursor: "al bundy".to_string(),
+                },
+                Some("prefix=al%20b&limit=1&cursor=al%20bundy") => SearchPage::default(),
+                _ => {
+                    return ready(Ok(Response::builder()
+                        .status(400)
+                        .body(Body::empty())
+                        .unwrap()))
+                }
+            };
+            let mut body = Vec::with_capacity(page.encoded_len());
+            page.encode(&mut body).unwrap();
+            ready(Ok(Response::new(Body::from(body))))
+        }
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let client = KeyserverClient::from_service(MockKeyserver);
+
+        let page = client
+            .search("http://keyserver", "al b", None, 1)
+            .await
+            .unwrap();
+        assert_eq!(page.matches[0].address, "alice");
+
+        let page = client
+            .search("http://keyserver", "al b", Some(&page.next_cursor), 1)
+            .await
+            .unwrap();
+        assert!(page.matches.is_empty());
+        assert!(page.next_cursor.is_empty());
+    }
+
+    /// Responds with peers, in chunks of three bytes.
+    #[derive(Clone)]
+    struct ChunkedPeers(Peers);
+
+    impl Service<Request<Body>> for ChunkedPeers {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<Body>) -> Self::Future {
+            let mut body = Vec::with_capacity(self.0.encoded_len());
+            self.0.encode(&mut body).unwrap();
+            let chunks: Vec<Result<_, Infallible>> =
+                body.chunks(3).map(|chunk| Ok(chunk.to_vec())).collect();
+            let body = Body::wrap_stream(futures_util::stream::iter(chunks));
+            ready(Ok(Response::new(body)))
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_peers() {
+        let peers = Peers {
+            peers: (0..100)
+                .map(|index| Peer::new(format!("http://keyserver-{}", index)))
+                .collect(),
+        };
+        let client = KeyserverClient::from_service(ChunkedPeers(peers.clone()));
+
+        let mut stream = client.stream_peers("http://keyserver").await.unwrap();
+        let first = futures_util::StreamExt::next(&mut stream).await;
+        assert_eq!(first.unwrap().unwrap(), peers.peers[0]);
+        assert_eq!(stream.collect().await.unwrap().peers, peers.peers[1..]);
+
+        assert_eq!(client.get_peers("http://keyserver").await.unwrap(), peers);
+    }
+}
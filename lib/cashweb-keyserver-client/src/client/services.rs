@@ -1,17 +1,24 @@
 //! This module contains lower-level primitives for working with the [`KeyserverClient`].
 
-use std::{fmt, pin::Pin};
+use std::{fmt, pin::Pin, time::Duration};
+
+use bytes::Bytes;
 
 use cashweb_auth_wrapper::{AuthWrapper, ParseError, VerifyError};
-use cashweb_keyserver::{AddressMetadata, Peers};
+use cashweb_keyserver::{
+    compression::{CompressionError, Encoding, ACCEPT_ENCODING},
+    idempotency::IDEMPOTENCY_KEY,
+    peers::{PeersDecodeError, PeersDecoder, VERSION_HEADER},
+    AddressMetadata, MetadataPage, MetadataPatch, Peer, Peers, SearchPage,
+};
 use futures_core::{
     task::{Context, Poll},
-    Future,
+    Future, Stream,
 };
-use futures_util::future::{join, join_all};
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use hyper::{
-    body::{aggregate, to_bytes},
-    http::header::AUTHORIZATION,
+    body::{aggregate, to_bytes, HttpBody},
+    http::header::{self, HeaderMap, AUTHORIZATION},
     http::Method,
     Body, Request, Response, StatusCode, Uri,
 };
@@ -19,11 +26,50 @@ use prost::Message as _;
 use thiserror::Error;
 use tower_service::Service;
 
-use crate::{KeyserverClient, MetadataPackage, RawAuthWrapperPackage};
+use crate::{
+    time::{self, timeout_at, Instant},
+    CachePolicy, KeyserverClient, MetadataPackage, RawAuthWrapperPackage,
+};
 
 type FutResponse<Response, Error> =
     Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
 
+/// The [`Encoding`] given by the `Content-Encoding` header.
+fn content_encoding(headers: &HeaderMap) -> Result<Encoding, CompressionError> {
+    match headers.get(header::CONTENT_ENCODING) {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| CompressionError::Unsupported(format!("{:?}", value)))?
+            .parse(),
+        None => Ok(Encoding::Identity),
+    }
+}
+
+/// Build a request carrying a metadata body, compressed using the encoding.
+fn metadata_request(
+    method: Method,
+    uri: Uri,
+    token: String,
+    idempotency_key: Option<String>,
+    body: Vec<u8>,
+    encoding: Encoding,
+) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(AUTHORIZATION, token);
+    if let Some(idempotency_key) = idempotency_key {
+        builder = builder.header(IDEMPOTENCY_KEY, idempotency_key);
+    }
+    let body = if encoding == Encoding::Identity {
+        body
+    } else {
+        builder = builder.header(header::CONTENT_ENCODING, encoding.as_str());
+        encoding.compress(&body)
+    };
+    builder.body(Body::from(body)).unwrap() // This is safe
+}
+
 /// Represents a request for the [`Peers`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GetPeers;
@@ -39,7 +85,7 @@ pub enum GetPeersError<E: fmt::Debug + fmt::Display> {
     Service(E),
     /// Error while decoding the body.
     #[error("body decoding failure: {0}")]
-    Decode(prost::DecodeError),
+    Decode(PeersDecodeError),
     /// Unexpected status code.
     #[error("unexpected status code: {0}")]
     UnexpectedStatusCode(u16),
@@ -48,6 +94,91 @@ pub enum GetPeersError<E: fmt::Debug + fmt::Display> {
     PeeringDisabled,
 }
 
+impl<E: fmt::Debug + fmt::Display> From<PeerStreamError> for GetPeersError<E> {
+    fn from(err: PeerStreamError) -> Self {
+        match err {
+            PeerStreamError::Body(err) => Self::Body(err),
+            PeerStreamError::Decode(err) => Self::Decode(err),
+        }
+    }
+}
+
+/// Error associated with a [`PeerStream`].
+#[derive(Debug, Error)]
+pub enum PeerStreamError {
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// Error while decoding the body.
+    #[error("body decoding failure: {0}")]
+    Decode(PeersDecodeError),
+}
+
+/// A [`Stream`] of the [`Peer`] entries of a [`Peers`] body, decoded as the body arrives.
+///
+/// The stream ends after the first error.
+#[derive(Debug)]
+pub struct PeerStream {
+    body: Body,
+    decoder: PeersDecoder,
+    done: bool,
+}
+
+impl PeerStream {
+    /// Decode the [`Peer`] entries of the body.
+    pub fn new(body: Body) -> Self {
+        Self {
+            body,
+            decoder: PeersDecoder::new(),
+            done: false,
+        }
+    }
+
+    /// Collect the remaining entries into [`Peers`].
+    pub async fn collect(mut self) -> Result<Peers, PeerStreamError> {
+        let mut peers = Vec::new();
+        while let Some(peer) = self.next().await {
+            peers.push(peer?);
+        }
+        Ok(Peers { peers })
+    }
+}
+
+impl Stream for PeerStream {
+    type Item = Result<Peer, PeerStreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match this.decoder.next_peer() {
+                Ok(Some(peer)) => return Poll::Ready(Some(Ok(peer))),
+                Ok(None) => (),
+                Err(err) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(PeerStreamError::Decode(err))));
+                }
+            }
+            match Pin::new(&mut this.body).poll_data(context) {
+                Poll::Ready(Some(Ok(chunk))) => this.decoder.push(&chunk),
+                Poll::Ready(Some(Err(err))) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(PeerStreamError::Body(err))));
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    if let Err(err) = this.decoder.finish() {
+                        return Poll::Ready(Some(Err(PeerStreamError::Decode(err))));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
 impl<S> Service<(Uri, GetPeers)> for KeyserverClient<S>
 where
     S: Service<Request<Body>, Response = Response<Body>>,
@@ -84,10 +215,266 @@ where
                 StatusCode::NOT_IMPLEMENTED => return Err(Self::Error::PeeringDisabled),
                 code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
             }
+            let peers = PeerStream::new(response.into_body()).collect().await?;
+            Ok(peers)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for the [`Peers`], responded to by a [`PeerStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamPeers;
+
+impl<S> Service<(Uri, StreamPeers)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Error: fmt::Debug,
+    <S as Service<Request<Body>>>::Error: fmt::Display,
+    <S as Service<Request<Body>>>::Future: Send,
+{
+    type Response = PeerStream;
+    type Error = GetPeersError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetPeersError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, StreamPeers)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+            match response.status() {
+                StatusCode::OK => (),
+                StatusCode::NOT_IMPLEMENTED => return Err(Self::Error::PeeringDisabled),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+            Ok(PeerStream::new(response.into_body()))
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for the [`Peers`] of a keyserver, measuring its quality as a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbePeers;
+
+/// The [`Peers`] of a keyserver, along with the quality of the keyserver observed while getting
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeersProbe {
+    /// The peers of the keyserver.
+    pub peers: Peers,
+    /// The round-trip latency of the request.
+    pub latency: Duration,
+    /// The version advertised by the keyserver, in its [`VERSION_HEADER`].
+    pub version: Option<String>,
+}
+
+impl<S> Service<(Uri, ProbePeers)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Error: fmt::Debug,
+    <S as Service<Request<Body>>>::Error: fmt::Display,
+    <S as Service<Request<Body>>>::Future: Send,
+{
+    type Response = PeersProbe;
+    type Error = GetPeersError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetPeersError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, ProbePeers)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            let start = Instant::now();
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+            let latency = start.elapsed();
+            match response.status() {
+                StatusCode::OK => (),
+                StatusCode::NOT_IMPLEMENTED => return Err(Self::Error::PeeringDisabled),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+            let version = response
+                .headers()
+                .get(VERSION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let peers = PeerStream::new(response.into_body()).collect().await?;
+            Ok(PeersProbe {
+                peers,
+                latency,
+                version,
+            })
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for a [`MetadataPage`] of the metadata with timestamps of at least
+/// `since`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetMetadataSince {
+    /// The minimum timestamp, in milliseconds.
+    pub since: i64,
+    /// The maximum number of entries.
+    pub limit: usize,
+}
+
+/// Error associated with getting a [`MetadataPage`] from a keyserver.
+#[derive(Debug, Error)]
+pub enum GetMetadataSinceError<E: fmt::Debug + fmt::Display> {
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while decoding the body.
+    #[error("body decoding failure: {0}")]
+    Decode(prost::DecodeError),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, GetMetadataSince)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = MetadataPage;
+    type Error = GetMetadataSinceError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetMetadataSinceError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, GetMetadataSince)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
             let body = response.into_body();
             let buf = aggregate(body).await.map_err(Self::Error::Body)?;
-            let peers = Peers::decode(buf).map_err(Self::Error::Decode)?;
-            Ok(peers)
+            let page = MetadataPage::decode(buf).map_err(Self::Error::Decode)?;
+            Ok(page)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for a [`SearchPage`] of the addresses with a handle or name starting with
+/// `prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Search {
+    /// The prefix of the handle or name.
+    pub prefix: String,
+    /// The cursor given by the previous page, if any.
+    pub cursor: Option<String>,
+    /// The maximum number of matches.
+    pub limit: usize,
+}
+
+/// Error associated with searching a keyserver.
+#[derive(Debug, Error)]
+pub enum SearchError<E: fmt::Debug + fmt::Display> {
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while decoding the body.
+    #[error("body decoding failure: {0}")]
+    Decode(prost::DecodeError),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, Search)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = SearchPage;
+    type Error = SearchError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(SearchError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, Search)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+            let body = response.into_body();
+            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let page = SearchPage::decode(buf).map_err(Self::Error::Decode)?;
+            Ok(page)
         };
         Box::pin(fut)
     }
@@ -114,6 +501,9 @@ pub enum GetRawAuthWrapperError<E: fmt::Debug + fmt::Display> {
     /// POP token missing from headers.
     #[error("missing token")]
     MissingToken,
+    /// Error while decompressing the body.
+    #[error("decompression failure: {0}")]
+    Decompress(CompressionError),
 }
 
 impl<S> Service<(Uri, GetRawAuthWrapper)> for KeyserverClient<S>
@@ -135,9 +525,11 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetRawAuthWrapper)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_metadata_size = self.max_metadata_size;
         let http_request = Request::builder()
             .method(Method::GET)
             .uri(uri)
+            .header(header::ACCEPT_ENCODING, ACCEPT_ENCODING)
             .body(Body::empty())
             .unwrap(); // This is safe
         let fut = async move {
@@ -165,9 +557,14 @@ where
                 .0
                 .to_string();
 
-            // Aggregate body
+            // Aggregate and decompress body
+            let encoding = content_encoding(response.headers()).map_err(Self::Error::Decompress)?;
             let body = response.into_body();
-            let raw_auth_wrapper = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_body = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_auth_wrapper = encoding
+                .decompress(&raw_body, max_metadata_size)
+                .map(Bytes::from)
+                .map_err(Self::Error::Decompress)?;
 
             Ok(RawAuthWrapperPackage {
                 token,
@@ -209,6 +606,9 @@ pub enum GetMetadataError<E: fmt::Debug + fmt::Display> {
     /// POP token missing from headers.
     #[error("missing token")]
     MissingToken,
+    /// Error while decompressing the body.
+    #[error("decompression failure: {0}")]
+    Decompress(CompressionError),
 }
 
 impl<S> Service<(Uri, GetMetadata)> for KeyserverClient<S>
@@ -230,9 +630,11 @@ where
 
     fn call(&mut self, (uri, _): (Uri, GetMetadata)) -> Self::Future {
         let mut client = self.inner_client.clone();
+        let max_metadata_size = self.max_metadata_size;
         let http_request = Request::builder()
             .method(Method::GET)
             .uri(uri)
+            .header(header::ACCEPT_ENCODING, ACCEPT_ENCODING)
             .body(Body::empty())
             .unwrap(); // This is safe
         let fut = async move {
@@ -260,9 +662,16 @@ where
                 .0
                 .to_string();
 
-            // Deserialize and decode body
+            let cache_policy = CachePolicy::from_headers(response.headers(), time::now());
+
+            // Decompress, deserialize and decode body
+            let encoding = content_encoding(response.headers()).map_err(Self::Error::Decompress)?;
             let body = response.into_body();
-            let raw_auth_wrapper = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_body = to_bytes(body).await.map_err(Self::Error::Body)?;
+            let raw_auth_wrapper = encoding
+                .decompress(&raw_body, max_metadata_size)
+                .map(Bytes::from)
+                .map_err(Self::Error::Decompress)?;
             let auth_wrapper = AuthWrapper::decode(raw_auth_wrapper.clone())
                 .map_err(Self::Error::AuthWrapperDecode)?;
 
@@ -285,6 +694,7 @@ where
                 public_key: parsed_auth_wrapper.public_key,
                 metadata,
                 raw_auth_wrapper,
+                cache_policy,
             })
         };
         Box::pin(fut)
@@ -298,6 +708,8 @@ pub struct PutMetadata {
     pub token: String,
     /// The [`AuthWrapper`] to be put to the keyserver.
     pub auth_wrapper: AuthWrapper,
+    /// Idempotency key, allowing the put to be retried safely.
+    pub idempotency_key: Option<String>,
 }
 
 /// Error associated with putting [`AddressMetadata`] to the keyserver.
@@ -306,6 +718,12 @@ pub enum PutMetadataError<E: fmt::Debug + fmt::Display> {
     /// A connection error occured.
     #[error("connection failure: {0}")]
     Service(E),
+    /// A put with the same idempotency key is in progress.
+    #[error("idempotent request in progress")]
+    InProgress,
+    /// The idempotency key was used by a different put.
+    #[error("idempotency key reused")]
+    KeyReused,
     /// Unexpected status code.
     #[error("unexpected status code: {0}")]
     UnexpectedStatusCode(u16),
@@ -335,12 +753,14 @@ where
         let mut body = Vec::with_capacity(request.auth_wrapper.encoded_len());
         request.auth_wrapper.encode(&mut body).unwrap();
 
-        let http_request = Request::builder()
-            .method(Method::PUT)
-            .uri(uri)
-            .header(AUTHORIZATION, request.token)
-            .body(Body::from(body))
-            .unwrap(); // This is safe
+        let http_request = metadata_request(
+            Method::PUT,
+            uri,
+            request.token,
+            request.idempotency_key,
+            body,
+            self.encoding,
+        );
 
         let fut = async move {
             // Get response
@@ -350,13 +770,12 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
             match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+                StatusCode::OK => Ok(()),
+                StatusCode::CONFLICT => Err(Self::Error::InProgress),
+                StatusCode::UNPROCESSABLE_ENTITY => Err(Self::Error::KeyReused),
+                code => Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
             }
-
-            Ok(())
         };
         Box::pin(fut)
     }
@@ -369,6 +788,8 @@ pub struct PutRawAuthWrapper {
     pub token: String,
     /// The raw [`AuthWrapper`] to be put to the keyserver.
     pub raw_auth_wrapper: Vec<u8>,
+    /// Idempotency key, allowing the put to be retried safely.
+    pub idempotency_key: Option<String>,
 }
 
 impl<S> Service<(Uri, PutRawAuthWrapper)> for KeyserverClient<S>
@@ -394,12 +815,14 @@ where
         // Construct body
         let body = request.raw_auth_wrapper;
 
-        let http_request = Request::builder()
-            .method(Method::PUT)
-            .uri(uri)
-            .header(AUTHORIZATION, request.token)
-            .body(Body::from(body))
-            .unwrap(); // This is safe
+        let http_request = metadata_request(
+            Method::PUT,
+            uri,
+            request.token,
+            request.idempotency_key,
+            body,
+            self.encoding,
+        );
 
         let fut = async move {
             // Get response
@@ -409,13 +832,80 @@ where
                 .map_err(Self::Error::Service)?;
 
             // Check status code
-            // TODO: Fix this
             match response.status() {
-                StatusCode::OK => (),
-                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+                StatusCode::OK => Ok(()),
+                StatusCode::CONFLICT => Err(Self::Error::InProgress),
+                StatusCode::UNPROCESSABLE_ENTITY => Err(Self::Error::KeyReused),
+                code => Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
             }
+        };
+        Box::pin(fut)
+    }
+}
 
-            Ok(())
+/// Request for patching the [`AddressMetadata`] held by a keyserver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchMetadata {
+    /// POP authorization token.
+    pub token: String,
+    /// The [`MetadataPatch`], including the [`AuthWrapper`] covering the patched metadata.
+    pub patch: MetadataPatch,
+}
+
+/// Error associated with patching [`AddressMetadata`] held by a keyserver.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PatchMetadataError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// The keyserver held metadata other than the base of the patch.
+    #[error("base digest conflict")]
+    Conflict,
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, PatchMetadata)> for KeyserverClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Error: fmt::Debug + fmt::Display,
+    S::Future: Send,
+{
+    type Response = ();
+    type Error = PatchMetadataError<S::Error>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(PatchMetadataError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, PatchMetadata)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        // Construct body
+        let mut body = Vec::with_capacity(request.patch.encoded_len());
+        request.patch.encode(&mut body).unwrap();
+
+        let http_request =
+            metadata_request(Method::PATCH, uri, request.token, None, body, self.encoding);
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => Ok(()),
+                StatusCode::CONFLICT => Err(Self::Error::Conflict),
+                code => Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
         };
         Box::pin(fut)
     }
@@ -428,6 +918,17 @@ pub struct SampleRequest<T> {
     pub uris: Vec<Uri>,
     /// The request to be broadcast.
     pub request: T,
+    /// The deadline of the whole sample, after which the outstanding requests are cancelled.
+    pub deadline: Option<Instant>,
+}
+
+/// The responses to a [`SampleRequest`], in the order of its [`Uri`]s.
+#[derive(Debug)]
+pub struct SampleResults<R, E> {
+    /// The completed requests, paired with the [`Uri`] of the keyserver they were sent to.
+    pub responses: Vec<(Uri, Result<R, E>)>,
+    /// The [`Uri`]s of the keyservers still pending at the deadline.
+    pub pending: Vec<Uri>,
 }
 
 /// Error associated with sending sample requests.
@@ -450,11 +951,8 @@ where
     <Self as Service<(Uri, T)>>::Error: fmt::Debug + fmt::Display + Send,
     <Self as Service<(Uri, T)>>::Future: Send,
 {
-    #[allow(clippy::type_complexity)]
-    type Response = Vec<(
-        Uri,
-        Result<<Self as Service<(Uri, T)>>::Response, <Self as Service<(Uri, T)>>::Error>,
-    )>;
+    type Response =
+        SampleResults<<Self as Service<(Uri, T)>>::Response, <Self as Service<(Uri, T)>>::Error>;
     type Error = SampleError<<Self as Service<(Uri, T)>>::Error>;
     type Future = FutResponse<Self::Response, Self::Error>;
 
@@ -462,20 +960,53 @@ where
         self.poll_ready(context).map_err(SampleError::Poll)
     }
 
-    fn call(&mut self, SampleRequest { uris, request }: SampleRequest<T>) -> Self::Future {
+    fn call(
+        &mut self,
+        SampleRequest {
+            uris,
+            request,
+            deadline,
+        }: SampleRequest<T>,
+    ) -> Self::Future {
         let mut inner_client = self.clone();
 
         let fut = async move {
             // Collect futures
-            let response_futs = uris.into_iter().map(move |uri| {
-                let response_fut = inner_client.call((uri.clone(), request.clone()));
-                let uri_fut = async move { uri };
-                join(uri_fut, response_fut)
-            });
-            let responses: Vec<(Uri, Result<_, _>)> = join_all(response_futs).await;
-
-            // If no successes then return all errors
-            if responses.iter().all(|(_, res)| res.is_err()) {
+            let mut in_flight: FuturesUnordered<_> = uris
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(index, uri)| {
+                    let response_fut = inner_client.call((uri, request.clone()));
+                    async move { (index, response_fut.await) }
+                })
+                .collect();
+            let mut completed = Vec::with_capacity(uris.len());
+            let collect = async {
+                while let Some(response) = in_flight.next().await {
+                    completed.push(response);
+                }
+            };
+            match deadline {
+                // Stragglers are cancelled by dropping their futures
+                Some(deadline) => {
+                    let _ = timeout_at(deadline, collect).await;
+                }
+                None => collect.await,
+            }
+            drop(in_flight);
+
+            // Restore the order of the URIs
+            completed.sort_unstable_by_key(|(index, _)| *index);
+            let mut uris: Vec<Option<Uri>> = uris.into_iter().map(Some).collect();
+            let responses: Vec<(Uri, Result<_, _>)> = completed
+                .into_iter()
+                .map(|(index, result)| (uris[index].take().unwrap(), result)) // This is safe as indices are unique
+                .collect();
+            let pending: Vec<Uri> = uris.into_iter().flatten().collect();
+
+            // If no successes, and none pending, then return all errors
+            if pending.is_empty() && responses.iter().all(|(_, res)| res.is_err()) {
                 let errors = responses
                     .into_iter()
                     .map(|(uri, result)| (uri, result.unwrap_err()))
@@ -483,8 +1014,45 @@ where
                 return Err(SampleError::Sample(errors));
             }
 
-            Ok(responses)
+            Ok(SampleResults { responses, pending })
         };
         Box::pin(fut)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_request() {
+        let uri: Uri = "http://keyserver/keys/address".parse().unwrap();
+        let body = vec![1; 4_096];
+
+        let request = metadata_request(
+            Method::PUT,
+            uri.clone(),
+            "POP token".to_string(),
+            Some("key".to_string()),
+            body.clone(),
+            Encoding::Gzip,
+        );
+        assert_eq!(content_encoding(request.headers()).unwrap(), Encoding::Gzip);
+        assert_eq!(request.headers().get(IDEMPOTENCY_KEY).unwrap(), "key");
+
+        let request = metadata_request(
+            Method::PUT,
+            uri,
+            "POP token".to_string(),
+            None,
+            body,
+            Encoding::Identity,
+        );
+        assert!(request.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            content_encoding(request.headers()).unwrap(),
+            Encoding::Identity
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! This module contains [`FetchClient`], a [`Service`] sending requests using the `fetch` API of
+//! browsers, so that web wallets share the aggregation and verification of the
+//! [`KeyserverClient`] and [`KeyserverManager`].
+//!
+//! Requests are made from the window or worker the client runs on, subject to its CORS policy.
+//! Requests which are dropped, such as those outstanding at the deadline of a sample, run to
+//! completion in the background.
+//!
+//! ```ignore
+//! let manager = KeyserverManager::new_fetch(uris)?.with_deadline(Duration::from_secs(5));
+//! ```
+//!
+//! [`KeyserverClient`]: crate::KeyserverClient
+//! [`KeyserverManager`]: crate::KeyserverManager
+
+use std::pin::Pin;
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use hyper::{
+    body::to_bytes,
+    http::{self, header::HeaderName},
+    Body, Request, Response,
+};
+use js_sys::{Array, Promise, Uint8Array};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tower_service::Service;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, RequestInit, Window, WorkerGlobalScope};
+
+type ResponseFuture<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// Error associated with sending a request using `fetch`.
+#[derive(Debug, Error)]
+pub enum FetchError {
+    /// Error while buffering the request body.
+    #[error("buffering body failed: {0}")]
+    Body(hyper::Error),
+    /// The value of the header could not be sent, as it is not visible ASCII.
+    #[error("invalid value of header {0}")]
+    Header(HeaderName),
+    /// The browser rejected the request, or failed to send it.
+    #[error("fetch failed: {0}")]
+    Fetch(String),
+    /// The response could not be converted.
+    #[error("invalid response: {0}")]
+    Response(http::Error),
+    /// The request was dropped by the browser before completing.
+    #[error("request cancelled")]
+    Cancelled,
+}
+
+impl From<JsValue> for FetchError {
+    fn from(value: JsValue) -> Self {
+        let message = match value.dyn_ref::<js_sys::Error>() {
+            Some(err) => err.message().into(),
+            None => value.as_string().unwrap_or_else(|| format!("{:?}", value)),
+        };
+        Self::Fetch(message)
+    }
+}
+
+/// `fetch` from the window, or else the worker, the client runs on.
+fn global_fetch(request: &web_sys::Request) -> Promise {
+    let global = js_sys::global();
+    match global.dyn_ref::<Window>() {
+        Some(window) => window.fetch_with_request(request),
+        None => global
+            .unchecked_into::<WorkerGlobalScope>()
+            .fetch_with_request(request),
+    }
+}
+
+/// Send the request using `fetch`, buffering the response body.
+async fn fetch(request: Request<Body>) -> Result<Response<Body>, FetchError> {
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body).await.map_err(FetchError::Body)?;
+
+    let headers = Headers::new()?;
+    for (name, value) in &parts.headers {
+        let value = value
+            .to_str()
+            .map_err(|_| FetchError::Header(name.clone()))?;
+        headers.append(name.as_str(), value)?;
+    }
+    let mut init = RequestInit::new();
+    init.method(parts.method.as_str()).headers(&headers);
+    if !body.is_empty() {
+        init.body(Some(&Uint8Array::from(&body[..])));
+    }
+    let request = web_sys::Request::new_with_str_and_init(&parts.uri.to_string(), &init)?;
+    let response: web_sys::Response = JsFuture::from(global_fetch(&request))
+        .await?
+        .unchecked_into();
+
+    let mut builder = Response::builder().status(response.status());
+    if let Some(entries) = js_sys::try_iter(&response.headers())? {
+        for entry in entries {
+            // Entries are `[name, value]` pairs of strings
+            let entry: Array = entry?.unchecked_into();
+            let name = entry.get(0).as_string().unwrap_or_default();
+            let value = entry.get(1).as_string().unwrap_or_default();
+            builder = builder.header(name, value);
+        }
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    let body = Uint8Array::new(&buffer).to_vec();
+    builder.body(Body::from(body)).map_err(FetchError::Response)
+}
+
+/// A [`Service`] sending requests using the `fetch` API of browsers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchClient;
+
+impl FetchClient {
+    /// Create a new [`FetchClient`].
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Service<Request<Body>> for FetchClient {
+    type Response = Response<Body>;
+    type Error = FetchError;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // JS values can't be sent between threads, so the fetch is driven by the event loop and
+        // its response sent back over a channel, keeping the future `Send`
+        let (sender, receiver) = oneshot::channel();
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = sender.send(fetch(request).await);
+        });
+        Box::pin(async move { receiver.await.unwrap_or(Err(FetchError::Cancelled)) })
+    }
+}
@@ -0,0 +1,316 @@
+//! This module contains the [`Http3Client`], an HTTP/3 transport for the [`KeyserverClient`].
+//!
+//! The client implements the same [`Service`] as a [`hyper::Client`], so every keyserver
+//! service is available over QUIC, which holds up better than TCP on lossy mobile networks.
+//! One connection is kept per keyserver and reused until it closes.
+//!
+//! Keyservers must be addressed using `https` URIs and are authenticated against the
+//! [`webpki_roots`] trust anchors, unless a TLS configuration is provided.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    future::Future,
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use h3::client::SendRequest;
+use h3_quinn::OpenStreams;
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    Body, Request, Response, StatusCode,
+};
+use quinn::{
+    crypto::rustls::QuicClientConfig,
+    rustls::{self, RootCertStore},
+    ClientConfig, Endpoint,
+};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::KeyserverClient;
+
+/// ALPN protocol identifier of HTTP/3.
+pub const ALPN: &[u8] = b"h3";
+
+/// Default port of HTTP/3 keyservers.
+const DEFAULT_PORT: u16 = 443;
+
+/// Error associated with the [`Http3Client`].
+#[derive(Debug, Error)]
+pub enum Http3Error {
+    /// The TLS configuration does not support QUIC.
+    #[error("unsupported TLS configuration: {0}")]
+    Tls(String),
+    /// Failed to bind the local UDP socket.
+    #[error("failed to bind socket: {0}")]
+    Bind(io::Error),
+    /// The URI has no host.
+    #[error("missing host")]
+    MissingHost,
+    /// The URI scheme was not `https`.
+    #[error("unsupported scheme")]
+    UnsupportedScheme,
+    /// Failed to resolve the host.
+    #[error("failed to resolve host: {0}")]
+    Resolve(io::Error),
+    /// The host resolved to no addresses.
+    #[error("no address found for host")]
+    NoAddress,
+    /// Failed to initiate the QUIC connection.
+    #[error("failed to connect: {0}")]
+    Connect(quinn::ConnectError),
+    /// The QUIC connection failed.
+    #[error("connection failure: {0}")]
+    Connection(quinn::ConnectionError),
+    /// The HTTP/3 connection failed.
+    #[error("http/3 connection failure: {0}")]
+    Handshake(h3::error::ConnectionError),
+    /// The HTTP/3 request stream failed.
+    #[error("http/3 stream failure: {0}")]
+    Stream(h3::error::StreamError),
+    /// The request could not be converted.
+    #[error("invalid request: {0}")]
+    Request(http::Error),
+    /// Failed to read the request body.
+    #[error("failed to read request body: {0}")]
+    Body(hyper::Error),
+}
+
+#[derive(Clone)]
+struct Connection {
+    connection: quinn::Connection,
+    send_request: SendRequest<OpenStreams, Bytes>,
+}
+
+/// An HTTP/3 client, implementing [`Service`] over [`Request<Body>`] like a [`hyper::Client`].
+#[derive(Clone)]
+pub struct Http3Client {
+    endpoint: Endpoint,
+    connections: Arc<Mutex<HashMap<String, Connection>>>,
+}
+
+impl std::fmt::Debug for Http3Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Http3Client")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl Http3Client {
+    /// Create a client trusting the [`webpki_roots`] certificate authorities.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new() -> Result<Self, Http3Error> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let tls_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|err| Http3Error::Tls(err.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        Self::with_tls_config(tls_config)
+    }
+
+    /// Create a client using the TLS configuration, setting its ALPN protocols to HTTP/3.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn with_tls_config(mut tls_config: rustls::ClientConfig) -> Result<Self, Http3Error> {
+        tls_config.alpn_protocols = vec![ALPN.to_vec()];
+        let quic_config = QuicClientConfig::try_from(tls_config)
+            .map_err(|err| Http3Error::Tls(err.to_string()))?;
+        let mut endpoint = Endpoint::client(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+            .map_err(Http3Error::Bind)?;
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(quic_config)));
+        Ok(Self {
+            endpoint,
+            connections: Default::default(),
+        })
+    }
+
+    /// Get an open connection to the authority, establishing one if necessary.
+    async fn connect(&self, host: &str, port: u16) -> Result<Connection, Http3Error> {
+        let authority = format!("{}:{}", host, port);
+        let cached = self
+            .connections
+            .lock()
+            .unwrap() // This is safe
+            .get(&authority)
+            .cloned();
+        if let Some(cached) = cached {
+            if cached.connection.close_reason().is_none() {
+                return Ok(cached);
+            }
+        }
+
+        let addr = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(Http3Error::Resolve)?
+            .next()
+            .ok_or(Http3Error::NoAddress)?;
+        let connection = self
+            .endpoint
+            .connect(addr, host)
+            .map_err(Http3Error::Connect)?
+            .await
+            .map_err(Http3Error::Connection)?;
+        let (mut driver, send_request) =
+            h3::client::new(h3_quinn::Connection::new(connection.clone()))
+                .await
+                .map_err(Http3Error::Handshake)?;
+        tokio::spawn(async move {
+            driver.wait_idle().await;
+        });
+
+        let connection = Connection {
+            connection,
+            send_request,
+        };
+        self.connections
+            .lock()
+            .unwrap() // This is safe
+            .insert(authority, connection.clone());
+        Ok(connection)
+    }
+
+    /// Send the request over HTTP/3, buffering the response body.
+    pub async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Http3Error> {
+        let uri = request.uri();
+        if uri.scheme_str() != Some("https") {
+            return Err(Http3Error::UnsupportedScheme);
+        }
+        let host = uri
+            .host()
+            .ok_or(Http3Error::MissingHost)?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or(DEFAULT_PORT);
+
+        let (h3_request, body) = into_h3_request(request).await?;
+        let mut connection = self.connect(&host, port).await?;
+        let mut stream = connection
+            .send_request
+            .send_request(h3_request)
+            .await
+            .map_err(Http3Error::Stream)?;
+        if !body.is_empty() {
+            stream.send_data(body).await.map_err(Http3Error::Stream)?;
+        }
+        stream.finish().await.map_err(Http3Error::Stream)?;
+
+        let h3_response = stream.recv_response().await.map_err(Http3Error::Stream)?;
+        let mut body = BytesMut::new();
+        while let Some(chunk) = stream.recv_data().await.map_err(Http3Error::Stream)? {
+            body.put(chunk);
+        }
+        Ok(from_h3_response(h3_response, body.freeze()))
+    }
+}
+
+/// Convert a hyper request into an HTTP/3 request and its body.
+async fn into_h3_request(request: Request<Body>) -> Result<(http::Request<()>, Bytes), Http3Error> {
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(Http3Error::Body)?;
+    let mut builder = http::Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string());
+    for (name, value) in &parts.headers {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    let h3_request = builder.body(()).map_err(Http3Error::Request)?;
+    Ok((h3_request, body))
+}
+
+/// Convert an HTTP/3 response and its body into a hyper response.
+fn from_h3_response(h3_response: http::Response<()>, body: Bytes) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    // This is safe as the status code was already validated
+    *response.status_mut() = StatusCode::from_u16(h3_response.status().as_u16()).unwrap();
+    let headers = response.headers_mut();
+    for (name, value) in h3_response.headers() {
+        // This is safe as the header was already validated
+        let name = HeaderName::from_bytes(name.as_str().as_bytes()).unwrap();
+        let value = HeaderValue::from_bytes(value.as_bytes()).unwrap();
+        headers.append(name, value);
+    }
+    response
+}
+
+impl Service<Request<Body>> for Http3Client {
+    type Response = Response<Body>;
+    type Error = Http3Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.send(request).await })
+    }
+}
+
+impl KeyserverClient<Http3Client> {
+    /// Create a new HTTP/3 client.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new_http3() -> Result<Self, Http3Error> {
+        Http3Client::new().map(Self::from_service)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn convert() {
+        let request = Request::put("https://keyserver.example:8443/keys/abc")
+            .header("authorization", "POP token")
+            .body(Body::from("metadata"))
+            .unwrap();
+        let (h3_request, body) = into_h3_request(request).await.unwrap();
+        assert_eq!(h3_request.method(), http::Method::PUT);
+        assert_eq!(
+            h3_request.uri().to_string(),
+            "https://keyserver.example:8443/keys/abc"
+        );
+        assert_eq!(h3_request.headers()["authorization"], "POP token");
+        assert_eq!(body, "metadata");
+
+        let h3_response = http::Response::builder()
+            .status(404)
+            .header("content-type", "application/x-protobuf")
+            .body(())
+            .unwrap();
+        let response = from_h3_response(h3_response, Bytes::new());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "application/x-protobuf");
+    }
+
+    #[tokio::test]
+    async fn unsupported_scheme() {
+        let client = Http3Client::new().unwrap();
+        let request = Request::get("http://keyserver.example/peers")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            client.send(request).await,
+            Err(Http3Error::UnsupportedScheme)
+        ));
+    }
+}
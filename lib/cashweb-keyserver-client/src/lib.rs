@@ -8,9 +8,34 @@
 //! `cashweb-bitcoin-client` is a library providing [`KeyserverClient`] which allows
 //! interaction with specific keyservers and [`KeyserverManager`]
 //! which allows sampling and aggregation over multiple keyservers.
+//!
+//! The `native` feature, enabled by default, connects using [`hyper`] and provides the operator
+//! tooling. Without it, and with the `wasm` feature, the crate compiles to
+//! `wasm32-unknown-unknown` and connects using the `fetch` API of browsers, see the `fetch` module.
 
+#[cfg(feature = "native")]
+pub mod admin;
+#[cfg(feature = "native")]
+pub mod archive;
 mod client;
+pub mod credentials;
+#[cfg(feature = "wasm")]
+pub mod fetch;
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod logging;
 mod manager;
+mod metadata_cache;
+pub mod policy;
+#[cfg(feature = "native")]
+pub mod replication;
+pub mod time;
+mod token_cache;
+pub mod trust;
+#[cfg(feature = "native")]
+pub mod webhook;
 
 pub use client::*;
 pub use manager::*;
+pub use metadata_cache::*;
+pub use token_cache::*;
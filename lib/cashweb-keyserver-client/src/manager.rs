@@ -1,22 +1,35 @@
-use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use cashweb_auth_wrapper::AuthWrapper;
-use cashweb_keyserver::{Peer, Peers};
-use hyper::{
-    client::Client as HyperClient,
-    client::HttpConnector,
-    http::uri::{InvalidUri, PathAndQuery},
-    Body, Request, Response, Uri,
-};
+use cashweb_keyserver::{peers::rank, Peer, Peers};
+#[cfg(feature = "native")]
+use hyper::client::{Client as HyperClient, HttpConnector};
+#[cfg(any(feature = "native", feature = "wasm"))]
+use hyper::http::uri::InvalidUri;
+use hyper::{http::uri::PathAndQuery, Body, Request, Response, Uri};
 use prost::Message as _;
 use rand::seq::SliceRandom;
 use tokio::sync::RwLock;
 use tower_service::Service;
 use tower_util::ServiceExt;
 
+#[cfg(feature = "native")]
+use crate::trust::PinnedConnector;
 use crate::{
     client::{KeyserverClient, MetadataPackage},
-    services::{GetMetadata, GetPeers, PutMetadata, PutRawAuthWrapper, SampleError, SampleRequest},
+    policy::PeerPolicy,
+    services::{
+        GetMetadata, GetPeers, ProbePeers, PutMetadata, PutRawAuthWrapper, SampleError,
+        SampleRequest, SampleResults,
+    },
+    time::{self, Instant},
+    trust::TrustBundle,
 };
 
 /// KeyserverManager wraps a client and allows sampling and selecting of queries across a set of keyservers.
@@ -24,6 +37,9 @@ use crate::{
 pub struct KeyserverManager<S> {
     inner_client: KeyserverClient<S>,
     uris: Arc<RwLock<Vec<Uri>>>,
+    trust: Option<Arc<TrustBundle>>,
+    policy: Option<PeerPolicy>,
+    deadline: Option<Duration>,
 }
 
 impl<S> KeyserverManager<S> {
@@ -32,7 +48,55 @@ impl<S> KeyserverManager<S> {
         Self {
             inner_client: KeyserverClient::from_service(service),
             uris: Arc::new(RwLock::new(uris)),
+            trust: None,
+            policy: None,
+            deadline: None,
+        }
+    }
+
+    /// Only sample and aggregate over keyservers pinned by the bundle.
+    ///
+    /// This filters the keyservers queried, it does not check their certificates. Pair it with a
+    /// [`PinnedConnector`] to do so, as [`KeyserverManager::new_pinned`] does.
+    pub fn with_trust(mut self, bundle: TrustBundle) -> Self {
+        self.trust = Some(Arc::new(bundle));
+        self
+    }
+
+    /// Report misbehaviour to the [`PeerPolicy`], and skip the keyservers it bans.
+    pub fn with_policy(mut self, policy: PeerPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Return partial results once the deadline has elapsed, cancelling the requests still pending.
+    ///
+    /// The keyservers still pending are listed by the [`SampleResponse`] or [`AggregateResponse`].
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// The deadline of a sample starting now.
+    fn sample_deadline(&self) -> Option<Instant> {
+        self.deadline.map(|deadline| Instant::now() + deadline)
+    }
+
+    /// Get the [`Uri`]s, excluding those not pinned by the trust bundle, if any, and those banned
+    /// by the policy, if any.
+    async fn trusted_uris(&self) -> Vec<Uri> {
+        let uris = self.uris.read().await.clone();
+        self.retain_trusted(uris)
+    }
+
+    fn retain_trusted(&self, mut uris: Vec<Uri>) -> Vec<Uri> {
+        if let Some(bundle) = &self.trust {
+            uris.retain(|uri| bundle.is_uri_pinned(uri));
+        }
+        if let Some(policy) = &self.policy {
+            policy.retain_allowed(&mut uris);
         }
+        uris
     }
 
     /// Get shared reference the [`Uri`]s.
@@ -46,6 +110,7 @@ impl<S> KeyserverManager<S> {
     }
 }
 
+#[cfg(feature = "native")]
 impl KeyserverManager<HyperClient<HttpConnector>> {
     /// Create a HTTP manager.
     pub fn new(uris: Vec<String>) -> Result<Self, InvalidUri> {
@@ -54,10 +119,34 @@ impl KeyserverManager<HyperClient<HttpConnector>> {
         Ok(Self {
             inner_client: KeyserverClient::new(),
             uris: Arc::new(RwLock::new(uris)),
+            trust: None,
+            policy: None,
+            deadline: None,
         })
     }
 }
 
+#[cfg(feature = "native")]
+impl KeyserverManager<HyperClient<PinnedConnector<HttpConnector>>> {
+    /// Create a HTTPS manager, only trusting keyservers whose certificates are pinned by the
+    /// bundle.
+    pub fn new_pinned(uris: Vec<String>, bundle: TrustBundle) -> Result<Self, InvalidUri> {
+        let uris: Result<Vec<Uri>, _> = uris.into_iter().map(|uri| uri.parse()).collect();
+        let connector = PinnedConnector::new(bundle.clone());
+        let client = HyperClient::builder().build(connector);
+        Ok(Self::from_service(client, uris?).with_trust(bundle))
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl KeyserverManager<crate::fetch::FetchClient> {
+    /// Create a manager using the `fetch` API of the browser.
+    pub fn new_fetch(uris: Vec<String>) -> Result<Self, InvalidUri> {
+        let uris: Result<Vec<Uri>, _> = uris.into_iter().map(|uri| uri.parse()).collect();
+        Ok(Self::from_service(crate::fetch::FetchClient::new(), uris?))
+    }
+}
+
 /// Takes a URI and appends a path to it.
 ///
 /// This panics if `new_path` is invalid.
@@ -97,6 +186,19 @@ pub fn uniform_random_sampler(uris: &[Uri], size: usize) -> Vec<Uri> {
     uris.choose_multiple(&mut rng, size).cloned().collect()
 }
 
+/// Choose the best URIs, by the quality data of their [`Peer`]s.
+///
+/// Peers with unparsable URLs are skipped.
+pub fn ranked_sampler(peers: &[Peer], size: usize) -> Vec<Uri> {
+    let mut peers = peers.to_vec();
+    rank(&mut peers);
+    peers
+        .into_iter()
+        .filter_map(|peer| peer.url.parse().ok())
+        .take(size)
+        .collect()
+}
+
 /// Select best [`AuthWrapper`] from a list.
 ///
 /// [`AuthWrapper`]: auth_wrapper::AuthWrapper
@@ -125,6 +227,8 @@ pub struct SampleResponse<R, E> {
     pub response: Option<(Uri, R)>,
     /// The errors paired with the [`Uri`] of the keyserver they originated at.
     pub errors: Vec<(Uri, E)>,
+    /// The [`Uri`]s of the keyservers still pending when the deadline elapsed.
+    pub pending: Vec<Uri>,
 }
 
 impl<R, E> SampleResponse<R, E>
@@ -132,9 +236,9 @@ where
     R: fmt::Debug,
     E: fmt::Debug,
 {
-    /// Create a sample response from a list of results.
+    /// Create a sample response from the results.
     pub fn select<F: FnOnce(Vec<(Uri, R)>) -> Option<(Uri, R)>>(
-        responses: Vec<(Uri, Result<R, E>)>,
+        SampleResults { responses, pending }: SampleResults<R, E>,
         selector: F,
     ) -> Self {
         let (oks, errors): (Vec<_>, Vec<_>) =
@@ -150,7 +254,11 @@ where
 
         let response = selector(oks);
 
-        SampleResponse { response, errors }
+        SampleResponse {
+            response,
+            errors,
+            pending,
+        }
     }
 }
 
@@ -161,6 +269,8 @@ pub struct AggregateResponse<R, E> {
     pub response: R,
     /// The errors paired with the [`Uri`] of the keyserver they originated at.
     pub errors: Vec<(Uri, E)>,
+    /// The [`Uri`]s of the keyservers still pending when the deadline elapsed.
+    pub pending: Vec<Uri>,
 }
 
 impl<R, E> AggregateResponse<R, E>
@@ -168,9 +278,9 @@ where
     R: fmt::Debug,
     E: fmt::Debug,
 {
-    /// Create a sample response from a list of results.
+    /// Create an aggregate response from the results.
     pub fn aggregate<F: FnOnce(Vec<(Uri, R)>) -> R>(
-        responses: Vec<(Uri, Result<R, E>)>,
+        SampleResults { responses, pending }: SampleResults<R, E>,
         aggregator: F,
     ) -> Self {
         let (oks, errors): (Vec<_>, Vec<_>) =
@@ -186,7 +296,11 @@ where
 
         let response = aggregator(oks);
 
-        AggregateResponse { response, errors }
+        AggregateResponse {
+            response,
+            errors,
+            pending,
+        }
     }
 }
 
@@ -206,7 +320,7 @@ where
         SampleResponse<MetadataPackage, <KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetMetadata)>>::Error>,
     > {
-        let uris = self.uris.read().await.clone();
+        let uris = self.trusted_uris().await;
         let uris = uris
             .into_iter()
             .map(|uri| append_path(uri, &format!("/keys/{}", address)))
@@ -215,10 +329,25 @@ where
         let sample_request = SampleRequest {
             request: GetMetadata,
             uris,
+            deadline: self.sample_deadline(),
         };
 
-        let responses = self.inner_client.clone().oneshot(sample_request).await?;
-        let sample_response = SampleResponse::select(responses, select_auth_wrapper);
+        let mut results = self.inner_client.clone().oneshot(sample_request).await?;
+
+        // Report misbehaviour, discarding metadata from the future so that it is not selected.
+        // Metadata is signed by users rather than the keyserver, so it isn't penalised for it.
+        if let Some(policy) = &self.policy {
+            results.responses.retain(|(uri, result)| match result {
+                Ok(package) => !policy.is_skewed(package.metadata.timestamp),
+                Err(err) => {
+                    if let Some(violation) = err.violation() {
+                        policy.record(uri, violation);
+                    }
+                    true
+                }
+            });
+        }
+        let sample_response = SampleResponse::select(results, select_auth_wrapper);
 
         Ok(sample_response)
     }
@@ -230,7 +359,7 @@ where
         AggregateResponse<Peers, <KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
     > {
-        let uris = self.uris.read().await.clone();
+        let uris = self.trusted_uris().await;
         let uris = uris
             .into_iter()
             .map(|uri| append_path(uri, "/peers"))
@@ -238,69 +367,125 @@ where
         let sample_request = SampleRequest {
             uris,
             request: GetPeers,
+            deadline: self.sample_deadline(),
         };
-        let responses = self.inner_client.clone().oneshot(sample_request).await?;
+        let results = self.inner_client.clone().oneshot(sample_request).await?;
 
-        let aggregate_response = AggregateResponse::aggregate(responses, aggregate_peers);
+        let aggregate_response = AggregateResponse::aggregate(results, aggregate_peers);
 
         Ok(aggregate_response)
     }
 
-    /// Crawl peers.
+    /// Crawl peers, probing each keyserver found.
+    ///
+    /// The deadline, if any, bounds the whole crawl rather than each round of probes.
+    ///
+    /// Each [`Peer`] carries the latency, last-seen and version measured when probing it. Peers
+    /// which could not be probed carry only the version advertised by other keyservers, as the
+    /// latency and last-seen they advertise could be forged to promote a peer in the ranking.
     #[allow(clippy::mutable_key_type)]
     pub async fn crawl_peers(
         &self,
     ) -> Result<
-        AggregateResponse<Peers, <KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
-        SampleError<<KeyserverClient<S> as Service<(Uri, GetPeers)>>::Error>,
+        AggregateResponse<Peers, <KeyserverClient<S> as Service<(Uri, ProbePeers)>>::Error>,
+        SampleError<<KeyserverClient<S> as Service<(Uri, ProbePeers)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
+        let read_uris = self.trusted_uris().await;
         let mut found_uris: HashSet<_> = read_uris.iter().cloned().collect();
 
         let mut total: HashSet<_> = read_uris.iter().cloned().collect();
 
+        let mut measured: HashMap<Uri, Peer> = HashMap::new();
+        let mut advertised: HashMap<Uri, Peer> = HashMap::new();
         let mut total_errors = Vec::new();
+        let mut total_pending = Vec::new();
+
+        // The deadline covers the whole crawl
+        let deadline = self.sample_deadline();
         while !found_uris.is_empty() {
             // Get sample
-            let uris = found_uris
+            let probe_uris: HashMap<_, _> = found_uris
                 .drain()
-                .map(|uri| append_path(uri, "/peers"))
+                .map(|uri| (append_path(uri.clone(), "/peers"), uri))
                 .collect();
             let sample_request = SampleRequest {
-                uris,
-                request: GetPeers,
+                uris: probe_uris.keys().cloned().collect(),
+                request: ProbePeers,
+                deadline,
             };
-            let responses: Vec<_> = self.inner_client.clone().oneshot(sample_request).await?;
-
-            let AggregateResponse { response, errors } =
-                AggregateResponse::aggregate(responses, aggregate_peers);
-
-            // Aggregate errors
-            total_errors.extend(errors);
-
-            // Aggregate URIs
-            let mut found_uris: HashSet<_> = response
-                .peers
-                .iter()
-                .filter_map(|peer| peer.url.parse::<Uri>().ok())
+            let results = self.inner_client.clone().oneshot(sample_request).await?;
+            total_pending.extend(results.pending);
+
+            let now = time::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap() // This is safe
+                .as_millis() as i64;
+            for (probe_uri, result) in results.responses {
+                let probe = match result {
+                    Ok(ok) => ok,
+                    Err(err) => {
+                        // Aggregate errors
+                        total_errors.push((probe_uri, err));
+                        continue;
+                    }
+                };
+
+                // Record measurements
+                let uri = probe_uris[&probe_uri].clone();
+                let peer = Peer {
+                    url: uri.to_string(),
+                    latency: probe.latency.as_millis().max(1) as u32,
+                    last_seen: now,
+                    version: probe.version.unwrap_or_default(),
+                };
+                measured.insert(uri, peer);
+
+                // Aggregate advertised peers
+                for peer in probe.peers.peers {
+                    if let Some(policy) = &self.policy {
+                        if !policy.check_timestamp(&probe_uri, peer.last_seen) {
+                            continue;
+                        }
+                    }
+                    if let Ok(uri) = peer.url.parse::<Uri>() {
+                        advertised
+                            .entry(uri)
+                            .and_modify(|existing| existing.merge(&peer))
+                            .or_insert(peer);
+                    }
+                }
+            }
+
+            // Only keep new, trusted URIs
+            let new_uris = advertised
+                .keys()
+                .filter(|uri| !total.contains(*uri))
+                .cloned()
                 .collect();
-
-            // Only keep new URIs
-            found_uris = found_uris.difference(&total).cloned().collect();
-            total = total.union(&found_uris).cloned().collect();
+            found_uris = self.retain_trusted(new_uris).into_iter().collect();
+            total.extend(found_uris.iter().cloned());
         }
 
         let response = Peers {
             peers: total
                 .into_iter()
-                .map(|uri| Peer {
-                    url: uri.to_string(),
+                .map(|uri| {
+                    let mut peer = measured
+                        .remove(&uri)
+                        .unwrap_or_else(|| Peer::new(uri.to_string()));
+                    if let Some(advertised) = advertised.get(&uri) {
+                        if peer.version.is_empty() {
+                            peer.version = advertised.version.clone();
+                        }
+                    }
+                    peer
                 })
                 .collect(),
         };
         Ok(AggregateResponse {
             response,
             errors: total_errors,
+            pending: total_pending,
         })
     }
 
@@ -315,7 +500,7 @@ where
         AggregateResponse<(), <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
+        let read_uris = self.trusted_uris().await;
         let uris = uniform_random_sampler(&read_uris, sample_size)
             .into_iter()
             .map(|uri| append_path(uri, &format!("/keys/{}", address)))
@@ -328,11 +513,16 @@ where
         let request = PutRawAuthWrapper {
             token,
             raw_auth_wrapper,
+            idempotency_key: None,
+        };
+        let sample_request = SampleRequest {
+            uris,
+            request,
+            deadline: self.sample_deadline(),
         };
-        let sample_request = SampleRequest { uris, request };
-        let responses = self.inner_client.clone().call(sample_request).await?;
+        let results = self.inner_client.clone().call(sample_request).await?;
 
-        Ok(AggregateResponse::aggregate(responses, |_| ()))
+        Ok(AggregateResponse::aggregate(results, |_| ()))
     }
 
     /// Perform a uniform broadcast of raw metadata over keyservers and select the latest.
@@ -346,7 +536,7 @@ where
         AggregateResponse<(), <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
         SampleError<<KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Error>,
     > {
-        let read_uris = self.uris.read().await;
+        let read_uris = self.trusted_uris().await;
         let uris = uniform_random_sampler(&read_uris, sample_size)
             .into_iter()
             .map(|uri| append_path(uri, &format!("/keys/{}", address)))
@@ -355,10 +545,119 @@ where
         let request = PutRawAuthWrapper {
             token,
             raw_auth_wrapper,
+            idempotency_key: None,
         };
-        let sample_request = SampleRequest { uris, request };
-        let responses = self.inner_client.clone().call(sample_request).await?;
+        let sample_request = SampleRequest {
+            uris,
+            request,
+            deadline: self.sample_deadline(),
+        };
+        let results = self.inner_client.clone().call(sample_request).await?;
+
+        Ok(AggregateResponse::aggregate(results, |_| ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use super::*;
+
+    /// Responds with a single peer, never responding in time when the host is `slow`. The host
+    /// `gossip` advertises `slow` with forged quality data.
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let slow = request.uri().host() == Some("slow");
+            let gossip = request.uri().host() == Some("gossip");
+            Box::pin(async move {
+                if slow {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                let peer = if gossip {
+                    Peer {
+                        url: "http://slow".to_string(),
+                        latency: 1,
+                        last_seen: i64::MAX,
+                        version: "0.2.0".to_string(),
+                    }
+                } else {
+                    Peer::new("http://fast".to_string())
+                };
+                let peers = Peers { peers: vec![peer] };
+                let mut body = Vec::with_capacity(peers.encoded_len());
+                peers.encode(&mut body).unwrap();
+                Ok(Response::new(Body::from(body)))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_results_at_deadline() {
+        let uris = vec![
+            "http://fast".parse().unwrap(),
+            "http://slow".parse().unwrap(),
+        ];
+        let manager = KeyserverManager::from_service(MockKeyserver, uris)
+            .with_deadline(Duration::from_millis(50));
+        let aggregate = manager.collect_peers().await.unwrap();
+        assert_eq!(aggregate.response.peers.len(), 1);
+        assert!(aggregate.errors.is_empty());
+        assert_eq!(
+            aggregate.pending,
+            vec![Uri::from_static("http://slow/peers")]
+        );
+
+        // Nothing found in time is not a failure of the sample
+        let manager =
+            KeyserverManager::from_service(MockKeyserver, vec!["http://slow".parse().unwrap()])
+                .with_deadline(Duration::from_millis(50));
+        let aggregate = manager.collect_peers().await.unwrap();
+        assert!(aggregate.response.peers.is_empty());
+        assert_eq!(aggregate.pending.len(), 1);
+    }
 
-        Ok(AggregateResponse::aggregate(responses, |_| ()))
+    #[tokio::test]
+    async fn crawl_ignores_advertised_quality() {
+        let manager =
+            KeyserverManager::from_service(MockKeyserver, vec!["http://gossip".parse().unwrap()])
+                .with_deadline(Duration::from_millis(50));
+        let aggregate = manager.crawl_peers().await.unwrap();
+        assert_eq!(
+            aggregate.pending,
+            vec![Uri::from_static("http://slow/peers")]
+        );
+
+        let mut peers = aggregate.response.peers;
+        peers.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].url, "http://gossip/");
+        assert!(peers[0].latency().is_some());
+
+        // The unprobed peer keeps only the advertised version
+        assert_eq!(peers[1].url, "http://slow/");
+        assert_eq!(peers[1].latency(), None);
+        assert_eq!(peers[1].last_seen(), None);
+        assert_eq!(peers[1].version(), Some("0.2.0"));
+        assert_eq!(
+            ranked_sampler(&peers, 1),
+            vec![Uri::from_static("http://gossip/")]
+        );
     }
 }
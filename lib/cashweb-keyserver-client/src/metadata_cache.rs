@@ -0,0 +1,416 @@
+//! This module contains the [`MetadataCache`] which caches [`MetadataPackage`]s per keyserver and
+//! address, for as long as the keyserver's `Cache-Control` or `Expires` headers allow.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use hyper::{
+    header::{CACHE_CONTROL, DATE, EXPIRES},
+    HeaderMap, Uri,
+};
+use tower_service::Service;
+
+use crate::{
+    client::services::GetMetadata, time::Instant, KeyserverClient, KeyserverError, MetadataPackage,
+};
+
+/// Default maximum number of [`MetadataPackage`]s held by a [`MetadataCache`].
+pub const DEFAULT_MAX_ENTRIES: usize = 4096;
+
+/// The caching directives of a keyserver response.
+///
+/// Responses without a `max-age` directive or an `Expires` header are not cached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// Duration for which the response is fresh.
+    pub max_age: Option<Duration>,
+    /// Duration, after the response becomes stale, for which it may still be served while it is
+    /// revalidated in the background.
+    pub stale_while_revalidate: Duration,
+    /// Whether the response must not be stored.
+    pub no_store: bool,
+}
+
+impl CachePolicy {
+    /// Parse the `Cache-Control`, `Expires` and `Date` headers of a response received at `now`.
+    ///
+    /// `max-age` takes precedence over `Expires`, `no-cache` forces revalidation on every use and
+    /// unknown directives are ignored.
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Self {
+        let mut policy = CachePolicy::default();
+        let mut no_cache = false;
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(ok) => ok,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let mut parts = directive.splitn(2, '=');
+                let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+                let seconds = parts
+                    .next()
+                    .and_then(|value| value.trim().trim_matches('"').parse().ok())
+                    .map(Duration::from_secs);
+                match (name.as_str(), seconds) {
+                    ("no-store", _) => policy.no_store = true,
+                    ("no-cache", _) => no_cache = true,
+                    ("max-age", Some(seconds)) => policy.max_age = Some(seconds),
+                    ("stale-while-revalidate", Some(seconds)) => {
+                        policy.stale_while_revalidate = seconds
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if policy.max_age.is_none() {
+            policy.max_age = headers.get(EXPIRES).map(|expires| {
+                // An invalid date, such as "0", means the response has already expired
+                let expires = match expires.to_str().ok().map(httpdate::parse_http_date) {
+                    Some(Ok(expires)) => expires,
+                    _ => return Duration::default(),
+                };
+                let date = headers
+                    .get(DATE)
+                    .and_then(|date| date.to_str().ok())
+                    .and_then(|date| httpdate::parse_http_date(date).ok())
+                    .unwrap_or(now);
+                expires.duration_since(date).unwrap_or_default()
+            });
+        }
+        if no_cache {
+            policy.max_age = Some(Duration::default());
+            policy.stale_while_revalidate = Duration::default();
+        }
+        policy
+    }
+
+    /// Whether the response may be stored.
+    pub fn is_storable(&self) -> bool {
+        !self.no_store && self.max_age.is_some()
+    }
+}
+
+/// Outcome of a [`MetadataCache`] lookup.
+#[derive(Clone, Debug)]
+pub enum Lookup {
+    /// The cached package is fresh.
+    Fresh(MetadataPackage),
+    /// The cached package is stale but may be served while it is revalidated. `revalidate` is set
+    /// for exactly one caller, which is expected to refresh the entry.
+    Stale {
+        /// The stale package.
+        package: MetadataPackage,
+        /// Whether the caller should revalidate the entry.
+        revalidate: bool,
+    },
+    /// No usable package is cached.
+    Miss,
+}
+
+#[derive(Debug)]
+struct Entry {
+    package: MetadataPackage,
+    fresh_until: Instant,
+    stale_until: Instant,
+    revalidating: bool,
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: RwLock<HashMap<(String, String), Entry>>,
+    max_entries: usize,
+}
+
+/// Caches [`MetadataPackage`]s per keyserver URL and address, following the [`CachePolicy`] of
+/// each response.
+///
+/// Cloning the cache is cheap and the clones share the same entries.
+#[derive(Clone, Debug)]
+pub struct MetadataCache(Arc<Inner>);
+
+impl Default for MetadataCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl MetadataCache {
+    /// Create an empty [`MetadataCache`] holding at most `max_entries` packages.
+    pub fn new(max_entries: usize) -> Self {
+        MetadataCache(Arc::new(Inner {
+            entries: Default::default(),
+            max_entries,
+        }))
+    }
+
+    /// Look up the package for the keyserver and address.
+    pub fn lookup(&self, keyserver_url: &str, address: &str) -> Lookup {
+        self.lookup_at(keyserver_url, address, Instant::now())
+    }
+
+    fn lookup_at(&self, keyserver_url: &str, address: &str, now: Instant) -> Lookup {
+        let mut entries = self.0.entries.write().unwrap();
+        let key = (keyserver_url.to_string(), address.to_string());
+        let entry = match entries.get_mut(&key) {
+            Some(some) => some,
+            None => return Lookup::Miss,
+        };
+        if now < entry.fresh_until {
+            return Lookup::Fresh(entry.package.clone());
+        }
+        if now < entry.stale_until {
+            let revalidate = !entry.revalidating;
+            entry.revalidating = true;
+            return Lookup::Stale {
+                package: entry.package.clone(),
+                revalidate,
+            };
+        }
+        entries.remove(&key);
+        Lookup::Miss
+    }
+
+    /// Cache the package for the keyserver and address, if its [`CachePolicy`] allows.
+    ///
+    /// Otherwise, any package already cached for the keyserver and address is removed.
+    pub fn insert(&self, keyserver_url: &str, address: &str, package: MetadataPackage) {
+        self.insert_at(keyserver_url, address, package, Instant::now())
+    }
+
+    fn insert_at(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        package: MetadataPackage,
+        now: Instant,
+    ) {
+        let key = (keyserver_url.to_string(), address.to_string());
+        let mut entries = self.0.entries.write().unwrap();
+        let max_age = match package.cache_policy.max_age {
+            Some(some) if package.cache_policy.is_storable() => some,
+            _ => {
+                entries.remove(&key);
+                return;
+            }
+        };
+        if entries.len() >= self.0.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, entry| now < entry.stale_until);
+            if entries.len() >= self.0.max_entries {
+                return;
+            }
+        }
+        let fresh_until = now + max_age;
+        let entry = Entry {
+            fresh_until,
+            stale_until: fresh_until + package.cache_policy.stale_while_revalidate,
+            package,
+            revalidating: false,
+        };
+        entries.insert(key, entry);
+    }
+
+    /// Allow a stale entry to be revalidated again, after a failed revalidation.
+    fn revalidation_failed(&self, keyserver_url: &str, address: &str) {
+        let key = (keyserver_url.to_string(), address.to_string());
+        if let Some(entry) = self.0.entries.write().unwrap().get_mut(&key) {
+            entry.revalidating = false;
+        }
+    }
+
+    /// Remove the package for the keyserver and address.
+    pub fn invalidate(&self, keyserver_url: &str, address: &str) -> Option<MetadataPackage> {
+        self.0
+            .entries
+            .write()
+            .unwrap()
+            .remove(&(keyserver_url.to_string(), address.to_string()))
+            .map(|entry| entry.package)
+    }
+}
+
+impl<S> KeyserverClient<S>
+where
+    Self: Service<(Uri, GetMetadata), Response = MetadataPackage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetMetadata)>>::Error: fmt::Display + std::error::Error + Send,
+    <Self as Service<(Uri, GetMetadata)>>::Future: Send + 'static,
+{
+    /// Get [`AddressMetadata`] from a keyserver, using the [`MetadataCache`].
+    ///
+    /// A fresh cached package is returned without contacting the keyserver. Within the
+    /// `stale-while-revalidate` window a stale package is returned immediately, while a
+    /// background task refreshes it.
+    ///
+    /// [`AddressMetadata`]: cashweb_keyserver::AddressMetadata
+    pub async fn get_metadata_cached(
+        &self,
+        cache: &MetadataCache,
+        keyserver_url: &str,
+        address: &str,
+    ) -> Result<MetadataPackage, KeyserverError<<Self as Service<(Uri, GetMetadata)>>::Error>> {
+        match cache.lookup(keyserver_url, address) {
+            Lookup::Fresh(package) => Ok(package),
+            Lookup::Stale {
+                package,
+                revalidate,
+            } => {
+                if revalidate {
+                    let (client, cache) = (self.clone(), cache.clone());
+                    let (keyserver_url, address) = (keyserver_url.to_string(), address.to_string());
+                    let revalidation = async move {
+                        match client.get_metadata(&keyserver_url, &address).await {
+                            Ok(package) => cache.insert(&keyserver_url, &address, package),
+                            Err(_) => cache.revalidation_failed(&keyserver_url, &address),
+                        }
+                    };
+                    #[cfg(any(feature = "native", not(feature = "wasm")))]
+                    tokio::spawn(revalidation);
+                    #[cfg(all(feature = "wasm", not(feature = "native")))]
+                    wasm_bindgen_futures::spawn_local(revalidation);
+                }
+                Ok(package)
+            }
+            Lookup::Miss => {
+                let package = self.get_metadata(keyserver_url, address).await?;
+                cache.insert(keyserver_url, address, package.clone());
+                Ok(package)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_keyserver::AddressMetadata;
+    use hyper::header::HeaderValue;
+    use secp256k1::{
+        key::{PublicKey, SecretKey},
+        Secp256k1,
+    };
+
+    use super::*;
+
+    fn policy(headers: &[(&str, &str)]) -> CachePolicy {
+        let headers: HeaderMap = headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect();
+        CachePolicy::from_headers(&headers, httpdate::parse_http_date(NOW).unwrap())
+    }
+
+    const NOW: &str = "Wed, 21 Oct 2026 07:28:00 GMT";
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(policy(&[]), CachePolicy::default());
+        assert!(!policy(&[]).is_storable());
+
+        let parsed = policy(&[(
+            "cache-control",
+            "public, Max-Age=60, stale-while-revalidate=30",
+        )]);
+        assert_eq!(parsed.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(parsed.stale_while_revalidate, Duration::from_secs(30));
+
+        // Expires is relative to Date, if given, and overridden by max-age
+        let expires = ("expires", "Wed, 21 Oct 2026 07:30:00 GMT");
+        assert_eq!(policy(&[expires]).max_age, Some(Duration::from_secs(120)));
+        let date = ("date", "Wed, 21 Oct 2026 07:29:00 GMT");
+        assert_eq!(
+            policy(&[expires, date]).max_age,
+            Some(Duration::from_secs(60))
+        );
+        let max_age = ("cache-control", "max-age=5");
+        assert_eq!(
+            policy(&[expires, max_age]).max_age,
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            policy(&[("expires", "0")]).max_age,
+            Some(Duration::default())
+        );
+
+        let parsed = policy(&[(
+            "cache-control",
+            "no-cache, max-age=60, stale-while-revalidate=5",
+        )]);
+        assert_eq!(parsed.max_age, Some(Duration::default()));
+        assert_eq!(parsed.stale_while_revalidate, Duration::default());
+        assert!(!policy(&[("cache-control", "no-store, max-age=60")]).is_storable());
+    }
+
+    fn package(max_age: u64, stale_while_revalidate: u64) -> MetadataPackage {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        MetadataPackage {
+            token: "POP token".to_string(),
+            public_key: PublicKey::from_secret_key(&Secp256k1::new(), &secret_key),
+            metadata: AddressMetadata::default(),
+            raw_auth_wrapper: Default::default(),
+            cache_policy: CachePolicy {
+                max_age: Some(Duration::from_secs(max_age)),
+                stale_while_revalidate: Duration::from_secs(stale_while_revalidate),
+                no_store: false,
+            },
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let cache = MetadataCache::new(1);
+        let (url, now) = ("http://keyserver", Instant::now());
+        cache.insert_at(url, "alice", package(10, 5), now);
+
+        assert!(matches!(
+            cache.lookup_at(url, "alice", now),
+            Lookup::Fresh(_)
+        ));
+        let stale = now + Duration::from_secs(12);
+        assert!(matches!(
+            cache.lookup_at(url, "alice", stale),
+            Lookup::Stale {
+                revalidate: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            cache.lookup_at(url, "alice", stale),
+            Lookup::Stale {
+                revalidate: false,
+                ..
+            }
+        ));
+        cache.revalidation_failed(url, "alice");
+        assert!(matches!(
+            cache.lookup_at(url, "alice", stale),
+            Lookup::Stale {
+                revalidate: true,
+                ..
+            }
+        ));
+
+        // The cache is full until the entry expires
+        cache.insert_at(url, "bob", package(10, 0), now);
+        assert!(matches!(cache.lookup_at(url, "bob", now), Lookup::Miss));
+        let expired = now + Duration::from_secs(15);
+        cache.insert_at(url, "bob", package(10, 0), expired);
+        assert!(matches!(
+            cache.lookup_at(url, "alice", expired),
+            Lookup::Miss
+        ));
+        assert!(matches!(
+            cache.lookup_at(url, "bob", expired),
+            Lookup::Fresh(_)
+        ));
+
+        // Unstorable responses evict the entry
+        let mut unstorable = package(10, 0);
+        unstorable.cache_policy.no_store = true;
+        cache.insert_at(url, "bob", unstorable, expired);
+        assert!(matches!(cache.lookup_at(url, "bob", expired), Lookup::Miss));
+    }
+}
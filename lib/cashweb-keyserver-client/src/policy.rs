@@ -0,0 +1,319 @@
+//! This module contains the [`PeerPolicy`], which scores keyservers by their misbehaviour and
+//! temporarily bans those exceeding a threshold.
+//!
+//! Operators define [`PolicyRules`], giving the penalty of each [`Violation`] and the limits beyond
+//! which a response is considered to violate them. The crawler and sampler of the
+//! [`KeyserverManager`] and the [`Replicator`] report violations to a shared policy and skip banned
+//! keyservers, so misbehaviour observed by one is acted upon by all.
+//!
+//! Keyservers are identified by the authority of their URI, so that requests to different paths
+//! of the same keyserver share a score. Scores decay over time, so that occasional faults do not
+//! accumulate into a ban.
+//!
+//! [`KeyserverManager`]: crate::KeyserverManager
+//! [`Replicator`]: crate::replication::Replicator
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use cashweb_keyserver::compression::CompressionError;
+use hyper::Uri;
+
+#[cfg(feature = "native")]
+use crate::replication::InvalidEntry;
+use crate::{
+    services::GetMetadataError,
+    time::{self, Instant},
+};
+
+/// Default maximum difference, in milliseconds, between a timestamp given by a keyserver and the
+/// local clock.
+pub const DEFAULT_MAX_CLOCK_SKEW: i64 = 5 * 60 * 1_000;
+
+/// Default score at which a keyserver is banned.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+
+/// Default duration of a ban.
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Misbehaviour of a keyserver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Violation {
+    /// A timestamp given by the keyserver lay too far in the future.
+    ClockSkew,
+    /// The keyserver served a signature which failed to parse or verify.
+    SignatureFailure,
+    /// The keyserver served a response exceeding the size limit.
+    OversizeResponse,
+}
+
+impl<E: fmt::Debug + fmt::Display> GetMetadataError<E> {
+    /// The [`Violation`] indicated by the error, if any.
+    pub fn violation(&self) -> Option<Violation> {
+        match self {
+            Self::AuthWrapperParse(_) | Self::AuthWrapperVerify(_) => {
+                Some(Violation::SignatureFailure)
+            }
+            Self::Decompress(CompressionError::TooLarge(_)) => Some(Violation::OversizeResponse),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl InvalidEntry {
+    /// The [`Violation`] indicated by the invalid entry, if any.
+    pub fn violation(&self) -> Option<Violation> {
+        match self {
+            Self::AuthWrapperParse(_)
+            | Self::AuthWrapperVerify(_)
+            | Self::AddressMismatch
+            | Self::TimestampMismatch { .. } => Some(Violation::SignatureFailure),
+            _ => None,
+        }
+    }
+}
+
+/// Rules defining what a [`PeerPolicy`] considers misbehaviour and how it is punished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyRules {
+    /// Maximum difference, in milliseconds, between a timestamp given by a keyserver and the
+    /// local clock.
+    pub max_clock_skew: i64,
+    /// Penalty of a [`Violation::ClockSkew`].
+    pub clock_skew_penalty: u32,
+    /// Penalty of a [`Violation::SignatureFailure`].
+    pub signature_failure_penalty: u32,
+    /// Penalty of a [`Violation::OversizeResponse`].
+    pub oversize_response_penalty: u32,
+    /// Score at which a keyserver is banned.
+    pub ban_threshold: u32,
+    /// Duration of a ban.
+    pub ban_duration: Duration,
+    /// Points deducted from a score each minute.
+    pub decay_per_minute: u32,
+}
+
+impl Default for PolicyRules {
+    fn default() -> Self {
+        Self {
+            max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+            clock_skew_penalty: 20,
+            signature_failure_penalty: 50,
+            oversize_response_penalty: 25,
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+            decay_per_minute: 1,
+        }
+    }
+}
+
+impl PolicyRules {
+    /// The penalty of the violation.
+    pub fn penalty(&self, violation: Violation) -> u32 {
+        match violation {
+            Violation::ClockSkew => self.clock_skew_penalty,
+            Violation::SignatureFailure => self.signature_failure_penalty,
+            Violation::OversizeResponse => self.oversize_response_penalty,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeerState {
+    score: u32,
+    updated: Instant,
+    banned_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    rules: PolicyRules,
+    peers: Mutex<HashMap<String, PeerState>>,
+}
+
+/// Scores keyservers by their misbehaviour and temporarily bans those exceeding the threshold of
+/// the [`PolicyRules`].
+///
+/// Cloning the policy is cheap and the clones share the same scores.
+#[derive(Clone, Debug)]
+pub struct PeerPolicy(Arc<Inner>);
+
+impl Default for PeerPolicy {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+/// The key identifying the keyserver, its authority, or the whole URI if it has none.
+fn peer_key(uri: &Uri) -> String {
+    match uri.authority() {
+        Some(authority) => authority.as_str().to_string(),
+        None => uri.to_string(),
+    }
+}
+
+impl PeerPolicy {
+    /// Create a policy enforcing the rules.
+    pub fn new(rules: PolicyRules) -> Self {
+        PeerPolicy(Arc::new(Inner {
+            rules,
+            peers: Default::default(),
+        }))
+    }
+
+    /// The rules enforced.
+    pub fn rules(&self) -> &PolicyRules {
+        &self.0.rules
+    }
+
+    /// Record a violation by the keyserver, returning the time until which it is banned, if it
+    /// is.
+    pub fn record(&self, peer: &Uri, violation: Violation) -> Option<Instant> {
+        self.record_at(peer, violation, Instant::now())
+    }
+
+    fn record_at(&self, peer: &Uri, violation: Violation, now: Instant) -> Option<Instant> {
+        let rules = &self.0.rules;
+        let mut peers = self.0.peers.lock().unwrap();
+        let state = peers.entry(peer_key(peer)).or_insert(PeerState {
+            score: 0,
+            updated: now,
+            banned_until: None,
+        });
+        if let Some(banned_until) = state.banned_until.filter(|until| now < *until) {
+            return Some(banned_until);
+        }
+        state.score = decayed(rules, state, now).saturating_add(rules.penalty(violation));
+        state.updated = now;
+        if state.score >= rules.ban_threshold {
+            state.score = 0;
+            state.banned_until = Some(now + rules.ban_duration);
+        }
+        state.banned_until.filter(|until| now < *until)
+    }
+
+    /// Check a timestamp, in milliseconds, asserted by the keyserver itself against the local
+    /// clock, recording a [`Violation::ClockSkew`] if it lies too far in the future.
+    ///
+    /// Timestamps of user-signed data relayed by the keyserver should be checked using
+    /// [`PeerPolicy::is_skewed`] instead, as the keyserver can't be held responsible for them.
+    ///
+    /// Returns whether the timestamp is acceptable.
+    pub fn check_timestamp(&self, peer: &Uri, timestamp: i64) -> bool {
+        if !self.is_skewed(timestamp) {
+            return true;
+        }
+        self.record(peer, Violation::ClockSkew);
+        false
+    }
+
+    /// Whether a timestamp, in milliseconds, lies too far in the future of the local clock.
+    pub fn is_skewed(&self, timestamp: i64) -> bool {
+        let now = time::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap() // This is safe
+            .as_millis() as i64;
+        timestamp > now.saturating_add(self.0.rules.max_clock_skew)
+    }
+
+    /// The current score of the keyserver.
+    pub fn score(&self, peer: &Uri) -> u32 {
+        let peers = self.0.peers.lock().unwrap();
+        peers
+            .get(&peer_key(peer))
+            .map(|state| decayed(&self.0.rules, state, Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Whether the keyserver is currently banned.
+    pub fn is_banned(&self, peer: &Uri) -> bool {
+        self.is_banned_at(peer, Instant::now())
+    }
+
+    fn is_banned_at(&self, peer: &Uri, now: Instant) -> bool {
+        let peers = self.0.peers.lock().unwrap();
+        matches!(
+            peers.get(&peer_key(peer)).and_then(|state| state.banned_until),
+            Some(banned_until) if now < banned_until
+        )
+    }
+
+    /// Remove the banned keyservers.
+    pub fn retain_allowed(&self, uris: &mut Vec<Uri>) {
+        let now = Instant::now();
+        uris.retain(|uri| !self.is_banned_at(uri, now));
+    }
+
+    /// Lift any ban on, and reset the score of, the keyserver.
+    pub fn forgive(&self, peer: &Uri) {
+        self.0.peers.lock().unwrap().remove(&peer_key(peer));
+    }
+}
+
+/// The score of the peer after decaying until `now`.
+fn decayed(rules: &PolicyRules, state: &PeerState, now: Instant) -> u32 {
+    let minutes = now.saturating_duration_since(state.updated).as_secs() / 60;
+    let decay = (minutes as u32).saturating_mul(rules.decay_per_minute);
+    state.score.saturating_sub(decay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban() {
+        let policy = PeerPolicy::new(PolicyRules {
+            ban_threshold: 60,
+            ..Default::default()
+        });
+        let peer: Uri = "http://peer:8080".parse().unwrap();
+        let same_peer: Uri = "http://peer:8080/keys/alice".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(policy.record_at(&peer, Violation::ClockSkew, now), None);
+        assert_eq!(policy.score(&same_peer), 20);
+
+        // Scores decay over time
+        let later = now + Duration::from_secs(10 * 60);
+        assert_eq!(
+            policy.record_at(&same_peer, Violation::ClockSkew, later),
+            None
+        );
+        assert_eq!(policy.0.peers.lock().unwrap()["peer:8080"].score, 30);
+
+        let banned_until = policy
+            .record_at(&peer, Violation::SignatureFailure, later)
+            .unwrap();
+        assert_eq!(banned_until, later + DEFAULT_BAN_DURATION);
+        assert!(policy.is_banned_at(&same_peer, later));
+        assert!(!policy.is_banned_at(&peer, banned_until));
+
+        let mut uris = vec![peer.clone(), "http://other".parse().unwrap()];
+        policy.retain_allowed(&mut uris);
+        assert_eq!(uris, vec!["http://other".parse::<Uri>().unwrap()]);
+
+        policy.forgive(&peer);
+        assert!(!policy.is_banned(&peer));
+    }
+
+    #[test]
+    fn check_timestamp() {
+        let policy = PeerPolicy::default();
+        let peer: Uri = "http://peer".parse().unwrap();
+
+        assert!(policy.check_timestamp(&peer, 0));
+        assert!(!policy.check_timestamp(&peer, i64::MAX));
+        assert_eq!(policy.score(&peer), 20);
+
+        // Checking without a peer records nothing
+        assert!(!policy.is_skewed(0));
+        assert!(policy.is_skewed(i64::MAX));
+        assert_eq!(policy.score(&peer), 20);
+    }
+}
@@ -0,0 +1,709 @@
+//! This module contains the [`Replicator`] which pulls newer metadata from peer keyservers into
+//! a local [`MetadataStore`].
+//!
+//! Each peer is paged through in ascending order of timestamp, from a per-peer cursor. Entries
+//! are verified before being written and conflicts are resolved in favour of the higher
+//! timestamp, so that a set of keyservers converges on the latest metadata for each address.
+//!
+//! With a [`Lifecycle`], each pass is tracked as in flight and stops between pages once shutdown
+//! is requested. The cursor is saved after every page, so the next pass resumes where it stopped.
+//! With an [`EventBus`], a [`MetadataUpdated`] event is published for every accepted entry.
+//! With a [`PeerPolicy`], entries failing verification and timestamps from the future are
+//! reported as violations, and banned peers are skipped.
+//!
+//! With a [`DynTokenScheme`], the POP token of each entry is validated before it is written.
+//! Keyservers only serve tokens to peers which sign their requests, so replicating with a token
+//! scheme requires a signing key trusted by the peer.
+
+use std::{collections::HashMap, error, fmt, sync::Arc};
+
+use cashweb_auth_wrapper::{AuthWrapper, ParseError, VerifyError};
+use cashweb_keyserver::{
+    namespace::{metadata_key, Namespace, NamespaceError, OutsideNamespace},
+    store::{MetadataStore, StoredMetadata},
+    AddressMetadata, MetadataEntry, MetadataPage,
+};
+use cashweb_lifecycle::{
+    bus::{EventBus, MetadataUpdated, Origin},
+    Lifecycle,
+};
+use cashweb_token::schemes::{DynTokenScheme, ErrorKind};
+use hyper::Uri;
+use prost::Message as _;
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tower_service::Service;
+
+use crate::{policy::PeerPolicy, services::GetMetadataSince, KeyserverClient, KeyserverError};
+
+/// Default maximum number of entries requested per page.
+pub const DEFAULT_PAGE_SIZE: usize = 256;
+
+/// Error associated with verifying a replicated [`MetadataEntry`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidEntry {
+    /// Error while decoding the [`AuthWrapper`].
+    #[error("authwrapper decoding failure: {0}")]
+    AuthWrapperDecode(prost::DecodeError),
+    /// Error while parsing the [`AuthWrapper`].
+    #[error("authwrapper parsing failure: {0}")]
+    AuthWrapperParse(ParseError),
+    /// Error while verifying the [`AuthWrapper`].
+    #[error("authwrapper verification failure: {0}")]
+    AuthWrapperVerify(VerifyError),
+    /// Error while decoding the [`AddressMetadata`].
+    #[error("metadata decoding failure: {0}")]
+    MetadataDecode(prost::DecodeError),
+    /// The timestamp of the entry differed from that of the signed [`AddressMetadata`].
+    #[error("timestamp mismatch: entry {entry}, metadata {metadata}")]
+    TimestampMismatch {
+        /// Timestamp of the entry.
+        entry: i64,
+        /// Timestamp of the signed metadata.
+        metadata: i64,
+    },
+    /// The namespace of the entry was invalid.
+    #[error("invalid namespace: {0}")]
+    Namespace(NamespaceError),
+    /// The signed [`AddressMetadata`] contained entries outside of the namespace.
+    #[error(transparent)]
+    OutsideNamespace(OutsideNamespace),
+    /// The public key of the [`AuthWrapper`] did not hash to the address.
+    #[error("public key does not match the address")]
+    AddressMismatch,
+    /// The POP token failed validation.
+    #[error("token validation failure: {0:?}")]
+    Token(ErrorKind),
+}
+
+/// Error associated with replicating from a peer.
+#[derive(Debug, Error)]
+pub enum ReplicationError<E: fmt::Display + error::Error + 'static, M: fmt::Debug + fmt::Display> {
+    /// Failed to fetch a page from the peer.
+    #[error("failed to fetch page: {0}")]
+    Fetch(KeyserverError<E>),
+    /// Failed to read from, or write to, the local store.
+    #[error("store failure: {0}")]
+    Store(M),
+    /// The token scheme was unable to validate a token, for example as bitcoind was unavailable.
+    #[error("token validation unavailable")]
+    TokenUnavailable,
+}
+
+/// Summary of a replication pass over a peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplicationReport {
+    /// Number of entries written to the local store.
+    pub accepted: usize,
+    /// Number of entries discarded as the local store held metadata at least as recent.
+    pub stale: usize,
+    /// Number of entries discarded as they failed verification.
+    pub invalid: usize,
+}
+
+/// Verify the [`MetadataEntry`] and convert it to [`StoredMetadata`].
+pub fn verify_entry(entry: &MetadataEntry) -> Result<StoredMetadata, InvalidEntry> {
+    let auth_wrapper = AuthWrapper::decode(entry.raw_auth_wrapper.as_slice())
+        .map_err(InvalidEntry::AuthWrapperDecode)?;
+    let pub_key_digest = Ripemd160::digest(digest(&SHA256, &auth_wrapper.public_key).as_ref());
+    if pub_key_digest[..] != entry.address[..] {
+        return Err(InvalidEntry::AddressMismatch);
+    }
+    let parsed_auth_wrapper = auth_wrapper
+        .parse()
+        .map_err(InvalidEntry::AuthWrapperParse)?;
+    parsed_auth_wrapper
+        .verify()
+        .map_err(InvalidEntry::AuthWrapperVerify)?;
+    let metadata = AddressMetadata::decode(parsed_auth_wrapper.payload.as_slice())
+        .map_err(InvalidEntry::MetadataDecode)?;
+    if metadata.timestamp != entry.timestamp {
+        return Err(InvalidEntry::TimestampMismatch {
+            entry: entry.timestamp,
+            metadata: metadata.timestamp,
+        });
+    }
+    if !entry.namespace.is_empty() {
+        Namespace::new(entry.namespace.as_str())
+            .map_err(InvalidEntry::Namespace)?
+            .check(&metadata)
+            .map_err(InvalidEntry::OutsideNamespace)?;
+    }
+    Ok(StoredMetadata {
+        raw_auth_wrapper: entry.raw_auth_wrapper.clone(),
+        token: entry.token.clone(),
+        timestamp: metadata.timestamp,
+        namespace: entry.namespace.clone(),
+    })
+}
+
+/// Validate the POP token of a [`MetadataEntry`] which passed [`verify_entry`].
+///
+/// The token must commit to the SHA256 digest of the public key of the [`AuthWrapper`] and the
+/// digest of its payload.
+pub async fn verify_token(
+    entry: &MetadataEntry,
+    token_scheme: &DynTokenScheme,
+) -> Result<(), InvalidEntry> {
+    let auth_wrapper = AuthWrapper::decode(entry.raw_auth_wrapper.as_slice())
+        .map_err(InvalidEntry::AuthWrapperDecode)?;
+    let pub_key_hash = digest(&SHA256, &auth_wrapper.public_key);
+    let metadata_hash = if auth_wrapper.payload_digest.len() == 32 {
+        auth_wrapper.payload_digest
+    } else {
+        digest(&SHA256, &auth_wrapper.payload).as_ref().to_vec()
+    };
+    let data = [pub_key_hash.as_ref(), &metadata_hash].concat();
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    let token = base64::encode_config(&entry.token, url_safe_config);
+    token_scheme
+        .validate(&data, &token)
+        .await
+        .map_err(|err| InvalidEntry::Token(err.kind()))
+}
+
+/// Pulls newer metadata from peer keyservers into a local [`MetadataStore`].
+pub struct Replicator<S, M> {
+    client: KeyserverClient<S>,
+    store: M,
+    page_size: usize,
+    cursors: RwLock<HashMap<Uri, i64>>,
+    lifecycle: Option<Lifecycle>,
+    events: Option<EventBus>,
+    policy: Option<PeerPolicy>,
+    token_scheme: Option<Arc<DynTokenScheme>>,
+}
+
+impl<S: fmt::Debug, M: fmt::Debug> fmt::Debug for Replicator<S, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replicator")
+            .field("client", &self.client)
+            .field("store", &self.store)
+            .field("page_size", &self.page_size)
+            .field("cursors", &self.cursors)
+            .field("lifecycle", &self.lifecycle)
+            .field("events", &self.events)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl<S, M> Replicator<S, M> {
+    /// Create a new replicator writing to the store.
+    pub fn new(client: KeyserverClient<S>, store: M) -> Self {
+        Self {
+            client,
+            store,
+            page_size: DEFAULT_PAGE_SIZE,
+            cursors: Default::default(),
+            lifecycle: None,
+            events: None,
+            policy: None,
+            token_scheme: None,
+        }
+    }
+
+    /// Set the maximum number of entries requested per page.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Track passes with the [`Lifecycle`], stopping them once shutdown is requested.
+    pub fn with_lifecycle(mut self, lifecycle: Lifecycle) -> Self {
+        self.lifecycle = Some(lifecycle);
+        self
+    }
+
+    /// Publish a [`MetadataUpdated`] event to the bus for every accepted entry.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Report misbehaviour to the [`PeerPolicy`], and skip the peers it bans.
+    pub fn with_policy(mut self, policy: PeerPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Validate the POP token of each entry using the scheme, discarding those which fail.
+    pub fn with_token_scheme(mut self, token_scheme: Arc<DynTokenScheme>) -> Self {
+        self.token_scheme = Some(token_scheme);
+        self
+    }
+
+    fn is_banned(&self, peer: &Uri) -> bool {
+        self.policy
+            .as_ref()
+            .map(|policy| policy.is_banned(peer))
+            .unwrap_or_default()
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.lifecycle
+            .as_ref()
+            .map(Lifecycle::is_shutting_down)
+            .unwrap_or_default()
+    }
+
+    /// The timestamp from which the next pass over the peer will start.
+    pub async fn cursor(&self, peer: &Uri) -> i64 {
+        self.cursors
+            .read()
+            .await
+            .get(peer)
+            .copied()
+            .unwrap_or(i64::MIN)
+    }
+}
+
+impl<S, M> Replicator<S, M>
+where
+    KeyserverClient<S>: Service<(Uri, GetMetadataSince), Response = MetadataPage>,
+    KeyserverClient<S>: Sync + Clone + Send + 'static,
+    <KeyserverClient<S> as Service<(Uri, GetMetadataSince)>>::Error:
+        fmt::Display + std::error::Error,
+    <KeyserverClient<S> as Service<(Uri, GetMetadataSince)>>::Future: Send + 'static,
+    M: MetadataStore,
+{
+    /// Pull all metadata newer than the cursor from the peer.
+    #[allow(clippy::type_complexity)]
+    pub async fn replicate(
+        &self,
+        peer: &Uri,
+    ) -> Result<
+        ReplicationReport,
+        ReplicationError<<KeyserverClient<S> as Service<(Uri, GetMetadataSince)>>::Error, M::Error>,
+    > {
+        let keyserver_url = peer.to_string();
+        let keyserver_url = keyserver_url.trim_end_matches('/');
+        let mut report = ReplicationReport::default();
+        if self.is_banned(peer) {
+            return Ok(report);
+        }
+        let _guard = match &self.lifecycle {
+            Some(lifecycle) => match lifecycle.begin() {
+                Some(guard) => Some(guard),
+                None => return Ok(report),
+            },
+            None => None,
+        };
+        let mut cursor = self.cursor(peer).await;
+
+        loop {
+            let page = self
+                .client
+                .get_metadata_since(keyserver_url, cursor, self.page_size)
+                .await
+                .map_err(ReplicationError::Fetch)?;
+            let mut full = page.entries.len() >= self.page_size;
+
+            let mut next_cursor = cursor;
+            for entry in page.entries {
+                // Entries are in ascending order of timestamp, so all that follow are also skewed.
+                // They are signed by users rather than the keyserver, so it isn't penalised.
+                if let Some(policy) = &self.policy {
+                    if policy.is_skewed(entry.timestamp) {
+                        full = false;
+                        break;
+                    }
+                }
+                next_cursor = next_cursor.max(entry.timestamp);
+                let metadata = match verify_entry(&entry) {
+                    Ok(ok) => ok,
+                    Err(err) => {
+                        if let (Some(policy), Some(violation)) = (&self.policy, err.violation()) {
+                            policy.record(peer, violation);
+                        }
+                        report.invalid += 1;
+                        continue;
+                    }
+                };
+
+                // Prefer the higher timestamp
+                let key = metadata_key(&entry.address, &entry.namespace);
+                let existing = self
+                    .store
+                    .get(&key)
+                    .await
+                    .map_err(ReplicationError::Store)?;
+                if matches!(existing, Some(existing) if existing.timestamp >= metadata.timestamp) {
+                    report.stale += 1;
+                    continue;
+                }
+                if let Some(token_scheme) = &self.token_scheme {
+                    match verify_token(&entry, token_scheme.as_ref()).await {
+                        Ok(()) => (),
+                        // Retry the page on the next pass, rather than discard valid entries
+                        Err(InvalidEntry::Token(ErrorKind::Internal)) => {
+                            return Err(ReplicationError::TokenUnavailable)
+                        }
+                        Err(_) => {
+                            report.invalid += 1;
+                            continue;
+                        }
+                    }
+                }
+                let timestamp = metadata.timestamp;
+                self.store
+                    .put(&key, metadata)
+                    .await
+                    .map_err(ReplicationError::Store)?;
+                report.accepted += 1;
+                if let Some(events) = &self.events {
+                    events.metadata_updated().publish(MetadataUpdated {
+                        address: entry.address,
+                        namespace: entry.namespace,
+                        timestamp,
+                        raw_auth_wrapper: entry.raw_auth_wrapper,
+                        origin: Origin::Peer(keyserver_url.to_string()),
+                    });
+                }
+            }
+
+            // The start bound is inclusive, so skip past a timestamp filling an entire page
+            if full && next_cursor == cursor {
+                next_cursor = cursor.saturating_add(1);
+            }
+            cursor = next_cursor;
+            self.cursors.write().await.insert(peer.clone(), cursor);
+
+            if !full || self.is_shutting_down() || self.is_banned(peer) {
+                return Ok(report);
+            }
+        }
+    }
+
+    /// Pull all metadata newer than the cursors from each of the peers.
+    #[allow(clippy::type_complexity)]
+    pub async fn replicate_all(
+        &self,
+        peers: &[Uri],
+    ) -> Vec<(
+        Uri,
+        Result<
+            ReplicationReport,
+            ReplicationError<
+                <KeyserverClient<S> as Service<(Uri, GetMetadataSince)>>::Error,
+                M::Error,
+            >,
+        >,
+    )> {
+        let mut results = Vec::with_capacity(peers.len());
+        for peer in peers {
+            if self.is_shutting_down() {
+                break;
+            }
+            results.push((peer.clone(), self.replicate(peer).await));
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use async_trait::async_trait;
+    use cashweb_keyserver::{store::MemoryMetadataStore, Entry};
+    use cashweb_token::schemes::{ErasedScheme, TokenError, TokenScheme};
+    use hyper::{Body, Request, Response};
+    use ring::digest::{digest, SHA256};
+    use secp256k1::{key::SecretKey, PublicKey, Secp256k1};
+
+    use super::*;
+    use crate::policy::PolicyRules;
+
+    impl<S, M> Replicator<S, M> {
+        async fn forget_cursor(&self, peer: &Uri) {
+            self.cursors.write().await.remove(peer);
+        }
+    }
+
+    /// The secret key of the user with the name.
+    fn secret_key(name: &[u8]) -> SecretKey {
+        SecretKey::from_slice(digest(&SHA256, name).as_ref()).unwrap()
+    }
+
+    /// The address payload of the user with the name.
+    fn address(name: &[u8]) -> Vec<u8> {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(name));
+        Ripemd160::digest(digest(&SHA256, &public_key.serialize()).as_ref()).to_vec()
+    }
+
+    fn entry(name: &[u8], timestamp: i64) -> MetadataEntry {
+        namespaced_entry(name, timestamp, "", vec![])
+    }
+
+    fn namespaced_entry(
+        name: &[u8],
+        timestamp: i64,
+        namespace: &str,
+        entries: Vec<Entry>,
+    ) -> MetadataEntry {
+        let secret_key = secret_key(name);
+        let metadata = AddressMetadata {
+            timestamp,
+            entries,
+            ..Default::default()
+        };
+        let mut payload = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut payload).unwrap();
+        let auth_wrapper = AuthWrapper::sign_ecdsa(&secret_key, payload);
+        let mut raw_auth_wrapper = Vec::with_capacity(auth_wrapper.encoded_len());
+        auth_wrapper.encode(&mut raw_auth_wrapper).unwrap();
+        MetadataEntry {
+            address: address(name),
+            raw_auth_wrapper,
+            token: b"token".to_vec(),
+            timestamp,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let mut forged = entry(b"carol", 300);
+            forged.timestamp = 400;
+            let entries = vec![
+                entry(b"alice", 100),
+                entry(b"bob", 200),
+                entry(b"bob", 250),
+                forged,
+            ];
+            let since: i64 = request
+                .uri()
+                .query()
+                .and_then(|query| query.split('&').next())
+                .and_then(|since| since.trim_start_matches("since=").parse().ok())
+                .unwrap();
+            let page = MetadataPage {
+                entries: entries
+                    .into_iter()
+                    .filter(|entry| entry.timestamp >= since)
+                    .take(2)
+                    .collect(),
+            };
+            let mut body = Vec::with_capacity(page.encoded_len());
+            page.encode(&mut body).unwrap();
+            ready(Ok(Response::new(Body::from(body))))
+        }
+    }
+
+    #[tokio::test]
+    async fn replicate() {
+        let store = MemoryMetadataStore::new();
+        store
+            .put(
+                &address(b"alice"),
+                StoredMetadata {
+                    timestamp: 150,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let events = EventBus::default();
+        let mut subscriber = events.metadata_updated().subscribe();
+        let replicator = Replicator::new(KeyserverClient::from_service(MockKeyserver), store)
+            .with_page_size(2)
+            .with_events(events);
+        let peer: Uri = "http://peer".parse().unwrap();
+
+        let report = replicator.replicate(&peer).await.unwrap();
+        assert_eq!(
+            report,
+            ReplicationReport {
+                accepted: 2,
+                stale: 3,
+                invalid: 2,
+            }
+        );
+        assert_eq!(replicator.cursor(&peer).await, 400);
+        assert_eq!(
+            replicator
+                .store
+                .get(&address(b"bob"))
+                .await
+                .unwrap()
+                .unwrap()
+                .timestamp,
+            250
+        );
+        assert_eq!(
+            replicator.store.get(&address(b"carol")).await.unwrap(),
+            None
+        );
+
+        let event = subscriber.recv().await.unwrap();
+        assert_eq!(event.address, address(b"bob"));
+        assert_eq!(event.origin, Origin::Peer("http://peer".to_string()));
+        assert_eq!(subscriber.recv().await.unwrap().timestamp, 250);
+    }
+
+    #[tokio::test]
+    async fn policy() {
+        let policy = PeerPolicy::new(PolicyRules {
+            signature_failure_penalty: 50,
+            ban_threshold: 100,
+            ..Default::default()
+        });
+        let replicator = Replicator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MemoryMetadataStore::new(),
+        )
+        .with_page_size(2)
+        .with_policy(policy.clone());
+        let peer: Uri = "http://peer".parse().unwrap();
+
+        // The forged entry is reported on each of the last two pages, banning the peer
+        let report = replicator.replicate(&peer).await.unwrap();
+        assert_eq!(report.invalid, 2);
+        assert!(policy.is_banned(&peer));
+        replicator.forget_cursor(&peer).await;
+        assert_eq!(
+            replicator.replicate(&peer).await.unwrap(),
+            Default::default()
+        );
+    }
+
+    #[test]
+    fn verify_namespace() {
+        let profile = |kind: &str| Entry {
+            kind: kind.to_string(),
+            ..Default::default()
+        };
+        let entry = namespaced_entry(b"alice", 100, "profile", vec![profile("profile/name")]);
+        assert_eq!(verify_entry(&entry).unwrap().namespace, "profile");
+
+        let entry = namespaced_entry(b"alice", 100, "profile", vec![profile("payment/address")]);
+        assert!(matches!(
+            verify_entry(&entry),
+            Err(InvalidEntry::OutsideNamespace(_))
+        ));
+
+        let entry = namespaced_entry(b"alice", 100, "Profile", vec![]);
+        assert!(matches!(
+            verify_entry(&entry),
+            Err(InvalidEntry::Namespace(_))
+        ));
+    }
+
+    #[test]
+    fn verify_address() {
+        let mut entry = entry(b"alice", 100);
+        assert!(verify_entry(&entry).is_ok());
+        entry.address = address(b"bob");
+        assert_eq!(verify_entry(&entry), Err(InvalidEntry::AddressMismatch));
+    }
+
+    #[derive(Debug)]
+    struct MockTokenError(ErrorKind);
+
+    impl fmt::Display for MockTokenError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+
+    impl TokenError for MockTokenError {
+        fn kind(&self) -> ErrorKind {
+            self.0
+        }
+    }
+
+    /// Accepts the tokens of the given public key, failing with the error kind otherwise.
+    struct MockScheme(Vec<u8>, ErrorKind);
+
+    #[async_trait]
+    impl TokenScheme for MockScheme {
+        type Error = MockTokenError;
+
+        async fn construct(&self, _data: &[u8]) -> Result<String, Self::Error> {
+            Err(MockTokenError(ErrorKind::Internal))
+        }
+
+        async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+            let pub_key_hash = digest(&SHA256, &self.0);
+            if data.starts_with(pub_key_hash.as_ref()) && token == "dG9rZW4" {
+                Ok(())
+            } else {
+                Err(MockTokenError(self.1))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn token_scheme() {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(b"bob"));
+        let token_scheme = MockScheme(public_key.serialize().to_vec(), ErrorKind::Invalid);
+        let replicator = Replicator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MemoryMetadataStore::new(),
+        )
+        .with_page_size(2)
+        .with_token_scheme(ErasedScheme::shared(token_scheme));
+        let peer: Uri = "http://peer".parse().unwrap();
+
+        // Only entries whose token commits to the signing key are accepted
+        let report = replicator.replicate(&peer).await.unwrap();
+        assert_eq!(report.accepted, 2);
+        assert_eq!(
+            replicator.store.get(&address(b"alice")).await.unwrap(),
+            None
+        );
+
+        // Entries are retried if the token can't be validated
+        let token_scheme = MockScheme(vec![], ErrorKind::Internal);
+        let replicator = Replicator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MemoryMetadataStore::new(),
+        )
+        .with_token_scheme(ErasedScheme::shared(token_scheme));
+        assert!(matches!(
+            replicator.replicate(&peer).await,
+            Err(ReplicationError::TokenUnavailable)
+        ));
+        assert_eq!(replicator.cursor(&peer).await, i64::MIN);
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let lifecycle = Lifecycle::new();
+        let replicator = Replicator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MemoryMetadataStore::new(),
+        )
+        .with_lifecycle(lifecycle.clone());
+        let peer: Uri = "http://peer".parse().unwrap();
+
+        lifecycle.shutdown();
+        assert!(replicator
+            .replicate_all(std::slice::from_ref(&peer))
+            .await
+            .is_empty());
+        assert_eq!(
+            replicator.replicate(&peer).await.unwrap(),
+            Default::default()
+        );
+        assert_eq!(replicator.cursor(&peer).await, i64::MIN);
+    }
+}
@@ -0,0 +1,119 @@
+//! This module contains the clocks and timers used by the client.
+//!
+//! Natively these are those of the standard library and [`tokio`]. In browsers, whose clocks
+//! panic when read through the standard library, [`Instant`] is read from `performance.now()`,
+//! [`now`] from `Date.now()`, and deadlines are driven by `setTimeout`.
+
+use std::time::SystemTime;
+
+use futures_core::Future;
+
+#[cfg(any(feature = "native", not(feature = "wasm")))]
+pub use std::time::Instant;
+
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub use browser::Instant;
+
+/// The current wall-clock time.
+#[cfg(any(feature = "native", not(feature = "wasm")))]
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// The current wall-clock time.
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub fn now() -> SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs_f64(js_sys::Date::now() / 1_000.)
+}
+
+/// Run the future until the deadline, returning `None` if the deadline elapsed first.
+#[cfg(any(feature = "native", not(feature = "wasm")))]
+pub(crate) async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Option<F::Output> {
+    tokio::time::timeout_at(deadline.into(), future).await.ok()
+}
+
+/// Run the future until the deadline, returning `None` if the deadline elapsed first.
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+pub(crate) async fn timeout_at<F: Future>(deadline: Instant, future: F) -> Option<F::Output> {
+    use futures_util::future::{select, Either};
+
+    futures_util::pin_mut!(future);
+    match select(future, browser::sleep_until(deadline)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[cfg(all(feature = "wasm", not(feature = "native")))]
+mod browser {
+    use std::{convert::TryFrom, ops::Add, time::Duration};
+
+    use js_sys::{Function, Promise, Reflect};
+    use tokio::sync::oneshot;
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+
+    /// A monotonic clock, read from `performance.now()`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        /// The current instant.
+        pub fn now() -> Self {
+            let performance = Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
+                .ok()
+                .and_then(|performance| performance.dyn_into::<web_sys::Performance>().ok());
+            // Fall back to the wall-clock where the performance API is missing
+            let millis = match performance {
+                Some(performance) => performance.now(),
+                None => js_sys::Date::now(),
+            };
+            Self(Duration::from_secs_f64(millis / 1_000.))
+        }
+
+        /// The time elapsed since the instant.
+        pub fn elapsed(&self) -> Duration {
+            Self::now().saturating_duration_since(*self)
+        }
+
+        /// The time elapsed from `earlier` to the instant, or zero if `earlier` is later.
+        pub fn saturating_duration_since(&self, earlier: Self) -> Duration {
+            self.0.checked_sub(earlier.0).unwrap_or_default()
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Self;
+
+        fn add(self, duration: Duration) -> Self {
+            Self(self.0 + duration)
+        }
+    }
+
+    /// Resolves once the deadline has elapsed.
+    ///
+    /// JS values can't be sent between threads, so the timer is driven by the event loop and its
+    /// expiry signalled over a channel, keeping the futures awaiting it [`Send`].
+    pub(super) fn sleep_until(deadline: Instant) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        let millis = deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis();
+        let millis = i32::try_from(millis).unwrap_or(i32::MAX);
+        let timer = Promise::new(&mut |resolve, _| {
+            // `setTimeout` is present on both windows and workers
+            let global = js_sys::global();
+            if let Some(set_timeout) = Reflect::get(&global, &JsValue::from_str("setTimeout"))
+                .ok()
+                .and_then(|set_timeout| set_timeout.dyn_into::<Function>().ok())
+            {
+                let _ = set_timeout.call2(&global, &resolve, &JsValue::from(millis));
+            }
+        });
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = JsFuture::from(timer).await;
+            let _ = sender.send(());
+        });
+        receiver
+    }
+}
@@ -0,0 +1,324 @@
+//! This module contains the [`TokenCache`] which caches POP tokens per keyserver and address,
+//! allowing them to be reused across [`KeyserverClient::put_metadata`] calls.
+
+use std::{collections::HashMap, error, fmt, sync::RwLock, time::SystemTime};
+
+use async_trait::async_trait;
+use cashweb_auth_wrapper::AuthWrapper;
+use cashweb_keyserver::namespace::Namespace;
+use hyper::{StatusCode, Uri};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::{
+    client::services::{PutMetadata, PutMetadataError},
+    time, KeyserverClient, KeyserverError,
+};
+
+/// A POP token held by the [`TokenCache`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedToken {
+    /// The token, including its authorization scheme, as attached to requests.
+    pub token: String,
+    /// Time at which the token expires, if any.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl CachedToken {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at
+            .map(|expires_at| now >= expires_at)
+            .unwrap_or_default()
+    }
+}
+
+/// Caches POP tokens per keyserver URL and address.
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    tokens: RwLock<HashMap<(String, String), CachedToken>>,
+}
+
+impl TokenCache {
+    /// Create an empty [`TokenCache`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the token for the keyserver and address, if one is cached and has not expired.
+    pub fn get(&self, keyserver_url: &str, address: &str) -> Option<CachedToken> {
+        let now = time::now();
+        self.tokens
+            .read()
+            .unwrap()
+            .get(&(keyserver_url.to_string(), address.to_string()))
+            .filter(|token| !token.is_expired(now))
+            .cloned()
+    }
+
+    /// Cache the token for the keyserver and address.
+    pub fn insert(&self, keyserver_url: &str, address: &str, token: CachedToken) {
+        self.tokens
+            .write()
+            .unwrap()
+            .insert((keyserver_url.to_string(), address.to_string()), token);
+    }
+
+    /// Remove the token for the keyserver and address.
+    pub fn invalidate(&self, keyserver_url: &str, address: &str) -> Option<CachedToken> {
+        self.tokens
+            .write()
+            .unwrap()
+            .remove(&(keyserver_url.to_string(), address.to_string()))
+    }
+
+    /// Remove all expired tokens.
+    pub fn prune(&self) {
+        let now = time::now();
+        self.tokens
+            .write()
+            .unwrap()
+            .retain(|_, token| !token.is_expired(now));
+    }
+}
+
+/// Acquires new POP tokens, for example by completing the keyserver's payment flow.
+#[async_trait]
+pub trait TokenAcquirer {
+    /// Error associated with acquiring a token.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Acquire a token allowing the [`AuthWrapper`] to be put to the keyserver.
+    async fn acquire(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        auth_wrapper: &AuthWrapper,
+    ) -> Result<CachedToken, Self::Error>;
+}
+
+/// Error associated with [`KeyserverClient::put_metadata_cached`].
+#[derive(Debug, Error)]
+pub enum CachedPutError<E: fmt::Display + error::Error + 'static, A: fmt::Debug + fmt::Display> {
+    /// Failed to put the metadata.
+    #[error(transparent)]
+    Put(KeyserverError<E>),
+    /// Failed to acquire a token.
+    #[error("failed to acquire token: {0}")]
+    Acquire(A),
+}
+
+fn is_unauthorized<E: fmt::Debug + fmt::Display>(
+    err: &KeyserverError<PutMetadataError<E>>,
+) -> bool {
+    match err {
+        KeyserverError::Error(PutMetadataError::UnexpectedStatusCode(code)) => {
+            *code == StatusCode::UNAUTHORIZED.as_u16()
+                || *code == StatusCode::PAYMENT_REQUIRED.as_u16()
+        }
+        _ => false,
+    }
+}
+
+impl<S, E> KeyserverClient<S>
+where
+    Self: Service<(Uri, PutMetadata), Response = (), Error = PutMetadataError<E>>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PutMetadata)>>::Future: Send + 'static,
+    E: fmt::Debug + fmt::Display + 'static,
+{
+    /// Put [`AuthWrapper`] to a keyserver, attaching a token from the [`TokenCache`].
+    ///
+    /// If no valid token is cached, or the keyserver rejects the cached token with a 401 or 402,
+    /// a new token is acquired using the [`TokenAcquirer`] and the request is retried.
+    pub async fn put_metadata_cached<A: TokenAcquirer>(
+        &self,
+        cache: &TokenCache,
+        acquirer: &A,
+        keyserver_url: &str,
+        address: &str,
+        auth_wrapper: AuthWrapper,
+    ) -> Result<(), CachedPutError<PutMetadataError<E>, A::Error>> {
+        self.put_cached(cache, acquirer, keyserver_url, address, None, auth_wrapper)
+            .await
+    }
+
+    /// Put [`AuthWrapper`], covering the [`AddressMetadata`] of a [`Namespace`], to a keyserver,
+    /// attaching a token from the [`TokenCache`].
+    ///
+    /// Tokens are acquired as in [`KeyserverClient::put_metadata_cached`].
+    ///
+    /// [`AddressMetadata`]: cashweb_keyserver::AddressMetadata
+    pub async fn put_namespace_metadata_cached<A: TokenAcquirer>(
+        &self,
+        cache: &TokenCache,
+        acquirer: &A,
+        keyserver_url: &str,
+        address: &str,
+        namespace: &Namespace,
+        auth_wrapper: AuthWrapper,
+    ) -> Result<(), CachedPutError<PutMetadataError<E>, A::Error>> {
+        self.put_cached(
+            cache,
+            acquirer,
+            keyserver_url,
+            address,
+            Some(namespace),
+            auth_wrapper,
+        )
+        .await
+    }
+
+    async fn put_with_token(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        namespace: Option<&Namespace>,
+        auth_wrapper: AuthWrapper,
+        token: String,
+    ) -> Result<(), KeyserverError<PutMetadataError<E>>> {
+        match namespace {
+            Some(namespace) => {
+                self.put_namespace_metadata(keyserver_url, address, namespace, auth_wrapper, token)
+                    .await
+            }
+            None => {
+                self.put_metadata(keyserver_url, address, auth_wrapper, token)
+                    .await
+            }
+        }
+    }
+
+    async fn put_cached<A: TokenAcquirer>(
+        &self,
+        cache: &TokenCache,
+        acquirer: &A,
+        keyserver_url: &str,
+        address: &str,
+        namespace: Option<&Namespace>,
+        auth_wrapper: AuthWrapper,
+    ) -> Result<(), CachedPutError<PutMetadataError<E>, A::Error>> {
+        // Attempt using the cached token
+        if let Some(cached) = cache.get(keyserver_url, address) {
+            match self
+                .put_with_token(
+                    keyserver_url,
+                    address,
+                    namespace,
+                    auth_wrapper.clone(),
+                    cached.token,
+                )
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) if is_unauthorized(&err) => {
+                    cache.invalidate(keyserver_url, address);
+                }
+                Err(err) => return Err(CachedPutError::Put(err)),
+            }
+        }
+
+        // Acquire a new token
+        let cached = acquirer
+            .acquire(keyserver_url, address, &auth_wrapper)
+            .await
+            .map_err(CachedPutError::Acquire)?;
+        let token = cached.token.clone();
+        cache.insert(keyserver_url, address, cached);
+
+        self.put_with_token(keyserver_url, address, namespace, auth_wrapper, token)
+            .await
+            .map_err(|err| {
+                if is_unauthorized(&err) {
+                    cache.invalidate(keyserver_url, address);
+                }
+                CachedPutError::Put(err)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll},
+    };
+
+    use hyper::{http::header::AUTHORIZATION, Body, Request, Response};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let status = match request.headers().get(AUTHORIZATION) {
+                Some(token) if token == "POP fresh" => StatusCode::OK,
+                _ => StatusCode::PAYMENT_REQUIRED,
+            };
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            ready(Ok(response))
+        }
+    }
+
+    #[derive(Default)]
+    struct MockAcquirer(AtomicUsize);
+
+    #[async_trait]
+    impl TokenAcquirer for MockAcquirer {
+        type Error = Infallible;
+
+        async fn acquire(
+            &self,
+            _keyserver_url: &str,
+            _address: &str,
+            _auth_wrapper: &AuthWrapper,
+        ) -> Result<CachedToken, Self::Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(CachedToken {
+                token: "POP fresh".to_string(),
+                expires_at: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn reacquire() {
+        let client = KeyserverClient::from_service(MockKeyserver);
+        let cache = TokenCache::new();
+        let acquirer = MockAcquirer::default();
+        let url = "http://keyserver";
+        cache.insert(
+            url,
+            "alice",
+            CachedToken {
+                token: "POP stale".to_string(),
+                expires_at: None,
+            },
+        );
+
+        client
+            .put_metadata_cached(&cache, &acquirer, url, "alice", AuthWrapper::default())
+            .await
+            .unwrap();
+        assert_eq!(acquirer.0.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(url, "alice").unwrap().token, "POP fresh");
+
+        client
+            .put_metadata_cached(&cache, &acquirer, url, "alice", AuthWrapper::default())
+            .await
+            .unwrap();
+        assert_eq!(acquirer.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,370 @@
+//! This module contains the [`TrustBundle`], which pins keyservers to the certificates they
+//! present, and the [`PinnedConnector`], which refuses connections to keyservers which are unpinned
+//! or present another certificate.
+//!
+//! Pins follow the presentation format of DANE [`TLSA`] records, restricted to end-entity
+//! certificates matched by their SHA-256 digest, that is `3 0 1 <hex digest>`. A bundle lists a
+//! pin per line, as `<host> <usage> <selector> <matching type> <hex digest>`, and is distributed
+//! alongside a signature of its publisher, so that deployments with strict supply-chain
+//! requirements need only trust the public key of the publisher. Pins apply to every port of the
+//! host.
+//!
+//! As with DANE-EE, a pinned certificate is trusted by its pin alone, so keyservers may present
+//! self-signed certificates. The [`PinnedConnector`] therefore skips certificate authority, expiry
+//! and hostname validation, refusing the connection unless the certificate matches a pin.
+//!
+//! The [`PinnedConnector`] requires the `native` feature. In browsers, certificates are checked
+//! by the browser itself, and the bundle only filters the keyservers sampled.
+//!
+//! [`TLSA`]: https://tools.ietf.org/html/rfc6698
+
+use std::{collections::HashMap, str};
+
+use hyper::Uri;
+use ring::digest::{digest, SHA256};
+use secp256k1::{key::PublicKey, Error as SecpError, Message, Secp256k1, Signature};
+use thiserror::Error;
+
+#[cfg(feature = "native")]
+use std::{
+    error::Error as StdError,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "native")]
+use hyper::client::{connect::Connection, HttpConnector};
+#[cfg(feature = "native")]
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
+#[cfg(feature = "native")]
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "native")]
+use tower_service::Service;
+
+#[cfg(feature = "native")]
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// The usage of a pin on the certificate of the keyserver itself, DANE-EE.
+pub const USAGE_DANE_EE: u8 = 3;
+
+/// The selector of a pin on the full certificate.
+pub const SELECTOR_FULL_CERTIFICATE: u8 = 0;
+
+/// The matching type of a pin on the SHA-256 digest.
+pub const MATCHING_SHA256: u8 = 1;
+
+/// A certificate pin, in the form of a TLSA record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsaRecord {
+    /// The certificate usage.
+    pub usage: u8,
+    /// The part of the certificate matched.
+    pub selector: u8,
+    /// How the certificate is matched.
+    pub matching_type: u8,
+    /// The certificate association data.
+    pub data: Vec<u8>,
+}
+
+impl TlsaRecord {
+    /// Create a record pinning the certificate, given in DER, by its SHA-256 digest.
+    pub fn from_certificate(certificate: &[u8]) -> Self {
+        Self {
+            usage: USAGE_DANE_EE,
+            selector: SELECTOR_FULL_CERTIFICATE,
+            matching_type: MATCHING_SHA256,
+            data: digest(&SHA256, certificate).as_ref().to_vec(),
+        }
+    }
+
+    /// Whether the record is supported, that is `3 0 1` with a 32 byte digest.
+    pub fn is_supported(&self) -> bool {
+        self.usage == USAGE_DANE_EE
+            && self.selector == SELECTOR_FULL_CERTIFICATE
+            && self.matching_type == MATCHING_SHA256
+            && self.data.len() == 32
+    }
+
+    /// Whether the certificate, given in DER, matches the record.
+    pub fn matches(&self, certificate: &[u8]) -> bool {
+        self.is_supported() && digest(&SHA256, certificate).as_ref() == self.data.as_slice()
+    }
+}
+
+/// Error associated with loading a [`TrustBundle`].
+#[derive(Debug, Error)]
+pub enum BundleError {
+    /// The signature of the bundle was invalid.
+    #[error("invalid signature: {0}")]
+    Signature(SecpError),
+    /// The bundle was not UTF-8.
+    #[error("bundle is not UTF-8")]
+    Encoding,
+    /// A line could not be parsed.
+    #[error("malformed pin on line {0}")]
+    Malformed(usize),
+    /// A line held a TLSA record other than `3 0 1`.
+    #[error("unsupported pin on line {0}, only 3 0 1 is supported")]
+    Unsupported(usize),
+}
+
+/// Error associated with connecting to a pinned keyserver.
+#[derive(Debug, Error)]
+pub enum PinError {
+    /// The host has no pins.
+    #[error("unpinned host: {0}")]
+    Unpinned(String),
+    /// The keyserver was reached without TLS.
+    #[error("host reached without TLS: {0}")]
+    NotTls(String),
+    /// The certificate of the keyserver could not be read.
+    #[cfg(feature = "native")]
+    #[error("failed to read certificate: {0}")]
+    Tls(native_tls::Error),
+    /// The keyserver presented no certificate.
+    #[error("missing certificate: {0}")]
+    MissingCertificate(String),
+    /// The certificate of the keyserver matched none of its pins.
+    #[error("certificate mismatch: {0}")]
+    Mismatch(String),
+}
+
+/// `TrustBundle` holds the certificate pins of keyservers, keyed by host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustBundle {
+    pins: HashMap<String, Vec<TlsaRecord>>,
+}
+
+impl TrustBundle {
+    /// Parse a bundle, skipping blank lines and those starting with `#`.
+    pub fn parse(raw: &str) -> Result<Self, BundleError> {
+        let mut bundle = Self::default();
+        for (index, line) in raw.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (host, usage, selector, matching_type, data) = match fields.as_slice() {
+                [host, usage, selector, matching_type, data] => {
+                    (host, usage, selector, matching_type, data)
+                }
+                _ => return Err(BundleError::Malformed(line_number)),
+            };
+            let record = TlsaRecord {
+                usage: usage
+                    .parse()
+                    .map_err(|_| BundleError::Malformed(line_number))?,
+                selector: selector
+                    .parse()
+                    .map_err(|_| BundleError::Malformed(line_number))?,
+                matching_type: matching_type
+                    .parse()
+                    .map_err(|_| BundleError::Malformed(line_number))?,
+                data: hex::decode(data).map_err(|_| BundleError::Malformed(line_number))?,
+            };
+            if !record.is_supported() {
+                return Err(BundleError::Unsupported(line_number));
+            }
+            bundle.insert(host, record);
+        }
+        Ok(bundle)
+    }
+
+    /// Parse a bundle after verifying the compact ECDSA signature of its publisher, covering the
+    /// SHA-256 digest of the bundle.
+    pub fn from_signed(
+        raw: &[u8],
+        signature: &[u8],
+        public_key: &PublicKey,
+    ) -> Result<Self, BundleError> {
+        let signature = Signature::from_compact(signature).map_err(BundleError::Signature)?;
+        let message = Message::from_slice(digest(&SHA256, raw).as_ref()).unwrap(); // This is safe
+        Secp256k1::verification_only()
+            .verify(&message, &signature, public_key)
+            .map_err(BundleError::Signature)?;
+        let raw = str::from_utf8(raw).map_err(|_| BundleError::Encoding)?;
+        Self::parse(raw)
+    }
+
+    /// Pin a host to a certificate.
+    pub fn insert(&mut self, host: &str, record: TlsaRecord) {
+        self.pins
+            .entry(host.to_ascii_lowercase())
+            .or_default()
+            .push(record);
+    }
+
+    /// Whether the host has pins.
+    pub fn is_pinned(&self, host: &str) -> bool {
+        self.pins.contains_key(&host.to_ascii_lowercase())
+    }
+
+    /// Whether the host of the URI has pins.
+    pub fn is_uri_pinned(&self, uri: &Uri) -> bool {
+        uri.host().map(|host| self.is_pinned(host)).unwrap_or(false)
+    }
+
+    /// Check the certificate, given in DER, presented by the host.
+    pub fn verify(&self, host: &str, certificate: &[u8]) -> Result<(), PinError> {
+        let records = self
+            .pins
+            .get(&host.to_ascii_lowercase())
+            .ok_or_else(|| PinError::Unpinned(host.to_string()))?;
+        if records.iter().any(|record| record.matches(certificate)) {
+            Ok(())
+        } else {
+            Err(PinError::Mismatch(host.to_string()))
+        }
+    }
+}
+
+/// `PinnedConnector` establishes TLS connections, refusing those to keyservers whose certificates
+/// aren't pinned by its [`TrustBundle`].
+#[cfg(feature = "native")]
+#[derive(Clone, Debug)]
+pub struct PinnedConnector<T> {
+    inner: HttpsConnector<T>,
+    bundle: Arc<TrustBundle>,
+}
+
+#[cfg(feature = "native")]
+impl<T> PinnedConnector<T> {
+    /// Create a connector from an [`HttpsConnector`], checking against the bundle.
+    ///
+    /// The certificate validation of the [`HttpsConnector`] applies in addition to the pins, so a
+    /// connector validating certificates rejects self-signed certificates despite their pins.
+    pub fn from_connector(inner: HttpsConnector<T>, bundle: TrustBundle) -> Self {
+        Self {
+            inner,
+            bundle: Arc::new(bundle),
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl PinnedConnector<HttpConnector> {
+    /// Create a connector, checking against the bundle.
+    ///
+    /// Certificates are trusted by their pins alone, accepting self-signed certificates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized, as [`HttpsConnector::new`] does.
+    pub fn new(bundle: TrustBundle) -> Self {
+        // Connections to unpinned hosts are refused and certificates are checked against their
+        // pins before the stream is returned, replacing certificate authority validation
+        let tls = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .expect("failed to initialize TLS backend");
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        Self::from_connector(HttpsConnector::from((http, tls.into())), bundle)
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T> Service<Uri> for PinnedConnector<T>
+where
+    T: Service<Uri>,
+    T::Response: AsyncRead + AsyncWrite + Connection + Send + Unpin + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError>,
+{
+    type Response = MaybeHttpsStream<T::Response>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = uri.host().unwrap_or_default().to_string();
+        if !self.bundle.is_pinned(&host) {
+            return Box::pin(async move { Err(PinError::Unpinned(host).into()) });
+        }
+        let bundle = self.bundle.clone();
+        let connecting = self.inner.call(uri);
+        Box::pin(async move {
+            let stream = connecting.await?;
+            let certificate = match &stream {
+                MaybeHttpsStream::Http(_) => return Err(PinError::NotTls(host).into()),
+                MaybeHttpsStream::Https(tls) => tls
+                    .get_ref()
+                    .peer_certificate()
+                    .map_err(PinError::Tls)?
+                    .ok_or_else(|| PinError::MissingCertificate(host.clone()))?
+                    .to_der()
+                    .map_err(PinError::Tls)?,
+            };
+            bundle.verify(&host, &certificate)?;
+            Ok(stream)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::key::SecretKey;
+
+    use super::*;
+
+    const CERTIFICATE: &[u8] = b"certificate";
+
+    fn bundle_raw() -> String {
+        format!(
+            "# Keyservers\nKeys.Example.com 3 0 1 {}\n\n",
+            hex::encode(digest(&SHA256, CERTIFICATE))
+        )
+    }
+
+    #[test]
+    fn pinned() {
+        let bundle = TrustBundle::parse(&bundle_raw()).unwrap();
+        assert!(bundle.is_uri_pinned(&"https://keys.example.com:8080/".parse().unwrap()));
+        assert!(!bundle.is_uri_pinned(&"https://other.example.com/".parse().unwrap()));
+        assert!(bundle.verify("keys.example.com", CERTIFICATE).is_ok());
+        assert!(matches!(
+            bundle.verify("keys.example.com", b"other"),
+            Err(PinError::Mismatch(_))
+        ));
+        assert!(matches!(
+            bundle.verify("other.example.com", CERTIFICATE),
+            Err(PinError::Unpinned(_))
+        ));
+
+        assert!(matches!(
+            TrustBundle::parse("keys.example.com 3 1 1 00"),
+            Err(BundleError::Unsupported(1))
+        ));
+        assert!(matches!(
+            TrustBundle::parse("\nkeys.example.com 3 0 1"),
+            Err(BundleError::Malformed(2))
+        ));
+    }
+
+    #[test]
+    fn signed() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let raw = bundle_raw();
+        let message = Message::from_slice(digest(&SHA256, raw.as_bytes()).as_ref()).unwrap();
+        let signature = secp.sign(&message, &secret_key).serialize_compact();
+
+        let bundle = TrustBundle::from_signed(raw.as_bytes(), &signature, &public_key).unwrap();
+        assert!(bundle.is_pinned("keys.example.com"));
+
+        let tampered = raw.replace("Keys", "Evil");
+        assert!(matches!(
+            TrustBundle::from_signed(tampered.as_bytes(), &signature, &public_key),
+            Err(BundleError::Signature(_))
+        ));
+    }
+}
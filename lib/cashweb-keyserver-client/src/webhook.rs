@@ -0,0 +1,277 @@
+//! This module contains the [`WebhookSender`], with which keyservers deliver signed
+//! [`MetadataNotification`]s to downstream services when metadata is updated, and the
+//! [`WebhookVerifier`], with which those services check and parse them.
+//!
+//! A notification is `POST`ed as a serialized [`MetadataNotification`]. The [`SIGNATURE_HEADER`]
+//! carries an expiring token of an [`HmacScheme`], keyed by a secret shared between the keyserver
+//! and the service, covering the body. The token embeds its issue and expiry times, so a captured
+//! notification can't be replayed once the token expires.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use cashweb_keyserver::MetadataNotification;
+use cashweb_token::schemes::hmac_bearer::{HmacScheme, ValidationError};
+use futures_util::future::join_all;
+use hyper::{
+    client::HttpConnector,
+    http::{header::CONTENT_TYPE, HeaderMap, Method},
+    Body, Request, Response, Uri,
+};
+use hyper_tls::HttpsConnector;
+use prost::Message as _;
+use thiserror::Error;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+/// The name of the header carrying the signature of a webhook.
+pub const SIGNATURE_HEADER: &str = "Cashweb-Webhook-Signature";
+
+/// The media type of a webhook body.
+pub const NOTIFICATION_MEDIA_TYPE: &str = "application/x-protobuf";
+
+/// Default lifetime of a webhook signature.
+pub const DEFAULT_SIGNATURE_TTL: Duration = Duration::from_secs(300);
+
+/// Error associated with delivering a webhook.
+#[derive(Debug, Error)]
+pub enum DeliveryError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+/// `WebhookSender` delivers signed [`MetadataNotification`]s.
+#[derive(Clone, Debug)]
+pub struct WebhookSender<S> {
+    inner_client: S,
+    scheme: Arc<HmacScheme>,
+    ttl: Duration,
+}
+
+impl<S> WebhookSender<S> {
+    /// Create a new sender from a [`Service`], signing with the scheme.
+    pub fn from_service(service: S, scheme: HmacScheme) -> Self {
+        Self {
+            inner_client: service,
+            scheme: Arc::new(scheme),
+            ttl: DEFAULT_SIGNATURE_TTL,
+        }
+    }
+
+    /// Set the lifetime of signatures.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sign a webhook body at `now`.
+    pub fn sign(&self, body: &[u8], now: SystemTime) -> String {
+        self.scheme.construct_token_expiring_at(body, now, self.ttl)
+    }
+}
+
+impl WebhookSender<hyper::Client<HttpsConnector<HttpConnector>>> {
+    /// Create new HTTPS sender, signing with the scheme.
+    pub fn new_tls(scheme: HmacScheme) -> Self {
+        let https = HttpsConnector::new();
+        Self::from_service(hyper::Client::builder().build(https), scheme)
+    }
+}
+
+impl<S> WebhookSender<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Clone,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    /// Deliver the notification to a webhook URL.
+    pub async fn deliver(
+        &self,
+        uri: Uri,
+        notification: &MetadataNotification,
+    ) -> Result<(), DeliveryError<S::Error>> {
+        let mut body = Vec::with_capacity(notification.encoded_len());
+        notification.encode(&mut body).unwrap(); // This is safe
+        let signature = self.sign(&body, SystemTime::now());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(CONTENT_TYPE, NOTIFICATION_MEDIA_TYPE)
+            .header(SIGNATURE_HEADER, signature)
+            .body(Body::from(body))
+            .unwrap(); // This is safe
+
+        let response = self
+            .inner_client
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(DeliveryError::Service)?;
+        if !response.status().is_success() {
+            return Err(DeliveryError::UnexpectedStatusCode(
+                response.status().as_u16(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Deliver the notification to each webhook URL concurrently.
+    pub async fn deliver_all(
+        &self,
+        uris: &[Uri],
+        notification: &MetadataNotification,
+    ) -> Vec<(Uri, Result<(), DeliveryError<S::Error>>)> {
+        let deliveries = uris.iter().map(|uri| async move {
+            let result = self.deliver(uri.clone(), notification).await;
+            (uri.clone(), result)
+        });
+        join_all(deliveries).await
+    }
+}
+
+/// Error associated with verifying a webhook.
+#[derive(Debug, PartialEq, Error)]
+pub enum VerifyError {
+    /// The signature header was missing or not visible ASCII.
+    #[error("missing signature")]
+    MissingSignature,
+    /// The signature was invalid or expired.
+    #[error("invalid signature: {0}")]
+    Signature(ValidationError),
+    /// The body could not be decoded.
+    #[error("failed to decode notification: {0}")]
+    Decode(prost::DecodeError),
+}
+
+/// `WebhookVerifier` checks the signatures of webhooks and parses their notifications.
+#[derive(Debug)]
+pub struct WebhookVerifier {
+    scheme: HmacScheme,
+}
+
+impl WebhookVerifier {
+    /// Create a new verifier, checking signatures with the scheme.
+    pub fn new(scheme: HmacScheme) -> Self {
+        Self { scheme }
+    }
+
+    /// Verify a webhook at `now`, returning its notification.
+    pub fn verify_at(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<MetadataNotification, VerifyError> {
+        let signature = headers
+            .get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(VerifyError::MissingSignature)?;
+        self.scheme
+            .validate_token_at(body, signature, now)
+            .map_err(VerifyError::Signature)?;
+        MetadataNotification::decode(body).map_err(VerifyError::Decode)
+    }
+
+    /// Verify a webhook, returning its notification.
+    pub fn verify(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<MetadataNotification, VerifyError> {
+        self.verify_at(headers, body, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        sync::Mutex,
+        task::{Context, Poll},
+    };
+
+    use hyper::{body::to_bytes, http::HeaderValue};
+
+    use super::*;
+
+    /// Records the requests it receives.
+    #[derive(Clone, Default)]
+    struct MockService {
+        requests: Arc<Mutex<Vec<Request<Body>>>>,
+    }
+
+    impl Service<Request<Body>> for MockService {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            self.requests.lock().unwrap().push(request);
+            ready(Ok(Response::new(Body::empty())))
+        }
+    }
+
+    fn notification() -> MetadataNotification {
+        MetadataNotification {
+            address: "bitcoincash:qq".to_string(),
+            timestamp: 100,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let service = MockService::default();
+        let sender = WebhookSender::from_service(service.clone(), HmacScheme::new(b"secret"));
+        let uri: Uri = "http://service/webhook".parse().unwrap();
+        let results = sender.deliver_all(&[uri], &notification()).await;
+        assert!(results[0].1.is_ok());
+
+        let request = service.requests.lock().unwrap().pop().unwrap();
+        let headers = request.headers().clone();
+        let body = to_bytes(request.into_body()).await.unwrap();
+        let verifier = WebhookVerifier::new(HmacScheme::new(b"secret"));
+        assert_eq!(verifier.verify(&headers, &body).unwrap(), notification());
+
+        // Tampered bodies, other secrets and missing signatures are rejected
+        assert!(matches!(
+            verifier.verify(&headers, b"tampered"),
+            Err(VerifyError::Signature(ValidationError::Invalid))
+        ));
+        let other = WebhookVerifier::new(HmacScheme::new(b"other"));
+        assert!(other.verify(&headers, &body).is_err());
+        assert_eq!(
+            verifier.verify(&HeaderMap::new(), &body),
+            Err(VerifyError::MissingSignature)
+        );
+    }
+
+    #[test]
+    fn expired() {
+        let sender =
+            WebhookSender::from_service(MockService::default(), HmacScheme::new(b"secret"))
+                .with_ttl(Duration::from_secs(60));
+        let now = SystemTime::now();
+        let mut headers = HeaderMap::new();
+        let signature = sender.sign(b"body", now);
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature).unwrap());
+
+        let verifier = WebhookVerifier::new(HmacScheme::new(b"secret"));
+        assert!(matches!(
+            verifier.verify_at(&headers, b"body", now + Duration::from_secs(61)),
+            Err(VerifyError::Signature(ValidationError::Expired))
+        ));
+    }
+}
@@ -1,3 +1,22 @@
 fn main() {
-    prost_build::compile_protos(&["src/proto/keyserver.proto"], &["src/"]).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Regenerate the models from the .proto files, rather than use those checked in
+    #[cfg(feature = "codegen")]
+    {
+        println!("cargo:rerun-if-changed=src/proto/keyserver.proto");
+        println!("cargo:rerun-if-changed=src/generated/keyserver.rs");
+        prost_build::compile_protos(&["src/proto/keyserver.proto"], &["src/"]).unwrap();
+
+        // Flag models which have drifted from those checked in
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let generated = std::fs::read_to_string(format!("{}/keyserver.rs", out_dir)).unwrap();
+        let checked_in = std::fs::read_to_string("src/generated/keyserver.rs").unwrap_or_default();
+        if generated != checked_in {
+            println!(
+                "cargo:warning=src/generated/keyserver.rs is out of date, see {}/keyserver.rs",
+                out_dir
+            );
+        }
+    }
 }
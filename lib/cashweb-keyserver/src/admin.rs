@@ -0,0 +1,49 @@
+//! This module contains the paths and request signing of the keyserver admin API.
+//!
+//! Admin requests carry an `Authorization: Bearer <token>` header, where the token is an expiring
+//! HMAC token, keyed by a secret shared between the keyserver and its operators, covering the
+//! [`signing_data`] of the request. Binding the token to the method, path and body prevents a
+//! captured token from authorizing a different operation.
+
+/// The root path of the admin API.
+pub const ADMIN_PATH: &str = "admin";
+
+/// The path, below [`ADMIN_PATH`], banning a peer with a `POST` of a [`Peer`](crate::Peer).
+pub const BAN_PEER_PATH: &str = "ban-peer";
+
+/// The path, below [`ADMIN_PATH`], of the addresses purged with a `DELETE`.
+pub const KEYS_PATH: &str = "keys";
+
+/// The path, below [`ADMIN_PATH`], of the [`StorageStats`](crate::StorageStats).
+pub const STATS_PATH: &str = "stats";
+
+/// The path, below [`ADMIN_PATH`], forcing a replication pass with a `POST`.
+pub const REPLICATE_PATH: &str = "replicate";
+
+/// The data covered by the token of an admin request, given its method, its path, such as
+/// `/admin/stats`, and its body.
+pub fn signing_data(method: &str, path: &str, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(method.len() + path.len() + 2 + body.len());
+    data.extend_from_slice(method.as_bytes());
+    data.push(b' ');
+    data.extend_from_slice(path.as_bytes());
+    data.push(b'\n');
+    data.extend_from_slice(body);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bound_to_request() {
+        let data = signing_data("DELETE", "/admin/keys/addr", b"");
+        assert_eq!(data, b"DELETE /admin/keys/addr\n");
+        assert_ne!(data, signing_data("GET", "/admin/keys/addr", b""));
+        assert_ne!(
+            signing_data("POST", "/admin/ban-peer", b"a"),
+            signing_data("POST", "/admin/ban-peer", b"b")
+        );
+    }
+}
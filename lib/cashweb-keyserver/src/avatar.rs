@@ -0,0 +1,453 @@
+//! This module contains [`ImageConstraints`], which validate image entries, such as avatars,
+//! before they are uploaded by clients and when they are put to a keyserver.
+//!
+//! An entry is an image entry if its `Content-Type` header is an `image/*` media type. The format
+//! is detected from the body and must match the declared type. Dimensions are read from the image
+//! header, so validation does not decode the image.
+//!
+//! With the `thumbnail` feature, [`thumbnail`] downscales an image for display.
+
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::{AddressMetadata, Entry};
+
+/// The name of the header declaring the media type of an entry.
+pub const CONTENT_TYPE: &str = "Content-Type";
+
+/// Default maximum size of an image body, in bytes.
+pub const DEFAULT_MAX_SIZE: usize = 256 * 1024;
+
+/// Default maximum width and height of an image, in pixels.
+pub const DEFAULT_MAX_DIMENSION: u32 = 1024;
+
+/// A supported image format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Portable Network Graphics.
+    Png,
+    /// JPEG.
+    Jpeg,
+    /// Graphics Interchange Format.
+    Gif,
+    /// WebP.
+    WebP,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.media_type())
+    }
+}
+
+impl ImageFormat {
+    /// All supported formats.
+    pub const ALL: [ImageFormat; 4] = [Self::Png, Self::Jpeg, Self::Gif, Self::WebP];
+
+    /// The media type of the format.
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::WebP => "image/webp",
+        }
+    }
+
+    /// The format of a media type, ignoring parameters.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        // This is safe as split always yields at least one part
+        let media_type = media_type.split(';').next().unwrap().trim();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.media_type().eq_ignore_ascii_case(media_type))
+    }
+
+    /// Detect the format of an image from its magic bytes.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(Self::WebP)
+        } else {
+            None
+        }
+    }
+
+    /// Read the width and height of an image from its header.
+    pub fn dimensions(&self, data: &[u8]) -> Option<(u32, u32)> {
+        match self {
+            Self::Png => {
+                let header = data.get(16..24)?;
+                Some((be_u32(&header[..4]), be_u32(&header[4..])))
+            }
+            Self::Gif => {
+                let header = data.get(6..10)?;
+                Some((le_u16(&header[..2]), le_u16(&header[2..])))
+            }
+            Self::Jpeg => jpeg_dimensions(data),
+            Self::WebP => webp_dimensions(data),
+        }
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le_u16(bytes: &[u8]) -> u32 {
+    u16::from_le_bytes([bytes[0], bytes[1]]) as u32
+}
+
+fn le_u24(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+/// Find the dimensions in the start of frame segment of a JPEG.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    // Skip the start of image marker
+    let mut offset = 2;
+    loop {
+        if *data.get(offset)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(offset + 1)?;
+        offset += 2;
+        match marker {
+            // Fill bytes
+            0xff => offset -= 1,
+            // Markers without a segment
+            0x01 | 0xd0..=0xd7 => (),
+            // Start of frame, excluding DHT, JPG and DAC
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let segment = data.get(offset + 3..offset + 7)?;
+                let height = u16::from_be_bytes([segment[0], segment[1]]) as u32;
+                let width = u16::from_be_bytes([segment[2], segment[3]]) as u32;
+                return Some((width, height));
+            }
+            _ => {
+                let length = data.get(offset..offset + 2)?;
+                offset += u16::from_be_bytes([length[0], length[1]]) as usize;
+            }
+        }
+    }
+}
+
+/// Find the dimensions in the first chunk of a WebP.
+fn webp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => {
+            let frame = data.get(26..30)?;
+            Some((le_u16(&frame[..2]) & 0x3fff, le_u16(&frame[2..]) & 0x3fff))
+        }
+        b"VP8L" => {
+            let bits = data.get(21..25)?;
+            let bits = u32::from_le_bytes([bits[0], bits[1], bits[2], bits[3]]);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => {
+            let canvas = data.get(24..30)?;
+            Some((le_u24(&canvas[..3]) + 1, le_u24(&canvas[3..]) + 1))
+        }
+        _ => None,
+    }
+}
+
+/// Error associated with validating an image.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ImageError {
+    /// The declared media type is not a supported image format.
+    #[error("unsupported image type: {0}")]
+    UnsupportedType(String),
+    /// The format of the body could not be detected.
+    #[error("unrecognized image data")]
+    Unrecognized,
+    /// The format of the body differed from the declared media type.
+    #[error("declared {declared} but found {detected}")]
+    TypeMismatch {
+        /// The declared format.
+        declared: ImageFormat,
+        /// The detected format.
+        detected: ImageFormat,
+    },
+    /// The dimensions could not be read from the image header.
+    #[error("malformed image header")]
+    Malformed,
+    /// The body exceeded the maximum size.
+    #[error("image is {size} bytes, exceeding the maximum of {max}")]
+    TooLarge {
+        /// The size of the body, in bytes.
+        size: usize,
+        /// The maximum size, in bytes.
+        max: usize,
+    },
+    /// The image exceeded the maximum dimensions.
+    #[error("image is {width}x{height}, exceeding the maximum of {max_width}x{max_height}")]
+    TooManyPixels {
+        /// The width of the image.
+        width: u32,
+        /// The height of the image.
+        height: u32,
+        /// The maximum width.
+        max_width: u32,
+        /// The maximum height.
+        max_height: u32,
+    },
+    /// The image could not be thumbnailed.
+    #[error("failed to create thumbnail: {0}")]
+    Thumbnail(String),
+}
+
+/// An image entry which failed validation.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("invalid image entry {kind:?}: {error}")]
+pub struct InvalidImageEntry {
+    /// The kind of the entry.
+    pub kind: String,
+    /// The reason the entry is invalid.
+    pub error: ImageError,
+}
+
+/// A validated image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// The format of the image.
+    pub format: ImageFormat,
+    /// The width of the image, in pixels.
+    pub width: u32,
+    /// The height of the image, in pixels.
+    pub height: u32,
+    /// The size of the body, in bytes.
+    pub size: usize,
+}
+
+/// The media type declared by the `Content-Type` header of an entry.
+pub fn content_type(entry: &Entry) -> Option<&str> {
+    entry
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(CONTENT_TYPE))
+        .map(|header| header.value.as_str())
+}
+
+/// Whether the entry declares an `image/*` media type.
+pub fn is_image_entry(entry: &Entry) -> bool {
+    content_type(entry)
+        .map(|media_type| media_type.trim().to_ascii_lowercase().starts_with("image/"))
+        .unwrap_or_default()
+}
+
+/// Constraints on image entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageConstraints {
+    max_size: usize,
+    max_width: u32,
+    max_height: u32,
+    formats: Vec<ImageFormat>,
+}
+
+impl Default for ImageConstraints {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            max_width: DEFAULT_MAX_DIMENSION,
+            max_height: DEFAULT_MAX_DIMENSION,
+            formats: ImageFormat::ALL.to_vec(),
+        }
+    }
+}
+
+impl ImageConstraints {
+    /// Create constraints with the default limits, accepting all supported formats.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum size of an image body, in bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set the maximum width and height of an image, in pixels.
+    pub fn with_max_dimensions(mut self, max_width: u32, max_height: u32) -> Self {
+        self.max_width = max_width;
+        self.max_height = max_height;
+        self
+    }
+
+    /// Set the accepted formats.
+    pub fn with_formats(mut self, formats: Vec<ImageFormat>) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    /// Validate an image against the constraints, given its declared media type.
+    pub fn validate(&self, data: &[u8], media_type: &str) -> Result<ImageInfo, ImageError> {
+        let declared = ImageFormat::from_media_type(media_type)
+            .filter(|format| self.formats.contains(format))
+            .ok_or_else(|| ImageError::UnsupportedType(media_type.to_string()))?;
+        if data.len() > self.max_size {
+            return Err(ImageError::TooLarge {
+                size: data.len(),
+                max: self.max_size,
+            });
+        }
+        let detected = ImageFormat::detect(data).ok_or(ImageError::Unrecognized)?;
+        if detected != declared {
+            return Err(ImageError::TypeMismatch { declared, detected });
+        }
+        let (width, height) = detected.dimensions(data).ok_or(ImageError::Malformed)?;
+        if width > self.max_width || height > self.max_height {
+            return Err(ImageError::TooManyPixels {
+                width,
+                height,
+                max_width: self.max_width,
+                max_height: self.max_height,
+            });
+        }
+        Ok(ImageInfo {
+            format: detected,
+            width,
+            height,
+            size: data.len(),
+        })
+    }
+
+    /// Validate an entry, returning `None` if it is not an image entry.
+    pub fn validate_entry(&self, entry: &Entry) -> Option<Result<ImageInfo, ImageError>> {
+        if !is_image_entry(entry) {
+            return None;
+        }
+        // This is safe as image entries have a content type
+        let media_type = content_type(entry).unwrap();
+        Some(self.validate(&entry.body, media_type))
+    }
+
+    /// Validate every image entry of the metadata.
+    pub fn validate_metadata(&self, metadata: &AddressMetadata) -> Result<(), InvalidImageEntry> {
+        for entry in &metadata.entries {
+            if let Some(Err(error)) = self.validate_entry(entry) {
+                return Err(InvalidImageEntry {
+                    kind: entry.kind.clone(),
+                    error,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Downscale an image to fit within `max_dimension` pixels, preserving its aspect ratio, encoded
+/// as PNG.
+#[cfg(feature = "thumbnail")]
+pub fn thumbnail(data: &[u8], max_dimension: u32) -> Result<Vec<u8>, ImageError> {
+    let image =
+        image::load_from_memory(data).map_err(|err| ImageError::Thumbnail(err.to_string()))?;
+    let thumbnail = image.thumbnail(max_dimension, max_dimension);
+    let mut png = Vec::new();
+    thumbnail
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|err| ImageError::Thumbnail(err.to_string()))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Header;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        data
+    }
+
+    fn image_entry(media_type: &str, body: Vec<u8>) -> Entry {
+        Entry {
+            kind: "profile/avatar".to_string(),
+            headers: vec![Header {
+                name: CONTENT_TYPE.to_string(),
+                value: media_type.to_string(),
+            }],
+            body,
+        }
+    }
+
+    #[test]
+    fn dimensions() {
+        assert_eq!(ImageFormat::Png.dimensions(&png(64, 32)), Some((64, 32)));
+
+        let gif = b"GIF89a\x40\x00\x20\x00".to_vec();
+        assert_eq!(ImageFormat::detect(&gif), Some(ImageFormat::Gif));
+        assert_eq!(ImageFormat::Gif.dimensions(&gif), Some((64, 32)));
+
+        // SOI, an APP0 segment, then SOF0
+        let jpeg = [
+            &[0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00][..],
+            &[0xff, 0xc0, 0x00, 0x11, 0x08, 0x00, 0x20, 0x00, 0x40],
+        ]
+        .concat();
+        assert_eq!(ImageFormat::detect(&jpeg), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::Jpeg.dimensions(&jpeg), Some((64, 32)));
+
+        let webp = [
+            &b"RIFF\x00\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00\x00\x00\x00\x00"[..],
+            &[0x3f, 0x00, 0x00, 0x1f, 0x00, 0x00],
+        ]
+        .concat();
+        assert_eq!(ImageFormat::detect(&webp), Some(ImageFormat::WebP));
+        assert_eq!(ImageFormat::WebP.dimensions(&webp), Some((64, 32)));
+    }
+
+    #[test]
+    fn validate() {
+        let constraints = ImageConstraints::new()
+            .with_max_size(1_000)
+            .with_max_dimensions(128, 128);
+
+        let entry = image_entry("image/png", png(64, 32));
+        let info = constraints.validate_entry(&entry).unwrap().unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Png, 64, 32)
+        );
+
+        let entry = image_entry("image/png", png(256, 32));
+        assert!(matches!(
+            constraints.validate_entry(&entry),
+            Some(Err(ImageError::TooManyPixels { width: 256, .. }))
+        ));
+
+        let entry = image_entry("image/jpeg", png(64, 32));
+        assert!(matches!(
+            constraints.validate_entry(&entry),
+            Some(Err(ImageError::TypeMismatch { .. }))
+        ));
+
+        let entry = image_entry("image/svg+xml", b"<svg/>".to_vec());
+        assert!(matches!(
+            constraints.validate_entry(&entry),
+            Some(Err(ImageError::UnsupportedType(_)))
+        ));
+
+        let metadata = AddressMetadata {
+            entries: vec![Entry::default(), image_entry("image/png", vec![0; 2_000])],
+            ..Default::default()
+        };
+        let err = constraints.validate_metadata(&metadata).unwrap_err();
+        assert_eq!(err.kind, "profile/avatar");
+        assert!(matches!(
+            err.error,
+            ImageError::TooLarge { size: 2_000, .. }
+        ));
+    }
+}
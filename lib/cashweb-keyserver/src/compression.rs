@@ -0,0 +1,190 @@
+//! This module contains [`Encoding`], used to compress metadata bodies in transport, negotiated
+//! using the `Content-Encoding` and `Accept-Encoding` headers.
+//!
+//! Decompression is bounded, so that a small compressed body can't expand beyond the metadata
+//! size limit of a keyserver.
+//!
+//! Without the `compression` feature only [`Encoding::Identity`] is supported.
+
+#[cfg(feature = "compression")]
+use std::io::{Read, Write};
+use std::{fmt, io, str::FromStr};
+
+use thiserror::Error;
+
+/// The `Accept-Encoding` header value advertising every supported [`Encoding`].
+#[cfg(feature = "compression")]
+pub const ACCEPT_ENCODING: &str = "zstd, gzip";
+/// The `Accept-Encoding` header value advertising every supported [`Encoding`].
+#[cfg(not(feature = "compression"))]
+pub const ACCEPT_ENCODING: &str = "identity";
+
+/// Compression level used for zstd.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Error associated with decompressing a body.
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    /// The content encoding is not supported.
+    #[error("unsupported content encoding: {0}")]
+    Unsupported(String),
+    /// The decompressed body exceeded the limit.
+    #[error("decompressed body exceeds {0} bytes")]
+    TooLarge(usize),
+    /// The body was malformed.
+    #[error("malformed body: {0}")]
+    Io(io::Error),
+}
+
+/// A content encoding of a metadata body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// No compression.
+    #[default]
+    Identity,
+    /// gzip compression.
+    #[cfg(feature = "compression")]
+    Gzip,
+    /// zstd compression.
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = CompressionError;
+
+    /// Parse a `Content-Encoding` header value.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(Self::Identity),
+            #[cfg(feature = "compression")]
+            "gzip" | "x-gzip" => Ok(Self::Gzip),
+            #[cfg(feature = "compression")]
+            "zstd" => Ok(Self::Zstd),
+            other => Err(CompressionError::Unsupported(other.to_string())),
+        }
+    }
+}
+
+impl Encoding {
+    /// The header value of the encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            #[cfg(feature = "compression")]
+            Self::Gzip => "gzip",
+            #[cfg(feature = "compression")]
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Choose the preferred encoding accepted by an `Accept-Encoding` header value, preferring
+    /// zstd over gzip and falling back to [`Encoding::Identity`].
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        let accepted = |encoding: Encoding| {
+            accept_encoding.split(',').any(|item| {
+                let mut params = item.split(';');
+                // This is safe as split always yields at least one part
+                let name = params.next().unwrap().trim();
+                let is_rejected = params.any(|param| {
+                    matches!(param.trim().strip_prefix("q="), Some(q) if q.parse() == Ok(0.0))
+                });
+                (name.eq_ignore_ascii_case(encoding.as_str()) || name == "*") && !is_rejected
+            })
+        };
+        let preferred: &[Self] = &[
+            #[cfg(feature = "compression")]
+            Self::Zstd,
+            #[cfg(feature = "compression")]
+            Self::Gzip,
+        ];
+        preferred
+            .iter()
+            .copied()
+            .find(|encoding| accepted(*encoding))
+            .unwrap_or(Self::Identity)
+    }
+
+    /// Compress the body.
+    pub fn compress(&self, body: &[u8]) -> Vec<u8> {
+        // This is safe as writing to a vector can't fail
+        match self {
+            Self::Identity => body.to_vec(),
+            #[cfg(feature = "compression")]
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            }
+            #[cfg(feature = "compression")]
+            Self::Zstd => zstd::stream::encode_all(body, ZSTD_LEVEL).unwrap(),
+        }
+    }
+
+    /// Decompress the body, failing if the result would exceed `limit` bytes.
+    pub fn decompress(&self, body: &[u8], limit: usize) -> Result<Vec<u8>, CompressionError> {
+        let decompressed = match self {
+            Self::Identity => body.to_vec(),
+            #[cfg(feature = "compression")]
+            Self::Gzip => read_bounded(flate2::read::GzDecoder::new(body), limit)?,
+            #[cfg(feature = "compression")]
+            Self::Zstd => {
+                let decoder =
+                    zstd::stream::read::Decoder::new(body).map_err(CompressionError::Io)?;
+                read_bounded(decoder, limit)?
+            }
+        };
+        if decompressed.len() > limit {
+            return Err(CompressionError::TooLarge(limit));
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Read at most one byte past `limit`, so that oversized bodies are detected without being
+/// decompressed in full.
+#[cfg(feature = "compression")]
+fn read_bounded<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, CompressionError> {
+    let mut decompressed = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(CompressionError::Io)?;
+    Ok(decompressed)
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Encoding::negotiate("gzip, deflate"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate(ACCEPT_ENCODING), Encoding::Zstd);
+        assert_eq!(Encoding::negotiate("zstd;q=0, gzip"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("br"), Encoding::Identity);
+        assert_eq!("GZIP".parse::<Encoding>().unwrap(), Encoding::Gzip);
+        assert!("br".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let body = vec![7; 10_000];
+        for encoding in &[Encoding::Identity, Encoding::Gzip, Encoding::Zstd] {
+            let compressed = encoding.compress(&body);
+            assert_eq!(encoding.decompress(&compressed, body.len()).unwrap(), body);
+            assert!(matches!(
+                encoding.decompress(&compressed, body.len() - 1),
+                Err(CompressionError::TooLarge(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! This module contains helpers for [`DelegatedKey`] entries, with which the key of an address
+//! delegates some of its authority to a child key.
+//!
+//! A wallet may keep its identity key cold and delegate day-to-day work, such as signing relay
+//! messages, to a hot key. The delegation is published as an [`Entry`] of kind
+//! [`DELEGATED_KEY_KIND`] in the metadata of the address, signed by the parent key, and a verifier
+//! holding the metadata checks the chain using [`find_delegation`].
+
+use std::convert::TryInto;
+
+use prost::{DecodeError, Message as _};
+use ring::digest::{digest, SHA256};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Message, Secp256k1, Signature,
+};
+use thiserror::Error;
+
+use crate::{AddressMetadata, DelegatedKey, Entry};
+
+/// The kind of an [`Entry`] holding a [`DelegatedKey`].
+pub const DELEGATED_KEY_KIND: &str = "delegated-key";
+
+/// The scope delegating the signing of relay messages.
+pub const RELAY_SCOPE: &str = "relay";
+
+/// Error associated with parsing and verifying a [`DelegatedKey`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DelegationError {
+    /// The entry was not of kind [`DELEGATED_KEY_KIND`].
+    #[error("unexpected entry kind: {0}")]
+    UnexpectedKind(String),
+    /// The body could not be decoded.
+    #[error("failed to decode delegated key: {0}")]
+    Decode(DecodeError),
+    /// The child public key was invalid.
+    #[error("invalid child public key: {0}")]
+    PublicKey(SecpError),
+    /// The signature was an invalid format.
+    #[error("invalid signature: {0}")]
+    Signature(SecpError),
+    /// The signature failed verification against the parent key.
+    #[error("signature verification failed: {0}")]
+    InvalidSignature(SecpError),
+    /// The delegation expired.
+    #[error("delegation expired at {0}")]
+    Expired(i64),
+    /// The delegation does not cover the scope.
+    #[error("delegation does not cover scope {0:?}")]
+    OutOfScope(String),
+    /// No delegation to the child key was found.
+    #[error("key is not delegated")]
+    NotDelegated,
+}
+
+/// Represents a [`DelegatedKey`] post-parsing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedDelegatedKey {
+    /// The child public key.
+    pub public_key: PublicKey,
+    /// The scopes the child key may act within.
+    pub scopes: Vec<String>,
+    /// Time after which the delegation is invalid, or zero if it does not expire. Given in
+    /// milliseconds.
+    pub expiry: i64,
+    /// The signature by the parent key.
+    pub signature: Signature,
+}
+
+/// The SHA256 digest covered by the parent signature.
+fn signing_digest(public_key: &PublicKey, scopes: &[String], expiry: i64) -> [u8; 32] {
+    let unsigned = DelegatedKey {
+        public_key: public_key.serialize().to_vec(),
+        scopes: scopes.to_vec(),
+        expiry,
+        signature: Vec::new(),
+    };
+    let mut raw_unsigned = Vec::with_capacity(unsigned.encoded_len());
+    unsigned.encode(&mut raw_unsigned).unwrap(); // This is safe
+    digest(&SHA256, &raw_unsigned).as_ref().try_into().unwrap() // This is safe
+}
+
+impl DelegatedKey {
+    /// Delegate the scopes to a child key, signing with the parent secret key.
+    pub fn sign(
+        public_key: &PublicKey,
+        scopes: Vec<String>,
+        expiry: i64,
+        parent_secret_key: &SecretKey,
+    ) -> Self {
+        let digest = signing_digest(public_key, &scopes, expiry);
+        let msg = Message::from_slice(&digest).unwrap(); // This is safe
+        let signature = Secp256k1::signing_only().sign(&msg, parent_secret_key);
+        Self {
+            public_key: public_key.serialize().to_vec(),
+            scopes,
+            expiry,
+            signature: signature.serialize_compact().to_vec(),
+        }
+    }
+
+    /// Parse a [`DelegatedKey`] from an [`Entry`] of kind [`DELEGATED_KEY_KIND`].
+    pub fn from_entry(entry: &Entry) -> Result<Self, DelegationError> {
+        if entry.kind != DELEGATED_KEY_KIND {
+            return Err(DelegationError::UnexpectedKind(entry.kind.clone()));
+        }
+        Self::decode(entry.body.as_slice()).map_err(DelegationError::Decode)
+    }
+
+    /// Convert into an [`Entry`] of kind [`DELEGATED_KEY_KIND`].
+    pub fn to_entry(&self) -> Entry {
+        let mut body = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut body).unwrap(); // This is safe
+        Entry {
+            kind: DELEGATED_KEY_KIND.to_string(),
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// Parse the [`DelegatedKey`] to construct a [`ParsedDelegatedKey`].
+    pub fn parse(self) -> Result<ParsedDelegatedKey, DelegationError> {
+        let public_key =
+            PublicKey::from_slice(&self.public_key).map_err(DelegationError::PublicKey)?;
+        let signature =
+            Signature::from_compact(&self.signature).map_err(DelegationError::Signature)?;
+        Ok(ParsedDelegatedKey {
+            public_key,
+            scopes: self.scopes,
+            expiry: self.expiry,
+            signature,
+        })
+    }
+}
+
+impl ParsedDelegatedKey {
+    /// Verify the signature of the parent key.
+    pub fn verify(&self, parent: &PublicKey) -> Result<(), DelegationError> {
+        let digest = signing_digest(&self.public_key, &self.scopes, self.expiry);
+        let msg = Message::from_slice(&digest).unwrap(); // This is safe
+        Secp256k1::verification_only()
+            .verify(&msg, &self.signature, parent)
+            .map_err(DelegationError::InvalidSignature)
+    }
+
+    /// Check that the delegation covers the scope and has not expired at `now`, given in
+    /// milliseconds.
+    pub fn check(&self, scope: &str, now: i64) -> Result<(), DelegationError> {
+        if !self.scopes.iter().any(|delegated| delegated == scope) {
+            return Err(DelegationError::OutOfScope(scope.to_string()));
+        }
+        if self.expiry != 0 && now >= self.expiry {
+            return Err(DelegationError::Expired(self.expiry));
+        }
+        Ok(())
+    }
+}
+
+/// Find a valid delegation of the scope from `parent` to `child` among the entries of `metadata`,
+/// signed by `parent`, at `now`, given in milliseconds.
+///
+/// If the child key is delegated by several entries, the first valid one is returned. Otherwise the
+/// error of the last entry delegating to the child key is returned, or
+/// [`DelegationError::NotDelegated`] if there is none.
+pub fn find_delegation(
+    parent: &PublicKey,
+    metadata: &AddressMetadata,
+    child: &PublicKey,
+    scope: &str,
+    now: i64,
+) -> Result<ParsedDelegatedKey, DelegationError> {
+    let mut last_error = DelegationError::NotDelegated;
+    let delegations = metadata
+        .entries
+        .iter()
+        .filter(|entry| entry.kind == DELEGATED_KEY_KIND)
+        .filter_map(|entry| DelegatedKey::from_entry(entry).ok())
+        .filter_map(|delegated_key| delegated_key.parse().ok())
+        .filter(|delegated_key| &delegated_key.public_key == child);
+    for delegated_key in delegations {
+        let result = delegated_key
+            .verify(parent)
+            .and_then(|_| delegated_key.check(scope, now));
+        match result {
+            Ok(()) => return Ok(delegated_key),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_pair(byte: u8) -> (SecretKey, PublicKey) {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+        (secret_key, public_key)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (parent_secret_key, parent) = key_pair(1);
+        let (_, child) = key_pair(2);
+
+        let delegated_key =
+            DelegatedKey::sign(&child, vec![RELAY_SCOPE.to_string()], 0, &parent_secret_key);
+        let parsed = DelegatedKey::from_entry(&delegated_key.to_entry())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(parsed.public_key, child);
+        parsed.verify(&parent).unwrap();
+        assert!(matches!(
+            parsed.verify(&child),
+            Err(DelegationError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn find() {
+        let (parent_secret_key, parent) = key_pair(1);
+        let (_, child) = key_pair(2);
+        let (forger_secret_key, _) = key_pair(3);
+
+        let forged =
+            DelegatedKey::sign(&child, vec![RELAY_SCOPE.to_string()], 0, &forger_secret_key);
+        let expiring = DelegatedKey::sign(
+            &child,
+            vec![RELAY_SCOPE.to_string()],
+            1_000,
+            &parent_secret_key,
+        );
+        let metadata = AddressMetadata {
+            entries: vec![forged.to_entry(), expiring.to_entry()],
+            ..Default::default()
+        };
+
+        let found = find_delegation(&parent, &metadata, &child, RELAY_SCOPE, 500).unwrap();
+        assert_eq!(found.expiry, 1_000);
+        assert_eq!(
+            find_delegation(&parent, &metadata, &child, RELAY_SCOPE, 1_000),
+            Err(DelegationError::Expired(1_000))
+        );
+        assert_eq!(
+            find_delegation(&parent, &metadata, &child, "payments", 500),
+            Err(DelegationError::OutOfScope("payments".to_string()))
+        );
+        assert_eq!(
+            find_delegation(&parent, &metadata, &parent, RELAY_SCOPE, 500),
+            Err(DelegationError::NotDelegated)
+        );
+    }
+}
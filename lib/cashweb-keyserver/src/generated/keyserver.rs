@@ -0,0 +1,234 @@
+/// Basic key/value used to store header data.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Header {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub value: ::prost::alloc::string::String,
+}
+/// Entry is an individual piece of structured data provided by wallet authors.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Entry {
+    /// Kind is a hint to wallets as to what type of data to deserialize from the
+    /// metadata field.
+    #[prost(string, tag="1")]
+    pub kind: ::prost::alloc::string::String,
+    /// The headers is excess metadata that may be useful to a wallet.
+    #[prost(message, repeated, tag="2")]
+    pub headers: ::prost::alloc::vec::Vec<Header>,
+    /// Body of the `Entry`.
+    #[prost(bytes="vec", tag="3")]
+    pub body: ::prost::alloc::vec::Vec<u8>,
+}
+/// AddressMetadata is the user-specified data that is covered by the users
+/// signature.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AddressMetadata {
+    /// Timestamp allows servers to determine which version of the data is the most
+    /// recent. Given in milliseconds.
+    #[prost(int64, tag="1")]
+    pub timestamp: i64,
+    /// TTL tells us how long this entry should exist before being considered
+    /// invalid. Given in milliseconds.
+    #[prost(int64, tag="2")]
+    pub ttl: i64,
+    /// User specified data.  Presumably some conventional data determined by
+    /// wallet authors.
+    #[prost(message, repeated, tag="3")]
+    pub entries: ::prost::alloc::vec::Vec<Entry>,
+}
+/// Peer represents a single peer.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Peer {
+    /// The URL pointing to the root of the keyserver REST API.
+    #[prost(string, tag="1")]
+    pub url: ::prost::alloc::string::String,
+    /// The round-trip latency of the last probe of the keyserver. Given in milliseconds, zero if
+    /// unknown.
+    #[prost(uint32, tag="2")]
+    pub latency: u32,
+    /// The time the keyserver was last seen responding. Given in milliseconds, zero if unknown.
+    #[prost(int64, tag="3")]
+    pub last_seen: i64,
+    /// The version advertised by the keyserver, empty if unknown.
+    #[prost(string, tag="4")]
+    pub version: ::prost::alloc::string::String,
+}
+/// A list of peers.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Peers {
+    #[prost(message, repeated, tag="1")]
+    pub peers: ::prost::alloc::vec::Vec<Peer>,
+}
+/// An address paired with its metadata, as held by a keyserver.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetadataEntry {
+    /// The address payload.
+    #[prost(bytes="vec", tag="1")]
+    pub address: ::prost::alloc::vec::Vec<u8>,
+    /// The serialized authorization wrapper covering the `AddressMetadata`.
+    #[prost(bytes="vec", tag="2")]
+    pub raw_auth_wrapper: ::prost::alloc::vec::Vec<u8>,
+    /// The raw POP token used to put the metadata.
+    #[prost(bytes="vec", tag="3")]
+    pub token: ::prost::alloc::vec::Vec<u8>,
+    /// The timestamp of the `AddressMetadata`. Given in milliseconds.
+    #[prost(int64, tag="4")]
+    pub timestamp: i64,
+    /// The namespace of the `AddressMetadata`, or empty for the root document.
+    #[prost(string, tag="5")]
+    pub namespace: ::prost::alloc::string::String,
+}
+/// A page of metadata, in ascending order of timestamp, used in replication
+/// between keyservers.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetadataPage {
+    #[prost(message, repeated, tag="1")]
+    pub entries: ::prost::alloc::vec::Vec<MetadataEntry>,
+}
+/// An archive of the metadata held by a keyserver, used for backups and
+/// migrations between storage backends.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetadataArchive {
+    /// The version of the archive format.
+    #[prost(uint32, tag="1")]
+    pub version: u32,
+    /// The time the archive was created. Given in milliseconds.
+    #[prost(int64, tag="2")]
+    pub timestamp: i64,
+    /// The entries, in ascending order of timestamp.
+    #[prost(message, repeated, tag="3")]
+    pub entries: ::prost::alloc::vec::Vec<MetadataEntry>,
+}
+/// An address whose handle or name matched a search.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SearchMatch {
+    /// The address, in cashaddr format.
+    #[prost(string, tag="1")]
+    pub address: ::prost::alloc::string::String,
+    /// The matched handle or name.
+    #[prost(string, tag="2")]
+    pub name: ::prost::alloc::string::String,
+    /// SHA256 digest of the serialized `AddressMetadata` the name was found in.
+    #[prost(bytes="vec", tag="3")]
+    pub payload_digest: ::prost::alloc::vec::Vec<u8>,
+    /// The timestamp of the `AddressMetadata`. Given in milliseconds.
+    #[prost(int64, tag="4")]
+    pub timestamp: i64,
+}
+/// A page of search matches, in ascending order of name.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SearchPage {
+    #[prost(message, repeated, tag="1")]
+    pub matches: ::prost::alloc::vec::Vec<SearchMatch>,
+    /// Opaque cursor from which the next page is requested, or empty if this is
+    /// the last page.
+    #[prost(string, tag="2")]
+    pub next_cursor: ::prost::alloc::string::String,
+}
+/// A patch to the `AddressMetadata` of an address, sent in place of the whole
+/// document.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetadataPatch {
+    /// SHA256 digest of the serialized `AddressMetadata` the patch applies to.
+    #[prost(bytes="vec", tag="1")]
+    pub base_digest: ::prost::alloc::vec::Vec<u8>,
+    /// Timestamp of the patched `AddressMetadata`. Given in milliseconds.
+    #[prost(int64, tag="2")]
+    pub timestamp: i64,
+    /// TTL of the patched `AddressMetadata`. Given in milliseconds.
+    #[prost(int64, tag="3")]
+    pub ttl: i64,
+    /// Entries replacing the first entry of the same kind, or appended if no
+    /// entry has the kind.
+    #[prost(message, repeated, tag="4")]
+    pub upserts: ::prost::alloc::vec::Vec<Entry>,
+    /// Kinds of the entries to remove.
+    #[prost(string, repeated, tag="5")]
+    pub removals: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// The serialized authorization wrapper covering the patched
+    /// `AddressMetadata`. The payload is omitted in favour of the payload digest.
+    #[prost(bytes="vec", tag="6")]
+    pub raw_auth_wrapper: ::prost::alloc::vec::Vec<u8>,
+}
+/// A delegation, by the key of an address, of some of its authority to a
+/// child key. Held in the body of an `Entry` of kind `delegated-key`.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DelegatedKey {
+    /// The child public key, in compressed form.
+    #[prost(bytes="vec", tag="1")]
+    pub public_key: ::prost::alloc::vec::Vec<u8>,
+    /// The scopes the child key may act within, such as `relay`.
+    #[prost(string, repeated, tag="2")]
+    pub scopes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Time after which the delegation is invalid, or zero if it does not
+    /// expire. Given in milliseconds.
+    #[prost(int64, tag="3")]
+    pub expiry: i64,
+    /// Compact ECDSA signature by the parent key over the SHA256 digest of the
+    /// `DelegatedKey` with this field empty.
+    #[prost(bytes="vec", tag="4")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
+/// A notification, delivered by webhook, that the metadata of an address was
+/// updated.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetadataNotification {
+    /// The address, in cashaddr format.
+    #[prost(string, tag="1")]
+    pub address: ::prost::alloc::string::String,
+    /// The namespace of the updated `AddressMetadata`, or empty for the root
+    /// document.
+    #[prost(string, tag="2")]
+    pub namespace: ::prost::alloc::string::String,
+    /// The timestamp of the updated `AddressMetadata`. Given in milliseconds.
+    #[prost(int64, tag="3")]
+    pub timestamp: i64,
+    /// The serialized authorization wrapper covering the updated
+    /// `AddressMetadata`.
+    #[prost(bytes="vec", tag="4")]
+    pub raw_auth_wrapper: ::prost::alloc::vec::Vec<u8>,
+}
+/// Statistics of the storage of a keyserver, returned by the admin API.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StorageStats {
+    /// The number of root metadata documents.
+    #[prost(uint64, tag="1")]
+    pub metadata: u64,
+    /// The number of namespaced metadata documents.
+    #[prost(uint64, tag="2")]
+    pub namespaced_metadata: u64,
+    /// The total size of the stored metadata, in bytes.
+    #[prost(uint64, tag="3")]
+    pub metadata_bytes: u64,
+    /// The number of stored POP tokens.
+    #[prost(uint64, tag="4")]
+    pub tokens: u64,
+    /// The number of known peers.
+    #[prost(uint64, tag="5")]
+    pub peers: u64,
+}
+/// The result of purging an address, returned by the admin API.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PurgeSummary {
+    /// The number of metadata documents removed, including namespaces.
+    #[prost(uint32, tag="1")]
+    pub removed: u32,
+}
+/// The result of a forced replication pass, returned by the admin API.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReplicationSummary {
+    /// The number of entries written to the local store.
+    #[prost(uint64, tag="1")]
+    pub accepted: u64,
+    /// The number of entries discarded as the local store held metadata at
+    /// least as recent.
+    #[prost(uint64, tag="2")]
+    pub stale: u64,
+    /// The number of entries discarded as they failed verification.
+    #[prost(uint64, tag="3")]
+    pub invalid: u64,
+    /// The URLs of the peers which could not be replicated from.
+    #[prost(string, repeated, tag="4")]
+    pub failed_peers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
@@ -0,0 +1,48 @@
+//! This module contains helpers for the `Idempotency-Key` header of metadata writes.
+//!
+//! A keyserver remembers the outcome of a write carrying an idempotency key, so that a client
+//! retrying after a timeout receives the original outcome rather than writing again. A retry may
+//! otherwise overwrite a newer write, or be broadcast to peers a second time.
+
+use ring::digest::{digest, SHA256};
+
+/// The name of the header carrying the idempotency key of a metadata write.
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// The name of the header set on the response to a replayed write.
+pub const IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+
+/// Maximum length of an idempotency key, in bytes.
+pub const MAX_KEY_LEN: usize = 255;
+
+/// Whether the idempotency key is non-empty, at most [`MAX_KEY_LEN`] bytes and visible ASCII.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && key.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Derive an idempotency key from the body of a write, given as the hex SHA256 digest of the body.
+///
+/// Retries of the same body share the key, without the client having to remember it.
+pub fn derive_key(body: &[u8]) -> String {
+    digest(&SHA256, body)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let key = derive_key(b"metadata");
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, derive_key(b"metadata"));
+        assert!(is_valid_key(&key));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("with space"));
+        assert!(!is_valid_key(&"a".repeat(MAX_KEY_LEN + 1)));
+    }
+}
@@ -1,3 +1,19 @@
 #![warn(missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
 
+pub mod admin;
+pub mod avatar;
+pub mod compression;
+pub mod delegation;
+pub mod idempotency;
+pub mod namespace;
+pub mod patch;
+pub mod payment_address;
+pub mod peers;
+pub mod store;
+pub mod unknown;
+pub mod vcard;
+
+#[cfg(feature = "codegen")]
 include!(concat!(env!("OUT_DIR"), "/keyserver.rs"));
+#[cfg(not(feature = "codegen"))]
+include!("generated/keyserver.rs");
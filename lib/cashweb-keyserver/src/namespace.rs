@@ -0,0 +1,252 @@
+//! This module contains [`Namespace`], which partitions the [`Entry`]s of an address's metadata
+//! so that applications can update their slice without re-uploading the whole document.
+//!
+//! An entry belongs to a namespace if its kind is the namespace or starts with the namespace
+//! followed by [`SEPARATOR`]. For example, the kinds `profile/name` and `profile/avatar` belong to
+//! the `profile` namespace, and `app.xyz/settings` belongs to the `app.xyz` namespace.
+//!
+//! Each namespace is held in its own [`AddressMetadata`], signed independently of the root
+//! document and of other namespaces. [`overlay`] combines them into a single view.
+
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::{AddressMetadata, Entry};
+
+/// Separator between the segments of a namespace, and between a namespace and the rest of an
+/// entry kind.
+pub const SEPARATOR: char = '/';
+
+/// Maximum length of a namespace, in bytes.
+pub const MAX_LEN: usize = 128;
+
+/// Error associated with parsing a [`Namespace`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum NamespaceError {
+    /// The namespace was empty.
+    #[error("namespace is empty")]
+    Empty,
+    /// The namespace exceeded [`MAX_LEN`].
+    #[error("namespace exceeds {MAX_LEN} bytes")]
+    TooLong,
+    /// A segment of the namespace was empty or contained a character other than lowercase ASCII
+    /// letters, digits, `.`, `-` and `_`.
+    #[error("invalid namespace segment: {0:?}")]
+    InvalidSegment(String),
+}
+
+/// An entry of a namespaced document lay outside of its namespace.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("entry kind {kind:?} is outside of namespace {namespace}")]
+pub struct OutsideNamespace {
+    /// The namespace of the document.
+    pub namespace: Namespace,
+    /// The kind of the offending entry.
+    pub kind: String,
+}
+
+/// A validated metadata namespace, such as `profile` or `app.xyz/settings`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Namespace(String);
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Namespace {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Namespace {
+    /// Parse a namespace.
+    pub fn new<N: Into<String>>(namespace: N) -> Result<Self, NamespaceError> {
+        let namespace = namespace.into();
+        if namespace.is_empty() {
+            return Err(NamespaceError::Empty);
+        }
+        if namespace.len() > MAX_LEN {
+            return Err(NamespaceError::TooLong);
+        }
+        for segment in namespace.split(SEPARATOR) {
+            let is_valid = !segment.is_empty()
+                && segment
+                    .bytes()
+                    .all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_'));
+            if !is_valid {
+                return Err(NamespaceError::InvalidSegment(segment.to_string()));
+            }
+        }
+        Ok(Self(namespace))
+    }
+
+    /// The top-level namespace of an entry kind, or `None` if the kind is not namespaced.
+    ///
+    /// For example, the top-level namespace of `profile/avatar` is `profile`.
+    pub fn of_kind(kind: &str) -> Option<Self> {
+        let (namespace, _) = kind.split_once(SEPARATOR)?;
+        Self::new(namespace).ok()
+    }
+
+    /// The namespace as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether an entry kind belongs to the namespace.
+    pub fn contains(&self, kind: &str) -> bool {
+        match kind.strip_prefix(self.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with(SEPARATOR),
+            None => false,
+        }
+    }
+
+    /// Check that every entry of the metadata belongs to the namespace.
+    pub fn check(&self, metadata: &AddressMetadata) -> Result<(), OutsideNamespace> {
+        match metadata
+            .entries
+            .iter()
+            .find(|entry| !self.contains(&entry.kind))
+        {
+            Some(entry) => Err(OutsideNamespace {
+                namespace: self.clone(),
+                kind: entry.kind.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The [`MetadataStore`] key of the metadata of an address within a namespace.
+///
+/// The root document, where `namespace` is empty, is keyed by the address payload alone.
+///
+/// [`MetadataStore`]: crate::store::MetadataStore
+pub fn metadata_key(address: &[u8], namespace: &str) -> Vec<u8> {
+    if namespace.is_empty() {
+        return address.to_vec();
+    }
+    let mut key = Vec::with_capacity(address.len() + 1 + namespace.len());
+    key.extend_from_slice(address);
+    key.push(SEPARATOR as u8);
+    key.extend_from_slice(namespace.as_bytes());
+    key
+}
+
+/// The address payload of a [`metadata_key`], given the namespace it was created with.
+pub fn split_metadata_key<'a>(key: &'a [u8], namespace: &str) -> &'a [u8] {
+    if namespace.is_empty() {
+        return key;
+    }
+    let suffix_len = namespace.len() + 1;
+    &key[..key.len().saturating_sub(suffix_len)]
+}
+
+/// Overlay namespaced documents onto a root document.
+///
+/// Entries of the root document belonging to an overlaid namespace are replaced by the entries of
+/// that namespace. The timestamp of the result is the latest of the documents, and the TTL the
+/// shortest non-zero TTL.
+pub fn overlay<I>(mut root: AddressMetadata, namespaces: I) -> AddressMetadata
+where
+    I: IntoIterator<Item = (Namespace, AddressMetadata)>,
+{
+    for (namespace, metadata) in namespaces {
+        root.entries
+            .retain(|entry| !namespace.contains(&entry.kind));
+        root.entries.extend(
+            metadata
+                .entries
+                .into_iter()
+                .filter(|entry: &Entry| namespace.contains(&entry.kind)),
+        );
+        root.timestamp = root.timestamp.max(metadata.timestamp);
+        if metadata.ttl != 0 && (root.ttl == 0 || metadata.ttl < root.ttl) {
+            root.ttl = metadata.ttl;
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: &str) -> Entry {
+        Entry {
+            kind: kind.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert!(Namespace::new("profile").is_ok());
+        assert!(Namespace::new("app.xyz/settings").is_ok());
+        assert_eq!(Namespace::new(""), Err(NamespaceError::Empty));
+        assert_eq!(
+            Namespace::new("profile//name"),
+            Err(NamespaceError::InvalidSegment(String::new()))
+        );
+        assert_eq!(
+            Namespace::new("Profile"),
+            Err(NamespaceError::InvalidSegment("Profile".to_string()))
+        );
+        assert_eq!(
+            Namespace::of_kind("profile/avatar"),
+            Namespace::new("profile").ok()
+        );
+        assert_eq!(Namespace::of_kind("avatar"), None);
+    }
+
+    #[test]
+    fn contains() {
+        let namespace = Namespace::new("profile").unwrap();
+        assert!(namespace.contains("profile"));
+        assert!(namespace.contains("profile/avatar"));
+        assert!(!namespace.contains("profiles/avatar"));
+        assert!(!namespace.contains("payment/address"));
+
+        let metadata = AddressMetadata {
+            entries: vec![entry("profile/name"), entry("payment/address")],
+            ..Default::default()
+        };
+        assert_eq!(
+            namespace.check(&metadata).unwrap_err().kind,
+            "payment/address"
+        );
+    }
+
+    #[test]
+    fn keys() {
+        let address = [0, b'/', 1];
+        assert_eq!(metadata_key(&address, ""), address.to_vec());
+        let key = metadata_key(&address, "app.xyz");
+        assert_eq!(key, b"\x00/\x01/app.xyz".to_vec());
+        assert_eq!(split_metadata_key(&key, "app.xyz"), &address[..]);
+    }
+
+    #[test]
+    fn overlay_namespaces() {
+        let root = AddressMetadata {
+            timestamp: 100,
+            ttl: 1_000,
+            entries: vec![entry("profile/name"), entry("payment/address")],
+        };
+        let profile = AddressMetadata {
+            timestamp: 200,
+            ttl: 500,
+            entries: vec![entry("profile/avatar"), entry("payment/ignored")],
+        };
+        let combined = overlay(root, vec![(Namespace::new("profile").unwrap(), profile)]);
+
+        let kinds: Vec<_> = combined.entries.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["payment/address", "profile/avatar"]);
+        assert_eq!(combined.timestamp, 200);
+        assert_eq!(combined.ttl, 500);
+    }
+}
@@ -0,0 +1,129 @@
+//! This module contains functions for creating and applying a [`MetadataPatch`], which carries
+//! only the changed and removed entries of an [`AddressMetadata`].
+//!
+//! The authorization wrapper of a patch covers the patched document, so [`apply`] must reproduce
+//! it exactly. [`diff`] returns `None` where a patch would not, for example if the new document
+//! reorders entries or repeats a kind, in which case the whole document should be uploaded.
+
+use crate::{AddressMetadata, Entry, MetadataPatch};
+
+/// Apply the patch to the base document.
+///
+/// Entries whose kind is removed are dropped, then each upsert replaces the first entry of the
+/// same kind or, if there is none, is appended. The timestamp and TTL are taken from the patch.
+pub fn apply(base: &AddressMetadata, patch: &MetadataPatch) -> AddressMetadata {
+    let mut entries: Vec<Entry> = base
+        .entries
+        .iter()
+        .filter(|entry| !patch.removals.contains(&entry.kind))
+        .cloned()
+        .collect();
+    for upsert in &patch.upserts {
+        match entries.iter_mut().find(|entry| entry.kind == upsert.kind) {
+            Some(entry) => *entry = upsert.clone(),
+            None => entries.push(upsert.clone()),
+        }
+    }
+    AddressMetadata {
+        timestamp: patch.timestamp,
+        ttl: patch.ttl,
+        entries,
+    }
+}
+
+/// Create a patch from `old`, whose serialization has the SHA256 digest `base_digest`, to `new`.
+///
+/// Returns `None` if applying the patch would not reproduce `new`. The authorization wrapper of
+/// the patch is left empty.
+pub fn diff(
+    base_digest: Vec<u8>,
+    old: &AddressMetadata,
+    new: &AddressMetadata,
+) -> Option<MetadataPatch> {
+    let mut removals: Vec<String> = Vec::new();
+    for entry in &old.entries {
+        let is_removed = !new.entries.iter().any(|new| new.kind == entry.kind);
+        if is_removed && !removals.contains(&entry.kind) {
+            removals.push(entry.kind.clone());
+        }
+    }
+    let upserts = new
+        .entries
+        .iter()
+        .filter(|entry| !old.entries.contains(entry))
+        .cloned()
+        .collect();
+    let patch = MetadataPatch {
+        base_digest,
+        timestamp: new.timestamp,
+        ttl: new.ttl,
+        upserts,
+        removals,
+        raw_auth_wrapper: Vec::new(),
+    };
+
+    if &apply(old, &patch) == new {
+        Some(patch)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: &str, body: &[u8]) -> Entry {
+        Entry {
+            kind: kind.to_string(),
+            body: body.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn metadata(timestamp: i64, entries: Vec<Entry>) -> AddressMetadata {
+        AddressMetadata {
+            timestamp,
+            ttl: 1_000,
+            entries,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let old = metadata(
+            100,
+            vec![
+                entry("profile/name", b"alice"),
+                entry("profile/avatar", b"png"),
+                entry("payment/address", b"addr"),
+            ],
+        );
+        let new = metadata(
+            200,
+            vec![
+                entry("profile/name", b"alice"),
+                entry("profile/avatar", b"jpeg"),
+                entry("app.xyz/settings", b"{}"),
+            ],
+        );
+
+        let patch = diff(vec![1; 32], &old, &new).unwrap();
+        assert_eq!(patch.upserts.len(), 2);
+        assert_eq!(patch.removals, vec!["payment/address".to_string()]);
+        assert_eq!(apply(&old, &patch), new);
+    }
+
+    #[test]
+    fn unrepresentable() {
+        let old = metadata(100, vec![entry("a", b"1"), entry("b", b"2")]);
+
+        // Reordered entries
+        let new = metadata(200, vec![entry("b", b"2"), entry("a", b"1")]);
+        assert_eq!(diff(vec![], &old, &new), None);
+
+        // Repeated kinds
+        let new = metadata(200, vec![entry("a", b"1"), entry("a", b"3")]);
+        assert_eq!(diff(vec![], &old, &new), None);
+    }
+}
@@ -0,0 +1,53 @@
+//! This module contains helpers for payment address entries, with which the metadata of an
+//! address advertises fresh output scripts for senders to pay, so that payments to the address
+//! are not linked on chain.
+//!
+//! Payment addresses are published in the [`PAYMENTS_NAMESPACE`] namespace, so that they may be
+//! rotated without re-signing the root document.
+
+use crate::{namespace::Namespace, AddressMetadata, Entry};
+
+/// The namespace holding payment address entries.
+pub const PAYMENTS_NAMESPACE: &str = "payments";
+
+/// The kind of an [`Entry`] whose body is an output script to pay.
+pub const PAYMENT_ADDRESS_KIND: &str = "payments/address";
+
+/// The [`Namespace`] holding payment address entries.
+pub fn payments_namespace() -> Namespace {
+    Namespace::new(PAYMENTS_NAMESPACE).unwrap() // This is safe
+}
+
+/// Construct an [`Entry`] of kind [`PAYMENT_ADDRESS_KIND`] paying the output script.
+pub fn to_entry(script: Vec<u8>) -> Entry {
+    Entry {
+        kind: PAYMENT_ADDRESS_KIND.to_string(),
+        headers: Vec::new(),
+        body: script,
+    }
+}
+
+/// The output scripts advertised by the metadata, in order of preference.
+pub fn payment_scripts(metadata: &AddressMetadata) -> impl Iterator<Item = &[u8]> {
+    metadata
+        .entries
+        .iter()
+        .filter(|entry| entry.kind == PAYMENT_ADDRESS_KIND)
+        .map(|entry| entry.body.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payment_scripts_in_namespace() {
+        let metadata = AddressMetadata {
+            entries: vec![to_entry(vec![1]), to_entry(vec![2])],
+            ..Default::default()
+        };
+        assert!(payments_namespace().check(&metadata).is_ok());
+        let scripts: Vec<_> = payment_scripts(&metadata).collect();
+        assert_eq!(scripts, vec![&[1][..], &[2][..]]);
+    }
+}
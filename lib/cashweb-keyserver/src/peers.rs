@@ -0,0 +1,314 @@
+//! This module contains the quality data carried by a [`Peer`], and the ranking of peers by it.
+//!
+//! Besides its URL, each field of a [`Peer`] is zero or empty when unknown. Keyservers advertise
+//! their version in the [`VERSION_HEADER`] of their responses, while latency and last-seen are
+//! measured by whoever probes the peer.
+//!
+//! An encoded [`Peers`](crate::Peers) is a sequence of length-prefixed [`Peer`] entries, which
+//! the [`PeersDecoder`] decodes as its bytes arrive, so that large peer lists need not be buffered.
+
+use std::{cmp::Reverse, collections::HashMap, time::Duration};
+
+use prost::Message as _;
+use thiserror::Error;
+
+use crate::Peer;
+
+/// The name of the header in which keyservers advertise their version.
+pub const VERSION_HEADER: &str = "Keyserver-Version";
+
+impl Peer {
+    /// Create a peer, with unknown quality data, from its URL.
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            ..Default::default()
+        }
+    }
+
+    /// The round-trip latency of the last probe, if known.
+    pub fn latency(&self) -> Option<Duration> {
+        if self.latency == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(self.latency as u64))
+        }
+    }
+
+    /// The time, in milliseconds, the peer was last seen responding, if known.
+    pub fn last_seen(&self) -> Option<i64> {
+        if self.last_seen == 0 {
+            None
+        } else {
+            Some(self.last_seen)
+        }
+    }
+
+    /// The version advertised by the peer, if known.
+    pub fn version(&self) -> Option<&str> {
+        if self.version.is_empty() {
+            None
+        } else {
+            Some(&self.version)
+        }
+    }
+
+    /// Fill the unknown quality data of the peer from another record of it.
+    ///
+    /// Known data is kept, except last-seen, which is advanced to the most recent of the two.
+    pub fn merge(&mut self, other: &Peer) {
+        if self.latency == 0 {
+            self.latency = other.latency;
+        }
+        self.last_seen = self.last_seen.max(other.last_seen);
+        if self.version.is_empty() {
+            self.version = other.version.clone();
+        }
+    }
+}
+
+/// Merge the records of each peer, keyed by URL, preserving the order of first appearance.
+pub fn dedup(peers: impl IntoIterator<Item = Peer>) -> Vec<Peer> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut deduped: Vec<Peer> = Vec::new();
+    for peer in peers {
+        match positions.get(&peer.url) {
+            Some(&position) => deduped[position].merge(&peer),
+            None => {
+                positions.insert(peer.url.clone(), deduped.len());
+                deduped.push(peer);
+            }
+        }
+    }
+    deduped
+}
+
+/// Sort peers best first: those with a known latency, fastest first, then the rest. Ties are
+/// broken by the most recently seen.
+pub fn rank(peers: &mut [Peer]) {
+    peers.sort_by_key(|peer| {
+        (
+            peer.latency().is_none(),
+            peer.latency,
+            Reverse(peer.last_seen),
+        )
+    });
+}
+
+/// Default maximum size of an encoded [`Peer`] entry, in bytes.
+pub const DEFAULT_MAX_PEER_LEN: usize = 16 * 1024;
+
+/// Error associated with incrementally decoding [`Peers`](crate::Peers).
+#[derive(Debug, Error)]
+pub enum PeersDecodeError {
+    /// A varint was longer than ten bytes.
+    #[error("invalid varint")]
+    Varint,
+    /// A field used the unsupported group wire type.
+    #[error("unsupported wire type {0}")]
+    WireType(u8),
+    /// A field exceeded the maximum entry size.
+    #[error("field of {0} bytes exceeds maximum")]
+    TooLarge(u64),
+    /// The body ended partway through a field.
+    #[error("body truncated")]
+    Truncated,
+    /// Failed to decode a [`Peer`] entry.
+    #[error("peer: {0}")]
+    Peer(prost::DecodeError),
+}
+
+/// Read a varint from the start of the buffer, returning it and its length, or `None` if the
+/// buffer ends first.
+fn read_varint(buf: &[u8]) -> Result<Option<(u64, usize)>, PeersDecodeError> {
+    let mut value = 0;
+    for (index, byte) in buf.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, index + 1)));
+        }
+    }
+    if buf.len() >= 10 {
+        return Err(PeersDecodeError::Varint);
+    }
+    Ok(None)
+}
+
+/// Incrementally decodes the [`Peer`] entries of an encoded [`Peers`](crate::Peers).
+///
+/// Bytes are given by [`PeersDecoder::push`] as they arrive, and complete entries taken by
+/// [`PeersDecoder::next_peer`]. Only the entry being decoded is buffered, and unknown fields are
+/// skipped.
+#[derive(Debug)]
+pub struct PeersDecoder {
+    buf: Vec<u8>,
+    max_peer_len: usize,
+}
+
+impl Default for PeersDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PeersDecoder {
+    /// Create a new [`PeersDecoder`], accepting entries of at most [`DEFAULT_MAX_PEER_LEN`] bytes.
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            max_peer_len: DEFAULT_MAX_PEER_LEN,
+        }
+    }
+
+    /// Set the maximum size of an encoded [`Peer`] entry, in bytes.
+    pub fn with_max_peer_len(mut self, max_peer_len: usize) -> Self {
+        self.max_peer_len = max_peer_len;
+        self
+    }
+
+    /// Append bytes of the encoded [`Peers`](crate::Peers).
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Decode the next [`Peer`], or `None` if more bytes are needed.
+    pub fn next_peer(&mut self) -> Result<Option<Peer>, PeersDecodeError> {
+        loop {
+            let (key, key_len) = match read_varint(&self.buf)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            let rest = &self.buf[key_len..];
+            let (field_len, prefix_len) = match key & 0x7 {
+                0 => match read_varint(rest)? {
+                    Some((_, len)) => (len, 0),
+                    None => return Ok(None),
+                },
+                1 => (8, 0),
+                2 => match read_varint(rest)? {
+                    Some((len, _)) if len > self.max_peer_len as u64 => {
+                        return Err(PeersDecodeError::TooLarge(len))
+                    }
+                    Some((len, prefix_len)) => (len as usize, prefix_len),
+                    None => return Ok(None),
+                },
+                5 => (4, 0),
+                wire_type => return Err(PeersDecodeError::WireType(wire_type as u8)),
+            };
+            let start = key_len + prefix_len;
+            let end = start + field_len;
+            if self.buf.len() < end {
+                return Ok(None);
+            }
+
+            // Field 1, length-delimited, holds an entry
+            let peer = if key == (1 << 3 | 2) {
+                Some(Peer::decode(&self.buf[start..end]).map_err(PeersDecodeError::Peer)?)
+            } else {
+                None
+            };
+            self.buf.drain(..end);
+            if peer.is_some() {
+                return Ok(peer);
+            }
+        }
+    }
+
+    /// Check the encoded [`Peers`](crate::Peers) ended between fields.
+    pub fn finish(&self) -> Result<(), PeersDecodeError> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(PeersDecodeError::Truncated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Peers;
+
+    use super::*;
+
+    fn peer(url: &str, latency: u32, last_seen: i64) -> Peer {
+        Peer {
+            url: url.to_string(),
+            latency,
+            last_seen,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merged() {
+        let mut measured = peer("a", 20, 100);
+        let advertised = Peer {
+            version: "0.2.0".to_string(),
+            ..peer("a", 5, 200)
+        };
+        measured.merge(&advertised);
+        assert_eq!(measured.latency(), Some(Duration::from_millis(20)));
+        assert_eq!(measured.last_seen(), Some(200));
+        assert_eq!(measured.version(), Some("0.2.0"));
+
+        let deduped = dedup(vec![
+            Peer::new("a".to_string()),
+            peer("b", 0, 0),
+            advertised,
+        ]);
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0].last_seen, 200);
+    }
+
+    #[test]
+    fn incremental() {
+        let peers = Peers {
+            peers: vec![
+                peer("a", 20, 100),
+                peer("b", 0, 0),
+                Peer::new("c".to_string()),
+            ],
+        };
+        let mut raw = Vec::with_capacity(peers.encoded_len());
+        peers.encode(&mut raw).unwrap();
+        // An unknown varint field is skipped
+        raw.extend_from_slice(&[2 << 3, 0x96, 0x01]);
+
+        // Byte by byte
+        let mut decoder = PeersDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in &raw {
+            decoder.push(&[*byte]);
+            while let Some(peer) = decoder.next_peer().unwrap() {
+                decoded.push(peer);
+            }
+        }
+        decoder.finish().unwrap();
+        assert_eq!(decoded, peers.peers);
+
+        let mut decoder = PeersDecoder::new();
+        decoder.push(&raw[..raw.len() - 1]);
+        while decoder.next_peer().unwrap().is_some() {}
+        assert!(matches!(decoder.finish(), Err(PeersDecodeError::Truncated)));
+
+        let mut decoder = PeersDecoder::new().with_max_peer_len(4);
+        decoder.push(&raw);
+        assert!(matches!(
+            decoder.next_peer(),
+            Err(PeersDecodeError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn ranked() {
+        let mut peers = vec![
+            peer("unknown", 0, 300),
+            peer("slow", 50, 100),
+            peer("fast", 10, 100),
+            peer("fast recent", 10, 200),
+        ];
+        rank(&mut peers);
+        let urls: Vec<_> = peers.iter().map(|peer| peer.url.as_str()).collect();
+        assert_eq!(urls, ["fast recent", "fast", "slow", "unknown"]);
+    }
+}
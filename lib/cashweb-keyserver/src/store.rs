@@ -0,0 +1,148 @@
+//! This module contains the [`MetadataStore`] trait, abstracting the storage backend of a
+//! keyserver, and the in-memory [`MemoryMetadataStore`].
+
+use std::{collections::HashMap, convert::Infallible, fmt, sync::RwLock};
+
+use async_trait::async_trait;
+
+/// Metadata held in a [`MetadataStore`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoredMetadata {
+    /// The serialized authorization wrapper covering the [`AddressMetadata`].
+    ///
+    /// [`AddressMetadata`]: crate::AddressMetadata
+    pub raw_auth_wrapper: Vec<u8>,
+    /// The raw POP token used to put the metadata.
+    pub token: Vec<u8>,
+    /// The timestamp of the [`AddressMetadata`], in milliseconds.
+    ///
+    /// [`AddressMetadata`]: crate::AddressMetadata
+    pub timestamp: i64,
+    /// The [`Namespace`] of the metadata, or empty for the root document.
+    ///
+    /// [`Namespace`]: crate::namespace::Namespace
+    pub namespace: String,
+}
+
+/// Persists metadata, keyed by address payload and indexed by timestamp.
+///
+/// Namespaced metadata is keyed by [`metadata_key`], which is the address payload for the root
+/// document.
+///
+/// [`metadata_key`]: crate::namespace::metadata_key
+#[async_trait]
+pub trait MetadataStore {
+    /// Error associated with the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get the metadata for the address.
+    async fn get(&self, address: &[u8]) -> Result<Option<StoredMetadata>, Self::Error>;
+
+    /// Put metadata, replacing any existing metadata for the address.
+    async fn put(&self, address: &[u8], metadata: StoredMetadata) -> Result<(), Self::Error>;
+
+    /// Delete the metadata for the address, returning whether it was present.
+    async fn delete(&self, address: &[u8]) -> Result<bool, Self::Error>;
+
+    /// Get up to `limit` keys, paired with their metadata, whose timestamp is at least
+    /// `since`, in ascending order of timestamp.
+    async fn since(
+        &self,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, StoredMetadata)>, Self::Error>;
+}
+
+/// An in-memory [`MetadataStore`].
+#[derive(Debug, Default)]
+pub struct MemoryMetadataStore {
+    entries: RwLock<HashMap<Vec<u8>, StoredMetadata>>,
+}
+
+impl MemoryMetadataStore {
+    /// Create an empty [`MemoryMetadataStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl MetadataStore for MemoryMetadataStore {
+    type Error = Infallible;
+
+    async fn get(&self, address: &[u8]) -> Result<Option<StoredMetadata>, Self::Error> {
+        Ok(self.entries.read().unwrap().get(address).cloned())
+    }
+
+    async fn put(&self, address: &[u8], metadata: StoredMetadata) -> Result<(), Self::Error> {
+        self.entries
+            .write()
+            .unwrap()
+            .insert(address.to_vec(), metadata);
+        Ok(())
+    }
+
+    async fn delete(&self, address: &[u8]) -> Result<bool, Self::Error> {
+        Ok(self.entries.write().unwrap().remove(address).is_some())
+    }
+
+    async fn since(
+        &self,
+        since: i64,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, StoredMetadata)>, Self::Error> {
+        let mut entries: Vec<_> = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, metadata)| metadata.timestamp >= since)
+            .map(|(address, metadata)| (address.clone(), metadata.clone()))
+            .collect();
+        entries.sort_by(|(address_a, a), (address_b, b)| {
+            a.timestamp
+                .cmp(&b.timestamp)
+                .then_with(|| address_a.cmp(address_b))
+        });
+        entries.truncate(limit);
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(timestamp: i64) -> StoredMetadata {
+        StoredMetadata {
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn since() {
+        let store = MemoryMetadataStore::new();
+        store.put(b"alice", metadata(300)).await.unwrap();
+        store.put(b"bob", metadata(100)).await.unwrap();
+        store.put(b"carol", metadata(200)).await.unwrap();
+        store.put(b"bob", metadata(400)).await.unwrap();
+
+        let addresses = |entries: Vec<(Vec<u8>, StoredMetadata)>| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(address, _)| address).collect()
+        };
+        assert_eq!(
+            addresses(store.since(200, 2).await.unwrap()),
+            vec![b"carol".to_vec(), b"alice".to_vec()]
+        );
+        assert_eq!(
+            addresses(store.since(350, 10).await.unwrap()),
+            vec![b"bob".to_vec()]
+        );
+
+        assert!(store.delete(b"bob").await.unwrap());
+        assert!(!store.delete(b"bob").await.unwrap());
+        assert_eq!(store.get(b"bob").await.unwrap(), None);
+        assert_eq!(store.get(b"alice").await.unwrap(), Some(metadata(300)));
+    }
+}
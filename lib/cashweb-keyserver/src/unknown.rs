@@ -0,0 +1,216 @@
+//! This module contains [`Preserved`], which decodes a message while preserving the fields it
+//! does not know, so that data from newer keyservers survives being relayed or mirrored.
+//!
+//! The generated models drop unknown fields when decoded. Re-serializing such a message, for
+//! example the payload of an authorization wrapper, would then strip the fields and break the
+//! signature covering them. A [`Preserved`] message re-serializes to its original bytes until it
+//! is modified, after which its unknown fields are appended to the encoded message. Unknown fields
+//! of nested messages are preserved only while the message is unmodified.
+
+use prost::{
+    bytes::Buf,
+    encoding::{decode_key, skip_field, DecodeContext},
+    DecodeError, Message,
+};
+
+use crate::{AddressMetadata, Entry, Header, Peer, Peers};
+
+/// A message whose field tags are known.
+pub trait KnownFields: Message + Default {
+    /// The tags of the fields of the message.
+    const TAGS: &'static [u32];
+}
+
+impl KnownFields for AddressMetadata {
+    const TAGS: &'static [u32] = &[1, 2, 3];
+}
+
+impl KnownFields for Entry {
+    const TAGS: &'static [u32] = &[1, 2, 3];
+}
+
+impl KnownFields for Header {
+    const TAGS: &'static [u32] = &[1, 2];
+}
+
+impl KnownFields for Peers {
+    const TAGS: &'static [u32] = &[1];
+}
+
+impl KnownFields for Peer {
+    const TAGS: &'static [u32] = &[1, 2, 3, 4];
+}
+
+/// The serialized unknown fields of a message, in their original order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnknownFields {
+    raw: Vec<u8>,
+    tags: Vec<u32>,
+}
+
+impl UnknownFields {
+    /// Collect the fields of a serialized message whose tags are not in `known_tags`.
+    pub fn from_message(mut raw: &[u8], known_tags: &[u32]) -> Result<Self, DecodeError> {
+        let mut unknown = Self::default();
+        while raw.has_remaining() {
+            let field_start = raw;
+            let (tag, wire_type) = decode_key(&mut raw)?;
+            skip_field(wire_type, tag, &mut raw, DecodeContext::default())?;
+            if !known_tags.contains(&tag) {
+                let field_len = field_start.len() - raw.len();
+                unknown.raw.extend_from_slice(&field_start[..field_len]);
+                if !unknown.tags.contains(&tag) {
+                    unknown.tags.push(tag);
+                }
+            }
+        }
+        Ok(unknown)
+    }
+
+    /// Whether there are no unknown fields.
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// The distinct tags of the unknown fields.
+    pub fn tags(&self) -> &[u32] {
+        &self.tags
+    }
+
+    /// The serialized unknown fields.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+/// A decoded message paired with its unknown fields and, until modified, its original bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preserved<M> {
+    message: M,
+    unknown: UnknownFields,
+    raw: Option<Vec<u8>>,
+}
+
+impl<M: KnownFields> Preserved<M> {
+    /// Decode a message, preserving its unknown fields and original bytes.
+    pub fn decode(raw: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            message: M::decode(raw)?,
+            unknown: UnknownFields::from_message(raw, M::TAGS)?,
+            raw: Some(raw.to_vec()),
+        })
+    }
+
+    /// Wrap a message without unknown fields.
+    pub fn new(message: M) -> Self {
+        Self {
+            message,
+            unknown: UnknownFields::default(),
+            raw: None,
+        }
+    }
+
+    /// The decoded message.
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
+    /// Modify the decoded message, discarding the original bytes.
+    pub fn message_mut(&mut self) -> &mut M {
+        self.raw = None;
+        &mut self.message
+    }
+
+    /// The unknown fields of the message.
+    pub fn unknown_fields(&self) -> &UnknownFields {
+        &self.unknown
+    }
+
+    /// The original bytes, if the message is unmodified.
+    pub fn raw(&self) -> Option<&[u8]> {
+        self.raw.as_deref()
+    }
+
+    /// Serialize the message.
+    ///
+    /// This is the original bytes if the message is unmodified, and otherwise the encoded message
+    /// followed by its unknown fields.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        if let Some(raw) = &self.raw {
+            return raw.clone();
+        }
+        let mut raw = Vec::with_capacity(self.message.encoded_len() + self.unknown.raw.len());
+        self.message.encode(&mut raw).unwrap(); // This is safe
+        raw.extend_from_slice(&self.unknown.raw);
+        raw
+    }
+
+    /// Consume the wrapper, returning the decoded message.
+    pub fn into_inner(self) -> M {
+        self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A newer `AddressMetadata`, with an additional field.
+    #[derive(Clone, PartialEq, Message)]
+    struct NewerAddressMetadata {
+        #[prost(int64, tag = "1")]
+        timestamp: i64,
+        #[prost(string, tag = "9")]
+        signature_hint: String,
+        #[prost(message, repeated, tag = "3")]
+        entries: Vec<Entry>,
+    }
+
+    fn newer_metadata() -> Vec<u8> {
+        let newer = NewerAddressMetadata {
+            timestamp: 100,
+            signature_hint: "hint".to_string(),
+            entries: vec![Entry {
+                kind: "profile/name".to_string(),
+                ..Default::default()
+            }],
+        };
+        let mut raw = Vec::new();
+        newer.encode(&mut raw).unwrap();
+        raw
+    }
+
+    #[test]
+    fn byte_exact() {
+        let raw = newer_metadata();
+        let preserved = Preserved::<AddressMetadata>::decode(&raw).unwrap();
+        assert_eq!(preserved.message().timestamp, 100);
+        assert_eq!(preserved.unknown_fields().tags(), &[9]);
+        assert_eq!(preserved.encode_to_vec(), raw);
+
+        // Plain decoding strips the field
+        let mut stripped = Vec::new();
+        AddressMetadata::decode(raw.as_slice())
+            .unwrap()
+            .encode(&mut stripped)
+            .unwrap();
+        assert_ne!(stripped, raw);
+    }
+
+    #[test]
+    fn modified() {
+        let mut preserved = Preserved::<AddressMetadata>::decode(&newer_metadata()).unwrap();
+        preserved.message_mut().timestamp = 200;
+        assert_eq!(preserved.raw(), None);
+
+        let newer = NewerAddressMetadata::decode(preserved.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(newer.timestamp, 200);
+        assert_eq!(newer.signature_hint, "hint");
+        assert_eq!(newer.entries.len(), 1);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(UnknownFields::from_message(&[0x0a, 0x05, 0x01], Peers::TAGS).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+//! This module contains [`VCard`], a typed contact card carried in the body of a metadata
+//! [`Entry`] of kind [`VCARD_KIND`].
+//!
+//! Cards are serialized as vCard 4.0 ([RFC 6350]), folding long lines and escaping text values as
+//! wallets do. vCard 3.0 cards are also accepted, with inline photos converted to data URIs.
+//! Properties without a typed field are preserved verbatim.
+//!
+//! [RFC 6350]: https://tools.ietf.org/html/rfc6350
+
+use std::{fmt, str};
+
+use thiserror::Error;
+
+use crate::{Entry, Header};
+
+/// The kind of an [`Entry`] holding a vCard.
+pub const VCARD_KIND: &str = "vcard";
+
+/// The media type of a vCard.
+pub const VCARD_MEDIA_TYPE: &str = "text/vcard";
+
+/// Maximum length of a serialized line, in octets, before folding.
+const LINE_LIMIT: usize = 75;
+
+/// Error associated with parsing a [`VCard`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum VCardError {
+    /// The entry was not of kind [`VCARD_KIND`].
+    #[error("unexpected entry kind: {0}")]
+    UnexpectedKind(String),
+    /// The body was not valid UTF-8.
+    #[error("body is not valid UTF-8")]
+    Utf8,
+    /// The card did not start with `BEGIN:VCARD`.
+    #[error("missing BEGIN:VCARD")]
+    MissingBegin,
+    /// The card did not end with `END:VCARD`.
+    #[error("missing END:VCARD")]
+    MissingEnd,
+    /// A line was not of the form `NAME[;PARAM=VALUE]:VALUE`.
+    #[error("malformed line: {0}")]
+    MalformedLine(String),
+}
+
+/// The structured name of a contact, given by the `N` property.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Name {
+    /// Family names.
+    pub family: String,
+    /// Given names.
+    pub given: String,
+    /// Additional names.
+    pub additional: String,
+    /// Honorific prefixes.
+    pub prefixes: String,
+    /// Honorific suffixes.
+    pub suffixes: String,
+}
+
+/// A handle on a social network, given by the `X-SOCIALPROFILE` property.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SocialHandle {
+    /// The network, such as `twitter`.
+    pub service: String,
+    /// The handle or profile URL.
+    pub handle: String,
+}
+
+/// A contact card.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VCard {
+    /// The formatted name, given by the `FN` property.
+    pub full_name: String,
+    /// The structured name.
+    pub name: Option<Name>,
+    /// Email addresses.
+    pub emails: Vec<String>,
+    /// Avatar URIs, given by the `PHOTO` property. Inline photos are data URIs.
+    pub avatars: Vec<String>,
+    /// Social network handles.
+    pub social_handles: Vec<SocialHandle>,
+    /// Other properties, as unfolded lines.
+    pub other: Vec<String>,
+}
+
+/// A parsed content line.
+struct ContentLine<'a> {
+    name: String,
+    params: Vec<(String, &'a str)>,
+    value: &'a str,
+}
+
+impl<'a> ContentLine<'a> {
+    fn parse(line: &'a str) -> Result<Self, VCardError> {
+        // Find the colon separating the value, skipping quoted parameter values
+        let mut in_quotes = false;
+        let colon = line
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                *c == ':' && !in_quotes
+            })
+            .map(|(index, _)| index)
+            .ok_or_else(|| VCardError::MalformedLine(line.to_string()))?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+        let mut parts = head.split(';');
+        // This is safe as split always yields at least one part
+        let name = parts.next().unwrap();
+        // Strip the group, such as `item1.`
+        let name = name.rsplit('.').next().unwrap_or(name).to_ascii_uppercase();
+        if name.is_empty() {
+            return Err(VCardError::MalformedLine(line.to_string()));
+        }
+        let params = parts
+            .map(|param| match param.split_once('=') {
+                Some((key, value)) => (key.to_ascii_uppercase(), value.trim_matches('"')),
+                None => ("TYPE".to_string(), param),
+            })
+            .collect();
+        Ok(Self {
+            name,
+            params,
+            value,
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Escape a text value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Unescape a text value.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Split a structured value on unescaped semicolons.
+fn split_components(value: &str) -> Vec<String> {
+    let mut components = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        // This is safe as components is never empty
+        let current = components.last_mut().unwrap();
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            ';' => components.push(String::new()),
+            c => current.push(c),
+        }
+    }
+    components
+        .iter()
+        .map(|component| unescape(component))
+        .collect()
+}
+
+/// Write a content line, folding it at [`LINE_LIMIT`] octets.
+fn write_line(f: &mut fmt::Formatter<'_>, line: &str) -> fmt::Result {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            f.write_str("\r\n ")?;
+            width = 1;
+        }
+        write!(f, "{}", c)?;
+        width += c.len_utf8();
+    }
+    f.write_str("\r\n")
+}
+
+impl fmt::Display for VCard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_line(f, "BEGIN:VCARD")?;
+        write_line(f, "VERSION:4.0")?;
+        write_line(f, &format!("FN:{}", escape(&self.full_name)))?;
+        if let Some(name) = &self.name {
+            let components = [
+                &name.family,
+                &name.given,
+                &name.additional,
+                &name.prefixes,
+                &name.suffixes,
+            ];
+            let components: Vec<_> = components.iter().map(|c| escape(c)).collect();
+            write_line(f, &format!("N:{}", components.join(";")))?;
+        }
+        for email in &self.emails {
+            write_line(f, &format!("EMAIL:{}", escape(email)))?;
+        }
+        for avatar in &self.avatars {
+            write_line(f, &format!("PHOTO:{}", avatar))?;
+        }
+        for social in &self.social_handles {
+            write_line(
+                f,
+                &format!(
+                    "X-SOCIALPROFILE;TYPE={}:{}",
+                    social.service,
+                    escape(&social.handle)
+                ),
+            )?;
+        }
+        for line in &self.other {
+            write_line(f, line)?;
+        }
+        write_line(f, "END:VCARD")
+    }
+}
+
+impl str::FromStr for VCard {
+    type Err = VCardError;
+
+    fn from_str(card: &str) -> Result<Self, Self::Err> {
+        // Unfold lines
+        let unfolded = card
+            .replace("\r\n ", "")
+            .replace("\r\n\t", "")
+            .replace("\n ", "")
+            .replace("\n\t", "");
+        let mut lines = unfolded
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.is_empty());
+
+        match lines.next() {
+            Some(line) if line.eq_ignore_ascii_case("BEGIN:VCARD") => (),
+            _ => return Err(VCardError::MissingBegin),
+        }
+
+        let mut vcard = VCard::default();
+        let mut is_ended = false;
+        for line in lines {
+            let content_line = ContentLine::parse(line)?;
+            match content_line.name.as_str() {
+                "END" if content_line.value.eq_ignore_ascii_case("VCARD") => {
+                    is_ended = true;
+                    break;
+                }
+                "VERSION" => (),
+                "FN" => vcard.full_name = unescape(content_line.value),
+                "N" => {
+                    let mut components = split_components(content_line.value).into_iter();
+                    let mut next = || components.next().unwrap_or_default();
+                    vcard.name = Some(Name {
+                        family: next(),
+                        given: next(),
+                        additional: next(),
+                        prefixes: next(),
+                        suffixes: next(),
+                    });
+                }
+                "EMAIL" => vcard.emails.push(unescape(content_line.value)),
+                "PHOTO" => {
+                    // vCard 3.0 inlines base64 encoded photos
+                    let is_inline = content_line
+                        .param("ENCODING")
+                        .map(|encoding| encoding.eq_ignore_ascii_case("b"))
+                        .unwrap_or_default();
+                    let avatar = if is_inline {
+                        let media_type = content_line
+                            .param("TYPE")
+                            .map(|kind| format!("image/{}", kind.to_ascii_lowercase()))
+                            .unwrap_or_else(|| "application/octet-stream".to_string());
+                        format!("data:{};base64,{}", media_type, content_line.value)
+                    } else {
+                        content_line.value.to_string()
+                    };
+                    vcard.avatars.push(avatar);
+                }
+                "X-SOCIALPROFILE" => vcard.social_handles.push(SocialHandle {
+                    service: content_line
+                        .param("TYPE")
+                        .unwrap_or_default()
+                        .to_ascii_lowercase(),
+                    handle: unescape(content_line.value),
+                }),
+                _ => vcard.other.push(line.to_string()),
+            }
+        }
+        if !is_ended {
+            return Err(VCardError::MissingEnd);
+        }
+        Ok(vcard)
+    }
+}
+
+impl VCard {
+    /// Parse the vCard held in an [`Entry`] of kind [`VCARD_KIND`].
+    pub fn from_entry(entry: &Entry) -> Result<Self, VCardError> {
+        if entry.kind != VCARD_KIND {
+            return Err(VCardError::UnexpectedKind(entry.kind.clone()));
+        }
+        str::from_utf8(&entry.body)
+            .map_err(|_| VCardError::Utf8)?
+            .parse()
+    }
+
+    /// Convert into an [`Entry`] of kind [`VCARD_KIND`].
+    pub fn to_entry(&self) -> Entry {
+        Entry {
+            kind: VCARD_KIND.to_string(),
+            headers: vec![Header {
+                name: "Content-Type".to_string(),
+                value: VCARD_MEDIA_TYPE.to_string(),
+            }],
+            body: self.to_string().into_bytes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let vcard = VCard {
+            full_name: "Alice, of Wonderland; Esq.".to_string(),
+            name: Some(Name {
+                family: "Liddell".to_string(),
+                given: "Alice".to_string(),
+                suffixes: "Esq.".to_string(),
+                ..Default::default()
+            }),
+            emails: vec!["alice@example.com".to_string()],
+            avatars: vec![format!("data:image/png;base64,{}", "A".repeat(200))],
+            social_handles: vec![SocialHandle {
+                service: "twitter".to_string(),
+                handle: "@alice".to_string(),
+            }],
+            other: vec!["NOTE:Down the rabbit hole".to_string()],
+        };
+        let entry = vcard.to_entry();
+        let text = str::from_utf8(&entry.body).unwrap();
+        assert!(text.contains("FN:Alice\\, of Wonderland\\; Esq.\r\n"));
+        assert!(text.lines().all(|line| line.len() <= LINE_LIMIT));
+        assert_eq!(VCard::from_entry(&entry).unwrap(), vcard);
+    }
+
+    #[test]
+    fn parse_v3() {
+        let card = "BEGIN:VCARD\nVERSION:3.0\nN:Liddell;Alice;;;\nFN:Alice\n\
+                    item1.EMAIL;TYPE=INTERNET:alice@example.com\n\
+                    PHOTO;ENCODING=b;TYPE=JPEG:/9j/4AA\n QSkZJRg==\nEND:VCARD\n";
+        let vcard: VCard = card.parse().unwrap();
+        assert_eq!(vcard.full_name, "Alice");
+        assert_eq!(vcard.name.unwrap().given, "Alice");
+        assert_eq!(vcard.emails, vec!["alice@example.com".to_string()]);
+        assert_eq!(
+            vcard.avatars,
+            vec!["data:image/jpeg;base64,/9j/4AAQSkZJRg==".to_string()]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "FN:Alice\nEND:VCARD".parse::<VCard>(),
+            Err(VCardError::MissingBegin)
+        );
+        assert_eq!(
+            "BEGIN:VCARD\nFN:Alice".parse::<VCard>(),
+            Err(VCardError::MissingEnd)
+        );
+        assert!(matches!(
+            "BEGIN:VCARD\nFN Alice\nEND:VCARD".parse::<VCard>(),
+            Err(VCardError::MalformedLine(_))
+        ));
+        let entry = Entry {
+            kind: "profile".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            VCard::from_entry(&entry),
+            Err(VCardError::UnexpectedKind("profile".to_string()))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! This module contains the [`EventBus`], a lightweight publish/subscribe bus over which the
+//! components of a service, such as ZMQ listeners, replicators and server handlers, announce
+//! [`MetadataUpdated`], [`PaymentSeen`], [`BlockConnected`] and [`NotificationGap`] events.
+//!
+//! Each topic is a bounded broadcast channel. Publishing never blocks; a subscriber which falls
+//! more than the capacity behind skips the oldest events, counting them as lagged.
+
+use std::{fmt, sync::Arc};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Default number of events buffered per topic.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The origin of a [`MetadataUpdated`] event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The metadata was written by a client of the service.
+    Local,
+    /// The metadata was replicated from the peer with the given URL.
+    Peer(String),
+}
+
+/// Metadata was written to the store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataUpdated {
+    /// The public key hash of the address.
+    pub address: Vec<u8>,
+    /// The namespace, empty for the root document.
+    pub namespace: String,
+    /// The timestamp of the metadata.
+    pub timestamp: i64,
+    /// The serialized authorization wrapper.
+    pub raw_auth_wrapper: Vec<u8>,
+    /// Where the metadata came from.
+    pub origin: Origin,
+}
+
+/// A payment was accepted and broadcast.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentSeen {
+    /// The ID of the transaction containing the commitment.
+    pub tx_id: Vec<u8>,
+    /// The index of the commitment output.
+    pub vout: u32,
+    /// The public key hash of the address paid for.
+    pub address: Vec<u8>,
+}
+
+/// A block was connected to the best chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockConnected {
+    /// The hash of the block.
+    pub hash: Vec<u8>,
+}
+
+/// The cause of a [`NotificationGap`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GapCause {
+    /// Sequence numbers were skipped, the given number of notifications being missed.
+    Skipped(u32),
+    /// The sequence restarted, as when the node restarts.
+    Restarted,
+    /// The listener reconnected to the node, possibly missing notifications in between.
+    Reconnected,
+}
+
+/// Notifications from the node were missed, so state derived from them must be recovered from
+/// the node over RPC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotificationGap {
+    /// The notification topic, such as `hashblock`.
+    pub topic: String,
+    /// The cause of the gap.
+    pub cause: GapCause,
+}
+
+/// A typed topic of an [`EventBus`].
+pub struct Topic<E> {
+    sender: broadcast::Sender<E>,
+}
+
+impl<E> fmt::Debug for Topic<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Topic")
+            .field("subscribers", &self.sender.receiver_count())
+            .finish()
+    }
+}
+
+impl<E: Clone> Topic<E> {
+    fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Publish an event, returning the number of subscribers it was delivered to.
+    pub fn publish(&self, event: E) -> usize {
+        self.sender.send(event).unwrap_or_default()
+    }
+
+    /// Subscribe to events published from now on.
+    pub fn subscribe(&self) -> Subscriber<E> {
+        Subscriber {
+            receiver: self.sender.subscribe(),
+            lagged: 0,
+        }
+    }
+
+    /// The number of subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// A subscription to a [`Topic`].
+pub struct Subscriber<E> {
+    receiver: broadcast::Receiver<E>,
+    lagged: u64,
+}
+
+impl<E> fmt::Debug for Subscriber<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("lagged", &self.lagged)
+            .finish()
+    }
+}
+
+impl<E: Clone> Subscriber<E> {
+    /// Receive the next event, skipping any the subscriber lagged behind on.
+    ///
+    /// Returns `None` once the [`EventBus`] has been dropped.
+    pub async fn recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => self.lagged += skipped,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// The number of events skipped as the subscriber lagged behind.
+    pub fn lagged(&self) -> u64 {
+        self.lagged
+    }
+}
+
+#[derive(Debug)]
+struct Topics {
+    metadata_updated: Topic<MetadataUpdated>,
+    payment_seen: Topic<PaymentSeen>,
+    block_connected: Topic<BlockConnected>,
+    notification_gap: Topic<NotificationGap>,
+}
+
+/// Shared publish/subscribe bus of a service.
+#[derive(Clone, Debug)]
+pub struct EventBus {
+    topics: Arc<Topics>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Create a bus buffering up to `capacity` events per topic.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            topics: Arc::new(Topics {
+                metadata_updated: Topic::new(capacity),
+                payment_seen: Topic::new(capacity),
+                block_connected: Topic::new(capacity),
+                notification_gap: Topic::new(capacity),
+            }),
+        }
+    }
+
+    /// The [`MetadataUpdated`] topic.
+    pub fn metadata_updated(&self) -> &Topic<MetadataUpdated> {
+        &self.topics.metadata_updated
+    }
+
+    /// The [`PaymentSeen`] topic.
+    pub fn payment_seen(&self) -> &Topic<PaymentSeen> {
+        &self.topics.payment_seen
+    }
+
+    /// The [`BlockConnected`] topic.
+    pub fn block_connected(&self) -> &Topic<BlockConnected> {
+        &self.topics.block_connected
+    }
+
+    /// The [`NotificationGap`] topic.
+    pub fn notification_gap(&self) -> &Topic<NotificationGap> {
+        &self.topics.notification_gap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(byte: u8) -> BlockConnected {
+        BlockConnected { hash: vec![byte] }
+    }
+
+    #[tokio::test]
+    async fn publish_subscribe() {
+        let bus = EventBus::new(2);
+        assert_eq!(bus.block_connected().publish(block(0)), 0);
+
+        let mut subscriber = bus.block_connected().subscribe();
+        assert_eq!(bus.block_connected().subscribers(), 1);
+        assert_eq!(bus.payment_seen().subscribers(), 0);
+        for byte in 1..=3 {
+            assert_eq!(bus.block_connected().publish(block(byte)), 1);
+        }
+
+        // The first event was dropped as the subscriber lagged behind
+        assert_eq!(subscriber.recv().await, Some(block(2)));
+        assert_eq!(subscriber.lagged(), 1);
+        assert_eq!(subscriber.recv().await, Some(block(3)));
+
+        drop(bus);
+        assert_eq!(subscriber.recv().await, None);
+    }
+}
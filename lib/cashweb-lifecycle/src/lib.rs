@@ -0,0 +1,295 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-lifecycle` is a library coordinating the graceful shutdown of long-running cash:web
+//! services.
+//!
+//! A [`Lifecycle`] is shared by the components of a service, such as ZMQ listeners, replicators
+//! and server handlers. It tracks:
+//!
+//! - the [`State`] of the service, from which readiness and liveness probes are answered,
+//! - a [`ShutdownToken`], which components await in order to stop accepting new work,
+//! - the number of in-flight operations, such as broadcasts, replication passes and metadata
+//!   writes, so that shutdown can wait for them to drain.
+//!
+//! The same components announce what they observe over an [`EventBus`](bus::EventBus), rather
+//! than being wired together with dedicated channels.
+
+pub mod bus;
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::{sync::watch, time::timeout};
+
+/// The state of a service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The service is initializing and not yet accepting work.
+    Starting,
+    /// The service is accepting work.
+    Ready,
+    /// Shutdown was requested; in-flight work is draining and new work is refused.
+    Draining,
+    /// The service has stopped.
+    Stopped,
+}
+
+/// In-flight operations failed to complete before the drain timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("{remaining} operations still in flight after {timeout:?}")]
+pub struct DrainTimeout {
+    /// Number of operations still in flight.
+    pub remaining: usize,
+    /// The drain timeout.
+    pub timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: watch::Sender<State>,
+    state_receiver: watch::Receiver<State>,
+    in_flight: AtomicUsize,
+    idle: watch::Sender<()>,
+    idle_receiver: watch::Receiver<()>,
+}
+
+/// Shared lifecycle of a service.
+#[derive(Clone, Debug)]
+pub struct Lifecycle {
+    inner: Arc<Inner>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    /// Create a lifecycle in the [`State::Starting`] state.
+    pub fn new() -> Self {
+        let (state, state_receiver) = watch::channel(State::Starting);
+        let (idle, idle_receiver) = watch::channel(());
+        Self {
+            inner: Arc::new(Inner {
+                state,
+                state_receiver,
+                in_flight: AtomicUsize::new(0),
+                idle,
+                idle_receiver,
+            }),
+        }
+    }
+
+    /// The current state.
+    pub fn state(&self) -> State {
+        *self.inner.state_receiver.borrow()
+    }
+
+    fn set_state(&self, state: State) {
+        // This is safe as the receiver held by the lifecycle keeps the channel open
+        self.inner.state.send(state).unwrap();
+    }
+
+    /// Mark the service as ready, unless shutdown has been requested.
+    pub fn set_ready(&self) {
+        if self.state() == State::Starting {
+            self.set_state(State::Ready);
+        }
+    }
+
+    /// Whether the service is accepting work, for readiness probes.
+    pub fn is_ready(&self) -> bool {
+        self.state() == State::Ready
+    }
+
+    /// Whether the service has not stopped, for liveness probes.
+    pub fn is_live(&self) -> bool {
+        self.state() != State::Stopped
+    }
+
+    /// Whether shutdown has been requested.
+    pub fn is_shutting_down(&self) -> bool {
+        matches!(self.state(), State::Draining | State::Stopped)
+    }
+
+    /// Request shutdown, failing readiness and cancelling [`ShutdownToken`]s.
+    pub fn shutdown(&self) {
+        if !self.is_shutting_down() {
+            self.set_state(State::Draining);
+        }
+    }
+
+    /// A token which is cancelled when shutdown is requested.
+    pub fn token(&self) -> ShutdownToken {
+        ShutdownToken(self.inner.state_receiver.clone())
+    }
+
+    /// Begin new work, returning `None` if shutdown has been requested.
+    ///
+    /// The work is in flight until the returned guard is dropped.
+    pub fn begin(&self) -> Option<InFlight> {
+        if self.is_shutting_down() {
+            return None;
+        }
+        Some(self.track())
+    }
+
+    /// Track work which must complete even if shutdown has been requested, such as a broadcast
+    /// made by an in-flight request.
+    ///
+    /// The work is in flight until the returned guard is dropped.
+    pub fn track(&self) -> InFlight {
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Run the future, tracking it as in flight until it completes.
+    pub async fn run<F: Future>(&self, future: F) -> F::Output {
+        let _guard = self.track();
+        future.await
+    }
+
+    /// The number of operations in flight.
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Request shutdown and wait for in-flight operations to complete, marking the service as
+    /// stopped.
+    ///
+    /// The service is marked as stopped after `drain_timeout` even if operations remain in
+    /// flight.
+    pub async fn drain(&self, drain_timeout: Duration) -> Result<(), DrainTimeout> {
+        self.shutdown();
+        let mut idle = self.inner.idle_receiver.clone();
+        let wait = async {
+            while self.in_flight() != 0 {
+                // This is safe as the sender is held by the lifecycle
+                idle.changed().await.unwrap();
+            }
+        };
+        let result = timeout(drain_timeout, wait)
+            .await
+            .map_err(|_| DrainTimeout {
+                remaining: self.in_flight(),
+                timeout: drain_timeout,
+            });
+        self.set_state(State::Stopped);
+        result
+    }
+}
+
+/// A token which is cancelled when shutdown of a [`Lifecycle`] is requested.
+#[derive(Clone, Debug)]
+pub struct ShutdownToken(watch::Receiver<State>);
+
+impl ShutdownToken {
+    /// Whether shutdown has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(*self.0.borrow(), State::Draining | State::Stopped)
+    }
+
+    /// Wait until shutdown is requested.
+    pub async fn cancelled(mut self) {
+        while !self.is_cancelled() {
+            if self.0.changed().await.is_err() {
+                // The lifecycle was dropped, so no shutdown will follow
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// A guard marking an operation as in flight until dropped.
+#[derive(Debug)]
+pub struct InFlight {
+    inner: Arc<Inner>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // This is safe as the lifecycle holds a receiver
+            self.inner.idle.send(()).unwrap();
+        }
+    }
+}
+
+/// Wait for a termination signal: `SIGTERM` or `SIGINT` on Unix, or Ctrl-C elsewhere.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // This is safe as signal handlers can be registered at any time
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lifecycle() {
+        let lifecycle = Lifecycle::new();
+        assert!(!lifecycle.is_ready() && lifecycle.is_live());
+        lifecycle.set_ready();
+        assert!(lifecycle.is_ready());
+
+        let token = lifecycle.token();
+        let guard = lifecycle.begin().unwrap();
+        lifecycle.shutdown();
+        assert!(token.is_cancelled());
+        token.cancelled().await;
+        assert!(!lifecycle.is_ready() && lifecycle.is_live());
+
+        // New work is refused, but tracked work is counted
+        assert!(lifecycle.begin().is_none());
+        let tracked = lifecycle.track();
+        assert_eq!(lifecycle.in_flight(), 2);
+
+        let draining = lifecycle.clone();
+        let drained = tokio::spawn(async move { draining.drain(Duration::from_secs(5)).await });
+        drop(guard);
+        drop(tracked);
+        drained.await.unwrap().unwrap();
+        assert_eq!(lifecycle.state(), State::Stopped);
+        assert!(!lifecycle.is_live());
+    }
+
+    #[tokio::test]
+    async fn drain_timeout() {
+        let lifecycle = Lifecycle::new();
+        let _guard = lifecycle.track();
+        let err = lifecycle
+            .drain(Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.remaining, 1);
+        assert_eq!(lifecycle.state(), State::Stopped);
+    }
+}
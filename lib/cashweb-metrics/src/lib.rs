@@ -0,0 +1,218 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-metrics` is a small metrics facade shared by the cash:web libraries.
+//!
+//! Libraries declare [`Counter`]s and [`Histogram`]s as constants and record to them
+//! unconditionally. Recording is a no-op until a binary installs a [`Recorder`] using
+//! [`set_recorder`], so that every library feeds the same exporter. With the `prometheus`
+//! feature enabled, [`prometheus::PrometheusRecorder`] exposes all metrics in the Prometheus text
+//! format, ready to be served from a single `/metrics` endpoint.
+
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use lazy_static::lazy_static;
+
+/// A monotonically increasing counter, partitioned by label values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Counter {
+    /// Name of the metric, for example `cashweb_broadcasts_total`.
+    pub name: &'static str,
+    /// Description of the metric.
+    pub help: &'static str,
+    /// Label names.
+    pub labels: &'static [&'static str],
+}
+
+impl Counter {
+    /// Declare a counter.
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self { name, help, labels }
+    }
+
+    /// Increment the counter by one. The label values must correspond to the label names.
+    pub fn increment(&self, values: &[&str]) {
+        self.increment_by(values, 1)
+    }
+
+    /// Increment the counter. The label values must correspond to the label names.
+    pub fn increment_by(&self, values: &[&str], value: u64) {
+        if let Some(recorder) = recorder() {
+            recorder.increment_counter(self, values, value);
+        }
+    }
+}
+
+/// A distribution of observations, partitioned by label values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    /// Name of the metric, for example `cashweb_broadcast_duration_seconds`.
+    pub name: &'static str,
+    /// Description of the metric.
+    pub help: &'static str,
+    /// Label names.
+    pub labels: &'static [&'static str],
+}
+
+impl Histogram {
+    /// Declare a histogram.
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self { name, help, labels }
+    }
+
+    /// Record an observation. The label values must correspond to the label names.
+    pub fn observe(&self, values: &[&str], value: f64) {
+        if let Some(recorder) = recorder() {
+            recorder.observe_histogram(self, values, value);
+        }
+    }
+}
+
+/// A destination for metrics.
+pub trait Recorder: Send + Sync {
+    /// Increment a counter.
+    fn increment_counter(&self, counter: &Counter, values: &[&str], value: u64);
+
+    /// Record an observation of a histogram.
+    fn observe_histogram(&self, histogram: &Histogram, values: &[&str], value: f64);
+}
+
+lazy_static! {
+    static ref RECORDER: RwLock<Option<Arc<dyn Recorder>>> = RwLock::new(None);
+}
+
+/// Install the global [`Recorder`], replacing any existing recorder.
+pub fn set_recorder(recorder: Arc<dyn Recorder>) {
+    *RECORDER.write().unwrap() = Some(recorder);
+}
+
+/// Get the global [`Recorder`], if one is installed.
+pub fn recorder() -> Option<Arc<dyn Recorder>> {
+    RECORDER.read().unwrap().clone()
+}
+
+/// A snapshot of a [`MemoryRecorder`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// Counter values, keyed by name and label values.
+    pub counters: HashMap<(String, Vec<String>), u64>,
+    /// Histogram observation counts and sums, keyed by name and label values.
+    pub histograms: HashMap<(String, Vec<String>), (u64, f64)>,
+}
+
+impl Snapshot {
+    /// Get the value of a counter.
+    pub fn counter(&self, counter: &Counter, values: &[&str]) -> u64 {
+        self.counters
+            .get(&key(counter.name, values))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the number of observations of a histogram.
+    pub fn observations(&self, histogram: &Histogram, values: &[&str]) -> u64 {
+        self.histograms
+            .get(&key(histogram.name, values))
+            .map(|(count, _)| *count)
+            .unwrap_or_default()
+    }
+}
+
+fn key(name: &str, values: &[&str]) -> (String, Vec<String>) {
+    (
+        name.to_string(),
+        values.iter().map(ToString::to_string).collect(),
+    )
+}
+
+/// An in-memory [`Recorder`], useful in tests.
+#[derive(Default)]
+pub struct MemoryRecorder {
+    snapshot: Mutex<Snapshot>,
+}
+
+impl fmt::Debug for MemoryRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryRecorder").finish()
+    }
+}
+
+impl MemoryRecorder {
+    /// Create an empty [`MemoryRecorder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a snapshot of the recorded metrics.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+}
+
+impl Recorder for MemoryRecorder {
+    fn increment_counter(&self, counter: &Counter, values: &[&str], value: u64) {
+        *self
+            .snapshot
+            .lock()
+            .unwrap()
+            .counters
+            .entry(key(counter.name, values))
+            .or_default() += value;
+    }
+
+    fn observe_histogram(&self, histogram: &Histogram, values: &[&str], value: f64) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        let (count, sum) = snapshot
+            .histograms
+            .entry(key(histogram.name, values))
+            .or_default();
+        *count += 1;
+        *sum += value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUESTS: Counter = Counter::new("requests_total", "Requests.", &["route"]);
+    const LATENCY: Histogram = Histogram::new("latency_seconds", "Latency.", &["route"]);
+
+    #[test]
+    fn record() {
+        // Recording without a recorder is a no-op
+        REQUESTS.increment(&["keys"]);
+
+        let memory = Arc::new(MemoryRecorder::new());
+        set_recorder(memory.clone());
+        REQUESTS.increment(&["keys"]);
+        REQUESTS.increment_by(&["keys"], 2);
+        REQUESTS.increment(&["peers"]);
+        LATENCY.observe(&["keys"], 0.5);
+
+        let snapshot = memory.snapshot();
+        assert_eq!(snapshot.counter(&REQUESTS, &["keys"]), 3);
+        assert_eq!(snapshot.counter(&REQUESTS, &["peers"]), 1);
+        assert_eq!(snapshot.observations(&LATENCY, &["keys"]), 1);
+        assert_eq!(snapshot.observations(&LATENCY, &["peers"]), 0);
+    }
+}
@@ -0,0 +1,123 @@
+//! This module contains the [`PrometheusRecorder`] which registers metrics with a Prometheus
+//! [`Registry`] and exports them in the text format.
+
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use prom::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{Counter, Histogram, Recorder};
+
+/// A [`Recorder`] registering metrics with a Prometheus [`Registry`] on first use.
+///
+/// Metrics registered directly with the same registry, such as the HTTP metrics of a server, are
+/// exported alongside those fed through the facade.
+pub struct PrometheusRecorder {
+    registry: Registry,
+    counters: Mutex<HashMap<&'static str, IntCounterVec>>,
+    histograms: Mutex<HashMap<&'static str, HistogramVec>>,
+}
+
+impl fmt::Debug for PrometheusRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrometheusRecorder").finish()
+    }
+}
+
+impl Default for PrometheusRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusRecorder {
+    /// Create a recorder using the default Prometheus registry.
+    pub fn new() -> Self {
+        Self::with_registry(prom::default_registry().clone())
+    }
+
+    /// Create a recorder using the registry.
+    pub fn with_registry(registry: Registry) -> Self {
+        Self {
+            registry,
+            counters: Default::default(),
+            histograms: Default::default(),
+        }
+    }
+
+    /// Encode all metrics in the registry using the Prometheus text format.
+    pub fn export(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        // This is safe as the text encoder only fails on invalid metric families
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    /// Register the collector, returning `None` if it conflicts with an existing metric.
+    fn register<C: Collector + Clone + 'static>(&self, collector: C) -> Option<C> {
+        self.registry
+            .register(Box::new(collector.clone()))
+            .ok()
+            .map(|_| collector)
+    }
+}
+
+impl Recorder for PrometheusRecorder {
+    fn increment_counter(&self, counter: &Counter, values: &[&str], value: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        if !counters.contains_key(counter.name) {
+            let vec = IntCounterVec::new(Opts::new(counter.name, counter.help), counter.labels)
+                .ok()
+                .and_then(|vec| self.register(vec));
+            match vec {
+                Some(vec) => counters.insert(counter.name, vec),
+                None => return,
+            };
+        }
+        if let Ok(metric) = counters[counter.name].get_metric_with_label_values(values) {
+            metric.inc_by(value);
+        }
+    }
+
+    fn observe_histogram(&self, histogram: &Histogram, values: &[&str], value: f64) {
+        let mut histograms = self.histograms.lock().unwrap();
+        if !histograms.contains_key(histogram.name) {
+            let opts = HistogramOpts::new(histogram.name, histogram.help);
+            let vec = HistogramVec::new(opts, histogram.labels)
+                .ok()
+                .and_then(|vec| self.register(vec));
+            match vec {
+                Some(vec) => histograms.insert(histogram.name, vec),
+                None => return,
+            };
+        }
+        if let Ok(metric) = histograms[histogram.name].get_metric_with_label_values(values) {
+            metric.observe(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export() {
+        const REQUESTS: Counter = Counter::new("requests_total", "Requests.", &["route"]);
+        const LATENCY: Histogram = Histogram::new("latency_seconds", "Latency.", &["route"]);
+
+        let recorder = PrometheusRecorder::with_registry(Registry::new());
+        recorder.increment_counter(&REQUESTS, &["keys"], 2);
+        recorder.observe_histogram(&LATENCY, &["keys"], 0.5);
+        // Mismatched label values are dropped
+        recorder.increment_counter(&REQUESTS, &["keys", "extra"], 1);
+
+        let text = String::from_utf8(recorder.export()).unwrap();
+        assert!(text.contains("requests_total{route=\"keys\"} 2"));
+        assert!(text.contains("latency_seconds_count{route=\"keys\"} 1"));
+    }
+}
@@ -1,3 +1,22 @@
 fn main() {
-    prost_build::compile_protos(&["src/proto/paymentrequest.proto"], &["src/"]).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Regenerate the models from the .proto files, rather than use those checked in
+    #[cfg(feature = "codegen")]
+    {
+        println!("cargo:rerun-if-changed=src/proto/paymentrequest.proto");
+        println!("cargo:rerun-if-changed=src/generated/bip70.rs");
+        prost_build::compile_protos(&["src/proto/paymentrequest.proto"], &["src/"]).unwrap();
+
+        // Flag models which have drifted from those checked in
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let generated = std::fs::read_to_string(format!("{}/bip70.rs", out_dir)).unwrap();
+        let checked_in = std::fs::read_to_string("src/generated/bip70.rs").unwrap_or_default();
+        if generated != checked_in {
+            println!(
+                "cargo:warning=src/generated/bip70.rs is out of date, see {}/bip70.rs",
+                out_dir
+            );
+        }
+    }
 }
@@ -0,0 +1,169 @@
+//! This module contains [`PaymentDetailsBuilder`] for constructing [`PaymentDetails`] and
+//! unsigned [`PaymentRequest`]s, along with helpers for encoding and decoding the [`bip70`]
+//! messages.
+//!
+//! [`bip70`]: crate::bip70
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+
+use crate::bip70::{Output, Payment, PaymentAck, PaymentDetails, PaymentRequest};
+
+/// Encode a protobuf message.
+pub fn encode_message<M: Message>(message: &M) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(message.encoded_len());
+    // This is safe as the buffer has sufficient capacity
+    message.encode(&mut raw).unwrap();
+    raw
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Builder for [`PaymentDetails`].
+#[derive(Clone, Debug)]
+pub struct PaymentDetailsBuilder {
+    details: PaymentDetails,
+}
+
+impl Default for PaymentDetailsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaymentDetailsBuilder {
+    /// Create a new [`PaymentDetailsBuilder`], with creation time set to now.
+    pub fn new() -> Self {
+        Self {
+            details: PaymentDetails {
+                time: unix_secs(SystemTime::now()),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set the network, "main" or "test".
+    pub fn network<S: Into<String>>(mut self, network: S) -> Self {
+        self.details.network = Some(network.into());
+        self
+    }
+
+    /// Add an output paying `amount` satoshis to the script.
+    ///
+    /// An amount of `None` allows the payer to choose the amount.
+    pub fn output(mut self, script: Vec<u8>, amount: Option<u64>) -> Self {
+        self.details.outputs.push(Output { amount, script });
+        self
+    }
+
+    /// Add outputs.
+    pub fn outputs<I: IntoIterator<Item = Output>>(mut self, outputs: I) -> Self {
+        self.details.outputs.extend(outputs);
+        self
+    }
+
+    /// Set the creation time.
+    pub fn time(mut self, time: SystemTime) -> Self {
+        self.details.time = unix_secs(time);
+        self
+    }
+
+    /// Set the time after which the request should be considered invalid.
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.details.expires = Some(unix_secs(expires));
+        self
+    }
+
+    /// Set the request to expire `ttl` after its creation time.
+    pub fn expires_after(mut self, ttl: Duration) -> Self {
+        self.details.expires = Some(self.details.time.saturating_add(ttl.as_secs()));
+        self
+    }
+
+    /// Set the human-readable memo.
+    pub fn memo<S: Into<String>>(mut self, memo: S) -> Self {
+        self.details.memo = Some(memo.into());
+        self
+    }
+
+    /// Set the URL to which the [`Payment`] should be sent.
+    pub fn payment_url<S: Into<String>>(mut self, payment_url: S) -> Self {
+        self.details.payment_url = Some(payment_url.into());
+        self
+    }
+
+    /// Set the merchant data, which is returned in the [`Payment`].
+    pub fn merchant_data(mut self, merchant_data: Vec<u8>) -> Self {
+        self.details.merchant_data = Some(merchant_data);
+        self
+    }
+
+    /// Build the [`PaymentDetails`].
+    pub fn build(self) -> PaymentDetails {
+        self.details
+    }
+
+    /// Build an unsigned [`PaymentRequest`] containing the [`PaymentDetails`].
+    pub fn build_request(self) -> PaymentRequest {
+        PaymentRequest::unsigned(&self.details)
+    }
+}
+
+impl PaymentRequest {
+    /// Create a [`PaymentRequest`], with `pki_type` "none", containing the [`PaymentDetails`].
+    pub fn unsigned(details: &PaymentDetails) -> Self {
+        Self {
+            payment_details_version: Some(1),
+            pki_type: Some("none".to_string()),
+            pki_data: None,
+            serialized_payment_details: encode_message(details),
+            signature: None,
+        }
+    }
+
+    /// Decode the [`PaymentDetails`].
+    pub fn payment_details(&self) -> Result<PaymentDetails, prost::DecodeError> {
+        PaymentDetails::decode(&self.serialized_payment_details[..])
+    }
+}
+
+impl PaymentAck {
+    /// Create a [`PaymentAck`] acknowledging the [`Payment`].
+    pub fn new(payment: Payment, memo: Option<String>) -> Self {
+        Self { payment, memo }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000);
+        let request = PaymentDetailsBuilder::new()
+            .network("test")
+            .output(vec![106], Some(500))
+            .time(time)
+            .expires_after(Duration::from_secs(60))
+            .merchant_data(b"alice".to_vec())
+            .payment_url("/payments")
+            .build_request();
+
+        let raw = encode_message(&request);
+        let decoded = PaymentRequest::decode(&raw[..]).unwrap();
+        assert_eq!(decoded.pki_type(), "none");
+
+        let details = decoded.payment_details().unwrap();
+        assert_eq!(details.network(), "test");
+        assert_eq!(details.time, 1_000);
+        assert_eq!(details.expires, Some(1_060));
+        assert_eq!(details.outputs[0].amount, Some(500));
+        assert_eq!(details.merchant_data(), b"alice");
+    }
+}
@@ -0,0 +1,81 @@
+/// Generalized form of "send payment to this/these bitcoin addresses"
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Output {
+    /// amount is integer-number-of-satoshis
+    #[prost(uint64, optional, tag="1", default="0")]
+    pub amount: ::core::option::Option<u64>,
+    /// usually one of the standard Script forms
+    #[prost(bytes="vec", required, tag="2")]
+    pub script: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PaymentDetails {
+    /// "main" or "test"
+    #[prost(string, optional, tag="1", default="main")]
+    pub network: ::core::option::Option<::prost::alloc::string::String>,
+    /// Where payment should be sent
+    #[prost(message, repeated, tag="2")]
+    pub outputs: ::prost::alloc::vec::Vec<Output>,
+    /// Timestamp; when payment request created
+    #[prost(uint64, required, tag="3")]
+    pub time: u64,
+    /// Timestamp; when this request should be considered invalid
+    #[prost(uint64, optional, tag="4")]
+    pub expires: ::core::option::Option<u64>,
+    /// Human-readable description of request for the customer
+    #[prost(string, optional, tag="5")]
+    pub memo: ::core::option::Option<::prost::alloc::string::String>,
+    /// URL to send Payment and get PaymentACK
+    #[prost(string, optional, tag="6")]
+    pub payment_url: ::core::option::Option<::prost::alloc::string::String>,
+    /// Arbitrary data to include in the Payment message
+    #[prost(bytes="vec", optional, tag="7")]
+    pub merchant_data: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PaymentRequest {
+    #[prost(uint32, optional, tag="1", default="1")]
+    pub payment_details_version: ::core::option::Option<u32>,
+    /// none / x509+sha256 / x509+sha1
+    #[prost(string, optional, tag="2", default="none")]
+    pub pki_type: ::core::option::Option<::prost::alloc::string::String>,
+    /// depends on pki_type
+    #[prost(bytes="vec", optional, tag="3")]
+    pub pki_data: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    /// PaymentDetails
+    #[prost(bytes="vec", required, tag="4")]
+    pub serialized_payment_details: ::prost::alloc::vec::Vec<u8>,
+    /// pki-dependent signature
+    #[prost(bytes="vec", optional, tag="5")]
+    pub signature: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct X509Certificates {
+    /// DER-encoded X.509 certificate chain
+    #[prost(bytes="vec", repeated, tag="1")]
+    pub certificate: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Payment {
+    /// From PaymentDetails.merchant_data
+    #[prost(bytes="vec", optional, tag="1")]
+    pub merchant_data: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    /// Signed transactions that satisfy PaymentDetails.outputs
+    #[prost(bytes="vec", repeated, tag="2")]
+    pub transactions: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// Where to send refunds, if a refund is necessary
+    #[prost(message, repeated, tag="3")]
+    pub refund_to: ::prost::alloc::vec::Vec<Output>,
+    /// Human-readable message for the merchant
+    #[prost(string, optional, tag="4")]
+    pub memo: ::core::option::Option<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PaymentAck {
+    /// Payment message that triggered this ACK
+    #[prost(message, required, tag="1")]
+    pub payment: Payment,
+    /// human-readable message for customer
+    #[prost(string, optional, tag="2")]
+    pub memo: ::core::option::Option<::prost::alloc::string::String>,
+}
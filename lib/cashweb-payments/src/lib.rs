@@ -12,6 +12,9 @@
 //! [`Wallet`]: wallet::Wallet
 //! [`BIP70: Payment Protocol`]: https://github.com/bitcoin/bips/blob/master/bip-0070.mediawiki
 
+pub mod builder;
+pub mod pki;
+pub mod verification;
 pub mod wallet;
 
 use bytes::Buf;
@@ -25,11 +28,23 @@ pub mod bip70 {
     //!
     //! [`BIP70: Payment Protocol`]: https://github.com/bitcoin/bips/blob/master/bip-0070.mediawiki
 
+    #[cfg(feature = "codegen")]
     include!(concat!(env!("OUT_DIR"), "/bip70.rs"));
+    #[cfg(not(feature = "codegen"))]
+    include!("generated/bip70.rs");
 }
 
 use bip70::Payment;
 
+/// MIME type of an encoded [`PaymentRequest`](bip70::PaymentRequest).
+pub const PAYMENT_REQUEST_MIME: &str = "application/bitcoincash-paymentrequest";
+
+/// MIME type of an encoded [`Payment`].
+pub const PAYMENT_MIME: &str = "application/bitcoincash-payment";
+
+/// MIME type of an encoded [`PaymentAck`](bip70::PaymentAck).
+pub const PAYMENT_ACK_MIME: &str = "application/bitcoincash-paymentack";
+
 /// Error associated with payment preprocessing.
 #[derive(Debug, Error)]
 pub enum PreprocessingError {
@@ -50,8 +65,8 @@ pub async fn preprocess_payment<B: Buf>(
     body: B,
 ) -> Result<Payment, PreprocessingError> {
     // Bitcoin Cash Headers
-    let bch_content_type_value = HeaderValue::from_static("application/bitcoincash-payment");
-    let bch_accept_value = HeaderValue::from_static("application/bitcoincash-paymentack");
+    let bch_content_type_value = HeaderValue::from_static(PAYMENT_MIME);
+    let bch_accept_value = HeaderValue::from_static(PAYMENT_ACK_MIME);
 
     // Check for content-type header
     if !headers
@@ -0,0 +1,303 @@
+//! This module contains signing and verification of [`PaymentRequest`]s, allowing wallets to
+//! display a verified payee identity.
+//!
+//! Requests with `pki_type` "x509+sha256" carry the payee's certificate chain in `pki_data` and
+//! a signature, by the leaf certificate's key, over the request serialized with an empty
+//! signature.
+
+use std::{convert::TryFrom, fmt, time::SystemTime};
+
+use prost::Message;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use thiserror::Error;
+use webpki::{DNSNameRef, EndEntityCert, SignatureAlgorithm, TLSServerTrustAnchors, TrustAnchor};
+
+use crate::{
+    bip70::{PaymentDetails, PaymentRequest, X509Certificates},
+    builder::encode_message,
+};
+
+/// Signature algorithms accepted when verifying certificate chains and requests.
+static SIGNATURE_ALGORITHMS: &[&SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+/// Signature algorithms which, per "x509+sha256", use SHA-256.
+static SHA256_ALGORITHMS: &[&SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+];
+
+/// Enumeration of supported PKI types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PkiType {
+    /// Unsigned request.
+    None,
+    /// Request signed using an X.509 certificate, with SHA-256.
+    X509Sha256,
+}
+
+impl PkiType {
+    /// The `pki_type` field value.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::X509Sha256 => "x509+sha256",
+        }
+    }
+}
+
+impl TryFrom<&str> for PkiType {
+    type Error = PkiError;
+
+    fn try_from(pki_type: &str) -> Result<Self, Self::Error> {
+        match pki_type {
+            "none" => Ok(Self::None),
+            "x509+sha256" => Ok(Self::X509Sha256),
+            other => Err(PkiError::UnsupportedPkiType(other.to_string())),
+        }
+    }
+}
+
+/// Error associated with signing and verifying [`PaymentRequest`]s.
+#[derive(Debug, Error)]
+pub enum PkiError {
+    /// The `pki_type` is not supported.
+    #[error("unsupported pki type: {0}")]
+    UnsupportedPkiType(String),
+    /// The request carried no certificate chain.
+    #[error("missing certificate chain")]
+    MissingCertificates,
+    /// The request carried no signature.
+    #[error("missing signature")]
+    MissingSignature,
+    /// Failed to decode the certificate chain.
+    #[error("failed to decode certificate chain: {0}")]
+    Decode(prost::DecodeError),
+    /// The certificate chain is invalid or untrusted.
+    #[error("invalid certificate: {0}")]
+    Certificate(webpki::Error),
+    /// The signature is invalid.
+    #[error("invalid signature")]
+    Signature,
+    /// Failed to sign the request.
+    #[error("failed to sign request")]
+    Signing,
+}
+
+/// Serialize a DER-encoded certificate chain, leaf first, for use as `pki_data`.
+pub fn encode_certificate_chain(certificates: Vec<Vec<u8>>) -> Vec<u8> {
+    encode_message(&X509Certificates {
+        certificate: certificates,
+    })
+}
+
+/// Deserialize the DER-encoded certificate chain, leaf first, from `pki_data`.
+pub fn decode_certificate_chain(pki_data: &[u8]) -> Result<Vec<Vec<u8>>, prost::DecodeError> {
+    X509Certificates::decode(pki_data).map(|certificates| certificates.certificate)
+}
+
+/// Key used to sign [`PaymentRequest`]s, corresponding to the leaf certificate.
+pub enum SigningKey {
+    /// RSA key, producing PKCS#1 SHA-256 signatures.
+    Rsa(RsaKeyPair),
+    /// ECDSA P-256 key, producing ASN.1 SHA-256 signatures.
+    EcdsaP256(EcdsaKeyPair),
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rsa(_) => f.write_str("SigningKey::Rsa"),
+            Self::EcdsaP256(_) => f.write_str("SigningKey::EcdsaP256"),
+        }
+    }
+}
+
+impl SigningKey {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, PkiError> {
+        let rng = SystemRandom::new();
+        match self {
+            Self::Rsa(key_pair) => {
+                let mut signature = vec![0; key_pair.public_modulus_len()];
+                key_pair
+                    .sign(&RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                    .map_err(|_| PkiError::Signing)?;
+                Ok(signature)
+            }
+            Self::EcdsaP256(key_pair) => key_pair
+                .sign(&rng, message)
+                .map(|signature| signature.as_ref().to_vec())
+                .map_err(|_| PkiError::Signing),
+        }
+    }
+}
+
+/// Serialize the request with an empty signature, producing the signed message.
+fn signing_message(request: &PaymentRequest) -> Vec<u8> {
+    let mut unsigned = request.clone();
+    unsigned.signature = Some(Vec::new());
+    encode_message(&unsigned)
+}
+
+/// Create a [`PaymentRequest`], with `pki_type` "x509+sha256", containing the
+/// [`PaymentDetails`] and signed by the key of the leaf certificate.
+///
+/// The certificate chain is DER-encoded, leaf first, and need not include the root.
+pub fn sign_request(
+    details: &PaymentDetails,
+    certificates: Vec<Vec<u8>>,
+    key: &SigningKey,
+) -> Result<PaymentRequest, PkiError> {
+    let mut request = PaymentRequest::unsigned(details);
+    request.pki_type = Some(PkiType::X509Sha256.as_str().to_string());
+    request.pki_data = Some(encode_certificate_chain(certificates));
+    let signature = key.sign(&signing_message(&request))?;
+    request.signature = Some(signature);
+    Ok(request)
+}
+
+/// The payee of a verified [`PaymentRequest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payee {
+    /// The request was unsigned, the payee is unknown.
+    Unverified,
+    /// The request was signed by the holder of the certificate.
+    Verified {
+        /// The DER-encoded leaf certificate.
+        certificate: Vec<u8>,
+    },
+}
+
+impl Payee {
+    /// Whether the payee was verified and its certificate is valid for the DNS name.
+    pub fn is_valid_for_dns_name(&self, dns_name: &str) -> bool {
+        let certificate = match self {
+            Self::Unverified => return false,
+            Self::Verified { certificate } => certificate,
+        };
+        let dns_name = match DNSNameRef::try_from_ascii_str(dns_name) {
+            Ok(ok) => ok,
+            Err(_) => return false,
+        };
+        EndEntityCert::from(certificate)
+            .and_then(|cert| cert.verify_is_valid_for_dns_name(dns_name))
+            .is_ok()
+    }
+}
+
+/// Verify the signature of a [`PaymentRequest`] at `time`, with certificate chains validated
+/// against the trust anchors.
+///
+/// Unsigned requests verify as [`Payee::Unverified`].
+pub fn verify_request(
+    request: &PaymentRequest,
+    trust_anchors: &[TrustAnchor<'_>],
+    time: SystemTime,
+) -> Result<Payee, PkiError> {
+    match PkiType::try_from(request.pki_type())? {
+        PkiType::None => return Ok(Payee::Unverified),
+        PkiType::X509Sha256 => (),
+    }
+
+    let pki_data = request
+        .pki_data
+        .as_ref()
+        .ok_or(PkiError::MissingCertificates)?;
+    let certificates = decode_certificate_chain(pki_data).map_err(PkiError::Decode)?;
+    let (leaf, intermediates) = certificates
+        .split_first()
+        .ok_or(PkiError::MissingCertificates)?;
+    let signature = request
+        .signature
+        .as_ref()
+        .filter(|signature| !signature.is_empty())
+        .ok_or(PkiError::MissingSignature)?;
+
+    // Validate certificate chain
+    let cert = EndEntityCert::from(leaf).map_err(PkiError::Certificate)?;
+    let intermediates: Vec<&[u8]> = intermediates.iter().map(Vec::as_slice).collect();
+    let time = webpki::Time::try_from(time)
+        .map_err(|_| PkiError::Certificate(webpki::Error::BadDERTime))?;
+    cert.verify_is_valid_tls_server_cert(
+        SIGNATURE_ALGORITHMS,
+        &TLSServerTrustAnchors(trust_anchors),
+        &intermediates,
+        time,
+    )
+    .map_err(PkiError::Certificate)?;
+
+    // Verify signature
+    let message = signing_message(request);
+    if SHA256_ALGORITHMS.iter().any(|algorithm| {
+        cert.verify_signature(algorithm, &message, signature)
+            .is_ok()
+    }) {
+        Ok(Payee::Verified {
+            certificate: leaf.clone(),
+        })
+    } else {
+        Err(PkiError::Signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+
+    use super::*;
+    use crate::builder::PaymentDetailsBuilder;
+
+    #[test]
+    fn sign_and_verify() {
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let ca_der = ca.serialize_der().unwrap();
+
+        let leaf =
+            rcgen::generate_simple_self_signed(vec!["keyserver.example".to_string()]).unwrap();
+        let leaf_der = leaf.serialize_der_with_signer(&ca).unwrap();
+        let key = SigningKey::EcdsaP256(
+            EcdsaKeyPair::from_pkcs8(
+                &ECDSA_P256_SHA256_ASN1_SIGNING,
+                &leaf.serialize_private_key_der(),
+            )
+            .unwrap(),
+        );
+
+        let details = PaymentDetailsBuilder::new().output(vec![106], None).build();
+        let request = sign_request(&details, vec![leaf_der.clone()], &key).unwrap();
+
+        let anchors = [webpki::trust_anchor_util::cert_der_as_trust_anchor(&ca_der).unwrap()];
+        let payee = verify_request(&request, &anchors, SystemTime::now()).unwrap();
+        assert_eq!(
+            payee,
+            Payee::Verified {
+                certificate: leaf_der
+            }
+        );
+        assert!(payee.is_valid_for_dns_name("keyserver.example"));
+        assert!(!payee.is_valid_for_dns_name("other.example"));
+
+        let mut tampered = request.clone();
+        tampered.serialized_payment_details = encode_message(&PaymentDetails::default());
+        assert!(matches!(
+            verify_request(&tampered, &anchors, SystemTime::now()),
+            Err(PkiError::Signature)
+        ));
+
+        let unsigned = PaymentRequest::unsigned(&details);
+        assert_eq!(
+            verify_request(&unsigned, &anchors, SystemTime::now()).unwrap(),
+            Payee::Unverified
+        );
+    }
+}
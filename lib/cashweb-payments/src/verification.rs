@@ -0,0 +1,249 @@
+//! This module contains [`verify_payment`] which checks that a [`Payment`] satisfies the
+//! [`PaymentDetails`] it was made against, reporting every [`Discrepancy`] found.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cashweb_bitcoin::{
+    transaction::{DecodeError, Transaction},
+    Decodable,
+};
+
+use crate::bip70::{Payment, PaymentDetails, PaymentRequest};
+
+/// A way in which a [`Payment`] fails to satisfy its [`PaymentDetails`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The payment contains no transactions.
+    NoTransactions,
+    /// A transaction failed to decode.
+    MalformedTransaction {
+        /// Index of the transaction within the payment.
+        index: usize,
+        /// The decoding error.
+        error: DecodeError,
+    },
+    /// No output pays the requested script.
+    MissingOutput {
+        /// The requested script.
+        script: Vec<u8>,
+    },
+    /// The outputs paying the requested script total less than the requested amount.
+    Underpaid {
+        /// The requested script.
+        script: Vec<u8>,
+        /// Total amount requested, in satoshis.
+        expected: u64,
+        /// Total amount received, in satoshis.
+        received: u64,
+    },
+    /// The request had expired.
+    Expired {
+        /// Expiry of the request, in UNIX seconds.
+        expires: u64,
+        /// Time of verification, in UNIX seconds.
+        now: u64,
+    },
+    /// The merchant data was not returned unaltered.
+    MerchantDataMismatch,
+    /// The memo differs from the memo of the request.
+    MemoMismatch,
+}
+
+/// The result of verifying a [`Payment`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// Transactions decoded from the payment.
+    pub transactions: Vec<Transaction>,
+    /// Discrepancies found, empty if the payment satisfies the request.
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl VerificationReport {
+    /// Whether the payment satisfies the request.
+    pub fn is_valid(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Verify that a [`Payment`], received at `now`, satisfies the [`PaymentDetails`].
+///
+/// Amounts requested for the same script are totalled, as are the amounts paid to it across all
+/// transactions. Outputs requested without an amount only need to be present. A memo is only
+/// checked if both the request and payment carry one.
+pub fn verify_payment(
+    details: &PaymentDetails,
+    payment: &Payment,
+    now: SystemTime,
+) -> VerificationReport {
+    let mut report = VerificationReport::default();
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    if let Some(expires) = details.expires {
+        if now > expires {
+            report
+                .discrepancies
+                .push(Discrepancy::Expired { expires, now });
+        }
+    }
+
+    if details.merchant_data != payment.merchant_data {
+        report.discrepancies.push(Discrepancy::MerchantDataMismatch);
+    }
+    if let (Some(expected), Some(memo)) = (&details.memo, &payment.memo) {
+        if expected != memo {
+            report.discrepancies.push(Discrepancy::MemoMismatch);
+        }
+    }
+
+    if payment.transactions.is_empty() {
+        report.discrepancies.push(Discrepancy::NoTransactions);
+    }
+    for (index, raw_tx) in payment.transactions.iter().enumerate() {
+        match Transaction::decode(&mut raw_tx.as_slice()) {
+            Ok(tx) => report.transactions.push(tx),
+            Err(error) => report
+                .discrepancies
+                .push(Discrepancy::MalformedTransaction { index, error }),
+        }
+    }
+
+    // Total amounts received per script
+    let mut received: HashMap<&[u8], Option<u64>> = HashMap::new();
+    for output in report.transactions.iter().flat_map(|tx| &tx.outputs) {
+        let total = received.entry(output.script.as_bytes()).or_insert(Some(0));
+        *total = total.and_then(|total| total.checked_add(output.value));
+    }
+
+    // Total amounts expected per script, in order of first request
+    let mut expected: Vec<(&[u8], u64)> = Vec::new();
+    for output in &details.outputs {
+        let amount = output.amount.unwrap_or_default();
+        match expected
+            .iter_mut()
+            .find(|(script, _)| *script == output.script.as_slice())
+        {
+            Some((_, total)) => *total = total.saturating_add(amount),
+            None => expected.push((&output.script, amount)),
+        }
+    }
+
+    let mut output_discrepancies = Vec::new();
+    for (script, expected) in expected {
+        match received.get(script) {
+            None => output_discrepancies.push(Discrepancy::MissingOutput {
+                script: script.to_vec(),
+            }),
+            // Overflow can only result from paying more than the requested amount
+            Some(None) => (),
+            Some(Some(received)) if *received < expected => {
+                output_discrepancies.push(Discrepancy::Underpaid {
+                    script: script.to_vec(),
+                    expected,
+                    received: *received,
+                })
+            }
+            Some(Some(_)) => (),
+        }
+    }
+    report.discrepancies.extend(output_discrepancies);
+
+    report
+}
+
+/// Verify that a [`Payment`], received at `now`, satisfies the [`PaymentRequest`].
+pub fn verify_payment_request(
+    request: &PaymentRequest,
+    payment: &Payment,
+    now: SystemTime,
+) -> Result<VerificationReport, prost::DecodeError> {
+    let details = request.payment_details()?;
+    Ok(verify_payment(&details, payment, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cashweb_bitcoin::{
+        transaction::{output::Output, script::Script},
+        Encodable,
+    };
+
+    use super::*;
+    use crate::builder::PaymentDetailsBuilder;
+
+    fn raw_transaction(outputs: Vec<(Vec<u8>, u64)>) -> Vec<u8> {
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![],
+            outputs: outputs
+                .into_iter()
+                .map(|(script, value)| Output {
+                    value,
+                    script: Script::from(script),
+                })
+                .collect(),
+            lock_time: 0,
+        };
+        let mut raw = Vec::with_capacity(tx.encoded_len());
+        tx.encode_raw(&mut raw);
+        raw
+    }
+
+    #[test]
+    fn discrepancies() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_000);
+        let details = PaymentDetailsBuilder::new()
+            .time(time)
+            .expires_after(Duration::from_secs(60))
+            .output(vec![1], Some(500))
+            .output(vec![1], Some(500))
+            .output(vec![2], None)
+            .merchant_data(b"alice".to_vec())
+            .build();
+
+        let payment = Payment {
+            merchant_data: Some(b"alice".to_vec()),
+            transactions: vec![raw_transaction(vec![(vec![1], 600), (vec![2], 0)])],
+            refund_to: vec![],
+            memo: None,
+        };
+        assert_eq!(
+            verify_payment(&details, &payment, time).discrepancies,
+            vec![Discrepancy::Underpaid {
+                script: vec![1],
+                expected: 1_000,
+                received: 600
+            }]
+        );
+
+        let payment = Payment {
+            merchant_data: Some(b"bob".to_vec()),
+            transactions: vec![
+                raw_transaction(vec![(vec![1], 600)]),
+                raw_transaction(vec![(vec![1], 400)]),
+            ],
+            refund_to: vec![],
+            memo: None,
+        };
+        let report = verify_payment(&details, &payment, time + Duration::from_secs(61));
+        assert_eq!(report.transactions.len(), 2);
+        assert_eq!(
+            report.discrepancies,
+            vec![
+                Discrepancy::Expired {
+                    expires: 1_060,
+                    now: 1_061
+                },
+                Discrepancy::MerchantDataMismatch,
+                Discrepancy::MissingOutput { script: vec![2] },
+            ]
+        );
+    }
+}
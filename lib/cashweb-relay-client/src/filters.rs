@@ -0,0 +1,165 @@
+//! This module contains [`MessageFilter`] which selects the range of messages, or payloads,
+//! pulled from a relay server.
+
+use cashweb_relay::{MessagePage, PayloadPage};
+
+/// A bound of a range of messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// Received time, in milliseconds since the UNIX epoch.
+    Time(u64),
+    /// Payload digest of a message.
+    Digest(Vec<u8>),
+}
+
+impl Bound {
+    fn to_query(&self, prefix: &str) -> String {
+        match self {
+            Self::Time(time) => format!("{}_time={}", prefix, time),
+            Self::Digest(digest) => format!("{}_digest={}", prefix, hex::encode(digest)),
+        }
+    }
+}
+
+/// Selects the messages received from the start bound, inclusive, up to the end bound,
+/// exclusive.
+///
+/// The selection may be narrowed by skipping an offset of messages from the start bound and
+/// limiting the number of messages returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageFilter {
+    start: Bound,
+    end: Option<Bound>,
+    offset: u32,
+    limit: Option<u32>,
+}
+
+impl MessageFilter {
+    /// Select messages received since the time, in milliseconds since the UNIX epoch.
+    pub fn since(time: u64) -> Self {
+        Self {
+            start: Bound::Time(time),
+            end: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+
+    /// Select messages starting from the message with the payload digest.
+    pub fn from_digest(digest: Vec<u8>) -> Self {
+        Self {
+            start: Bound::Digest(digest),
+            end: None,
+            offset: 0,
+            limit: None,
+        }
+    }
+
+    /// Select only messages before the end bound.
+    pub fn with_end(mut self, end: Bound) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Skip a number of messages from the start bound.
+    pub fn with_offset(mut self, offset: u32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Select at most a number of messages.
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// The start bound.
+    pub fn start(&self) -> &Bound {
+        &self.start
+    }
+
+    /// The end bound, if any.
+    pub fn end(&self) -> Option<&Bound> {
+        self.end.as_ref()
+    }
+
+    /// The number of messages skipped from the start bound.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// The maximum number of messages selected, if any.
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    /// The filter selecting the page following the page ending with the payload digest.
+    ///
+    /// As the start bound is inclusive, the following page begins with the final message of the
+    /// previous page, unless skipped using an offset of one. An empty digest, as returned with an
+    /// empty page, leaves the filter unchanged.
+    pub fn next(&self, end_digest: &[u8]) -> Self {
+        if end_digest.is_empty() {
+            return self.clone();
+        }
+        Self {
+            start: Bound::Digest(end_digest.to_vec()),
+            end: self.end.clone(),
+            offset: self.offset,
+            limit: self.limit,
+        }
+    }
+
+    /// The filter selecting the page following the [`MessagePage`].
+    pub fn next_messages(&self, page: &MessagePage) -> Self {
+        self.next(&page.end_digest)
+    }
+
+    /// The filter selecting the page following the [`PayloadPage`].
+    pub fn next_payloads(&self, page: &PayloadPage) -> Self {
+        self.next(&page.end_digest)
+    }
+
+    /// Encode as a URL query string.
+    pub fn to_query(&self) -> String {
+        let mut query = self.start.to_query("start");
+        if let Some(end) = &self.end {
+            query = format!("{}&{}", query, end.to_query("end"));
+        }
+        if self.offset != 0 {
+            query = format!("{}&offset={}", query, self.offset);
+        }
+        if let Some(limit) = self.limit {
+            query = format!("{}&limit={}", query, limit);
+        }
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query() {
+        let filter = MessageFilter::since(100);
+        assert_eq!(filter.to_query(), "start_time=100");
+
+        let filter = filter.with_end(Bound::Time(200));
+        assert_eq!(filter.to_query(), "start_time=100&end_time=200");
+
+        let page = MessagePage {
+            end_digest: vec![0xab, 0xcd],
+            ..Default::default()
+        };
+        let next = filter.next_messages(&page);
+        assert_eq!(next.to_query(), "start_digest=abcd&end_time=200");
+        assert_eq!(next.next_messages(&MessagePage::default()), next);
+
+        let next = next.with_offset(1).with_limit(50);
+        assert_eq!(
+            next.to_query(),
+            "start_digest=abcd&end_time=200&offset=1&limit=50"
+        );
+    }
+}
@@ -8,7 +8,9 @@
 //! `cashweb-relay-client` is a library providing [`RelayClient`] which allows
 //! interaction with specific relay server.
 
+pub mod filters;
 pub mod services;
+pub mod sync;
 
 use std::{error, fmt};
 
@@ -17,7 +19,9 @@ pub use hyper::{
     Uri,
 };
 
-use cashweb_relay::Profile;
+use cashweb_relay::{
+    Acknowledgements, DeliveryReceipts, Filters, MessagePage, MessageSet, PayloadPage, Profile,
+};
 use hyper::client::Client as HyperClient;
 use hyper::http::uri::InvalidUri;
 use secp256k1::key::PublicKey;
@@ -25,7 +29,13 @@ use thiserror::Error;
 use tower_service::Service;
 use tower_util::ServiceExt;
 
-use crate::services::{GetProfile, PutProfile};
+use crate::{
+    filters::MessageFilter,
+    services::{
+        GetFilters, GetMessages, GetPayloads, GetProfile, GetReceipts, PutAcks, PutFilters,
+        PutMessages, PutProfile,
+    },
+};
 
 /// RelayClient allows queries to specific relay servers.
 #[derive(Clone, Debug)]
@@ -133,3 +143,351 @@ where
             .map_err(RelayError::Error)
     }
 }
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, PutMessages), Response = ()>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PutMessages)>>::Future: Send + 'static,
+    <Self as Service<(Uri, PutMessages)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Push a [`MessageSet`] to a relay server.
+    pub async fn push_messages(
+        &self,
+        relay_url: &str,
+        address: &str,
+        messages: MessageSet,
+    ) -> Result<(), RelayError<<Self as Service<(Uri, PutMessages)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/messages/{}", relay_url, address);
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, PutMessages { messages });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetMessages), Response = MessagePage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Pull a [`MessagePage`], selected by the [`MessageFilter`], from a relay server.
+    ///
+    /// Subsequent pages are pulled using [`MessageFilter::next_messages`], or followed
+    /// transparently using [`RelayClient::sync_messages`].
+    pub async fn get_messages(
+        &self,
+        relay_url: &str,
+        address: &str,
+        filter: &MessageFilter,
+        token: String,
+    ) -> Result<MessagePage, RelayError<<Self as Service<(Uri, GetMessages)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/messages/{}?{}", relay_url, address, filter.to_query());
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetMessages { token });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetPayloads), Response = PayloadPage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetPayloads)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetPayloads)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Pull a [`PayloadPage`], selected by the [`MessageFilter`], from a relay server.
+    ///
+    /// Subsequent pages are pulled using [`MessageFilter::next_payloads`].
+    pub async fn get_payloads(
+        &self,
+        relay_url: &str,
+        address: &str,
+        filter: &MessageFilter,
+        token: String,
+    ) -> Result<PayloadPage, RelayError<<Self as Service<(Uri, GetPayloads)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/payloads/{}?{}", relay_url, address, filter.to_query());
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetPayloads { token });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetFilters), Response = Filters>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetFilters)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetFilters)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Get the [`Filters`] published by an address.
+    pub async fn get_filters(
+        &self,
+        relay_url: &str,
+        address: &str,
+    ) -> Result<Filters, RelayError<<Self as Service<(Uri, GetFilters)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/filters/{}", relay_url, address);
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetFilters);
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, PutFilters), Response = ()>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PutFilters)>>::Future: Send + 'static,
+    <Self as Service<(Uri, PutFilters)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Publish [`Filters`] for an address to a relay server.
+    pub async fn put_filters(
+        &self,
+        relay_url: &str,
+        address: &str,
+        filters: Filters,
+        token: String,
+    ) -> Result<(), RelayError<<Self as Service<(Uri, PutFilters)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/filters/{}", relay_url, address);
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, PutFilters { token, filters });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, PutAcks), Response = ()>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, PutAcks)>>::Future: Send + 'static,
+    <Self as Service<(Uri, PutAcks)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Acknowledge the delivery of a message, by its payload digest, to a relay server.
+    ///
+    /// The relay server prunes the message and holds a receipt for its source.
+    pub async fn acknowledge(
+        &self,
+        relay_url: &str,
+        address: &str,
+        payload_digest: Vec<u8>,
+        token: String,
+    ) -> Result<(), RelayError<<Self as Service<(Uri, PutAcks)>>::Error>> {
+        self.acknowledge_batch(relay_url, address, vec![payload_digest], token)
+            .await
+    }
+
+    /// Acknowledge the delivery of a batch of messages, by their payload digests, to a relay
+    /// server.
+    ///
+    /// Messages unknown to the relay server, such as those already acknowledged, are ignored.
+    pub async fn acknowledge_batch(
+        &self,
+        relay_url: &str,
+        address: &str,
+        payload_digests: Vec<Vec<u8>>,
+        token: String,
+    ) -> Result<(), RelayError<<Self as Service<(Uri, PutAcks)>>::Error>> {
+        // Construct URI
+        let full_path = format!("{}/acks/{}", relay_url, address);
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let acknowledgements = Acknowledgements { payload_digests };
+        let request = (
+            uri,
+            PutAcks {
+                token,
+                acknowledgements,
+            },
+        );
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetReceipts), Response = DeliveryReceipts>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetReceipts)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetReceipts)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Get a page of the [`DeliveryReceipts`] held for the messages sent by an address.
+    ///
+    /// Receipts are ordered by the time of acknowledgement, skipping `offset` receipts and
+    /// returning at most `limit`, subject to the relay server's page limit.
+    pub async fn get_receipts(
+        &self,
+        relay_url: &str,
+        address: &str,
+        offset: u32,
+        limit: Option<u32>,
+        token: String,
+    ) -> Result<DeliveryReceipts, RelayError<<Self as Service<(Uri, GetReceipts)>>::Error>> {
+        // Construct URI
+        let mut full_path = format!("{}/receipts/{}?offset={}", relay_url, address, offset);
+        if let Some(limit) = limit {
+            full_path.push_str(&format!("&limit={}", limit));
+        }
+        let uri: Uri = full_path.parse().map_err(RelayError::Uri)?;
+
+        // Construct request
+        let request = (uri, GetReceipts { token });
+
+        // Get response
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(RelayError::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use cashweb_relay::DeliveryReceipt;
+    use hyper::{Body, Method, Request, Response, StatusCode};
+    use prost::Message as _;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockRelay;
+
+    impl Service<Request<Body>> for MockRelay {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let uri = request.uri();
+            let response = match (request.method(), uri.path(), uri.query()) {
+                (&Method::PUT, "/messages/alice", _) => Response::new(Body::empty()),
+                (&Method::PUT, "/acks/bob", _) => Response::new(Body::empty()),
+                (&Method::GET, "/receipts/alice", Some("offset=0&limit=10")) => {
+                    let receipts = DeliveryReceipts {
+                        receipts: vec![DeliveryReceipt {
+                            payload_digest: vec![1],
+                            ..Default::default()
+                        }],
+                    };
+                    let mut raw_receipts = Vec::with_capacity(receipts.encoded_len());
+                    receipts.encode(&mut raw_receipts).unwrap();
+                    Response::new(Body::from(raw_receipts))
+                }
+                (&Method::GET, "/messages/alice", Some("start_time=100")) => {
+                    let page = MessagePage {
+                        start_time: 100,
+                        end_time: 200,
+                        end_digest: vec![1],
+                        ..Default::default()
+                    };
+                    let mut raw_page = Vec::with_capacity(page.encoded_len());
+                    page.encode(&mut raw_page).unwrap();
+                    Response::new(Body::from(raw_page))
+                }
+                _ => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    response
+                }
+            };
+            ready(Ok(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn push_and_pull() {
+        let client = RelayClient::from_service(MockRelay);
+        let url = "http://relay";
+
+        client
+            .push_messages(url, "alice", MessageSet::default())
+            .await
+            .unwrap();
+
+        let filter = MessageFilter::since(100);
+        let page = client
+            .get_messages(url, "alice", &filter, "POP token".to_string())
+            .await
+            .unwrap();
+        assert_eq!(page.end_time, 200);
+
+        let next = filter.next_messages(&page);
+        assert!(client
+            .get_messages(url, "alice", &next, "POP token".to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn acknowledge() {
+        let client = RelayClient::from_service(MockRelay);
+        let url = "http://relay";
+
+        client
+            .acknowledge(url, "bob", vec![1], "POP token".to_string())
+            .await
+            .unwrap();
+
+        let receipts = client
+            .get_receipts(url, "alice", 0, Some(10), "POP token".to_string())
+            .await
+            .unwrap();
+        assert_eq!(receipts.receipts[0].payload_digest, vec![1]);
+    }
+}
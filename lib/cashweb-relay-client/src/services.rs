@@ -3,7 +3,9 @@
 use std::{fmt, pin::Pin};
 
 use cashweb_auth_wrapper::AuthWrapper;
-use cashweb_relay::{MessagePage, Profile};
+use cashweb_relay::{
+    Acknowledgements, DeliveryReceipts, Filters, MessagePage, MessageSet, PayloadPage, Profile,
+};
 use futures_core::{
     task::{Context, Poll},
     Future,
@@ -248,3 +250,431 @@ where
         Box::pin(fut)
     }
 }
+
+/// Request for pushing a [`MessageSet`] to the relay server.
+#[derive(Clone, Debug)]
+pub struct PutMessages {
+    /// The messages to be pushed.
+    pub messages: MessageSet,
+}
+
+/// Error associated with pushing a [`MessageSet`] to the relay server.
+#[derive(Clone, Debug, Error)]
+pub enum PutMessagesError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, PutMessages)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = ();
+    type Error = PutMessagesError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(PutMessagesError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, PutMessages)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        // Construct body
+        let mut body = Vec::with_capacity(request.messages.encoded_len());
+        request.messages.encode(&mut body).unwrap(); // This is safe
+
+        let http_request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .body(Body::from(body))
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            Ok(())
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Error associated with getting a [`PayloadPage`] from the relay server.
+#[derive(Debug, Error)]
+pub enum GetPayloadsError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(HyperError),
+    /// Error while decoding the [`PayloadPage`].
+    #[error("payloadpage decoding failure: {0}")]
+    PayloadPageDecode(DecodeError),
+}
+
+/// Represents a request for a [`PayloadPage`].
+#[derive(Clone, Debug)]
+pub struct GetPayloads {
+    /// POP token attached to the request.
+    pub token: String,
+}
+
+impl<S> Service<(Uri, GetPayloads)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = PayloadPage;
+    type Error = GetPayloadsError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetPayloadsError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, GetPayloads)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .header(AUTHORIZATION, request.token)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            // Deserialize and decode body
+            let body = response.into_body();
+            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let payload_page = PayloadPage::decode(buf).map_err(Self::Error::PayloadPageDecode)?;
+
+            Ok(payload_page)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for the [`Filters`] of an address.
+#[derive(Clone, Debug)]
+pub struct GetFilters;
+
+/// Error associated with getting [`Filters`] from the relay server.
+#[derive(Debug, Error)]
+pub enum GetFiltersError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(HyperError),
+    /// Error while decoding the [`Filters`].
+    #[error("filters decoding failure: {0}")]
+    FiltersDecode(DecodeError),
+}
+
+impl<S> Service<(Uri, GetFilters)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = Filters;
+    type Error = GetFiltersError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetFiltersError::Service)
+    }
+
+    fn call(&mut self, (uri, _): (Uri, GetFilters)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            // Deserialize and decode body
+            let body = response.into_body();
+            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let filters = Filters::decode(buf).map_err(Self::Error::FiltersDecode)?;
+
+            Ok(filters)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Request for putting [`Filters`] to the relay server.
+#[derive(Clone, Debug)]
+pub struct PutFilters {
+    /// POP token attached to the request.
+    pub token: String,
+    /// The [`Filters`] to be put.
+    pub filters: Filters,
+}
+
+/// Error associated with putting [`Filters`] to the relay server.
+#[derive(Clone, Debug, Error)]
+pub enum PutFiltersError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, PutFilters)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = ();
+    type Error = PutFiltersError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(PutFiltersError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, PutFilters)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        // Construct body
+        let mut body = Vec::with_capacity(request.filters.encoded_len());
+        request.filters.encode(&mut body).unwrap(); // This is safe
+
+        let http_request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .header(AUTHORIZATION, request.token)
+            .body(Body::from(body))
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            Ok(())
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Request for acknowledging the delivery of messages to the relay server.
+#[derive(Clone, Debug)]
+pub struct PutAcks {
+    /// POP token attached to the request.
+    pub token: String,
+    /// The [`Acknowledgements`] to be put.
+    pub acknowledgements: Acknowledgements,
+}
+
+/// Error associated with putting [`Acknowledgements`] to the relay server.
+#[derive(Clone, Debug, Error)]
+pub enum PutAcksError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+}
+
+impl<S> Service<(Uri, PutAcks)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = ();
+    type Error = PutAcksError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(PutAcksError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, PutAcks)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        // Construct body
+        let mut body = Vec::with_capacity(request.acknowledgements.encoded_len());
+        request.acknowledgements.encode(&mut body).unwrap(); // This is safe
+
+        let http_request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .header(AUTHORIZATION, request.token)
+            .body(Body::from(body))
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            Ok(())
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Represents a request for the [`DeliveryReceipts`] held for an address.
+#[derive(Clone, Debug)]
+pub struct GetReceipts {
+    /// POP token attached to the request.
+    pub token: String,
+}
+
+/// Error associated with getting [`DeliveryReceipts`] from the relay server.
+#[derive(Debug, Error)]
+pub enum GetReceiptsError<E: fmt::Debug + fmt::Display> {
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(HyperError),
+    /// Error while decoding the [`DeliveryReceipts`].
+    #[error("receipts decoding failure: {0}")]
+    ReceiptsDecode(DecodeError),
+}
+
+impl<S> Service<(Uri, GetReceipts)> for RelayClient<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = DeliveryReceipts;
+    type Error = GetReceiptsError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner_client
+            .poll_ready(context)
+            .map_err(GetReceiptsError::Service)
+    }
+
+    fn call(&mut self, (uri, request): (Uri, GetReceipts)) -> Self::Future {
+        let mut client = self.inner_client.clone();
+
+        let http_request = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .header(AUTHORIZATION, request.token)
+            .body(Body::empty())
+            .unwrap(); // This is safe
+
+        let fut = async move {
+            // Get response
+            let response = client
+                .call(http_request)
+                .await
+                .map_err(Self::Error::Service)?;
+
+            // Check status code
+            match response.status() {
+                StatusCode::OK => (),
+                code => return Err(Self::Error::UnexpectedStatusCode(code.as_u16())),
+            }
+
+            // Deserialize and decode body
+            let body = response.into_body();
+            let buf = aggregate(body).await.map_err(Self::Error::Body)?;
+            let receipts = DeliveryReceipts::decode(buf).map_err(Self::Error::ReceiptsDecode)?;
+
+            Ok(receipts)
+        };
+        Box::pin(fut)
+    }
+}
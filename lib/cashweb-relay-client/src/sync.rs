@@ -0,0 +1,344 @@
+//! This module contains [`Cursor`], a resumable position within the messages of an address, and
+//! [`RelayClient::sync_messages`] which streams the messages following a cursor, transparently
+//! pulling page after page.
+//!
+//! Clients returning from a long offline period can sync a large backlog without holding it in
+//! memory, persisting the [`Cursor`] of each [`SyncedMessage`] once it is processed and resuming
+//! from it after an interruption.
+
+use std::{error, fmt, num::ParseIntError, str::FromStr};
+
+use cashweb_relay::{DigestError, Message, MessagePage};
+use futures_core::Stream;
+use futures_util::{
+    stream::{self, TryStreamExt},
+    FutureExt,
+};
+use hex::FromHexError;
+use hyper::Uri;
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::{filters::MessageFilter, services::GetMessages, RelayClient, RelayError};
+
+/// Default number of messages pulled per page.
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// A resumable position within the messages of an address.
+///
+/// A cursor is encoded as `since:<time>` or `after:<hex digest>` by its [`fmt::Display`] and
+/// [`FromStr`] implementations, so that it may be persisted between sessions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cursor {
+    /// Position before the messages received since the time, in milliseconds since the UNIX
+    /// epoch.
+    Since(u64),
+    /// Position after the message with the payload digest.
+    After(Vec<u8>),
+}
+
+impl Cursor {
+    /// The filter selecting the messages following the cursor.
+    pub fn filter(&self) -> MessageFilter {
+        match self {
+            Self::Since(time) => MessageFilter::since(*time),
+            // Skip the message itself, as the start bound is inclusive
+            Self::After(digest) => MessageFilter::from_digest(digest.clone()).with_offset(1),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Since(time) => write!(f, "since:{}", time),
+            Self::After(digest) => write!(f, "after:{}", hex::encode(digest)),
+        }
+    }
+}
+
+/// Error associated with parsing a [`Cursor`].
+#[derive(Debug, Error)]
+pub enum ParseCursorError {
+    /// The cursor was neither of the form `since:<time>` nor `after:<hex digest>`.
+    #[error("malformed cursor")]
+    Malformed,
+    /// Failed to parse the time.
+    #[error("failed to parse time: {0}")]
+    Time(ParseIntError),
+    /// Failed to decode the digest.
+    #[error("failed to decode digest: {0}")]
+    Digest(FromHexError),
+}
+
+impl FromStr for Cursor {
+    type Err = ParseCursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some("since"), Some(time)) => time
+                .parse()
+                .map(Self::Since)
+                .map_err(ParseCursorError::Time),
+            (Some("after"), Some(digest)) => hex::decode(digest)
+                .map(Self::After)
+                .map_err(ParseCursorError::Digest),
+            _ => Err(ParseCursorError::Malformed),
+        }
+    }
+}
+
+/// A message pulled by [`RelayClient::sync_messages`].
+#[derive(Clone, Debug)]
+pub struct SyncedMessage {
+    /// The message.
+    pub message: Message,
+    /// The cursor from which to resume after the message.
+    pub cursor: Cursor,
+}
+
+/// Error associated with syncing messages from a relay server.
+#[derive(Debug, Error)]
+pub enum SyncError<E: fmt::Debug + fmt::Display + error::Error + 'static> {
+    /// Error pulling a page.
+    #[error(transparent)]
+    Relay(RelayError<E>),
+    /// A message in the page was missing its digest.
+    #[error("failed to digest message: {0}")]
+    Digest(DigestError),
+}
+
+/// An item of the stream returned by [`RelayClient::sync_messages`].
+pub type SyncResult<E> = Result<SyncedMessage, SyncError<E>>;
+
+/// Pair each message with the cursor following it, returning the cursor following the page if it
+/// is full.
+fn split_page<E>(
+    page: MessagePage,
+    page_size: u32,
+) -> Result<(Vec<SyncedMessage>, Option<Cursor>), SyncError<E>>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+{
+    let full = page.messages.len() >= page_size as usize;
+    let messages = page
+        .messages
+        .into_iter()
+        .map(|message| {
+            let digest = message.digest().map_err(SyncError::Digest)?;
+            Ok(SyncedMessage {
+                message,
+                cursor: Cursor::After(digest.to_vec()),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let next = match messages.last() {
+        Some(last) if full => Some(last.cursor.clone()),
+        _ => None,
+    };
+    Ok((messages, next))
+}
+
+impl<S> RelayClient<S>
+where
+    Self: Service<(Uri, GetMessages), Response = MessagePage>,
+    Self: Sync + Clone + Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Future: Send + 'static,
+    <Self as Service<(Uri, GetMessages)>>::Error: fmt::Debug + fmt::Display + error::Error,
+{
+    /// Stream the messages following the [`Cursor`] from a relay server, pulling pages of at most
+    /// `page_size` messages, such as [`DEFAULT_PAGE_SIZE`], as the stream is consumed.
+    ///
+    /// The stream ends after a page which is not full, or after the first error. In the latter
+    /// case, syncing is resumed from the cursor of the last message received.
+    pub fn sync_messages(
+        &self,
+        relay_url: &str,
+        address: &str,
+        cursor: Cursor,
+        page_size: u32,
+        token: String,
+    ) -> impl Stream<Item = SyncResult<<Self as Service<(Uri, GetMessages)>>::Error>>
+           + Send
+           + Unpin
+           + 'static {
+        let client = self.clone();
+        let relay_url = relay_url.to_string();
+        let address = address.to_string();
+        stream::unfold(Some(cursor), move |state| {
+            let (client, relay_url, address, token) = (
+                client.clone(),
+                relay_url.clone(),
+                address.clone(),
+                token.clone(),
+            );
+            async move {
+                let filter = state?.filter().with_limit(page_size);
+                let result = client
+                    .get_messages(&relay_url, &address, &filter, token)
+                    .await
+                    .map_err(SyncError::Relay)
+                    .and_then(|page| split_page(page, page_size));
+                match result {
+                    Ok((messages, next)) => Some((Ok(messages), next)),
+                    Err(err) => Some((Err(err), None)),
+                }
+            }
+            .boxed()
+        })
+        .map_ok(|messages| stream::iter(messages.into_iter().map(Ok)))
+        .try_flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use futures_util::StreamExt;
+    use hyper::{Body, Request, Response, StatusCode};
+    use prost::Message as _;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockRelay(Vec<Message>);
+
+    impl MockRelay {
+        fn page(&self, query: &str) -> Option<MessagePage> {
+            let (mut start, mut offset, mut limit) = (None, 0, usize::MAX);
+            for pair in query.split('&') {
+                let mut split = pair.splitn(2, '=');
+                let (key, value) = (split.next()?, split.next()?);
+                match key {
+                    "start_time" => {
+                        let time: i64 = value.parse().ok()?;
+                        start = self.0.iter().position(|msg| msg.received_time >= time);
+                    }
+                    "start_digest" => {
+                        let digest = hex::decode(value).ok()?;
+                        start = Some(
+                            self.0
+                                .iter()
+                                .position(|msg| msg.digest().unwrap()[..] == digest[..])?,
+                        );
+                    }
+                    "offset" => offset = value.parse().ok()?,
+                    "limit" => limit = value.parse().ok()?,
+                    _ => return None,
+                }
+            }
+            let messages = self.0[start.unwrap_or(self.0.len())..]
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect();
+            Some(MessagePage {
+                messages,
+                ..Default::default()
+            })
+        }
+    }
+
+    impl Service<Request<Body>> for MockRelay {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let page = match request.uri().query() {
+                Some(query) if request.uri().path() == "/messages/alice" => self.page(query),
+                _ => None,
+            };
+            let response = match page {
+                Some(page) => {
+                    let mut raw_page = Vec::with_capacity(page.encoded_len());
+                    page.encode(&mut raw_page).unwrap();
+                    Response::new(Body::from(raw_page))
+                }
+                None => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    response
+                }
+            };
+            ready(Ok(response))
+        }
+    }
+
+    #[test]
+    fn cursor() {
+        let cursor = Cursor::After(vec![0xab, 0xcd]);
+        assert_eq!(cursor.to_string(), "after:abcd");
+        assert_eq!("after:abcd".parse::<Cursor>().unwrap(), cursor);
+        assert_eq!(cursor.filter().to_query(), "start_digest=abcd&offset=1");
+        assert_eq!("since:100".parse::<Cursor>().unwrap(), Cursor::Since(100));
+        assert!("until:100".parse::<Cursor>().is_err());
+    }
+
+    #[tokio::test]
+    async fn sync() {
+        let messages: Vec<_> = (0..5)
+            .map(|i| Message {
+                received_time: 100 + i,
+                payload: vec![i as u8],
+                ..Default::default()
+            })
+            .collect();
+        let client = RelayClient::from_service(MockRelay(messages.clone()));
+        let url = "http://relay";
+
+        // Pages are followed transparently
+        let synced: Vec<_> = client
+            .sync_messages(url, "alice", Cursor::Since(101), 2, "POP token".to_string())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let synced_messages: Vec<_> = synced.iter().map(|synced| synced.message.clone()).collect();
+        assert_eq!(synced_messages, messages[1..]);
+
+        // Resume after the second message synced
+        let resumed: Vec<_> = client
+            .sync_messages(
+                url,
+                "alice",
+                synced[1].cursor.clone(),
+                2,
+                "POP token".to_string(),
+            )
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(resumed.len(), 2);
+        assert_eq!(resumed[0].message, messages[3]);
+
+        // Nothing follows the last message
+        let cursor = synced.last().unwrap().cursor.clone();
+        let mut stream = client.sync_messages(url, "alice", cursor, 2, "POP token".to_string());
+        assert!(stream.next().await.is_none());
+
+        // Errors end the stream
+        let mut stream = client.sync_messages(
+            url,
+            "alice",
+            Cursor::After(vec![0]),
+            2,
+            "POP token".to_string(),
+        );
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(SyncError::Relay(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,274 @@
+//! This module contains [`seal`] and [`open`] which encrypt and decrypt [`Payload`]s for a
+//! recipient, identified by their keyserver-published public key.
+//!
+//! The sender generates an ephemeral key pair and performs ECDH against the recipient's public
+//! key. The key is derived from the shared point using HKDF-SHA256, salted with random bytes and
+//! bound to both public keys, and the payload is encrypted using AES-256-GCM. As every key is
+//! used for a single encryption, the nonce is fixed.
+
+use std::convert::TryInto;
+
+use prost::{DecodeError, Message as _};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+use secp256k1::{Error as SecpError, PublicKey, Secp256k1, SecretKey};
+use thiserror::Error;
+
+use crate::Payload;
+
+/// Info separating keys derived by [`seal`] from other uses of the shared point.
+const INFO: &[u8] = b"cashweb-relay aes-256-gcm";
+
+/// Length of the salt.
+pub const SALT_LEN: usize = 32;
+
+/// Length of a compressed public key.
+const PUBLIC_KEY_LEN: usize = 33;
+
+/// A [`Payload`] encrypted for a recipient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SealedPayload {
+    /// The ephemeral public key of the sender.
+    pub ephemeral_public_key: PublicKey,
+    /// The salt used in key derivation.
+    pub salt: [u8; SALT_LEN],
+    /// The ciphertext, followed by the authentication tag.
+    pub ciphertext: Vec<u8>,
+}
+
+/// Error associated with decoding a [`SealedPayload`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecodeSealedError {
+    /// The input was too short to contain a public key, salt and tag.
+    #[error("sealed payload too short")]
+    TooShort,
+    /// The ephemeral public key was invalid.
+    #[error("ephemeral public key: {0}")]
+    EphemeralPublicKey(SecpError),
+}
+
+impl SealedPayload {
+    /// Serialize as the ephemeral public key, the salt and the ciphertext, concatenated.
+    pub fn encode(&self) -> Vec<u8> {
+        [
+            &self.ephemeral_public_key.serialize()[..],
+            &self.salt[..],
+            &self.ciphertext[..],
+        ]
+        .concat()
+    }
+
+    /// Deserialize from the ephemeral public key, the salt and the ciphertext, concatenated.
+    pub fn decode(raw: &[u8]) -> Result<Self, DecodeSealedError> {
+        if raw.len() < PUBLIC_KEY_LEN + SALT_LEN + AES_256_GCM.tag_len() {
+            return Err(DecodeSealedError::TooShort);
+        }
+        let (raw_public_key, rest) = raw.split_at(PUBLIC_KEY_LEN);
+        let (salt, ciphertext) = rest.split_at(SALT_LEN);
+        let ephemeral_public_key =
+            PublicKey::from_slice(raw_public_key).map_err(DecodeSealedError::EphemeralPublicKey)?;
+        Ok(Self {
+            ephemeral_public_key,
+            // This is safe as the length was checked
+            salt: salt.try_into().unwrap(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+/// Error associated with [`seal`] or [`open`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum EncryptionError {
+    /// Failed to perform ECDH.
+    #[error("key exchange: {0}")]
+    KeyExchange(SecpError),
+    /// Failed to generate randomness.
+    #[error("failed to generate randomness")]
+    Random,
+    /// Decryption failed, the ciphertext or key is invalid.
+    #[error("decryption failure")]
+    Decrypt,
+    /// Failed to decode the plaintext [`Payload`].
+    #[error("payload decoding failure: {0}")]
+    Payload(DecodeError),
+}
+
+/// Derive the AES-256-GCM key from the shared point.
+fn derive_key(
+    shared_point: &PublicKey,
+    ephemeral_public_key: &PublicKey,
+    recipient_public_key: &PublicKey,
+    salt: &[u8],
+) -> LessSafeKey {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&shared_point.serialize());
+    let ephemeral_public_key = ephemeral_public_key.serialize();
+    let recipient_public_key = recipient_public_key.serialize();
+    let info = [INFO, &ephemeral_public_key, &recipient_public_key];
+    // This is safe as the output length is far below the HKDF limit
+    let okm = prk.expand(&info, &AES_256_GCM).unwrap();
+    LessSafeKey::new(UnboundKey::from(okm))
+}
+
+/// Multiply the public key by the secret key.
+fn shared_point(public_key: &PublicKey, secret_key: &SecretKey) -> Result<PublicKey, SecpError> {
+    let mut shared_point = *public_key;
+    shared_point.mul_assign(&Secp256k1::verification_only(), &secret_key[..])?;
+    Ok(shared_point)
+}
+
+/// Encrypt the plaintext for the recipient using the given ephemeral secret key and salt.
+///
+/// Both must be freshly generated for each encryption, prefer [`seal_bytes`] outside of tests.
+pub fn seal_with(
+    recipient_public_key: &PublicKey,
+    plaintext: &[u8],
+    ephemeral_secret_key: &SecretKey,
+    salt: [u8; SALT_LEN],
+) -> Result<SealedPayload, EncryptionError> {
+    let ephemeral_public_key =
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), ephemeral_secret_key);
+    let shared_point = shared_point(recipient_public_key, ephemeral_secret_key)
+        .map_err(EncryptionError::KeyExchange)?;
+    let key = derive_key(
+        &shared_point,
+        &ephemeral_public_key,
+        recipient_public_key,
+        &salt,
+    );
+
+    let mut ciphertext = plaintext.to_vec();
+    // This is safe as the plaintext is far below the AES-GCM limit
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+        Aad::empty(),
+        &mut ciphertext,
+    )
+    .unwrap();
+
+    Ok(SealedPayload {
+        ephemeral_public_key,
+        salt,
+        ciphertext,
+    })
+}
+
+/// Encrypt the plaintext for the recipient, using a random ephemeral key and salt.
+pub fn seal_bytes(
+    recipient_public_key: &PublicKey,
+    plaintext: &[u8],
+) -> Result<SealedPayload, EncryptionError> {
+    let rng = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    rng.fill(&mut salt).map_err(|_| EncryptionError::Random)?;
+
+    // Rejection sample the ephemeral secret key
+    let ephemeral_secret_key = loop {
+        let mut raw_secret_key = [0; 32];
+        rng.fill(&mut raw_secret_key)
+            .map_err(|_| EncryptionError::Random)?;
+        if let Ok(secret_key) = SecretKey::from_slice(&raw_secret_key) {
+            break secret_key;
+        }
+    };
+
+    seal_with(recipient_public_key, plaintext, &ephemeral_secret_key, salt)
+}
+
+/// Decrypt the [`SealedPayload`] using the recipient's secret key.
+pub fn open_bytes(
+    recipient_secret_key: &SecretKey,
+    sealed: &SealedPayload,
+) -> Result<Vec<u8>, EncryptionError> {
+    let recipient_public_key =
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), recipient_secret_key);
+    let shared_point = shared_point(&sealed.ephemeral_public_key, recipient_secret_key)
+        .map_err(EncryptionError::KeyExchange)?;
+    let key = derive_key(
+        &shared_point,
+        &sealed.ephemeral_public_key,
+        &recipient_public_key,
+        &sealed.salt,
+    );
+
+    let mut plaintext = sealed.ciphertext.clone();
+    let len = key
+        .open_in_place(
+            Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+            Aad::empty(),
+            &mut plaintext,
+        )
+        .map_err(|_| EncryptionError::Decrypt)?
+        .len();
+    plaintext.truncate(len);
+    Ok(plaintext)
+}
+
+/// Serialize and encrypt the [`Payload`] for the recipient.
+pub fn seal(
+    recipient_public_key: &PublicKey,
+    payload: &Payload,
+) -> Result<SealedPayload, EncryptionError> {
+    let mut raw_payload = Vec::with_capacity(payload.encoded_len());
+    payload.encode(&mut raw_payload).unwrap(); // This is safe
+    seal_bytes(recipient_public_key, &raw_payload)
+}
+
+/// Decrypt and deserialize the [`Payload`] using the recipient's secret key.
+pub fn open(
+    recipient_secret_key: &SecretKey,
+    sealed: &SealedPayload,
+) -> Result<Payload, EncryptionError> {
+    let raw_payload = open_bytes(recipient_secret_key, sealed)?;
+    Payload::decode(raw_payload.as_slice()).map_err(EncryptionError::Payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient() -> (SecretKey, PublicKey) {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+        (secret_key, public_key)
+    }
+
+    #[test]
+    fn vector() {
+        let (secret_key, public_key) = recipient();
+        let ephemeral_secret_key = SecretKey::from_slice(&[2; 32]).unwrap();
+
+        let sealed = seal_with(&public_key, b"hello", &ephemeral_secret_key, [3; 32]).unwrap();
+        assert_eq!(
+            hex::encode(sealed.ephemeral_public_key.serialize()),
+            "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766"
+        );
+        assert_eq!(
+            hex::encode(&sealed.ciphertext),
+            "e40d921c60d07fc00beb51f6d374a2601e257e3bca"
+        );
+        assert_eq!(open_bytes(&secret_key, &sealed).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn round_trip() {
+        let (secret_key, public_key) = recipient();
+        let payload = Payload {
+            timestamp: 1_000,
+            ..Default::default()
+        };
+
+        let sealed = seal(&public_key, &payload).unwrap();
+        let decoded = SealedPayload::decode(&sealed.encode()).unwrap();
+        assert_eq!(open(&secret_key, &decoded).unwrap(), payload);
+
+        let mut tampered = decoded;
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(open(&secret_key, &tampered), Err(EncryptionError::Decrypt));
+
+        let other = SecretKey::from_slice(&[4; 32]).unwrap();
+        assert_eq!(open(&other, &sealed), Err(EncryptionError::Decrypt));
+    }
+}
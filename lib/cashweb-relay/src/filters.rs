@@ -0,0 +1,190 @@
+//! This module contains construction and evaluation of the [`Filters`] which addresses publish
+//! to relay servers, allowing them to drop unwanted messages.
+//!
+//! Senders are identified by their serialized source public key. Messages from senders in the
+//! deny filter are dropped, messages from senders in the allow filter are accepted, and all other
+//! messages are accepted only if their stamp value meets the price floor.
+
+use std::{
+    convert::TryFrom,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::digest::{digest, SHA256};
+use secp256k1::PublicKey;
+use thiserror::Error;
+
+use crate::models::{BloomFilter, Filters};
+
+/// Maximum size, in bytes, of a [`BloomFilter`] accepted by [`Filters::validate`].
+pub const MAX_BLOOM_SIZE: usize = 36_000;
+
+/// Maximum number of hash functions of a [`BloomFilter`] accepted by [`Filters::validate`].
+pub const MAX_HASH_COUNT: u32 = 50;
+
+/// Error associated with [`Filters::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FilterError {
+    /// A bloom filter exceeded [`MAX_BLOOM_SIZE`].
+    #[error("bloom filter too large: {0} bytes")]
+    TooLarge(usize),
+    /// A bloom filter exceeded [`MAX_HASH_COUNT`].
+    #[error("too many hash functions: {0}")]
+    TooManyHashes(u32),
+}
+
+impl BloomFilter {
+    /// Create an empty bloom filter of `size` bytes using `hash_count` hash functions.
+    pub fn new(size: usize, hash_count: u32) -> Self {
+        Self {
+            bits: vec![0; size],
+            hash_count,
+        }
+    }
+
+    /// Create an empty bloom filter sized to hold `items` with the false positive rate.
+    ///
+    /// The size and number of hash functions are capped at [`MAX_BLOOM_SIZE`] and
+    /// [`MAX_HASH_COUNT`].
+    pub fn with_rate(items: usize, false_positive_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = -items * false_positive_rate.ln() / (ln2 * ln2);
+        let size = ((bits / 8.0).ceil() as usize).clamp(1, MAX_BLOOM_SIZE);
+        let hash_count = ((size * 8) as f64 / items * ln2).round() as u32;
+        Self::new(size, hash_count.clamp(1, MAX_HASH_COUNT))
+    }
+
+    /// Calculate the bit indices of an item.
+    fn indices<'a>(&'a self, item: &[u8]) -> impl Iterator<Item = usize> + 'a {
+        let item_digest = digest(&SHA256, item);
+        let (h1, rest) = item_digest.as_ref().split_at(8);
+        // This is safe as the digest is 32 bytes
+        let h1 = u64::from_le_bytes(<[u8; 8]>::try_from(h1).unwrap());
+        let h2 = u64::from_le_bytes(<[u8; 8]>::try_from(&rest[..8]).unwrap());
+        let len = self.bits.len() as u64 * 8;
+        (0..u64::from(self.hash_count))
+            .map(move |index| (h1.wrapping_add(index.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Insert an item.
+    pub fn insert(&mut self, item: &[u8]) {
+        if self.bits.is_empty() {
+            return;
+        }
+        let indices: Vec<usize> = self.indices(item).collect();
+        for index in indices {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Whether the item may have been inserted. An empty filter contains nothing.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        !self.bits.is_empty()
+            && self
+                .indices(item)
+                .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Insert a sender's public key.
+    pub fn insert_sender(&mut self, public_key: &PublicKey) {
+        self.insert(&public_key.serialize());
+    }
+
+    fn validate(&self) -> Result<(), FilterError> {
+        if self.bits.len() > MAX_BLOOM_SIZE {
+            return Err(FilterError::TooLarge(self.bits.len()));
+        }
+        if self.hash_count > MAX_HASH_COUNT {
+            return Err(FilterError::TooManyHashes(self.hash_count));
+        }
+        Ok(())
+    }
+}
+
+impl Filters {
+    /// Create filters accepting messages whose stamp value meets the price floor, timestamped
+    /// now.
+    pub fn new(price_floor: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or_default();
+        Self {
+            timestamp,
+            price_floor,
+            allow: None,
+            deny: None,
+        }
+    }
+
+    /// Set the filter of senders whose messages are accepted regardless of stamp value.
+    pub fn with_allow(mut self, allow: BloomFilter) -> Self {
+        self.allow = Some(allow);
+        self
+    }
+
+    /// Set the filter of senders whose messages are dropped.
+    pub fn with_deny(mut self, deny: BloomFilter) -> Self {
+        self.deny = Some(deny);
+        self
+    }
+
+    /// Check the bloom filters are within [`MAX_BLOOM_SIZE`] and [`MAX_HASH_COUNT`].
+    ///
+    /// Relay servers should validate filters before storing them, as evaluation cost grows with
+    /// the number of hash functions.
+    pub fn validate(&self) -> Result<(), FilterError> {
+        for bloom in self.allow.iter().chain(&self.deny) {
+            bloom.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Whether a message from the sender, with the stamp value in satoshis, is accepted.
+    pub fn accepts(&self, source_public_key: &PublicKey, stamp_value: u64) -> bool {
+        let sender = source_public_key.serialize();
+        if matches!(&self.deny, Some(deny) if deny.contains(&sender)) {
+            return false;
+        }
+        if matches!(&self.allow, Some(allow) if allow.contains(&sender)) {
+            return true;
+        }
+        stamp_value >= self.price_floor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+
+    fn public_key(byte: u8) -> PublicKey {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key)
+    }
+
+    #[test]
+    fn accepts() {
+        let (friend, spammer, stranger) = (public_key(1), public_key(2), public_key(3));
+
+        let mut allow = BloomFilter::with_rate(10, 0.0001);
+        allow.insert_sender(&friend);
+        let mut deny = BloomFilter::with_rate(10, 0.0001);
+        deny.insert_sender(&spammer);
+        let filters = Filters::new(1_000).with_allow(allow).with_deny(deny);
+        assert_eq!(filters.validate(), Ok(()));
+
+        assert!(filters.accepts(&friend, 0));
+        assert!(!filters.accepts(&spammer, 10_000));
+        assert!(!filters.accepts(&stranger, 999));
+        assert!(filters.accepts(&stranger, 1_000));
+
+        let oversized = Filters::new(0).with_deny(BloomFilter::new(1, MAX_HASH_COUNT + 1));
+        assert_eq!(
+            oversized.validate(),
+            Err(FilterError::TooManyHashes(MAX_HASH_COUNT + 1))
+        );
+    }
+}
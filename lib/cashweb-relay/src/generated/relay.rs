@@ -0,0 +1,267 @@
+/// Basic key/value pair used to store header data.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Header {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub value: ::prost::alloc::string::String,
+}
+/// ProfileEntry is an individual piece of structured data provided by wallet
+/// authors.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProfileEntry {
+    /// Kind is a hint to wallets as to what type of data to deserialize from the
+    /// `body` field.
+    #[prost(string, tag="1")]
+    pub kind: ::prost::alloc::string::String,
+    /// The `headers` are metadata that may be useful to an application.
+    #[prost(message, repeated, tag="2")]
+    pub headers: ::prost::alloc::vec::Vec<Header>,
+    /// The body of the `ProfileEntry`.
+    #[prost(bytes="vec", tag="3")]
+    pub body: ::prost::alloc::vec::Vec<u8>,
+}
+/// A profile attached to an address.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Profile {
+    /// Timestamp allows servers to determine which profile is the most
+    /// recent. Given in unix time milliseconds.
+    #[prost(int64, tag="1")]
+    pub timestamp: i64,
+    /// Time to live tells us how long this profile should exist before being
+    /// considered invalid. Given in unix time milliseconds.
+    #[prost(int64, tag="2")]
+    pub ttl: i64,
+    /// User specified data to be interpreted by applications.
+    #[prost(message, repeated, tag="3")]
+    pub entries: ::prost::alloc::vec::Vec<ProfileEntry>,
+}
+/// Entry is an individual piece of structured data.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PayloadEntry {
+    /// Informs the wallet what to do with this payload.
+    #[prost(string, tag="1")]
+    pub kind: ::prost::alloc::string::String,
+    /// The `headers` provide some extra metadata about the field that
+    /// may be relevant to the wallet.
+    #[prost(message, repeated, tag="2")]
+    pub headers: ::prost::alloc::vec::Vec<Header>,
+    /// The body of the `PayloadEntry`.
+    #[prost(bytes="vec", tag="3")]
+    pub body: ::prost::alloc::vec::Vec<u8>,
+}
+/// Payload is the user-specified data section of the message that is
+/// encrypted by the shared secret.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Payload {
+    /// A timestamp provided by sender.
+    #[prost(int64, tag="1")]
+    pub timestamp: i64,
+    /// User specified data to be interpreted by applications.
+    #[prost(message, repeated, tag="2")]
+    pub entries: ::prost::alloc::vec::Vec<PayloadEntry>,
+}
+/// A stamp transaction paired with a list of vouts identifying to stamp outputs.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StampOutpoints {
+    /// A serialized stamp transaction.
+    #[prost(bytes="vec", tag="1")]
+    pub stamp_tx: ::prost::alloc::vec::Vec<u8>,
+    /// The specified outputs of the stamp transaction.
+    #[prost(uint32, repeated, tag="2")]
+    pub vouts: ::prost::alloc::vec::Vec<u32>,
+}
+/// Represents a stamp. This is used within Message in order to attach value.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Stamp {
+    /// The stamp type.
+    #[prost(enumeration="stamp::StampType", tag="1")]
+    pub stamp_type: i32,
+    /// A collection of stamp outpoints.
+    #[prost(message, repeated, tag="2")]
+    pub stamp_outpoints: ::prost::alloc::vec::Vec<StampOutpoints>,
+}
+/// Nested message and enum types in `Stamp`.
+pub mod stamp {
+    /// Represents the stamp type.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum StampType {
+        /// Indicates no stamp information is attached.
+        None = 0,
+        /// Indicates that the stamp outputs are redeemable as HD derivations from a
+        /// master private key `d + SHA-256(payload)`.
+        MessageCommitment = 1,
+    }
+}
+/// The primary message used in communication over the relay protocol.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Message {
+    /// The source public key.
+    #[prost(bytes="vec", tag="1")]
+    pub source_public_key: ::prost::alloc::vec::Vec<u8>,
+    /// The destinations public key.
+    #[prost(bytes="vec", tag="2")]
+    pub destination_public_key: ::prost::alloc::vec::Vec<u8>,
+    /// Maleable server time.
+    #[prost(int64, tag="3")]
+    pub received_time: i64,
+    /// The SHA-256 digest of the payload.
+    #[prost(bytes="vec", tag="4")]
+    pub payload_digest: ::prost::alloc::vec::Vec<u8>,
+    /// The stamp attached to the message.
+    #[prost(message, optional, tag="5")]
+    pub stamp: ::core::option::Option<Stamp>,
+    /// The encryption scheme used on the serialized `Payload` to produce the
+    /// `payload` field.
+    #[prost(enumeration="message::EncryptionScheme", tag="6")]
+    pub scheme: i32,
+    /// The `salt` is used to salt both the `payload_hmac` and the encryption key.
+    #[prost(bytes="vec", tag="7")]
+    pub salt: ::prost::alloc::vec::Vec<u8>,
+    /// The HMAC of the `payload`, specifically `HMAC(HMAC(sdG, salt),
+    /// payload_digest)`.
+    #[prost(bytes="vec", tag="8")]
+    pub payload_hmac: ::prost::alloc::vec::Vec<u8>,
+    /// The size, in bytes, of the `payload`.
+    #[prost(uint64, tag="9")]
+    pub payload_size: u64,
+    /// The encrypted `payload`.
+    #[prost(bytes="vec", tag="100")]
+    pub payload: ::prost::alloc::vec::Vec<u8>,
+}
+/// Nested message and enum types in `Message`.
+pub mod message {
+    /// Represents an encryption scheme.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum EncryptionScheme {
+        /// Indicates the `payload` is unencrypted.
+        None = 0,
+        /// Indicates the `payload` is encrypted using AES and the Ephemeral
+        /// Diffie-Hellman style protocol key exchange, specifically `HMAC(sdG,
+        /// salt)`.
+        EphemeralDh = 1,
+        /// Indicates the `payload` is a sealed payload: an ephemeral public key,
+        /// salt and AES-256-GCM ciphertext, keyed by `HKDF(salt, deG)`.
+        EphemeralAesGcm = 2,
+    }
+}
+/// Collection of messages. Pushed from client to server via HTTP.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageSet {
+    #[prost(message, repeated, tag="1")]
+    pub messages: ::prost::alloc::vec::Vec<Message>,
+}
+/// An error associated with the validation and insertion of a message.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PushError {
+    /// Status code of the error.
+    #[prost(uint32, tag="1")]
+    pub status_code: u32,
+    /// Textual information of the error.
+    #[prost(string, tag="2")]
+    pub error_text: ::prost::alloc::string::String,
+}
+/// A collection of errors yeilded when pushing message to the server.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PushErrors {
+    /// A map of errors, keyed by the index of the failed message.
+    #[prost(map="int32, message", tag="1")]
+    pub errors: ::std::collections::HashMap<i32, PushError>,
+}
+/// A page of messages. Pulled from server via HTTP.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessagePage {
+    /// Collection of messages.
+    #[prost(message, repeated, tag="1")]
+    pub messages: ::prost::alloc::vec::Vec<Message>,
+    /// The received time of the earliest message in the page.
+    #[prost(int64, tag="2")]
+    pub start_time: i64,
+    /// The received time of the latest message in the page.
+    #[prost(int64, tag="3")]
+    pub end_time: i64,
+    /// The payload digest of the earliest message in the page.
+    #[prost(bytes="vec", tag="4")]
+    pub start_digest: ::prost::alloc::vec::Vec<u8>,
+    /// The payload digest of the latest message in the page.
+    #[prost(bytes="vec", tag="5")]
+    pub end_digest: ::prost::alloc::vec::Vec<u8>,
+}
+/// A page of payloads. Pulled from server via HTTP.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PayloadPage {
+    /// Collection of payloads.
+    #[prost(bytes="vec", repeated, tag="1")]
+    pub payloads: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// The received time of the earliest payload in the page.
+    #[prost(int64, tag="2")]
+    pub start_time: i64,
+    /// The received time of the latest payload in the page.
+    #[prost(int64, tag="3")]
+    pub end_time: i64,
+    /// The payload digest of the earliest payload in the page.
+    #[prost(bytes="vec", tag="4")]
+    pub start_digest: ::prost::alloc::vec::Vec<u8>,
+    /// The payload digest of the latest payload in the page.
+    #[prost(bytes="vec", tag="5")]
+    pub end_digest: ::prost::alloc::vec::Vec<u8>,
+}
+/// A bloom filter, using double hashing of the SHA-256 digest of each item.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BloomFilter {
+    /// The bit array.
+    #[prost(bytes="vec", tag="1")]
+    pub bits: ::prost::alloc::vec::Vec<u8>,
+    /// The number of hash functions.
+    #[prost(uint32, tag="2")]
+    pub hash_count: u32,
+}
+/// Filters published by an address, allowing relay servers to drop unwanted
+/// messages before storing them.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Filters {
+    /// Timestamp allows servers to determine which filters are the most recent.
+    /// Given in unix time milliseconds.
+    #[prost(int64, tag="1")]
+    pub timestamp: i64,
+    /// The minimum stamp value, in satoshis, of accepted messages.
+    #[prost(uint64, tag="2")]
+    pub price_floor: u64,
+    /// Source public keys whose messages are accepted regardless of stamp value.
+    #[prost(message, optional, tag="3")]
+    pub allow: ::core::option::Option<BloomFilter>,
+    /// Source public keys whose messages are dropped.
+    #[prost(message, optional, tag="4")]
+    pub deny: ::core::option::Option<BloomFilter>,
+}
+/// A batch of acknowledgements, by the destination of messages, that the
+/// messages were delivered. Acknowledged messages are pruned by the relay
+/// server.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Acknowledgements {
+    /// The payload digests of the acknowledged messages.
+    #[prost(bytes="vec", repeated, tag="1")]
+    pub payload_digests: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+/// A receipt, held for the source of a message, that the destination
+/// acknowledged it.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeliveryReceipt {
+    /// The payload digest of the acknowledged message.
+    #[prost(bytes="vec", tag="1")]
+    pub payload_digest: ::prost::alloc::vec::Vec<u8>,
+    /// The destination public key of the acknowledged message.
+    #[prost(bytes="vec", tag="2")]
+    pub destination_public_key: ::prost::alloc::vec::Vec<u8>,
+    /// The time the message was acknowledged. Given in unix time milliseconds.
+    #[prost(int64, tag="3")]
+    pub acknowledged_time: i64,
+}
+/// A collection of delivery receipts. Pulled from server via HTTP.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeliveryReceipts {
+    #[prost(message, repeated, tag="1")]
+    pub receipts: ::prost::alloc::vec::Vec<DeliveryReceipt>,
+}
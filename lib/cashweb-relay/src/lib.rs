@@ -10,13 +10,15 @@
 //!
 //! [`Relay Protocol`]: https://github.com/cashweb/specifications/blob/master/authorization-wrapper/specification.mediawiki
 
+pub mod encryption;
+pub mod filters;
 #[allow(unreachable_pub, missing_docs)]
 mod models;
 pub mod stamp;
 
 pub use crate::models::{
-    message::EncryptionScheme, Message, MessagePage, MessageSet, Payload, PayloadPage, Profile,
-    Stamp,
+    message::EncryptionScheme, Acknowledgements, BloomFilter, DeliveryReceipt, DeliveryReceipts,
+    Filters, Message, MessagePage, MessageSet, Payload, PayloadPage, Profile, Stamp,
 };
 
 use std::convert::TryInto;
@@ -1 +1,4 @@
+#[cfg(feature = "codegen")]
 include!(concat!(env!("OUT_DIR"), "/relay.rs"));
+#[cfg(not(feature = "codegen"))]
+include!("generated/relay.rs");
@@ -2,7 +2,7 @@
 
 use cashweb_bitcoin::{
     bip32::*,
-    transaction::{self, Transaction},
+    transaction::{self, script::address::HashType, Transaction},
     Decodable,
 };
 use ring::digest::{digest, SHA256};
@@ -108,11 +108,10 @@ pub fn verify_stamp(
                 .outputs
                 .get(*vout as usize)
                 .ok_or(StampError::MissingOutput)?;
-            let script = &output.script;
-            if !script.is_p2pkh() {
-                return Err(StampError::NotP2PKH);
-            }
-            let pubkey_hash = &script.as_bytes()[3..23]; // This is safe as we've checked it's a p2pkh
+            let pubkey_hash = match output.script.address_hash() {
+                Some((HashType::Key, pubkey_hash)) => pubkey_hash,
+                _ => return Err(StampError::NotP2PKH),
+            };
 
             // Derive child key
             let child_number = ChildNumber::from_normal_index(index as u32)
@@ -0,0 +1,113 @@
+//! This module contains [`TokenEncoding`] which enumerates the supported token wire encodings.
+//!
+//! Validation auto-detects the encoding using [`decode_any`], allowing interoperation with
+//! deployments issuing tokens in a different encoding.
+
+use thiserror::Error;
+
+/// Enumeration of token wire encodings.
+///
+/// Tokens are encoded as [`TokenEncoding::UrlSafe`] unless otherwise configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenEncoding {
+    /// URL safe base64 without padding.
+    UrlSafe,
+    /// URL safe base64 with padding.
+    UrlSafePadded,
+    /// Standard base64 with padding.
+    Standard,
+    /// Lowercase hexadecimal.
+    Hex,
+}
+
+/// Error associated with decoding a token.
+#[derive(Debug, Error, PartialEq)]
+pub enum DecodeError {
+    /// Failed to decode base64.
+    #[error("failed to decode base64: {0}")]
+    Base64(base64::DecodeError),
+    /// Failed to decode hex.
+    #[error("failed to decode hex: {0}")]
+    Hex(hex::FromHexError),
+}
+
+fn url_safe_config() -> base64::Config {
+    base64::Config::new(base64::CharacterSet::UrlSafe, false)
+}
+
+impl TokenEncoding {
+    /// Encode a raw token.
+    pub fn encode(self, raw_token: &[u8]) -> String {
+        match self {
+            Self::UrlSafe => base64::encode_config(raw_token, url_safe_config()),
+            Self::UrlSafePadded => base64::encode_config(raw_token, base64::URL_SAFE),
+            Self::Standard => base64::encode_config(raw_token, base64::STANDARD),
+            Self::Hex => hex::encode(raw_token),
+        }
+    }
+
+    /// Decode a token.
+    pub fn decode(self, token: &str) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Self::UrlSafe => {
+                base64::decode_config(token, url_safe_config()).map_err(DecodeError::Base64)
+            }
+            Self::UrlSafePadded => {
+                base64::decode_config(token, base64::URL_SAFE).map_err(DecodeError::Base64)
+            }
+            Self::Standard => {
+                base64::decode_config(token, base64::STANDARD).map_err(DecodeError::Base64)
+            }
+            Self::Hex => hex::decode(token).map_err(DecodeError::Hex),
+        }
+    }
+}
+
+/// Decode a token of unknown encoding, returning every plausible raw token.
+///
+/// Hexadecimal tokens are also valid base64, so callers should accept the token if any of the
+/// candidates validates.
+pub fn decode_any(token: &str) -> Result<Vec<Vec<u8>>, base64::DecodeError> {
+    let mut candidates = Vec::with_capacity(2);
+    if let Ok(raw_token) = hex::decode(token) {
+        candidates.push(raw_token);
+    }
+
+    // Normalize all base64 variants to unpadded URL safe base64
+    let normalized: String = token
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect();
+    match base64::decode_config(normalized, url_safe_config()) {
+        Ok(raw_token) => candidates.push(raw_token),
+        Err(err) if candidates.is_empty() => return Err(err),
+        Err(_) => (),
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_detect() {
+        let raw_token = [0xfb, 0xff, 0x01, 0x02];
+        for encoding in &[
+            TokenEncoding::UrlSafe,
+            TokenEncoding::UrlSafePadded,
+            TokenEncoding::Standard,
+            TokenEncoding::Hex,
+        ] {
+            let token = encoding.encode(&raw_token);
+            assert_eq!(encoding.decode(&token).unwrap(), raw_token);
+            assert!(decode_any(&token).unwrap().contains(&raw_token.to_vec()));
+        }
+        assert!(decode_any("*").is_err());
+    }
+}
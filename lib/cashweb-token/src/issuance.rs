@@ -0,0 +1,352 @@
+//! This module contains [`Issuer`] which implements the challenge-response flow used to issue
+//! tokens.
+//!
+//! The server responds to an unauthorized request with a [`Challenge`], binding a random nonce
+//! to the requested resource and price. The client returns a payment or signature committing to
+//! the challenge, which is checked by a [`ResponseVerifier`] before a token is minted. Each
+//! challenge may be redeemed at most once.
+//!
+//! Outstanding challenges are bounded. Expired challenges are pruned as new ones are issued, and
+//! once the limit is reached the oldest outstanding challenge is evicted.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+
+use crate::{pricing::PriceOracle, schemes::TokenScheme};
+
+/// Length of a challenge nonce.
+pub const NONCE_LEN: usize = 32;
+
+/// Default maximum number of outstanding challenges.
+pub const DEFAULT_MAX_PENDING: usize = 10_000;
+
+/// A challenge binding a nonce to a resource and price.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    /// Random nonce identifying the challenge.
+    pub nonce: [u8; NONCE_LEN],
+    /// The resource the token will grant access to.
+    pub resource: Vec<u8>,
+    /// The price of the token, in satoshis.
+    pub price: u64,
+    /// Time after which the challenge can no longer be redeemed.
+    pub expires_at: SystemTime,
+}
+
+/// Verifies the response to a [`Challenge`].
+#[async_trait]
+pub trait ResponseVerifier {
+    /// Error associated with verification.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Verify that the response, for example a payment or signature, satisfies the challenge.
+    async fn verify(&self, challenge: &Challenge, response: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Error associated with redeeming a [`Challenge`].
+#[derive(Debug, Error)]
+pub enum IssuanceError<V: fmt::Debug + fmt::Display, T: fmt::Debug + fmt::Display> {
+    /// The challenge was never issued or has already been redeemed.
+    #[error("unknown challenge")]
+    UnknownChallenge,
+    /// The challenge has expired.
+    #[error("challenge expired")]
+    Expired,
+    /// The response failed verification.
+    #[error("verification failed: {0}")]
+    Verification(V),
+    /// Failed to construct the token.
+    #[error("failed to construct token: {0}")]
+    Token(T),
+}
+
+/// Outstanding challenges, along with their nonces in order of issuance.
+#[derive(Default)]
+struct Pending {
+    challenges: HashMap<[u8; NONCE_LEN], Challenge>,
+    order: VecDeque<[u8; NONCE_LEN]>,
+}
+
+impl Pending {
+    /// Remove expired challenges from the front of the queue, along with nonces which were
+    /// already redeemed.
+    fn prune_front(&mut self, now: SystemTime) -> usize {
+        let mut removed = 0;
+        while let Some(nonce) = self.order.front() {
+            match self.challenges.get(nonce) {
+                Some(challenge) if now < challenge.expires_at => break,
+                Some(_) => {
+                    self.challenges.remove(nonce);
+                    removed += 1;
+                }
+                None => {}
+            }
+            self.order.pop_front();
+        }
+        removed
+    }
+
+    fn insert(&mut self, challenge: Challenge, max_pending: usize) {
+        self.prune_front(SystemTime::now());
+        while self.challenges.len() >= max_pending {
+            match self.order.pop_front() {
+                Some(nonce) => {
+                    self.challenges.remove(&nonce);
+                }
+                None => break,
+            }
+        }
+
+        // Compact the queue if redeemed nonces have accumulated behind the front
+        if self.order.len() > 2 * max_pending {
+            let challenges = &self.challenges;
+            self.order.retain(|nonce| challenges.contains_key(nonce));
+        }
+
+        self.order.push_back(challenge.nonce);
+        self.challenges.insert(challenge.nonce, challenge);
+    }
+}
+
+/// Issues challenges and mints tokens once they are satisfied.
+pub struct Issuer<T, V> {
+    scheme: T,
+    verifier: V,
+    ttl: Duration,
+    max_pending: usize,
+    rng: SystemRandom,
+    pending: Mutex<Pending>,
+}
+
+impl<T: fmt::Debug, V: fmt::Debug> fmt::Debug for Issuer<T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Issuer")
+            .field("scheme", &self.scheme)
+            .field("verifier", &self.verifier)
+            .field("ttl", &self.ttl)
+            .field("max_pending", &self.max_pending)
+            .finish()
+    }
+}
+
+impl<T, V> Issuer<T, V>
+where
+    T: TokenScheme + Sync,
+    V: ResponseVerifier + Sync,
+{
+    /// Create a new [`Issuer`] whose challenges may be redeemed within `ttl`.
+    ///
+    /// At most [`DEFAULT_MAX_PENDING`] challenges are outstanding at once.
+    pub fn new(scheme: T, verifier: V, ttl: Duration) -> Self {
+        Self {
+            scheme,
+            verifier,
+            ttl,
+            max_pending: DEFAULT_MAX_PENDING,
+            rng: SystemRandom::new(),
+            pending: Default::default(),
+        }
+    }
+
+    /// Set the maximum number of outstanding challenges.
+    ///
+    /// Once reached, issuing a challenge evicts the oldest outstanding challenge.
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// The number of outstanding challenges.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().challenges.len()
+    }
+
+    /// Issue a new [`Challenge`] for the resource and price.
+    pub fn challenge(&self, resource: &[u8], price: u64) -> Challenge {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .expect("system random number generator unavailable");
+        let challenge = Challenge {
+            nonce,
+            resource: resource.to_vec(),
+            price,
+            expires_at: SystemTime::now() + self.ttl,
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(challenge.clone(), self.max_pending);
+        challenge
+    }
+
+    /// Issue a new [`Challenge`] for the address payload, priced by the [`PriceOracle`] for a
+    /// payload of `size` bytes.
+    pub async fn quote<O: PriceOracle>(
+        &self,
+        oracle: &O,
+        address: &[u8],
+        size: usize,
+    ) -> Result<Challenge, O::Error> {
+        let price = oracle.quote(address, size).await?;
+        Ok(self.challenge(address, price))
+    }
+
+    /// Redeem a [`Challenge`], returning a token for its resource if the response is verified.
+    ///
+    /// The challenge is consumed regardless of the outcome.
+    pub async fn redeem(
+        &self,
+        nonce: &[u8; NONCE_LEN],
+        response: &[u8],
+    ) -> Result<String, IssuanceError<V::Error, T::Error>> {
+        let challenge = self
+            .pending
+            .lock()
+            .unwrap()
+            .challenges
+            .remove(nonce)
+            .ok_or(IssuanceError::UnknownChallenge)?;
+        if SystemTime::now() >= challenge.expires_at {
+            return Err(IssuanceError::Expired);
+        }
+
+        self.verifier
+            .verify(&challenge, response)
+            .await
+            .map_err(IssuanceError::Verification)?;
+        self.scheme
+            .construct(&challenge.resource)
+            .await
+            .map_err(IssuanceError::Token)
+    }
+
+    /// Remove expired challenges, returning the number removed.
+    pub fn prune(&self) -> usize {
+        // Challenges share a TTL, so they expire in order of issuance
+        self.pending.lock().unwrap().prune_front(SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pricing::SizePrice, schemes::hmac_bearer::HmacScheme};
+
+    #[derive(Debug)]
+    struct NonceVerifier;
+
+    #[async_trait]
+    impl ResponseVerifier for NonceVerifier {
+        type Error = &'static str;
+
+        async fn verify(&self, challenge: &Challenge, response: &[u8]) -> Result<(), Self::Error> {
+            if response == challenge.nonce {
+                Ok(())
+            } else {
+                Err("nonce mismatch")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn redeem() {
+        let issuer = Issuer::new(
+            HmacScheme::new(b"secret"),
+            NonceVerifier,
+            Duration::from_secs(60),
+        );
+
+        let challenge = issuer.challenge(b"resource", 1_000);
+        let token = issuer
+            .redeem(&challenge.nonce, &challenge.nonce)
+            .await
+            .unwrap();
+        assert!(HmacScheme::new(b"secret")
+            .validate_token(b"resource", &token)
+            .is_ok());
+        assert!(matches!(
+            issuer.redeem(&challenge.nonce, &challenge.nonce).await,
+            Err(IssuanceError::UnknownChallenge)
+        ));
+
+        let challenge = issuer.challenge(b"resource", 1_000);
+        assert!(matches!(
+            issuer.redeem(&challenge.nonce, b"wrong").await,
+            Err(IssuanceError::Verification(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn quote() {
+        let issuer = Issuer::new(
+            HmacScheme::new(b"secret"),
+            NonceVerifier,
+            Duration::from_secs(60),
+        );
+        let oracle = SizePrice {
+            base: 100,
+            per_byte: 2,
+        };
+        let challenge = issuer.quote(&oracle, b"address", 50).await.unwrap();
+        assert_eq!(challenge.price, 200);
+        assert_eq!(challenge.resource, b"address");
+    }
+
+    #[tokio::test]
+    async fn capacity() {
+        let issuer = Issuer::new(
+            HmacScheme::new(b"secret"),
+            NonceVerifier,
+            Duration::from_secs(60),
+        )
+        .with_max_pending(2);
+
+        let first = issuer.challenge(b"resource", 1_000);
+        let second = issuer.challenge(b"resource", 1_000);
+        issuer.redeem(&second.nonce, &second.nonce).await.unwrap();
+        let third = issuer.challenge(b"resource", 1_000);
+        assert_eq!(issuer.pending(), 2);
+
+        // The oldest challenge is evicted once the limit is reached
+        let fourth = issuer.challenge(b"resource", 1_000);
+        assert_eq!(issuer.pending(), 2);
+        assert!(matches!(
+            issuer.redeem(&first.nonce, &first.nonce).await,
+            Err(IssuanceError::UnknownChallenge)
+        ));
+        assert!(issuer.redeem(&third.nonce, &third.nonce).await.is_ok());
+        assert!(issuer.redeem(&fourth.nonce, &fourth.nonce).await.is_ok());
+        assert_eq!(issuer.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let issuer = Issuer::new(HmacScheme::new(b"secret"), NonceVerifier, Duration::ZERO);
+        let challenge = issuer.challenge(b"resource", 1_000);
+        assert_eq!(issuer.prune(), 1);
+        assert!(matches!(
+            issuer.redeem(&challenge.nonce, &challenge.nonce).await,
+            Err(IssuanceError::UnknownChallenge)
+        ));
+
+        let challenge = issuer.challenge(b"resource", 1_000);
+        assert!(matches!(
+            issuer.redeem(&challenge.nonce, &challenge.nonce).await,
+            Err(IssuanceError::Expired)
+        ));
+
+        // Expired challenges are pruned as new ones are issued
+        issuer.challenge(b"resource", 1_000);
+        issuer.challenge(b"resource", 1_000);
+        assert_eq!(issuer.pending(), 1);
+    }
+}
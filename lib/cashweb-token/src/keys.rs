@@ -0,0 +1,203 @@
+//! This module contains [`SecretKey`], which holds token scheme secrets and zeroes them on drop,
+//! along with helpers to load secrets from configuration, files and the environment.
+//!
+//! A key specification takes one of the following forms:
+//!
+//! - `hex:<key>` or `<key>`, a hexadecimal key,
+//! - `base64:<key>`, a base64 key, in either the standard or URL safe alphabet,
+//! - `file:<path>`, a file containing a key specification,
+//! - `env:<variable>`, an environment variable containing a key specification.
+
+use std::{env, fmt, fs, io, path::Path};
+
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// Recommended minimum estimated entropy of a secret key, in bits.
+pub const MIN_ENTROPY_BITS: f64 = 128.0;
+
+/// Error associated with loading a [`SecretKey`].
+#[derive(Debug, Error)]
+pub enum KeyError {
+    /// Failed to read the key file.
+    #[error("failed to read key file: {0}")]
+    Io(io::Error),
+    /// Failed to read the environment variable.
+    #[error("failed to read key from environment: {0}")]
+    Env(env::VarError),
+    /// Failed to decode a hexadecimal key.
+    #[error("failed to decode hex key: {0}")]
+    Hex(hex::FromHexError),
+    /// Failed to decode a base64 key.
+    #[error("failed to decode base64 key: {0}")]
+    Base64(base64::DecodeError),
+    /// The key specification referred to another file or variable.
+    #[error("key specification is nested")]
+    Nested,
+    /// The key was empty.
+    #[error("key is empty")]
+    Empty,
+    /// The key has insufficient estimated entropy.
+    #[error("key has an estimated {estimate:.0} bits of entropy, at least {minimum:.0} required")]
+    LowEntropy {
+        /// Estimated entropy of the key, in bits.
+        estimate: f64,
+        /// Required entropy, in bits.
+        minimum: f64,
+    },
+}
+
+/// A secret key which is zeroed when dropped.
+#[derive(Clone)]
+pub struct SecretKey(Zeroizing<Vec<u8>>);
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SecretKey").field(&"<redacted>").finish()
+    }
+}
+
+impl From<Vec<u8>> for SecretKey {
+    fn from(key: Vec<u8>) -> Self {
+        Self(Zeroizing::new(key))
+    }
+}
+
+impl From<&[u8]> for SecretKey {
+    fn from(key: &[u8]) -> Self {
+        Self::from(key.to_vec())
+    }
+}
+
+impl SecretKey {
+    /// The raw key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Decode a hexadecimal key.
+    pub fn from_hex(key: &str) -> Result<Self, KeyError> {
+        hex::decode(key.trim())
+            .map(Self::from)
+            .map_err(KeyError::Hex)
+    }
+
+    /// Decode a base64 key, in either the standard or URL safe alphabet, with or without padding.
+    pub fn from_base64(key: &str) -> Result<Self, KeyError> {
+        let normalized: Zeroizing<String> = Zeroizing::new(
+            key.trim()
+                .trim_end_matches('=')
+                .chars()
+                .map(|c| match c {
+                    '+' => '-',
+                    '/' => '_',
+                    c => c,
+                })
+                .collect(),
+        );
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        base64::decode_config(normalized.as_bytes(), url_safe_config)
+            .map(Self::from)
+            .map_err(KeyError::Base64)
+    }
+
+    /// Parse a key specification, which must not refer to a file or environment variable.
+    pub fn parse(spec: &str) -> Result<Self, KeyError> {
+        let spec = spec.trim();
+        let key = if let Some(key) = spec.strip_prefix("base64:") {
+            Self::from_base64(key)?
+        } else if spec.starts_with("file:") || spec.starts_with("env:") {
+            return Err(KeyError::Nested);
+        } else {
+            Self::from_hex(spec.strip_prefix("hex:").unwrap_or(spec))?
+        };
+        if key.as_bytes().is_empty() {
+            return Err(KeyError::Empty);
+        }
+        Ok(key)
+    }
+
+    /// Read a key specification from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, KeyError> {
+        let contents = Zeroizing::new(fs::read_to_string(path).map_err(KeyError::Io)?);
+        Self::parse(&contents)
+    }
+
+    /// Read a key specification from an environment variable.
+    pub fn from_env(variable: &str) -> Result<Self, KeyError> {
+        let contents = Zeroizing::new(env::var(variable).map_err(KeyError::Env)?);
+        Self::parse(&contents)
+    }
+
+    /// Load a key specification, following a single `file:` or `env:` indirection.
+    pub fn load(spec: &str) -> Result<Self, KeyError> {
+        let spec = spec.trim();
+        if let Some(path) = spec.strip_prefix("file:") {
+            Self::from_file(path)
+        } else if let Some(variable) = spec.strip_prefix("env:") {
+            Self::from_env(variable)
+        } else {
+            Self::parse(spec)
+        }
+    }
+
+    /// Estimate the entropy of the key, in bits, from its length.
+    ///
+    /// A key which repeats a shorter pattern at least twice is credited with the length of the pattern only. This
+    /// is a heuristic catching short or repetitive keys. It cannot detect keys derived from
+    /// guessable passphrases.
+    pub fn estimate_entropy(&self) -> f64 {
+        let key = self.as_bytes();
+        let period = (1..=key.len() / 2)
+            .find(|period| key[*period..] == key[..key.len() - period])
+            .unwrap_or(key.len());
+        (period * 8) as f64
+    }
+
+    /// Check that the estimated entropy of the key is at least `minimum` bits.
+    pub fn check_entropy(&self, minimum: f64) -> Result<(), KeyError> {
+        let estimate = self.estimate_entropy();
+        if estimate < minimum {
+            return Err(KeyError::LowEntropy { estimate, minimum });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let key = SecretKey::parse("hex:00ff").unwrap();
+        assert_eq!(key.as_bytes(), &[0x00, 0xff]);
+        let key = SecretKey::parse("00ff").unwrap();
+        assert_eq!(key.as_bytes(), &[0x00, 0xff]);
+        let key = SecretKey::parse("base64:AP8=").unwrap();
+        assert_eq!(key.as_bytes(), &[0x00, 0xff]);
+        assert!(matches!(
+            SecretKey::parse("file:/etc/key"),
+            Err(KeyError::Nested)
+        ));
+        assert!(matches!(SecretKey::parse(""), Err(KeyError::Empty)));
+        assert_eq!(format!("{:?}", key), "SecretKey(\"<redacted>\")");
+    }
+
+    #[test]
+    fn entropy() {
+        let key = SecretKey::from(vec![7; 64]);
+        assert!(key.check_entropy(MIN_ENTROPY_BITS).is_err());
+
+        let key = SecretKey::from(b"abcdabcdabcdabcdabcdabcd".to_vec());
+        assert_eq!(key.estimate_entropy(), 32.0);
+
+        let key: Vec<u8> = (0..=255).collect();
+        assert!(SecretKey::from(key).check_entropy(MIN_ENTROPY_BITS).is_ok());
+
+        // Random 16 byte keys have repeated bytes, but no repeating pattern
+        let key = SecretKey::parse("3c9a17f0e2b4583c6d1a9f07c2e45b3c").unwrap();
+        assert_eq!(key.estimate_entropy(), 128.0);
+        assert!(key.check_entropy(MIN_ENTROPY_BITS).is_ok());
+    }
+}
@@ -0,0 +1,338 @@
+//! This module contains [`AuthLayer`], a [`Layer`] which validates the token in the
+//! `Authorization` header before passing the request to the inner service.
+//!
+//! Validated requests carry a [`ValidatedToken`] in their extensions. Requests without a token
+//! are rejected with `402 Payment Required` and requests with a rejected token are rejected with
+//! the status of its [`ErrorKind`], both accompanied by a `WWW-Authenticate` challenge.
+
+use std::{fmt, pin::Pin, sync::Arc};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    Request, Response, StatusCode, Uri,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::schemes::{ErrorKind, TokenError, TokenScheme};
+
+type ResponseFuture<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// Authorization scheme used to present a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// `Authorization: POP <token>`.
+    Pop,
+    /// `Authorization: Bearer <token>`.
+    Bearer,
+    /// `Authorization: HMAC <token>`.
+    Hmac,
+}
+
+impl AuthScheme {
+    /// The scheme name, as it appears in `Authorization` and `WWW-Authenticate` headers.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pop => "POP",
+            Self::Bearer => "Bearer",
+            Self::Hmac => "HMAC",
+        }
+    }
+
+    /// Parse an `Authorization` header value into its scheme and token.
+    ///
+    /// Scheme names are matched case-insensitively.
+    pub fn parse(value: &str) -> Option<(Self, &str)> {
+        let mut parts = value.splitn(2, ' ');
+        let name = parts.next()?;
+        let token = parts.next()?.trim();
+        if token.is_empty() {
+            return None;
+        }
+        [Self::Pop, Self::Bearer, Self::Hmac]
+            .iter()
+            .find(|auth_scheme| auth_scheme.as_str().eq_ignore_ascii_case(name))
+            .map(|auth_scheme| (*auth_scheme, token))
+    }
+}
+
+/// A token which has passed validation, inserted into the request extensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatedToken {
+    /// Authorization scheme the token was presented with.
+    pub scheme: AuthScheme,
+    /// The token.
+    pub token: String,
+    /// The data the token was validated against.
+    pub target: Vec<u8>,
+}
+
+/// Extract the first token, presented using a known [`AuthScheme`], from [`HeaderMap`].
+pub fn extract_authorization(headers: &HeaderMap) -> Option<(AuthScheme, &str)> {
+    headers
+        .get_all(AUTHORIZATION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(AuthScheme::parse)
+}
+
+fn path_target(uri: &Uri) -> Vec<u8> {
+    uri.path().as_bytes().to_vec()
+}
+
+/// A [`Layer`] which wraps services in an [`AuthService`].
+pub struct AuthLayer<T: ?Sized> {
+    scheme: Arc<T>,
+    target: fn(&Uri) -> Vec<u8>,
+}
+
+impl<T: ?Sized> fmt::Debug for AuthLayer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthLayer").finish()
+    }
+}
+
+impl<T: ?Sized> Clone for AuthLayer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            scheme: self.scheme.clone(),
+            target: self.target,
+        }
+    }
+}
+
+impl<T: ?Sized> AuthLayer<T> {
+    /// Create a new [`AuthLayer`] validating tokens against the request path.
+    pub fn new(scheme: Arc<T>) -> Self {
+        Self {
+            scheme,
+            target: path_target,
+        }
+    }
+
+    /// Set the function which derives, from the request URI, the data tokens are validated
+    /// against.
+    pub fn with_target(mut self, target: fn(&Uri) -> Vec<u8>) -> Self {
+        self.target = target;
+        self
+    }
+}
+
+impl<S, T: ?Sized> Layer<S> for AuthLayer<T> {
+    type Service = AuthService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            scheme: self.scheme.clone(),
+            target: self.target,
+        }
+    }
+}
+
+/// A [`Service`] which validates the token in the `Authorization` header before calling the
+/// inner service.
+pub struct AuthService<S, T: ?Sized> {
+    inner: S,
+    scheme: Arc<T>,
+    target: fn(&Uri) -> Vec<u8>,
+}
+
+impl<S: fmt::Debug, T: ?Sized> fmt::Debug for AuthService<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<S: Clone, T: ?Sized> Clone for AuthService<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            scheme: self.scheme.clone(),
+            target: self.target,
+        }
+    }
+}
+
+impl<S, T: ?Sized> AuthService<S, T> {
+    /// Converts the auth service into the underlying service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+fn challenge<B: Default>(status: StatusCode, challenges: &[String]) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    for challenge in challenges {
+        if let Ok(value) = HeaderValue::from_str(challenge) {
+            headers.append(WWW_AUTHENTICATE, value);
+        }
+    }
+    response
+}
+
+fn escape(description: &str) -> String {
+    description
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '"' { '\'' } else { c })
+        .collect()
+}
+
+/// Format a `WWW-Authenticate` challenge reporting a rejected token.
+pub(crate) fn error_challenge(
+    auth_scheme: AuthScheme,
+    kind: ErrorKind,
+    description: &str,
+) -> String {
+    let error = match kind {
+        ErrorKind::Malformed => "invalid_request",
+        ErrorKind::WrongScope => "insufficient_scope",
+        _ => "invalid_token",
+    };
+    format!(
+        "{} error=\"{}\", error_description=\"{}\"",
+        auth_scheme.as_str(),
+        error,
+        escape(description)
+    )
+}
+
+impl<S, T, ReqBody, ResBody> Service<Request<ReqBody>> for AuthService<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S: Send + Clone + 'static,
+    S::Future: Send,
+    T: TokenScheme + Send + Sync + ?Sized + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(context)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let scheme = self.scheme.clone();
+        let target = (self.target)(request.uri());
+        let authorization = extract_authorization(request.headers())
+            .map(|(auth_scheme, token)| (auth_scheme, token.to_string()));
+
+        let fut = async move {
+            let (auth_scheme, token) = match authorization {
+                Some(some) => some,
+                None => {
+                    let challenges = [AuthScheme::Pop, AuthScheme::Bearer]
+                        .iter()
+                        .map(|auth_scheme| auth_scheme.as_str().to_string())
+                        .collect::<Vec<_>>();
+                    return Ok(challenge(StatusCode::PAYMENT_REQUIRED, &challenges));
+                }
+            };
+
+            if let Err(err) = scheme.validate(&target, &token).await {
+                let kind = err.kind();
+                let challenge_value = error_challenge(auth_scheme, kind, &err.to_string());
+                return Ok(challenge(kind.status(), &[challenge_value]));
+            }
+
+            request.extensions_mut().insert(ValidatedToken {
+                scheme: auth_scheme,
+                token,
+                target,
+            });
+            inner.call(request).await
+        };
+        Box::pin(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::schemes::hmac_bearer::HmacScheme;
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Response<Option<ValidatedToken>>;
+        type Error = Infallible;
+        type Future = ResponseFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let validated = request.extensions().get::<ValidatedToken>().cloned();
+            Box::pin(async move { Ok(Response::new(validated)) })
+        }
+    }
+
+    fn request(authorization: Option<String>) -> Request<()> {
+        let mut builder = Request::builder().uri("/profiles/alice");
+        if let Some(authorization) = authorization {
+            builder = builder.header(AUTHORIZATION, authorization);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn authorize() {
+        let scheme = Arc::new(HmacScheme::new(b"secret"));
+        let token = scheme.construct_token(b"/profiles/alice");
+        let mut service = AuthLayer::new(scheme).layer(Echo);
+
+        let response = service.call(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            response.headers().get_all(WWW_AUTHENTICATE).iter().count(),
+            2
+        );
+
+        let response = service
+            .call(request(Some("Bearer invalid".to_string())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let other = HmacScheme::new(b"other").construct_token(b"/profiles/alice");
+        let response = service
+            .call(request(Some(format!("Bearer {}", other))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = service
+            .call(request(Some(format!("POP {}", token))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.into_body(),
+            Some(ValidatedToken {
+                scheme: AuthScheme::Pop,
+                token,
+                target: b"/profiles/alice".to_vec(),
+            })
+        );
+    }
+}
@@ -9,7 +9,16 @@
 //!
 //! [`POP Token Protocol`]: https://github.com/cashweb/specifications/blob/master/proof-of-payment-token/specification.mediawiki
 
+pub mod encoding;
+pub mod issuance;
+pub mod keys;
+pub mod layer;
+pub mod pricing;
+pub mod rates;
+pub mod registry;
 pub mod schemes;
+pub mod signing;
+pub mod store;
 
 use http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 
@@ -0,0 +1,66 @@
+//! This module contains the [`PriceOracle`] trait, consulted when issuing tokens to quote the
+//! price of a resource.
+//!
+//! This allows operators to charge by storage size or, using [`FiatPrice`], to peg prices to fiat.
+//!
+//! [`FiatPrice`]: crate::rates::FiatPrice
+
+use std::{convert::Infallible, fmt, sync::Arc};
+
+use async_trait::async_trait;
+
+/// Quotes the price of a token.
+#[async_trait]
+pub trait PriceOracle {
+    /// Error associated with quoting.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Quote the price, in satoshis, of a token for the address payload, covering a payload of
+    /// `size` bytes.
+    async fn quote(&self, address: &[u8], size: usize) -> Result<u64, Self::Error>;
+}
+
+#[async_trait]
+impl<T> PriceOracle for Arc<T>
+where
+    T: PriceOracle + Send + Sync + ?Sized,
+{
+    type Error = T::Error;
+
+    async fn quote(&self, address: &[u8], size: usize) -> Result<u64, Self::Error> {
+        self.as_ref().quote(address, size).await
+    }
+}
+
+/// A [`PriceOracle`] quoting the same price for every token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedPrice(pub u64);
+
+#[async_trait]
+impl PriceOracle for FixedPrice {
+    type Error = Infallible;
+
+    async fn quote(&self, _address: &[u8], _size: usize) -> Result<u64, Self::Error> {
+        Ok(self.0)
+    }
+}
+
+/// A [`PriceOracle`] quoting a base price plus a price per byte of payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizePrice {
+    /// Price, in satoshis, charged regardless of size.
+    pub base: u64,
+    /// Price, in satoshis, charged per byte of payload.
+    pub per_byte: u64,
+}
+
+#[async_trait]
+impl PriceOracle for SizePrice {
+    type Error = Infallible;
+
+    async fn quote(&self, _address: &[u8], size: usize) -> Result<u64, Self::Error> {
+        Ok(self
+            .base
+            .saturating_add(self.per_byte.saturating_mul(size as u64)))
+    }
+}
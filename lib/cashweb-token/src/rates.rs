@@ -0,0 +1,363 @@
+//! This module contains the [`RateProvider`] trait, supplying exchange rates between coins and
+//! fiat currencies, and the [`FiatPrice`] oracle which pegs token prices to fiat.
+//!
+//! Rates are expressed as the value of one whole coin in the fiat currency. Providers are
+//! pluggable; [`JsonRateProvider`] fetches rates from an HTTP JSON API and [`CachedRates`] wraps
+//! any provider to avoid fetching a rate on every quote.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use hyper::{
+    body::to_bytes, client::HttpConnector, http::uri::InvalidUri, Body, Request, Response, Uri,
+};
+use hyper_tls::HttpsConnector;
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::pricing::PriceOracle;
+
+/// Default number of satoshis in one whole coin.
+pub const DEFAULT_SATS_PER_COIN: u64 = 100_000_000;
+
+/// Placeholder substituted with the currency code in the URL and pointer of a
+/// [`JsonRateProvider`].
+pub const CURRENCY_PLACEHOLDER: &str = "{currency}";
+
+/// Supplies exchange rates.
+#[async_trait]
+pub trait RateProvider {
+    /// Error associated with fetching a rate.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Fetch the value of one whole coin in the currency, for example "usd".
+    async fn rate(&self, currency: &str) -> Result<f64, Self::Error>;
+}
+
+/// Convert an amount of fiat to satoshis at the rate, rounding up.
+///
+/// Returns `None` if the rate is not positive and finite.
+pub fn fiat_to_sats(amount: f64, rate: f64, sats_per_coin: u64) -> Option<u64> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return None;
+    }
+    let sats = amount.max(0.0) / rate * sats_per_coin as f64;
+    // Discard floating point error before rounding up
+    Some(((sats * 1e6).round() / 1e6).ceil() as u64)
+}
+
+/// Convert an amount of satoshis to fiat at the rate.
+pub fn sats_to_fiat(sats: u64, rate: f64, sats_per_coin: u64) -> f64 {
+    sats as f64 / sats_per_coin as f64 * rate
+}
+
+/// A [`RateProvider`] supplying the same rate for every currency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedRate(pub f64);
+
+#[async_trait]
+impl RateProvider for FixedRate {
+    type Error = std::convert::Infallible;
+
+    async fn rate(&self, _currency: &str) -> Result<f64, Self::Error> {
+        Ok(self.0)
+    }
+}
+
+/// Error associated with [`JsonRateProvider`].
+#[derive(Debug, Error)]
+pub enum JsonRateError<E: fmt::Debug + fmt::Display> {
+    /// Invalid URI.
+    #[error(transparent)]
+    Uri(InvalidUri),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// Failed to parse the response JSON.
+    #[error("failed to parse response: {0}")]
+    Json(serde_json::Error),
+    /// The response did not contain a number at the pointer.
+    #[error("missing rate at {0}")]
+    MissingRate(String),
+}
+
+/// A [`RateProvider`] fetching rates from an HTTP JSON API.
+///
+/// The URL and [`JSON pointer`] may contain [`CURRENCY_PLACEHOLDER`], which is substituted with
+/// the currency code, for example `https://api.example.com/price?vs={currency}` and
+/// `/rates/{currency}`.
+///
+/// [`JSON pointer`]: https://tools.ietf.org/html/rfc6901
+#[derive(Clone, Debug)]
+pub struct JsonRateProvider<S> {
+    service: S,
+    url: String,
+    pointer: String,
+}
+
+impl JsonRateProvider<hyper::Client<HttpsConnector<HttpConnector>>> {
+    /// Create a new provider using a HTTPS client.
+    pub fn new(url: String, pointer: String) -> Self {
+        let https = HttpsConnector::new();
+        Self::from_service(hyper::Client::builder().build(https), url, pointer)
+    }
+}
+
+impl<S> JsonRateProvider<S> {
+    /// Create a new provider from a [`Service`].
+    pub fn from_service(service: S, url: String, pointer: String) -> Self {
+        Self {
+            service,
+            url,
+            pointer,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> RateProvider for JsonRateProvider<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Sync + Clone + Send + 'static,
+    S::Error: fmt::Debug + fmt::Display + Send,
+    S::Future: Send,
+{
+    type Error = JsonRateError<S::Error>;
+
+    async fn rate(&self, currency: &str) -> Result<f64, Self::Error> {
+        let uri: Uri = self
+            .url
+            .replace(CURRENCY_PLACEHOLDER, currency)
+            .parse()
+            .map_err(JsonRateError::Uri)?;
+        let request = Request::get(uri).body(Body::empty()).unwrap(); // This is safe
+        let response = self
+            .service
+            .clone()
+            .call(request)
+            .await
+            .map_err(JsonRateError::Service)?;
+        if !response.status().is_success() {
+            return Err(JsonRateError::UnexpectedStatusCode(
+                response.status().as_u16(),
+            ));
+        }
+        let body = to_bytes(response.into_body())
+            .await
+            .map_err(JsonRateError::Body)?;
+        let json: serde_json::Value = serde_json::from_slice(&body).map_err(JsonRateError::Json)?;
+
+        let pointer = self.pointer.replace(CURRENCY_PLACEHOLDER, currency);
+        match json.pointer(&pointer) {
+            Some(serde_json::Value::Number(number)) => number.as_f64(),
+            // Some APIs return decimal strings to preserve precision
+            Some(serde_json::Value::String(string)) => string.parse().ok(),
+            _ => None,
+        }
+        .ok_or(JsonRateError::MissingRate(pointer))
+    }
+}
+
+/// A [`RateProvider`] caching the rates of another provider for a period.
+#[derive(Debug)]
+pub struct CachedRates<P> {
+    provider: P,
+    ttl: Duration,
+    cache: RwLock<HashMap<String, (Instant, f64)>>,
+}
+
+impl<P> CachedRates<P> {
+    /// Wrap the provider, caching each rate for `ttl`.
+    pub fn new(provider: P, ttl: Duration) -> Self {
+        Self {
+            provider,
+            ttl,
+            cache: Default::default(),
+        }
+    }
+
+    /// Get the cached rate, if it has not expired.
+    fn cached(&self, currency: &str) -> Option<f64> {
+        self.cache
+            .read()
+            .unwrap()
+            .get(currency)
+            .filter(|(fetched, _)| fetched.elapsed() < self.ttl)
+            .map(|(_, rate)| *rate)
+    }
+}
+
+#[async_trait]
+impl<P> RateProvider for CachedRates<P>
+where
+    P: RateProvider + Send + Sync,
+{
+    type Error = P::Error;
+
+    async fn rate(&self, currency: &str) -> Result<f64, Self::Error> {
+        if let Some(rate) = self.cached(currency) {
+            return Ok(rate);
+        }
+        let rate = self.provider.rate(currency).await?;
+        self.cache
+            .write()
+            .unwrap()
+            .insert(currency.to_string(), (Instant::now(), rate));
+        Ok(rate)
+    }
+}
+
+/// Error associated with [`FiatPrice`].
+#[derive(Debug, Error)]
+pub enum FiatPriceError<E: fmt::Debug + fmt::Display> {
+    /// Failed to fetch the rate.
+    #[error("failed to fetch rate: {0}")]
+    Provider(E),
+    /// The rate was not positive and finite.
+    #[error("invalid rate: {0}")]
+    InvalidRate(f64),
+}
+
+/// A [`PriceOracle`] quoting a base price plus a price per byte of payload, both in fiat, and
+/// converting to satoshis at the current rate.
+#[derive(Debug)]
+pub struct FiatPrice<P> {
+    rates: P,
+    currency: String,
+    base: f64,
+    per_byte: f64,
+    sats_per_coin: u64,
+}
+
+impl<P> FiatPrice<P> {
+    /// Create a new oracle charging `base`, in the currency, for every token.
+    pub fn new(rates: P, currency: String, base: f64) -> Self {
+        Self {
+            rates,
+            currency,
+            base,
+            per_byte: 0.0,
+            sats_per_coin: DEFAULT_SATS_PER_COIN,
+        }
+    }
+
+    /// Set the price, in the currency, charged per byte of payload.
+    pub fn with_per_byte(mut self, per_byte: f64) -> Self {
+        self.per_byte = per_byte;
+        self
+    }
+
+    /// Set the number of satoshis in one whole coin.
+    pub fn with_sats_per_coin(mut self, sats_per_coin: u64) -> Self {
+        self.sats_per_coin = sats_per_coin;
+        self
+    }
+}
+
+#[async_trait]
+impl<P> PriceOracle for FiatPrice<P>
+where
+    P: RateProvider + Send + Sync,
+{
+    type Error = FiatPriceError<P::Error>;
+
+    async fn quote(&self, _address: &[u8], size: usize) -> Result<u64, Self::Error> {
+        let rate = self
+            .rates
+            .rate(&self.currency)
+            .await
+            .map_err(FiatPriceError::Provider)?;
+        let amount = self.base + self.per_byte * size as f64;
+        fiat_to_sats(amount, rate, self.sats_per_coin).ok_or(FiatPriceError::InvalidRate(rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll},
+    };
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockApi;
+
+    impl Service<Request<Body>> for MockApi {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            assert_eq!(request.uri().query(), Some("vs=usd"));
+            ready(Ok(Response::new(Body::from(
+                r#"{"rates":{"usd":"250.0","eur":200}}"#,
+            ))))
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingRate(AtomicUsize);
+
+    #[async_trait]
+    impl RateProvider for CountingRate {
+        type Error = Infallible;
+
+        async fn rate(&self, _currency: &str) -> Result<f64, Self::Error> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(500.0)
+        }
+    }
+
+    #[test]
+    fn conversion() {
+        assert_eq!(
+            fiat_to_sats(0.01, 500.0, DEFAULT_SATS_PER_COIN),
+            Some(2_000)
+        );
+        assert_eq!(
+            fiat_to_sats(0.01, 300.0, DEFAULT_SATS_PER_COIN),
+            Some(3_334)
+        );
+        assert_eq!(fiat_to_sats(0.01, 0.0, DEFAULT_SATS_PER_COIN), None);
+        assert!((sats_to_fiat(2_000, 500.0, DEFAULT_SATS_PER_COIN) - 0.01).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn json_provider() {
+        let provider = JsonRateProvider::from_service(
+            MockApi,
+            "http://rates/price?vs={currency}".to_string(),
+            "/rates/{currency}".to_string(),
+        );
+        assert_eq!(provider.rate("usd").await.unwrap(), 250.0);
+    }
+
+    #[tokio::test]
+    async fn fiat_price() {
+        let rates = CachedRates::new(CountingRate::default(), Duration::from_secs(60));
+        let oracle = FiatPrice::new(rates, "usd".to_string(), 0.01).with_per_byte(0.0001);
+
+        assert_eq!(oracle.quote(b"alice", 0).await.unwrap(), 2_000);
+        assert_eq!(oracle.quote(b"alice", 100).await.unwrap(), 4_000);
+        assert_eq!(oracle.rates.provider.0.load(Ordering::SeqCst), 1);
+    }
+}
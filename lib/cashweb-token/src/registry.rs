@@ -0,0 +1,204 @@
+//! This module contains [`SchemeRegistry`] which dispatches tokens to a [`TokenScheme`] based on
+//! the [`AuthScheme`] they were presented with.
+//!
+//! This allows operators to keep several schemes live at once, for example while clients migrate
+//! from one scheme to another.
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use http::header::{HeaderMap, AUTHORIZATION};
+use thiserror::Error;
+
+use crate::{
+    layer::{error_challenge, AuthScheme},
+    schemes::{ErrorKind, TokenError, TokenScheme},
+};
+
+/// Error associated with [`SchemeRegistry::validate`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RegistryError {
+    /// No scheme is registered for the [`AuthScheme`].
+    #[error("unsupported authorization scheme: {}", .0.as_str())]
+    Unsupported(AuthScheme),
+    /// The registered scheme rejected the token.
+    #[error("{1}")]
+    Token(ErrorKind, String),
+}
+
+impl TokenError for RegistryError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Unsupported(_) => ErrorKind::Invalid,
+            Self::Token(kind, _) => *kind,
+        }
+    }
+}
+
+/// A [`TokenScheme`] with its error type erased.
+#[async_trait]
+trait DynScheme {
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), RegistryError>;
+}
+
+#[async_trait]
+impl<T> DynScheme for T
+where
+    T: TokenScheme + Send + Sync,
+{
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), RegistryError> {
+        TokenScheme::validate(self, data, token)
+            .await
+            .map_err(|err| RegistryError::Token(err.kind(), err.to_string()))
+    }
+}
+
+/// Dispatches tokens to the [`TokenScheme`] registered for their [`AuthScheme`].
+#[derive(Clone, Default)]
+pub struct SchemeRegistry {
+    schemes: Vec<(AuthScheme, Arc<dyn DynScheme + Send + Sync>)>,
+}
+
+impl fmt::Debug for SchemeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.schemes.iter().map(|(auth_scheme, _)| auth_scheme))
+            .finish()
+    }
+}
+
+impl SchemeRegistry {
+    /// Create an empty [`SchemeRegistry`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the [`TokenScheme`] validating tokens presented with the [`AuthScheme`],
+    /// replacing any scheme already registered for it.
+    ///
+    /// Schemes are advertised in the order they are first registered.
+    pub fn register<T>(mut self, auth_scheme: AuthScheme, scheme: T) -> Self
+    where
+        T: TokenScheme + Send + Sync + 'static,
+    {
+        let scheme: Arc<dyn DynScheme + Send + Sync> = Arc::new(scheme);
+        match self
+            .schemes
+            .iter_mut()
+            .find(|(registered, _)| *registered == auth_scheme)
+        {
+            Some((_, existing)) => *existing = scheme,
+            None => self.schemes.push((auth_scheme, scheme)),
+        }
+        self
+    }
+
+    /// Whether a scheme is registered for the [`AuthScheme`].
+    pub fn supports(&self, auth_scheme: AuthScheme) -> bool {
+        self.schemes
+            .iter()
+            .any(|(registered, _)| *registered == auth_scheme)
+    }
+
+    /// Iterate over the registered [`AuthScheme`]s.
+    pub fn auth_schemes(&self) -> impl Iterator<Item = AuthScheme> + '_ {
+        self.schemes.iter().map(|(auth_scheme, _)| *auth_scheme)
+    }
+
+    /// Extract the first token, presented using a registered [`AuthScheme`], from [`HeaderMap`].
+    pub fn extract<'a>(&self, headers: &'a HeaderMap) -> Option<(AuthScheme, &'a str)> {
+        headers
+            .get_all(AUTHORIZATION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(AuthScheme::parse)
+            .find(|(auth_scheme, _)| self.supports(*auth_scheme))
+    }
+
+    /// Validate the token against the data using the scheme registered for the [`AuthScheme`].
+    pub async fn validate(
+        &self,
+        auth_scheme: AuthScheme,
+        data: &[u8],
+        token: &str,
+    ) -> Result<(), RegistryError> {
+        let (_, scheme) = self
+            .schemes
+            .iter()
+            .find(|(registered, _)| *registered == auth_scheme)
+            .ok_or(RegistryError::Unsupported(auth_scheme))?;
+        scheme.validate(data, token).await
+    }
+
+    /// `WWW-Authenticate` challenges advertising every registered scheme.
+    pub fn challenges(&self) -> Vec<String> {
+        self.auth_schemes()
+            .map(|auth_scheme| auth_scheme.as_str().to_string())
+            .collect()
+    }
+
+    /// `WWW-Authenticate` challenges reporting that a token was rejected, followed by the
+    /// remaining registered schemes.
+    pub fn invalid_token_challenges(
+        &self,
+        auth_scheme: AuthScheme,
+        err: &RegistryError,
+    ) -> Vec<String> {
+        let rejected = error_challenge(auth_scheme, err.kind(), &err.to_string());
+        std::iter::once(rejected)
+            .chain(
+                self.auth_schemes()
+                    .filter(|registered| *registered != auth_scheme)
+                    .map(|registered| registered.as_str().to_string()),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderValue;
+
+    use super::*;
+    use crate::schemes::hmac_bearer::HmacScheme;
+
+    #[tokio::test]
+    async fn dispatch() {
+        let legacy = HmacScheme::new(b"legacy");
+        let current = HmacScheme::new(b"current");
+        let legacy_token = legacy.construct_token(b"data");
+        let current_token = current.construct_token(b"data");
+        let registry = SchemeRegistry::new()
+            .register(AuthScheme::Pop, legacy)
+            .register(AuthScheme::Hmac, current);
+
+        assert_eq!(registry.challenges(), vec!["POP", "HMAC"]);
+
+        let mut headers = HeaderMap::new();
+        headers.append(AUTHORIZATION, HeaderValue::from_static("Bearer abc"));
+        headers.append(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("hmac {}", current_token)).unwrap(),
+        );
+        let (auth_scheme, token) = registry.extract(&headers).unwrap();
+        assert_eq!(auth_scheme, AuthScheme::Hmac);
+        assert!(registry.validate(auth_scheme, b"data", token).await.is_ok());
+
+        assert!(registry
+            .validate(AuthScheme::Pop, b"data", &legacy_token)
+            .await
+            .is_ok());
+        assert!(matches!(
+            registry
+                .validate(AuthScheme::Hmac, b"data", &legacy_token)
+                .await,
+            Err(RegistryError::Token(ErrorKind::Invalid, _))
+        ));
+        assert_eq!(
+            registry
+                .validate(AuthScheme::Bearer, b"data", &legacy_token)
+                .await,
+            Err(RegistryError::Unsupported(AuthScheme::Bearer))
+        );
+    }
+}
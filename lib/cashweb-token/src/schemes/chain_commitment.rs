@@ -1,18 +1,25 @@
 //! This module contains the [`ChainCommitmentScheme`] which provides the ability to validate POP
 //! tokens given in the [`Keyserver Protocol`].
 //!
+//! A POP token points to an output of a transaction, which must be an `OP_RETURN` pushing the
+//! commitment to an address and its metadata. A [`Mandate`] additionally requires the transaction
+//! to burn, in the commitment output, or pay a minimum amount.
+//!
 //! [`Keyserver Protocol`]: https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
+use async_trait::async_trait;
 use cashweb_bitcoin::{
-    transaction::{self, Transaction},
+    transaction::{self, script::Script, Transaction},
     Decodable,
 };
-use cashweb_bitcoin_client::{BitcoinClient, NodeError};
+use cashweb_bitcoin_client::{utxo::UtxoCache, BitcoinClient, NodeError};
 use ring::digest::{Context, SHA256};
 use thiserror::Error;
 
+use super::{ErrorKind, TokenError, TokenScheme};
+
 /// Error associated with token validation.
 #[derive(Debug, Error)]
 pub enum ValidationError {
@@ -40,15 +47,94 @@ pub enum ValidationError {
     /// Token was unexpected length.
     #[error("unexpected token length")]
     TokenLength,
+    /// The transaction committed less than the mandated amount.
+    #[error("insufficient amount: {committed} of {required} satoshis")]
+    InsufficientAmount {
+        /// The mandated amount.
+        required: u64,
+        /// The amount committed by the transaction.
+        committed: u64,
+    },
+}
+
+impl TokenError for ValidationError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Base64(_) | Self::TokenLength => ErrorKind::Malformed,
+            Self::Node(_) | Self::Transaction(_) => ErrorKind::Internal,
+            Self::IncorrectLength
+            | Self::Invalid
+            | Self::NotOpReturn
+            | Self::OutputNotFound
+            | Self::InsufficientAmount { .. } => ErrorKind::Invalid,
+        }
+    }
 }
 
 /// Chain commitment scheme used in the keyserver protocol.
+///
+/// With a [`UtxoCache`], transactions confirmed by the blocks it retains are validated without
+/// fetching them from bitcoind.
 #[derive(Clone, Debug)]
 pub struct ChainCommitmentScheme<C: BitcoinClient> {
     client: C,
+    cache: Option<UtxoCache>,
 }
 
 const COMMITMENT_LEN: usize = 32;
+const TOKEN_LEN: usize = 32 + 4;
+
+/// The amount, in satoshis, a POP transaction must commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mandate {
+    /// Burn at least the amount in the commitment output.
+    Burn(u64),
+    /// Pay at least the amount, over any number of outputs, to the script.
+    Pay {
+        /// The script paid to.
+        script: Script,
+        /// The minimum amount.
+        amount: u64,
+    },
+}
+
+impl Default for Mandate {
+    fn default() -> Self {
+        Self::Burn(0)
+    }
+}
+
+impl Mandate {
+    /// Check the transaction commits the mandated amount, given the index of its commitment
+    /// output.
+    pub fn check(&self, transaction: &Transaction, vout: usize) -> Result<(), ValidationError> {
+        let (required, committed) = match self {
+            Self::Burn(amount) => {
+                let burned = transaction
+                    .outputs
+                    .get(vout)
+                    .ok_or(ValidationError::OutputNotFound)?
+                    .value;
+                (*amount, burned)
+            }
+            Self::Pay { script, amount } => {
+                let paid = transaction
+                    .outputs
+                    .iter()
+                    .filter(|output| &output.script == script)
+                    .fold(0u64, |total, output| total.saturating_add(output.value));
+                (*amount, paid)
+            }
+        };
+        if committed < required {
+            return Err(ValidationError::InsufficientAmount {
+                required,
+                committed,
+            });
+        }
+        Ok(())
+    }
+}
 
 /// Construct the commitment.
 pub fn construct_commitment(pub_key_hash: &[u8], address_metadata_hash: &[u8]) -> Vec<u8> {
@@ -73,7 +159,32 @@ pub fn construct_token(tx_id: &[u8], vout: u32) -> String {
 impl<Client: BitcoinClient> ChainCommitmentScheme<Client> {
     /// Create a [`ChainCommitmentScheme`] from a [`BitcoinClient`].
     pub fn from_client(client: Client) -> Self {
-        ChainCommitmentScheme { client }
+        ChainCommitmentScheme {
+            client,
+            cache: None,
+        }
+    }
+
+    /// Consult the [`UtxoCache`] for transactions before fetching them from bitcoind.
+    pub fn with_cache(mut self, cache: UtxoCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    async fn get_transaction(&self, tx_id: &[u8]) -> Result<Transaction, ValidationError> {
+        // The cache is keyed by the transaction ID in little-endian format
+        if let (Some(cache), Ok(mut tx_id)) = (&self.cache, <[u8; 32]>::try_from(tx_id)) {
+            tx_id.reverse();
+            if let Some(transaction) = cache.get_transaction(&tx_id) {
+                return Ok(transaction);
+            }
+        }
+        let raw_transaction = self
+            .client
+            .get_raw_transaction(tx_id)
+            .await
+            .map_err(ValidationError::Node)?;
+        Transaction::decode(&mut raw_transaction.as_slice()).map_err(ValidationError::Transaction)
     }
 
     /// Validate a token.
@@ -82,14 +193,40 @@ impl<Client: BitcoinClient> ChainCommitmentScheme<Client> {
         pub_key_hash: &[u8],
         address_metadata_hash: &[u8],
         token: &str,
+    ) -> Result<Vec<u8>, ValidationError> {
+        self.validate_token_mandated(
+            pub_key_hash,
+            address_metadata_hash,
+            token,
+            &Mandate::default(),
+        )
+        .await
+    }
+
+    /// Validate a token, checking its transaction commits the mandated amount.
+    pub async fn validate_token_mandated(
+        &self,
+        pub_key_hash: &[u8],
+        address_metadata_hash: &[u8],
+        token: &str,
+        mandate: &Mandate,
+    ) -> Result<Vec<u8>, ValidationError> {
+        let commitment = construct_commitment(pub_key_hash, address_metadata_hash);
+        self.validate_commitment(&commitment, token, mandate).await
+    }
+
+    async fn validate_commitment(
+        &self,
+        expected_commitment: &[u8],
+        token: &str,
+        mandate: &Mandate,
     ) -> Result<Vec<u8>, ValidationError> {
         let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
         let outpoint_raw =
             base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
 
         // Check token length
-        const PAYLOAD_LEN: usize = 32 + 4;
-        if outpoint_raw.len() != PAYLOAD_LEN {
+        if outpoint_raw.len() != TOKEN_LEN {
             return Err(ValidationError::TokenLength);
         }
 
@@ -97,41 +234,186 @@ impl<Client: BitcoinClient> ChainCommitmentScheme<Client> {
         let tx_id = &outpoint_raw[..32];
 
         // Get transaction
-        let raw_transaction = self
-            .client
-            .get_raw_transaction(tx_id)
-            .await
-            .map_err(ValidationError::Node)?;
-        let transaction = Transaction::decode(&mut raw_transaction.as_slice())
-            .map_err(ValidationError::Transaction)?;
+        let transaction = self.get_transaction(tx_id).await?;
 
         // Get vout
         let vout_raw: [u8; 4] = outpoint_raw[32..36].try_into().unwrap(); // This is safe
-        let vout = u32::from_le_bytes(vout_raw);
+        let vout = u32::from_le_bytes(vout_raw) as usize;
 
         // Parse script
         let output = transaction
             .outputs
-            .get(vout as usize)
+            .get(vout)
             .ok_or(ValidationError::OutputNotFound)?;
+        let commitment = match output.script.op_return_data() {
+            Some(data) => match data.as_slice() {
+                [commitment] if commitment.len() == COMMITMENT_LEN => *commitment,
+                _ => return Err(ValidationError::IncorrectLength),
+            },
+            None if output.script.is_op_return() => return Err(ValidationError::IncorrectLength),
+            None => return Err(ValidationError::NotOpReturn),
+        };
 
-        if !output.script.is_op_return() {
-            return Err(ValidationError::NotOpReturn);
+        // Check commitment
+        if expected_commitment != commitment {
+            return Err(ValidationError::Invalid);
         }
 
-        let raw_script = output.script.as_bytes();
+        // Check amount
+        mandate.check(&transaction, vout)?;
+        Ok(outpoint_raw)
+    }
+}
 
-        // Check length
-        if raw_script.len() != 2 + COMMITMENT_LEN || raw_script[1] != COMMITMENT_LEN as u8 {
-            return Err(ValidationError::IncorrectLength);
+/// The data passed to [`TokenScheme::validate`] is the public key hash followed by the address
+/// metadata hash, and the [`Mandate::default`] is applied.
+///
+/// [`TokenScheme::construct`] expects the raw token, as produced by [`construct_token_raw`].
+#[async_trait]
+impl<C> TokenScheme for ChainCommitmentScheme<C>
+where
+    C: BitcoinClient + Send + Sync,
+{
+    type Error = ValidationError;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        if data.len() != TOKEN_LEN {
+            return Err(ValidationError::TokenLength);
         }
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        Ok(base64::encode_config(data, url_safe_config))
+    }
 
-        // Check commitment
-        let commitment = &raw_script[2..34];
-        let expected_commitment = construct_commitment(pub_key_hash, address_metadata_hash);
-        if expected_commitment != commitment {
-            return Err(ValidationError::Invalid);
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        // The commitment is the digest of the public key hash and address metadata hash
+        let mut sha256_context = Context::new(&SHA256);
+        sha256_context.update(data);
+        let commitment = sha256_context.finish();
+        self.validate_commitment(commitment.as_ref(), token, &Mandate::default())
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_bitcoin::{
+        transaction::{output::Output, script::opcodes::OP_RETURN},
+        Encodable,
+    };
+
+    use super::*;
+
+    fn transaction(burned: u64, paid: &[u64]) -> Transaction {
+        let commitment = construct_commitment(b"pub key hash", b"metadata hash");
+        let mut outputs = vec![Output {
+            value: burned,
+            script: Script::from([&[OP_RETURN, COMMITMENT_LEN as u8][..], &commitment].concat()),
+        }];
+        outputs.extend(paid.iter().map(|value| Output {
+            value: *value,
+            script: Script::from(vec![1, 2, 3]),
+        }));
+        Transaction {
+            outputs,
+            ..Default::default()
         }
-        Ok(outpoint_raw)
+    }
+
+    #[test]
+    fn mandated() {
+        let burn = Mandate::Burn(500);
+        assert!(burn.check(&transaction(500, &[]), 0).is_ok());
+        assert!(matches!(
+            burn.check(&transaction(499, &[1000]), 0),
+            Err(ValidationError::InsufficientAmount {
+                required: 500,
+                committed: 499
+            })
+        ));
+
+        let pay = Mandate::Pay {
+            script: Script::from(vec![1, 2, 3]),
+            amount: 500,
+        };
+        assert!(pay.check(&transaction(0, &[200, 300]), 0).is_ok());
+        assert!(pay.check(&transaction(1000, &[200]), 0).is_err());
+        assert!(Mandate::default().check(&transaction(0, &[]), 0).is_ok());
+    }
+
+    struct MockClient(Transaction);
+
+    #[async_trait]
+    impl BitcoinClient for MockClient {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: cashweb_bitcoin_client::FeePolicy,
+        ) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            let mut raw_transaction = Vec::with_capacity(self.0.encoded_len());
+            self.0.encode_raw(&mut raw_transaction);
+            Ok(raw_transaction)
+        }
+    }
+
+    #[tokio::test]
+    async fn token_scheme() {
+        let scheme = ChainCommitmentScheme::from_client(MockClient(transaction(0, &[])));
+        let token = scheme
+            .construct(&construct_token_raw(&[0; 32], 0))
+            .await
+            .unwrap();
+
+        assert!(scheme
+            .validate(b"pub key hashmetadata hash", &token)
+            .await
+            .is_ok());
+        assert!(matches!(
+            scheme
+                .validate(b"other key hashmetadata hash", &token)
+                .await,
+            Err(ValidationError::Invalid)
+        ));
+        assert!(matches!(
+            scheme.construct(&[0; 32]).await,
+            Err(ValidationError::TokenLength)
+        ));
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        use cashweb_bitcoin::block::Block;
+
+        // bitcoind serves a transaction burning too little
+        let cache = UtxoCache::new(10);
+        let scheme = ChainCommitmentScheme::from_client(MockClient(transaction(0, &[])))
+            .with_cache(cache.clone());
+        let confirmed = transaction(1_000, &[]);
+        let mut tx_id = confirmed.transaction_id();
+        tx_id.reverse();
+        let token = construct_token(&tx_id, 0);
+        let mandate = Mandate::Burn(1_000);
+        assert!(scheme
+            .validate_token_mandated(b"pub key hash", b"metadata hash", &token, &mandate)
+            .await
+            .is_err());
+
+        // Once confirmed, the transaction is taken from the cache
+        cache.connect(&Block {
+            transactions: vec![Transaction::default(), confirmed],
+            ..Default::default()
+        });
+        assert!(scheme
+            .validate_token_mandated(b"pub key hash", b"metadata hash", &token, &mandate)
+            .await
+            .is_ok());
     }
 }
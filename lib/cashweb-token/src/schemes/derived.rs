@@ -0,0 +1,114 @@
+//! This module contains [`DerivedScheme`] which provides HMAC tokens keyed per address.
+//!
+//! Each address's key is derived from a master seed using HKDF-SHA256, with the address as the
+//! info parameter. Replicas configured with the same seed can therefore validate each other's
+//! tokens without sharing any further state.
+
+use async_trait::async_trait;
+use ring::{hkdf, hmac};
+
+use super::{
+    hmac_bearer::{MalformedError, ValidationError},
+    TokenScheme,
+};
+use crate::{
+    encoding::{decode_any, TokenEncoding},
+    keys::SecretKey,
+};
+
+/// Salt separating keys derived by [`DerivedScheme`] from other uses of the seed.
+const SALT: &[u8] = b"cashweb-token 2021 derived";
+
+/// Length of the HMAC-SHA256 tag forming a token.
+const TAG_LEN: usize = 32;
+
+/// HMAC token scheme with per-address keys derived from a master seed.
+#[derive(Debug)]
+pub struct DerivedScheme {
+    seed: SecretKey,
+}
+
+impl DerivedScheme {
+    /// Create a new derived scheme from a master seed.
+    pub fn new(seed: SecretKey) -> Self {
+        Self { seed }
+    }
+
+    fn address_key(&self, address: &[u8]) -> hmac::Key {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, SALT).extract(self.seed.as_bytes());
+        let info = [address];
+        // This is safe as the output length is far below the HKDF limit
+        let okm = prk.expand(&info, hmac::HMAC_SHA256).unwrap();
+        hmac::Key::from(okm)
+    }
+
+    /// Construct a token for the data, keyed by the address.
+    pub fn construct_token(&self, address: &[u8], data: &[u8]) -> String {
+        let tag = hmac::sign(&self.address_key(address), data);
+        TokenEncoding::UrlSafe.encode(tag.as_ref())
+    }
+
+    /// Validate a token for the data, keyed by the address.
+    pub fn validate_token(
+        &self,
+        address: &[u8],
+        data: &[u8],
+        token: &str,
+    ) -> Result<(), ValidationError> {
+        let tags: Vec<_> = decode_any(token)
+            .map_err(|err| ValidationError::Malformed(MalformedError::Base64(err)))?
+            .into_iter()
+            .filter(|tag| tag.len() == TAG_LEN)
+            .collect();
+        if tags.is_empty() {
+            return Err(ValidationError::Malformed(MalformedError::Length));
+        }
+
+        let key = self.address_key(address);
+        if tags.iter().any(|tag| hmac::verify(&key, data, tag).is_ok()) {
+            Ok(())
+        } else {
+            Err(ValidationError::Invalid)
+        }
+    }
+}
+
+/// Tokens are keyed by, and constructed over, the data, which is expected to be the address
+/// payload.
+#[async_trait]
+impl TokenScheme for DerivedScheme {
+    type Error = ValidationError;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        Ok(self.construct_token(data, data))
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.validate_token(data, data, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replicas() {
+        let seed = SecretKey::from(&b"master seed"[..]);
+        let replica_a = DerivedScheme::new(seed.clone());
+        let replica_b = DerivedScheme::new(seed);
+
+        let token = replica_a.construct_token(b"alice", b"data");
+        assert_eq!(replica_b.validate_token(b"alice", b"data", &token), Ok(()));
+        assert_eq!(
+            replica_b.validate_token(b"bob", b"data", &token),
+            Err(ValidationError::Invalid)
+        );
+
+        let other = DerivedScheme::new(SecretKey::from(&b"other seed"[..]));
+        assert_eq!(
+            other.validate_token(b"alice", b"data", &token),
+            Err(ValidationError::Invalid)
+        );
+    }
+}
@@ -1,43 +1,447 @@
 //! This module contains [`HmacScheme`] which provides a rudimentary HMAC validation scheme.
+//!
+//! Tokens using [`MacAlgorithm::HmacSha256`] carry no algorithm identifier, matching the original
+//! wire format. Tokens using other algorithms are prefixed by the algorithm identifier, allowing
+//! deployments to migrate hash functions while continuing to accept existing tokens.
+//!
+//! Expiring tokens are authenticated under a key derived from the secret, separate from the key
+//! authenticating plain tokens, so that a plain token over attacker-chosen data can never be
+//! passed off as an expiring token carrying attacker-chosen timestamps.
 
+use std::{
+    convert::TryInto,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
 use ring::hmac;
 use thiserror::Error;
+#[cfg(feature = "blake3")]
+use zeroize::Zeroizing;
 
-/// Error associated with basic HMAC token validation.
+use super::{ErrorKind, TokenError, TokenScheme};
+use crate::{
+    encoding::{decode_any, TokenEncoding},
+    keys::SecretKey,
+};
+
+/// Length of the timestamps prefixed to an expiring token.
+const TIMESTAMPS_LEN: usize = 16;
+
+/// Context from which the key authenticating expiring tokens is derived.
+const EXPIRING_CONTEXT: &[u8] = b"cashweb-token 2021 hmac_bearer expiring";
+
+/// Error associated with parsing a token.
 #[derive(Debug, Error, PartialEq, Eq)]
-pub enum ValidationError {
+pub enum MalformedError {
     /// Failed to decode token.
     #[error("failed to decode token: {0}")]
     Base64(base64::DecodeError),
+    /// Token had an unexpected length.
+    #[error("unexpected token length")]
+    Length,
+}
+
+/// Error associated with basic HMAC token validation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Token could not be parsed.
+    #[error("malformed token: {0}")]
+    Malformed(MalformedError),
+    /// Token has expired.
+    #[error("token expired")]
+    Expired,
+    /// Token was issued for a different resource.
+    #[error("token not valid for this resource")]
+    WrongScope,
     /// Token was invalid.
     #[error("invalid token")]
     Invalid,
 }
 
+impl TokenError for ValidationError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Malformed(_) => ErrorKind::Malformed,
+            Self::Expired => ErrorKind::Expired,
+            Self::WrongScope => ErrorKind::WrongScope,
+            Self::Invalid => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// Enumeration of MAC algorithms supported by [`HmacScheme`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacAlgorithm {
+    /// HMAC-SHA256.
+    HmacSha256,
+    /// HMAC-SHA512.
+    HmacSha512,
+    /// Keyed BLAKE3.
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl MacAlgorithm {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::HmacSha512),
+            #[cfg(feature = "blake3")]
+            2 => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Length of the tag produced by the algorithm.
+    fn tag_len(self) -> usize {
+        match self {
+            Self::HmacSha256 => 32,
+            Self::HmacSha512 => 64,
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => blake3::OUT_LEN,
+        }
+    }
+
+    /// Prefix identifying the algorithm.
+    fn prefix(self) -> &'static [u8] {
+        match self {
+            Self::HmacSha256 => &[],
+            Self::HmacSha512 => &[1],
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => &[2],
+        }
+    }
+}
+
+/// Split a raw token into its algorithm and body, the body being `body_len` bytes plus the tag.
+fn split_algorithm(raw_token: &[u8], body_len: usize) -> Option<(MacAlgorithm, &[u8])> {
+    let legacy = MacAlgorithm::HmacSha256;
+    if raw_token.len() == body_len + legacy.tag_len() {
+        return Some((legacy, raw_token));
+    }
+    let (id, rest) = raw_token.split_first()?;
+    let algorithm = MacAlgorithm::from_id(*id)?;
+    if rest.len() != body_len + algorithm.tag_len() {
+        return None;
+    }
+    Some((algorithm, rest))
+}
+
+/// Decode a token and split each candidate decoding into its algorithm and body.
+fn decode_candidates(
+    token: &str,
+    body_len: usize,
+) -> Result<Vec<(MacAlgorithm, Vec<u8>)>, ValidationError> {
+    let candidates: Vec<_> = decode_any(token)
+        .map_err(|err| ValidationError::Malformed(MalformedError::Base64(err)))?
+        .iter()
+        .filter_map(|raw_token| {
+            split_algorithm(raw_token, body_len).map(|(algorithm, body)| (algorithm, body.to_vec()))
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(ValidationError::Malformed(MalformedError::Length));
+    }
+    Ok(candidates)
+}
+
+/// Form of token, each authenticated under its own key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Form {
+    Plain,
+    Expiring,
+}
+
+/// Secret keys from which the MAC keys are derived on use, so that only the zeroizing
+/// [`SecretKey`]s are retained.
+#[derive(Debug)]
+struct Keys {
+    plain: SecretKey,
+    expiring: SecretKey,
+}
+
+impl Keys {
+    fn new(secret: SecretKey) -> Self {
+        let expiring = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            EXPIRING_CONTEXT,
+        );
+        Self {
+            plain: secret,
+            expiring: SecretKey::from(expiring.as_ref()),
+        }
+    }
+
+    fn sign(&self, form: Form, algorithm: MacAlgorithm, message: &[&[u8]]) -> Vec<u8> {
+        let secret = match form {
+            Form::Plain => &self.plain,
+            Form::Expiring => &self.expiring,
+        };
+        let sign_hmac = |algorithm| {
+            let key = hmac::Key::new(algorithm, secret.as_bytes());
+            let mut context = hmac::Context::with_key(&key);
+            for part in message {
+                context.update(part);
+            }
+            context.sign().as_ref().to_vec()
+        };
+        match algorithm {
+            MacAlgorithm::HmacSha256 => sign_hmac(hmac::HMAC_SHA256),
+            MacAlgorithm::HmacSha512 => sign_hmac(hmac::HMAC_SHA512),
+            #[cfg(feature = "blake3")]
+            MacAlgorithm::Blake3 => {
+                let key = Zeroizing::new(blake3::derive_key(
+                    "cashweb-token 2021 hmac_bearer",
+                    secret.as_bytes(),
+                ));
+                let mut hasher = blake3::Hasher::new_keyed(&key);
+                for part in message {
+                    hasher.update(part);
+                }
+                hasher.finalize().as_bytes().to_vec()
+            }
+        }
+    }
+
+    fn verify(&self, form: Form, algorithm: MacAlgorithm, message: &[&[u8]], tag: &[u8]) -> bool {
+        ring::constant_time::verify_slices_are_equal(&self.sign(form, algorithm, message), tag)
+            .is_ok()
+    }
+}
+
 /// Basic HMAC token scheme.
 #[derive(Debug)]
 pub struct HmacScheme {
-    key: hmac::Key,
+    keys: Keys,
+    algorithm: MacAlgorithm,
+    encoding: TokenEncoding,
 }
 
 impl HmacScheme {
     /// Create a new HMAC scheme using a speficied secret key.
     pub fn new(key: &[u8]) -> Self {
-        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
-        Self { key }
+        Self::from_secret(SecretKey::from(key))
+    }
+
+    /// Create a new HMAC scheme from a [`SecretKey`], which is zeroed when the scheme is dropped.
+    pub fn from_secret(secret: SecretKey) -> Self {
+        Self {
+            keys: Keys::new(secret),
+            algorithm: MacAlgorithm::HmacSha256,
+            encoding: TokenEncoding::UrlSafe,
+        }
+    }
+
+    /// Set the MAC algorithm of constructed tokens.
+    ///
+    /// Validation accepts tokens using any [`MacAlgorithm`].
+    pub fn with_algorithm(mut self, algorithm: MacAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the encoding of constructed tokens.
+    ///
+    /// Validation accepts tokens in any [`TokenEncoding`].
+    pub fn with_encoding(mut self, encoding: TokenEncoding) -> Self {
+        self.encoding = encoding;
+        self
     }
 
     /// Construct a token.
     pub fn construct_token(&self, data: &[u8]) -> String {
-        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let tag = hmac::sign(&self.key, data);
-        base64::encode_config(tag.as_ref(), url_safe_config)
+        let tag = self.keys.sign(Form::Plain, self.algorithm, &[data]);
+        let raw_token = [self.algorithm.prefix(), &tag].concat();
+        self.encoding.encode(&raw_token)
     }
 
     /// Validate a token.
     pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
-        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        let tag = base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
-        hmac::verify(&self.key, data, &tag).map_err(|_| ValidationError::Invalid)
+        if decode_candidates(token, 0)?
+            .iter()
+            .any(|(algorithm, tag)| self.keys.verify(Form::Plain, *algorithm, &[data], tag))
+        {
+            Ok(())
+        } else {
+            Err(ValidationError::Invalid)
+        }
+    }
+
+    /// Construct a token which expires after `ttl`.
+    ///
+    /// The issue and expiry timestamps are embedded in the token and covered by the HMAC, which
+    /// uses a key distinct from that of [`HmacScheme::construct_token`].
+    pub fn construct_token_expiring(&self, data: &[u8], ttl: Duration) -> String {
+        self.construct_token_expiring_at(data, SystemTime::now(), ttl)
+    }
+
+    /// Construct a token issued at `now` which expires after `ttl`.
+    pub fn construct_token_expiring_at(
+        &self,
+        data: &[u8],
+        now: SystemTime,
+        ttl: Duration,
+    ) -> String {
+        let issued_at = unix_secs(now);
+        let expires_at = issued_at.saturating_add(ttl.as_secs());
+        let mut timestamps = [0; TIMESTAMPS_LEN];
+        timestamps[..8].copy_from_slice(&issued_at.to_be_bytes());
+        timestamps[8..].copy_from_slice(&expires_at.to_be_bytes());
+
+        let tag = self
+            .keys
+            .sign(Form::Expiring, self.algorithm, &[&timestamps, data]);
+        let raw_token = [self.algorithm.prefix(), &timestamps, &tag].concat();
+        self.encoding.encode(&raw_token)
+    }
+
+    /// Validate a token constructed by [`HmacScheme::construct_token_expiring`] at a given time.
+    ///
+    /// Returns [`ValidationError::Expired`] if the token is authentic but has expired.
+    pub fn validate_token_at(
+        &self,
+        data: &[u8],
+        token: &str,
+        now: SystemTime,
+    ) -> Result<(), ValidationError> {
+        let timestamps = decode_candidates(token, TIMESTAMPS_LEN)?
+            .into_iter()
+            .find_map(|(algorithm, mut body)| {
+                let tag = body.split_off(TIMESTAMPS_LEN);
+                if self
+                    .keys
+                    .verify(Form::Expiring, algorithm, &[&body, data], &tag)
+                {
+                    Some(body)
+                } else {
+                    None
+                }
+            })
+            .ok_or(ValidationError::Invalid)?;
+
+        let expires_at = u64::from_be_bytes(timestamps[8..].try_into().unwrap()); // This is safe as the length is checked
+        if unix_secs(now) >= expires_at {
+            return Err(ValidationError::Expired);
+        }
+        Ok(())
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl TokenScheme for HmacScheme {
+    type Error = ValidationError;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        Ok(self.construct_token(data))
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.validate_token(data, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiring_token() {
+        let scheme = HmacScheme::new(b"secret");
+        let now = SystemTime::now();
+        let token = scheme.construct_token_expiring_at(b"data", now, Duration::from_secs(60));
+
+        assert_eq!(scheme.validate_token_at(b"data", &token, now), Ok(()));
+        assert_eq!(
+            scheme.validate_token_at(b"other", &token, now),
+            Err(ValidationError::Invalid)
+        );
+        assert_eq!(
+            scheme.validate_token_at(b"data", &token, now + Duration::from_secs(61)),
+            Err(ValidationError::Expired)
+        );
+        assert_eq!(
+            scheme.validate_token_at(b"data", "AAAA", now),
+            Err(ValidationError::Malformed(MalformedError::Length))
+        );
+        assert_eq!(
+            scheme
+                .validate_token_at(b"data", &token, now + Duration::from_secs(61))
+                .unwrap_err()
+                .kind()
+                .status(),
+            http::StatusCode::PAYMENT_REQUIRED
+        );
+    }
+
+    #[test]
+    fn plain_token_is_not_expiring() {
+        let scheme = HmacScheme::new(b"secret");
+        let now = SystemTime::now();
+        let mut forged = vec![0; 8];
+        forged.extend_from_slice(&u64::MAX.to_be_bytes());
+        let tag = decode_any(&scheme.construct_token(&[&forged[..], b"data"].concat())).unwrap()[0]
+            .clone();
+        let token = TokenEncoding::UrlSafe.encode(&[forged, tag].concat());
+
+        assert_eq!(
+            scheme.validate_token_at(b"data", &token, now),
+            Err(ValidationError::Invalid)
+        );
+    }
+
+    #[test]
+    fn encodings() {
+        let issuer = HmacScheme::new(b"secret").with_encoding(TokenEncoding::Standard);
+        let token = issuer.construct_token(b"data");
+        assert!(token.ends_with('='));
+        assert_eq!(
+            HmacScheme::new(b"secret").validate_token(b"data", &token),
+            Ok(())
+        );
+
+        let issuer = HmacScheme::new(b"secret").with_encoding(TokenEncoding::Hex);
+        let token = issuer.construct_token_expiring(b"data", Duration::from_secs(60));
+        assert_eq!(
+            HmacScheme::new(b"secret").validate_token_at(b"data", &token, SystemTime::now()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn algorithms() {
+        let legacy = HmacScheme::new(b"secret");
+        let migrated = HmacScheme::new(b"secret").with_algorithm(MacAlgorithm::HmacSha512);
+
+        let token = legacy.construct_token(b"data");
+        assert_eq!(migrated.validate_token(b"data", &token), Ok(()));
+        let token = migrated.construct_token(b"data");
+        assert_eq!(legacy.validate_token(b"data", &token), Ok(()));
+        assert_eq!(
+            legacy.validate_token(b"other", &token),
+            Err(ValidationError::Invalid)
+        );
+
+        let now = SystemTime::now();
+        let token = migrated.construct_token_expiring_at(b"data", now, Duration::from_secs(60));
+        assert_eq!(legacy.validate_token_at(b"data", &token, now), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn token_scheme() {
+        let scheme: Box<dyn TokenScheme<Error = ValidationError> + Send + Sync> =
+            Box::new(HmacScheme::new(b"secret"));
+        let token = scheme.construct(b"data").await.unwrap();
+
+        assert_eq!(scheme.validate(b"data", &token).await, Ok(()));
+        assert_eq!(
+            scheme.validate(b"other", &token).await,
+            Err(ValidationError::Invalid)
+        );
     }
 }
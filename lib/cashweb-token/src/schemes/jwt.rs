@@ -0,0 +1,299 @@
+//! This module contains [`JwtScheme`] which provides JSON Web Tokens, signed using either HS256 or
+//! ES256, for interoperability with third-party services.
+//!
+//! The token data is carried, URL safe base64 encoded, in the `sub` claim.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ring::{
+    hmac,
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+        ECDSA_P256_SHA256_FIXED_SIGNING,
+    },
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError, TokenScheme};
+
+pub use ring::error::KeyRejected;
+
+/// Error associated with JWT validation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Token did not consist of three segments.
+    #[error("malformed token")]
+    Malformed,
+    /// Failed to decode a segment.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Failed to deserialize the header or claims.
+    #[error("failed to deserialize token: {0}")]
+    Json(String),
+    /// Token was signed with an unexpected algorithm.
+    #[error("unexpected algorithm {0}")]
+    Algorithm(String),
+    /// Token signature was invalid.
+    #[error("invalid token")]
+    Invalid,
+    /// Token was issued for different data.
+    #[error("subject mismatch")]
+    SubjectMismatch,
+    /// Token has expired.
+    #[error("token expired")]
+    Expired,
+}
+
+impl TokenError for ValidationError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Malformed | Self::Base64(_) | Self::Json(_) => ErrorKind::Malformed,
+            Self::Algorithm(_) | Self::Invalid => ErrorKind::Invalid,
+            Self::SubjectMismatch => ErrorKind::WrongScope,
+            Self::Expired => ErrorKind::Expired,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Header {
+    alg: String,
+    typ: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Claims {
+    sub: String,
+    iat: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+}
+
+enum Algorithm {
+    Hs256(hmac::Key),
+    Es256 {
+        key_pair: EcdsaKeyPair,
+        rng: SystemRandom,
+    },
+}
+
+impl std::fmt::Debug for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hs256(key) => f.debug_tuple("Hs256").field(key).finish(),
+            Self::Es256 { key_pair, .. } => f.debug_tuple("Es256").field(key_pair).finish(),
+        }
+    }
+}
+
+impl Algorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Hs256(_) => "HS256",
+            Self::Es256 { .. } => "ES256",
+        }
+    }
+}
+
+fn url_safe_config() -> base64::Config {
+    base64::Config::new(base64::CharacterSet::UrlSafe, false)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// JSON Web Token scheme.
+#[derive(Debug)]
+pub struct JwtScheme {
+    algorithm: Algorithm,
+    ttl: Option<Duration>,
+}
+
+impl JwtScheme {
+    /// Create a new HS256 scheme using a specified secret key.
+    pub fn hs256(key: &[u8]) -> Self {
+        Self {
+            algorithm: Algorithm::Hs256(hmac::Key::new(hmac::HMAC_SHA256, key)),
+            ttl: None,
+        }
+    }
+
+    /// Create a new ES256 scheme using a PKCS#8 encoded P-256 key pair.
+    pub fn es256(pkcs8: &[u8]) -> Result<Self, KeyRejected> {
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8)?;
+        Ok(Self {
+            algorithm: Algorithm::Es256 {
+                key_pair,
+                rng: SystemRandom::new(),
+            },
+            ttl: None,
+        })
+    }
+
+    /// Set the duration after which constructed tokens expire.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// The public key of an ES256 scheme, for distribution to third-party verifiers.
+    pub fn public_key(&self) -> Option<&[u8]> {
+        match &self.algorithm {
+            Algorithm::Hs256(_) => None,
+            Algorithm::Es256 { key_pair, .. } => Some(key_pair.public_key().as_ref()),
+        }
+    }
+
+    /// Construct a token.
+    pub fn construct_token(&self, data: &[u8]) -> String {
+        self.construct_token_at(data, SystemTime::now())
+    }
+
+    /// Construct a token issued at `now`.
+    pub fn construct_token_at(&self, data: &[u8], now: SystemTime) -> String {
+        let header = Header {
+            alg: self.algorithm.name().to_string(),
+            typ: "JWT".to_string(),
+        };
+        let issued_at = unix_secs(now);
+        let claims = Claims {
+            sub: base64::encode_config(data, url_safe_config()),
+            iat: issued_at,
+            exp: self.ttl.map(|ttl| issued_at.saturating_add(ttl.as_secs())),
+        };
+        let header = serde_json::to_vec(&header).unwrap(); // This is safe as serialization is infallible
+        let claims = serde_json::to_vec(&claims).unwrap(); // This is safe as serialization is infallible
+        let signing_input = format!(
+            "{}.{}",
+            base64::encode_config(header, url_safe_config()),
+            base64::encode_config(claims, url_safe_config())
+        );
+
+        let signature = match &self.algorithm {
+            Algorithm::Hs256(key) => hmac::sign(key, signing_input.as_bytes()).as_ref().to_vec(),
+            Algorithm::Es256 { key_pair, rng } => key_pair
+                .sign(rng, signing_input.as_bytes())
+                .expect("system random number generator unavailable")
+                .as_ref()
+                .to_vec(),
+        };
+        format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(signature, url_safe_config())
+        )
+    }
+
+    /// Validate a token.
+    pub fn validate_token(&self, data: &[u8], token: &str) -> Result<(), ValidationError> {
+        self.validate_token_at(data, token, SystemTime::now())
+    }
+
+    /// Validate a token at a given time.
+    pub fn validate_token_at(
+        &self,
+        data: &[u8],
+        token: &str,
+        now: SystemTime,
+    ) -> Result<(), ValidationError> {
+        let mut segments = token.rsplitn(2, '.');
+        let signature = segments.next().ok_or(ValidationError::Malformed)?;
+        let signing_input = segments.next().ok_or(ValidationError::Malformed)?;
+        let mut parts = signing_input.splitn(2, '.');
+        let header = parts.next().ok_or(ValidationError::Malformed)?;
+        let claims = parts.next().ok_or(ValidationError::Malformed)?;
+        let decode = |segment| {
+            base64::decode_config(segment, url_safe_config()).map_err(ValidationError::Base64)
+        };
+
+        // Check header
+        let header: Header = serde_json::from_slice(&decode(header)?)
+            .map_err(|err| ValidationError::Json(err.to_string()))?;
+        if header.alg != self.algorithm.name() {
+            return Err(ValidationError::Algorithm(header.alg));
+        }
+
+        // Check signature
+        let signature = decode(signature)?;
+        match &self.algorithm {
+            Algorithm::Hs256(key) => hmac::verify(key, signing_input.as_bytes(), &signature),
+            Algorithm::Es256 { key_pair, .. } => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key_pair.public_key().as_ref())
+                    .verify(signing_input.as_bytes(), &signature)
+            }
+        }
+        .map_err(|_| ValidationError::Invalid)?;
+
+        // Check claims
+        let claims: Claims = serde_json::from_slice(&decode(claims)?)
+            .map_err(|err| ValidationError::Json(err.to_string()))?;
+        if claims.sub != base64::encode_config(data, url_safe_config()) {
+            return Err(ValidationError::SubjectMismatch);
+        }
+        if let Some(expires_at) = claims.exp {
+            if unix_secs(now) >= expires_at {
+                return Err(ValidationError::Expired);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TokenScheme for JwtScheme {
+    type Error = ValidationError;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        Ok(self.construct_token(data))
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.validate_token(data, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hs256() {
+        let scheme = JwtScheme::hs256(b"secret").with_ttl(Duration::from_secs(60));
+        let now = SystemTime::now();
+        let token = scheme.construct_token_at(b"data", now);
+
+        assert_eq!(scheme.validate_token_at(b"data", &token, now), Ok(()));
+        assert_eq!(
+            scheme.validate_token_at(b"other", &token, now),
+            Err(ValidationError::SubjectMismatch)
+        );
+        assert_eq!(
+            scheme.validate_token_at(b"data", &token, now + Duration::from_secs(60)),
+            Err(ValidationError::Expired)
+        );
+        assert_eq!(
+            JwtScheme::hs256(b"other").validate_token_at(b"data", &token, now),
+            Err(ValidationError::Invalid)
+        );
+    }
+
+    #[test]
+    fn es256() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let scheme = JwtScheme::es256(pkcs8.as_ref()).unwrap();
+        let token = scheme.construct_token(b"data");
+
+        assert_eq!(scheme.validate_token(b"data", &token), Ok(()));
+        assert_eq!(
+            JwtScheme::hs256(b"secret").validate_token(b"data", &token),
+            Err(ValidationError::Algorithm("ES256".to_string()))
+        );
+    }
+}
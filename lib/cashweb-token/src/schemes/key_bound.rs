@@ -0,0 +1,176 @@
+//! This module contains [`KeyBoundScheme`] which binds tokens to a client's public key.
+//!
+//! The token carries the client's secp256k1 public key, covered by the inner scheme, and each
+//! request must be accompanied by a signature over the [`request_digest`] by that key. A stolen
+//! token is therefore useless without the client's secret key.
+
+use std::{convert::TryInto, fmt};
+
+use ring::digest::{Context, SHA256};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Error as SecpError, Message, Secp256k1, Signature,
+};
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError, TokenScheme};
+
+/// Calculate the digest of a request, which the client signs to prove possession of the key.
+pub fn request_digest(method: &str, path: &str, body: &[u8]) -> [u8; 32] {
+    let mut context = Context::new(&SHA256);
+    context.update(method.as_bytes());
+    context.update(&[0]);
+    context.update(path.as_bytes());
+    context.update(&[0]);
+    context.update(body);
+    context.finish().as_ref().try_into().unwrap() // This is safe as SHA256 digests are 32 bytes
+}
+
+/// Sign a request digest, producing the signature accompanying a key bound token.
+pub fn sign_request(secret_key: &SecretKey, digest: &[u8; 32]) -> Signature {
+    let message = Message::from_slice(digest).unwrap(); // This is safe as the length is 32
+    Secp256k1::signing_only().sign(&message, secret_key)
+}
+
+/// Error associated with key bound token validation.
+#[derive(Debug, Error)]
+pub enum KeyBoundError<E: fmt::Debug + fmt::Display> {
+    /// Token did not carry a public key.
+    #[error("malformed token")]
+    Malformed,
+    /// Failed to decode the public key.
+    #[error("failed to decode public key: {0}")]
+    Base64(base64::DecodeError),
+    /// Failed to parse the public key.
+    #[error("invalid public key: {0}")]
+    PublicKey(SecpError),
+    /// The inner scheme rejected the token.
+    #[error(transparent)]
+    Token(E),
+    /// The request signature was invalid.
+    #[error("invalid request signature: {0}")]
+    Signature(SecpError),
+}
+
+impl<E: TokenError> TokenError for KeyBoundError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Malformed | Self::Base64(_) | Self::PublicKey(_) => ErrorKind::Malformed,
+            Self::Token(err) => err.kind(),
+            Self::Signature(_) => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// A token scheme binding tokens to a client public key.
+#[derive(Debug)]
+pub struct KeyBoundScheme<T> {
+    inner: T,
+}
+
+impl<T> KeyBoundScheme<T> {
+    /// Wrap a [`TokenScheme`], binding its tokens to client public keys.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Converts the key bound scheme into the underlying scheme.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+fn bind(public_key: &[u8], data: &[u8]) -> Vec<u8> {
+    [public_key, data].concat()
+}
+
+impl<T: TokenScheme> KeyBoundScheme<T> {
+    /// Construct a token for the data, bound to the client's public key.
+    pub async fn construct_token(
+        &self,
+        public_key: &PublicKey,
+        data: &[u8],
+    ) -> Result<String, KeyBoundError<T::Error>> {
+        let raw_public_key = public_key.serialize();
+        let token = self
+            .inner
+            .construct(&bind(&raw_public_key, data))
+            .await
+            .map_err(KeyBoundError::Token)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        Ok(format!(
+            "{}.{}",
+            base64::encode_config(raw_public_key, url_safe_config),
+            token
+        ))
+    }
+
+    /// Validate a token and the signature over the request digest by its bound public key.
+    ///
+    /// Returns the bound public key.
+    pub async fn validate_token(
+        &self,
+        data: &[u8],
+        token: &str,
+        digest: &[u8; 32],
+        signature: &Signature,
+    ) -> Result<PublicKey, KeyBoundError<T::Error>> {
+        let mut parts = token.splitn(2, '.');
+        let raw_public_key = parts.next().ok_or(KeyBoundError::Malformed)?;
+        let token = parts.next().ok_or(KeyBoundError::Malformed)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_public_key = base64::decode_config(raw_public_key, url_safe_config)
+            .map_err(KeyBoundError::Base64)?;
+        let public_key =
+            PublicKey::from_slice(&raw_public_key).map_err(KeyBoundError::PublicKey)?;
+
+        // Check token
+        self.inner
+            .validate(&bind(&raw_public_key, data), token)
+            .await
+            .map_err(KeyBoundError::Token)?;
+
+        // Check request signature
+        let message = Message::from_slice(digest).unwrap(); // This is safe as the length is 32
+        Secp256k1::verification_only()
+            .verify(&message, signature, &public_key)
+            .map_err(KeyBoundError::Signature)?;
+
+        Ok(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemes::hmac_bearer::HmacScheme;
+
+    #[tokio::test]
+    async fn bound() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let thief_key = SecretKey::from_slice(&[2; 32]).unwrap();
+
+        let scheme = KeyBoundScheme::new(HmacScheme::new(b"secret"));
+        let token = scheme.construct_token(&public_key, b"data").await.unwrap();
+        let digest = request_digest("PUT", "/profiles/alice", b"body");
+
+        let signature = sign_request(&secret_key, &digest);
+        assert_eq!(
+            scheme
+                .validate_token(b"data", &token, &digest, &signature)
+                .await
+                .unwrap(),
+            public_key
+        );
+
+        let signature = sign_request(&thief_key, &digest);
+        assert!(matches!(
+            scheme
+                .validate_token(b"data", &token, &digest, &signature)
+                .await,
+            Err(KeyBoundError::Signature(_))
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+//! This module contains [`MacaroonScheme`] which provides chained-HMAC tokens supporting caveat
+//! attenuation.
+//!
+//! The holder of a [`Macaroon`] may append [`Caveat`]s to it, producing a more restricted token,
+//! without contacting the issuer. The verifier enforces every caveat.
+
+use std::{
+    convert::TryInto,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::{constant_time::verify_slices_are_equal, hmac};
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError};
+
+const SIGNATURE_LEN: usize = 32;
+
+const EXPIRES_AT_TAG: u8 = 0;
+const ADDRESS_TAG: u8 = 1;
+
+/// A restriction placed on a [`Macaroon`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Caveat {
+    /// The token expires at the given UNIX timestamp, in seconds.
+    ExpiresAt(u64),
+    /// The token is only valid for the given address payload.
+    Address(Vec<u8>),
+}
+
+impl Caveat {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::ExpiresAt(expires_at) => {
+                buf.push(EXPIRES_AT_TAG);
+                buf.extend_from_slice(&expires_at.to_be_bytes());
+            }
+            Self::Address(address) => {
+                buf.push(ADDRESS_TAG);
+                buf.extend_from_slice(&(address.len() as u16).to_be_bytes());
+                buf.extend_from_slice(address);
+            }
+        }
+    }
+
+    fn decode(buf: &mut &[u8]) -> Result<Self, DecodeError> {
+        let (tag, rest) = buf.split_first().ok_or(DecodeError::TooShort)?;
+        *buf = rest;
+        match *tag {
+            EXPIRES_AT_TAG => {
+                let expires_at = take(buf, 8)?;
+                Ok(Self::ExpiresAt(u64::from_be_bytes(
+                    expires_at.try_into().unwrap(), // This is safe as the length is checked
+                )))
+            }
+            ADDRESS_TAG => Ok(Self::Address(take_prefixed(buf)?.to_vec())),
+            tag => Err(DecodeError::UnknownCaveat(tag)),
+        }
+    }
+
+    fn raw(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        self.encode(&mut raw);
+        raw
+    }
+}
+
+/// Error associated with decoding a [`Macaroon`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Failed to decode base64.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Token was too short.
+    #[error("token too short")]
+    TooShort,
+    /// Token contained an unknown caveat.
+    #[error("unknown caveat {0}")]
+    UnknownCaveat(u8),
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if buf.len() < len {
+        return Err(DecodeError::TooShort);
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+fn take_prefixed<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+    let len = take(buf, 2)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    take(buf, len)
+}
+
+/// A chained-HMAC token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Macaroon {
+    identifier: Vec<u8>,
+    caveats: Vec<Caveat>,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+fn chain(signature: &[u8], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, signature);
+    hmac::sign(&key, message).as_ref().try_into().unwrap() // This is safe as SHA256 digests are 32 bytes
+}
+
+impl Macaroon {
+    /// The identifier given by the issuer.
+    pub fn identifier(&self) -> &[u8] {
+        &self.identifier
+    }
+
+    /// The caveats restricting the token.
+    pub fn caveats(&self) -> &[Caveat] {
+        &self.caveats
+    }
+
+    /// Append a caveat, restricting the token further.
+    pub fn attenuate(mut self, caveat: Caveat) -> Self {
+        self.signature = chain(&self.signature, &caveat.raw());
+        self.caveats.push(caveat);
+        self
+    }
+
+    /// Encode the token as a URL safe base64 string.
+    pub fn encode(&self) -> String {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(self.identifier.len() as u16).to_be_bytes());
+        raw.extend_from_slice(&self.identifier);
+        raw.extend_from_slice(&(self.caveats.len() as u16).to_be_bytes());
+        for caveat in &self.caveats {
+            caveat.encode(&mut raw);
+        }
+        raw.extend_from_slice(&self.signature);
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        base64::encode_config(raw, url_safe_config)
+    }
+
+    /// Decode a token from a URL safe base64 string.
+    pub fn decode(token: &str) -> Result<Self, DecodeError> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw = base64::decode_config(token, url_safe_config).map_err(DecodeError::Base64)?;
+        let mut buf = raw.as_slice();
+
+        let identifier = take_prefixed(&mut buf)?.to_vec();
+        let n_caveats = take(&mut buf, 2)?;
+        let n_caveats = u16::from_be_bytes([n_caveats[0], n_caveats[1]]);
+        let caveats = (0..n_caveats)
+            .map(|_| Caveat::decode(&mut buf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let signature = take(&mut buf, SIGNATURE_LEN)?.try_into().unwrap(); // This is safe as the length is checked
+
+        Ok(Self {
+            identifier,
+            caveats,
+            signature,
+        })
+    }
+}
+
+/// Error associated with [`Macaroon`] validation.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Failed to decode token.
+    #[error(transparent)]
+    Decode(DecodeError),
+    /// Token signature was invalid.
+    #[error("invalid token")]
+    Invalid,
+    /// Token has expired.
+    #[error("token expired")]
+    Expired,
+    /// Token is not valid for the address.
+    #[error("address mismatch")]
+    AddressMismatch,
+}
+
+impl TokenError for ValidationError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Decode(_) => ErrorKind::Malformed,
+            Self::Invalid => ErrorKind::Invalid,
+            Self::Expired => ErrorKind::Expired,
+            Self::AddressMismatch => ErrorKind::WrongScope,
+        }
+    }
+}
+
+/// The context in which a [`Macaroon`] is being used, against which caveats are checked.
+#[derive(Clone, Copy, Debug)]
+pub struct Context<'a> {
+    /// The current time.
+    pub now: SystemTime,
+    /// The address payload being accessed.
+    pub address: &'a [u8],
+}
+
+/// Macaroon token scheme.
+#[derive(Debug)]
+pub struct MacaroonScheme {
+    key: hmac::Key,
+}
+
+impl MacaroonScheme {
+    /// Create a new macaroon scheme using a specified root key.
+    pub fn new(key: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        Self { key }
+    }
+
+    fn root_signature(&self, identifier: &[u8]) -> [u8; SIGNATURE_LEN] {
+        hmac::sign(&self.key, identifier)
+            .as_ref()
+            .try_into()
+            .unwrap() // This is safe as SHA256 digests are 32 bytes
+    }
+
+    /// Mint a new unrestricted [`Macaroon`].
+    pub fn mint(&self, identifier: &[u8]) -> Macaroon {
+        Macaroon {
+            identifier: identifier.to_vec(),
+            caveats: Vec::new(),
+            signature: self.root_signature(identifier),
+        }
+    }
+
+    /// Validate a [`Macaroon`], enforcing its caveats against the [`Context`].
+    pub fn validate(
+        &self,
+        macaroon: &Macaroon,
+        context: &Context<'_>,
+    ) -> Result<(), ValidationError> {
+        let signature = macaroon.caveats.iter().fold(
+            self.root_signature(&macaroon.identifier),
+            |signature, caveat| chain(&signature, &caveat.raw()),
+        );
+        verify_slices_are_equal(&signature, &macaroon.signature)
+            .map_err(|_| ValidationError::Invalid)?;
+
+        let now = context
+            .now
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        for caveat in &macaroon.caveats {
+            match caveat {
+                Caveat::ExpiresAt(expires_at) => {
+                    if now >= *expires_at {
+                        return Err(ValidationError::Expired);
+                    }
+                }
+                Caveat::Address(address) => {
+                    if address.as_slice() != context.address {
+                        return Err(ValidationError::AddressMismatch);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode and validate a token, enforcing its caveats against the [`Context`].
+    pub fn validate_token(
+        &self,
+        token: &str,
+        context: &Context<'_>,
+    ) -> Result<(), ValidationError> {
+        let macaroon = Macaroon::decode(token).map_err(ValidationError::Decode)?;
+        self.validate(&macaroon, context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn attenuate() {
+        let scheme = MacaroonScheme::new(b"root key");
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let context = Context {
+            now,
+            address: b"alice",
+        };
+
+        let operator = scheme.mint(b"operator");
+        assert_eq!(scheme.validate_token(&operator.encode(), &context), Ok(()));
+
+        let user = operator
+            .attenuate(Caveat::ExpiresAt(2_000))
+            .attenuate(Caveat::Address(b"alice".to_vec()));
+        let token = user.encode();
+        assert_eq!(Macaroon::decode(&token).unwrap(), user);
+        assert_eq!(scheme.validate_token(&token, &context), Ok(()));
+
+        let bob = Context {
+            now,
+            address: b"bob",
+        };
+        assert_eq!(
+            scheme.validate_token(&token, &bob),
+            Err(ValidationError::AddressMismatch)
+        );
+        let later = Context {
+            now: UNIX_EPOCH + Duration::from_secs(2_000),
+            address: b"alice",
+        };
+        assert_eq!(
+            scheme.validate_token(&token, &later),
+            Err(ValidationError::Expired)
+        );
+    }
+
+    #[test]
+    fn remove_caveat() {
+        let scheme = MacaroonScheme::new(b"root key");
+        let mut macaroon = scheme
+            .mint(b"operator")
+            .attenuate(Caveat::Address(b"alice".to_vec()));
+        macaroon.caveats.clear();
+        let context = Context {
+            now: SystemTime::now(),
+            address: b"bob",
+        };
+        assert_eq!(
+            scheme.validate(&macaroon, &context),
+            Err(ValidationError::Invalid)
+        );
+    }
+}
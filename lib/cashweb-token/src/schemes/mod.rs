@@ -1,4 +1,167 @@
 //! This module is a directory of different token schemes.
 
 pub mod chain_commitment;
+pub mod derived;
 pub mod hmac_bearer;
+pub mod jwt;
+pub mod key_bound;
+pub mod macaroon;
+pub mod pop;
+pub mod renewable;
+pub mod revocation;
+pub mod single_use;
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use http::StatusCode;
+
+/// Classification of token validation failures, shared by all schemes.
+///
+/// This allows servers to respond to, and record metrics on, failures independently of the scheme
+/// in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Token could not be parsed, for example due to bad encoding, length or fields.
+    Malformed,
+    /// Token is authentic but has expired.
+    Expired,
+    /// Token is authentic but does not grant access to the requested resource.
+    WrongScope,
+    /// Token failed authentication.
+    Invalid,
+    /// Token could not be validated, for example because a backend was unavailable.
+    Internal,
+}
+
+impl ErrorKind {
+    /// The HTTP status code a server should respond with.
+    ///
+    /// Expired tokens are met with `402 Payment Required`, prompting the client to pay for a new
+    /// token.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::Malformed => StatusCode::BAD_REQUEST,
+            Self::Expired => StatusCode::PAYMENT_REQUIRED,
+            Self::WrongScope => StatusCode::FORBIDDEN,
+            Self::Invalid => StatusCode::UNAUTHORIZED,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An error reported by a token scheme.
+pub trait TokenError: fmt::Debug + fmt::Display {
+    /// Classify the error.
+    fn kind(&self) -> ErrorKind;
+}
+
+impl TokenError for BoxTokenError {
+    fn kind(&self) -> ErrorKind {
+        self.as_ref().kind()
+    }
+}
+
+/// A boxed [`TokenError`].
+pub type BoxTokenError = Box<dyn TokenError + Send + Sync>;
+
+/// A token scheme, allowing consumers to be generic over the authentication mechanism.
+///
+/// This trait is object safe for a given error type. Schemes with differing error types can be
+/// used interchangeably as a [`DynTokenScheme`] by wrapping them in an [`ErasedScheme`].
+#[async_trait]
+pub trait TokenScheme {
+    /// Error associated with constructing and validating tokens.
+    type Error: TokenError;
+
+    /// Construct a token for the data.
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error>;
+
+    /// Validate a token against the data.
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+impl<T> TokenScheme for Arc<T>
+where
+    T: TokenScheme + Send + Sync + ?Sized,
+{
+    type Error = T::Error;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        self.as_ref().construct(data).await
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.as_ref().validate(data, token).await
+    }
+}
+
+/// A [`TokenScheme`] trait object, with its error type erased.
+pub type DynTokenScheme = dyn TokenScheme<Error = BoxTokenError> + Send + Sync;
+
+/// Wraps a [`TokenScheme`], boxing its errors so that it may be used as a [`DynTokenScheme`].
+#[derive(Clone, Debug)]
+pub struct ErasedScheme<T>(T);
+
+impl<T> ErasedScheme<T>
+where
+    T: TokenScheme + Send + Sync + 'static,
+    T::Error: Send + Sync + 'static,
+{
+    /// Wrap a [`TokenScheme`].
+    pub fn new(scheme: T) -> Self {
+        Self(scheme)
+    }
+
+    /// Wrap a [`TokenScheme`] in a shared [`DynTokenScheme`].
+    pub fn shared(scheme: T) -> Arc<DynTokenScheme> {
+        Arc::new(Self(scheme))
+    }
+
+    /// Converts the wrapper into the underlying scheme.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[async_trait]
+impl<T> TokenScheme for ErasedScheme<T>
+where
+    T: TokenScheme + Send + Sync,
+    T::Error: Send + Sync + 'static,
+{
+    type Error = BoxTokenError;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        self.0
+            .construct(data)
+            .await
+            .map_err(|err| Box::new(err) as BoxTokenError)
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.0
+            .validate(data, token)
+            .await
+            .map_err(|err| Box::new(err) as BoxTokenError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hmac_bearer::HmacScheme, jwt::JwtScheme, *};
+
+    #[tokio::test]
+    async fn erased() {
+        let schemes: Vec<Arc<DynTokenScheme>> = vec![
+            ErasedScheme::shared(HmacScheme::new(b"secret")),
+            ErasedScheme::shared(JwtScheme::hs256(b"secret")),
+        ];
+        for scheme in schemes {
+            let token = scheme.construct(b"data").await.unwrap();
+            assert!(scheme.validate(b"data", &token).await.is_ok());
+            assert!(scheme.validate(b"other", &token).await.is_err());
+        }
+    }
+}
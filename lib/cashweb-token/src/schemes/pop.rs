@@ -0,0 +1,452 @@
+//! This module contains [`PopScheme`] which provides POP tokens bound to the outpoint of a payment.
+//!
+//! The token encodes the outpoint and amount of the payment. The verifier checks, via a pluggable
+//! [`UtxoLookup`], that the referenced output is unspent, pays the required script and meets the
+//! price. The payment transaction must also commit, in an `OP_RETURN` output, to the address and
+//! metadata the token authorizes, as constructed by [`construct_commitment`].
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+};
+
+use async_trait::async_trait;
+use cashweb_bitcoin::{
+    transaction::{self, outpoint::Outpoint, Transaction},
+    Decodable,
+};
+use cashweb_bitcoin_client::{
+    chain::UtxoClient,
+    utxo::{commitments, UtxoCache},
+    BitcoinClient, NodeError,
+};
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+
+pub use super::chain_commitment::construct_commitment;
+use super::{ErrorKind, TokenError, TokenScheme};
+
+const TOKEN_LEN: usize = 32 + 4 + 8;
+
+/// An output referenced by a POP token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Utxo {
+    /// Value of the output, in satoshis.
+    pub value: u64,
+    /// Raw output script.
+    pub script: Vec<u8>,
+    /// The [`commitments`] of the transaction which created the output.
+    pub commitments: Vec<Vec<u8>>,
+}
+
+/// Provides the outputs referenced by POP tokens.
+#[async_trait]
+pub trait UtxoLookup {
+    /// Error associated with the lookup.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Lookup the output at the outpoint, returning `None` if it doesn't exist or is spent.
+    async fn lookup(&self, tx_id: &[u8], vout: u32) -> Result<Option<Utxo>, Self::Error>;
+}
+
+/// Error associated with [`TransactionLookup`].
+#[derive(Debug, Error)]
+pub enum TransactionLookupError {
+    /// Error occured when communicating with bitcoind.
+    #[error(transparent)]
+    Node(NodeError),
+    /// Error decoding specified transaction.
+    #[error("failed to decode transaction: {0}")]
+    Transaction(transaction::DecodeError),
+}
+
+/// A [`UtxoLookup`] which checks the output is unspent, then fetches the transaction, using a
+/// [`BitcoinClient`].
+#[derive(Clone, Debug)]
+pub struct TransactionLookup<C>(pub C);
+
+#[async_trait]
+impl<C> UtxoLookup for TransactionLookup<C>
+where
+    C: BitcoinClient + UtxoClient + Sync,
+{
+    type Error = TransactionLookupError;
+
+    async fn lookup(&self, tx_id: &[u8], vout: u32) -> Result<Option<Utxo>, Self::Error> {
+        let unspent = self
+            .0
+            .is_unspent(tx_id, vout)
+            .await
+            .map_err(TransactionLookupError::Node)?;
+        if !unspent {
+            return Ok(None);
+        }
+        let raw_transaction = self
+            .0
+            .get_raw_transaction(tx_id)
+            .await
+            .map_err(TransactionLookupError::Node)?;
+        let transaction = Transaction::decode(&mut raw_transaction.as_slice())
+            .map_err(TransactionLookupError::Transaction)?;
+        let commitments = commitments(&transaction);
+        Ok(transaction
+            .outputs
+            .into_iter()
+            .nth(vout as usize)
+            .map(|output| Utxo {
+                value: output.value,
+                script: output.script.into_bytes(),
+                commitments,
+            }))
+    }
+}
+
+/// A [`UtxoLookup`] which consults a [`UtxoCache`] before falling back to another lookup.
+///
+/// Payments made since the cache began following the chain are verified without a round trip to
+/// bitcoind, and outputs the cache saw spent are rejected without one. Only outputs unknown to
+/// the cache fall back.
+#[derive(Clone, Debug)]
+pub struct CachedLookup<L> {
+    cache: UtxoCache,
+    fallback: L,
+}
+
+impl<L> CachedLookup<L> {
+    /// Create a new [`CachedLookup`] consulting the cache, then the fallback.
+    pub fn new(cache: UtxoCache, fallback: L) -> Self {
+        Self { cache, fallback }
+    }
+}
+
+#[async_trait]
+impl<L> UtxoLookup for CachedLookup<L>
+where
+    L: UtxoLookup + Sync,
+{
+    type Error = L::Error;
+
+    async fn lookup(&self, tx_id: &[u8], vout: u32) -> Result<Option<Utxo>, Self::Error> {
+        // The cache is keyed by the transaction ID in little-endian format
+        if let Ok(mut tx_id) = <[u8; 32]>::try_from(tx_id) {
+            tx_id.reverse();
+            let outpoint = Outpoint { tx_id, vout };
+            if let Some(cached) = self.cache.get(&outpoint) {
+                return Ok(Some(Utxo {
+                    value: cached.output.value,
+                    script: cached.output.script.into_bytes(),
+                    commitments: cached.commitments,
+                }));
+            }
+            if self.cache.is_spent(&outpoint) {
+                return Ok(None);
+            }
+        }
+        self.fallback.lookup(tx_id, vout).await
+    }
+}
+
+/// The payment referenced by a POP token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PopToken {
+    /// ID of the payment transaction.
+    pub tx_id: Vec<u8>,
+    /// Index of the payment output.
+    pub vout: u32,
+    /// Amount paid, in satoshis.
+    pub amount: u64,
+}
+
+/// Error associated with POP token validation.
+#[derive(Debug, Error)]
+pub enum ValidationError<E: fmt::Debug + fmt::Display> {
+    /// Failed to decode token.
+    #[error("failed to decode token: {0}")]
+    Base64(base64::DecodeError),
+    /// Token was unexpected length.
+    #[error("unexpected token length")]
+    TokenLength,
+    /// Failed to lookup the output.
+    #[error("lookup failed: {0}")]
+    Lookup(E),
+    /// Specified output did not exist.
+    #[error("output missing")]
+    OutputNotFound,
+    /// Output did not pay the required script.
+    #[error("unexpected output script")]
+    ScriptMismatch,
+    /// Output value differed from the amount in the token.
+    #[error("amount mismatch")]
+    AmountMismatch,
+    /// Output value was less than the price.
+    #[error("insufficient payment")]
+    InsufficientPayment,
+    /// The payment did not commit to the address and metadata.
+    #[error("payment does not commit to the address and metadata")]
+    Uncommitted,
+}
+
+impl<E: fmt::Debug + fmt::Display> TokenError for ValidationError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Base64(_) | Self::TokenLength => ErrorKind::Malformed,
+            Self::Lookup(_) => ErrorKind::Internal,
+            Self::OutputNotFound
+            | Self::ScriptMismatch
+            | Self::AmountMismatch
+            | Self::InsufficientPayment
+            | Self::Uncommitted => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// Construct the raw token.
+pub fn construct_token_raw(tx_id: &[u8], vout: u32, amount: u64) -> Vec<u8> {
+    [tx_id, &vout.to_le_bytes()[..], &amount.to_le_bytes()[..]].concat()
+}
+
+/// Construct the token.
+pub fn construct_token(tx_id: &[u8], vout: u32, amount: u64) -> String {
+    let raw_token = construct_token_raw(tx_id, vout, amount);
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    base64::encode_config(raw_token, url_safe_config)
+}
+
+/// Proof-of-payment token scheme.
+#[derive(Clone, Debug)]
+pub struct PopScheme<L> {
+    lookup: L,
+    script: Vec<u8>,
+    price: u64,
+}
+
+impl<L: UtxoLookup> PopScheme<L> {
+    /// Create a new [`PopScheme`] requiring payments of at least `price` satoshis to `script`.
+    pub fn new(lookup: L, script: Vec<u8>, price: u64) -> Self {
+        Self {
+            lookup,
+            script,
+            price,
+        }
+    }
+
+    /// Validate a token authorizing the metadata, with the given digest, of the address with the
+    /// given public key hash, returning the payment it references.
+    pub async fn validate_token(
+        &self,
+        pub_key_hash: &[u8],
+        address_metadata_hash: &[u8],
+        token: &str,
+    ) -> Result<PopToken, ValidationError<L::Error>> {
+        let commitment = construct_commitment(pub_key_hash, address_metadata_hash);
+        self.validate_commitment(&commitment, token).await
+    }
+
+    async fn validate_commitment(
+        &self,
+        commitment: &[u8],
+        token: &str,
+    ) -> Result<PopToken, ValidationError<L::Error>> {
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let raw_token =
+            base64::decode_config(token, url_safe_config).map_err(ValidationError::Base64)?;
+
+        // Check token length
+        if raw_token.len() != TOKEN_LEN {
+            return Err(ValidationError::TokenLength);
+        }
+
+        // Parse token
+        let tx_id = raw_token[..32].to_vec();
+        let vout = u32::from_le_bytes(raw_token[32..36].try_into().unwrap()); // This is safe as the length is checked
+        let amount = u64::from_le_bytes(raw_token[36..44].try_into().unwrap()); // This is safe as the length is checked
+
+        // Check output
+        let utxo = self
+            .lookup
+            .lookup(&tx_id, vout)
+            .await
+            .map_err(ValidationError::Lookup)?
+            .ok_or(ValidationError::OutputNotFound)?;
+        if utxo.script != self.script {
+            return Err(ValidationError::ScriptMismatch);
+        }
+        if utxo.value != amount {
+            return Err(ValidationError::AmountMismatch);
+        }
+        if utxo.value < self.price {
+            return Err(ValidationError::InsufficientPayment);
+        }
+        if !utxo.commitments.iter().any(|data| data == commitment) {
+            return Err(ValidationError::Uncommitted);
+        }
+
+        Ok(PopToken {
+            tx_id,
+            vout,
+            amount,
+        })
+    }
+}
+
+/// The data passed to [`TokenScheme::validate`] is the public key hash of the address followed by
+/// the digest of its metadata, which the payment must commit to.
+///
+/// [`TokenScheme::construct`] expects the raw token, as produced by [`construct_token_raw`].
+#[async_trait]
+impl<L> TokenScheme for PopScheme<L>
+where
+    L: UtxoLookup + Send + Sync,
+    L::Error: Send,
+{
+    type Error = ValidationError<L::Error>;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        if data.len() != TOKEN_LEN {
+            return Err(ValidationError::TokenLength);
+        }
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        Ok(base64::encode_config(data, url_safe_config))
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        // The commitment is the digest of the public key hash and metadata digest
+        let commitment = digest(&SHA256, data);
+        self.validate_commitment(commitment.as_ref(), token)
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    struct MockLookup;
+
+    #[async_trait]
+    impl UtxoLookup for MockLookup {
+        type Error = Infallible;
+
+        async fn lookup(&self, _tx_id: &[u8], vout: u32) -> Result<Option<Utxo>, Self::Error> {
+            Ok(match vout {
+                0 => Some(Utxo {
+                    value: 1_000,
+                    script: vec![1, 2, 3],
+                    commitments: vec![construct_commitment(b"alice", b"metadata")],
+                }),
+                1 => Some(Utxo {
+                    value: 1_000,
+                    script: vec![4, 5, 6],
+                    commitments: vec![construct_commitment(b"alice", b"metadata")],
+                }),
+                // Spent or missing
+                _ => None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn validate() {
+        let scheme = PopScheme::new(MockLookup, vec![1, 2, 3], 500);
+        let tx_id = [0; 32];
+
+        let token = scheme
+            .validate_token(b"alice", b"metadata", &construct_token(&tx_id, 0, 1_000))
+            .await
+            .unwrap();
+        assert_eq!(token.amount, 1_000);
+
+        assert!(matches!(
+            scheme
+                .validate_token(b"alice", b"metadata", &construct_token(&tx_id, 0, 2_000))
+                .await,
+            Err(ValidationError::AmountMismatch)
+        ));
+        assert!(matches!(
+            scheme
+                .validate_token(b"alice", b"metadata", &construct_token(&tx_id, 1, 1_000))
+                .await,
+            Err(ValidationError::ScriptMismatch)
+        ));
+        assert!(matches!(
+            scheme
+                .validate_token(b"alice", b"metadata", &construct_token(&tx_id, 2, 1_000))
+                .await,
+            Err(ValidationError::OutputNotFound)
+        ));
+
+        let expensive = PopScheme::new(MockLookup, vec![1, 2, 3], 5_000);
+        assert!(matches!(
+            expensive
+                .validate_token(b"alice", b"metadata", &construct_token(&tx_id, 0, 1_000))
+                .await,
+            Err(ValidationError::InsufficientPayment)
+        ));
+
+        // The payment only authorizes the address and metadata it commits to
+        let token = construct_token(&tx_id, 0, 1_000);
+        assert!(matches!(
+            scheme.validate_token(b"bob", b"metadata", &token).await,
+            Err(ValidationError::Uncommitted)
+        ));
+        assert!(matches!(
+            scheme.validate_token(b"alice", b"other", &token).await,
+            Err(ValidationError::Uncommitted)
+        ));
+        assert!(scheme.validate(b"alicemetadata", &token).await.is_ok());
+        assert!(scheme.validate(b"bobmetadata", &token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cached_lookup() {
+        use cashweb_bitcoin::{
+            block::Block,
+            transaction::{input::Input, output::Output, script::Script},
+        };
+
+        let transaction = Transaction {
+            outputs: vec![Output {
+                value: 2_000,
+                script: Script::from(vec![1, 2, 3]),
+            }],
+            ..Default::default()
+        };
+        let cache = UtxoCache::new(10);
+        cache.connect(&Block {
+            transactions: vec![transaction.clone()],
+            ..Default::default()
+        });
+        let lookup = CachedLookup::new(cache, MockLookup);
+
+        // Cached outputs are found by the transaction ID in the RPC byte order
+        let mut tx_id = transaction.transaction_id();
+        tx_id.reverse();
+        let utxo = lookup.lookup(&tx_id, 0).await.unwrap().unwrap();
+        assert_eq!(utxo.value, 2_000);
+
+        // Outputs seen spent are rejected without falling back
+        let spend = Transaction {
+            inputs: vec![Input {
+                outpoint: Outpoint {
+                    tx_id: transaction.transaction_id(),
+                    vout: 0,
+                },
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            ..Default::default()
+        };
+        lookup.cache.connect(&Block {
+            transactions: vec![Transaction::default(), spend],
+            ..Default::default()
+        });
+        assert_eq!(lookup.lookup(&tx_id, 0).await.unwrap(), None);
+
+        // Misses fall back
+        let utxo = lookup.lookup(&[0; 32], 0).await.unwrap().unwrap();
+        assert_eq!(utxo.value, 1_000);
+    }
+}
@@ -0,0 +1,226 @@
+//! This module contains [`RenewableScheme`] which provides expiring tokens that may be exchanged
+//! for fresh ones, without a new payment, a limited number of times.
+//!
+//! The expiry and renewal count are carried in the token and covered by the inner scheme.
+
+use std::{
+    convert::TryInto,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError, TokenScheme};
+
+const HEADER_LEN: usize = 8 + 4;
+
+/// Error associated with [`RenewableScheme`].
+#[derive(Debug, Error)]
+pub enum RenewalError<E: fmt::Debug + fmt::Display> {
+    /// Token did not carry a header.
+    #[error("malformed token")]
+    Malformed,
+    /// Failed to decode the header.
+    #[error("failed to decode token header: {0}")]
+    Base64(base64::DecodeError),
+    /// The inner scheme rejected the token.
+    #[error(transparent)]
+    Token(E),
+    /// Token has expired.
+    #[error("token expired")]
+    Expired,
+    /// Token has been renewed the maximum number of times.
+    #[error("renewal limit reached")]
+    RenewalLimit,
+}
+
+impl<E: TokenError> TokenError for RenewalError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Malformed | Self::Base64(_) => ErrorKind::Malformed,
+            Self::Token(err) => err.kind(),
+            // A new token must be paid for once the renewal limit is reached
+            Self::Expired | Self::RenewalLimit => ErrorKind::Expired,
+        }
+    }
+}
+
+/// Expiry and renewal count of a valid token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Renewal {
+    /// Time at which the token expires.
+    pub expires_at: SystemTime,
+    /// Number of times the token has been renewed.
+    pub renewals: u32,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn bind(header: &[u8], data: &[u8]) -> Vec<u8> {
+    [header, data].concat()
+}
+
+/// A [`TokenScheme`] whose tokens expire but may be renewed.
+#[derive(Debug)]
+pub struct RenewableScheme<T> {
+    inner: T,
+    ttl: Duration,
+    max_renewals: u32,
+}
+
+impl<T> RenewableScheme<T> {
+    /// Wrap a [`TokenScheme`], issuing tokens valid for `ttl` which may be renewed at most
+    /// `max_renewals` times.
+    pub fn new(inner: T, ttl: Duration, max_renewals: u32) -> Self {
+        Self {
+            inner,
+            ttl,
+            max_renewals,
+        }
+    }
+
+    /// Converts the renewable scheme into the underlying scheme.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: TokenScheme> RenewableScheme<T> {
+    async fn construct_with(
+        &self,
+        data: &[u8],
+        now: SystemTime,
+        renewals: u32,
+    ) -> Result<String, RenewalError<T::Error>> {
+        let expires_at = unix_secs(now).saturating_add(self.ttl.as_secs());
+        let header = [&expires_at.to_be_bytes()[..], &renewals.to_be_bytes()[..]].concat();
+        let token = self
+            .inner
+            .construct(&bind(&header, data))
+            .await
+            .map_err(RenewalError::Token)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        Ok(format!(
+            "{}.{}",
+            base64::encode_config(header, url_safe_config),
+            token
+        ))
+    }
+
+    /// Construct a token issued at `now`.
+    pub async fn construct_token_at(
+        &self,
+        data: &[u8],
+        now: SystemTime,
+    ) -> Result<String, RenewalError<T::Error>> {
+        self.construct_with(data, now, 0).await
+    }
+
+    /// Validate a token at a given time, returning its expiry and renewal count.
+    pub async fn validate_token_at(
+        &self,
+        data: &[u8],
+        token: &str,
+        now: SystemTime,
+    ) -> Result<Renewal, RenewalError<T::Error>> {
+        let mut parts = token.splitn(2, '.');
+        let header = parts.next().ok_or(RenewalError::Malformed)?;
+        let token = parts.next().ok_or(RenewalError::Malformed)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let header =
+            base64::decode_config(header, url_safe_config).map_err(RenewalError::Base64)?;
+        if header.len() != HEADER_LEN {
+            return Err(RenewalError::Malformed);
+        }
+
+        self.inner
+            .validate(&bind(&header, data), token)
+            .await
+            .map_err(RenewalError::Token)?;
+
+        let expires_at = u64::from_be_bytes(header[..8].try_into().unwrap()); // This is safe as the length is checked
+        let renewals = u32::from_be_bytes(header[8..].try_into().unwrap()); // This is safe as the length is checked
+        if unix_secs(now) >= expires_at {
+            return Err(RenewalError::Expired);
+        }
+        Ok(Renewal {
+            expires_at: UNIX_EPOCH + Duration::from_secs(expires_at),
+            renewals,
+        })
+    }
+
+    /// Exchange a still valid token for a fresh one, valid for `ttl` from now.
+    pub async fn renew(&self, data: &[u8], token: &str) -> Result<String, RenewalError<T::Error>> {
+        self.renew_at(data, token, SystemTime::now()).await
+    }
+
+    /// Exchange a token, valid at `now`, for a fresh one, valid for `ttl` from `now`.
+    pub async fn renew_at(
+        &self,
+        data: &[u8],
+        token: &str,
+        now: SystemTime,
+    ) -> Result<String, RenewalError<T::Error>> {
+        let renewal = self.validate_token_at(data, token, now).await?;
+        if renewal.renewals >= self.max_renewals {
+            return Err(RenewalError::RenewalLimit);
+        }
+        self.construct_with(data, now, renewal.renewals + 1).await
+    }
+}
+
+#[async_trait]
+impl<T> TokenScheme for RenewableScheme<T>
+where
+    T: TokenScheme + Send + Sync,
+    T::Error: Send,
+{
+    type Error = RenewalError<T::Error>;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        self.construct_token_at(data, SystemTime::now()).await
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        self.validate_token_at(data, token, SystemTime::now())
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemes::hmac_bearer::HmacScheme;
+
+    #[tokio::test]
+    async fn renew() {
+        let scheme = RenewableScheme::new(HmacScheme::new(b"secret"), Duration::from_secs(60), 1);
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(30);
+        let token = scheme.construct_token_at(b"data", now).await.unwrap();
+
+        let renewed = scheme.renew_at(b"data", &token, later).await.unwrap();
+        let renewal = scheme
+            .validate_token_at(b"data", &renewed, later + Duration::from_secs(45))
+            .await
+            .unwrap();
+        assert_eq!(renewal.renewals, 1);
+        assert!(matches!(
+            scheme.renew_at(b"data", &renewed, later).await,
+            Err(RenewalError::RenewalLimit)
+        ));
+        assert!(matches!(
+            scheme
+                .renew_at(b"data", &token, now + Duration::from_secs(60))
+                .await,
+            Err(RenewalError::Expired)
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! This module contains the [`RevocationList`] and [`RevocableScheme`], allowing operators to
+//! revoke tokens before their natural expiry.
+//!
+//! Tokens are identified by the SHA-256 digest of their raw bytes, so the list never holds the
+//! tokens themselves, and re-encoding a revoked token, for example from base64 to hex, does not
+//! evade revocation.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use ring::digest::{digest, SHA256};
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError, TokenScheme};
+use crate::encoding::decode_any;
+
+/// Digest identifying a token.
+pub type TokenDigest = [u8; 32];
+
+/// Calculate the digest of a raw token.
+pub fn raw_token_digest(raw_token: &[u8]) -> TokenDigest {
+    digest(&SHA256, raw_token).as_ref().try_into().unwrap() // This is safe as SHA256 digests are 32 bytes
+}
+
+/// Calculate the digests of each plausible decoding of a token, see [`decode_any`].
+///
+/// Tokens which are neither base64 nor hex, such as JWTs, are identified by their encoding.
+pub fn token_digests(token: &str) -> Vec<TokenDigest> {
+    match decode_any(token) {
+        Ok(candidates) => candidates
+            .iter()
+            .map(|raw_token| raw_token_digest(raw_token))
+            .collect(),
+        Err(_) => vec![raw_token_digest(token.as_bytes())],
+    }
+}
+
+/// A list of revoked tokens.
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    revoked: RwLock<HashMap<TokenDigest, Option<SystemTime>>>,
+}
+
+impl RevocationList {
+    /// Create an empty [`RevocationList`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Revoke a token, in every encoding.
+    pub fn revoke(&self, token: &str) {
+        let mut revoked = self.revoked.write().unwrap();
+        for digest in token_digests(token) {
+            revoked.insert(digest, None);
+        }
+    }
+
+    /// Revoke a token by its digest.
+    ///
+    /// If `expires_at` is given, the entry is removed by [`RevocationList::prune`] once the token
+    /// would have naturally expired.
+    pub fn revoke_digest(&self, digest: TokenDigest, expires_at: Option<SystemTime>) {
+        self.revoked.write().unwrap().insert(digest, expires_at);
+    }
+
+    /// Reinstate a previously revoked token, returning whether it was revoked.
+    pub fn reinstate(&self, token: &str) -> bool {
+        let mut revoked = self.revoked.write().unwrap();
+        let mut reinstated = false;
+        for digest in token_digests(token) {
+            reinstated |= revoked.remove(&digest).is_some();
+        }
+        reinstated
+    }
+
+    /// Reinstate a previously revoked token by its digest, returning whether it was revoked.
+    pub fn reinstate_digest(&self, digest: &TokenDigest) -> bool {
+        self.revoked.write().unwrap().remove(digest).is_some()
+    }
+
+    /// Check whether a token has been revoked, in any encoding.
+    pub fn is_revoked(&self, token: &str) -> bool {
+        let revoked = self.revoked.read().unwrap();
+        token_digests(token)
+            .iter()
+            .any(|digest| revoked.contains_key(digest))
+    }
+
+    /// Number of revoked tokens.
+    pub fn len(&self) -> usize {
+        self.revoked.read().unwrap().len()
+    }
+
+    /// Whether no tokens have been revoked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove entries for tokens which have naturally expired, returning the number removed.
+    pub fn prune(&self, now: SystemTime) -> usize {
+        let mut revoked = self.revoked.write().unwrap();
+        let before = revoked.len();
+        revoked.retain(|_, expires_at| match expires_at {
+            Some(expires_at) => now < *expires_at,
+            None => true,
+        });
+        before - revoked.len()
+    }
+}
+
+/// Error associated with [`RevocableScheme`].
+#[derive(Debug, Error)]
+pub enum RevocationError<E: fmt::Debug + fmt::Display> {
+    /// The inner scheme rejected the token.
+    #[error(transparent)]
+    Token(E),
+    /// The token has been revoked.
+    #[error("token revoked")]
+    Revoked,
+}
+
+impl<E: TokenError> TokenError for RevocationError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Token(err) => err.kind(),
+            Self::Revoked => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// A [`TokenScheme`] which rejects tokens present in a [`RevocationList`].
+#[derive(Debug)]
+pub struct RevocableScheme<T> {
+    inner: T,
+    revocation_list: Arc<RevocationList>,
+}
+
+impl<T> RevocableScheme<T> {
+    /// Wrap a [`TokenScheme`], consulting the [`RevocationList`] during validation.
+    pub fn new(inner: T, revocation_list: Arc<RevocationList>) -> Self {
+        Self {
+            inner,
+            revocation_list,
+        }
+    }
+
+    /// Get the [`RevocationList`].
+    pub fn revocation_list(&self) -> &Arc<RevocationList> {
+        &self.revocation_list
+    }
+
+    /// Converts the revocable scheme into the underlying scheme.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<T> TokenScheme for RevocableScheme<T>
+where
+    T: TokenScheme + Send + Sync,
+{
+    type Error = RevocationError<T::Error>;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        self.inner
+            .construct(data)
+            .await
+            .map_err(RevocationError::Token)
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        if self.revocation_list.is_revoked(token) {
+            return Err(RevocationError::Revoked);
+        }
+        self.inner
+            .validate(data, token)
+            .await
+            .map_err(RevocationError::Token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{encoding::TokenEncoding, schemes::hmac_bearer::HmacScheme};
+
+    #[tokio::test]
+    async fn revoke() {
+        let revocation_list = Arc::new(RevocationList::new());
+        let scheme = RevocableScheme::new(HmacScheme::new(b"secret"), revocation_list.clone());
+        let token = scheme.construct(b"data").await.unwrap();
+
+        assert!(scheme.validate(b"data", &token).await.is_ok());
+        revocation_list.revoke(&token);
+        assert!(matches!(
+            scheme.validate(b"data", &token).await,
+            Err(RevocationError::Revoked)
+        ));
+        assert!(revocation_list.reinstate(&token));
+        assert!(scheme.validate(b"data", &token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn revoke_reencoded() {
+        let revocation_list = Arc::new(RevocationList::new());
+        let scheme = RevocableScheme::new(HmacScheme::new(b"secret"), revocation_list.clone());
+        let token = scheme.construct(b"data").await.unwrap();
+        let raw_token = TokenEncoding::UrlSafe.decode(&token).unwrap();
+
+        revocation_list.revoke(&token);
+        for encoding in &[
+            TokenEncoding::UrlSafePadded,
+            TokenEncoding::Standard,
+            TokenEncoding::Hex,
+        ] {
+            let reencoded = encoding.encode(&raw_token);
+            assert!(matches!(
+                scheme.validate(b"data", &reencoded).await,
+                Err(RevocationError::Revoked)
+            ));
+        }
+        assert!(revocation_list.reinstate_digest(&raw_token_digest(&raw_token)));
+        assert!(scheme.validate(b"data", &token).await.is_ok());
+    }
+
+    #[test]
+    fn prune() {
+        let revocation_list = RevocationList::new();
+        let now = SystemTime::now();
+        revocation_list.revoke_digest([0; 32], Some(now + Duration::from_secs(60)));
+        revocation_list.revoke_digest([1; 32], None);
+
+        assert_eq!(revocation_list.prune(now), 0);
+        assert_eq!(revocation_list.prune(now + Duration::from_secs(60)), 1);
+        assert_eq!(revocation_list.len(), 1);
+    }
+}
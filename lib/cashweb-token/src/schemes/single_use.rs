@@ -0,0 +1,225 @@
+//! This module contains [`SingleUseScheme`] which adds replay protection to another
+//! [`TokenScheme`].
+//!
+//! Tokens carry a random nonce, covered by the inner scheme, and validation consults a
+//! [`NonceStore`] to reject tokens which have already been used.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    fmt,
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use ring::rand::{SecureRandom, SystemRandom};
+use thiserror::Error;
+
+use super::{ErrorKind, TokenError, TokenScheme};
+
+/// Length of the nonce carried by single use tokens.
+pub const NONCE_LEN: usize = 16;
+
+/// Records the nonces of tokens which have been used.
+///
+/// Implementations backed by persistent storage allow replay protection to survive restarts.
+#[async_trait]
+pub trait NonceStore {
+    /// Error associated with the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Record the nonce, returning `false` if it had already been recorded.
+    async fn insert(&self, nonce: &[u8]) -> Result<bool, Self::Error>;
+}
+
+#[derive(Debug, Default)]
+struct SeenNonces {
+    seen: HashSet<Vec<u8>>,
+    order: VecDeque<Vec<u8>>,
+}
+
+/// An in-memory [`NonceStore`] holding a bounded number of nonces.
+///
+/// Once full, the oldest nonces are evicted, the capacity should therefore exceed the number of
+/// tokens issued within their lifetime.
+#[derive(Debug)]
+pub struct MemoryNonceStore {
+    capacity: usize,
+    inner: Mutex<SeenNonces>,
+}
+
+impl MemoryNonceStore {
+    /// Create a new [`MemoryNonceStore`] holding at most `capacity` nonces.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl NonceStore for MemoryNonceStore {
+    type Error = Infallible;
+
+    async fn insert(&self, nonce: &[u8]) -> Result<bool, Self::Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let SeenNonces { seen, order } = &mut *inner;
+        if !seen.insert(nonce.to_vec()) {
+            return Ok(false);
+        }
+        order.push_back(nonce.to_vec());
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Error associated with [`SingleUseScheme`].
+#[derive(Debug, Error)]
+pub enum ReplayError<T: fmt::Debug + fmt::Display, N: fmt::Debug + fmt::Display> {
+    /// Token did not carry a nonce.
+    #[error("malformed token")]
+    Malformed,
+    /// Failed to decode the nonce.
+    #[error("failed to decode nonce: {0}")]
+    Base64(base64::DecodeError),
+    /// The inner scheme rejected the token.
+    #[error(transparent)]
+    Token(T),
+    /// The nonce store failed.
+    #[error("nonce store failure: {0}")]
+    Store(N),
+    /// The token has already been used.
+    #[error("token replayed")]
+    Replayed,
+}
+
+impl<T: TokenError, N: fmt::Debug + fmt::Display> TokenError for ReplayError<T, N> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Malformed | Self::Base64(_) => ErrorKind::Malformed,
+            Self::Token(err) => err.kind(),
+            Self::Store(_) => ErrorKind::Internal,
+            Self::Replayed => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// A [`TokenScheme`] whose tokens may only be validated once.
+#[derive(Debug)]
+pub struct SingleUseScheme<T, N> {
+    inner: T,
+    store: N,
+    rng: SystemRandom,
+}
+
+impl<T, N> SingleUseScheme<T, N> {
+    /// Wrap a [`TokenScheme`], recording used tokens in the [`NonceStore`].
+    pub fn new(inner: T, store: N) -> Self {
+        Self {
+            inner,
+            store,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Converts the single use scheme into the underlying scheme.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+fn bind(nonce: &[u8], data: &[u8]) -> Vec<u8> {
+    [nonce, data].concat()
+}
+
+#[async_trait]
+impl<T, N> TokenScheme for SingleUseScheme<T, N>
+where
+    T: TokenScheme + Send + Sync,
+    T::Error: Send,
+    N: NonceStore + Send + Sync,
+    N::Error: Send,
+{
+    type Error = ReplayError<T::Error, N::Error>;
+
+    async fn construct(&self, data: &[u8]) -> Result<String, Self::Error> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .expect("system random number generator unavailable");
+        let token = self
+            .inner
+            .construct(&bind(&nonce, data))
+            .await
+            .map_err(ReplayError::Token)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        Ok(format!(
+            "{}.{}",
+            base64::encode_config(nonce, url_safe_config),
+            token
+        ))
+    }
+
+    async fn validate(&self, data: &[u8], token: &str) -> Result<(), Self::Error> {
+        let mut parts = token.splitn(2, '.');
+        let nonce = parts.next().ok_or(ReplayError::Malformed)?;
+        let token = parts.next().ok_or(ReplayError::Malformed)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let nonce = base64::decode_config(nonce, url_safe_config).map_err(ReplayError::Base64)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(ReplayError::Malformed);
+        }
+
+        // Check the token is authentic before consuming the nonce
+        self.inner
+            .validate(&bind(&nonce, data), token)
+            .await
+            .map_err(ReplayError::Token)?;
+
+        if !self
+            .store
+            .insert(&nonce)
+            .await
+            .map_err(ReplayError::Store)?
+        {
+            return Err(ReplayError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemes::hmac_bearer::HmacScheme;
+
+    #[tokio::test]
+    async fn replay() {
+        let scheme = SingleUseScheme::new(HmacScheme::new(b"secret"), MemoryNonceStore::new(8));
+        let token = scheme.construct(b"data").await.unwrap();
+
+        assert!(matches!(
+            scheme.validate(b"other", &token).await,
+            Err(ReplayError::Token(_))
+        ));
+        assert!(scheme.validate(b"data", &token).await.is_ok());
+        assert!(matches!(
+            scheme.validate(b"data", &token).await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn eviction() {
+        let store = MemoryNonceStore::new(1);
+        assert!(store.insert(b"a").await.unwrap());
+        assert!(!store.insert(b"a").await.unwrap());
+        assert!(store.insert(b"b").await.unwrap());
+        assert!(store.insert(b"a").await.unwrap());
+    }
+}
@@ -0,0 +1,725 @@
+//! This module contains [`SigningLayer`], a [`Layer`] which signs outgoing requests with a
+//! secp256k1 key, and [`RequestVerifier`], which checks those signatures server-side.
+//!
+//! Signed requests carry a `Date` header, a `Digest` header holding the SHA-256 digest of the
+//! body, and a [`SIGNATURE_HEADER`] holding the public key of the signer and a compact ECDSA
+//! signature covering the method, [`request_target`], date and digest. Servers trusting a set of
+//! public keys can thereby authenticate peers and operators without client TLS certificates.
+//!
+//! Servers may sign their responses in the same way, with the signature also covering the status
+//! and the request responded to. [`VerifyingLayer`] checks those signatures client-side.
+//!
+//! Requests are accepted while their date is within the skew of the verifier, so a captured
+//! request can be replayed to the same server, with the same query, within that window.
+
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http::{
+    header::{HeaderMap, HeaderValue, DATE, HOST},
+    Method, Request, Response, StatusCode, Uri,
+};
+use hyper::{body::to_bytes, Body};
+use ring::digest::{digest, SHA256};
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Message, Secp256k1, Signature,
+};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::schemes::{ErrorKind, TokenError};
+
+type ResponseFuture<Response, Error> =
+    Pin<Box<dyn Future<Output = Result<Response, Error>> + 'static + Send>>;
+
+/// The name of the header carrying the signature of a request.
+pub const SIGNATURE_HEADER: &str = "Signature";
+
+/// The name of the header carrying the digest of the body of a request.
+pub const DIGEST_HEADER: &str = "Digest";
+
+/// Default maximum difference between the date of a request and the time it is verified.
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(300);
+
+/// The `Digest` header value of a body.
+pub fn body_digest(body: &[u8]) -> String {
+    format!("SHA-256={}", base64::encode(digest(&SHA256, body)))
+}
+
+/// The target of a request covered by its signature, being its authority, path and query.
+///
+/// The authority is taken from the `Host` header if the URI lacks one, as is the case for requests
+/// received by servers.
+pub fn request_target(uri: &Uri, headers: &HeaderMap) -> String {
+    let authority = uri
+        .authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| headers.get(HOST).and_then(|value| value.to_str().ok()))
+        .unwrap_or_default();
+    let path_and_query = uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    format!("{}{}", authority.to_ascii_lowercase(), path_and_query)
+}
+
+/// The data covered by the signature of a request.
+pub fn signing_data(method: &Method, target: &str, date: &str, body_digest: &str) -> Vec<u8> {
+    format!("{} {}\n{}\n{}", method, target, date, body_digest).into_bytes()
+}
+
+/// The data covered by the signature of a response to a request.
+pub fn response_signing_data(
+    method: &Method,
+    target: &str,
+    status: StatusCode,
+    date: &str,
+    body_digest: &str,
+) -> Vec<u8> {
+    format!(
+        "{} {} {}\n{}\n{}",
+        status.as_u16(),
+        method,
+        target,
+        date,
+        body_digest
+    )
+    .into_bytes()
+}
+
+fn signing_message(data: &[u8]) -> Message {
+    Message::from_slice(digest(&SHA256, data).as_ref()).unwrap() // This is safe
+}
+
+/// `RequestSigner` signs requests with a secp256k1 key.
+#[derive(Clone)]
+pub struct RequestSigner {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+}
+
+impl fmt::Debug for RequestSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestSigner")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+impl RequestSigner {
+    /// Create a signer from a secret key.
+    pub fn new(secret_key: SecretKey) -> Self {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+        Self {
+            secret_key,
+            public_key,
+        }
+    }
+
+    /// The public key servers verify signatures with.
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Sign a request, with the given body, at `now`.
+    pub fn sign_at<B>(&self, request: &mut Request<B>, body: &[u8], now: SystemTime) {
+        let target = request_target(request.uri(), request.headers());
+        let method = request.method().clone();
+        self.sign_headers(request.headers_mut(), body, now, |date, body_digest| {
+            signing_data(&method, &target, date, body_digest)
+        });
+    }
+
+    /// Sign a response to the request with the method and [`request_target`], with the given
+    /// body, at `now`.
+    pub fn sign_response_at<B>(
+        &self,
+        method: &Method,
+        target: &str,
+        response: &mut Response<B>,
+        body: &[u8],
+        now: SystemTime,
+    ) {
+        let status = response.status();
+        self.sign_headers(response.headers_mut(), body, now, |date, body_digest| {
+            response_signing_data(method, target, status, date, body_digest)
+        });
+    }
+
+    fn sign_headers<F>(&self, headers: &mut HeaderMap, body: &[u8], now: SystemTime, data: F)
+    where
+        F: FnOnce(&str, &str) -> Vec<u8>,
+    {
+        let date = httpdate::fmt_http_date(now);
+        let body_digest = body_digest(body);
+        let message = signing_message(&data(&date, &body_digest));
+        let signature = Secp256k1::signing_only().sign(&message, &self.secret_key);
+        let signature = format!(
+            "keyId=\"{}\",signature=\"{}\"",
+            hex::encode(self.public_key.serialize()),
+            base64::encode(signature.serialize_compact())
+        );
+
+        headers.insert(DATE, HeaderValue::from_str(&date).unwrap()); // This is safe
+        headers.insert(
+            DIGEST_HEADER,
+            HeaderValue::from_str(&body_digest).unwrap(), // This is safe
+        );
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&signature).unwrap(), // This is safe
+        );
+    }
+}
+
+/// Error associated with verifying a signed request or response.
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    /// The request or response was not signed.
+    #[error("missing signature")]
+    Missing,
+    /// The signature headers could not be parsed.
+    #[error("malformed signature")]
+    Malformed,
+    /// The date of the request or response was outside the allowed skew.
+    #[error("request date outside allowed skew")]
+    Expired,
+    /// The body did not match its digest.
+    #[error("body digest mismatch")]
+    DigestMismatch,
+    /// The signer is not trusted.
+    #[error("untrusted key")]
+    UntrustedKey,
+    /// The signature failed verification.
+    #[error("invalid signature")]
+    Invalid,
+}
+
+impl TokenError for SignatureError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Missing | Self::Malformed => ErrorKind::Malformed,
+            Self::Expired => ErrorKind::Expired,
+            Self::DigestMismatch | Self::UntrustedKey | Self::Invalid => ErrorKind::Invalid,
+        }
+    }
+}
+
+/// Parse the `keyId` and `signature` parameters of a [`SIGNATURE_HEADER`] value.
+fn parse_signature(value: &str) -> Option<(PublicKey, Signature)> {
+    let mut key_id = None;
+    let mut signature = None;
+    for param in value.split(',') {
+        let mut parts = param.trim().splitn(2, '=');
+        let name = parts.next()?;
+        let value = parts.next()?.trim_matches('"');
+        match name {
+            "keyId" => key_id = Some(hex::decode(value).ok()?),
+            "signature" => signature = Some(base64::decode(value).ok()?),
+            _ => (),
+        }
+    }
+    let public_key = PublicKey::from_slice(&key_id?).ok()?;
+    let signature = Signature::from_compact(&signature?).ok()?;
+    Some((public_key, signature))
+}
+
+/// `RequestVerifier` checks that requests, or responses, are signed by trusted keys.
+#[derive(Clone, Debug)]
+pub struct RequestVerifier {
+    trusted_keys: Vec<PublicKey>,
+    max_skew: Duration,
+}
+
+impl RequestVerifier {
+    /// Create a verifier trusting the public keys.
+    pub fn new(trusted_keys: Vec<PublicKey>) -> Self {
+        Self {
+            trusted_keys,
+            max_skew: DEFAULT_MAX_SKEW,
+        }
+    }
+
+    /// Set the maximum difference between the date of a request and the time it is verified.
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Verify a request at `now`, returning the public key of its signer.
+    pub fn verify_at(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<PublicKey, SignatureError> {
+        let target = request_target(uri, headers);
+        self.verify_headers(headers, body, now, |date, body_digest| {
+            signing_data(method, &target, date, body_digest)
+        })
+    }
+
+    /// Verify a request, returning the public key of its signer.
+    pub fn verify(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<PublicKey, SignatureError> {
+        self.verify_at(method, uri, headers, body, SystemTime::now())
+    }
+
+    /// Verify a response, with the given body, to the request with the method and
+    /// [`request_target`] at `now`, returning the public key of its signer.
+    pub fn verify_response_at<B>(
+        &self,
+        method: &Method,
+        target: &str,
+        response: &Response<B>,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<PublicKey, SignatureError> {
+        let status = response.status();
+        self.verify_headers(response.headers(), body, now, |date, body_digest| {
+            response_signing_data(method, target, status, date, body_digest)
+        })
+    }
+
+    fn verify_headers<F>(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: SystemTime,
+        data: F,
+    ) -> Result<PublicKey, SignatureError>
+    where
+        F: FnOnce(&str, &str) -> Vec<u8>,
+    {
+        let header = |name| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().map_err(|_| SignatureError::Malformed))
+                .transpose()
+        };
+        let signature_value = header(SIGNATURE_HEADER)?.ok_or(SignatureError::Missing)?;
+        let date = header(DATE.as_str())?.ok_or(SignatureError::Malformed)?;
+        let claimed_digest = header(DIGEST_HEADER)?.ok_or(SignatureError::Malformed)?;
+        let (public_key, signature) =
+            parse_signature(signature_value).ok_or(SignatureError::Malformed)?;
+
+        // Check signer
+        if !self.trusted_keys.contains(&public_key) {
+            return Err(SignatureError::UntrustedKey);
+        }
+
+        // Check date
+        let signed_at = httpdate::parse_http_date(date).map_err(|_| SignatureError::Malformed)?;
+        let skew = now
+            .duration_since(signed_at)
+            .or_else(|_| signed_at.duration_since(now))
+            .unwrap(); // This is safe
+        if skew > self.max_skew {
+            return Err(SignatureError::Expired);
+        }
+
+        // Check body
+        if body_digest(body) != claimed_digest {
+            return Err(SignatureError::DigestMismatch);
+        }
+
+        // Check signature
+        let message = signing_message(&data(date, claimed_digest));
+        Secp256k1::verification_only()
+            .verify(&message, &signature, &public_key)
+            .map_err(|_| SignatureError::Invalid)?;
+        Ok(public_key)
+    }
+}
+
+/// A [`Layer`] which wraps services in a [`SigningService`].
+#[derive(Clone, Debug)]
+pub struct SigningLayer {
+    signer: Option<Arc<RequestSigner>>,
+}
+
+impl SigningLayer {
+    /// Create a new [`SigningLayer`] signing with the signer.
+    pub fn new(signer: RequestSigner) -> Self {
+        Self {
+            signer: Some(Arc::new(signer)),
+        }
+    }
+
+    /// Create a [`SigningLayer`] signing with the signer, if any, and otherwise passing requests
+    /// through unsigned.
+    pub fn optional(signer: Option<RequestSigner>) -> Self {
+        Self {
+            signer: signer.map(Arc::new),
+        }
+    }
+}
+
+impl<S> Layer<S> for SigningLayer {
+    type Service = SigningService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SigningService {
+            inner,
+            signer: self.signer.clone(),
+        }
+    }
+}
+
+/// Error associated with signing a request.
+#[derive(Debug, Error)]
+pub enum SigningError<E: fmt::Debug + fmt::Display> {
+    /// Error while buffering the body.
+    #[error("buffering body failed: {0}")]
+    Body(hyper::Error),
+    /// Error from the inner service.
+    #[error(transparent)]
+    Service(E),
+}
+
+/// A [`Service`] which signs requests before calling the inner service.
+#[derive(Clone, Debug)]
+pub struct SigningService<S> {
+    inner: S,
+    signer: Option<Arc<RequestSigner>>,
+}
+
+impl<S> SigningService<S> {
+    /// Converts the signing service into the underlying service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Service<Request<Body>> for SigningService<S>
+where
+    S: Service<Request<Body>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = S::Response;
+    type Error = SigningError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner
+            .poll_ready(context)
+            .map_err(SigningError::Service)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let signer = match &self.signer {
+            Some(signer) => signer.clone(),
+            None => {
+                let fut = inner.call(request);
+                return Box::pin(async move { fut.await.map_err(SigningError::Service) });
+            }
+        };
+
+        let fut = async move {
+            let (parts, body) = request.into_parts();
+            let body = to_bytes(body).await.map_err(SigningError::Body)?;
+            let mut request = Request::from_parts(parts, Body::from(body.clone()));
+            signer.sign_at(&mut request, &body, SystemTime::now());
+            inner.call(request).await.map_err(SigningError::Service)
+        };
+        Box::pin(fut)
+    }
+}
+
+/// A [`Layer`] which wraps services in a [`VerifyingService`].
+#[derive(Clone, Debug)]
+pub struct VerifyingLayer {
+    verifier: Option<Arc<RequestVerifier>>,
+}
+
+impl VerifyingLayer {
+    /// Create a new [`VerifyingLayer`] verifying responses with the verifier.
+    pub fn new(verifier: RequestVerifier) -> Self {
+        Self {
+            verifier: Some(Arc::new(verifier)),
+        }
+    }
+
+    /// Create a [`VerifyingLayer`] verifying responses with the verifier, if any, and otherwise
+    /// passing responses through unverified.
+    pub fn optional(verifier: Option<RequestVerifier>) -> Self {
+        Self {
+            verifier: verifier.map(Arc::new),
+        }
+    }
+}
+
+impl<S> Layer<S> for VerifyingLayer {
+    type Service = VerifyingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        VerifyingService {
+            inner,
+            verifier: self.verifier.clone(),
+        }
+    }
+}
+
+/// Error associated with verifying a response.
+#[derive(Debug, Error)]
+pub enum VerifyingError<E: fmt::Debug + fmt::Display> {
+    /// Error while buffering the body.
+    #[error("buffering body failed: {0}")]
+    Body(hyper::Error),
+    /// The response signature was rejected.
+    #[error("response signature rejected: {0}")]
+    Signature(SignatureError),
+    /// Error from the inner service.
+    #[error(transparent)]
+    Service(E),
+}
+
+/// A [`Service`] which verifies the signatures of responses from the inner service.
+#[derive(Clone, Debug)]
+pub struct VerifyingService<S> {
+    inner: S,
+    verifier: Option<Arc<RequestVerifier>>,
+}
+
+impl<S> VerifyingService<S> {
+    /// Converts the verifying service into the underlying service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Service<Request<Body>> for VerifyingService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: fmt::Debug + fmt::Display,
+{
+    type Response = Response<Body>;
+    type Error = VerifyingError<S::Error>;
+    type Future = ResponseFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner
+            .poll_ready(context)
+            .map_err(VerifyingError::Service)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let verifier = match &self.verifier {
+            Some(verifier) => verifier.clone(),
+            None => {
+                let fut = inner.call(request);
+                return Box::pin(async move { fut.await.map_err(VerifyingError::Service) });
+            }
+        };
+
+        let method = request.method().clone();
+        let target = request_target(request.uri(), request.headers());
+        let fut = async move {
+            let response = inner.call(request).await.map_err(VerifyingError::Service)?;
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body).await.map_err(VerifyingError::Body)?;
+            let response = Response::from_parts(parts, Body::from(body.clone()));
+            verifier
+                .verify_response_at(&method, &target, &response, &body, SystemTime::now())
+                .map_err(VerifyingError::Signature)?;
+            Ok(response)
+        };
+        Box::pin(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+    };
+
+    use super::*;
+
+    /// Responds with the request it receives.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<Body>> for Echo {
+        type Response = Request<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Request<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            ready(Ok(request))
+        }
+    }
+
+    fn signer() -> RequestSigner {
+        RequestSigner::new(SecretKey::from_slice(&[1; 32]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn signed() {
+        let mut service = SigningLayer::new(signer()).layer(Echo);
+        let request = Request::post("http://peer/admin/replicate?full=true")
+            .body(Body::from("body"))
+            .unwrap();
+        let signed = service.call(request).await.unwrap();
+        let (parts, body) = signed.into_parts();
+        let body = to_bytes(body).await.unwrap();
+
+        let verifier = RequestVerifier::new(vec![signer().public_key()]);
+        let verify = |method: &Method, body: &[u8], now: SystemTime| {
+            verifier.verify_at(method, &parts.uri, &parts.headers, body, now)
+        };
+        let now = SystemTime::now();
+        assert_eq!(
+            verify(&parts.method, &body, now).unwrap(),
+            signer().public_key()
+        );
+
+        // Other bodies, methods, times and signers are rejected
+        assert!(matches!(
+            verify(&parts.method, b"other", now),
+            Err(SignatureError::DigestMismatch)
+        ));
+        assert!(matches!(
+            verify(&Method::GET, &body, now),
+            Err(SignatureError::Invalid)
+        ));
+
+        // As are other queries and servers
+        for uri in &[
+            "http://peer/admin/replicate?full=false",
+            "http://other/admin/replicate?full=true",
+        ] {
+            let uri: Uri = uri.parse().unwrap();
+            assert!(matches!(
+                verifier.verify_at(&parts.method, &uri, &parts.headers, &body, now),
+                Err(SignatureError::Invalid)
+            ));
+        }
+
+        // Servers receive the authority in the Host header
+        let uri: Uri = "/admin/replicate?full=true".parse().unwrap();
+        let mut headers = parts.headers.clone();
+        headers.insert(HOST, HeaderValue::from_static("peer"));
+        assert!(verifier
+            .verify_at(&parts.method, &uri, &headers, &body, now)
+            .is_ok());
+        assert!(matches!(
+            verify(&parts.method, &body, now + Duration::from_secs(600)),
+            Err(SignatureError::Expired)
+        ));
+        let other = RequestVerifier::new(vec![]);
+        assert!(matches!(
+            other.verify_at(&parts.method, &parts.uri, &parts.headers, &body, now),
+            Err(SignatureError::UntrustedKey)
+        ));
+        assert!(matches!(
+            verifier.verify_at(&parts.method, &parts.uri, &HeaderMap::new(), &body, now),
+            Err(SignatureError::Missing)
+        ));
+    }
+
+    /// Responds with a body, signed by the signer if set.
+    #[derive(Clone)]
+    struct Responder(bool);
+
+    impl Service<Request<Body>> for Responder {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<Body>, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let target = request_target(request.uri(), request.headers());
+            let mut response = Response::new(Body::from("body"));
+            if !self.0 {
+                return ready(Ok(response));
+            }
+            signer().sign_response_at(
+                request.method(),
+                &target,
+                &mut response,
+                b"body",
+                SystemTime::now(),
+            );
+            ready(Ok(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn signed_response() {
+        let verifier = RequestVerifier::new(vec![signer().public_key()]);
+        let mut service = VerifyingLayer::new(verifier.clone()).layer(Responder(true));
+        let request = Request::get("http://peer/keys?since=0")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"body");
+
+        // The response is bound to the request
+        let mut response = Responder(true)
+            .call(
+                Request::get("http://peer/keys?since=0")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let now = SystemTime::now();
+        assert!(verifier
+            .verify_response_at(&Method::GET, "peer/keys?since=0", &response, b"body", now)
+            .is_ok());
+        assert!(matches!(
+            verifier.verify_response_at(&Method::GET, "peer/keys?since=1", &response, b"body", now),
+            Err(SignatureError::Invalid)
+        ));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        assert!(matches!(
+            verifier.verify_response_at(&Method::GET, "peer/keys?since=0", &response, b"body", now),
+            Err(SignatureError::Invalid)
+        ));
+
+        // Unsigned responses are rejected
+        let mut service = VerifyingLayer::new(verifier).layer(Responder(false));
+        let request = Request::get("http://peer/keys")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            service.call(request).await,
+            Err(VerifyingError::Signature(SignatureError::Missing))
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+//! This module contains the [`TokenStore`] trait, allowing issued tokens to be persisted across
+//! restarts, and the in-memory [`MemoryTokenStore`].
+
+use std::{
+    collections::HashMap,
+    convert::{Infallible, TryInto},
+    fmt,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+
+/// A token held in a [`TokenStore`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredToken {
+    /// The token.
+    pub token: String,
+    /// Time at which the token expires, if any.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// Error associated with decoding a [`StoredToken`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum StoredTokenDecodeError {
+    /// The encoding was too short.
+    #[error("stored token too short")]
+    TooShort,
+    /// The token was not valid UTF-8.
+    #[error("stored token not utf-8")]
+    Utf8,
+}
+
+impl StoredToken {
+    /// Whether the token has expired at `now`.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at
+            .map(|expires_at| now >= expires_at)
+            .unwrap_or_default()
+    }
+
+    /// Encode the token for persistent storage.
+    ///
+    /// The expiry is encoded as big-endian UNIX seconds, zero indicating no expiry, followed by
+    /// the token.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let expires_at = self
+            .expires_at
+            .and_then(|expires_at| expires_at.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs().max(1))
+            .unwrap_or_default();
+        [&expires_at.to_be_bytes()[..], self.token.as_bytes()].concat()
+    }
+
+    /// Decode a token encoded using [`StoredToken::to_bytes`].
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, StoredTokenDecodeError> {
+        if raw.len() < 8 {
+            return Err(StoredTokenDecodeError::TooShort);
+        }
+        let (expires_at, token) = raw.split_at(8);
+        let expires_at = u64::from_be_bytes(expires_at.try_into().unwrap()); // This is safe as the length is checked
+        let token = String::from_utf8(token.to_vec()).map_err(|_| StoredTokenDecodeError::Utf8)?;
+        Ok(Self {
+            token,
+            expires_at: if expires_at == 0 {
+                None
+            } else {
+                Some(UNIX_EPOCH + Duration::from_secs(expires_at))
+            },
+        })
+    }
+}
+
+/// Persists issued tokens, keyed by address payload.
+#[async_trait]
+pub trait TokenStore {
+    /// Error associated with the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Insert a token, replacing any existing token for the address.
+    async fn insert(&self, address: &[u8], token: StoredToken) -> Result<(), Self::Error>;
+
+    /// Lookup the token for the address, returning `None` if it is absent or expired.
+    async fn lookup(&self, address: &[u8]) -> Result<Option<StoredToken>, Self::Error>;
+
+    /// Remove tokens which have expired at `now`, returning the number removed.
+    async fn expire(&self, now: SystemTime) -> Result<usize, Self::Error>;
+}
+
+/// An in-memory [`TokenStore`].
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: RwLock<HashMap<Vec<u8>, StoredToken>>,
+}
+
+impl MemoryTokenStore {
+    /// Create an empty [`MemoryTokenStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    type Error = Infallible;
+
+    async fn insert(&self, address: &[u8], token: StoredToken) -> Result<(), Self::Error> {
+        self.tokens.write().unwrap().insert(address.to_vec(), token);
+        Ok(())
+    }
+
+    async fn lookup(&self, address: &[u8]) -> Result<Option<StoredToken>, Self::Error> {
+        let now = SystemTime::now();
+        Ok(self
+            .tokens
+            .read()
+            .unwrap()
+            .get(address)
+            .filter(|token| !token.is_expired(now))
+            .cloned())
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Self::Error> {
+        let mut tokens = self.tokens.write().unwrap();
+        let before = tokens.len();
+        tokens.retain(|_, token| !token.is_expired(now));
+        Ok(before - tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let token = StoredToken {
+            token: "abc".to_string(),
+            expires_at: Some(UNIX_EPOCH + Duration::from_secs(1_000)),
+        };
+        assert_eq!(StoredToken::from_bytes(&token.to_bytes()), Ok(token));
+
+        let token = StoredToken {
+            token: "abc".to_string(),
+            expires_at: None,
+        };
+        assert_eq!(StoredToken::from_bytes(&token.to_bytes()), Ok(token));
+    }
+
+    #[tokio::test]
+    async fn expire() {
+        let store = MemoryTokenStore::new();
+        let now = SystemTime::now();
+        let token = StoredToken {
+            token: "abc".to_string(),
+            expires_at: Some(now + Duration::from_secs(60)),
+        };
+        store.insert(b"alice", token.clone()).await.unwrap();
+        assert_eq!(store.lookup(b"alice").await.unwrap(), Some(token));
+
+        assert_eq!(store.expire(now).await.unwrap(), 0);
+        assert_eq!(
+            store.expire(now + Duration::from_secs(60)).await.unwrap(),
+            1
+        );
+        assert_eq!(store.lookup(b"alice").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! This module contains the [`Account`] struct which derives the keys and P2PKH scripts of a
+//! single [`Hierarchical Deterministic Wallets`] account.
+//!
+//! Receiving keys are derived from the external chain, `account/0/index`, and change keys from
+//! the internal chain, `account/1/index`.
+//!
+//! [`Hierarchical Deterministic Wallets`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+
+use cashweb_bitcoin::{
+    bip32::{ChildNumber, ExtendedPrivateKey},
+    transaction::script::Script,
+};
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+use secp256k1::{PublicKey, Secp256k1, SecretKey, SignOnly};
+
+/// The chain from which a key is derived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyChain {
+    /// Keys handed out for receiving funds.
+    External,
+    /// Keys receiving change.
+    Internal,
+}
+
+impl KeyChain {
+    fn child_number(self) -> ChildNumber {
+        match self {
+            KeyChain::External => ChildNumber::Normal(0),
+            KeyChain::Internal => ChildNumber::Normal(1),
+        }
+    }
+}
+
+/// Calculate the HASH160 of the public key.
+pub fn pubkey_hash(public_key: &PublicKey) -> [u8; 20] {
+    let sha256_digest = digest(&SHA256, &public_key.serialize());
+    Ripemd160::digest(sha256_digest.as_ref()).into()
+}
+
+/// A [`Hierarchical Deterministic Wallets`] account, tracking the next unused index of each
+/// [`KeyChain`].
+///
+/// [`Hierarchical Deterministic Wallets`]: https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki
+pub struct Account {
+    secp: Secp256k1<SignOnly>,
+    external: ExtendedPrivateKey,
+    internal: ExtendedPrivateKey,
+    next_external: u32,
+    next_internal: u32,
+}
+
+impl std::fmt::Debug for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Omit the keys
+        f.debug_struct("Account")
+            .field("next_external", &self.next_external)
+            .field("next_internal", &self.next_internal)
+            .finish()
+    }
+}
+
+impl Account {
+    /// Create an account from its extended private key, such as `m/44'/coin'/account'`.
+    pub fn new(account_key: ExtendedPrivateKey) -> Self {
+        let secp = Secp256k1::signing_only();
+        let external = account_key.derive_private_child(&secp, KeyChain::External.child_number());
+        let internal = account_key.derive_private_child(&secp, KeyChain::Internal.child_number());
+        Self {
+            secp,
+            external,
+            internal,
+            next_external: 0,
+            next_internal: 0,
+        }
+    }
+
+    /// Set the next unused indices of the external and internal chains, as recorded by a
+    /// previous session.
+    pub fn with_next_indices(mut self, external: u32, internal: u32) -> Self {
+        self.next_external = external;
+        self.next_internal = internal;
+        self
+    }
+
+    /// The next unused index of the chain.
+    pub fn next_index(&self, chain: KeyChain) -> u32 {
+        match chain {
+            KeyChain::External => self.next_external,
+            KeyChain::Internal => self.next_internal,
+        }
+    }
+
+    /// Advance the next unused index of the chain to at least `next`.
+    pub fn advance(&mut self, chain: KeyChain, next: u32) {
+        let current = match chain {
+            KeyChain::External => &mut self.next_external,
+            KeyChain::Internal => &mut self.next_internal,
+        };
+        *current = (*current).max(next);
+    }
+
+    /// Derive the secret key at the index of the chain.
+    pub fn secret_key(&self, chain: KeyChain, index: u32) -> SecretKey {
+        let chain_key = match chain {
+            KeyChain::External => &self.external,
+            KeyChain::Internal => &self.internal,
+        };
+        chain_key
+            .derive_private_child(&self.secp, ChildNumber::Normal(index))
+            .into_private_key()
+    }
+
+    /// Derive the public key at the index of the chain.
+    pub fn public_key(&self, chain: KeyChain, index: u32) -> PublicKey {
+        PublicKey::from_secret_key(&self.secp, &self.secret_key(chain, index))
+    }
+
+    /// Derive the P2PKH script at the index of the chain.
+    pub fn script(&self, chain: KeyChain, index: u32) -> Script {
+        Script::p2pkh(&pubkey_hash(&self.public_key(chain, index)))
+    }
+
+    /// Derive the P2PKH script at the next unused index of the chain, marking it as used.
+    pub fn next_script(&mut self, chain: KeyChain) -> (u32, Script) {
+        let next = match chain {
+            KeyChain::External => &mut self.next_external,
+            KeyChain::Internal => &mut self.next_internal,
+        };
+        let index = *next;
+        *next += 1;
+        (index, self.script(chain, index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account_key = ExtendedPrivateKey::new_master(secret_key, [2; 32]);
+        let mut account = Account::new(account_key).with_next_indices(3, 0);
+
+        let (index, script) = account.next_script(KeyChain::External);
+        assert_eq!(index, 3);
+        assert!(script.is_p2pkh());
+        assert_eq!(script, account.script(KeyChain::External, 3));
+        assert_ne!(script, account.script(KeyChain::Internal, 3));
+        assert_eq!(account.next_index(KeyChain::External), 4);
+
+        // Matches derivation along the full path
+        let secp = Secp256k1::signing_only();
+        let path = [ChildNumber::Normal(1), ChildNumber::Normal(5)];
+        let expected = account_key.derive_private_path(&secp, &path);
+        assert_eq!(
+            &account.secret_key(KeyChain::Internal, 5),
+            expected.get_private_key()
+        );
+    }
+}
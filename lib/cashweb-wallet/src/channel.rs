@@ -0,0 +1,469 @@
+//! This module contains a unidirectional micropayment channel, allowing a payer to make many small
+//! payments to a payee, such as a relay server charging per message, with only two transactions
+//! reaching the chain.
+//!
+//! The payer funds a P2SH output, see [`ChannelParams::funding_script`], which is spendable by
+//! both parties together or, after the expiry, by the payer alone. Each payment is a
+//! [`StateUpdate`]: the payer's signature over a transaction splitting the funds between the
+//! parties, paying the payee the running total. The payee keeps only the latest update and
+//! cooperatively closes the channel, see [`PayeeChannel::close`], by adding its own signature and
+//! broadcasting. If the payee never closes, the payer reclaims the funds after the expiry, see
+//! [`PayerChannel::refund`].
+
+use cashweb_bitcoin::transaction::{
+    input::Input,
+    outpoint::Outpoint,
+    output::Output,
+    script::{opcodes, push_data, timelock::Timelock, Script},
+    sighash::{SighashCache, SighashParams},
+    SignatureHashType, Transaction,
+};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signature};
+use thiserror::Error;
+
+use crate::{account::pubkey_hash, select::DUST_LIMIT, DEFAULT_SIGHASH_PARAMS};
+
+/// Default fee, in satoshis, of the transactions closing a channel.
+pub const DEFAULT_CLOSE_FEE: u64 = 1_000;
+
+/// Error associated with a payment channel.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ChannelError {
+    /// The payment exceeds the funds remaining in the channel.
+    #[error("insufficient capacity: required {required}, available {available}")]
+    InsufficientCapacity {
+        /// Total amount paid, including the fee, in satoshis.
+        required: u64,
+        /// Capacity of the channel, in satoshis.
+        available: u64,
+    },
+    /// The update does not increase the amount paid.
+    #[error("update does not increase the amount paid")]
+    NotIncreasing,
+    /// The signature of the update is malformed or invalid.
+    #[error("invalid signature")]
+    InvalidSignature,
+    /// The amount paid is too small to be closed.
+    #[error("amount paid is below the dust limit")]
+    Dust,
+}
+
+/// The parameters of a channel, agreed by both parties before funding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelParams {
+    /// Public key of the payer.
+    pub payer: PublicKey,
+    /// Public key of the payee.
+    pub payee: PublicKey,
+    /// Lock time, as a block height or UNIX time, after which the payer may reclaim the funds.
+    pub expiry: u32,
+    /// Fee, in satoshis, of the transactions closing the channel.
+    pub fee: u64,
+    /// Signature hash parameters of the chain.
+    pub sighash_params: SighashParams,
+}
+
+impl ChannelParams {
+    /// Create the parameters of a channel, with the [`DEFAULT_CLOSE_FEE`] and
+    /// [`DEFAULT_SIGHASH_PARAMS`].
+    pub fn new(payer: PublicKey, payee: PublicKey, expiry: u32) -> Self {
+        Self {
+            payer,
+            payee,
+            expiry,
+            fee: DEFAULT_CLOSE_FEE,
+            sighash_params: DEFAULT_SIGHASH_PARAMS,
+        }
+    }
+
+    /// Set the fee, in satoshis, of the transactions closing the channel.
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Set the signature hash parameters of the chain.
+    pub fn with_sighash_params(mut self, sighash_params: SighashParams) -> Self {
+        self.sighash_params = sighash_params;
+        self
+    }
+
+    /// Prepare a transaction spending the funding output, of `capacity` satoshis, for signing.
+    fn sighash_cache<'a>(&self, transaction: &'a Transaction, capacity: u64) -> SighashCache<'a> {
+        SighashCache::with_params(transaction, self.sighash_params, vec![capacity])
+    }
+
+    /// The redeem script, spendable by both parties or, after the expiry, by the payer alone.
+    ///
+    /// `OP_IF OP_2 <payer> <payee> OP_2 OP_CHECKMULTISIG OP_ELSE <expiry> OP_CHECKLOCKTIMEVERIFY
+    /// OP_DROP <payer> OP_CHECKSIG OP_ENDIF`
+    pub fn redeem_script(&self) -> Script {
+        let payer = self.payer.serialize();
+        let payee = self.payee.serialize();
+        let mut script = Vec::with_capacity(128);
+        script.push(opcodes::OP_IF);
+        script.push(opcodes::OP_2);
+        script.push(opcodes::OP_PUSHBYTES_33);
+        script.extend_from_slice(&payer);
+        script.push(opcodes::OP_PUSHBYTES_33);
+        script.extend_from_slice(&payee);
+        script.push(opcodes::OP_2);
+        script.push(opcodes::OP_CHECKMULTISIG);
+        script.push(opcodes::OP_ELSE);
+        Timelock::Absolute(self.expiry).push_verify(&mut script);
+        script.push(opcodes::OP_PUSHBYTES_33);
+        script.extend_from_slice(&payer);
+        script.push(opcodes::OP_CHECKSIG);
+        script.push(opcodes::OP_ENDIF);
+        Script::from(script)
+    }
+
+    /// The P2SH script of the funding output.
+    pub fn funding_script(&self) -> Script {
+        self.redeem_script().to_p2sh()
+    }
+
+    /// The transaction paying `paid` satoshis to the payee and the remainder, less the fee, back
+    /// to the payer.
+    fn state_transaction(&self, funding: &Outpoint, capacity: u64, paid: u64) -> Transaction {
+        let mut outputs = vec![Output {
+            value: paid,
+            script: Script::p2pkh(&pubkey_hash(&self.payee)),
+        }];
+        // Change below the dust limit is given to the fee
+        let change = capacity - self.fee - paid;
+        if change >= DUST_LIMIT {
+            outputs.push(Output {
+                value: change,
+                script: Script::p2pkh(&pubkey_hash(&self.payer)),
+            });
+        }
+        Transaction {
+            version: 1,
+            inputs: vec![Input {
+                outpoint: funding.clone(),
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            outputs,
+            lock_time: 0,
+        }
+    }
+
+    /// The message signed, by either party, to spend the funding output of `capacity` satoshis.
+    fn signature_message(&self, transaction: &Transaction, capacity: u64) -> Message {
+        // This is safe as the transaction has a single input, spending the capacity
+        let sig_hash = self
+            .sighash_cache(transaction, capacity)
+            .signature_hash(0, &self.redeem_script(), SignatureHashType::All)
+            .unwrap();
+        // This is safe as the hash is 32 bytes
+        Message::from_slice(&sig_hash).unwrap()
+    }
+}
+
+/// Sign the transaction spending the funding output of `capacity` satoshis, returning the DER
+/// signature followed by the signature hash type.
+fn sign(
+    params: &ChannelParams,
+    secret_key: &SecretKey,
+    transaction: &Transaction,
+    capacity: u64,
+) -> Vec<u8> {
+    // This is safe as the transaction has a single input, spending the capacity
+    params
+        .sighash_cache(transaction, capacity)
+        .sign(
+            &Secp256k1::signing_only(),
+            0,
+            &params.redeem_script(),
+            SignatureHashType::All,
+            secret_key,
+        )
+        .unwrap()
+}
+
+/// Check the required capacity of a payment of `paid` satoshis.
+fn check_capacity(params: &ChannelParams, capacity: u64, paid: u64) -> Result<(), ChannelError> {
+    let required = paid.saturating_add(params.fee);
+    if required > capacity {
+        return Err(ChannelError::InsufficientCapacity {
+            required,
+            available: capacity,
+        });
+    }
+    Ok(())
+}
+
+/// A payment made through a channel, authorizing the payee to claim the running total.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateUpdate {
+    /// Total amount paid through the channel, in satoshis.
+    pub paid: u64,
+    /// The payer's signature over the state transaction, followed by the signature hash type.
+    pub signature: Vec<u8>,
+}
+
+/// The payer's side of a channel.
+pub struct PayerChannel {
+    secret_key: SecretKey,
+    params: ChannelParams,
+    funding: Outpoint,
+    capacity: u64,
+    paid: u64,
+}
+
+impl std::fmt::Debug for PayerChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Omit the key
+        f.debug_struct("PayerChannel")
+            .field("params", &self.params)
+            .field("funding", &self.funding)
+            .field("capacity", &self.capacity)
+            .field("paid", &self.paid)
+            .finish()
+    }
+}
+
+impl PayerChannel {
+    /// Create the payer's side of a channel funded by the outpoint, holding `capacity` satoshis.
+    pub fn new(
+        secret_key: SecretKey,
+        params: ChannelParams,
+        funding: Outpoint,
+        capacity: u64,
+    ) -> Self {
+        Self {
+            secret_key,
+            params,
+            funding,
+            capacity,
+            paid: 0,
+        }
+    }
+
+    /// The parameters of the channel.
+    pub fn params(&self) -> &ChannelParams {
+        &self.params
+    }
+
+    /// The outpoint funding the channel.
+    pub fn funding(&self) -> &Outpoint {
+        &self.funding
+    }
+
+    /// Total amount paid through the channel, in satoshis.
+    pub fn paid(&self) -> u64 {
+        self.paid
+    }
+
+    /// Amount which may still be paid through the channel, in satoshis.
+    pub fn remaining(&self) -> u64 {
+        self.capacity
+            .saturating_sub(self.params.fee)
+            .saturating_sub(self.paid)
+    }
+
+    /// Pay a further amount, in satoshis, returning the update to be sent to the payee.
+    pub fn pay(&mut self, amount: u64) -> Result<StateUpdate, ChannelError> {
+        let paid = self.paid.saturating_add(amount);
+        check_capacity(&self.params, self.capacity, paid)?;
+        let transaction = self
+            .params
+            .state_transaction(&self.funding, self.capacity, paid);
+        let signature = sign(&self.params, &self.secret_key, &transaction, self.capacity);
+        self.paid = paid;
+        Ok(StateUpdate { paid, signature })
+    }
+
+    /// The transaction returning the funds, less the fee, to the payer. It is valid once the
+    /// expiry has passed.
+    pub fn refund(&self) -> Transaction {
+        let mut transaction = Transaction {
+            version: 1,
+            inputs: vec![Input {
+                outpoint: self.funding.clone(),
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![Output {
+                value: self.capacity.saturating_sub(self.params.fee),
+                script: Script::p2pkh(&pubkey_hash(&self.params.payer)),
+            }],
+            lock_time: 0,
+        };
+        // This is safe as the input exists
+        Timelock::Absolute(self.params.expiry)
+            .apply(&mut transaction, 0)
+            .unwrap();
+        let signature = sign(&self.params, &self.secret_key, &transaction, self.capacity);
+
+        // <payer sig> OP_0 <redeem script>
+        let mut script_sig = Vec::new();
+        push_data(&mut script_sig, &signature);
+        script_sig.push(opcodes::OP_0);
+        push_data(&mut script_sig, self.params.redeem_script().as_bytes());
+        transaction.inputs[0].script = Script::from(script_sig);
+        transaction
+    }
+}
+
+/// The payee's side of a channel.
+pub struct PayeeChannel {
+    secret_key: SecretKey,
+    params: ChannelParams,
+    funding: Outpoint,
+    capacity: u64,
+    latest: Option<StateUpdate>,
+}
+
+impl std::fmt::Debug for PayeeChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Omit the key
+        f.debug_struct("PayeeChannel")
+            .field("params", &self.params)
+            .field("funding", &self.funding)
+            .field("capacity", &self.capacity)
+            .field("latest", &self.latest)
+            .finish()
+    }
+}
+
+impl PayeeChannel {
+    /// Create the payee's side of a channel funded by the outpoint, holding `capacity` satoshis.
+    ///
+    /// The funding output should be confirmed to pay the funding script of the parameters, with
+    /// the capacity, before accepting payments.
+    pub fn new(
+        secret_key: SecretKey,
+        params: ChannelParams,
+        funding: Outpoint,
+        capacity: u64,
+    ) -> Self {
+        Self {
+            secret_key,
+            params,
+            funding,
+            capacity,
+            latest: None,
+        }
+    }
+
+    /// The parameters of the channel.
+    pub fn params(&self) -> &ChannelParams {
+        &self.params
+    }
+
+    /// Total amount received through the channel, in satoshis.
+    pub fn paid(&self) -> u64 {
+        self.latest.as_ref().map_or(0, |update| update.paid)
+    }
+
+    /// Verify and accept an update, returning the amount it pays beyond the previous update.
+    pub fn receive(&mut self, update: StateUpdate) -> Result<u64, ChannelError> {
+        let previous = self.paid();
+        if update.paid <= previous {
+            return Err(ChannelError::NotIncreasing);
+        }
+        check_capacity(&self.params, self.capacity, update.paid)?;
+
+        // Verify the payer's signature
+        let (hash_type, der) = update
+            .signature
+            .split_last()
+            .ok_or(ChannelError::InvalidSignature)?;
+        if *hash_type != self.params.sighash_params.hash_type(SignatureHashType::All) as u8 {
+            return Err(ChannelError::InvalidSignature);
+        }
+        let signature = Signature::from_der(der).map_err(|_| ChannelError::InvalidSignature)?;
+        let transaction = self
+            .params
+            .state_transaction(&self.funding, self.capacity, update.paid);
+        let message = self.params.signature_message(&transaction, self.capacity);
+        Secp256k1::verification_only()
+            .verify(&message, &signature, &self.params.payer)
+            .map_err(|_| ChannelError::InvalidSignature)?;
+
+        let amount = update.paid - previous;
+        self.latest = Some(update);
+        Ok(amount)
+    }
+
+    /// Cooperatively close the channel, returning the fully signed transaction claiming the
+    /// latest update, to be broadcast by the payee.
+    pub fn close(&self) -> Result<Transaction, ChannelError> {
+        let update = match &self.latest {
+            Some(update) if update.paid >= DUST_LIMIT => update,
+            _ => return Err(ChannelError::Dust),
+        };
+        let mut transaction =
+            self.params
+                .state_transaction(&self.funding, self.capacity, update.paid);
+        let signature = sign(&self.params, &self.secret_key, &transaction, self.capacity);
+
+        // OP_0 <payer sig> <payee sig> OP_1 <redeem script>
+        let mut script_sig = vec![opcodes::OP_0];
+        push_data(&mut script_sig, &update.signature);
+        push_data(&mut script_sig, &signature);
+        script_sig.push(opcodes::OP_1);
+        push_data(&mut script_sig, self.params.redeem_script().as_bytes());
+        transaction.inputs[0].script = Script::from(script_sig);
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pay_and_close() {
+        let secp = Secp256k1::signing_only();
+        let payer_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let payee_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let params = ChannelParams::new(
+            PublicKey::from_secret_key(&secp, &payer_key),
+            PublicKey::from_secret_key(&secp, &payee_key),
+            700_000,
+        );
+        assert!(params.funding_script().is_p2sh());
+        let funding = Outpoint {
+            tx_id: [3; 32],
+            vout: 0,
+        };
+
+        let mut payer = PayerChannel::new(payer_key, params.clone(), funding.clone(), 10_000);
+        let mut payee = PayeeChannel::new(payee_key, params, funding, 10_000);
+
+        // Nothing to close before the dust limit is paid
+        assert_eq!(payee.close(), Err(ChannelError::Dust));
+
+        let first = payer.pay(600).unwrap();
+        assert_eq!(payee.receive(first.clone()).unwrap(), 600);
+        let second = payer.pay(400).unwrap();
+        assert_eq!(payee.receive(second).unwrap(), 400);
+        assert_eq!(payer.remaining(), 10_000 - DEFAULT_CLOSE_FEE - 1_000);
+
+        // Stale and forged updates are rejected
+        assert_eq!(payee.receive(first), Err(ChannelError::NotIncreasing));
+        let mut forged = payer.pay(1).unwrap();
+        forged.paid = 2_000;
+        assert_eq!(payee.receive(forged), Err(ChannelError::InvalidSignature));
+        assert_eq!(
+            payer.pay(10_000),
+            Err(ChannelError::InsufficientCapacity {
+                required: 1_001 + 10_000 + DEFAULT_CLOSE_FEE,
+                available: 10_000
+            })
+        );
+
+        let close = payee.close().unwrap();
+        assert_eq!(close.outputs[0].value, 1_000);
+        assert_eq!(close.outputs[1].value, 10_000 - DEFAULT_CLOSE_FEE - 1_000);
+        assert_eq!(close.inputs[0].script.as_bytes()[0], opcodes::OP_0);
+
+        let refund = payer.refund();
+        assert_eq!(refund.lock_time, 700_000);
+        assert_eq!(refund.outputs[0].value, 10_000 - DEFAULT_CLOSE_FEE);
+    }
+}
@@ -0,0 +1,889 @@
+#![warn(
+    missing_debug_implementations,
+    missing_docs,
+    rust_2018_idioms,
+    unreachable_pub
+)]
+
+//! `cashweb-wallet` is a library providing a minimal [`Wallet`], intended for server-side hot
+//! wallets paying cash:web fees automatically.
+//!
+//! A [`Wallet`] ties together an HD [`Account`], a [`UtxoStore`] persisting its UTXO set and a
+//! [`BitcoinClient`] used for broadcasting.
+//!
+//! Besides paying outputs, a [`Wallet`] can [`sweep`](Wallet::sweep) the UTXOs of a set of
+//! addresses to a destination, or [`consolidate`](Wallet::consolidate) small UTXOs into fresh
+//! ones, batching thousands of inputs into standard-sized transactions.
+//!
+//! Repeated fees, such as those paid per message to a relay server, can instead be paid through a
+//! payment [`channel`] opened using [`Wallet::open_channel`].
+
+pub mod account;
+pub mod channel;
+pub mod select;
+pub mod store;
+pub mod sweep;
+
+use std::{
+    collections::BTreeSet,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use cashweb_bitcoin::{
+    transaction::{
+        input::Input,
+        outpoint::Outpoint,
+        output::Output,
+        script::Script,
+        sighash::{SighashCache, SighashParams},
+        SignatureHashType, Transaction,
+    },
+    Encodable,
+};
+use cashweb_bitcoin_client::{utxo::UtxoCache, BitcoinClient, NodeError};
+use secp256k1::{PublicKey, Secp256k1};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+pub use account::{Account, KeyChain};
+pub use channel::{ChannelError, ChannelParams, PayeeChannel, PayerChannel, StateUpdate};
+pub use select::{InsufficientFunds, Selection};
+pub use store::{MemoryUtxoStore, UtxoStore, WalletUtxo};
+pub use sweep::{SweepBatch, SweepPlan};
+
+/// Default fee rate, in satoshis per byte.
+pub const DEFAULT_FEE_PER_BYTE: u64 = 1;
+
+/// Default signature hash parameters, those of Bitcoin Cash.
+pub const DEFAULT_SIGHASH_PARAMS: SighashParams = SighashParams::BCH;
+
+/// Error associated with [`Wallet::send`].
+#[derive(Debug, Error)]
+pub enum SendError<E: std::fmt::Debug + std::fmt::Display> {
+    /// Failed to read from, or write to, the [`UtxoStore`].
+    #[error("store failure: {0}")]
+    Store(E),
+    /// The UTXOs do not cover the outputs and fee.
+    #[error(transparent)]
+    InsufficientFunds(InsufficientFunds),
+    /// Failed to broadcast the transaction. The UTXO set is left unchanged.
+    #[error("failed to broadcast transaction: {error}")]
+    Broadcast {
+        /// The signed transaction.
+        transaction: Transaction,
+        /// The broadcast error.
+        error: NodeError,
+    },
+}
+
+/// The result of a successful [`Wallet::send`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SendReceipt {
+    /// The signed transaction.
+    pub transaction: Transaction,
+    /// The transaction ID returned by the broadcaster.
+    pub tx_id: String,
+    /// The fee paid, in satoshis.
+    pub fee: u64,
+}
+
+/// A sweep transaction which failed to broadcast. Its UTXOs are left in the store.
+#[derive(Debug)]
+pub struct SweepFailure {
+    /// The signed transaction.
+    pub transaction: Transaction,
+    /// The broadcast error.
+    pub error: NodeError,
+}
+
+/// The result of [`Wallet::sweep`] or [`Wallet::consolidate`].
+#[derive(Debug, Default)]
+pub struct SweepReceipt {
+    /// The broadcast transactions.
+    pub sent: Vec<SendReceipt>,
+    /// The transactions which failed to broadcast.
+    pub failed: Vec<SweepFailure>,
+    /// UTXOs left unspent as they are worth less than the fee to spend them.
+    pub uneconomical: Vec<WalletUtxo>,
+}
+
+/// Construct a script pushing each of the items.
+fn push_script(items: &[&[u8]]) -> Script {
+    let mut script = Vec::new();
+    for item in items {
+        // Direct pushes suffice as items are signatures and public keys
+        script.push(item.len() as u8);
+        script.extend_from_slice(item);
+    }
+    Script::from(script)
+}
+
+/// Sign the inputs of the transaction, which spend the UTXOs in order, with the signature hash
+/// parameters of the chain.
+fn sign_inputs(
+    account: &Account,
+    params: SighashParams,
+    transaction: &mut Transaction,
+    utxos: &[WalletUtxo],
+) {
+    let secp = Secp256k1::signing_only();
+    let values = utxos.iter().map(|utxo| utxo.value).collect();
+    let cache = SighashCache::with_params(transaction, params, values);
+    let script_sigs: Vec<Script> = utxos
+        .iter()
+        .enumerate()
+        .map(|(index, utxo)| {
+            let secret_key = account.secret_key(utxo.chain, utxo.index);
+            let public_key = PublicKey::from_secret_key(&secp, &secret_key).serialize();
+            // This is safe as the input, and the value it spends, exist
+            let signature = cache
+                .sign(
+                    &secp,
+                    index,
+                    &utxo.script,
+                    SignatureHashType::All,
+                    &secret_key,
+                )
+                .unwrap();
+            push_script(&[&signature, &public_key])
+        })
+        .collect();
+    for (input, script_sig) in transaction.inputs.iter_mut().zip(script_sigs) {
+        input.script = script_sig;
+    }
+}
+
+/// A hot wallet spending the UTXOs of an [`Account`], held in a [`UtxoStore`], and broadcasting
+/// via a [`BitcoinClient`].
+///
+/// Sends are serialized, so that concurrent sends never select the same UTXOs.
+#[derive(Debug)]
+pub struct Wallet<S, B> {
+    account: Mutex<Account>,
+    store: S,
+    broadcaster: B,
+    fee_per_byte: u64,
+    max_sweep_inputs: usize,
+    sighash_params: SighashParams,
+    utxo_cache: Option<UtxoCache>,
+    // One past the highest external index seen receiving funds, zero if none
+    used_external: AtomicU32,
+}
+
+impl<S, B> Wallet<S, B> {
+    /// Create a new [`Wallet`].
+    pub fn new(account: Account, store: S, broadcaster: B) -> Self {
+        Self {
+            account: Mutex::new(account),
+            store,
+            broadcaster,
+            fee_per_byte: DEFAULT_FEE_PER_BYTE,
+            max_sweep_inputs: sweep::DEFAULT_MAX_INPUTS,
+            sighash_params: DEFAULT_SIGHASH_PARAMS,
+            utxo_cache: None,
+            used_external: AtomicU32::new(0),
+        }
+    }
+
+    /// Set the fee rate, in satoshis per byte.
+    pub fn with_fee_per_byte(mut self, fee_per_byte: u64) -> Self {
+        self.fee_per_byte = fee_per_byte;
+        self
+    }
+
+    /// Set the maximum number of inputs per sweep transaction.
+    pub fn with_max_sweep_inputs(mut self, max_sweep_inputs: usize) -> Self {
+        self.max_sweep_inputs = max_sweep_inputs;
+        self
+    }
+
+    /// Set the signature hash parameters of the chain, as given by the node's
+    /// `Chain::sighash_params`.
+    pub fn with_sighash_params(mut self, sighash_params: SighashParams) -> Self {
+        self.sighash_params = sighash_params;
+        self
+    }
+
+    /// Consult the [`UtxoCache`] before spending, removing UTXOs it saw spent by a confirmed
+    /// block from the store, such as those spent by another instance of the wallet.
+    pub fn with_utxo_cache(mut self, utxo_cache: UtxoCache) -> Self {
+        self.utxo_cache = Some(utxo_cache);
+        self
+    }
+
+    /// The [`UtxoStore`] of the wallet, into which received UTXOs should be inserted.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The next unused indices of the external and internal chains, to be persisted between
+    /// sessions.
+    pub async fn next_indices(&self) -> (u32, u32) {
+        let account = self.account.lock().await;
+        (
+            account.next_index(KeyChain::External),
+            account.next_index(KeyChain::Internal),
+        )
+    }
+
+    /// Derive a fresh P2PKH script for receiving funds, along with its index on the external
+    /// chain.
+    pub async fn receive_script(&self) -> (u32, Script) {
+        self.account.lock().await.next_script(KeyChain::External)
+    }
+
+    /// Derive `count` consecutive fresh P2PKH scripts for receiving funds, along with their
+    /// indices on the external chain.
+    pub async fn receive_scripts(&self, count: usize) -> Vec<(u32, Script)> {
+        let mut account = self.account.lock().await;
+        (0..count)
+            .map(|_| account.next_script(KeyChain::External))
+            .collect()
+    }
+
+    /// Derive the next `count` P2PKH scripts for receiving funds, along with their indices on the
+    /// external chain, without marking them as used, see [`Wallet::advance_receive_index`].
+    pub async fn peek_receive_scripts(&self, count: usize) -> Vec<(u32, Script)> {
+        let account = self.account.lock().await;
+        let next = account.next_index(KeyChain::External);
+        (next..next.saturating_add(count as u32))
+            .map(|index| (index, account.script(KeyChain::External, index)))
+            .collect()
+    }
+
+    /// Mark the external indices below `next` as used.
+    pub async fn advance_receive_index(&self, next: u32) {
+        self.account.lock().await.advance(KeyChain::External, next);
+    }
+
+    /// The highest index, on the external chain, seen receiving funds in the [`UtxoStore`] during
+    /// this session.
+    pub fn last_used_index(&self) -> Option<u32> {
+        self.used_external.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    /// Record the external indices of the UTXOs as used.
+    fn record_used(&self, utxos: &[WalletUtxo]) {
+        if let Some(index) = utxos
+            .iter()
+            .filter(|utxo| utxo.chain == KeyChain::External)
+            .map(|utxo| utxo.index)
+            .max()
+        {
+            self.used_external
+                .fetch_max(index.saturating_add(1), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S, B> Wallet<S, B>
+where
+    S: UtxoStore + Sync,
+    B: BitcoinClient + Sync,
+{
+    /// Get the UTXOs in the store, removing those the [`UtxoCache`] saw spent.
+    async fn unspent_utxos(&self) -> Result<Vec<WalletUtxo>, S::Error> {
+        let utxos = self.store.utxos().await?;
+        self.record_used(&utxos);
+        let utxo_cache = match &self.utxo_cache {
+            Some(some) => some,
+            None => return Ok(utxos),
+        };
+        let mut unspent = Vec::with_capacity(utxos.len());
+        for utxo in utxos {
+            if utxo_cache.is_spent(&utxo.outpoint) {
+                self.store.remove(&utxo.outpoint).await?;
+            } else {
+                unspent.push(utxo);
+            }
+        }
+        Ok(unspent)
+    }
+
+    /// The indices, on the external chain, of the unspent UTXOs in the store.
+    pub async fn received_indices(&self) -> Result<BTreeSet<u32>, S::Error> {
+        Ok(self
+            .unspent_utxos()
+            .await?
+            .iter()
+            .filter(|utxo| utxo.chain == KeyChain::External)
+            .map(|utxo| utxo.index)
+            .collect())
+    }
+
+    /// The total value of the UTXO set, in satoshis.
+    pub async fn balance(&self) -> Result<u64, S::Error> {
+        Ok(self
+            .unspent_utxos()
+            .await?
+            .iter()
+            .map(|utxo| utxo.value)
+            .sum())
+    }
+
+    /// Pay the amount, in satoshis, to the P2PKH address with the public key hash.
+    pub async fn send_to_address(
+        &self,
+        pubkey_hash: &[u8; 20],
+        amount: u64,
+    ) -> Result<SendReceipt, SendError<S::Error>> {
+        self.send_to_script(Script::p2pkh(pubkey_hash), amount)
+            .await
+    }
+
+    /// Pay the amount, in satoshis, to the script.
+    pub async fn send_to_script(
+        &self,
+        script: Script,
+        amount: u64,
+    ) -> Result<SendReceipt, SendError<S::Error>> {
+        self.send(vec![Output {
+            value: amount,
+            script,
+        }])
+        .await
+    }
+
+    /// Fund, sign and broadcast a transaction paying the outputs.
+    ///
+    /// Change is paid to the next unused script of the internal chain. Once broadcast, the spent
+    /// UTXOs are removed from the store and the change UTXO inserted.
+    pub async fn send(&self, outputs: Vec<Output>) -> Result<SendReceipt, SendError<S::Error>> {
+        let mut account = self.account.lock().await;
+
+        // Select UTXOs
+        let utxos = self.unspent_utxos().await.map_err(SendError::Store)?;
+        let selection = select::select_coins(utxos, &outputs, self.fee_per_byte)
+            .map_err(SendError::InsufficientFunds)?;
+
+        // Build transaction
+        let mut transaction = Transaction {
+            version: 1,
+            inputs: selection
+                .utxos
+                .iter()
+                .map(|utxo| Input {
+                    outpoint: utxo.outpoint.clone(),
+                    script: Script::default(),
+                    sequence: u32::MAX,
+                })
+                .collect(),
+            outputs,
+            lock_time: 0,
+        };
+        let change_index = account.next_index(KeyChain::Internal);
+        let change_script = account.script(KeyChain::Internal, change_index);
+        if selection.change != 0 {
+            transaction.outputs.push(Output {
+                value: selection.change,
+                script: change_script.clone(),
+            });
+        }
+
+        // Sign inputs
+        sign_inputs(
+            &account,
+            self.sighash_params,
+            &mut transaction,
+            &selection.utxos,
+        );
+
+        // Broadcast transaction
+        let mut raw_transaction = Vec::with_capacity(transaction.encoded_len());
+        transaction.encode_raw(&mut raw_transaction);
+        let tx_id = match self.broadcaster.send_tx(&raw_transaction).await {
+            Ok(ok) => ok,
+            Err(error) => return Err(SendError::Broadcast { transaction, error }),
+        };
+
+        // Update UTXO set
+        for utxo in &selection.utxos {
+            self.store
+                .remove(&utxo.outpoint)
+                .await
+                .map_err(SendError::Store)?;
+        }
+        if selection.change != 0 {
+            account.next_script(KeyChain::Internal);
+            self.store
+                .insert(WalletUtxo {
+                    outpoint: Outpoint {
+                        tx_id: transaction.transaction_id(),
+                        vout: transaction.outputs.len() as u32 - 1,
+                    },
+                    value: selection.change,
+                    script: change_script,
+                    chain: KeyChain::Internal,
+                    index: change_index,
+                })
+                .await
+                .map_err(SendError::Store)?;
+        }
+
+        Ok(SendReceipt {
+            transaction,
+            tx_id,
+            fee: selection.fee,
+        })
+    }
+
+    /// Open a payment channel to the payee, funding it with `capacity` satoshis which the payer
+    /// may reclaim after the expiry.
+    ///
+    /// The payer key is the next unused key of the internal chain. The funding output is the
+    /// first output of the broadcast transaction.
+    pub async fn open_channel(
+        &self,
+        payee: PublicKey,
+        capacity: u64,
+        expiry: u32,
+    ) -> Result<(SendReceipt, PayerChannel), SendError<S::Error>> {
+        // Reserve a key for the channel
+        let secret_key = {
+            let mut account = self.account.lock().await;
+            let (index, _) = account.next_script(KeyChain::Internal);
+            account.secret_key(KeyChain::Internal, index)
+        };
+        let payer = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+        let params =
+            ChannelParams::new(payer, payee, expiry).with_sighash_params(self.sighash_params);
+
+        let receipt = self
+            .send_to_script(params.funding_script(), capacity)
+            .await?;
+        let funding = Outpoint {
+            tx_id: receipt.transaction.transaction_id(),
+            vout: 0,
+        };
+        let channel = PayerChannel::new(secret_key, params, funding, capacity);
+        Ok((receipt, channel))
+    }
+
+    /// Sweep the UTXOs of the P2PKH addresses with the public key hashes to the destination, at
+    /// the fee rate in satoshis per byte.
+    ///
+    /// The UTXOs are spent in batches of standard-sized transactions. A batch which fails to
+    /// broadcast leaves its UTXOs in the store, without stopping the remaining batches.
+    pub async fn sweep(
+        &self,
+        pubkey_hashes: &[[u8; 20]],
+        destination: Script,
+        fee_per_byte: u64,
+    ) -> Result<SweepReceipt, S::Error> {
+        let mut account = self.account.lock().await;
+        let scripts: Vec<Script> = pubkey_hashes.iter().map(Script::p2pkh).collect();
+        let utxos = self
+            .unspent_utxos()
+            .await?
+            .into_iter()
+            .filter(|utxo| scripts.contains(&utxo.script))
+            .collect();
+        self.sweep_utxos(&mut account, utxos, Some(destination), fee_per_byte)
+            .await
+    }
+
+    /// Consolidate the UTXOs worth less than `below` satoshis into fresh UTXOs on the internal
+    /// chain, at the fee rate in satoshis per byte.
+    ///
+    /// The UTXOs are spent in batches, as with [`Wallet::sweep`], each paying a new internal
+    /// script whose UTXO is inserted into the store once broadcast.
+    pub async fn consolidate(
+        &self,
+        below: u64,
+        fee_per_byte: u64,
+    ) -> Result<SweepReceipt, S::Error> {
+        let mut account = self.account.lock().await;
+        let utxos = self
+            .unspent_utxos()
+            .await?
+            .into_iter()
+            .filter(|utxo| utxo.value < below)
+            .collect();
+        self.sweep_utxos(&mut account, utxos, None, fee_per_byte)
+            .await
+    }
+
+    /// Sweep the UTXOs to the destination, or to fresh internal scripts if there is none.
+    async fn sweep_utxos(
+        &self,
+        account: &mut Account,
+        utxos: Vec<WalletUtxo>,
+        destination: Option<Script>,
+        fee_per_byte: u64,
+    ) -> Result<SweepReceipt, S::Error> {
+        // The fee of an internal destination matches that of any P2PKH script
+        let fee_script = destination
+            .clone()
+            .unwrap_or_else(|| account.script(KeyChain::Internal, 0));
+        let plan = sweep::plan_sweep(utxos, &fee_script, fee_per_byte, self.max_sweep_inputs);
+
+        // Build and sign a transaction per batch
+        let mut transactions = Vec::with_capacity(plan.batches.len());
+        for batch in plan.batches {
+            let (script, internal) = match &destination {
+                Some(script) => (script.clone(), None),
+                None => {
+                    let (index, script) = account.next_script(KeyChain::Internal);
+                    (script.clone(), Some(index))
+                }
+            };
+            let mut transaction = Transaction {
+                version: 1,
+                inputs: batch
+                    .utxos
+                    .iter()
+                    .map(|utxo| Input {
+                        outpoint: utxo.outpoint.clone(),
+                        script: Script::default(),
+                        sequence: u32::MAX,
+                    })
+                    .collect(),
+                outputs: vec![Output {
+                    value: batch.value,
+                    script: script.clone(),
+                }],
+                lock_time: 0,
+            };
+            sign_inputs(account, self.sighash_params, &mut transaction, &batch.utxos);
+            transactions.push((transaction, batch, script, internal));
+        }
+
+        // Broadcast transactions
+        let raw_transactions: Vec<Vec<u8>> = transactions
+            .iter()
+            .map(|(transaction, ..)| {
+                let mut raw_transaction = Vec::with_capacity(transaction.encoded_len());
+                transaction.encode_raw(&mut raw_transaction);
+                raw_transaction
+            })
+            .collect();
+        let results = self.broadcaster.send_txs(&raw_transactions).await;
+
+        // Update UTXO set
+        let mut receipt = SweepReceipt {
+            uneconomical: plan.uneconomical,
+            ..Default::default()
+        };
+        for ((transaction, batch, script, internal), result) in
+            transactions.into_iter().zip(results)
+        {
+            let tx_id = match result {
+                Ok(ok) => ok,
+                Err(error) => {
+                    receipt.failed.push(SweepFailure { transaction, error });
+                    continue;
+                }
+            };
+            for utxo in &batch.utxos {
+                self.store.remove(&utxo.outpoint).await?;
+            }
+            if let Some(index) = internal {
+                self.store
+                    .insert(WalletUtxo {
+                        outpoint: Outpoint {
+                            tx_id: transaction.transaction_id(),
+                            vout: 0,
+                        },
+                        value: batch.value,
+                        script,
+                        chain: KeyChain::Internal,
+                        index,
+                    })
+                    .await?;
+            }
+            receipt.sent.push(SendReceipt {
+                transaction,
+                tx_id,
+                fee: batch.fee,
+            });
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use async_trait::async_trait;
+    use cashweb_bitcoin::{bip32::ExtendedPrivateKey, Decodable};
+    use cashweb_bitcoin_client::FeePolicy;
+    use secp256k1::{Message, SecretKey, Signature};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockBroadcaster(StdMutex<Vec<Vec<u8>>>);
+
+    #[async_trait]
+    impl BitcoinClient for MockBroadcaster {
+        async fn send_tx_with_fee_policy(
+            &self,
+            raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            self.0.lock().unwrap().push(raw_tx.to_vec());
+            Ok("tx_id".to_string())
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+    }
+
+    #[tokio::test]
+    async fn send() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster::default());
+
+        let (index, script) = wallet.receive_script().await;
+        wallet
+            .store()
+            .insert(WalletUtxo {
+                outpoint: Outpoint {
+                    tx_id: [3; 32],
+                    vout: 0,
+                },
+                value: 10_000,
+                script: script.clone(),
+                chain: KeyChain::External,
+                index,
+            })
+            .await
+            .unwrap();
+
+        let receipt = wallet.send_to_address(&[4; 20], 6_000).await.unwrap();
+        assert_eq!(receipt.tx_id, "tx_id");
+        assert_eq!(receipt.transaction.outputs.len(), 2);
+        assert_eq!(wallet.balance().await.unwrap(), 4_000 - receipt.fee);
+        assert_eq!(wallet.next_indices().await, (1, 1));
+
+        // The broadcast transaction spends the UTXO
+        let raw_transaction = wallet.broadcaster.0.lock().unwrap()[0].clone();
+        let transaction = Transaction::decode(&mut raw_transaction.as_slice()).unwrap();
+        assert_eq!(transaction, receipt.transaction);
+        assert_eq!(transaction.inputs[0].outpoint.tx_id, [3; 32]);
+
+        // The input is signed with the fork ID algorithm, committing to the value spent
+        let script_sig = transaction.inputs[0].script.as_bytes();
+        let signature = &script_sig[1..1 + script_sig[0] as usize];
+        let (hash_type, der) = signature.split_last().unwrap();
+        assert_eq!(*hash_type, 0x41);
+        let sig_hash =
+            SighashCache::with_params(&transaction, DEFAULT_SIGHASH_PARAMS, vec![10_000])
+                .signature_hash(0, &script, SignatureHashType::All)
+                .unwrap();
+        let public_key = PublicKey::from_slice(&script_sig[2 + der.len() + 1..]).unwrap();
+        Secp256k1::verification_only()
+            .verify(
+                &Message::from_slice(&sig_hash).unwrap(),
+                &Signature::from_der(der).unwrap(),
+                &public_key,
+            )
+            .unwrap();
+
+        // The change is spendable
+        let utxos = wallet.store().utxos().await.unwrap();
+        assert_eq!(utxos[0].chain, KeyChain::Internal);
+        assert_eq!(utxos[0].outpoint.tx_id, transaction.transaction_id());
+        assert!(matches!(
+            wallet.send_to_address(&[4; 20], 5_000).await,
+            Err(SendError::InsufficientFunds(_))
+        ));
+        wallet.send_to_address(&[4; 20], 3_000).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn utxo_cache() {
+        use cashweb_bitcoin::block::Block;
+
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let utxo_cache = UtxoCache::new(10);
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster::default())
+            .with_utxo_cache(utxo_cache.clone());
+
+        let (index, script) = wallet.receive_script().await;
+        for tx_id in &[[3; 32], [4; 32]] {
+            wallet
+                .store()
+                .insert(WalletUtxo {
+                    outpoint: Outpoint {
+                        tx_id: *tx_id,
+                        vout: 0,
+                    },
+                    value: 10_000,
+                    script: script.clone(),
+                    chain: KeyChain::External,
+                    index,
+                })
+                .await
+                .unwrap();
+        }
+
+        // A UTXO spent elsewhere is removed rather than selected
+        let spend = Transaction {
+            inputs: vec![Input {
+                outpoint: Outpoint {
+                    tx_id: [3; 32],
+                    vout: 0,
+                },
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            ..Default::default()
+        };
+        utxo_cache.connect(&Block {
+            transactions: vec![Transaction::default(), spend],
+            ..Default::default()
+        });
+        assert_eq!(wallet.balance().await.unwrap(), 10_000);
+        assert_eq!(wallet.store().utxos().await.unwrap().len(), 1);
+        let receipt = wallet.send_to_address(&[5; 20], 6_000).await.unwrap();
+        assert_eq!(receipt.transaction.inputs[0].outpoint.tx_id, [4; 32]);
+    }
+
+    #[tokio::test]
+    async fn sweep() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster::default());
+
+        let (index, script) = wallet.receive_script().await;
+        for (vout, value) in [10_000, 5_000, 100].iter().enumerate() {
+            wallet
+                .store()
+                .insert(WalletUtxo {
+                    outpoint: Outpoint {
+                        tx_id: [3; 32],
+                        vout: vout as u32,
+                    },
+                    value: *value,
+                    script: script.clone(),
+                    chain: KeyChain::External,
+                    index,
+                })
+                .await
+                .unwrap();
+        }
+
+        // The dust UTXO is not worth consolidating
+        let receipt = wallet.consolidate(6_000, 1).await.unwrap();
+        assert_eq!(receipt.sent.len(), 1);
+        assert_eq!(receipt.uneconomical.len(), 1);
+        assert_eq!(receipt.uneconomical[0].value, 100);
+        let utxos = wallet.store().utxos().await.unwrap();
+        assert_eq!(utxos.len(), 3);
+        assert!(utxos
+            .iter()
+            .any(|utxo| utxo.chain == KeyChain::Internal
+                && utxo.value == 5_000 - receipt.sent[0].fee));
+
+        // Only the UTXOs of the swept address are spent
+        let mut pubkey_hash = [0; 20];
+        pubkey_hash.copy_from_slice(&script.as_bytes()[3..23]);
+        let destination = Script::p2pkh(&[4; 20]);
+        let receipt = wallet
+            .sweep(&[pubkey_hash], destination.clone(), 1)
+            .await
+            .unwrap();
+        assert_eq!(receipt.sent.len(), 1);
+        let transaction = &receipt.sent[0].transaction;
+        assert_eq!(transaction.inputs.len(), 1);
+        assert_eq!(transaction.outputs[0].script, destination);
+        assert_eq!(transaction.outputs[0].value + receipt.sent[0].fee, 10_000);
+        assert_eq!(wallet.store().utxos().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn open_channel() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster::default());
+
+        let (index, script) = wallet.receive_script().await;
+        wallet
+            .store()
+            .insert(WalletUtxo {
+                outpoint: Outpoint {
+                    tx_id: [3; 32],
+                    vout: 0,
+                },
+                value: 10_000,
+                script,
+                chain: KeyChain::External,
+                index,
+            })
+            .await
+            .unwrap();
+
+        let payee = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+        let (receipt, mut channel) = wallet.open_channel(payee, 5_000, 700_000).await.unwrap();
+        assert_eq!(
+            receipt.transaction.outputs[0].script,
+            channel.params().funding_script()
+        );
+        assert_eq!(
+            channel.funding().tx_id,
+            receipt.transaction.transaction_id()
+        );
+
+        // The channel key is reserved from the internal chain, before the change key
+        assert_eq!(wallet.next_indices().await, (1, 2));
+        assert_eq!(channel.pay(1_000).unwrap().paid, 1_000);
+    }
+
+    #[tokio::test]
+    async fn receive_indices() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster::default());
+
+        // Peeking does not mark scripts as used
+        let peeked = wallet.peek_receive_scripts(2).await;
+        assert_eq!(wallet.peek_receive_scripts(2).await, peeked);
+        assert_eq!(wallet.next_indices().await, (0, 0));
+        wallet.advance_receive_index(2).await;
+        wallet.advance_receive_index(1).await;
+        assert_eq!(wallet.next_indices().await, (2, 0));
+        assert_eq!(wallet.receive_script().await.0, 2);
+
+        assert_eq!(wallet.last_used_index(), None);
+        wallet
+            .store()
+            .insert(WalletUtxo {
+                outpoint: Outpoint {
+                    tx_id: [3; 32],
+                    vout: 0,
+                },
+                value: 10_000,
+                script: peeked[1].1.clone(),
+                chain: KeyChain::External,
+                index: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            wallet.received_indices().await.unwrap(),
+            vec![1].into_iter().collect()
+        );
+        assert_eq!(wallet.last_used_index(), Some(1));
+
+        // The index remains used once the UTXO is spent
+        wallet
+            .store()
+            .remove(&Outpoint {
+                tx_id: [3; 32],
+                vout: 0,
+            })
+            .await
+            .unwrap();
+        assert!(wallet.received_indices().await.unwrap().is_empty());
+        assert_eq!(wallet.last_used_index(), Some(1));
+    }
+}
@@ -0,0 +1,147 @@
+//! This module contains [`select_coins`] which chooses the [`WalletUtxo`]s funding a set of
+//! outputs.
+
+use std::cmp::Reverse;
+
+use cashweb_bitcoin::{
+    transaction::{input::Input, output::Output, script::Script, Transaction},
+    Encodable,
+};
+use thiserror::Error;
+
+use crate::store::WalletUtxo;
+
+/// Outputs below this value, in satoshis, are not created.
+pub const DUST_LIMIT: u64 = 546;
+
+/// Upper bound on the length of a P2PKH `scriptSig`: a pushed 72 byte DER signature with its
+/// sighash byte and a pushed 33 byte compressed public key.
+pub(crate) const P2PKH_SCRIPT_SIG_LEN: usize = 1 + 73 + 1 + 33;
+
+/// Length of a P2PKH output: value, script length and a 25 byte script.
+const P2PKH_OUTPUT_LEN: usize = 8 + 1 + 25;
+
+/// The UTXOs do not cover the requested amount and fee.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("insufficient funds: required {required}, available {available}")]
+pub struct InsufficientFunds {
+    /// Amount required, including the fee, in satoshis.
+    pub required: u64,
+    /// Total value of the UTXOs, in satoshis.
+    pub available: u64,
+}
+
+/// The result of [`select_coins`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    /// The UTXOs to spend.
+    pub utxos: Vec<WalletUtxo>,
+    /// The fee, in satoshis.
+    pub fee: u64,
+    /// The change, in satoshis, or zero if it would be dust.
+    pub change: u64,
+}
+
+/// Select UTXOs, largest first, until the outputs and fee are covered.
+///
+/// The fee accounts for a P2PKH change output, which is omitted, and its value given to the fee,
+/// if it would fall below [`DUST_LIMIT`].
+pub fn select_coins(
+    mut utxos: Vec<WalletUtxo>,
+    outputs: &[Output],
+    fee_per_byte: u64,
+) -> Result<Selection, InsufficientFunds> {
+    let amount: u64 = outputs.iter().map(|output| output.value).sum();
+    let available: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    utxos.sort_by_key(|utxo| Reverse(utxo.value));
+
+    let mut transaction = Transaction {
+        version: 1,
+        inputs: Vec::new(),
+        outputs: outputs.to_vec(),
+        lock_time: 0,
+    };
+    let mut selected = Vec::new();
+    let mut total = 0;
+    let mut fee = 0;
+    for utxo in utxos {
+        total += utxo.value;
+        transaction.inputs.push(Input {
+            outpoint: utxo.outpoint.clone(),
+            script: Script::default(),
+            sequence: u32::MAX,
+        });
+        selected.push(utxo);
+
+        let len = transaction.encoded_len()
+            + transaction.inputs.len() * P2PKH_SCRIPT_SIG_LEN
+            + P2PKH_OUTPUT_LEN;
+        fee = len as u64 * fee_per_byte;
+        if total >= amount + fee {
+            break;
+        }
+    }
+    if selected.is_empty() || total < amount + fee {
+        return Err(InsufficientFunds {
+            required: amount + fee,
+            available,
+        });
+    }
+
+    let mut change = total - amount - fee;
+    if change < DUST_LIMIT {
+        fee += change;
+        change = 0;
+    }
+    Ok(Selection {
+        utxos: selected,
+        fee,
+        change,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_bitcoin::transaction::outpoint::Outpoint;
+
+    use super::*;
+    use crate::account::KeyChain;
+
+    fn utxo(vout: u32, value: u64) -> WalletUtxo {
+        WalletUtxo {
+            outpoint: Outpoint {
+                tx_id: [1; 32],
+                vout,
+            },
+            value,
+            script: Script::default(),
+            chain: KeyChain::External,
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn select() {
+        let outputs = [Output {
+            value: 5_000,
+            script: Script::from(vec![0; 25]),
+        }];
+        let utxos = vec![utxo(0, 1_000), utxo(1, 4_500), utxo(2, 2_000)];
+
+        let selection = select_coins(utxos.clone(), &outputs, 1).unwrap();
+        assert_eq!(selection.utxos, vec![utxos[1].clone(), utxos[2].clone()]);
+        assert_eq!(selection.fee + selection.change, 6_500 - 5_000);
+
+        // Change below the dust limit is given to the fee
+        let selection = select_coins(vec![utxo(0, 5_400)], &outputs, 1).unwrap();
+        assert_eq!((selection.fee, selection.change), (400, 0));
+
+        assert!(matches!(
+            select_coins(utxos, &outputs, 10),
+            Err(InsufficientFunds {
+                available: 7_500,
+                ..
+            })
+        ));
+    }
+}
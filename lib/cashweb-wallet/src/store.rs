@@ -0,0 +1,76 @@
+//! This module contains the [`UtxoStore`] trait, abstracting the persistence of the UTXO set of
+//! a wallet, and the in-memory [`MemoryUtxoStore`].
+
+use std::{convert::Infallible, fmt, sync::RwLock};
+
+use async_trait::async_trait;
+use cashweb_bitcoin::transaction::{outpoint::Outpoint, script::Script};
+
+use crate::account::KeyChain;
+
+/// An unspent output held by the wallet, along with the derivation of the key spending it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalletUtxo {
+    /// The outpoint of the output.
+    pub outpoint: Outpoint,
+    /// The value, in satoshis.
+    pub value: u64,
+    /// The P2PKH script of the output.
+    pub script: Script,
+    /// The chain of the spending key.
+    pub chain: KeyChain,
+    /// The index of the spending key.
+    pub index: u32,
+}
+
+/// Persists the UTXO set of a wallet.
+#[async_trait]
+pub trait UtxoStore {
+    /// Error associated with the store.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Get all UTXOs.
+    async fn utxos(&self) -> Result<Vec<WalletUtxo>, Self::Error>;
+
+    /// Insert a UTXO, replacing any existing UTXO with the same outpoint.
+    async fn insert(&self, utxo: WalletUtxo) -> Result<(), Self::Error>;
+
+    /// Remove the UTXO at the outpoint, returning whether it was present.
+    async fn remove(&self, outpoint: &Outpoint) -> Result<bool, Self::Error>;
+}
+
+/// An in-memory [`UtxoStore`].
+#[derive(Debug, Default)]
+pub struct MemoryUtxoStore {
+    utxos: RwLock<Vec<WalletUtxo>>,
+}
+
+impl MemoryUtxoStore {
+    /// Create an empty [`MemoryUtxoStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UtxoStore for MemoryUtxoStore {
+    type Error = Infallible;
+
+    async fn utxos(&self) -> Result<Vec<WalletUtxo>, Self::Error> {
+        Ok(self.utxos.read().unwrap().clone())
+    }
+
+    async fn insert(&self, utxo: WalletUtxo) -> Result<(), Self::Error> {
+        let mut utxos = self.utxos.write().unwrap();
+        utxos.retain(|existing| existing.outpoint != utxo.outpoint);
+        utxos.push(utxo);
+        Ok(())
+    }
+
+    async fn remove(&self, outpoint: &Outpoint) -> Result<bool, Self::Error> {
+        let mut utxos = self.utxos.write().unwrap();
+        let len = utxos.len();
+        utxos.retain(|existing| &existing.outpoint != outpoint);
+        Ok(utxos.len() != len)
+    }
+}
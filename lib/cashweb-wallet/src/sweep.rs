@@ -0,0 +1,130 @@
+//! This module contains [`plan_sweep`] which splits a set of [`WalletUtxo`]s into batches, each
+//! spent by a single sweep transaction paying one output.
+
+use std::cmp::Reverse;
+
+use cashweb_bitcoin::{
+    transaction::{input::Input, output::Output, script::Script, Transaction},
+    Encodable,
+};
+
+use crate::{
+    select::{DUST_LIMIT, P2PKH_SCRIPT_SIG_LEN},
+    store::WalletUtxo,
+};
+
+/// Default maximum number of inputs per sweep transaction, keeping transactions well below the
+/// 100 kB standardness limit.
+pub const DEFAULT_MAX_INPUTS: usize = 500;
+
+/// A batch of UTXOs spent by a single sweep transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SweepBatch {
+    /// The UTXOs to spend.
+    pub utxos: Vec<WalletUtxo>,
+    /// The fee, in satoshis.
+    pub fee: u64,
+    /// The value paid to the destination, in satoshis.
+    pub value: u64,
+}
+
+/// The result of [`plan_sweep`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SweepPlan {
+    /// The batches to sweep.
+    pub batches: Vec<SweepBatch>,
+    /// UTXOs left unspent as they are worth less than the fee to spend them.
+    pub uneconomical: Vec<WalletUtxo>,
+}
+
+/// The fee, in satoshis, of a transaction spending `inputs` P2PKH inputs to the destination.
+fn sweep_fee(inputs: usize, destination: &Script, fee_per_byte: u64) -> u64 {
+    let transaction = Transaction {
+        version: 1,
+        inputs: vec![Input::default(); inputs],
+        outputs: vec![Output {
+            value: 0,
+            script: destination.clone(),
+        }],
+        lock_time: 0,
+    };
+    let len = transaction.encoded_len() + inputs * P2PKH_SCRIPT_SIG_LEN;
+    len as u64 * fee_per_byte
+}
+
+/// Split the UTXOs, largest first, into batches of at most `max_inputs`, each paying the
+/// destination.
+///
+/// UTXOs worth no more than the fee to spend them are left out, as are batches whose value would
+/// fall below [`DUST_LIMIT`].
+pub fn plan_sweep(
+    mut utxos: Vec<WalletUtxo>,
+    destination: &Script,
+    fee_per_byte: u64,
+    max_inputs: usize,
+) -> SweepPlan {
+    let input_fee =
+        sweep_fee(1, destination, fee_per_byte) - sweep_fee(0, destination, fee_per_byte);
+    let (mut economical, mut uneconomical): (Vec<_>, Vec<_>) =
+        utxos.drain(..).partition(|utxo| utxo.value > input_fee);
+    economical.sort_by_key(|utxo| Reverse(utxo.value));
+
+    let mut batches = Vec::new();
+    for chunk in economical.chunks(max_inputs.max(1)) {
+        let total: u64 = chunk.iter().map(|utxo| utxo.value).sum();
+        let fee = sweep_fee(chunk.len(), destination, fee_per_byte);
+        match total.checked_sub(fee) {
+            Some(value) if value >= DUST_LIMIT => batches.push(SweepBatch {
+                utxos: chunk.to_vec(),
+                fee,
+                value,
+            }),
+            _ => uneconomical.extend_from_slice(chunk),
+        }
+    }
+    SweepPlan {
+        batches,
+        uneconomical,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cashweb_bitcoin::transaction::outpoint::Outpoint;
+
+    use super::*;
+    use crate::account::KeyChain;
+
+    fn utxo(vout: u32, value: u64) -> WalletUtxo {
+        WalletUtxo {
+            outpoint: Outpoint {
+                tx_id: [1; 32],
+                vout,
+            },
+            value,
+            script: Script::default(),
+            chain: KeyChain::External,
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn plan() {
+        let destination = Script::p2pkh(&[2; 20]);
+        let utxos = vec![utxo(0, 100), utxo(1, 5_000), utxo(2, 800), utxo(3, 3_000)];
+        let plan = plan_sweep(utxos, &destination, 1, 2);
+
+        // The 100 satoshi UTXO costs more to spend than it is worth
+        assert_eq!(plan.uneconomical, vec![utxo(0, 100)]);
+        assert_eq!(plan.batches.len(), 2);
+        assert_eq!(plan.batches[0].utxos, vec![utxo(1, 5_000), utxo(3, 3_000)]);
+        assert_eq!(plan.batches[0].value + plan.batches[0].fee, 8_000);
+        assert_eq!(plan.batches[1].utxos, vec![utxo(2, 800)]);
+
+        // At a higher fee rate the smallest batch falls below the dust limit
+        let utxos = vec![utxo(1, 5_000), utxo(2, 800)];
+        let plan = plan_sweep(utxos, &destination, 2, 1);
+        assert_eq!(plan.batches.len(), 1);
+        assert_eq!(plan.uneconomical, vec![utxo(2, 800)]);
+    }
+}
@@ -0,0 +1,481 @@
+//! This module contains [`Error`], a failure from any of the cash:web crates classified by a stable
+//! [`ErrorCode`], so that HTTP gateways can map internal failures to consistent, machine-readable
+//! responses.
+//!
+//! Errors of the bitcoin, bitcoin client, keyserver client and token crates convert into [`Error`],
+//! keeping the original error as its [`source`](std::error::Error::source). An [`Error`]
+//! serializes to its code, the number of its code and its message, for example
+//! `{"code":"token_expired","number":1101,"message":"expired token"}`.
+
+use std::{error::Error as StdError, fmt};
+
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Response, StatusCode,
+};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    bitcoin::{bip32, block, transaction, var_int},
+    bitcoin_client::{confirm::ConfirmError, NodeError},
+    keyserver_client::{
+        services::{
+            GetMetadataError, GetMetadataSinceError, GetPeersError, PutMetadataError, SampleError,
+        },
+        KeyserverError,
+    },
+    token::{
+        keys::KeyError,
+        schemes::{chain_commitment, hmac_bearer, jwt, macaroon, ErrorKind, TokenError},
+        signing::SignatureError,
+    },
+};
+
+type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// The stable code of an [`Error`].
+///
+/// Codes are never renumbered or renamed, only added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// An unexpected internal failure.
+    Internal,
+    /// The request could not be parsed.
+    Malformed,
+    /// Data could not be decoded.
+    DecodeFailed,
+    /// The resource was not found.
+    NotFound,
+    /// The token or signature failed authentication.
+    Unauthorized,
+    /// The token has expired.
+    TokenExpired,
+    /// The token does not grant access to the resource.
+    InsufficientScope,
+    /// The request conflicts with another.
+    Conflict,
+    /// bitcoind could not be reached.
+    NodeUnavailable,
+    /// bitcoind rejected the request.
+    NodeRejected,
+    /// A broadcast transaction was not announced in time.
+    BroadcastTimeout,
+    /// A keyserver could not be reached.
+    PeerUnavailable,
+    /// A keyserver rejected the request.
+    PeerRejected,
+    /// A keyserver responded with invalid data.
+    InvalidResponse,
+    /// Peering is disabled on the keyserver.
+    PeeringDisabled,
+}
+
+impl ErrorCode {
+    /// All codes.
+    pub const ALL: &'static [Self] = &[
+        Self::Internal,
+        Self::Malformed,
+        Self::DecodeFailed,
+        Self::NotFound,
+        Self::Unauthorized,
+        Self::TokenExpired,
+        Self::InsufficientScope,
+        Self::Conflict,
+        Self::NodeUnavailable,
+        Self::NodeRejected,
+        Self::BroadcastTimeout,
+        Self::PeerUnavailable,
+        Self::PeerRejected,
+        Self::InvalidResponse,
+        Self::PeeringDisabled,
+    ];
+
+    /// The stable number of the code.
+    pub fn number(self) -> u16 {
+        match self {
+            Self::Internal => 1000,
+            Self::Malformed => 1001,
+            Self::DecodeFailed => 1002,
+            Self::NotFound => 1003,
+            Self::Unauthorized => 1100,
+            Self::TokenExpired => 1101,
+            Self::InsufficientScope => 1102,
+            Self::Conflict => 1200,
+            Self::NodeUnavailable => 2000,
+            Self::NodeRejected => 2001,
+            Self::BroadcastTimeout => 2002,
+            Self::PeerUnavailable => 3000,
+            Self::PeerRejected => 3001,
+            Self::InvalidResponse => 3002,
+            Self::PeeringDisabled => 3003,
+        }
+    }
+
+    /// The code with the number, if any.
+    pub fn from_number(number: u16) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|code| code.number() == number)
+            .copied()
+    }
+
+    /// The stable name of the code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Internal => "internal",
+            Self::Malformed => "malformed",
+            Self::DecodeFailed => "decode_failed",
+            Self::NotFound => "not_found",
+            Self::Unauthorized => "unauthorized",
+            Self::TokenExpired => "token_expired",
+            Self::InsufficientScope => "insufficient_scope",
+            Self::Conflict => "conflict",
+            Self::NodeUnavailable => "node_unavailable",
+            Self::NodeRejected => "node_rejected",
+            Self::BroadcastTimeout => "broadcast_timeout",
+            Self::PeerUnavailable => "peer_unavailable",
+            Self::PeerRejected => "peer_rejected",
+            Self::InvalidResponse => "invalid_response",
+            Self::PeeringDisabled => "peering_disabled",
+        }
+    }
+
+    /// The HTTP status code a gateway should respond with.
+    ///
+    /// Expired tokens are met with `402 Payment Required`, as with [`ErrorKind::status`].
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Malformed | Self::DecodeFailed | Self::NodeRejected => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::TokenExpired => StatusCode::PAYMENT_REQUIRED,
+            Self::InsufficientScope => StatusCode::FORBIDDEN,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::NodeUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::BroadcastTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::PeerUnavailable | Self::PeerRejected | Self::InvalidResponse => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::PeeringDisabled => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    /// The code of an unexpected status code returned by a keyserver.
+    fn from_peer_status(status: u16) -> Self {
+        match status {
+            404 => Self::NotFound,
+            501 => Self::PeeringDisabled,
+            _ => Self::PeerRejected,
+        }
+    }
+}
+
+impl From<ErrorKind> for ErrorCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Malformed => Self::Malformed,
+            ErrorKind::Expired => Self::TokenExpired,
+            ErrorKind::WrongScope => Self::InsufficientScope,
+            ErrorKind::Invalid => Self::Unauthorized,
+            ErrorKind::Internal => Self::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A failure classified by a stable [`ErrorCode`].
+#[derive(Debug)]
+pub struct Error {
+    code: ErrorCode,
+    message: String,
+    source: Option<BoxError>,
+}
+
+impl Error {
+    /// Create an error from a code and message.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Create an error from a code and the error it originated from.
+    pub fn with_source(code: ErrorCode, source: impl StdError + Send + Sync + 'static) -> Self {
+        Self {
+            code,
+            message: source.to_string(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// Create an error from a [`TokenError`], classified by its [`ErrorKind`].
+    pub fn from_token_error(source: impl TokenError + StdError + Send + Sync + 'static) -> Self {
+        Self::with_source(source.kind().into(), source)
+    }
+
+    /// The code of the error.
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The message of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// A JSON response describing the error, with the status of its code.
+    pub fn to_response(&self) -> Response<Body> {
+        let body = serde_json::to_vec(self).unwrap(); // This is safe
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = self.code.status();
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        response
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("code", &self.code)?;
+        state.serialize_field("number", &self.code.number())?;
+        state.serialize_field("message", &self.message)?;
+        state.end()
+    }
+}
+
+/// The serialized form of an [`Error`], the number being implied by the code.
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: ErrorCode,
+    message: String,
+}
+
+impl<'de> Deserialize<'de> for Error {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let body = ErrorBody::deserialize(deserializer)?;
+        Ok(Self::new(body.code, body.message))
+    }
+}
+
+macro_rules! impl_from {
+    ($code:expr => $($error:ty),+ $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(err: $error) -> Self {
+                    Self::with_source($code, err)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! impl_from_token_error {
+    ($($error:ty),+ $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(err: $error) -> Self {
+                    Self::from_token_error(err)
+                }
+            }
+        )+
+    };
+}
+
+impl_from!(
+    ErrorCode::DecodeFailed =>
+    block::DecodeError,
+    block::HeaderDecodeError,
+    block::MetadataDecodeError,
+    transaction::DecodeError,
+    transaction::input::DecodeError,
+    transaction::outpoint::DecodeError,
+    transaction::output::DecodeError,
+    var_int::DecodeError,
+);
+
+impl_from!(ErrorCode::Malformed => bip32::DeriveError);
+
+impl_from!(ErrorCode::Internal => KeyError);
+
+impl_from_token_error!(
+    chain_commitment::ValidationError,
+    hmac_bearer::ValidationError,
+    jwt::ValidationError,
+    macaroon::ValidationError,
+    SignatureError,
+);
+
+impl From<NodeError> for Error {
+    fn from(err: NodeError) -> Self {
+        let code = match &err {
+            NodeError::RpcConnectError(_) | NodeError::Timeout | NodeError::EmptyResponse => {
+                ErrorCode::NodeUnavailable
+            }
+            NodeError::Rpc(_) => ErrorCode::NodeRejected,
+            NodeError::Json(_) | NodeError::HexDecode(_) | NodeError::BlockDecode(_) => {
+                ErrorCode::DecodeFailed
+            }
+            NodeError::Amount(_) => ErrorCode::Malformed,
+            NodeError::Journal(_) => ErrorCode::Internal,
+        };
+        Self::with_source(code, err)
+    }
+}
+
+impl From<ConfirmError> for Error {
+    fn from(err: ConfirmError) -> Self {
+        match err {
+            ConfirmError::Node(err) => err.into(),
+            ConfirmError::Timeout => Self::with_source(ErrorCode::BroadcastTimeout, err),
+        }
+    }
+}
+
+impl<E> From<GetMetadataError<E>> for Error
+where
+    E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    fn from(err: GetMetadataError<E>) -> Self {
+        let code = match &err {
+            GetMetadataError::Service(_) | GetMetadataError::Body(_) => ErrorCode::PeerUnavailable,
+            GetMetadataError::UnexpectedStatusCode(status) => ErrorCode::from_peer_status(*status),
+            GetMetadataError::MetadataDecode(_)
+            | GetMetadataError::AuthWrapperDecode(_)
+            | GetMetadataError::AuthWrapperParse(_)
+            | GetMetadataError::AuthWrapperVerify(_)
+            | GetMetadataError::MissingToken
+            | GetMetadataError::Decompress(_) => ErrorCode::InvalidResponse,
+        };
+        Self::with_source(code, err)
+    }
+}
+
+impl<E> From<PutMetadataError<E>> for Error
+where
+    E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    fn from(err: PutMetadataError<E>) -> Self {
+        let code = match &err {
+            PutMetadataError::Service(_) => ErrorCode::PeerUnavailable,
+            PutMetadataError::InProgress | PutMetadataError::KeyReused => ErrorCode::Conflict,
+            PutMetadataError::UnexpectedStatusCode(status) => ErrorCode::from_peer_status(*status),
+        };
+        Self::with_source(code, err)
+    }
+}
+
+impl<E> From<GetPeersError<E>> for Error
+where
+    E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    fn from(err: GetPeersError<E>) -> Self {
+        let code = match &err {
+            GetPeersError::Service(_) | GetPeersError::Body(_) => ErrorCode::PeerUnavailable,
+            GetPeersError::Decode(_) => ErrorCode::InvalidResponse,
+            GetPeersError::UnexpectedStatusCode(status) => ErrorCode::from_peer_status(*status),
+            GetPeersError::PeeringDisabled => ErrorCode::PeeringDisabled,
+        };
+        Self::with_source(code, err)
+    }
+}
+
+impl<E> From<GetMetadataSinceError<E>> for Error
+where
+    E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    fn from(err: GetMetadataSinceError<E>) -> Self {
+        let code = match &err {
+            GetMetadataSinceError::Service(_) | GetMetadataSinceError::Body(_) => {
+                ErrorCode::PeerUnavailable
+            }
+            GetMetadataSinceError::Decode(_) => ErrorCode::InvalidResponse,
+            GetMetadataSinceError::UnexpectedStatusCode(status) => {
+                ErrorCode::from_peer_status(*status)
+            }
+        };
+        Self::with_source(code, err)
+    }
+}
+
+impl<E> From<SampleError<E>> for Error
+where
+    E: fmt::Debug + fmt::Display + Send + Sync + 'static,
+{
+    fn from(err: SampleError<E>) -> Self {
+        Self::with_source(ErrorCode::PeerUnavailable, err)
+    }
+}
+
+impl<E> From<KeyserverError<E>> for Error
+where
+    E: fmt::Display + StdError + Into<Error> + 'static,
+{
+    fn from(err: KeyserverError<E>) -> Self {
+        match err {
+            KeyserverError::Uri(err) => Self::with_source(ErrorCode::Malformed, err),
+            KeyserverError::Error(err) => err.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn stable_codes() {
+        let numbers: HashSet<_> = ErrorCode::ALL.iter().map(|code| code.number()).collect();
+        assert_eq!(numbers.len(), ErrorCode::ALL.len());
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_number(code.number()), Some(*code));
+            let name = serde_json::to_string(code).unwrap();
+            assert_eq!(name, format!("\"{}\"", code.as_str()));
+        }
+    }
+
+    #[test]
+    fn converted() {
+        let err: Error = hmac_bearer::ValidationError::Expired.into();
+        assert_eq!(err.code(), ErrorCode::TokenExpired);
+        assert!(err.source().is_some());
+
+        let err: Error = PutMetadataError::<hyper::Error>::UnexpectedStatusCode(404).into();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+
+        let json = serde_json::to_string(&err).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":"not_found","number":1003,"message":"unexpected status code: 404"}"#
+        );
+        let parsed: Error = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.code(), ErrorCode::NotFound);
+        assert_eq!(parsed.message(), err.message());
+    }
+}
@@ -0,0 +1,209 @@
+//! This module contains [`MetadataEscrow`] which holds a signed metadata update to be published
+//! once a timelock expires, unless the owner revokes it first.
+//!
+//! The owner signs the [`AddressMetadata`] in advance and funds an output paying a
+//! [`RevocableScript`], with the owner as revoker and an escrow agent as beneficiary. The owner
+//! hands the signed metadata and the funding outpoint to the agent, and may revoke at any time
+//! before the timelock expires by spending the output, see [`MetadataEscrow::revoke`]. Once the
+//! timelock expires the agent spends the output, see [`MetadataEscrow::claim`], and publishes the
+//! metadata only if the claim, rather than a revocation, is what spent it, see
+//! [`MetadataEscrow::release`].
+//!
+//! [`AddressMetadata`]: crate::keyserver::AddressMetadata
+
+use secp256k1::{key::SecretKey, Secp256k1};
+use thiserror::Error;
+
+use crate::{
+    auth_wrapper::AuthWrapper,
+    bitcoin::transaction::{
+        input::Input,
+        outpoint::Outpoint,
+        output::Output,
+        script::{
+            timelock::{RevocableScript, SpendPath},
+            Script,
+        },
+        sighash::{SighashCache, SighashParams},
+        SignatureHashType, Transaction,
+    },
+};
+
+/// Error associated with releasing escrowed metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum EscrowError {
+    /// The transaction does not spend the escrow output.
+    #[error("transaction does not spend the escrow output")]
+    NotSpent,
+    /// The owner revoked the metadata.
+    #[error("metadata revoked")]
+    Revoked,
+}
+
+/// A signed metadata update held in escrow by an agent.
+#[derive(Clone, Debug)]
+pub struct MetadataEscrow {
+    /// The signed metadata.
+    pub auth_wrapper: AuthWrapper,
+    /// The script of the escrow output, revocable by the owner and claimable by the agent.
+    pub script: RevocableScript,
+    /// The escrow output.
+    pub funding: Outpoint,
+    /// The value of the escrow output, in satoshis.
+    pub value: u64,
+    /// The signature hash parameters of the chain.
+    pub sighash_params: SighashParams,
+}
+
+impl MetadataEscrow {
+    /// Create a new [`MetadataEscrow`] holding the signed metadata, funded by the outpoint.
+    pub fn new(
+        auth_wrapper: AuthWrapper,
+        script: RevocableScript,
+        funding: Outpoint,
+        value: u64,
+    ) -> Self {
+        Self {
+            auth_wrapper,
+            script,
+            funding,
+            value,
+            sighash_params: SighashParams::BCH,
+        }
+    }
+
+    /// Set the signature hash parameters of the chain, those of Bitcoin Cash by default.
+    pub fn with_sighash_params(mut self, sighash_params: SighashParams) -> Self {
+        self.sighash_params = sighash_params;
+        self
+    }
+
+    /// The P2SH script to be paid by the escrow output.
+    pub fn funding_script(&self) -> Script {
+        self.script.p2sh_script()
+    }
+
+    /// Construct and sign a transaction spending the escrow output by the path.
+    fn spend(
+        &self,
+        path: SpendPath,
+        secret_key: &SecretKey,
+        destination: Script,
+        fee: u64,
+    ) -> Transaction {
+        let mut transaction = Transaction {
+            version: 1,
+            inputs: vec![Input {
+                outpoint: self.funding.clone(),
+                script: Script::default(),
+                sequence: u32::MAX,
+            }],
+            outputs: vec![Output {
+                value: self.value.saturating_sub(fee),
+                script: destination,
+            }],
+            lock_time: 0,
+        };
+        if path == SpendPath::Claim {
+            // This is safe as the input exists
+            self.script.timelock.apply(&mut transaction, 0).unwrap();
+        }
+
+        // This is safe as the input, and the value it spends, exist
+        let signature =
+            SighashCache::with_params(&transaction, self.sighash_params, vec![self.value])
+                .sign(
+                    &Secp256k1::signing_only(),
+                    0,
+                    &self.script.redeem_script(),
+                    SignatureHashType::All,
+                    secret_key,
+                )
+                .unwrap();
+        transaction.inputs[0].script = self.script.script_sig(path, &signature);
+        transaction
+    }
+
+    /// The transaction, signed by the owner, revoking the metadata and paying the escrow output,
+    /// less the fee, to the destination. It is valid at any time.
+    pub fn revoke(&self, owner_key: &SecretKey, destination: Script, fee: u64) -> Transaction {
+        self.spend(SpendPath::Revoke, owner_key, destination, fee)
+    }
+
+    /// The transaction, signed by the agent, claiming the escrow output and paying it, less the
+    /// fee, to the destination. It is valid once the timelock expires.
+    pub fn claim(&self, agent_key: &SecretKey, destination: Script, fee: u64) -> Transaction {
+        self.spend(SpendPath::Claim, agent_key, destination, fee)
+    }
+
+    /// Release the signed metadata, to be published, given the transaction which spent the
+    /// escrow output.
+    pub fn release(&self, spend: &Transaction) -> Result<&AuthWrapper, EscrowError> {
+        let input = spend
+            .inputs
+            .iter()
+            .find(|input| input.outpoint == self.funding)
+            .ok_or(EscrowError::NotSpent)?;
+        match self.script.spend_path(&input.script) {
+            Some(SpendPath::Claim) => Ok(&self.auth_wrapper),
+            Some(SpendPath::Revoke) => Err(EscrowError::Revoked),
+            None => Err(EscrowError::NotSpent),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::key::PublicKey;
+
+    use crate::{
+        bitcoin::transaction::script::timelock::Timelock, keyserver::AddressMetadata,
+        publish::sign_metadata,
+    };
+
+    use super::*;
+
+    #[test]
+    fn release() {
+        let secp = Secp256k1::signing_only();
+        let owner_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let agent_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let metadata = AddressMetadata {
+            timestamp: 1_000,
+            ttl: 1_000,
+            entries: vec![],
+        };
+        let escrow = MetadataEscrow::new(
+            sign_metadata(&owner_key, &metadata),
+            RevocableScript {
+                revoker: PublicKey::from_secret_key(&secp, &owner_key),
+                beneficiary: PublicKey::from_secret_key(&secp, &agent_key),
+                timelock: Timelock::Absolute(1_700_000_000),
+            },
+            Outpoint {
+                tx_id: [3; 32],
+                vout: 1,
+            },
+            10_000,
+        );
+        assert!(escrow.funding_script().is_p2sh());
+        let destination = Script::from(vec![0x51]);
+
+        let claim = escrow.claim(&agent_key, destination.clone(), 500);
+        assert_eq!(claim.lock_time, 1_700_000_000);
+        assert_eq!(claim.outputs[0].value, 9_500);
+        assert_eq!(escrow.release(&claim).unwrap(), &escrow.auth_wrapper);
+
+        // The signature carries the fork ID flag
+        let script_sig = claim.inputs[0].script.as_bytes();
+        assert_eq!(script_sig[script_sig[0] as usize], 0x41);
+
+        let revocation = escrow.revoke(&owner_key, destination, 500);
+        assert_eq!(revocation.lock_time, 0);
+        assert_eq!(escrow.release(&revocation), Err(EscrowError::Revoked));
+
+        let mut unrelated = claim;
+        unrelated.inputs[0].outpoint.vout = 0;
+        assert_eq!(escrow.release(&unrelated), Err(EscrowError::NotSpent));
+    }
+}
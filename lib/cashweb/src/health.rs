@@ -0,0 +1,347 @@
+//! This module contains [`Health`], which aggregates the [`Lifecycle`] of a server with checks of
+//! its dependencies into a [`HealthReport`], and [`HealthService`], which serves the report as
+//! JSON.
+//!
+//! Load balancers should probe the health endpoint, rather than an API endpoint such as `/peers`,
+//! so that probes neither touch the peer list nor skew request metrics. The response status is
+//! `200 OK` when the service is ready and every check passes, and `503 Service Unavailable`
+//! otherwise.
+//!
+//! Checks are provided for the reachability of the node, [`NodeCheck`], and for the age of a
+//! [`Heartbeat`], such as that of a replication loop, [`HeartbeatCheck`]. Any other check, such as
+//! whether the store is writable, is built from an async closure using [`from_fn`].
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use hyper::{header::CONTENT_TYPE, Body, Request, Response, StatusCode};
+use serde::Serialize;
+use tower_service::Service;
+
+use crate::{
+    bitcoin_client::chain::ChainClient,
+    lifecycle::{Lifecycle, State},
+};
+
+/// Default time after which a check is considered failed.
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The outcome of a single check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    /// Whether the check passed.
+    pub healthy: bool,
+    /// Human-readable detail, such as a measurement or the error encountered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CheckResult {
+    /// A passing check.
+    pub fn healthy() -> Self {
+        Self {
+            healthy: true,
+            detail: None,
+        }
+    }
+
+    /// A failing check.
+    pub fn unhealthy(detail: impl fmt::Display) -> Self {
+        Self {
+            healthy: false,
+            detail: Some(detail.to_string()),
+        }
+    }
+
+    /// Attach detail to the result.
+    pub fn with_detail(mut self, detail: impl fmt::Display) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// A check of a dependency of a server.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Perform the check.
+    async fn check(&self) -> CheckResult;
+}
+
+/// A [`HealthCheck`] performed by an async closure, see [`from_fn`].
+pub struct FnCheck<F>(F);
+
+impl<F> fmt::Debug for FnCheck<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FnCheck").finish()
+    }
+}
+
+/// Build a [`HealthCheck`] from an async closure.
+pub fn from_fn<F, Fut>(check: F) -> FnCheck<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = CheckResult> + Send,
+{
+    FnCheck(check)
+}
+
+#[async_trait]
+impl<F, Fut> HealthCheck for FnCheck<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = CheckResult> + Send,
+{
+    async fn check(&self) -> CheckResult {
+        (self.0)().await
+    }
+}
+
+/// Checks that the node is reachable by requesting its block count.
+#[derive(Clone, Debug)]
+pub struct NodeCheck<C>(pub C);
+
+#[async_trait]
+impl<C: ChainClient + Send + Sync> HealthCheck for NodeCheck<C> {
+    async fn check(&self) -> CheckResult {
+        match self.0.get_block_count().await {
+            Ok(height) => CheckResult::healthy().with_detail(format!("height {}", height)),
+            Err(err) => CheckResult::unhealthy(err),
+        }
+    }
+}
+
+/// Records the last time a recurring task, such as a replication pass, completed.
+///
+/// Cloning a heartbeat is cheap and the clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat(Arc<Mutex<Option<Instant>>>);
+
+impl Heartbeat {
+    /// Record that the task completed now.
+    pub fn beat(&self) {
+        *self.0.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Time elapsed since the task last completed, if it has.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.0.lock().unwrap().map(|last| last.elapsed())
+    }
+}
+
+/// Checks that a [`Heartbeat`] beat within `max_age`, reporting the lag.
+///
+/// A heartbeat which never beat is healthy, so that a server is ready before the first pass of a
+/// recurring task completes.
+#[derive(Clone, Debug)]
+pub struct HeartbeatCheck {
+    /// The heartbeat.
+    pub heartbeat: Heartbeat,
+    /// Maximum time between beats.
+    pub max_age: Duration,
+}
+
+#[async_trait]
+impl HealthCheck for HeartbeatCheck {
+    async fn check(&self) -> CheckResult {
+        match self.heartbeat.elapsed() {
+            Some(elapsed) if elapsed > self.max_age => {
+                CheckResult::unhealthy(format!("lag {}ms", elapsed.as_millis()))
+            }
+            Some(elapsed) => {
+                CheckResult::healthy().with_detail(format!("lag {}ms", elapsed.as_millis()))
+            }
+            None => CheckResult::healthy().with_detail("pending"),
+        }
+    }
+}
+
+/// The health of a server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    /// The state of the [`Lifecycle`], `starting`, `ready`, `draining` or `stopped`.
+    pub state: &'static str,
+    /// Whether the server is ready and every check passed.
+    pub ready: bool,
+    /// The result of each check, by name.
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+impl HealthReport {
+    /// The status code of the report, `200 OK` if ready and `503 Service Unavailable` otherwise.
+    pub fn status_code(&self) -> StatusCode {
+        if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+
+    /// Convert the report into a JSON response.
+    pub fn into_response(self) -> Response<Body> {
+        let body = serde_json::to_vec(&self).unwrap(); // This is safe
+        Response::builder()
+            .status(self.status_code())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap() // This is safe
+    }
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Starting => "starting",
+        State::Ready => "ready",
+        State::Draining => "draining",
+        State::Stopped => "stopped",
+    }
+}
+
+/// Aggregates the [`Lifecycle`] of a server with named [`HealthCheck`]s.
+pub struct Health {
+    lifecycle: Lifecycle,
+    checks: Vec<(String, Box<dyn HealthCheck>)>,
+    timeout: Duration,
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.checks.iter().map(|(name, _)| name).collect();
+        f.debug_struct("Health")
+            .field("lifecycle", &self.lifecycle)
+            .field("checks", &names)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Health {
+    /// Create a [`Health`] reporting the state of the lifecycle.
+    pub fn new(lifecycle: Lifecycle) -> Self {
+        Self {
+            lifecycle,
+            checks: Vec::new(),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Add a named check.
+    pub fn with_check<C: HealthCheck + 'static>(mut self, name: &str, check: C) -> Self {
+        self.checks.push((name.to_string(), Box::new(check)));
+        self
+    }
+
+    /// Set the time after which a check is considered failed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Perform every check and report the health of the server.
+    pub async fn report(&self) -> HealthReport {
+        let mut checks = BTreeMap::new();
+        for (name, check) in &self.checks {
+            let result = tokio::time::timeout(self.timeout, check.check())
+                .await
+                .unwrap_or_else(|_| CheckResult::unhealthy("timed out"));
+            checks.insert(name.clone(), result);
+        }
+        let state = self.lifecycle.state();
+        HealthReport {
+            state: state_name(state),
+            ready: state == State::Ready && checks.values().all(|result| result.healthy),
+            checks,
+        }
+    }
+}
+
+/// A [`Service`] responding to every request with the JSON [`HealthReport`].
+#[derive(Clone, Debug)]
+pub struct HealthService(Arc<Health>);
+
+impl HealthService {
+    /// Create a new service reporting the health.
+    pub fn new(health: Health) -> Self {
+        Self(Arc::new(health))
+    }
+
+    /// The health reported.
+    pub fn health(&self) -> &Health {
+        &self.0
+    }
+}
+
+impl<B> Service<Request<B>> for HealthService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: Request<B>) -> Self::Future {
+        let health = self.0.clone();
+        Box::pin(async move { Ok(health.report().await.into_response()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::body::to_bytes;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn report() {
+        let lifecycle = Lifecycle::new();
+        let heartbeat = Heartbeat::default();
+        let mut service = HealthService::new(
+            Health::new(lifecycle.clone())
+                .with_check(
+                    "replication",
+                    HeartbeatCheck {
+                        heartbeat: heartbeat.clone(),
+                        max_age: Duration::from_secs(60),
+                    },
+                )
+                .with_check("store", from_fn(|| async { CheckResult::healthy() })),
+        );
+
+        // Not ready until the lifecycle is
+        let report = service.health().report().await;
+        assert_eq!(report.state, "starting");
+        assert!(!report.ready);
+
+        lifecycle.set_ready();
+        heartbeat.beat();
+        let response = service.call(Request::new(())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["checks"]["store"],
+            serde_json::json!({ "healthy": true })
+        );
+        assert_eq!(json["checks"]["replication"]["healthy"], true);
+
+        // A failing check fails readiness
+        let report = Health::new(lifecycle)
+            .with_check(
+                "store",
+                from_fn(|| async { CheckResult::unhealthy("read-only") }),
+            )
+            .report()
+            .await;
+        assert!(!report.ready);
+        assert_eq!(report.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -11,6 +11,13 @@
 //! * [Keyserver Protocol](https://github.com/cashweb/specifications/blob/master/keyserver-protocol/specification.mediawiki)
 //! * [Relay Server Protocol](https://github.com/cashweb/specifications/blob/master/relay-server-protocol/specification.mediawiki)
 
+pub mod error;
+pub mod escrow;
+pub mod health;
+pub mod publish;
+pub mod rotation;
+pub mod vectors;
+
 #[doc(inline)]
 pub use auth_wrapper;
 #[doc(inline)]
@@ -18,10 +25,16 @@ pub use bitcoin;
 #[doc(inline)]
 pub use bitcoin_client;
 #[doc(inline)]
+pub use config;
+#[doc(inline)]
 pub use keyserver;
 #[doc(inline)]
 pub use keyserver_client;
 #[doc(inline)]
+pub use lifecycle;
+#[doc(inline)]
+pub use metrics;
+#[doc(inline)]
 pub use payments;
 #[doc(inline)]
 pub use relay;
@@ -31,3 +44,5 @@ pub use relay_client;
 pub use secp256k1;
 #[doc(inline)]
 pub use token;
+#[doc(inline)]
+pub use wallet;
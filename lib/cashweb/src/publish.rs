@@ -0,0 +1,828 @@
+//! This module contains [`Publisher`] which performs the complete flow for publishing
+//! [`AddressMetadata`] to a keyserver: fetching the payment request, funding and signing the
+//! payment transaction, broadcasting it, exchanging the payment for a POP token and finally
+//! putting the signed metadata.
+
+use std::{
+    error, fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use hyper::{
+    body::to_bytes,
+    http::{
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+        uri::InvalidUri,
+        Method,
+    },
+    Body, Request, Response, StatusCode, Uri,
+};
+use prost::Message as _;
+use secp256k1::{
+    key::{PublicKey, SecretKey},
+    Secp256k1,
+};
+use thiserror::Error;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+use crate::{
+    auth_wrapper::AuthWrapper,
+    bitcoin::{
+        transaction::{
+            input::Input,
+            outpoint::Outpoint,
+            output::Output,
+            script::Script,
+            sighash::{SighashCache, SighashParams},
+            SignatureHashType, Transaction,
+        },
+        Encodable,
+    },
+    bitcoin_client::{BitcoinClient, NodeError},
+    keyserver::{idempotency, AddressMetadata},
+    keyserver_client::{services::PutMetadataError, KeyserverClient, KeyserverError},
+    payments::{
+        bip70::{Payment, PaymentDetails, PaymentRequest},
+        builder::encode_message,
+        PAYMENT_ACK_MIME, PAYMENT_MIME,
+    },
+};
+
+/// Default fee rate, in satoshis per byte.
+pub const DEFAULT_FEE_PER_BYTE: u64 = 1;
+
+/// Default signature hash parameters, those of Bitcoin Cash.
+pub const DEFAULT_SIGHASH_PARAMS: SighashParams = SighashParams::BCH;
+
+/// Default maximum price paid for a POP token, in satoshis.
+pub const DEFAULT_MAX_PRICE: u64 = 100_000;
+
+/// Default network of payment requests, as named by BIP70.
+pub const DEFAULT_NETWORK: &str = "main";
+
+/// Outputs below this value, in satoshis, are not created.
+const DUST_LIMIT: u64 = 546;
+
+/// Upper bound on the length of a P2PKH `scriptSig`: a pushed 72 byte DER signature with its
+/// sighash byte and a pushed 33 byte compressed public key.
+const P2PKH_SCRIPT_SIG_LEN: usize = 1 + 73 + 1 + 33;
+
+/// Length of a P2PKH output: value, script length and a 25 byte script.
+const P2PKH_OUTPUT_LEN: usize = 8 + 1 + 25;
+
+/// An unspent output, spendable by the publishing key, used to fund the payment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Utxo {
+    /// The outpoint of the output.
+    pub outpoint: Outpoint,
+    /// The value, in satoshis.
+    pub value: u64,
+    /// The P2PKH script of the output.
+    pub script: Script,
+}
+
+/// Source of [`Utxo`]s spendable by the publishing key.
+#[async_trait]
+pub trait UtxoSource {
+    /// Error associated with fetching UTXOs.
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Fetch the available UTXOs, in order of preference.
+    async fn utxos(&self) -> Result<Vec<Utxo>, Self::Error>;
+}
+
+/// Error associated with an HTTP exchange with the keyserver.
+#[derive(Debug, Error)]
+pub enum RequestError<E: fmt::Debug + fmt::Display> {
+    /// Invalid URI.
+    #[error(transparent)]
+    Uri(InvalidUri),
+    /// A connection error occured.
+    #[error("connection failure: {0}")]
+    Service(E),
+    /// Error while processing the body.
+    #[error("processing body failed: {0}")]
+    Body(hyper::Error),
+    /// Unexpected status code.
+    #[error("unexpected status code: {0}")]
+    UnexpectedStatusCode(u16),
+    /// Failed to decode the response.
+    #[error("failed to decode response: {0}")]
+    Decode(prost::DecodeError),
+    /// The payment request did not specify a payment URL.
+    #[error("missing payment url")]
+    MissingPaymentUrl,
+    /// The payment URL is not on the origin of the keyserver.
+    #[error("payment url {0} is not on the keyserver's origin")]
+    ForeignPaymentUrl(String),
+    /// The payment request is for another network.
+    #[error("payment request for network {0}")]
+    WrongNetwork(String),
+    /// The payment request has expired.
+    #[error("payment request expired at {0}")]
+    Expired(u64),
+    /// The outputs of the payment request sum to more than the maximum price.
+    #[error("price {price} exceeds maximum {max_price}")]
+    PriceTooHigh {
+        /// Sum of the requested outputs, in satoshis.
+        price: u64,
+        /// The maximum price, in satoshis.
+        max_price: u64,
+    },
+    /// The outputs of the payment request overflow when summed.
+    #[error("requested amount overflows")]
+    AmountOverflow,
+    /// POP token missing from the payment response.
+    #[error("missing token")]
+    MissingToken,
+}
+
+/// Error associated with funding the payment transaction.
+#[derive(Debug, Error)]
+pub enum FundingError<U: fmt::Debug + fmt::Display> {
+    /// Failed to fetch UTXOs.
+    #[error("failed to fetch utxos: {0}")]
+    Source(U),
+    /// The requested amount and fee overflow when summed.
+    #[error("required amount overflows")]
+    Overflow,
+    /// The UTXOs do not cover the requested amount and fee.
+    #[error("insufficient funds: required {required}, available {available}")]
+    InsufficientFunds {
+        /// Amount required, including the fee, in satoshis.
+        required: u64,
+        /// Total value of the UTXOs, in satoshis.
+        available: u64,
+    },
+}
+
+/// Error associated with [`Publisher::publish`], tagged by the stage at which it occurred.
+///
+/// Errors after the payment transaction was built carry the progress made, so that the caller
+/// can recover the transaction, or the POP token, rather than paying again.
+#[derive(Debug, Error)]
+pub enum PublishError<E, U>
+where
+    E: fmt::Debug + fmt::Display + error::Error + 'static,
+    U: fmt::Debug + fmt::Display,
+{
+    /// Failed to fetch the payment request.
+    #[error("failed to fetch payment request: {0}")]
+    PaymentRequest(RequestError<E>),
+    /// Failed to fund the payment transaction.
+    #[error("failed to fund payment: {0}")]
+    Funding(FundingError<U>),
+    /// Failed to broadcast the payment transaction.
+    #[error("failed to broadcast transaction: {error}")]
+    Broadcast {
+        /// The signed payment transaction.
+        transaction: Transaction,
+        /// The broadcast error.
+        error: NodeError,
+    },
+    /// Failed to exchange the payment for a POP token.
+    #[error("failed to submit payment: {error}")]
+    Payment {
+        /// The broadcast payment transaction.
+        transaction: Transaction,
+        /// The submission error.
+        error: RequestError<E>,
+    },
+    /// Failed to put the metadata using the POP token.
+    #[error("failed to put metadata: {error}")]
+    PutMetadata {
+        /// The POP token obtained for the payment.
+        token: String,
+        /// The put error.
+        error: KeyserverError<PutMetadataError<E>>,
+    },
+}
+
+/// The result of each stage of a successful [`Publisher::publish`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishReceipt {
+    /// The payment request issued by the keyserver.
+    pub payment_request: PaymentRequest,
+    /// The payment details contained in the request.
+    pub payment_details: PaymentDetails,
+    /// The signed payment transaction.
+    pub transaction: Transaction,
+    /// The transaction ID returned by the broadcaster.
+    pub tx_id: String,
+    /// The POP token obtained for the payment.
+    pub token: String,
+}
+
+/// Sign [`AddressMetadata`] using ECDSA, producing the [`AuthWrapper`] to be put to the
+/// keyserver.
+pub fn sign_metadata(secret_key: &SecretKey, metadata: &AddressMetadata) -> AuthWrapper {
+    AuthWrapper::sign_ecdsa(secret_key, encode_message(metadata))
+}
+
+/// Sum the amounts of the outputs of the [`PaymentDetails`], returning `None` on overflow.
+fn requested_amount(details: &PaymentDetails) -> Option<u64> {
+    details.outputs.iter().try_fold(0u64, |sum, output| {
+        sum.checked_add(output.amount.unwrap_or_default())
+    })
+}
+
+/// Check that the [`PaymentDetails`] are for the network, unexpired at `now` and within the
+/// maximum price, returning the price.
+fn check_payment_details<E: fmt::Debug + fmt::Display>(
+    details: &PaymentDetails,
+    network: &str,
+    max_price: u64,
+    now: SystemTime,
+) -> Result<u64, RequestError<E>> {
+    if details.network() != network {
+        return Err(RequestError::WrongNetwork(details.network().to_string()));
+    }
+    if let Some(expires) = details.expires {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        if now > expires {
+            return Err(RequestError::Expired(expires));
+        }
+    }
+    let price = requested_amount(details).ok_or(RequestError::AmountOverflow)?;
+    if price > max_price {
+        return Err(RequestError::PriceTooHigh { price, max_price });
+    }
+    Ok(price)
+}
+
+/// Resolve the payment URL against the keyserver URL, requiring it to be on the same origin.
+fn resolve_payment_url<E: fmt::Debug + fmt::Display>(
+    keyserver_url: &str,
+    payment_url: Option<&str>,
+) -> Result<Uri, RequestError<E>> {
+    let payment_url = match payment_url {
+        Some(url) if url.starts_with('/') => format!("{}{}", keyserver_url, url),
+        Some(url) => url.to_string(),
+        None => return Err(RequestError::MissingPaymentUrl),
+    };
+    let payment_uri: Uri = payment_url.parse().map_err(RequestError::Uri)?;
+    let keyserver_uri: Uri = keyserver_url.parse().map_err(RequestError::Uri)?;
+    if payment_uri.scheme() != keyserver_uri.scheme()
+        || payment_uri.authority() != keyserver_uri.authority()
+    {
+        return Err(RequestError::ForeignPaymentUrl(payment_url));
+    }
+    Ok(payment_uri)
+}
+
+/// Construct a script pushing each of the items.
+fn push_script(items: &[&[u8]]) -> Script {
+    let mut script = Vec::new();
+    for item in items {
+        // Direct pushes suffice as items are signatures and public keys
+        script.push(item.len() as u8);
+        script.extend_from_slice(item);
+    }
+    Script::from(script)
+}
+
+/// Construct and sign a transaction paying the outputs of the [`PaymentDetails`], funded by the
+/// UTXOs in order, with change returned to the script of the first UTXO spent.
+fn build_transaction<U: fmt::Debug + fmt::Display>(
+    details: &PaymentDetails,
+    utxos: Vec<Utxo>,
+    secret_key: &SecretKey,
+    fee_per_byte: u64,
+    sighash_params: SighashParams,
+) -> Result<Transaction, FundingError<U>> {
+    let outputs: Vec<Output> = details
+        .outputs
+        .iter()
+        .map(|output| Output {
+            value: output.amount.unwrap_or_default(),
+            script: Script::from(output.script.clone()),
+        })
+        .collect();
+    let amount = requested_amount(details).ok_or(FundingError::Overflow)?;
+    let mut transaction = Transaction {
+        version: 1,
+        inputs: Vec::new(),
+        outputs,
+        lock_time: 0,
+    };
+
+    // Select UTXOs until the amount and fee, including a change output, are covered
+    let available = utxos
+        .iter()
+        .fold(0u64, |sum, utxo| sum.saturating_add(utxo.value));
+    let mut selected = Vec::new();
+    let mut total = 0u64;
+    let mut required = amount;
+    for utxo in utxos {
+        total = total.saturating_add(utxo.value);
+        transaction.inputs.push(Input {
+            outpoint: utxo.outpoint.clone(),
+            script: Script::default(),
+            sequence: u32::MAX,
+        });
+        selected.push(utxo);
+
+        let len = transaction.encoded_len()
+            + transaction.inputs.len() * P2PKH_SCRIPT_SIG_LEN
+            + P2PKH_OUTPUT_LEN;
+        required = (len as u64)
+            .checked_mul(fee_per_byte)
+            .and_then(|fee| amount.checked_add(fee))
+            .ok_or(FundingError::Overflow)?;
+        if total >= required {
+            break;
+        }
+    }
+    if selected.is_empty() || total < required {
+        return Err(FundingError::InsufficientFunds {
+            required,
+            available,
+        });
+    }
+
+    // Add change
+    let change = total - required;
+    if change >= DUST_LIMIT {
+        transaction.outputs.push(Output {
+            value: change,
+            script: selected[0].script.clone(),
+        });
+    }
+
+    // Sign inputs
+    let secp = Secp256k1::signing_only();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key).serialize();
+    let values = selected.iter().map(|utxo| utxo.value).collect();
+    let cache = SighashCache::with_params(&transaction, sighash_params, values);
+    let script_sigs: Vec<Script> = selected
+        .iter()
+        .enumerate()
+        .map(|(index, utxo)| {
+            // This is safe as the input, and the value it spends, exist
+            let signature = cache
+                .sign(
+                    &secp,
+                    index,
+                    &utxo.script,
+                    SignatureHashType::All,
+                    secret_key,
+                )
+                .unwrap();
+            push_script(&[&signature, &public_key])
+        })
+        .collect();
+    for (input, script_sig) in transaction.inputs.iter_mut().zip(script_sigs) {
+        input.script = script_sig;
+    }
+
+    Ok(transaction)
+}
+
+/// Publishes [`AddressMetadata`] to keyservers, paying for the POP token using [`Utxo`]s from a
+/// [`UtxoSource`] and broadcasting the payment via a [`BitcoinClient`].
+#[derive(Clone, Debug)]
+pub struct Publisher<S, U, B> {
+    service: S,
+    utxo_source: U,
+    broadcaster: B,
+    fee_per_byte: u64,
+    sighash_params: SighashParams,
+    max_price: u64,
+    network: String,
+}
+
+impl<S, U, B> Publisher<S, U, B> {
+    /// Create a new [`Publisher`] from an HTTP [`Service`], a [`UtxoSource`] and a
+    /// [`BitcoinClient`] used for broadcasting.
+    pub fn new(service: S, utxo_source: U, broadcaster: B) -> Self {
+        Self {
+            service,
+            utxo_source,
+            broadcaster,
+            fee_per_byte: DEFAULT_FEE_PER_BYTE,
+            sighash_params: DEFAULT_SIGHASH_PARAMS,
+            max_price: DEFAULT_MAX_PRICE,
+            network: DEFAULT_NETWORK.to_string(),
+        }
+    }
+
+    /// Set the fee rate, in satoshis per byte.
+    pub fn with_fee_per_byte(mut self, fee_per_byte: u64) -> Self {
+        self.fee_per_byte = fee_per_byte;
+        self
+    }
+
+    /// Set the signature hash parameters of the chain the payment is broadcast to.
+    pub fn with_sighash_params(mut self, sighash_params: SighashParams) -> Self {
+        self.sighash_params = sighash_params;
+        self
+    }
+
+    /// Set the maximum price paid for a POP token, in satoshis, excluding the fee.
+    pub fn with_max_price(mut self, max_price: u64) -> Self {
+        self.max_price = max_price;
+        self
+    }
+
+    /// Set the network payment requests must be for, such as "main", "test" or "regtest".
+    pub fn with_network<N: Into<String>>(mut self, network: N) -> Self {
+        self.network = network.into();
+        self
+    }
+}
+
+impl<S, U, B> Publisher<S, U, B>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S: Sync + Clone + Send + 'static,
+    S::Error: fmt::Debug + fmt::Display + error::Error + Send,
+    S::Future: Send,
+    U: UtxoSource + Sync,
+    B: BitcoinClient + Sync,
+{
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, RequestError<S::Error>> {
+        self.service
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(RequestError::Service)
+    }
+
+    /// Put the [`AuthWrapper`] without a token, expecting a [`PaymentRequest`] in response.
+    async fn fetch_payment_request(
+        &self,
+        uri: Uri,
+        auth_wrapper: &AuthWrapper,
+    ) -> Result<PaymentRequest, RequestError<S::Error>> {
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .body(Body::from(encode_message(auth_wrapper)))
+            .unwrap(); // This is safe
+        let response = self.send(request).await?;
+        match response.status() {
+            StatusCode::PAYMENT_REQUIRED => (),
+            code => return Err(RequestError::UnexpectedStatusCode(code.as_u16())),
+        }
+        let body = to_bytes(response.into_body())
+            .await
+            .map_err(RequestError::Body)?;
+        PaymentRequest::decode(body).map_err(RequestError::Decode)
+    }
+
+    /// Send the [`Payment`], expecting a POP token in response.
+    async fn submit_payment(
+        &self,
+        uri: Uri,
+        payment: &Payment,
+    ) -> Result<String, RequestError<S::Error>> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(CONTENT_TYPE, PAYMENT_MIME)
+            .header(ACCEPT, PAYMENT_ACK_MIME)
+            .body(Body::from(encode_message(payment)))
+            .unwrap(); // This is safe
+        let response = self.send(request).await?;
+        match response.status() {
+            StatusCode::OK => (),
+            code => return Err(RequestError::UnexpectedStatusCode(code.as_u16())),
+        }
+        response
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+            .ok_or(RequestError::MissingToken)
+    }
+
+    /// Publish the [`AddressMetadata`], signed by the secret key, to the keyserver.
+    ///
+    /// The payment request must be for the network, unexpired, within the maximum price and have
+    /// its payment URL on the keyserver's origin. The payment transaction is broadcast before being submitted to the keyserver. Its change,
+    /// if any, is returned to the script of the first UTXO spent.
+    pub async fn publish(
+        &self,
+        keyserver_url: &str,
+        address: &str,
+        secret_key: &SecretKey,
+        metadata: &AddressMetadata,
+    ) -> Result<PublishReceipt, PublishError<S::Error, U::Error>> {
+        let auth_wrapper = sign_metadata(secret_key, metadata);
+
+        // Fetch payment request
+        let full_path = format!("{}/keys/{}", keyserver_url, address);
+        let uri: Uri = full_path
+            .parse()
+            .map_err(|err| PublishError::PaymentRequest(RequestError::Uri(err)))?;
+        let payment_request = self
+            .fetch_payment_request(uri, &auth_wrapper)
+            .await
+            .map_err(PublishError::PaymentRequest)?;
+        let payment_details = payment_request
+            .payment_details()
+            .map_err(|err| PublishError::PaymentRequest(RequestError::Decode(err)))?;
+        check_payment_details(
+            &payment_details,
+            &self.network,
+            self.max_price,
+            SystemTime::now(),
+        )
+        .map_err(PublishError::PaymentRequest)?;
+        let payment_uri =
+            resolve_payment_url(keyserver_url, payment_details.payment_url.as_deref())
+                .map_err(PublishError::PaymentRequest)?;
+
+        // Build transaction
+        let utxos = self
+            .utxo_source
+            .utxos()
+            .await
+            .map_err(|err| PublishError::Funding(FundingError::Source(err)))?;
+        let transaction = build_transaction(
+            &payment_details,
+            utxos,
+            secret_key,
+            self.fee_per_byte,
+            self.sighash_params,
+        )
+        .map_err(PublishError::Funding)?;
+        let raw_transaction = encode_transaction(&transaction);
+
+        // Broadcast transaction
+        let tx_id = match self.broadcaster.send_tx(&raw_transaction).await {
+            Ok(ok) => ok,
+            Err(error) => return Err(PublishError::Broadcast { transaction, error }),
+        };
+
+        // Exchange payment for token
+        let payment = Payment {
+            merchant_data: payment_details.merchant_data.clone(),
+            transactions: vec![raw_transaction],
+            refund_to: Vec::new(),
+            memo: None,
+        };
+        let token = match self.submit_payment(payment_uri, &payment).await {
+            Ok(ok) => ok,
+            Err(error) => return Err(PublishError::Payment { transaction, error }),
+        };
+
+        // Put metadata, keyed so that a retry with the token can't overwrite a newer write
+        let idempotency_key = idempotency::derive_key(&encode_message(&auth_wrapper));
+        if let Err(error) = KeyserverClient::from_service(self.service.clone())
+            .put_metadata_idempotent(
+                keyserver_url,
+                address,
+                auth_wrapper,
+                token.clone(),
+                idempotency_key,
+            )
+            .await
+        {
+            return Err(PublishError::PutMetadata { token, error });
+        }
+
+        Ok(PublishReceipt {
+            payment_request,
+            payment_details,
+            transaction,
+            tx_id,
+            token,
+        })
+    }
+}
+
+/// Serialize a [`Transaction`].
+fn encode_transaction(transaction: &Transaction) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(transaction.encoded_len());
+    transaction.encode_raw(&mut raw);
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        sync::Mutex,
+        task::{Context, Poll},
+        time::{Duration, SystemTime},
+    };
+
+    use secp256k1::Message;
+
+    use crate::{
+        bitcoin_client::FeePolicy,
+        payments::{builder::PaymentDetailsBuilder, verification::verify_payment},
+    };
+
+    use super::*;
+
+    const PRICE: u64 = 1_000;
+
+    fn payment_details() -> PaymentDetails {
+        PaymentDetailsBuilder::new()
+            .output(vec![106, 1, 42], Some(PRICE))
+            .merchant_data(b"commitment".to_vec())
+            .payment_url("/payments")
+            .build()
+    }
+
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let token = request.headers().get(AUTHORIZATION).cloned();
+            let response = match (request.method(), request.uri().path(), token) {
+                (&Method::PUT, "/keys/alice", None) => Response::builder()
+                    .status(StatusCode::PAYMENT_REQUIRED)
+                    .body(Body::from(encode_message(&PaymentRequest::unsigned(
+                        &payment_details(),
+                    )))),
+                (&Method::PUT, "/keys/alice", Some(token)) if token == "POP paid" => {
+                    Response::builder().body(Body::empty())
+                }
+                (&Method::POST, "/payments", None) => Response::builder()
+                    .header(AUTHORIZATION, "POP paid")
+                    .body(Body::empty()),
+                _ => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::empty()),
+            };
+            ready(Ok(response.unwrap()))
+        }
+    }
+
+    struct MockUtxos(Vec<Utxo>);
+
+    #[async_trait]
+    impl UtxoSource for MockUtxos {
+        type Error = Infallible;
+
+        async fn utxos(&self) -> Result<Vec<Utxo>, Self::Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockBroadcaster(Mutex<Vec<Vec<u8>>>);
+
+    #[async_trait]
+    impl BitcoinClient for MockBroadcaster {
+        async fn send_tx_with_fee_policy(
+            &self,
+            raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            self.0.lock().unwrap().push(raw_tx.to_vec());
+            Ok("tx_id".to_string())
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+    }
+
+    fn utxo(value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                tx_id: [1; 32],
+                vout: 0,
+            },
+            value,
+            script: Script::from(vec![
+                118, 169, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 136, 172,
+            ]),
+        }
+    }
+
+    #[tokio::test]
+    async fn publish() {
+        let secret_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let metadata = AddressMetadata::default();
+        let publisher = Publisher::new(
+            MockKeyserver,
+            MockUtxos(vec![utxo(10_000)]),
+            MockBroadcaster::default(),
+        );
+
+        let receipt = publisher
+            .publish("http://keyserver", "alice", &secret_key, &metadata)
+            .await
+            .unwrap();
+        assert_eq!(receipt.token, "POP paid");
+        assert_eq!(receipt.tx_id, "tx_id");
+        assert_eq!(publisher.broadcaster.0.lock().unwrap().len(), 1);
+
+        let transaction = &receipt.transaction;
+        let fee = 10_000
+            - transaction
+                .outputs
+                .iter()
+                .map(|output| output.value)
+                .sum::<u64>();
+        assert!(fee > 0 && fee < 1_000);
+        assert_eq!(transaction.outputs[1].script, utxo(0).script);
+
+        // The input is signed with the fork ID algorithm, committing to the value spent
+        let script_sig = transaction.inputs[0].script.as_bytes();
+        let (hash_type, der) = script_sig[1..1 + script_sig[0] as usize]
+            .split_last()
+            .unwrap();
+        assert_eq!(*hash_type, 0x41);
+        let sig_hash = SighashCache::with_params(transaction, DEFAULT_SIGHASH_PARAMS, vec![10_000])
+            .signature_hash(0, &utxo(0).script, SignatureHashType::All)
+            .unwrap();
+        Secp256k1::verification_only()
+            .verify(
+                &Message::from_slice(&sig_hash).unwrap(),
+                &secp256k1::Signature::from_der(der).unwrap(),
+                &PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key),
+            )
+            .unwrap();
+
+        let payment = Payment {
+            merchant_data: receipt.payment_details.merchant_data.clone(),
+            transactions: vec![encode_transaction(transaction)],
+            refund_to: Vec::new(),
+            memo: None,
+        };
+        assert!(verify_payment(&payment_details(), &payment, SystemTime::now()).is_valid());
+
+        let publisher = Publisher::new(
+            MockKeyserver,
+            MockUtxos(vec![utxo(PRICE)]),
+            MockBroadcaster::default(),
+        );
+        assert!(matches!(
+            publisher
+                .publish("http://keyserver", "alice", &secret_key, &metadata)
+                .await,
+            Err(PublishError::Funding(FundingError::InsufficientFunds {
+                available: PRICE,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn payment_request_checks() {
+        type Error = RequestError<Infallible>;
+        let now = SystemTime::now();
+        let check = |details: &PaymentDetails, max_price| {
+            check_payment_details::<Infallible>(details, DEFAULT_NETWORK, max_price, now)
+        };
+        assert_eq!(check(&payment_details(), PRICE).unwrap(), PRICE);
+        assert!(matches!(
+            check(&payment_details(), PRICE - 1),
+            Err(Error::PriceTooHigh { price: PRICE, .. })
+        ));
+
+        let details = PaymentDetailsBuilder::new().network("test").build();
+        assert!(matches!(
+            check(&details, PRICE),
+            Err(Error::WrongNetwork(network)) if network == "test"
+        ));
+        let details = PaymentDetailsBuilder::new()
+            .time(now - Duration::from_secs(120))
+            .expires_after(Duration::from_secs(60))
+            .build();
+        assert!(matches!(check(&details, PRICE), Err(Error::Expired(_))));
+        let details = PaymentDetailsBuilder::new()
+            .output(vec![106], Some(u64::MAX))
+            .output(vec![106], Some(1))
+            .build();
+        assert!(matches!(
+            check(&details, u64::MAX),
+            Err(Error::AmountOverflow)
+        ));
+
+        let resolve = |url| resolve_payment_url::<Infallible>("http://keyserver", Some(url));
+        assert_eq!(
+            resolve("/payments").unwrap(),
+            "http://keyserver/payments".parse::<Uri>().unwrap()
+        );
+        assert!(resolve("http://keyserver/payments").is_ok());
+        for url in &["http://attacker/payments", "https://keyserver/payments"] {
+            assert!(matches!(resolve(url), Err(Error::ForeignPaymentUrl(_))));
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! This module contains [`AddressRotator`] which automates the rotation of payment addresses:
+//! deriving fresh receiving scripts from a [`Wallet`], packaging them as payment address entries,
+//! signing the resulting [`AddressMetadata`] and putting it to keyservers on a schedule.
+//!
+//! Scripts which have not received funds are republished rather than replaced, and fewer than
+//! the gap limit unused indices are derived past the last one used, so that a wallet recovered
+//! from its seed finds every payment.
+//!
+//! The entries are published in the [`payments`] namespace, leaving the root document untouched.
+//!
+//! [`payments`]: crate::keyserver::payment_address::PAYMENTS_NAMESPACE
+
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::Uri;
+use secp256k1::key::SecretKey;
+use tower_service::Service;
+
+use crate::{
+    bitcoin::transaction::script::Script,
+    bitcoin_client::BitcoinClient,
+    keyserver::{payment_address, AddressMetadata},
+    keyserver_client::{
+        services::{PutMetadata, PutMetadataError},
+        CachedPutError, KeyserverClient, TokenAcquirer, TokenCache,
+    },
+    lifecycle::ShutdownToken,
+    publish::sign_metadata,
+    wallet::{UtxoStore, Wallet},
+};
+
+/// Default number of payment addresses published per rotation.
+pub const DEFAULT_BATCH_SIZE: usize = 5;
+
+/// Default TTL of the published metadata, in milliseconds.
+pub const DEFAULT_TTL: i64 = 24 * 60 * 60 * 1_000;
+
+/// Default maximum number of unused external indices past the last one used, being the gap
+/// limit scanned by BIP44 wallets on recovery.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// The result of putting rotated metadata to a keyserver.
+pub type RotationResult<E, A> = Result<(), CachedPutError<PutMetadataError<E>, A>>;
+
+/// The outcome of a single [`AddressRotator::rotate`].
+#[derive(Debug)]
+pub struct RotationReceipt<E, A>
+where
+    E: fmt::Debug + fmt::Display + 'static,
+    A: fmt::Debug + fmt::Display,
+{
+    /// The indices, on the external chain, of the published scripts.
+    pub indices: Vec<u32>,
+    /// The signed metadata.
+    pub metadata: AddressMetadata,
+    /// The result of putting the metadata to each keyserver.
+    pub results: Vec<(String, RotationResult<E, A>)>,
+}
+
+impl<E, A> RotationReceipt<E, A>
+where
+    E: fmt::Debug + fmt::Display + 'static,
+    A: fmt::Debug + fmt::Display,
+{
+    /// Whether the metadata was put to every keyserver.
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+/// Publishes fresh payment addresses of a [`Wallet`] to keyservers.
+pub struct AddressRotator<S, A> {
+    client: KeyserverClient<S>,
+    acquirer: A,
+    tokens: TokenCache,
+    keyserver_urls: Vec<String>,
+    address: String,
+    secret_key: SecretKey,
+    batch_size: usize,
+    ttl: i64,
+    gap_limit: u32,
+    // The scripts last published, along with their external indices
+    published: Mutex<Vec<(u32, Script)>>,
+}
+
+impl<S, A> fmt::Debug for AddressRotator<S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Omit the key
+        f.debug_struct("AddressRotator")
+            .field("keyserver_urls", &self.keyserver_urls)
+            .field("address", &self.address)
+            .field("batch_size", &self.batch_size)
+            .field("ttl", &self.ttl)
+            .field("gap_limit", &self.gap_limit)
+            .finish()
+    }
+}
+
+impl<S, A> AddressRotator<S, A> {
+    /// Create a new [`AddressRotator`], publishing the metadata of the address, signed by the
+    /// secret key, to the keyservers. Tokens are acquired using the [`TokenAcquirer`].
+    pub fn new(
+        client: KeyserverClient<S>,
+        acquirer: A,
+        keyserver_urls: Vec<String>,
+        address: String,
+        secret_key: SecretKey,
+    ) -> Self {
+        Self {
+            client,
+            acquirer,
+            tokens: TokenCache::new(),
+            keyserver_urls,
+            address,
+            secret_key,
+            batch_size: DEFAULT_BATCH_SIZE,
+            ttl: DEFAULT_TTL,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            published: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Set the number of payment addresses published per rotation.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set the TTL of the published metadata, in milliseconds.
+    pub fn with_ttl(mut self, ttl: i64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the maximum number of unused external indices past the last one used, which should
+    /// not exceed the gap limit scanned when recovering the wallet.
+    pub fn with_gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = gap_limit;
+        self
+    }
+
+    /// The [`TokenCache`] holding the tokens acquired for each keyserver.
+    pub fn tokens(&self) -> &TokenCache {
+        &self.tokens
+    }
+}
+
+impl<S, A, E> AddressRotator<S, A>
+where
+    KeyserverClient<S>: Service<(Uri, PutMetadata), Response = (), Error = PutMetadataError<E>>,
+    KeyserverClient<S>: Sync + Clone + Send + 'static,
+    <KeyserverClient<S> as Service<(Uri, PutMetadata)>>::Future: Send + 'static,
+    E: fmt::Debug + fmt::Display + 'static,
+    A: TokenAcquirer + Sync,
+{
+    /// Publish a batch of receiving scripts of the wallet to every keyserver.
+    ///
+    /// The scripts of the last batch which have not received funds are republished, and the
+    /// batch is topped up with fresh scripts, deriving no more than the gap limit unused indices
+    /// past the last one used. Fresh scripts are marked as used by the wallet only if they were
+    /// put to at least one keyserver. The rotator should be the only consumer of the wallet's
+    /// external chain.
+    pub async fn rotate<St, B>(&self, wallet: &Wallet<St, B>) -> RotationReceipt<E, A::Error>
+    where
+        St: UtxoStore + Sync,
+        B: BitcoinClient + Sync,
+    {
+        // Keep the scripts yet to receive funds, keeping all of them if the store fails
+        let received = wallet.received_indices().await.unwrap_or_default();
+        let mut scripts: Vec<(u32, Script)> = self
+            .published
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(index, _)| !received.contains(index))
+            .cloned()
+            .collect();
+        let kept = scripts.len();
+
+        // Top up with fresh scripts, within the gap limit
+        let (next_index, _) = wallet.next_indices().await;
+        let first_unused = wallet.last_used_index().map_or(0, |index| index + 1);
+        let allowance = first_unused
+            .saturating_add(self.gap_limit)
+            .saturating_sub(next_index) as usize;
+        let count = self.batch_size.saturating_sub(kept).min(allowance);
+        scripts.extend(wallet.peek_receive_scripts(count).await);
+
+        let (indices, entries) = scripts
+            .iter()
+            .map(|(index, script)| {
+                let entry = payment_address::to_entry(script.as_bytes().to_vec());
+                (*index, entry)
+            })
+            .unzip();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap() // This is safe
+            .as_millis() as i64;
+        let metadata = AddressMetadata {
+            timestamp,
+            ttl: self.ttl,
+            entries,
+        };
+        let auth_wrapper = sign_metadata(&self.secret_key, &metadata);
+
+        let namespace = payment_address::payments_namespace();
+        let mut results = Vec::with_capacity(self.keyserver_urls.len());
+        for keyserver_url in &self.keyserver_urls {
+            let result = self
+                .client
+                .put_namespace_metadata_cached(
+                    &self.tokens,
+                    &self.acquirer,
+                    keyserver_url,
+                    &self.address,
+                    &namespace,
+                    auth_wrapper.clone(),
+                )
+                .await;
+            results.push((keyserver_url.clone(), result));
+        }
+
+        // Mark the fresh scripts as used only once published
+        if results.iter().any(|(_, result)| result.is_ok()) {
+            if let Some((index, _)) = scripts[kept..].last() {
+                wallet.advance_receive_index(index + 1).await;
+            }
+        } else {
+            scripts.truncate(kept);
+        }
+        *self.published.lock().unwrap() = scripts;
+
+        RotationReceipt {
+            indices,
+            metadata,
+            results,
+        }
+    }
+
+    /// Rotate immediately and then once every period, passing each receipt to `on_rotation`,
+    /// until shutdown.
+    pub async fn run<St, B, F>(
+        &self,
+        wallet: &Wallet<St, B>,
+        period: Duration,
+        shutdown: ShutdownToken,
+        mut on_rotation: F,
+    ) where
+        St: UtxoStore + Sync,
+        B: BitcoinClient + Sync,
+        F: FnMut(RotationReceipt<E, A::Error>),
+    {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = shutdown.clone().cancelled() => break,
+                _ = interval.tick() => on_rotation(self.rotate(wallet).await),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use async_trait::async_trait;
+    use hyper::{header::AUTHORIZATION, Body, Method, Request, Response, StatusCode};
+
+    use crate::{
+        auth_wrapper::AuthWrapper,
+        bitcoin::{bip32::ExtendedPrivateKey, transaction::outpoint::Outpoint},
+        bitcoin_client::{BitcoinClient, FeePolicy, NodeError},
+        keyserver_client::CachedToken,
+        wallet::{Account, KeyChain, MemoryUtxoStore, WalletUtxo},
+    };
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MockKeyserver;
+
+    impl Service<Request<Body>> for MockKeyserver {
+        type Response = Response<Body>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<Body>) -> Self::Future {
+            let token = request.headers().get(AUTHORIZATION).cloned();
+            let status = match (request.method(), request.uri().path(), token) {
+                (&Method::PUT, "/keys/alice/payments", Some(token)) if token == "POP paid" => {
+                    StatusCode::OK
+                }
+                _ => StatusCode::PAYMENT_REQUIRED,
+            };
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            ready(Ok(response))
+        }
+    }
+
+    struct MockAcquirer;
+
+    #[async_trait]
+    impl TokenAcquirer for MockAcquirer {
+        type Error = Infallible;
+
+        async fn acquire(
+            &self,
+            _keyserver_url: &str,
+            _address: &str,
+            _auth_wrapper: &AuthWrapper,
+        ) -> Result<CachedToken, Self::Error> {
+            Ok(CachedToken {
+                token: "POP paid".to_string(),
+                expires_at: None,
+            })
+        }
+    }
+
+    struct MockBroadcaster;
+
+    #[async_trait]
+    impl BitcoinClient for MockBroadcaster {
+        async fn send_tx_with_fee_policy(
+            &self,
+            _raw_tx: &[u8],
+            _fee_policy: FeePolicy,
+        ) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_new_addr(&self) -> Result<String, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+
+        async fn get_raw_transaction(&self, _tx_id: &[u8]) -> Result<Vec<u8>, NodeError> {
+            Err(NodeError::EmptyResponse)
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster);
+        let rotator = AddressRotator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MockAcquirer,
+            vec!["http://keyserver".to_string()],
+            "alice".to_string(),
+            SecretKey::from_slice(&[3; 32]).unwrap(),
+        )
+        .with_batch_size(2);
+
+        let receipt = rotator.rotate(&wallet).await;
+        assert!(receipt.is_complete());
+        assert_eq!(receipt.indices, vec![0, 1]);
+        assert_eq!(wallet.next_indices().await, (2, 0));
+
+        // Unpaid scripts are republished
+        let receipt = rotator.rotate(&wallet).await;
+        assert_eq!(receipt.indices, vec![0, 1]);
+        assert_eq!(wallet.next_indices().await, (2, 0));
+
+        // Paid scripts are replaced
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        wallet
+            .store()
+            .insert(WalletUtxo {
+                outpoint: Outpoint {
+                    tx_id: [4; 32],
+                    vout: 0,
+                },
+                value: 10_000,
+                script: account.script(KeyChain::External, 0),
+                chain: KeyChain::External,
+                index: 0,
+            })
+            .await
+            .unwrap();
+        let receipt = rotator.rotate(&wallet).await;
+        assert!(receipt.is_complete());
+        assert_eq!(receipt.indices, vec![1, 2]);
+        assert_eq!(wallet.next_indices().await, (3, 0));
+
+        // The published scripts are those of the wallet's external chain
+        let scripts: Vec<_> = payment_address::payment_scripts(&receipt.metadata).collect();
+        assert_eq!(
+            scripts,
+            vec![
+                account.script(KeyChain::External, 1).as_bytes(),
+                account.script(KeyChain::External, 2).as_bytes(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gap_limit() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        let wallet = Wallet::new(account, MemoryUtxoStore::new(), MockBroadcaster);
+
+        // Indices are not advanced if every put fails
+        let failing = AddressRotator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MockAcquirer,
+            vec!["http://keyserver".to_string()],
+            "bob".to_string(),
+            SecretKey::from_slice(&[3; 32]).unwrap(),
+        );
+        let receipt = failing.rotate(&wallet).await;
+        assert!(!receipt.is_complete());
+        assert_eq!(wallet.next_indices().await, (0, 0));
+
+        // Fresh scripts stop at the gap limit
+        let rotator = AddressRotator::new(
+            KeyserverClient::from_service(MockKeyserver),
+            MockAcquirer,
+            vec!["http://keyserver".to_string()],
+            "alice".to_string(),
+            SecretKey::from_slice(&[3; 32]).unwrap(),
+        )
+        .with_batch_size(5)
+        .with_gap_limit(3);
+        assert_eq!(rotator.rotate(&wallet).await.indices, vec![0, 1, 2]);
+        assert_eq!(wallet.next_indices().await, (3, 0));
+
+        let account = Account::new(ExtendedPrivateKey::new_master(secret_key, [2; 32]));
+        wallet
+            .store()
+            .insert(WalletUtxo {
+                outpoint: Outpoint {
+                    tx_id: [4; 32],
+                    vout: 0,
+                },
+                value: 10_000,
+                script: account.script(KeyChain::External, 1),
+                chain: KeyChain::External,
+                index: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rotator.rotate(&wallet).await.indices, vec![0, 2, 3, 4]);
+        assert_eq!(wallet.next_indices().await, (5, 0));
+    }
+}
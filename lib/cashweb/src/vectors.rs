@@ -0,0 +1,595 @@
+//! This module contains [`TestVectors`], canonical test vectors generated from fixed keys, shared
+//! with the other implementations of the cash:web protocols.
+//!
+//! The vectors cover signed [`AuthWrapper`]s, HMAC bearer and POP tokens, and signature hashes.
+//! They serialize to JSON with every byte string hex encoded, so that the JS and mobile
+//! implementations can check themselves against them, and [`TestVectors::validate`] checks
+//! vectors produced elsewhere against this implementation. Drift between implementations then
+//! fails the CI of whichever consumer drifted.
+//!
+//! The generated vectors are checked in as `vectors.json`, at the root of this crate, and
+//! regenerated with:
+//!
+//! ```ignore
+//! let json = serde_json::to_string_pretty(&TestVectors::generate())?;
+//! ```
+
+use prost::Message as _;
+use secp256k1::{key::SecretKey, Secp256k1};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    auth_wrapper::AuthWrapper,
+    bitcoin::transaction::{
+        input::Input,
+        outpoint::Outpoint,
+        output::Output,
+        script::Script,
+        sighash::{SighashAlgorithm, SighashCache, SighashParams},
+        SignatureHashType, Transaction,
+    },
+    keyserver::{payment_address, AddressMetadata},
+    payments::builder::encode_message,
+    token::schemes::{hmac_bearer::HmacScheme, hmac_bearer::MacAlgorithm, pop},
+};
+
+/// Version of the format of the [`TestVectors`].
+pub const VECTORS_VERSION: u32 = 1;
+
+/// Error associated with validating [`TestVectors`].
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum VectorError {
+    /// The vectors were of an unsupported version.
+    #[error("unsupported version {0}")]
+    Version(u32),
+    /// A field of a vector was not hex.
+    #[error("{name}: {field} is not hex")]
+    Hex {
+        /// Name of the vector.
+        name: String,
+        /// The field.
+        field: &'static str,
+    },
+    /// A field of a vector was invalid.
+    #[error("{name}: invalid {field}")]
+    Invalid {
+        /// Name of the vector.
+        name: String,
+        /// The field.
+        field: &'static str,
+    },
+    /// A field of a vector differed from that computed by this implementation.
+    #[error("{name}: {field} mismatch")]
+    Mismatch {
+        /// Name of the vector.
+        name: String,
+        /// The field.
+        field: &'static str,
+    },
+}
+
+/// An [`AuthWrapper`] signed by a fixed key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthWrapperVector {
+    /// Name of the vector.
+    pub name: String,
+    /// The secret key, hex encoded.
+    pub secret_key: String,
+    /// The payload, hex encoded.
+    pub payload: String,
+    /// The serialized public key of the secret key, hex encoded.
+    pub public_key: String,
+    /// The compact ECDSA signature, hex encoded.
+    pub signature: String,
+    /// The protobuf encoded [`AuthWrapper`], hex encoded.
+    pub wrapper: String,
+}
+
+/// An HMAC bearer token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HmacTokenVector {
+    /// Name of the vector.
+    pub name: String,
+    /// The MAC algorithm, either `hmac-sha256` or `hmac-sha512`.
+    pub algorithm: String,
+    /// The key, hex encoded.
+    pub key: String,
+    /// The data covered by the token, hex encoded.
+    pub data: String,
+    /// The URL safe base64 token.
+    pub token: String,
+}
+
+/// A POP token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PopTokenVector {
+    /// Name of the vector.
+    pub name: String,
+    /// ID of the payment transaction, hex encoded.
+    pub tx_id: String,
+    /// Index of the payment output.
+    pub vout: u32,
+    /// Amount paid, in satoshis.
+    pub amount: u64,
+    /// The URL safe base64 token.
+    pub token: String,
+}
+
+/// The signature hash, and signature, of an input.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SighashVector {
+    /// Name of the vector.
+    pub name: String,
+    /// The fork ID of the `SIGHASH_FORKID` algorithm, or none for the legacy algorithm.
+    pub fork_id: Option<u32>,
+    /// The transaction, hex encoded.
+    pub transaction: String,
+    /// Index of the input signed.
+    pub input_index: usize,
+    /// The script code, hex encoded.
+    pub script_code: String,
+    /// Value of the output spent, in satoshis.
+    pub value: u64,
+    /// The signature hash type, excluding the `SIGHASH_FORKID` flag.
+    pub sig_hash_type: u8,
+    /// The signature hash, hex encoded.
+    pub sighash: String,
+    /// The secret key, hex encoded.
+    pub secret_key: String,
+    /// The DER signature followed by the signature hash type, hex encoded.
+    pub signature: String,
+}
+
+/// The canonical test vectors.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// Version of the format, see [`VECTORS_VERSION`].
+    pub version: u32,
+    /// Signed [`AuthWrapper`]s.
+    pub auth_wrappers: Vec<AuthWrapperVector>,
+    /// HMAC bearer tokens.
+    pub hmac_tokens: Vec<HmacTokenVector>,
+    /// POP tokens.
+    pub pop_tokens: Vec<PopTokenVector>,
+    /// Signature hashes.
+    pub sighashes: Vec<SighashVector>,
+}
+
+fn decode_hex(name: &str, field: &'static str, hex: &str) -> Result<Vec<u8>, VectorError> {
+    hex::decode(hex).map_err(|_| VectorError::Hex {
+        name: name.to_string(),
+        field,
+    })
+}
+
+fn decode_secret_key(name: &str, hex: &str) -> Result<SecretKey, VectorError> {
+    let raw_secret_key = decode_hex(name, "secret_key", hex)?;
+    SecretKey::from_slice(&raw_secret_key).map_err(|_| VectorError::Invalid {
+        name: name.to_string(),
+        field: "secret_key",
+    })
+}
+
+fn check(name: &str, field: &'static str, expected: &str, found: &str) -> Result<(), VectorError> {
+    if expected.eq_ignore_ascii_case(found) {
+        Ok(())
+    } else {
+        Err(VectorError::Mismatch {
+            name: name.to_string(),
+            field,
+        })
+    }
+}
+
+fn mac_algorithm(name: &str) -> Option<MacAlgorithm> {
+    match name {
+        "hmac-sha256" => Some(MacAlgorithm::HmacSha256),
+        "hmac-sha512" => Some(MacAlgorithm::HmacSha512),
+        _ => None,
+    }
+}
+
+fn sig_hash_type_from_u8(sig_hash_type: u8) -> Option<SignatureHashType> {
+    Some(match sig_hash_type {
+        0x01 => SignatureHashType::All,
+        0x02 => SignatureHashType::None,
+        0x03 => SignatureHashType::Single,
+        0x81 => SignatureHashType::AnyoneCanPayAll,
+        0x82 => SignatureHashType::AnyoneCanPayNone,
+        0x83 => SignatureHashType::AnyoneCanPaySingle,
+        _ => return None,
+    })
+}
+
+fn sighash_params(fork_id: Option<u32>) -> SighashParams {
+    match fork_id {
+        Some(fork_id) => SighashParams {
+            algorithm: SighashAlgorithm::ForkId,
+            fork_id,
+        },
+        None => SighashParams::LEGACY,
+    }
+}
+
+impl AuthWrapperVector {
+    fn generate(name: &str, secret_key: [u8; 32], payload: Vec<u8>) -> Self {
+        // This is safe as the fixed keys are valid
+        let auth_wrapper =
+            AuthWrapper::sign_ecdsa(&SecretKey::from_slice(&secret_key).unwrap(), payload);
+        Self {
+            name: name.to_string(),
+            secret_key: hex::encode(secret_key),
+            payload: hex::encode(&auth_wrapper.payload),
+            public_key: hex::encode(&auth_wrapper.public_key),
+            signature: hex::encode(&auth_wrapper.signature),
+            wrapper: hex::encode(encode_message(&auth_wrapper)),
+        }
+    }
+
+    fn validate(&self) -> Result<(), VectorError> {
+        let name = &self.name;
+        let secret_key = decode_secret_key(name, &self.secret_key)?;
+        let payload = decode_hex(name, "payload", &self.payload)?;
+        let raw_wrapper = decode_hex(name, "wrapper", &self.wrapper)?;
+        let invalid_wrapper = || VectorError::Invalid {
+            name: name.to_string(),
+            field: "wrapper",
+        };
+        AuthWrapper::decode(&raw_wrapper[..])
+            .map_err(|_| invalid_wrapper())?
+            .parse()
+            .map_err(|_| invalid_wrapper())?
+            .verify()
+            .map_err(|_| invalid_wrapper())?;
+
+        let auth_wrapper = AuthWrapper::sign_ecdsa(&secret_key, payload);
+        check(
+            name,
+            "public_key",
+            &self.public_key,
+            &hex::encode(&auth_wrapper.public_key),
+        )?;
+        check(
+            name,
+            "signature",
+            &self.signature,
+            &hex::encode(&auth_wrapper.signature),
+        )?;
+        check(
+            name,
+            "wrapper",
+            &self.wrapper,
+            &hex::encode(encode_message(&auth_wrapper)),
+        )
+    }
+}
+
+impl HmacTokenVector {
+    fn generate(algorithm: &str, key: &[u8], data: &[u8]) -> Self {
+        // This is safe as the algorithms generated are known
+        let scheme = HmacScheme::new(key).with_algorithm(mac_algorithm(algorithm).unwrap());
+        Self {
+            name: algorithm.to_string(),
+            algorithm: algorithm.to_string(),
+            key: hex::encode(key),
+            data: hex::encode(data),
+            token: scheme.construct_token(data),
+        }
+    }
+
+    fn validate(&self) -> Result<(), VectorError> {
+        let name = &self.name;
+        let algorithm = mac_algorithm(&self.algorithm).ok_or_else(|| VectorError::Invalid {
+            name: name.to_string(),
+            field: "algorithm",
+        })?;
+        let key = decode_hex(name, "key", &self.key)?;
+        let data = decode_hex(name, "data", &self.data)?;
+        let scheme = HmacScheme::new(&key).with_algorithm(algorithm);
+        // Tokens are base64, so compared exactly
+        if scheme.construct_token(&data) != self.token {
+            return Err(VectorError::Mismatch {
+                name: name.to_string(),
+                field: "token",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PopTokenVector {
+    fn generate(name: &str, tx_id: [u8; 32], vout: u32, amount: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            tx_id: hex::encode(tx_id),
+            vout,
+            amount,
+            token: pop::construct_token(&tx_id, vout, amount),
+        }
+    }
+
+    fn validate(&self) -> Result<(), VectorError> {
+        let tx_id = decode_hex(&self.name, "tx_id", &self.tx_id)?;
+        if pop::construct_token(&tx_id, self.vout, self.amount) != self.token {
+            return Err(VectorError::Mismatch {
+                name: self.name.clone(),
+                field: "token",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SighashVector {
+    #[allow(clippy::too_many_arguments)]
+    fn generate(
+        name: &str,
+        fork_id: Option<u32>,
+        transaction: &Transaction,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sig_hash_type: SignatureHashType,
+        secret_key: [u8; 32],
+    ) -> Self {
+        let mut values = vec![0; transaction.inputs.len()];
+        values[input_index] = value;
+        let cache = SighashCache::with_params(transaction, sighash_params(fork_id), values);
+        // These are safe as the fixed inputs and keys are valid
+        let sighash = cache
+            .signature_hash(input_index, script_code, sig_hash_type.clone())
+            .unwrap();
+        let signature = cache
+            .sign(
+                &Secp256k1::signing_only(),
+                input_index,
+                script_code,
+                sig_hash_type.clone(),
+                &SecretKey::from_slice(&secret_key).unwrap(),
+            )
+            .unwrap();
+        Self {
+            name: name.to_string(),
+            fork_id,
+            transaction: transaction.to_hex(),
+            input_index,
+            script_code: hex::encode(script_code.as_bytes()),
+            value,
+            sig_hash_type: sig_hash_type as u8,
+            sighash: hex::encode(sighash),
+            secret_key: hex::encode(secret_key),
+            signature: hex::encode(signature),
+        }
+    }
+
+    fn validate(&self) -> Result<(), VectorError> {
+        let name = &self.name;
+        let invalid = |field| VectorError::Invalid {
+            name: name.to_string(),
+            field,
+        };
+        let transaction =
+            Transaction::from_hex(&self.transaction).map_err(|_| VectorError::Hex {
+                name: name.to_string(),
+                field: "transaction",
+            })?;
+        let script_code = Script::from(decode_hex(name, "script_code", &self.script_code)?);
+        let sig_hash_type =
+            sig_hash_type_from_u8(self.sig_hash_type).ok_or_else(|| invalid("sig_hash_type"))?;
+        let secret_key = decode_secret_key(name, &self.secret_key)?;
+        if self.input_index >= transaction.inputs.len() {
+            return Err(invalid("input_index"));
+        }
+
+        let mut values = vec![0; transaction.inputs.len()];
+        values[self.input_index] = self.value;
+        let cache = SighashCache::with_params(&transaction, sighash_params(self.fork_id), values);
+        // These are safe as the input exists
+        let sighash = cache
+            .signature_hash(self.input_index, &script_code, sig_hash_type.clone())
+            .unwrap();
+        check(name, "sighash", &self.sighash, &hex::encode(sighash))?;
+        let signature = cache
+            .sign(
+                &Secp256k1::signing_only(),
+                self.input_index,
+                &script_code,
+                sig_hash_type,
+                &secret_key,
+            )
+            .unwrap();
+        check(name, "signature", &self.signature, &hex::encode(signature))
+    }
+}
+
+/// The transaction whose inputs are signed by the signature hash vectors.
+fn sighash_transaction() -> Transaction {
+    let input = |tx_id, vout, sequence| Input {
+        outpoint: Outpoint { tx_id, vout },
+        script: Script::default(),
+        sequence,
+    };
+    Transaction {
+        version: 2,
+        inputs: vec![
+            input([0x33; 32], 0, u32::MAX),
+            input([0x44; 32], 3, 0xffff_fffe),
+        ],
+        outputs: vec![
+            Output {
+                value: 50_000,
+                script: Script::p2pkh(&[0x55; 20]),
+            },
+            Output {
+                value: 12_345,
+                script: Script::p2sh(&[0x66; 20]),
+            },
+        ],
+        lock_time: 700_000,
+    }
+}
+
+impl TestVectors {
+    /// Generate the canonical test vectors.
+    pub fn generate() -> Self {
+        let empty_metadata = AddressMetadata {
+            timestamp: 1_600_000_000_000,
+            ttl: 24 * 60 * 60 * 1_000,
+            entries: Vec::new(),
+        };
+        let payment_metadata = AddressMetadata {
+            entries: vec![payment_address::to_entry(
+                Script::p2pkh(&[0x22; 20]).as_bytes().to_vec(),
+            )],
+            ..empty_metadata.clone()
+        };
+        let auth_wrappers = vec![
+            AuthWrapperVector::generate(
+                "empty-metadata",
+                [0x11; 32],
+                encode_message(&empty_metadata),
+            ),
+            AuthWrapperVector::generate(
+                "payment-address-metadata",
+                [0x11; 32],
+                encode_message(&payment_metadata),
+            ),
+            AuthWrapperVector::generate("raw-payload", [0x12; 32], b"cash:web".to_vec()),
+        ];
+
+        let hmac_tokens = vec![
+            HmacTokenVector::generate("hmac-sha256", &[0x21; 32], b"/keys/qz6r2y5p8w0m"),
+            HmacTokenVector::generate("hmac-sha512", &[0x21; 32], b"/keys/qz6r2y5p8w0m"),
+        ];
+
+        let pop_tokens = vec![
+            PopTokenVector::generate("first-output", [0x31; 32], 0, 1_000),
+            PopTokenVector::generate("large-amount", [0x32; 32], 7, 21_000_000 * 100_000_000),
+        ];
+
+        let transaction = sighash_transaction();
+        let script_code = Script::p2pkh(&[0x77; 20]);
+        let sighashes = vec![
+            SighashVector::generate(
+                "legacy-all",
+                None,
+                &transaction,
+                0,
+                &script_code,
+                60_000,
+                SignatureHashType::All,
+                [0x41; 32],
+            ),
+            SighashVector::generate(
+                "legacy-single",
+                None,
+                &transaction,
+                1,
+                &script_code,
+                10_000,
+                SignatureHashType::Single,
+                [0x41; 32],
+            ),
+            SighashVector::generate(
+                "forkid-all",
+                Some(0),
+                &transaction,
+                0,
+                &script_code,
+                60_000,
+                SignatureHashType::All,
+                [0x41; 32],
+            ),
+            SighashVector::generate(
+                "forkid-anyone-can-pay-single",
+                Some(0),
+                &transaction,
+                1,
+                &script_code,
+                10_000,
+                SignatureHashType::AnyoneCanPaySingle,
+                [0x41; 32],
+            ),
+        ];
+
+        Self {
+            version: VECTORS_VERSION,
+            auth_wrappers,
+            hmac_tokens,
+            pop_tokens,
+            sighashes,
+        }
+    }
+
+    /// Check each vector against this implementation, returning the first which differs.
+    pub fn validate(&self) -> Result<(), VectorError> {
+        if self.version != VECTORS_VERSION {
+            return Err(VectorError::Version(self.version));
+        }
+        for vector in &self.auth_wrappers {
+            vector.validate()?;
+        }
+        for vector in &self.hmac_tokens {
+            vector.validate()?;
+        }
+        for vector in &self.pop_tokens {
+            vector.validate()?;
+        }
+        for vector in &self.sighashes {
+            vector.validate()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_vectors_validate() {
+        let vectors = TestVectors::generate();
+        vectors.validate().unwrap();
+        // Generation is deterministic
+        assert_eq!(TestVectors::generate(), vectors);
+
+        let json = serde_json::to_string(&vectors).unwrap();
+        let decoded: TestVectors = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, vectors);
+    }
+
+    #[test]
+    fn checked_in_vectors() {
+        let vectors: TestVectors = serde_json::from_str(include_str!("../vectors.json")).unwrap();
+        vectors.validate().unwrap();
+        assert_eq!(vectors, TestVectors::generate());
+    }
+
+    #[test]
+    fn drift_detected() {
+        let mut vectors = TestVectors::generate();
+        vectors.sighashes[2].value += 1;
+        assert_eq!(
+            vectors.validate(),
+            Err(VectorError::Mismatch {
+                name: "forkid-all".to_string(),
+                field: "sighash",
+            })
+        );
+
+        let mut vectors = TestVectors::generate();
+        vectors.auth_wrappers[0].payload.push_str("00");
+        assert_eq!(
+            vectors.validate(),
+            Err(VectorError::Mismatch {
+                name: "empty-metadata".to_string(),
+                field: "signature",
+            })
+        );
+
+        let mut vectors = TestVectors::generate();
+        vectors.version += 1;
+        assert_eq!(vectors.validate(), Err(VectorError::Version(2)));
+    }
+}
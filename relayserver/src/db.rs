@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use cashweb::{
     auth_wrapper::AuthWrapper,
-    relay::{Message, MessagePage},
+    relay::{DeliveryReceipt, Filters, Message, MessagePage},
 };
 use prost::Message as _;
 use rocksdb::{Direction, Error as RocksError, IteratorMode, Options, DB};
@@ -11,9 +11,13 @@ const DIGEST_LEN: usize = 4;
 const NAMESPACE_LEN: usize = 20 + 1;
 
 const DIGEST_NAMESPACE: u8 = b'd';
+const RECEIPT_NAMESPACE: u8 = b'a';
 pub const FEED_NAMESPACE: u8 = b'f';
 pub const MESSAGE_NAMESPACE: u8 = b'm';
 const PROFILE_NAMESPACE: u8 = b'p';
+const FILTERS_NAMESPACE: u8 = b'b';
+// Keys are otherwise prefixed by a 20 byte hash, so a single byte key cannot collide
+const HEALTH_KEY: u8 = b'h';
 
 #[derive(Clone)]
 pub struct Database(Arc<DB>);
@@ -110,6 +114,63 @@ impl Database {
         }
     }
 
+    pub fn push_receipt(
+        &self,
+        pubkey_hash: &[u8],
+        receipt: &DeliveryReceipt,
+    ) -> Result<(), RocksError> {
+        // Receipts are ordered by the time of acknowledgement, allowing those expired to be pruned
+        let raw_timestamp: [u8; 8] = (receipt.acknowledged_time.max(0) as u64).to_be_bytes();
+        let key = [
+            pubkey_hash,
+            &[RECEIPT_NAMESPACE],
+            &raw_timestamp,
+            &receipt.payload_digest[..],
+        ]
+        .concat();
+        let mut raw_receipt = Vec::with_capacity(receipt.encoded_len());
+        receipt.encode(&mut raw_receipt).unwrap(); // This is safe
+        self.0.put(key, raw_receipt)
+    }
+
+    pub fn get_receipts(
+        &self,
+        pubkey_hash: &[u8],
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<DeliveryReceipt>, RocksError> {
+        let prefix = [pubkey_hash, &[RECEIPT_NAMESPACE]].concat();
+        let receipts = self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .skip(offset)
+            .take(limit)
+            .map(|(_, item)| {
+                DeliveryReceipt::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
+            })
+            .collect();
+        Ok(receipts)
+    }
+
+    /// Remove the receipts held for an address which were acknowledged before a timestamp.
+    pub fn remove_receipts_before(
+        &self,
+        pubkey_hash: &[u8],
+        timestamp: u64,
+    ) -> Result<(), RocksError> {
+        let prefix = [pubkey_hash, &[RECEIPT_NAMESPACE]].concat();
+        let end_prefix = [&prefix[..], &timestamp.to_be_bytes()].concat();
+        let iter = self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(&prefix) && key[..] < end_prefix[..]);
+        for (key, _) in iter {
+            self.0.delete(key)?;
+        }
+        Ok(())
+    }
+
     pub fn get_message_by_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
         self.0.get(key)
     }
@@ -118,35 +179,34 @@ impl Database {
         &self,
         start_prefix: &[u8],
         opt_end_prefix: Option<&[u8]>,
+        offset: usize,
+        limit: Option<usize>,
     ) -> Result<MessagePage, RocksError> {
         let namespace = &start_prefix[..NAMESPACE_LEN]; // addr || msg namespace byte
 
         // Check whether key is within namespace
         let in_namespace = |key: &[u8]| key[..NAMESPACE_LEN] == namespace[..];
 
+        // Check whether key is before end time
+        let before_end_key = |key: &[u8]| match opt_end_prefix {
+            Some(end_prefix) => key[NAMESPACE_LEN..] < end_prefix[NAMESPACE_LEN..],
+            None => true,
+        };
+
         // Init iterator
         let iter = self
             .0
             .iterator(IteratorMode::From(start_prefix, Direction::Forward));
 
-        let messages: Vec<Message> = if let Some(end_prefix) = opt_end_prefix {
-            // Check whether key is before end time
-            let before_end_key = |key: &[u8]| key[NAMESPACE_LEN..] < end_prefix[NAMESPACE_LEN..];
-
-            // Take items inside namespace and before end time
-            iter.take_while(|(key, _)| in_namespace(key) && before_end_key(key))
-                .map(|(_, item)| {
-                    Message::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
-                })
-                .collect()
-        } else {
-            // Take items inside namespace
-            iter.take_while(|(key, _)| in_namespace(key))
-                .map(|(_, item)| {
-                    Message::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
-                })
-                .collect()
-        };
+        // Take items inside namespace and before end time, skipping the offset
+        let messages: Vec<Message> = iter
+            .take_while(|(key, _)| in_namespace(key) && before_end_key(key))
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(_, item)| {
+                Message::decode(&item[..]).unwrap() // This panics if stored bytes are malformed
+            })
+            .collect();
 
         let mut message_page = MessagePage::default();
         if let Some(message) = messages.first() {
@@ -155,9 +215,9 @@ impl Database {
             message_page.start_digest = payload_digest.to_vec();
         }
         if let Some(message) = messages.last() {
-            message_page.start_time = message.received_time;
+            message_page.end_time = message.received_time;
             let payload_digest = message.digest().unwrap(); // This is safe
-            message_page.start_digest = payload_digest.to_vec();
+            message_page.end_digest = payload_digest.to_vec();
         }
         message_page.messages = messages;
         Ok(message_page)
@@ -221,6 +281,31 @@ impl Database {
 
         self.0.put(key, raw_profile)
     }
+
+    pub fn get_raw_filters(&self, addr: &[u8]) -> Result<Option<Vec<u8>>, RocksError> {
+        // Prefix key
+        let key = [addr, &[FILTERS_NAMESPACE]].concat();
+
+        self.0.get(key)
+    }
+
+    pub fn get_filters(&self, addr: &[u8]) -> Result<Option<Filters>, RocksError> {
+        self.get_raw_filters(addr).map(|raw_filters_opt| {
+            raw_filters_opt.and_then(|raw_filters| Filters::decode(&raw_filters[..]).ok())
+        })
+    }
+
+    pub fn put_filters(&self, addr: &[u8], raw_filters: &[u8]) -> Result<(), RocksError> {
+        // Prefix key
+        let key = [addr, &[FILTERS_NAMESPACE]].concat();
+
+        self.0.put(key, raw_filters)
+    }
+
+    /// Write to a reserved key, checking that the database is writable.
+    pub fn probe_write(&self) -> Result<(), RocksError> {
+        self.0.put([HEALTH_KEY], [])
+    }
 }
 
 #[cfg(test)]
@@ -335,7 +420,10 @@ mod tests {
         // Check out of range [106, inf)
         let prefix = msg_prefix(address_payload, 106, MESSAGE_NAMESPACE);
         assert_eq!(
-            database.get_messages_range(&prefix, None).unwrap().messages,
+            database
+                .get_messages_range(&prefix, None, 0, None)
+                .unwrap()
+                .messages,
             vec![]
         );
 
@@ -343,19 +431,26 @@ mod tests {
         let prefix = msg_prefix(address_payload, 100, MESSAGE_NAMESPACE);
         assert_eq!(
             database
-                .get_messages_range(&prefix, None)
+                .get_messages_range(&prefix, None, 0, None)
                 .unwrap()
                 .messages
                 .len(),
             2
         );
 
+        // Check offset and limit within range [100, inf)
+        let page = database
+            .get_messages_range(&prefix, None, 1, Some(1))
+            .unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.end_digest, vec![0; 32]);
+
         // Check within range [100, 101)
         let prefix = msg_prefix(address_payload, 100, MESSAGE_NAMESPACE);
         let prefix_end = msg_prefix(address_payload, 101, MESSAGE_NAMESPACE);
         assert_eq!(
             database
-                .get_messages_range(&prefix, Some(&prefix_end))
+                .get_messages_range(&prefix, Some(&prefix_end), 0, None)
                 .unwrap()
                 .messages
                 .len(),
@@ -367,11 +462,71 @@ mod tests {
         let prefix_end = msg_prefix(address_payload, 105, MESSAGE_NAMESPACE);
         assert_eq!(
             database
-                .get_messages_range(&prefix, Some(&prefix_end))
+                .get_messages_range(&prefix, Some(&prefix_end), 0, None)
                 .unwrap()
                 .messages
                 .len(),
             0
         )
     }
+
+    #[test]
+    fn receipts() {
+        let database = Database::try_new("./test_dbs/receipts").unwrap();
+
+        let addr = Address::decode("bchtest:qz35wy0grm4tze4p5tvu0fc6kujsa5vnrcr7y5xl65").unwrap();
+        let address_payload = addr.as_body();
+        database
+            .remove_receipts_before(address_payload, u64::MAX)
+            .unwrap();
+
+        let receipt = DeliveryReceipt {
+            payload_digest: vec![1; 32],
+            destination_public_key: vec![2; 33],
+            acknowledged_time: 100,
+        };
+        database.push_receipt(address_payload, &receipt).unwrap();
+
+        // Receipts of other addresses and other namespaces are not included
+        database
+            .push_receipt(&[0; 20], &Default::default())
+            .unwrap();
+        database
+            .push_message(address_payload, 100, &[], &[3; 32], MESSAGE_NAMESPACE)
+            .unwrap();
+
+        assert_eq!(
+            database
+                .get_receipts(address_payload, 0, usize::MAX)
+                .unwrap(),
+            vec![receipt.clone()]
+        );
+
+        // Receipts are paged in order of acknowledgement
+        let later = DeliveryReceipt {
+            payload_digest: vec![0; 32],
+            acknowledged_time: 200,
+            ..receipt.clone()
+        };
+        database.push_receipt(address_payload, &later).unwrap();
+        assert_eq!(
+            database.get_receipts(address_payload, 1, 1).unwrap(),
+            vec![later.clone()]
+        );
+        assert_eq!(
+            database.get_receipts(address_payload, 0, 1).unwrap(),
+            vec![receipt]
+        );
+
+        // Expired receipts are removed
+        database
+            .remove_receipts_before(address_payload, 200)
+            .unwrap();
+        assert_eq!(
+            database
+                .get_receipts(address_payload, 0, usize::MAX)
+                .unwrap(),
+            vec![later]
+        );
+    }
 }
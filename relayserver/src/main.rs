@@ -10,16 +10,22 @@ pub mod monitoring;
 
 use std::{env, sync::Arc, time::Duration};
 
-use cashweb::bitcoin_client::BitcoinClientHTTP;
+use cashweb::bitcoin_client::{BitcoinClientHTTP, Timeouts};
 use cashweb::{
+    health::{from_fn, CheckResult, Health, NodeCheck},
+    lifecycle::{shutdown_signal, Lifecycle},
     payments::{preprocess_payment, wallet::Wallet},
-    token::schemes::hmac_bearer::HmacScheme,
+    relay::filters::MAX_BLOOM_SIZE,
+    token::{
+        keys::{SecretKey, MIN_ENTROPY_BITS},
+        schemes::{hmac_bearer::HmacScheme, jwt::JwtScheme, ErasedScheme},
+    },
 };
 use dashmap::DashMap;
 use futures::prelude::*;
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 use warp::{
     http::{header, Method},
@@ -31,15 +37,20 @@ use prometheus::{Encoder, TextEncoder};
 
 use crate::{
     db::{Database, FEED_NAMESPACE, MESSAGE_NAMESPACE},
-    settings::Settings,
+    settings::{Settings, TokenSchemeKind},
 };
 
 const DASHMAP_CAPACITY: usize = 2048;
+const FILTERS_LIMIT: u64 = 2 * MAX_BLOOM_SIZE as u64 + 1024; // Both bloom filters, and the rest
 
+const ACKS_PATH: &str = "acks";
+const FILTERS_PATH: &str = "filters";
 const PROFILES_PATH: &str = "profiles";
 const WS_PATH: &str = "ws";
 const MESSAGES_PATH: &str = "messages";
 const PAYLOADS_PATH: &str = "payloads";
+const RECEIPTS_PATH: &str = "receipts";
+const HEALTH_PATH: &str = "health";
 const FEEDS_PATH: &str = "feeds";
 pub const PAYMENTS_PATH: &str = "payments";
 
@@ -63,11 +74,25 @@ async fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("no global subscriber has been set");
 
+    // Feed library metrics to the Prometheus exporter
+    #[cfg(feature = "monitoring")]
+    monitoring::install();
+
+    // Request shutdown on SIGTERM or Ctrl-C
+    let lifecycle = Lifecycle::new();
+    let lifecycle_inner = lifecycle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!(message = "shutdown requested");
+        lifecycle_inner.shutdown();
+    });
+
     info!(message = "starting", version = crate_version!());
 
     // Database state
     info!(message = "opening database", path = %SETTINGS.db_path);
     let db = Database::try_new(&SETTINGS.db_path).expect("failed to open database");
+    let db_health = db.clone();
     let db_state = warp::any().map(move || db.clone());
 
     // Message broadcast state
@@ -90,10 +115,30 @@ async fn main() {
 
     // Bitcoin client state
     info!(message = "constructing bitcoin client", address = %SETTINGS.bitcoin_rpc.address);
-    let bitcoin_client = BitcoinClientHTTP::new(
+    let bitcoin_client = BitcoinClientHTTP::with_timeouts(
         SETTINGS.bitcoin_rpc.address.clone(),
         SETTINGS.bitcoin_rpc.username.clone(),
-        SETTINGS.bitcoin_rpc.password.clone(),
+        SETTINGS.bitcoin_rpc.password.expose().clone(),
+        Timeouts {
+            connect: Some(SETTINGS.bitcoin_rpc.connect_timeout()),
+            request: Some(SETTINGS.bitcoin_rpc.request_timeout()),
+        },
+    );
+
+    // Health report, covering the node and database
+    let health = Arc::new(
+        Health::new(lifecycle.clone())
+            .with_check("node", NodeCheck(bitcoin_client.clone()))
+            .with_check(
+                "store",
+                from_fn(move || {
+                    let result = match db_health.probe_write() {
+                        Ok(()) => CheckResult::healthy(),
+                        Err(err) => CheckResult::unhealthy(err),
+                    };
+                    async move { result }
+                }),
+            ),
     );
     let bitcoin_client_state = warp::any().map(move || bitcoin_client.clone());
 
@@ -104,8 +149,14 @@ async fn main() {
 
     // Token generator
     let key =
-        hex::decode(&SETTINGS.payments.hmac_secret).expect("unable to interpret hmac key as hex");
-    let token_scheme = Arc::new(HmacScheme::new(&key));
+        SecretKey::load(SETTINGS.payments.hmac_secret.expose()).expect("unable to load hmac key");
+    if let Err(err) = key.check_entropy(MIN_ENTROPY_BITS) {
+        warn!(message = "weak hmac key", error = %err);
+    }
+    let token_scheme: net::SharedTokenScheme = match SETTINGS.payments.token_scheme {
+        TokenSchemeKind::Hmac => ErasedScheme::shared(HmacScheme::from_secret(key)),
+        TokenSchemeKind::Jwt => ErasedScheme::shared(JwtScheme::hs256(key.as_bytes())),
+    };
     let token_scheme_state = warp::any().map(move || token_scheme.clone());
 
     // Protection
@@ -205,6 +256,27 @@ async fn main() {
             net::get_payloads(addr, query, db, MESSAGE_NAMESPACE).map_err(warp::reject::custom)
         });
 
+    // Acknowledgement handlers
+    let acks_put = warp::path(ACKS_PATH)
+        .and(addr_protected.clone())
+        .and(warp::put())
+        .and(warp::body::content_length_limit(
+            SETTINGS.limits.message_size,
+        ))
+        .and(warp::body::bytes())
+        .and(db_state.clone())
+        .and_then(move |addr, body, db| {
+            net::put_acks(addr, body, db).map_err(warp::reject::custom)
+        });
+    let receipts_get = warp::path(RECEIPTS_PATH)
+        .and(addr_protected.clone())
+        .and(warp::get())
+        .and(warp::query())
+        .and(db_state.clone())
+        .and_then(move |addr, query, db| {
+            net::get_receipts(addr, query, db).map_err(warp::reject::custom)
+        });
+
     // Websocket handlers
     let websocket_messages = warp::path(WS_PATH)
         .and(warp::path(MESSAGES_PATH))
@@ -233,17 +305,33 @@ async fn main() {
         .and(db_state.clone())
         .and_then(move |addr, db| net::get_profile(addr, db).map_err(warp::reject::custom));
     let profile_put = warp::path(PROFILES_PATH)
-        .and(addr_protected)
+        .and(addr_protected.clone())
         .and(warp::put())
         .and(warp::body::content_length_limit(
             SETTINGS.limits.profile_size,
         ))
         .and(warp::body::bytes())
-        .and(db_state)
+        .and(db_state.clone())
         .and_then(move |addr, body, db| {
             net::put_profile(addr, body, db).map_err(warp::reject::custom)
         });
 
+    // Filter handlers
+    let filters_get = warp::path(FILTERS_PATH)
+        .and(addr_base)
+        .and(warp::get())
+        .and(db_state.clone())
+        .and_then(move |addr, db| net::get_filters(addr, db).map_err(warp::reject::custom));
+    let filters_put = warp::path(FILTERS_PATH)
+        .and(addr_protected)
+        .and(warp::put())
+        .and(warp::body::content_length_limit(FILTERS_LIMIT))
+        .and(warp::body::bytes())
+        .and(db_state)
+        .and_then(move |addr, body, db| {
+            net::put_filters(addr, body, db).map_err(warp::reject::custom)
+        });
+
     // Payment handler
     let payments = warp::path(PAYMENTS_PATH)
         .and(warp::post())
@@ -273,6 +361,24 @@ async fn main() {
         .and(warp::get())
         .and(warp::fs::file("./static/index.html"));
 
+    // Health probes
+    let health_get = warp::path(HEALTH_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || net::health_report(health.clone()));
+    let lifecycle_inner = lifecycle.clone();
+    let health_live = warp::path(HEALTH_PATH)
+        .and(warp::path("live"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || net::probe(lifecycle_inner.is_live()));
+    let lifecycle_inner = lifecycle.clone();
+    let health_ready = warp::path(HEALTH_PATH)
+        .and(warp::path("ready"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || net::probe(lifecycle_inner.is_ready()));
+
     // CORs
     let cors = warp::cors()
         .allow_any_origin()
@@ -298,8 +404,15 @@ async fn main() {
         .or(feeds_delete)
         .or(feeds_put)
         .or(payloads_get)
+        .or(acks_put)
+        .or(receipts_get)
         .or(profile_get)
         .or(profile_put)
+        .or(filters_get)
+        .or(filters_put)
+        .or(health_get)
+        .or(health_live)
+        .or(health_ready)
         .recover(net::handle_rejection)
         .with(cors)
         .with(warp::trace::request());
@@ -314,11 +427,13 @@ async fn main() {
         let prometheus_task = warp::serve(prometheus_server).run(SETTINGS.bind_prom);
 
         let rest_api = rest_api.with(warp::log::custom(monitoring::measure));
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
+        let (_, rest_api_task) = warp::serve(rest_api)
+            .bind_with_graceful_shutdown(SETTINGS.bind, lifecycle.token().cancelled());
 
         // Spawn servers
         tokio::spawn(prometheus_task);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        lifecycle.set_ready();
+        rest_api_task.await;
     }
 
     // If monitoring is disabled
@@ -326,7 +441,18 @@ async fn main() {
     {
         info!(monitoring = false);
 
-        let rest_api_task = warp::serve(rest_api).run(SETTINGS.bind);
-        tokio::spawn(rest_api_task).await.unwrap(); // Unrecoverable
+        let (_, rest_api_task) = warp::serve(rest_api)
+            .bind_with_graceful_shutdown(SETTINGS.bind, lifecycle.token().cancelled());
+        lifecycle.set_ready();
+        rest_api_task.await;
+    }
+
+    // Wait for in-flight work to complete
+    if let Err(err) = lifecycle
+        .drain(Duration::from_millis(SETTINGS.drain_timeout))
+        .await
+    {
+        warn!(message = "stopped before draining", error = %err);
     }
+    info!(message = "stopped");
 }
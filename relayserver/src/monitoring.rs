@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use cashweb::metrics::prometheus::PrometheusRecorder;
 use lazy_static::lazy_static;
 use prometheus::{CounterVec, HistogramVec};
 use warp::filters::log::Info;
@@ -77,6 +80,9 @@ impl From<&str> for Route {
 
 // Prometheus metrics
 lazy_static! {
+    // Recorder for the library metrics, sharing the default registry
+    pub static ref RECORDER: Arc<PrometheusRecorder> = Arc::new(PrometheusRecorder::new());
+
     // Request counter
     pub static ref HTTP_TOTAL_VEC: CounterVec = prometheus::register_counter_vec!(
         "http_request_total",
@@ -111,11 +117,12 @@ pub fn measure(info: Info) {
         .observe(duration_secs as f64);
 }
 
-pub fn export() -> Vec<u8> {
-    let metric_families = prometheus::gather();
+/// Install the Prometheus recorder, so that metrics recorded by the cash:web libraries are
+/// exported alongside the HTTP metrics.
+pub fn install() {
+    cashweb::metrics::set_recorder(RECORDER.clone());
+}
 
-    let mut buffer = Vec::new();
-    let encoder = TextEncoder::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-    buffer
+pub fn export() -> Vec<u8> {
+    RECORDER.export()
 }
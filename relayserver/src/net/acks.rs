@@ -0,0 +1,131 @@
+use bitcoincash_addr::Address;
+use bytes::Bytes;
+use cashweb::relay::{self, Acknowledgements, DeliveryReceipt, DeliveryReceipts};
+use prost::Message as _;
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+use serde::Deserialize;
+use thiserror::Error;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{
+    db::{Database, MESSAGE_NAMESPACE},
+    net::{get_unix_now, ToResponse},
+    SETTINGS,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiptQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Error)]
+pub enum AckError {
+    #[error("failed to access database: {0}")]
+    DB(rocksdb::Error),
+    #[error("failed to decode acknowledgements: {0}")]
+    AcksDecode(prost::DecodeError),
+}
+
+impl From<rocksdb::Error> for AckError {
+    fn from(err: rocksdb::Error) -> Self {
+        Self::DB(err)
+    }
+}
+
+impl Reject for AckError {}
+
+impl ToResponse for AckError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::DB(_) => 500,
+            Self::AcksDecode(_) => 400,
+        }
+    }
+}
+
+pub async fn put_acks(
+    addr: Address,
+    acks_raw: Bytes,
+    database: Database,
+) -> Result<Response<Body>, AckError> {
+    // Time now
+    let timestamp = get_unix_now();
+
+    // Decode acknowledgements
+    let acks = Acknowledgements::decode(&acks_raw[..]).map_err(AckError::AcksDecode)?;
+
+    let address_payload = addr.as_body();
+    for payload_digest in acks.payload_digests {
+        // Unknown messages, such as those already acknowledged, are ignored
+        let raw_message = match database.get_message_by_digest(
+            address_payload,
+            &payload_digest,
+            MESSAGE_NAMESPACE,
+        )? {
+            Some(some) => some,
+            None => continue,
+        };
+        let message = relay::Message::decode(&raw_message[..]).unwrap(); // This is safe
+
+        // Only the destination may acknowledge a message
+        let destination_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &message.destination_public_key).as_ref());
+        if address_payload != &destination_pubkey_hash[..] {
+            continue;
+        }
+
+        // Prune the delivered message
+        database.remove_message_by_digest(address_payload, &payload_digest, MESSAGE_NAMESPACE)?;
+
+        // Hold a receipt for the source
+        let source_pubkey_hash =
+            Ripemd160::digest(digest(&SHA256, &message.source_public_key).as_ref());
+        let receipt = DeliveryReceipt {
+            payload_digest,
+            destination_public_key: message.destination_public_key,
+            acknowledged_time: timestamp as i64,
+        };
+        database.remove_receipts_before(
+            &source_pubkey_hash,
+            timestamp.saturating_sub(SETTINGS.receipts.ttl),
+        )?;
+        database.push_receipt(&source_pubkey_hash, &receipt)?;
+    }
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
+
+pub async fn get_receipts(
+    addr: Address,
+    query: ReceiptQuery,
+    database: Database,
+) -> Result<Response<Body>, AckError> {
+    let address_payload = addr.as_body();
+
+    // Prune expired receipts
+    let cutoff = get_unix_now().saturating_sub(SETTINGS.receipts.ttl);
+    database.remove_receipts_before(address_payload, cutoff)?;
+
+    // Get page of receipts
+    let limit = query
+        .limit
+        .unwrap_or(SETTINGS.receipts.page_limit)
+        .min(SETTINGS.receipts.page_limit);
+    let receipts = DeliveryReceipts {
+        receipts: database.get_receipts(
+            address_payload,
+            query.offset.unwrap_or_default(),
+            limit,
+        )?,
+    };
+
+    // Serialize receipts
+    let mut raw_receipts = Vec::with_capacity(receipts.encoded_len());
+    receipts.encode(&mut raw_receipts).unwrap(); // This is safe
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_receipts)).unwrap())
+}
@@ -0,0 +1,94 @@
+use bitcoincash_addr::Address;
+use bytes::Bytes;
+use cashweb::relay::{filters::FilterError, Filters};
+use prost::Message as _;
+use thiserror::Error;
+use tokio::task;
+use warp::{http::Response, hyper::Body, reject::Reject};
+
+use crate::{db::Database, net::ToResponse};
+
+#[derive(Debug, Error)]
+pub enum GetFiltersError {
+    #[error("not found")]
+    NotFound,
+    #[error("failed to read from database: {0}")]
+    Database(#[from] rocksdb::Error),
+}
+
+impl Reject for GetFiltersError {}
+
+impl ToResponse for GetFiltersError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::Database(_) => 500,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PutFiltersError {
+    #[error("failed to write to database: {0}")]
+    Database(#[from] rocksdb::Error),
+    #[error("failed to decode filters: {0}")]
+    FiltersDecode(prost::DecodeError),
+    #[error("invalid filters: {0}")]
+    Invalid(FilterError),
+    #[error("filters are older than those held")]
+    Outdated,
+}
+
+impl Reject for PutFiltersError {}
+
+impl ToResponse for PutFiltersError {
+    fn to_status(&self) -> u16 {
+        match self {
+            Self::Database(_) => 500,
+            Self::Outdated => 409,
+            _ => 400,
+        }
+    }
+}
+
+pub async fn get_filters(
+    addr: Address,
+    database: Database,
+) -> Result<Response<Body>, GetFiltersError> {
+    // Get filters
+    let raw_filters = task::spawn_blocking(move || database.get_raw_filters(addr.as_body()))
+        .await
+        .unwrap()?
+        .ok_or(GetFiltersError::NotFound)?;
+
+    // Respond
+    Ok(Response::builder().body(Body::from(raw_filters)).unwrap())
+}
+
+pub async fn put_filters(
+    addr: Address,
+    filters_raw: Bytes,
+    database: Database,
+) -> Result<Response<Body>, PutFiltersError> {
+    // Decode filters
+    let filters = Filters::decode(filters_raw.clone()).map_err(PutFiltersError::FiltersDecode)?;
+    filters.validate().map_err(PutFiltersError::Invalid)?;
+
+    task::spawn_blocking(move || {
+        // Reject filters older than those held, which may be replayed to roll them back
+        if let Some(existing) = database.get_filters(addr.as_body())? {
+            if filters.timestamp <= existing.timestamp {
+                return Err(PutFiltersError::Outdated);
+            }
+        }
+
+        // Put to database
+        database.put_filters(addr.as_body(), &filters_raw)?;
+        Ok(())
+    })
+    .await
+    .unwrap()?;
+
+    // Respond
+    Ok(Response::builder().body(Body::empty()).unwrap())
+}
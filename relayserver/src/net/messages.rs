@@ -6,7 +6,10 @@ use std::{
 use bitcoincash_addr::Address;
 use bytes::Bytes;
 use cashweb::{
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
+    bitcoin_client::{
+        metrics::{GlobalMetrics, InstrumentedClient},
+        BitcoinClient, BitcoinClientHTTP, NodeError,
+    },
     relay::{self, stamp::StampError},
 };
 use futures::future;
@@ -16,7 +19,7 @@ use ring::digest::{digest, SHA256};
 use ripemd160::{Digest, Ripemd160};
 use serde::Deserialize;
 use thiserror::Error;
-use tracing::warn;
+use tracing::{info, warn};
 use warp::{http::Response, hyper::Body, reject::Reject};
 
 use crate::{
@@ -31,6 +34,8 @@ pub struct Query {
     end_digest: Option<String>,
     start_time: Option<u64>,
     end_time: Option<u64>,
+    offset: Option<usize>,
+    limit: Option<usize>,
     digest: Option<String>,
 }
 
@@ -78,7 +83,7 @@ impl ToResponse for GetMessageError {
     }
 }
 
-fn get_unix_now() -> u64 {
+pub fn get_unix_now() -> u64 {
     u64::try_from(
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -147,10 +152,15 @@ pub async fn get_payloads(
             .unwrap());
     }
 
+    let (offset, limit) = (query.offset.unwrap_or_default(), query.limit);
     let (start_prefix, end_prefix) =
         construct_prefixes(address_payload, query, &database, namespace)?;
-    let message_page =
-        database.get_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    let message_page = database.get_messages_range(
+        &start_prefix,
+        end_prefix.as_ref().map(|v| &v[..]),
+        offset,
+        limit,
+    )?;
     let payload_page = message_page.into_payload_page();
 
     // Serialize messages
@@ -181,10 +191,15 @@ pub async fn get_messages(
         return Ok(Response::builder().body(Body::from(message)).unwrap());
     }
 
+    let (offset, limit) = (query.offset.unwrap_or_default(), query.limit);
     let (start_prefix, end_prefix) =
         construct_prefixes(address_payload, query, &database, namespace)?;
-    let message_set =
-        database.get_messages_range(&start_prefix, end_prefix.as_ref().map(|v| &v[..]))?;
+    let message_set = database.get_messages_range(
+        &start_prefix,
+        end_prefix.as_ref().map(|v| &v[..]),
+        offset,
+        limit,
+    )?;
 
     // Serialize messages
     let mut raw_message_page = Vec::with_capacity(message_set.encoded_len());
@@ -308,11 +323,31 @@ pub async fn put_message(
 
         let is_self_send = destination_pubkey_hash == source_pubkey_hash;
 
-        // If sender is not self then check stamp
+        // If sender is not self then check stamp, and the filters published by the destination
         if !is_self_send {
-            parsed_message
+            let stamp_txs = parsed_message
                 .verify_stamp()
                 .map_err(PutMessageError::StampVerify)?;
+            if let Some(filters) = database.get_filters(&destination_pubkey_hash)? {
+                let stamp_value = stamp_txs
+                    .iter()
+                    .zip(&parsed_message.stamp.stamp_outpoints)
+                    .flat_map(|(tx, outpoints)| {
+                        outpoints
+                            .vouts
+                            .iter()
+                            .filter_map(move |vout| tx.outputs.get(*vout as usize))
+                    })
+                    .map(|output| output.value)
+                    .sum();
+                if !filters.accepts(&parsed_message.source_public_key, stamp_value) {
+                    info!(
+                        message = "message dropped by filters",
+                        destination = %hex::encode(destination_pubkey_hash),
+                    );
+                    continue;
+                }
+            }
         }
 
         // Try broadcast stamp transactions
@@ -321,7 +356,8 @@ pub async fn put_message(
             .stamp_outpoints
             .iter()
             .map(|stamp_oupoint| {
-                let bitcoin_client_inner = bitcoin_client.clone();
+                let bitcoin_client_inner =
+                    InstrumentedClient::new(bitcoin_client.clone(), GlobalMetrics);
                 async move { bitcoin_client_inner.send_tx(&stamp_oupoint.stamp_tx).await }
             });
 
@@ -1,24 +1,30 @@
+mod acks;
+mod filters;
 mod messages;
 mod payments;
 mod profiles;
 mod protection;
 mod ws;
 
+pub use acks::*;
+pub use filters::*;
 pub use messages::*;
 pub use payments::*;
 pub use profiles::*;
 pub use protection::*;
 pub use ws::*;
 
-use std::{convert::Infallible, fmt};
+use std::{convert::Infallible, fmt, sync::Arc};
 
 use bitcoincash_addr::Address;
+use cashweb::health::Health;
 use thiserror::Error;
 use tracing::error;
 use warp::{
-    http::Response,
+    http::{Response, StatusCode},
     hyper::Body,
     reject::{PayloadTooLarge, Reject, Rejection},
+    Reply,
 };
 
 #[derive(Debug, Error)]
@@ -89,6 +95,16 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<GetFiltersError>() {
+        error!(message = "failed to get filters", error = %err);
+        return Ok(err.to_response());
+    }
+
+    if let Some(err) = err.find::<PutFiltersError>() {
+        error!(message = "failed to put filters", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<GetMessageError>() {
         error!(message = "failed to get messages", error = %err);
         return Ok(err.to_response());
@@ -99,6 +115,11 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
         return Ok(err.to_response());
     }
 
+    if let Some(err) = err.find::<AckError>() {
+        error!(message = "failed to process acknowledgements", error = %err);
+        return Ok(err.to_response());
+    }
+
     if let Some(err) = err.find::<PaymentError>() {
         error!(message = "payment failed", error = %err);
         return Ok(err.to_response());
@@ -122,3 +143,18 @@ pub async fn handle_rejection(err: Rejection) -> Result<Response<Body>, Infallib
     error!(message = "unexpected error", error = ?err);
     Ok(Response::builder().status(500).body(Body::empty()).unwrap())
 }
+
+/// Reply to a liveness or readiness probe.
+pub fn probe(healthy: bool) -> impl Reply {
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply(), status)
+}
+
+/// Reply with the JSON health report, for load balancers.
+pub async fn health_report(health: Arc<Health>) -> Result<Response<Body>, Infallible> {
+    Ok(health.report().await.into_response())
+}
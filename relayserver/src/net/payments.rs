@@ -1,32 +1,42 @@
-use std::{
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::time::Duration;
 
 use bitcoincash_addr::{base58, cashaddr, Address};
 use cashweb::{
     bitcoin::{
-        transaction::{self, Transaction},
+        transaction::{
+            self,
+            script::{address::AddressError, Script},
+            Transaction,
+        },
         Decodable,
     },
-    bitcoin_client::{BitcoinClient, BitcoinClientHTTP, NodeError},
-    payments::bip70::{Output, Payment, PaymentAck, PaymentDetails, PaymentRequest},
+    bitcoin_client::{
+        metrics::{GlobalMetrics, InstrumentedClient},
+        BitcoinClient, BitcoinClientHTTP, NodeError,
+    },
+    payments::bip70::{Output, Payment, PaymentAck},
     payments::{
+        builder::{encode_message, PaymentDetailsBuilder},
         wallet::{self, UnexpectedOutputs},
-        PreprocessingError,
+        PreprocessingError, PAYMENT_ACK_MIME, PAYMENT_REQUEST_MIME,
     },
-    token::schemes::hmac_bearer::HmacScheme,
+    token::schemes::BoxTokenError,
 };
-use prost::Message as _;
 use thiserror::Error;
 use tracing::info;
 use warp::{
-    http::{header::AUTHORIZATION, Response},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        Response,
+    },
     hyper::Body,
     reject::Reject,
 };
 
-use crate::{net::ToResponse, PAYMENTS_PATH, SETTINGS};
+use crate::{
+    net::{SharedTokenScheme, ToResponse},
+    PAYMENTS_PATH, SETTINGS,
+};
 
 pub type Wallet = wallet::Wallet<Vec<u8>, Output>;
 
@@ -42,6 +52,8 @@ pub enum PaymentError {
     MissingMerchantData,
     #[error("bitcoin request failed: {0}")]
     Node(NodeError),
+    #[error("failed to construct token: {0}")]
+    Token(BoxTokenError),
 }
 
 impl Reject for PaymentError {}
@@ -61,6 +73,7 @@ impl ToResponse for PaymentError {
                 NodeError::Rpc(_) => 400,
                 _ => 500,
             },
+            PaymentError::Token(_) => 500,
         }
     }
 }
@@ -69,7 +82,7 @@ pub async fn process_payment(
     payment: Payment,
     wallet: Wallet,
     bitcoin_client: BitcoinClientHTTP,
-    token_state: Arc<HmacScheme>,
+    token_state: SharedTokenScheme,
 ) -> Result<Response<Body>, PaymentError> {
     let txs_res: Result<Vec<Transaction>, transaction::DecodeError> = payment
         .transactions
@@ -97,6 +110,7 @@ pub async fn process_payment(
         .recv_outputs(pubkey_hash, &outputs)
         .map_err(PaymentError::Wallet)?;
 
+    let bitcoin_client = InstrumentedClient::new(bitcoin_client, GlobalMetrics);
     for tx in &payment.transactions {
         bitcoin_client
             .send_tx(tx)
@@ -105,17 +119,19 @@ pub async fn process_payment(
     }
 
     // Construct token
-    let token = format!("POP {}", token_state.construct_token(pubkey_hash));
+    let token = token_state
+        .construct(pubkey_hash)
+        .await
+        .map_err(PaymentError::Token)?;
+    let token = format!("POP {}", token);
 
     // Create PaymentAck
     let memo = Some(SETTINGS.payments.memo.clone());
-    let payment_ack = PaymentAck { payment, memo };
-
-    // Encode payment ack
-    let mut raw_ack = Vec::with_capacity(payment_ack.encoded_len());
-    payment_ack.encode(&mut raw_ack).unwrap();
+    let payment_ack = PaymentAck::new(payment, memo);
+    let raw_ack = encode_message(&payment_ack);
 
     Ok(Response::builder()
+        .header(CONTENT_TYPE, PAYMENT_ACK_MIME)
         .header(AUTHORIZATION, token)
         .body(Body::from(raw_ack))
         .unwrap())
@@ -127,6 +143,8 @@ pub enum PaymentRequestError {
     Address(cashaddr::DecodingError, base58::DecodingError),
     #[error("failed to retrieve address from bitcoind: {0}")]
     Node(NodeError),
+    #[error("failed to construct output script: {0}")]
+    Script(AddressError),
     #[error("mismatched network")]
     MismatchedNetwork,
 }
@@ -144,55 +162,29 @@ pub async fn generate_payment_request(
         .map_err(|(cash_err, base58_err)| PaymentRequestError::Address(cash_err, base58_err))?;
 
     // Generate output
-    let p2pkh_script_pre: [u8; 3] = [118, 169, 20];
-    let p2pkh_script_post: [u8; 2] = [136, 172];
-    let script = [
-        &p2pkh_script_pre[..],
-        output_addr.as_body(),
-        &p2pkh_script_post[..],
-    ]
-    .concat();
+    let script = Script::from_address(&output_addr).map_err(PaymentRequestError::Script)?;
     let output = Output {
         amount: Some(SETTINGS.payments.token_fee),
-        script,
+        script: script.into_bytes(),
     };
     let cleanup = wallet.add_outputs(addr.as_body().to_vec(), vec![output.clone()]);
     info!(message = "added to wallet", output = ?output, address_payload = ?addr.as_body());
     tokio::spawn(cleanup);
 
-    // Valid interval
-    let current_time = SystemTime::now();
-    let expiry_time = current_time + Duration::from_millis(SETTINGS.payments.timeout);
-
-    let payment_details = PaymentDetails {
-        network: Some(SETTINGS.network.to_string()),
-        time: current_time.duration_since(UNIX_EPOCH).unwrap().as_secs(),
-        expires: Some(expiry_time.duration_since(UNIX_EPOCH).unwrap().as_secs()),
-        memo: None,
-        merchant_data: Some(addr.into_body()),
-        outputs: vec![output],
-        payment_url: Some(format!("/{}", PAYMENTS_PATH)),
-    };
-    let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
-    payment_details
-        .encode(&mut serialized_payment_details)
-        .unwrap();
-
     // Generate payment invoice
     // TODO: Signing
-    let pki_type = Some("none".to_string());
-    let payment_invoice = PaymentRequest {
-        pki_type,
-        pki_data: None,
-        payment_details_version: Some(1),
-        serialized_payment_details,
-        signature: None,
-    };
-    let mut payment_invoice_raw = Vec::with_capacity(payment_invoice.encoded_len());
-    payment_invoice.encode(&mut payment_invoice_raw).unwrap();
+    let payment_invoice = PaymentDetailsBuilder::new()
+        .network(SETTINGS.network.to_string())
+        .outputs(vec![output])
+        .expires_after(Duration::from_millis(SETTINGS.payments.timeout))
+        .merchant_data(addr.into_body())
+        .payment_url(format!("/{}", PAYMENTS_PATH))
+        .build_request();
+    let payment_invoice_raw = encode_message(&payment_invoice);
 
     Ok(Response::builder()
         .status(402)
+        .header(CONTENT_TYPE, PAYMENT_REQUEST_MIME)
         .body(Body::from(payment_invoice_raw))
         .unwrap())
 }
@@ -4,7 +4,7 @@ use bitcoincash_addr::Address;
 use cashweb::bitcoin_client::BitcoinClientHTTP;
 use cashweb::token::{
     extract_pop,
-    schemes::hmac_bearer::{HmacScheme, ValidationError},
+    schemes::{BoxTokenError, DynTokenScheme, TokenError},
     split_pop_token,
 };
 use http::header::HeaderMap;
@@ -13,18 +13,20 @@ use warp::{http::Response, hyper::Body, reject::Reject};
 
 use crate::net::payments::{generate_payment_request, Wallet};
 
+pub type SharedTokenScheme = Arc<DynTokenScheme>;
+
 #[derive(Debug, Error)]
 pub enum ProtectionError {
     #[error("missing token: {0:?}")] // TODO: Make this prettier
     MissingToken(Address, Wallet, BitcoinClientHTTP),
     #[error("validation failed: {0}")]
-    Validation(ValidationError),
+    Validation(BoxTokenError),
 }
 
 pub async fn protection_error_recovery(err: &ProtectionError) -> Response<Body> {
     match err {
-        ProtectionError::Validation(_) => Response::builder()
-            .status(400)
+        ProtectionError::Validation(validation_err) => Response::builder()
+            .status(validation_err.kind().status())
             .body(Body::from(err.to_string()))
             .unwrap(),
         ProtectionError::MissingToken(addr, wallet, bitcoin_client) => {
@@ -48,7 +50,7 @@ pub async fn pop_protection(
     addr: Address,
     header_map: HeaderMap,
     access_token: Option<String>,
-    token_scheme: Arc<HmacScheme>,
+    token_scheme: SharedTokenScheme,
     wallet: Wallet,
     bitcoin_client: BitcoinClientHTTP,
 ) -> Result<Address, ProtectionError> {
@@ -59,7 +61,8 @@ pub async fn pop_protection(
     }) {
         Some(pop_token) => {
             token_scheme
-                .validate_token(&addr.as_body().to_vec(), pop_token)
+                .validate(addr.as_body(), pop_token)
+                .await
                 .map_err(ProtectionError::Validation)?;
             Ok(addr)
         }
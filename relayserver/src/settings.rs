@@ -1,35 +1,37 @@
 use std::net::SocketAddr;
 
-use cashweb::bitcoin::Network;
+use cashweb::{
+    bitcoin::Network,
+    config::{ConfigError, Loader, NodeConfig, Secret, Validate, ValidationError},
+};
 use clap::App;
-use config::{Config, ConfigError, File};
 use serde::Deserialize;
 
 const FOLDER_DIR: &str = ".relay";
+const ENV_PREFIX: &str = "RELAY";
 const DEFAULT_BIND: &str = "127.0.0.1:8080";
 const DEFAULT_RPC_ADDR: &str = "http://127.0.0.1:18443";
 const DEFAULT_RPC_USER: &str = "user";
 const DEFAULT_RPC_PASSWORD: &str = "password";
+const DEFAULT_RPC_CONNECT_TIMEOUT: u64 = 5_000;
+const DEFAULT_RPC_REQUEST_TIMEOUT: u64 = 30_000;
 const DEFAULT_NETWORK: &str = "regtest";
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30_000;
 const DEFAULT_PING_INTERVAL: u64 = 10_000;
 const DEFAULT_MESSAGE_LIMIT: usize = 1024 * 1024 * 20; // 20Mb
 const DEFAULT_PROFILE_LIMIT: usize = 1024 * 512; // 512Kb
 const DEFAULT_PAYMENT_LIMIT: usize = 1024 * 3; // 3Kb
 const DEFAULT_PAYMENT_TIMEOUT: usize = 1_000 * 60; // 60 seconds
 const DEFAULT_TRUNCATION_LENGTH: usize = 500;
+const DEFAULT_RECEIPT_TTL: u64 = 1_000 * 60 * 60 * 24 * 7; // 7 days
+const DEFAULT_RECEIPT_PAGE_LIMIT: usize = 1_000;
 const DEFAULT_TOKEN_FEE: u64 = 100_000;
+const DEFAULT_TOKEN_SCHEME: &str = "hmac";
 const DEFAULT_MEMO: &str = "Thanks for your custom!";
 
 #[cfg(feature = "monitoring")]
 const DEFAULT_BIND_PROM: &str = "127.0.0.1:9095";
 
-#[derive(Debug, Deserialize)]
-pub struct BitcoinRpc {
-    pub address: String,
-    pub username: String,
-    pub password: String,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct Limits {
     pub message_size: u64,
@@ -37,12 +39,26 @@ pub struct Limits {
     pub payment_size: u64,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenSchemeKind {
+    Hmac,
+    Jwt,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Payment {
     pub timeout: u64,
     pub token_fee: u64,
+    pub token_scheme: TokenSchemeKind,
     pub memo: String,
-    pub hmac_secret: String,
+    pub hmac_secret: Secret<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Receipts {
+    pub ttl: u64,
+    pub page_limit: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,17 +73,17 @@ pub struct Settings {
     #[cfg(feature = "monitoring")]
     pub bind_prom: SocketAddr,
     pub db_path: String,
+    pub drain_timeout: u64,
     pub network: Network,
-    pub bitcoin_rpc: BitcoinRpc,
+    pub bitcoin_rpc: NodeConfig,
     pub limits: Limits,
     pub payments: Payment,
+    pub receipts: Receipts,
     pub websocket: Websocket,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
-        let mut s = Config::new();
-
         // Set defaults
         let yaml = load_yaml!("cli.yml");
         #[allow(deprecated)]
@@ -80,81 +96,125 @@ impl Settings {
             Some(some) => some,
             None => return Err(ConfigError::Message("no home directory".to_string())),
         };
-        s.set_default("bind", DEFAULT_BIND)?;
+        let mut s = Loader::new(ENV_PREFIX)
+            .with_default("bind", DEFAULT_BIND)
+            .with_default("network", DEFAULT_NETWORK)
+            .with_default("drain_timeout", DEFAULT_DRAIN_TIMEOUT as i64);
         #[cfg(feature = "monitoring")]
-        s.set_default("bind_prom", DEFAULT_BIND_PROM)?;
-        s.set_default("network", DEFAULT_NETWORK)?;
+        {
+            s = s.with_default("bind_prom", DEFAULT_BIND_PROM);
+        }
         let mut default_db = home_dir.clone();
         default_db.push(format!("{}/db", FOLDER_DIR));
-        s.set_default("db_path", default_db.to_str())?;
-        s.set_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)?;
-        s.set_default("bitcoin_rpc.username", DEFAULT_RPC_USER)?;
-        s.set_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)?;
-        s.set_default("limits.message_size", DEFAULT_MESSAGE_LIMIT as i64)?;
-        s.set_default("limits.profile_size", DEFAULT_PROFILE_LIMIT as i64)?;
-        s.set_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)?;
-        s.set_default("payments.token_fee", DEFAULT_TOKEN_FEE as i64)?;
-        s.set_default("payments.memo", DEFAULT_MEMO)?;
-        s.set_default("payments.timeout", DEFAULT_PAYMENT_TIMEOUT as i64)?;
-        s.set_default(
-            "websocket.truncation_length",
-            DEFAULT_TRUNCATION_LENGTH as i64,
-        )?;
-        s.set_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64)?;
+        s = s
+            .with_default("db_path", default_db.to_string_lossy().as_ref())
+            .with_default("bitcoin_rpc.address", DEFAULT_RPC_ADDR)
+            .with_default("bitcoin_rpc.username", DEFAULT_RPC_USER)
+            .with_default("bitcoin_rpc.password", DEFAULT_RPC_PASSWORD)
+            .with_default(
+                "bitcoin_rpc.connect_timeout",
+                DEFAULT_RPC_CONNECT_TIMEOUT as i64,
+            )
+            .with_default(
+                "bitcoin_rpc.request_timeout",
+                DEFAULT_RPC_REQUEST_TIMEOUT as i64,
+            )
+            .with_default("limits.message_size", DEFAULT_MESSAGE_LIMIT as i64)
+            .with_default("limits.profile_size", DEFAULT_PROFILE_LIMIT as i64)
+            .with_default("limits.payment_size", DEFAULT_PAYMENT_LIMIT as i64)
+            .with_default("payments.token_fee", DEFAULT_TOKEN_FEE as i64)
+            .with_default("payments.token_scheme", DEFAULT_TOKEN_SCHEME)
+            .with_default("payments.memo", DEFAULT_MEMO)
+            .with_default("payments.timeout", DEFAULT_PAYMENT_TIMEOUT as i64)
+            .with_default("receipts.ttl", DEFAULT_RECEIPT_TTL as i64)
+            .with_default("receipts.page_limit", DEFAULT_RECEIPT_PAGE_LIMIT as i64)
+            .with_default(
+                "websocket.truncation_length",
+                DEFAULT_TRUNCATION_LENGTH as i64,
+            )
+            .with_default("websocket.ping_interval", DEFAULT_PING_INTERVAL as i64);
 
         // NOTE: Don't set HMAC key to a default during release for security reasons
         #[cfg(debug_assertions)]
         {
-            s.set_default("payments.hmac_secret", "1234")?;
+            s = s.with_default("payments.hmac_secret", "1234");
         }
 
-        // Load config from file
+        // Load config from file, then the environment
         let mut default_config = home_dir;
         default_config.push(format!("{}/config", FOLDER_DIR));
-        let default_config_str = default_config.to_str().unwrap();
-        let config_path = matches.value_of("config").unwrap_or(default_config_str);
-        s.merge(File::with_name(config_path).required(false))?;
+        s = match matches.value_of("config") {
+            Some(config_path) => s.with_file(config_path),
+            None => s.with_optional_file(default_config),
+        };
 
         // Set bind address from cmd line
         if let Some(bind) = matches.value_of("bind") {
-            s.set("bind", bind)?;
+            s = s.with_override("bind", bind);
         }
 
         // Set bind address from cmd line
         if let Some(bind_prom) = matches.value_of("bind-prom") {
-            s.set("bind_prom", bind_prom)?;
+            s = s.with_override("bind_prom", bind_prom);
         }
 
         // Set the bitcoin network
         if let Some(network) = matches.value_of("network") {
-            s.set("network", network)?;
+            s = s.with_override("network", network);
         }
 
         // Set db from cmd line
         if let Some(db_path) = matches.value_of("db-path") {
-            s.set("db_path", db_path)?;
+            s = s.with_override("db_path", db_path);
         }
 
         // Set node IP from cmd line
         if let Some(node_ip) = matches.value_of("rpc-addr") {
-            s.set("bitcoin_rpc.address", node_ip)?;
+            s = s.with_override("bitcoin_rpc.address", node_ip);
         }
 
         // Set rpc username from cmd line
         if let Some(rpc_username) = matches.value_of("rpc-username") {
-            s.set("bitcoin_rpc.username", rpc_username)?;
+            s = s.with_override("bitcoin_rpc.username", rpc_username);
         }
 
         // Set rpc password from cmd line
         if let Some(rpc_password) = matches.value_of("rpc-password") {
-            s.set("bitcoin_rpc.password", rpc_password)?;
+            s = s.with_override("bitcoin_rpc.password", rpc_password);
         }
 
         // Set secret from cmd line
         if let Some(hmac_secret) = matches.value_of("hmac-secret") {
-            s.set("payments.hmac_secret", hmac_secret)?;
+            s = s.with_override("payments.hmac_secret", hmac_secret);
         }
 
-        s.try_into()
+        s.load()
+    }
+}
+
+impl Validate for Settings {
+    fn validate(&self) -> Result<(), ValidationError> {
+        self.bitcoin_rpc
+            .validate()
+            .map_err(|err| err.in_section("bitcoin_rpc"))?;
+        if self.limits.message_size == 0
+            || self.limits.profile_size == 0
+            || self.limits.payment_size == 0
+        {
+            return Err(ValidationError::new("limits", "sizes must be positive"));
+        }
+        if self.receipts.page_limit == 0 {
+            return Err(ValidationError::new(
+                "receipts.page_limit",
+                "must be positive",
+            ));
+        }
+        if self.payments.hmac_secret.expose().is_empty() {
+            return Err(ValidationError::new(
+                "payments.hmac_secret",
+                "must not be empty",
+            ));
+        }
+        Ok(())
     }
 }